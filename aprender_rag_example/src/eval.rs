@@ -0,0 +1,140 @@
+//! 检索评测：从 JSONL 读入“问题 -> 期望文档”，按不同的融合 / 重排设置检索，
+//! 统计 recall@k 和 MRR。
+//!
+//! 每行一个问题，例如：
+//! `{"question": "What is Reciprocal Rank Fusion?", "document": "memory://aprender-rag/retrieval"}`
+
+use std::{error::Error, fs, path::Path};
+
+use aprender_rag::embed::Embedder;
+use serde::Deserialize;
+
+use crate::{
+  search::{Fusion, Hit, Rerank, SearchSettings, search},
+  store::PersistentIndex,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Question {
+  pub question: String,
+  /// 期望命中的文档 id
+  pub document: String,
+}
+
+pub fn load_questions(path: &Path) -> Result<Vec<Question>, Box<dyn Error>> {
+  let text = fs::read_to_string(path)?;
+  let mut questions = Vec::new();
+  for (number, line) in text.lines().enumerate() {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let question =
+      serde_json::from_str(line).map_err(|e| format!("{}:{}: {e}", path.display(), number + 1))?;
+    questions.push(question);
+  }
+  Ok(questions)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+  pub recall_at_k: f64,
+  pub mrr: f64,
+}
+
+/// 要比较的设置：几种融合方式，各自带或不带词面重排
+pub fn settings_grid() -> Vec<SearchSettings> {
+  let fusions = [
+    Fusion::Rrf { k: 60.0 },
+    Fusion::Rrf { k: 10.0 },
+    Fusion::Linear { dense_weight: 0.5 },
+    Fusion::Linear { dense_weight: 1.0 },
+    Fusion::Linear { dense_weight: 0.0 },
+  ];
+  let reranks = [Rerank::None, SearchSettings::default().rerank];
+  fusions
+    .into_iter()
+    .flat_map(|fusion| {
+      reranks.into_iter().map(move |rerank| SearchSettings {
+        fusion,
+        rerank,
+        ..SearchSettings::default()
+      })
+    })
+    .collect()
+}
+
+/// 对每个问题检索全部候选，期望文档排在前 `k` 名算召回，名次的倒数计入 MRR
+pub fn evaluate(
+  index: &PersistentIndex,
+  embedder: &impl Embedder,
+  questions: &[Question],
+  k: usize,
+  settings: &SearchSettings,
+) -> Result<Metrics, Box<dyn Error>> {
+  let mut ranks = Vec::with_capacity(questions.len());
+  for question in questions {
+    let hits = search(
+      index,
+      embedder,
+      &question.question,
+      settings.candidates,
+      settings,
+    )?;
+    let documents = ranked_documents(index, &hits);
+    ranks.push(
+      documents
+        .iter()
+        .position(|document| *document == question.document)
+        .map(|position| position + 1),
+    );
+  }
+  Ok(metrics(&ranks, k))
+}
+
+/// 文本块的排名换算成文档的排名：一篇文档按它排得最靠前的文本块算
+fn ranked_documents<'a>(index: &'a PersistentIndex, hits: &[Hit]) -> Vec<&'a str> {
+  let mut documents: Vec<&str> = Vec::new();
+  for hit in hits {
+    if let Some(chunk) = index.chunk(hit.chunk)
+      && !documents.contains(&chunk.document.as_str())
+    {
+      documents.push(&chunk.document);
+    }
+  }
+  documents
+}
+
+/// `ranks` 是每个问题期望文档的名次（从 1 开始），没检索到为 `None`
+fn metrics(ranks: &[Option<usize>], k: usize) -> Metrics {
+  if ranks.is_empty() {
+    return Metrics {
+      recall_at_k: 0.0,
+      mrr: 0.0,
+    };
+  }
+  let total = ranks.len() as f64;
+  let recalled = ranks.iter().flatten().filter(|&&rank| rank <= k).count();
+  let reciprocal: f64 = ranks.iter().flatten().map(|&rank| 1.0 / rank as f64).sum();
+  Metrics {
+    recall_at_k: recalled as f64 / total,
+    mrr: reciprocal / total,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn computes_recall_and_mrr() {
+    let ranks = [Some(1), Some(2), Some(4), None];
+    assert_eq!(
+      metrics(&ranks, 2),
+      Metrics {
+        recall_at_k: 0.5,
+        mrr: (1.0 + 0.5 + 0.25) / 4.0,
+      }
+    );
+    assert_eq!(metrics(&ranks, 10).recall_at_k, 0.75);
+  }
+}
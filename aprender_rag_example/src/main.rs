@@ -1,54 +1,75 @@
-use aprender_rag::{
-  Document, FusionStrategy, RecursiveChunker, embed::MockEmbedder, pipeline::RagPipelineBuilder,
-  rerank::LexicalReranker,
+mod eval;
+mod search;
+mod sparse;
+mod store;
+
+use std::{env, error::Error, path::Path};
+
+use aprender_rag::{RecursiveChunker, embed::MockEmbedder};
+
+use crate::{
+  eval::{evaluate, load_questions, settings_grid},
+  search::{Hit, SearchSettings, search},
+  store::{Change, PersistentIndex, SourceDocument},
 };
 
 const QUERY: &str = "What does aprender-rag do in a RAG pipeline?";
+/// 文本块、向量和 BM25 倒排表都存在这里，下次运行直接复用
+const INDEX_PATH: &str = "data/aprender-rag-index.json";
+const EMBEDDING_DIM: usize = 384;
+const USAGE: &str = "用法: aprender_rag_example [eval <questions.jsonl> [--k N]]";
 
-fn main() -> aprender_rag::Result<()> {
-  print_intro();
-
-  let mut pipeline = RagPipelineBuilder::new()
-    .chunker(RecursiveChunker::new(700, 80))
-    .embedder(MockEmbedder::new(384))
-    .reranker(LexicalReranker::new().with_weights(0.35, 0.5, 0.15))
-    .fusion(FusionStrategy::RRF { k: 60.0 })
-    .max_context_tokens(700)
-    .build()?;
+fn main() -> Result<(), Box<dyn Error>> {
+  let args: Vec<String> = env::args().skip(1).collect();
+  match args.as_slice() {
+    [] => run_demo(),
+    [command, path, rest @ ..] if command == "eval" => {
+      let k = match rest {
+        [] => 3,
+        [flag, k] if flag == "--k" => k.parse().map_err(|_| format!("invalid --k `{k}`"))?,
+        _ => return Err(USAGE.into()),
+      };
+      run_eval(Path::new(path), k)
+    }
+    _ => Err(USAGE.into()),
+  }
+}
 
-  let documents = demo_documents();
-  let chunk_count = pipeline.index_documents(&documents)?;
+fn run_demo() -> Result<(), Box<dyn Error>> {
+  print_intro();
 
-  println!("索引完成");
-  println!("- 文档数: {}", pipeline.document_count());
-  println!("- 文本块数: {chunk_count}");
-  println!();
+  let embedder = MockEmbedder::new(EMBEDDING_DIM);
+  let index = open_synced_index(&embedder)?;
 
   println!("用户问题: {QUERY}");
   println!();
 
-  let (results, context) = pipeline.query_with_context(QUERY, 3)?;
+  let hits = search(&index, &embedder, QUERY, 3, &SearchSettings::default())?;
 
   println!("检索到的证据");
-  for (rank, result) in results.iter().enumerate() {
-    let title = result.chunk.metadata.title.as_deref().unwrap_or("Untitled");
+  for (rank, hit) in hits.iter().enumerate() {
+    let Some(chunk) = index.chunk(hit.chunk) else {
+      continue;
+    };
     println!(
-      "{}. {title} | best={:.3} dense={:?} sparse={:?} rerank={:?}",
+      "{}. {} | best={:.3} dense={:?} sparse={:?} rerank={:?}",
       rank + 1,
-      result.best_score(),
-      result.dense_score.map(round_score),
-      result.sparse_score.map(round_score),
-      result.rerank_score.map(round_score),
+      document_title(&index, &chunk.document),
+      hit.score,
+      hit.dense_score.map(round_score),
+      hit.sparse_score.map(round_score),
+      hit.rerank_score.map(round_score),
     );
-    println!("   {}", compact(&result.chunk.content, 160));
+    println!("   {}", compact(&chunk.content, 160));
   }
   println!();
 
+  let (context, citations) = format_context(&index, &hits);
   println!("组装给 LLM 的上下文");
-  println!("{}", context.format_with_citations());
+  println!("{context}");
   println!();
   println!("引用");
-  println!("{}", context.citation_list());
+  println!("{citations}");
   println!();
 
   println!("模拟生成回答");
@@ -57,47 +78,136 @@ fn main() -> aprender_rag::Result<()> {
   Ok(())
 }
 
+fn run_eval(path: &Path, k: usize) -> Result<(), Box<dyn Error>> {
+  let embedder = MockEmbedder::new(EMBEDDING_DIM);
+  let index = open_synced_index(&embedder)?;
+  let questions = load_questions(path)?;
+
+  println!("评测集: {}（{} 个问题）", path.display(), questions.len());
+  println!();
+  println!(
+    "{:<20} {:<24} {:>10} {:>8}",
+    "fusion",
+    "rerank",
+    format!("recall@{k}"),
+    "MRR"
+  );
+  for settings in settings_grid() {
+    let metrics = evaluate(&index, &embedder, &questions, k, &settings)?;
+    println!(
+      "{:<20} {:<24} {:>10.3} {:>8.3}",
+      settings.fusion.to_string(),
+      settings.rerank.to_string(),
+      metrics.recall_at_k,
+      metrics.mrr
+    );
+  }
+  Ok(())
+}
+
+/// 打开落盘的索引，按 `demo_documents()` 增量同步：新增或改动的文档重新切块、
+/// 生成向量，没变的跳过，语料里已经没有的文档删掉
+fn open_synced_index(embedder: &MockEmbedder) -> Result<PersistentIndex, Box<dyn Error>> {
+  let chunker = RecursiveChunker::new(700, 80);
+  let mut index = PersistentIndex::open(Path::new(INDEX_PATH), EMBEDDING_DIM)?;
+
+  let documents = demo_documents();
+  let (mut added, mut updated, mut unchanged) = (0, 0, 0);
+  for document in &documents {
+    match index.upsert(document, &chunker, embedder)? {
+      Change::Added => added += 1,
+      Change::Updated => updated += 1,
+      Change::Unchanged => unchanged += 1,
+    }
+  }
+  let stale: Vec<String> = index
+    .document_ids()
+    .filter(|id| !documents.iter().any(|document| document.id == *id))
+    .map(str::to_string)
+    .collect();
+  for id in &stale {
+    index.remove(id);
+  }
+  index.save()?;
+
+  println!("索引同步完成（{INDEX_PATH}）");
+  println!(
+    "- 新增 {added} 篇，更新 {updated} 篇，未变 {unchanged} 篇，删除 {} 篇",
+    stale.len()
+  );
+  println!("- 文档数: {}", index.document_count());
+  println!("- 文本块数: {}", index.chunk_count());
+  println!();
+  Ok(index)
+}
+
+/// 按检索顺序编号的上下文，以及对应的引用列表
+fn format_context(index: &PersistentIndex, hits: &[Hit]) -> (String, String) {
+  let mut context = Vec::new();
+  let mut citations = Vec::new();
+  for (number, hit) in hits.iter().enumerate() {
+    let Some(chunk) = index.chunk(hit.chunk) else {
+      continue;
+    };
+    context.push(format!("[{}] {}", number + 1, chunk.content));
+    citations.push(format!(
+      "[{}] {} ({})",
+      number + 1,
+      document_title(index, &chunk.document),
+      chunk.document
+    ));
+  }
+  (context.join("\n\n"), citations.join("\n"))
+}
+
+fn document_title<'a>(index: &'a PersistentIndex, id: &str) -> &'a str {
+  index
+    .document(id)
+    .map_or("Untitled", |document| document.title.as_str())
+}
+
 fn print_intro() {
   println!("aprender-rag 功能说明");
   println!("- 把原始文档切分成适合检索的小块。");
-  println!("- 为文本块生成向量表示，并同时建立稀疏 BM25 索引。");
+  println!("- 为文本块生成向量表示，并同时建立稀疏 BM25 索引，索引落盘后按文档增量更新。");
   println!("- 查询时做 dense + sparse 混合检索，再用融合和 rerank 排序。");
   println!("- 把命中的文本块组装成带引用的上下文，交给大模型生成答案。");
-  println!("- 本示例使用 MockEmbedder 和 LexicalReranker 离线演示，不需要 API key 或外部模型。");
+  println!("- 本示例使用 MockEmbedder 和词面重排离线演示，不需要 API key 或外部模型。");
+  println!("- `eval <questions.jsonl>` 对比不同融合 / 重排设置的 recall@k 和 MRR。");
   println!();
 }
 
-fn demo_documents() -> Vec<Document> {
+fn demo_documents() -> Vec<SourceDocument> {
   vec![
-    Document::new(
+    SourceDocument::new(
+      "memory://aprender-rag/overview",
+      "aprender-rag overview",
       "aprender-rag is a pure Rust retrieval-augmented generation toolkit. In a RAG pipeline it \
        loads documents, chunks long text, creates embeddings, builds sparse and dense indexes, \
        retrieves relevant chunks, reranks candidates, and assembles cited context for an LLM \
        answer.",
-    )
-    .with_title("aprender-rag overview")
-    .with_source("memory://aprender-rag/overview"),
-    Document::new(
+    ),
+    SourceDocument::new(
+      "memory://aprender-rag/chunking",
+      "chunking strategies",
       "Chunking matters because language models have context limits. aprender-rag provides \
        RecursiveChunker, fixed-size chunking, sentence chunking, paragraph chunking, structural \
        chunking, and timestamp-aware chunking for subtitles or transcripts.",
-    )
-    .with_title("chunking strategies")
-    .with_source("memory://aprender-rag/chunking"),
-    Document::new(
+    ),
+    SourceDocument::new(
+      "memory://aprender-rag/retrieval",
+      "hybrid retrieval and fusion",
       "Hybrid retrieval combines vector similarity with keyword search. aprender-rag can fuse \
        dense embedding results with BM25 sparse results using Reciprocal Rank Fusion, linear \
        fusion, convex fusion, DBSF, union, or intersection.",
-    )
-    .with_title("hybrid retrieval and fusion")
-    .with_source("memory://aprender-rag/retrieval"),
-    Document::new(
+    ),
+    SourceDocument::new(
+      "memory://rag/benefit",
+      "why RAG helps",
       "A normal prompt asks the model to answer from its parameters only. A RAG prompt first \
        retrieves fresh or private evidence from your own corpus, then asks the model to answer \
        using that evidence and cite the source chunks.",
-    )
-    .with_title("why RAG helps")
-    .with_source("memory://rag/benefit"),
+    ),
   ]
 }
 
@@ -124,6 +234,8 @@ fn round_score(score: f32) -> f32 {
 
 #[cfg(test)]
 mod tests {
+  use aprender_rag::{FusionStrategy, pipeline::RagPipelineBuilder, rerank::LexicalReranker};
+
   use super::*;
 
   #[test]
@@ -137,7 +249,12 @@ mod tests {
       .expect("demo pipeline should be valid");
 
     pipeline
-      .index_documents(&demo_documents())
+      .index_documents(
+        &demo_documents()
+          .iter()
+          .map(SourceDocument::to_document)
+          .collect::<Vec<_>>(),
+      )
       .expect("demo documents should index");
 
     let results = pipeline.query(QUERY, 3).expect("demo query should run");
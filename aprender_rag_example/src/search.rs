@@ -0,0 +1,251 @@
+//! 在持久化索引上做混合检索：dense（余弦相似度）和 sparse（BM25）各取一批候选，
+//! 融合排序后可选再做一次词面重排。
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use aprender_rag::embed::Embedder;
+
+use crate::{sparse::tokenize, store::PersistentIndex};
+
+/// 两路候选的融合方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+  /// Reciprocal Rank Fusion：只看名次，`1 / (k + rank)` 相加
+  Rrf { k: f32 },
+  /// 两路分数各自 min-max 归一化后加权，`dense_weight` 为 1 即纯向量检索，为 0 即纯 BM25
+  Linear { dense_weight: f32 },
+}
+
+impl fmt::Display for Fusion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Rrf { k } => write!(f, "rrf(k={k})"),
+      Self::Linear { dense_weight } => write!(f, "linear(dense={dense_weight})"),
+    }
+  }
+}
+
+/// 融合之后的重排
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rerank {
+  None,
+  /// 按查询词在正文、标题中的覆盖率和融合分数加权重排
+  Lexical {
+    content: f32,
+    title: f32,
+    retrieval: f32,
+  },
+}
+
+impl fmt::Display for Rerank {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::None => f.write_str("none"),
+      Self::Lexical {
+        content,
+        title,
+        retrieval,
+      } => write!(f, "lexical({content}/{title}/{retrieval})"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchSettings {
+  pub fusion: Fusion,
+  pub rerank: Rerank,
+  /// 每一路取多少个候选参与融合
+  pub candidates: usize,
+}
+
+impl Default for SearchSettings {
+  fn default() -> Self {
+    Self {
+      fusion: Fusion::Rrf { k: 60.0 },
+      rerank: Rerank::Lexical {
+        content: 0.35,
+        title: 0.5,
+        retrieval: 0.15,
+      },
+      candidates: 20,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+  pub chunk: u64,
+  pub score: f32,
+  pub dense_score: Option<f32>,
+  pub sparse_score: Option<f32>,
+  pub rerank_score: Option<f32>,
+}
+
+pub fn search(
+  index: &PersistentIndex,
+  embedder: &impl Embedder,
+  query: &str,
+  top_k: usize,
+  settings: &SearchSettings,
+) -> Result<Vec<Hit>, Box<dyn Error>> {
+  let query_embedding = embedder.embed(query)?;
+  let mut dense: Vec<(u64, f32)> = index
+    .chunks()
+    .map(|(id, chunk)| (id, cosine(&query_embedding, &chunk.embedding)))
+    .collect();
+  dense.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+  dense.truncate(settings.candidates);
+  let sparse = index.sparse().search(query, settings.candidates);
+
+  let mut hits = fuse(&dense, &sparse, settings.fusion);
+  if let Rerank::Lexical {
+    content,
+    title,
+    retrieval,
+  } = settings.rerank
+  {
+    let terms: Vec<String> = tokenize(query).collect();
+    let best = hits.first().map_or(0.0, |hit| hit.score);
+    for hit in &mut hits {
+      let Some(chunk) = index.chunk(hit.chunk) else {
+        continue;
+      };
+      let chunk_title = index
+        .document(&chunk.document)
+        .map_or("", |document| document.title.as_str());
+      let retrieval_score = if best > 0.0 { hit.score / best } else { 0.0 };
+      let score = content * coverage(&terms, &chunk.content)
+        + title * coverage(&terms, chunk_title)
+        + retrieval * retrieval_score;
+      hit.rerank_score = Some(score);
+      hit.score = score;
+    }
+    sort(&mut hits);
+  }
+  hits.truncate(top_k);
+  Ok(hits)
+}
+
+/// 融合两路候选，按融合分数从高到低排序
+fn fuse(dense: &[(u64, f32)], sparse: &[(u64, f32)], fusion: Fusion) -> Vec<Hit> {
+  let mut hits: HashMap<u64, Hit> = HashMap::new();
+  for &(chunk, score) in dense {
+    hits
+      .entry(chunk)
+      .or_insert_with(|| empty_hit(chunk))
+      .dense_score = Some(score);
+  }
+  for &(chunk, score) in sparse {
+    hits
+      .entry(chunk)
+      .or_insert_with(|| empty_hit(chunk))
+      .sparse_score = Some(score);
+  }
+
+  match fusion {
+    Fusion::Rrf { k } => {
+      for list in [dense, sparse] {
+        for (rank, &(chunk, _)) in list.iter().enumerate() {
+          if let Some(hit) = hits.get_mut(&chunk) {
+            hit.score += 1.0 / (k + rank as f32 + 1.0);
+          }
+        }
+      }
+    }
+    Fusion::Linear { dense_weight } => {
+      let dense = normalize(dense);
+      let sparse = normalize(sparse);
+      for hit in hits.values_mut() {
+        hit.score = dense_weight * dense.get(&hit.chunk).copied().unwrap_or(0.0)
+          + (1.0 - dense_weight) * sparse.get(&hit.chunk).copied().unwrap_or(0.0);
+      }
+    }
+  }
+
+  let mut hits: Vec<Hit> = hits.into_values().collect();
+  sort(&mut hits);
+  hits
+}
+
+fn empty_hit(chunk: u64) -> Hit {
+  Hit {
+    chunk,
+    score: 0.0,
+    dense_score: None,
+    sparse_score: None,
+    rerank_score: None,
+  }
+}
+
+fn sort(hits: &mut [Hit]) {
+  hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.chunk.cmp(&b.chunk)));
+}
+
+/// min-max 归一化到 [0, 1]，所有分数相同时都算 1
+fn normalize(scores: &[(u64, f32)]) -> HashMap<u64, f32> {
+  let min = scores.iter().map(|s| s.1).fold(f32::INFINITY, f32::min);
+  let max = scores.iter().map(|s| s.1).fold(f32::NEG_INFINITY, f32::max);
+  scores
+    .iter()
+    .map(|&(chunk, score)| {
+      let norm = if max > min {
+        (score - min) / (max - min)
+      } else {
+        1.0
+      };
+      (chunk, norm)
+    })
+    .collect()
+}
+
+/// 查询词中出现在 `text` 里的比例
+fn coverage(terms: &[String], text: &str) -> f32 {
+  if terms.is_empty() {
+    return 0.0;
+  }
+  let words: Vec<String> = tokenize(text).collect();
+  let found = terms.iter().filter(|term| words.contains(term)).count();
+  found as f32 / terms.len() as f32
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+  let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+  let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+  let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+  if norm_a == 0.0 || norm_b == 0.0 {
+    0.0
+  } else {
+    dot / (norm_a * norm_b)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rrf_rewards_agreement_between_lists() {
+    let dense = [(1, 0.9), (2, 0.8), (3, 0.1)];
+    let sparse = [(2, 7.0), (3, 5.0)];
+    let hits = fuse(&dense, &sparse, Fusion::Rrf { k: 60.0 });
+    assert_eq!(
+      hits.iter().map(|hit| hit.chunk).collect::<Vec<_>>(),
+      [2, 3, 1]
+    );
+    assert_eq!(hits[2].sparse_score, None);
+  }
+
+  #[test]
+  fn linear_fusion_weights_normalized_scores() {
+    let dense = [(1, 0.9), (2, 0.5)];
+    let sparse = [(2, 3.0), (3, 1.0)];
+    let ranked = |dense_weight| {
+      fuse(&dense, &sparse, Fusion::Linear { dense_weight })
+        .iter()
+        .map(|hit| hit.chunk)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(ranked(1.0)[0], 1);
+    assert_eq!(ranked(0.0)[0], 2);
+  }
+}
@@ -0,0 +1,108 @@
+//! BM25 稀疏索引。倒排表按文本块增删，加减一个文档不用重建整个索引。
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// 小写后按非字母数字切词，检索和重排用同一套切词
+pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|term| !term.is_empty())
+    .map(str::to_lowercase)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Bm25 {
+  /// 词 -> (文本块 id -> 词频)
+  postings: BTreeMap<String, BTreeMap<u64, u32>>,
+  /// 文本块 id -> 词数
+  lengths: BTreeMap<u64, u32>,
+  total_length: u64,
+}
+
+impl Bm25 {
+  pub(crate) fn add(&mut self, chunk: u64, text: &str) {
+    let mut length = 0;
+    for term in tokenize(text) {
+      *self
+        .postings
+        .entry(term)
+        .or_default()
+        .entry(chunk)
+        .or_default() += 1;
+      length += 1;
+    }
+    self.lengths.insert(chunk, length);
+    self.total_length += u64::from(length);
+  }
+
+  /// `text` 必须是 `add` 时的文本，只有它的词需要从倒排表里摘掉
+  pub(crate) fn remove(&mut self, chunk: u64, text: &str) {
+    for term in tokenize(text) {
+      if let Some(postings) = self.postings.get_mut(&term) {
+        postings.remove(&chunk);
+        if postings.is_empty() {
+          self.postings.remove(&term);
+        }
+      }
+    }
+    if let Some(length) = self.lengths.remove(&chunk) {
+      self.total_length -= u64::from(length);
+    }
+  }
+
+  /// 按 BM25 分数从高到低返回最多 `limit` 个文本块
+  pub(crate) fn search(&self, query: &str, limit: usize) -> Vec<(u64, f32)> {
+    let chunks = self.lengths.len() as f32;
+    if chunks == 0.0 {
+      return Vec::new();
+    }
+    let average_length = self.total_length as f32 / chunks;
+
+    let mut scores: HashMap<u64, f32> = HashMap::new();
+    let mut terms: Vec<String> = tokenize(query).collect();
+    terms.sort();
+    terms.dedup();
+    for term in terms {
+      let Some(postings) = self.postings.get(&term) else {
+        continue;
+      };
+      let df = postings.len() as f32;
+      let idf = (1.0 + (chunks - df + 0.5) / (df + 0.5)).ln();
+      for (&chunk, &tf) in postings {
+        let tf = tf as f32;
+        let length = self.lengths[&chunk] as f32;
+        let norm = K1 * (1.0 - B + B * length / average_length);
+        *scores.entry(chunk).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+      }
+    }
+
+    let mut scores: Vec<(u64, f32)> = scores.into_iter().collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    scores.truncate(limit);
+    scores
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn removed_chunks_leave_no_postings() {
+    let mut bm25 = Bm25::default();
+    bm25.add(1, "Hybrid retrieval fuses dense and sparse results");
+    bm25.add(2, "Chunking splits long documents");
+    assert_eq!(bm25.search("sparse retrieval", 10)[0].0, 1);
+
+    bm25.remove(1, "Hybrid retrieval fuses dense and sparse results");
+    assert!(bm25.search("sparse retrieval", 10).is_empty());
+    assert_eq!(bm25.search("documents", 10)[0].0, 2);
+    assert_eq!(bm25.total_length, 4);
+    assert!(!bm25.postings.contains_key("hybrid"));
+  }
+}
@@ -0,0 +1,270 @@
+//! 落盘的文本块库和 dense / sparse 索引。
+//!
+//! 文档按 id 增删改：只对变化的文档切块、生成向量、更新 BM25 倒排表，
+//! 其余文档的向量原样保留，不需要全量重建。整个索引存成一个 JSON 文件，
+//! 写入临时文件后再 rename，进程中途退出也不会留下写了一半的索引。
+
+use std::{
+  collections::BTreeMap,
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+use aprender_rag::{Document, chunk::Chunker, embed::Embedder};
+use serde::{Deserialize, Serialize};
+
+use crate::sparse::Bm25;
+
+/// 索引里的一篇源文档。`id` 同时作为引用来源（source）
+#[derive(Debug, Clone)]
+pub struct SourceDocument {
+  pub id: String,
+  pub title: String,
+  pub content: String,
+}
+
+impl SourceDocument {
+  pub fn new(id: &str, title: &str, content: &str) -> Self {
+    Self {
+      id: id.to_string(),
+      title: title.to_string(),
+      content: content.to_string(),
+    }
+  }
+
+  pub fn to_document(&self) -> Document {
+    Document::new(self.content.as_str())
+      .with_title(self.title.as_str())
+      .with_source(self.id.as_str())
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentRecord {
+  pub title: String,
+  /// 内容的 FNV-1a 哈希，用来判断文档是否变化
+  content_hash: u64,
+  chunks: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkRecord {
+  pub document: String,
+  pub content: String,
+  pub embedding: Vec<f32>,
+}
+
+/// `upsert` 对一篇文档做了什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+  Added,
+  Updated,
+  Unchanged,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+  dimension: usize,
+  next_chunk: u64,
+  documents: BTreeMap<String, DocumentRecord>,
+  chunks: BTreeMap<u64, ChunkRecord>,
+  sparse: Bm25,
+}
+
+#[derive(Debug)]
+pub struct PersistentIndex {
+  path: PathBuf,
+  contents: Contents,
+}
+
+impl PersistentIndex {
+  /// 打开 `path` 处的索引，文件不存在时得到一个空索引。
+  /// 向量维度和已有索引不一致说明换了嵌入模型，旧向量不能再用
+  pub fn open(path: &Path, dimension: usize) -> Result<Self, Box<dyn Error>> {
+    let contents = match fs::read(path) {
+      Ok(bytes) => {
+        let contents: Contents = serde_json::from_slice(&bytes)?;
+        if contents.dimension != dimension {
+          return Err(
+            format!(
+              "index {} has {}-dimensional embeddings, the embedder produces {dimension}",
+              path.display(),
+              contents.dimension
+            )
+            .into(),
+          );
+        }
+        contents
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Contents {
+        dimension,
+        ..Contents::default()
+      },
+      Err(e) => return Err(e.into()),
+    };
+    Ok(Self {
+      path: path.to_path_buf(),
+      contents,
+    })
+  }
+
+  pub fn save(&self) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = self.path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let tmp = self.path.with_extension("json.tmp");
+    let file = fs::File::create(&tmp)?;
+    serde_json::to_writer(&file, &self.contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, &self.path)?;
+    Ok(())
+  }
+
+  /// 加入或更新一篇文档。内容没变时什么都不做，变了就先删掉它的旧文本块
+  pub fn upsert(
+    &mut self,
+    document: &SourceDocument,
+    chunker: &impl Chunker,
+    embedder: &impl Embedder,
+  ) -> Result<Change, Box<dyn Error>> {
+    let content_hash = fnv1a(&document.content);
+    let change = match self.contents.documents.get(&document.id) {
+      Some(record) if record.content_hash == content_hash && record.title == document.title => {
+        return Ok(Change::Unchanged);
+      }
+      Some(_) => Change::Updated,
+      None => Change::Added,
+    };
+
+    // 先把新文本块和向量都算好，出错时索引保持原样
+    let mut chunks = Vec::new();
+    for chunk in chunker.chunk(&document.to_document())? {
+      let embedding = embedder.embed(&chunk.content)?;
+      chunks.push((chunk.content, embedding));
+    }
+
+    self.remove(&document.id);
+    let mut ids = Vec::with_capacity(chunks.len());
+    for (content, embedding) in chunks {
+      let id = self.contents.next_chunk;
+      self.contents.next_chunk += 1;
+      self.contents.sparse.add(id, &content);
+      self.contents.chunks.insert(
+        id,
+        ChunkRecord {
+          document: document.id.clone(),
+          content,
+          embedding,
+        },
+      );
+      ids.push(id);
+    }
+    self.contents.documents.insert(
+      document.id.clone(),
+      DocumentRecord {
+        title: document.title.clone(),
+        content_hash,
+        chunks: ids,
+      },
+    );
+    Ok(change)
+  }
+
+  /// 删掉一篇文档和它的文本块，返回文档原来是否存在
+  pub fn remove(&mut self, id: &str) -> bool {
+    let Some(record) = self.contents.documents.remove(id) else {
+      return false;
+    };
+    for chunk in record.chunks {
+      if let Some(chunk_record) = self.contents.chunks.remove(&chunk) {
+        self.contents.sparse.remove(chunk, &chunk_record.content);
+      }
+    }
+    true
+  }
+
+  pub fn document_ids(&self) -> impl Iterator<Item = &str> {
+    self.contents.documents.keys().map(String::as_str)
+  }
+
+  pub fn document(&self, id: &str) -> Option<&DocumentRecord> {
+    self.contents.documents.get(id)
+  }
+
+  pub fn document_count(&self) -> usize {
+    self.contents.documents.len()
+  }
+
+  pub fn chunk_count(&self) -> usize {
+    self.contents.chunks.len()
+  }
+
+  pub fn chunk(&self, id: u64) -> Option<&ChunkRecord> {
+    self.contents.chunks.get(&id)
+  }
+
+  pub(crate) fn chunks(&self) -> impl Iterator<Item = (u64, &ChunkRecord)> {
+    self.contents.chunks.iter().map(|(&id, chunk)| (id, chunk))
+  }
+
+  pub(crate) fn sparse(&self) -> &Bm25 {
+    &self.contents.sparse
+  }
+}
+
+fn fnv1a(text: &str) -> u64 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  for byte in text.bytes() {
+    hash ^= u64::from(byte);
+    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+  }
+  hash
+}
+
+#[cfg(test)]
+mod tests {
+  use aprender_rag::{RecursiveChunker, embed::MockEmbedder};
+
+  use super::*;
+
+  #[test]
+  fn updates_survive_reopening() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("index.json");
+    let chunker = RecursiveChunker::new(700, 80);
+    let embedder = MockEmbedder::new(16);
+    let overview = SourceDocument::new("doc://overview", "overview", "RAG retrieves evidence.");
+    let fusion = SourceDocument::new("doc://fusion", "fusion", "RRF fuses ranked lists.");
+
+    let mut index = PersistentIndex::open(&path, 16).unwrap();
+    assert_eq!(
+      index.upsert(&overview, &chunker, &embedder).unwrap(),
+      Change::Added
+    );
+    assert_eq!(
+      index.upsert(&fusion, &chunker, &embedder).unwrap(),
+      Change::Added
+    );
+    assert_eq!(
+      index.upsert(&overview, &chunker, &embedder).unwrap(),
+      Change::Unchanged
+    );
+    index.save().unwrap();
+
+    let mut index = PersistentIndex::open(&path, 16).unwrap();
+    assert_eq!(index.document_count(), 2);
+    let edited = SourceDocument::new("doc://fusion", "fusion", "DBSF normalises scores.");
+    assert_eq!(
+      index.upsert(&edited, &chunker, &embedder).unwrap(),
+      Change::Updated
+    );
+    assert!(index.sparse().search("RRF", 10).is_empty());
+    assert!(index.remove("doc://overview"));
+    assert!(!index.remove("doc://overview"));
+    assert_eq!(index.document_ids().collect::<Vec<_>>(), ["doc://fusion"]);
+    assert_eq!(index.chunk_count(), index.chunks().count());
+
+    assert!(PersistentIndex::open(&path, 32).is_err());
+  }
+}
@@ -0,0 +1,750 @@
+//! Flight SQL endpoint over a DataFusion `SessionContext`.
+//!
+//! Queries are planned in `get_flight_info` and run again in `do_get`, so
+//! only read-only SQL is accepted there; DDL and DML go through
+//! `do_put` as `CommandStatementUpdate`. Uploaded batches land in
+//! in-memory tables, and every call except the handshake needs a bearer
+//! token the handshake hands out. Tokens expire after [`TOKEN_TTL`].
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError},
+  time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use arrow_array::{ArrayRef, RecordBatch, StringArray, cast::AsArray, types::UInt64Type};
+use arrow_flight::{
+  Action, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse,
+  IpcMessage, SchemaAsIpc, Ticket,
+  decode::FlightRecordBatchStream,
+  encode::FlightDataEncoderBuilder,
+  error::FlightError,
+  flight_service_server::FlightService,
+  sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult, CommandGetCatalogs, CommandGetDbSchemas,
+    CommandGetSqlInfo, CommandGetTableTypes, CommandGetTables, CommandPreparedStatementQuery,
+    CommandStatementIngest, CommandStatementQuery, CommandStatementUpdate,
+    DoPutPreparedStatementResult, ProstMessageExt, SqlInfo, TableExistsOption, TableNotExistOption,
+    TicketStatementQuery,
+    metadata::{SqlInfoData, SqlInfoDataBuilder},
+    server::{FlightSqlService, PeekableFlightDataStream},
+  },
+};
+use arrow_ipc::writer::IpcWriteOptions;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use datafusion::{
+  common::TableReference,
+  datasource::{MemTable, TableProvider, TableType},
+  error::DataFusionError,
+  execution::context::{SQLOptions, SessionContext},
+  logical_expr::{DdlStatement, LogicalPlan},
+  scalar::ScalarValue,
+};
+use futures::{Stream, StreamExt, TryStreamExt, stream::BoxStream};
+use prost::{Message, bytes::Bytes};
+use tonic::{Request, Response, Status, Streaming, metadata::MetadataValue};
+
+static SQL_INFO: LazyLock<SqlInfoData> = LazyLock::new(|| {
+  let mut builder = SqlInfoDataBuilder::new();
+  builder.append(SqlInfo::FlightSqlServerName, "sales-flight-sql");
+  builder.append(SqlInfo::FlightSqlServerVersion, env!("CARGO_PKG_VERSION"));
+  builder.append(SqlInfo::FlightSqlServerArrowVersion, "59");
+  builder.append(SqlInfo::FlightSqlServerReadOnly, false);
+  builder.append(SqlInfo::FlightSqlServerSql, true);
+  builder.build().expect("SqlInfo values are valid")
+});
+
+/// How long a handshake token stays valid; clients handshake again after.
+pub const TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+type DoGetStream = <SalesFlightService as FlightService>::DoGetStream;
+
+/// The query of a prepared statement and the parameters last bound to it.
+struct PreparedStatement {
+  query: String,
+  parameters: Vec<ScalarValue>,
+}
+
+#[derive(Clone)]
+pub struct SalesFlightService {
+  ctx: Arc<SessionContext>,
+  endpoint_uri: String,
+  username: String,
+  password: String,
+  /// Bearer tokens and when they expire.
+  tokens: Arc<Mutex<HashMap<String, Instant>>>,
+  prepared: Arc<Mutex<HashMap<String, PreparedStatement>>>,
+  /// Ingest replaces a table to append to it, so two of them must not
+  /// interleave.
+  ingest: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SalesFlightService {
+  /// A service with `batches` registered as the `sales` table.
+  pub fn new(
+    batches: Vec<RecordBatch>,
+    endpoint_uri: String,
+    username: impl Into<String>,
+    password: impl Into<String>,
+  ) -> Result<Self> {
+    let ctx = SessionContext::new();
+    let schema = batches
+      .first()
+      .map(RecordBatch::schema)
+      .ok_or_else(|| anyhow::anyhow!("demo needs at least one RecordBatch"))?;
+    ctx.register_table("sales", Arc::new(MemTable::try_new(schema, vec![batches])?))?;
+
+    Ok(Self {
+      ctx: Arc::new(ctx),
+      endpoint_uri,
+      username: username.into(),
+      password: password.into(),
+      tokens: Arc::default(),
+      prepared: Arc::default(),
+      ingest: Arc::default(),
+    })
+  }
+
+  fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+    let token = request
+      .metadata()
+      .get("authorization")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Bearer "))
+      .ok_or_else(|| Status::unauthenticated("missing bearer token, call handshake first"))?;
+    let mut tokens = lock(&self.tokens);
+    match tokens.get(token) {
+      Some(expires) if *expires > Instant::now() => Ok(()),
+      Some(_) => {
+        tokens.remove(token);
+        Err(Status::unauthenticated(
+          "bearer token expired, call handshake again",
+        ))
+      }
+      None => Err(Status::unauthenticated("unknown bearer token")),
+    }
+  }
+
+  /// Plans `query` without running it, rejecting anything but queries.
+  async fn plan(&self, query: &str) -> Result<LogicalPlan, Status> {
+    let plan = self
+      .ctx
+      .state()
+      .create_logical_plan(query)
+      .await
+      .map_err(datafusion_status)?;
+    read_only().verify_plan(&plan).map_err(datafusion_status)?;
+    Ok(plan)
+  }
+
+  async fn execute(
+    &self,
+    query: &str,
+    parameters: Vec<ScalarValue>,
+  ) -> Result<DoGetStream, Status> {
+    let mut dataframe = self
+      .ctx
+      .sql_with_options(query, read_only())
+      .await
+      .map_err(datafusion_status)?;
+    if !parameters.is_empty() {
+      dataframe = dataframe
+        .with_param_values(parameters)
+        .map_err(datafusion_status)?;
+    }
+    let schema = Arc::clone(dataframe.schema().inner());
+    let batches = dataframe
+      .execute_stream()
+      .await
+      .map_err(datafusion_status)?
+      .map_err(|error| FlightError::ExternalError(Box::new(error)));
+    Ok(encode(schema, batches))
+  }
+
+  fn flight_info(
+    &self,
+    schema: &Schema,
+    ticket: Ticket,
+    descriptor: FlightDescriptor,
+  ) -> Result<Response<FlightInfo>, Status> {
+    let info = FlightInfo::new()
+      .try_with_schema(schema)
+      .map_err(arrow_status)?
+      .with_descriptor(descriptor)
+      .with_endpoint(
+        FlightEndpoint::new()
+          .with_ticket(ticket)
+          .with_location(self.endpoint_uri.clone()),
+      );
+    Ok(Response::new(info))
+  }
+
+  fn prepared_statement(&self, handle: &[u8]) -> Result<(String, Vec<ScalarValue>), Status> {
+    let handle = std::str::from_utf8(handle)
+      .map_err(|_| Status::invalid_argument("prepared statement handle is not UTF-8"))?;
+    lock(&self.prepared)
+      .get(handle)
+      .map(|statement| (statement.query.clone(), statement.parameters.clone()))
+      .ok_or_else(|| Status::not_found(format!("unknown prepared statement {handle}")))
+  }
+
+  /// Every table of every schema and catalog, with its provider.
+  async fn tables(&self) -> Result<Vec<(String, String, String, Arc<dyn TableProvider>)>, Status> {
+    let mut rows = Vec::new();
+    for catalog_name in self.ctx.catalog_names() {
+      let Some(catalog) = self.ctx.catalog(&catalog_name) else {
+        continue;
+      };
+      for schema_name in catalog.schema_names() {
+        let Some(schema) = catalog.schema(&schema_name) else {
+          continue;
+        };
+        for table_name in schema.table_names() {
+          if let Some(table) = schema.table(&table_name).await.map_err(datafusion_status)? {
+            rows.push((catalog_name.clone(), schema_name.clone(), table_name, table));
+          }
+        }
+      }
+    }
+    Ok(rows)
+  }
+}
+
+#[tonic::async_trait]
+impl FlightSqlService for SalesFlightService {
+  type FlightService = Self;
+
+  /// Basic auth: `authorization: Basic base64(user:password)` is answered
+  /// with a bearer token in the `authorization` header of the response.
+  async fn do_handshake(
+    &self,
+    request: Request<Streaming<HandshakeRequest>>,
+  ) -> Result<Response<BoxStream<'static, Result<HandshakeResponse, Status>>>, Status> {
+    let credentials = request
+      .metadata()
+      .get("authorization")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Basic "))
+      .and_then(|encoded| BASE64_STANDARD.decode(encoded).ok())
+      .and_then(|decoded| String::from_utf8(decoded).ok())
+      .ok_or_else(|| Status::invalid_argument("expected basic authorization"))?;
+    let (username, password) = credentials
+      .split_once(':')
+      .ok_or_else(|| Status::invalid_argument("expected user:password"))?;
+    if username != self.username || password != self.password {
+      return Err(Status::unauthenticated("invalid credentials"));
+    }
+
+    let token = uuid::Uuid::new_v4().to_string();
+    let now = Instant::now();
+    let mut tokens = lock(&self.tokens);
+    tokens.retain(|_, expires| *expires > now);
+    tokens.insert(token.clone(), now + TOKEN_TTL);
+    drop(tokens);
+    let handshake = HandshakeResponse {
+      protocol_version: 0,
+      payload: token.clone().into(),
+    };
+    let mut response = Response::new(futures::stream::iter([Ok(handshake)]).boxed());
+    let header = MetadataValue::try_from(format!("Bearer {token}"))
+      .map_err(|error| Status::internal(error.to_string()))?;
+    response.metadata_mut().insert("authorization", header);
+    Ok(response)
+  }
+
+  async fn get_flight_info_statement(
+    &self,
+    query: CommandStatementQuery,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let plan = self.plan(&query.query).await?;
+    let ticket = TicketStatementQuery {
+      statement_handle: query.query.into(),
+    };
+    self.flight_info(
+      plan.schema().as_arrow(),
+      Ticket::new(ticket.as_any().encode_to_vec()),
+      request.into_inner(),
+    )
+  }
+
+  async fn do_get_statement(
+    &self,
+    ticket: TicketStatementQuery,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let query = std::str::from_utf8(&ticket.statement_handle)
+      .map_err(|_| Status::invalid_argument("statement handle is not UTF-8"))?;
+    Ok(Response::new(self.execute(query, Vec::new()).await?))
+  }
+
+  async fn do_put_statement_update(
+    &self,
+    command: CommandStatementUpdate,
+    request: Request<PeekableFlightDataStream>,
+  ) -> Result<i64, Status> {
+    self.authenticate(&request)?;
+    let plan = self
+      .ctx
+      .state()
+      .create_logical_plan(&command.query)
+      .await
+      .map_err(datafusion_status)?;
+    check_update(&plan)?;
+    let batches = self
+      .ctx
+      .execute_logical_plan(plan)
+      .await
+      .map_err(datafusion_status)?
+      .collect()
+      .await
+      .map_err(datafusion_status)?;
+    Ok(affected_rows(&batches))
+  }
+
+  /// Creates, appends to or replaces an in-memory table as the table
+  /// definition options ask. Unspecified options create missing tables and
+  /// refuse existing ones.
+  async fn do_put_statement_ingest(
+    &self,
+    command: CommandStatementIngest,
+    request: Request<PeekableFlightDataStream>,
+  ) -> Result<i64, Status> {
+    self.authenticate(&request)?;
+    if command.temporary {
+      return Err(Status::unimplemented("temporary tables are not supported"));
+    }
+    let table = match (command.catalog, command.schema) {
+      (Some(catalog), Some(schema)) => TableReference::full(catalog, schema, command.table),
+      (None, Some(schema)) => TableReference::partial(schema, command.table),
+      (None, None) => TableReference::bare(command.table),
+      (Some(_), None) => return Err(Status::invalid_argument("a catalog needs a schema")),
+    };
+    let options = command.table_definition_options.unwrap_or_default();
+
+    let mut stream = FlightRecordBatchStream::new_from_flight_data(
+      request.into_inner().map_err(FlightError::from),
+    );
+    let mut uploaded = Vec::new();
+    while let Some(batch) = stream.try_next().await? {
+      uploaded.push(batch);
+    }
+    let uploaded_schema = stream
+      .schema()
+      .cloned()
+      .ok_or_else(|| Status::invalid_argument("ingest stream has no schema"))?;
+    let rows = uploaded.iter().map(RecordBatch::num_rows).sum::<usize>();
+
+    let _ingest = self.ingest.lock().await;
+    let exists = self
+      .ctx
+      .table_exist(table.clone())
+      .map_err(datafusion_status)?;
+    let (schema, mut batches) = match (exists, options.if_exists(), options.if_not_exist()) {
+      (true, TableExistsOption::Append, _) => {
+        let existing = self
+          .ctx
+          .table(table.clone())
+          .await
+          .map_err(datafusion_status)?;
+        let schema = Arc::clone(existing.schema().inner());
+        if !same_columns(&schema, &uploaded_schema) {
+          return Err(Status::invalid_argument(format!(
+            "columns of {table} do not match the uploaded batches"
+          )));
+        }
+        (schema, existing.collect().await.map_err(datafusion_status)?)
+      }
+      (true, TableExistsOption::Replace, _) => (uploaded_schema, Vec::new()),
+      (true, ..) => return Err(Status::already_exists(format!("table {table} exists"))),
+      (false, _, TableNotExistOption::Fail) => {
+        return Err(Status::not_found(format!("table {table} does not exist")));
+      }
+      (false, ..) => (uploaded_schema, Vec::new()),
+    };
+    for batch in uploaded {
+      batches.push(
+        batch
+          .with_schema(Arc::clone(&schema))
+          .map_err(|error| Status::invalid_argument(error.to_string()))?,
+      );
+    }
+
+    let memory_table = MemTable::try_new(schema, vec![batches]).map_err(datafusion_status)?;
+    if exists {
+      self
+        .ctx
+        .deregister_table(table.clone())
+        .map_err(datafusion_status)?;
+    }
+    self
+      .ctx
+      .register_table(table, Arc::new(memory_table))
+      .map_err(datafusion_status)?;
+    Ok(rows as i64)
+  }
+
+  async fn do_action_create_prepared_statement(
+    &self,
+    query: ActionCreatePreparedStatementRequest,
+    request: Request<Action>,
+  ) -> Result<ActionCreatePreparedStatementResult, Status> {
+    self.authenticate(&request)?;
+    let plan = self.plan(&query.query).await?;
+    let dataset_schema = schema_bytes(plan.schema().as_arrow())?;
+    let parameter_schema = schema_bytes(&parameter_schema(&plan)?)?;
+
+    let handle = uuid::Uuid::new_v4().to_string();
+    lock(&self.prepared).insert(
+      handle.clone(),
+      PreparedStatement {
+        query: query.query,
+        parameters: Vec::new(),
+      },
+    );
+    Ok(ActionCreatePreparedStatementResult {
+      prepared_statement_handle: handle.into(),
+      dataset_schema,
+      parameter_schema,
+    })
+  }
+
+  async fn do_action_close_prepared_statement(
+    &self,
+    query: ActionClosePreparedStatementRequest,
+    request: Request<Action>,
+  ) -> Result<(), Status> {
+    self.authenticate(&request)?;
+    let handle = String::from_utf8_lossy(&query.prepared_statement_handle);
+    lock(&self.prepared).remove(handle.as_ref());
+    Ok(())
+  }
+
+  /// Binds parameters: the upload must hold exactly one row, with one
+  /// column per placeholder in order.
+  async fn do_put_prepared_statement_query(
+    &self,
+    query: CommandPreparedStatementQuery,
+    request: Request<PeekableFlightDataStream>,
+  ) -> Result<DoPutPreparedStatementResult, Status> {
+    self.authenticate(&request)?;
+    let batches: Vec<RecordBatch> = FlightRecordBatchStream::new_from_flight_data(
+      request.into_inner().map_err(FlightError::from),
+    )
+    .try_collect()
+    .await?;
+    let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+    let Some(row) = batches
+      .iter()
+      .find(|batch| batch.num_rows() == 1)
+      .filter(|_| rows == 1)
+    else {
+      return Err(Status::invalid_argument(format!(
+        "expected one row of parameters, got {rows}"
+      )));
+    };
+    let parameters = row
+      .columns()
+      .iter()
+      .map(|column| ScalarValue::try_from_array(column.as_ref(), 0))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(datafusion_status)?;
+
+    let handle = String::from_utf8_lossy(&query.prepared_statement_handle);
+    lock(&self.prepared)
+      .get_mut(handle.as_ref())
+      .ok_or_else(|| Status::not_found(format!("unknown prepared statement {handle}")))?
+      .parameters = parameters;
+    Ok(DoPutPreparedStatementResult {
+      prepared_statement_handle: Some(query.prepared_statement_handle),
+    })
+  }
+
+  async fn get_flight_info_prepared_statement(
+    &self,
+    query: CommandPreparedStatementQuery,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let (statement, _) = self.prepared_statement(&query.prepared_statement_handle)?;
+    let plan = self.plan(&statement).await?;
+    self.flight_info(
+      plan.schema().as_arrow(),
+      Ticket::new(query.as_any().encode_to_vec()),
+      request.into_inner(),
+    )
+  }
+
+  async fn do_get_prepared_statement(
+    &self,
+    query: CommandPreparedStatementQuery,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let (statement, parameters) = self.prepared_statement(&query.prepared_statement_handle)?;
+    Ok(Response::new(self.execute(&statement, parameters).await?))
+  }
+
+  async fn get_flight_info_catalogs(
+    &self,
+    query: CommandGetCatalogs,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let ticket = Ticket::new(query.as_any().encode_to_vec());
+    let schema = query.into_builder().schema();
+    self.flight_info(&schema, ticket, request.into_inner())
+  }
+
+  async fn do_get_catalogs(
+    &self,
+    query: CommandGetCatalogs,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let mut builder = query.into_builder();
+    for catalog in self.ctx.catalog_names() {
+      builder.append(catalog);
+    }
+    let schema = builder.schema();
+    Ok(Response::new(single_batch(schema, builder.build())))
+  }
+
+  async fn get_flight_info_schemas(
+    &self,
+    query: CommandGetDbSchemas,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let ticket = Ticket::new(query.as_any().encode_to_vec());
+    let schema = query.into_builder().schema();
+    self.flight_info(&schema, ticket, request.into_inner())
+  }
+
+  async fn do_get_schemas(
+    &self,
+    query: CommandGetDbSchemas,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let mut builder = query.into_builder();
+    for catalog_name in self.ctx.catalog_names() {
+      if let Some(catalog) = self.ctx.catalog(&catalog_name) {
+        for schema_name in catalog.schema_names() {
+          builder.append(&catalog_name, schema_name);
+        }
+      }
+    }
+    let schema = builder.schema();
+    Ok(Response::new(single_batch(schema, builder.build())))
+  }
+
+  async fn get_flight_info_tables(
+    &self,
+    query: CommandGetTables,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let ticket = Ticket::new(query.as_any().encode_to_vec());
+    let schema = query.into_builder().schema();
+    self.flight_info(&schema, ticket, request.into_inner())
+  }
+
+  async fn do_get_tables(
+    &self,
+    query: CommandGetTables,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let mut builder = query.into_builder();
+    for (catalog, schema, name, table) in self.tables().await? {
+      builder
+        .append(
+          catalog,
+          schema,
+          name,
+          table_type(table.table_type()),
+          table.schema().as_ref(),
+        )
+        .map_err(arrow_status)?;
+    }
+    let schema = builder.schema();
+    Ok(Response::new(single_batch(schema, builder.build())))
+  }
+
+  async fn get_flight_info_table_types(
+    &self,
+    query: CommandGetTableTypes,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    self.flight_info(
+      &table_types().schema(),
+      Ticket::new(query.as_any().encode_to_vec()),
+      request.into_inner(),
+    )
+  }
+
+  async fn do_get_table_types(
+    &self,
+    _query: CommandGetTableTypes,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let batch = table_types();
+    Ok(Response::new(single_batch(batch.schema(), Ok(batch))))
+  }
+
+  async fn get_flight_info_sql_info(
+    &self,
+    query: CommandGetSqlInfo,
+    request: Request<FlightDescriptor>,
+  ) -> Result<Response<FlightInfo>, Status> {
+    self.authenticate(&request)?;
+    let ticket = Ticket::new(query.as_any().encode_to_vec());
+    let schema = query.into_builder(&SQL_INFO).schema();
+    self.flight_info(&schema, ticket, request.into_inner())
+  }
+
+  async fn do_get_sql_info(
+    &self,
+    query: CommandGetSqlInfo,
+    request: Request<Ticket>,
+  ) -> Result<Response<DoGetStream>, Status> {
+    self.authenticate(&request)?;
+    let builder = query.into_builder(&SQL_INFO);
+    let schema = builder.schema();
+    Ok(Response::new(single_batch(schema, builder.build())))
+  }
+
+  async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What `get_flight_info` may plan: it must not change anything, since the
+/// query runs again for `do_get`.
+fn read_only() -> SQLOptions {
+  SQLOptions::new()
+    .with_allow_ddl(false)
+    .with_allow_dml(false)
+    .with_allow_statements(false)
+}
+
+/// What `do_put_statement_update` may run: DML and in-memory tables.
+/// External tables and `COPY … TO` would read or write files on the
+/// server, and statements like `SET` change the session every client shares.
+fn check_update(plan: &LogicalPlan) -> Result<(), Status> {
+  match plan {
+    LogicalPlan::Dml(_)
+    | LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(_) | DdlStatement::DropTable(_)) => Ok(()),
+    _ => Err(Status::permission_denied(
+      "only INSERT, UPDATE, DELETE, CREATE TABLE and DROP TABLE are allowed",
+    )),
+  }
+}
+
+fn encode(
+  schema: SchemaRef,
+  batches: impl Stream<Item = Result<RecordBatch, FlightError>> + Send + 'static,
+) -> DoGetStream {
+  FlightDataEncoderBuilder::new()
+    .with_schema(schema)
+    .build(batches)
+    .map_err(Status::from)
+    .boxed()
+}
+
+fn single_batch(schema: SchemaRef, batch: Result<RecordBatch, ArrowError>) -> DoGetStream {
+  encode(
+    schema,
+    futures::stream::once(async { batch.map_err(FlightError::from) }),
+  )
+}
+
+/// Placeholders `$1`, `$2`, … in order, with the types DataFusion inferred.
+fn parameter_schema(plan: &LogicalPlan) -> Result<Schema, Status> {
+  let mut parameters = plan
+    .get_parameter_fields()
+    .map_err(datafusion_status)?
+    .into_iter()
+    .collect::<Vec<_>>();
+  parameters.sort_by_key(|(name, _)| {
+    name
+      .trim_start_matches('$')
+      .parse::<usize>()
+      .unwrap_or(usize::MAX)
+  });
+  Ok(Schema::new(
+    parameters
+      .into_iter()
+      .map(|(name, field)| {
+        let data_type = field.map_or(DataType::Null, |field| field.data_type().clone());
+        Field::new(name, data_type, true)
+      })
+      .collect::<Vec<_>>(),
+  ))
+}
+
+fn schema_bytes(schema: &Schema) -> Result<Bytes, Status> {
+  let IpcMessage(bytes) = SchemaAsIpc::new(schema, &IpcWriteOptions::default())
+    .try_into()
+    .map_err(arrow_status)?;
+  Ok(bytes)
+}
+
+/// DataFusion reports DML results as a single `count` column.
+fn affected_rows(batches: &[RecordBatch]) -> i64 {
+  batches
+    .iter()
+    .filter_map(|batch| {
+      batch
+        .column_by_name("count")?
+        .as_primitive_opt::<UInt64Type>()
+    })
+    .flat_map(|counts| counts.iter().flatten())
+    .sum::<u64>() as i64
+}
+
+fn same_columns(a: &Schema, b: &Schema) -> bool {
+  a.fields().len() == b.fields().len()
+    && a
+      .fields()
+      .iter()
+      .zip(b.fields())
+      .all(|(a, b)| a.name() == b.name() && a.data_type() == b.data_type())
+}
+
+fn table_type(table_type: TableType) -> &'static str {
+  match table_type {
+    TableType::Base => "TABLE",
+    TableType::View => "VIEW",
+    TableType::Temporary => "LOCAL TEMPORARY",
+  }
+}
+
+fn table_types() -> RecordBatch {
+  let types: ArrayRef = Arc::new(StringArray::from(vec!["TABLE", "VIEW", "LOCAL TEMPORARY"]));
+  RecordBatch::try_from_iter([("table_type", types)]).expect("a single Utf8 column")
+}
+
+fn datafusion_status(error: DataFusionError) -> Status {
+  match error.find_root() {
+    DataFusionError::SQL(..) | DataFusionError::Plan(..) | DataFusionError::SchemaError(..) => {
+      Status::invalid_argument(error.to_string())
+    }
+    DataFusionError::NotImplemented(..) => Status::unimplemented(error.to_string()),
+    _ => Status::internal(error.to_string()),
+  }
+}
+
+fn arrow_status(error: ArrowError) -> Status {
+  Status::internal(error.to_string())
+}
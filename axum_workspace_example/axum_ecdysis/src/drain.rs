@@ -0,0 +1,141 @@
+use std::{
+  sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+  },
+  time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, watch};
+
+/// Shared drain state: HTTP connections and WebSocket sessions register here,
+/// and everything is told to wind down once draining starts.
+#[derive(Clone)]
+pub struct Drain {
+  inner: Arc<DrainInner>,
+}
+
+struct DrainInner {
+  started: watch::Sender<bool>,
+  connections: Inflight,
+  websockets: Inflight,
+}
+
+/// Outcome of draining the old process, reported to the status endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DrainReport {
+  pub connections: usize,
+  pub websockets: usize,
+  pub forced: usize,
+  pub timed_out: bool,
+  pub duration_ms: u64,
+}
+
+impl Drain {
+  pub fn new() -> Self {
+    let (started, _) = watch::channel(false);
+    Self {
+      inner: Arc::new(DrainInner {
+        started,
+        connections: Inflight::default(),
+        websockets: Inflight::default(),
+      }),
+    }
+  }
+
+  pub fn is_draining(&self) -> bool {
+    *self.inner.started.borrow()
+  }
+
+  pub fn start(&self) {
+    if !self.inner.started.send_replace(true) {
+      log::info!(
+        "draining {} connections and {} websocket sessions",
+        self.inner.connections.count(),
+        self.inner.websockets.count()
+      );
+    }
+  }
+
+  /// Resolves once draining has started (immediately if it already has).
+  pub async fn started(&self) {
+    let mut started = self.inner.started.subscribe();
+    let _ = started.wait_for(|started| *started).await;
+  }
+
+  pub fn track_connection(&self) -> InflightGuard {
+    InflightGuard::new(self.inner.clone(), |inner| &inner.connections)
+  }
+
+  pub fn track_websocket(&self) -> InflightGuard {
+    InflightGuard::new(self.inner.clone(), |inner| &inner.websockets)
+  }
+
+  /// Starts draining and waits for in-flight work, giving up after `deadline`.
+  pub async fn finish(&self, deadline: Duration) -> DrainReport {
+    self.start();
+
+    let started_at = Instant::now();
+    let connections = self.inner.connections.count();
+    let websockets = self.inner.websockets.count();
+    let drained = tokio::time::timeout(deadline, async {
+      tokio::join!(self.inner.connections.idle(), self.inner.websockets.idle());
+    })
+    .await
+    .is_ok();
+
+    DrainReport {
+      connections,
+      websockets,
+      forced: self.inner.connections.count() + self.inner.websockets.count(),
+      timed_out: !drained,
+      duration_ms: started_at.elapsed().as_millis() as u64,
+    }
+  }
+}
+
+#[derive(Default)]
+struct Inflight {
+  count: AtomicUsize,
+  idle: Notify,
+}
+
+impl Inflight {
+  fn count(&self) -> usize {
+    self.count.load(Ordering::Acquire)
+  }
+
+  async fn idle(&self) {
+    loop {
+      // `notify_waiters` wakes futures created before it is called, so create
+      // it before checking the count to avoid missing the last release.
+      let idle = self.idle.notified();
+      if self.count() == 0 {
+        return;
+      }
+      idle.await;
+    }
+  }
+}
+
+pub struct InflightGuard {
+  inner: Arc<DrainInner>,
+  select: fn(&DrainInner) -> &Inflight,
+}
+
+impl InflightGuard {
+  fn new(inner: Arc<DrainInner>, select: fn(&DrainInner) -> &Inflight) -> Self {
+    select(&inner).count.fetch_add(1, Ordering::AcqRel);
+    Self { inner, select }
+  }
+}
+
+impl Drop for InflightGuard {
+  fn drop(&mut self) {
+    let inflight = (self.select)(&self.inner);
+    if inflight.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+      inflight.idle.notify_waiters();
+    }
+  }
+}
@@ -4,16 +4,27 @@ use std::{
   net::SocketAddr,
   os::unix::{fs::FileTypeExt, net::UnixStream as StdUnixStream},
   path::Path,
+  pin::pin,
+  sync::{
+    Arc, OnceLock,
+    atomic::{AtomicBool, Ordering},
+  },
   time::{Duration, Instant},
 };
 
 use axum::{
   Json, Router,
-  extract::{ConnectInfo, Request, State},
+  body::Body,
+  extract::{
+    ConnectInfo, Request, State,
+    ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+  },
+  http::StatusCode,
   response::IntoResponse,
   routing::get,
 };
-use ecdysis::tokio_ecdysis::{SignalKind, StopOnShutdown, TokioEcdysisBuilder};
+use drain::{Drain, DrainReport, InflightGuard};
+use ecdysis::tokio_ecdysis::{ExitMode, SignalKind, StopOnShutdown, TokioEcdysisBuilder};
 use futures::{Stream, StreamExt};
 use hyper::body::Incoming;
 use hyper_util::{
@@ -24,19 +35,44 @@ use nix::unistd::getpid;
 use serde::Serialize;
 use tokio::net::TcpStream;
 use tower::{Service, ServiceExt};
-
-const EXIT_AFTER_UPGRADE_DURATION: Duration = Duration::from_secs(60);
+use upgrade::{ChildLink, ParentLink, UPGRADE_CHANNEL, UpgradeHistory, UpgradeRecord};
+
+mod drain;
+mod upgrade;
+
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_WARMUP: Duration = Duration::ZERO;
+// ecdysis kills a child that has not called `ready` within 5s, so warmup and
+// the readiness probe have to fit comfortably inside that window.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(4);
+const READINESS_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+// Fault injection for trying out rollbacks; only honoured when the operator
+// opts in through `FAULT_INJECTION_ENV`.
+const FAULT_INJECTION_ENV: &str = "APP_FAULT_INJECTION";
+const WARMUP_CRASH_FILE: &str = "./crash_on_warmup";
 const DEFAULT_BIND_ADDR: &str = "127.0.0.1:3000";
 const PID_FILE: &str = "./pidfile";
 const UPGRADE_SOCKET_PATH: &str = "/tmp/axum_ecdysis_upgrade.sock";
 const STOP_SOCKET_PATH: &str = "/tmp/axum_ecdysis_exit.sock";
 const PARTIAL_STOP_SOCKET_PATH: &str = "/tmp/axum_ecdysis_partial_exit.sock";
+const CONTROL_SOCKETS: [&str; 3] = [
+  UPGRADE_SOCKET_PATH,
+  STOP_SOCKET_PATH,
+  PARTIAL_STOP_SOCKET_PATH,
+];
+const READINESS_BODY_LIMIT: usize = 64 * 1024;
 
 #[derive(Clone)]
 struct AppState {
   pid: u32,
   reload_count: u32,
   started_at: Instant,
+  // Set once warmup has finished; `/readyz` checks it alongside the sockets
+  warmed_up: Arc<OnceLock<Duration>>,
+  // Set once the readiness probe passed and the listener is being handed over
+  ready: Arc<AtomicBool>,
+  drain: Drain,
+  history: UpgradeHistory,
 }
 
 #[derive(Serialize)]
@@ -46,6 +82,23 @@ struct StatusResponse {
   reload_count: u32,
   uptime_secs: u64,
   remote_addr: String,
+  ready: bool,
+  draining: bool,
+  upgrade_history: Vec<UpgradeRecord>,
+}
+
+#[derive(Serialize)]
+struct ReadinessCheck {
+  name: &'static str,
+  ok: bool,
+  detail: String,
+}
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+  ready: bool,
+  draining: bool,
+  checks: Vec<ReadinessCheck>,
 }
 
 #[derive(Serialize)]
@@ -73,12 +126,25 @@ async fn main() -> io::Result<()> {
   }
 
   let bind_addr = parse_bind_addr()?;
+  let drain_timeout =
+    parse_duration_env("APP_DRAIN_TIMEOUT_SECS", DEFAULT_DRAIN_TIMEOUT, |secs| {
+      Duration::from_secs(secs)
+    })?;
+  let warmup = parse_duration_env("APP_WARMUP_MS", DEFAULT_WARMUP, Duration::from_millis)?;
+  let fault_injection = env::var(FAULT_INJECTION_ENV).is_ok_and(|value| value == "1");
+  if fault_injection {
+    log::warn!("fault injection enabled: {WARMUP_CRASH_FILE} makes upgrades crash");
+  }
   let app_state = AppState {
     pid,
     reload_count,
     started_at: Instant::now(),
+    warmed_up: Arc::new(OnceLock::new()),
+    ready: Arc::new(AtomicBool::new(false)),
+    drain: Drain::new(),
+    history: UpgradeHistory::default(),
   };
-  let app = build_app(app_state);
+  let app = build_app(app_state.clone());
 
   let mut ecdysis_builder = TokioEcdysisBuilder::new(SignalKind::hangup())?;
   if reload_count == 0 {
@@ -103,10 +169,8 @@ async fn main() -> io::Result<()> {
   let should_try_systemd = should_try_systemd_sockets(reload_count);
 
   #[cfg(feature = "systemd_sockets")]
-  if should_try_systemd {
-    if let Err(err) = ecdysis_builder.read_systemd_sockets() {
-      log::info!("systemd sockets unavailable, skipping: {err:?}");
-    }
+  if should_try_systemd && let Err(err) = ecdysis_builder.read_systemd_sockets() {
+    log::info!("systemd sockets unavailable, skipping: {err:?}");
   }
 
   let listener_stream =
@@ -124,40 +188,120 @@ async fn main() -> io::Result<()> {
       Ok(builder.into())
     })?;
 
-  log::info!("HTTP server listening on {bind_addr}");
+  // The parent end links us to the process we are replacing; the child end is
+  // handed to whichever process eventually replaces us.
+  let (to_parent, to_child) = ecdysis_builder.unix_datagram_pair(UPGRADE_CHANNEL.to_owned());
+  let parent = match to_parent {
+    Some(Ok(socket)) => {
+      Some(ParentLink::connect(socket, app_state.history.clone(), pid, reload_count).await)
+    }
+    Some(Err(err)) => {
+      log::warn!("cannot open upgrade channel to parent: {err}");
+      None
+    }
+    None => None,
+  };
+  let children = ChildLink::new(to_child?, app_state.history.clone(), pid);
+  tokio::spawn(children.clone().watch());
+
+  let warmup_start_time = Instant::now();
+  if let Err(reason) = warm_up(&app, &app_state, warmup, fault_injection).await {
+    log::error!("warmup failed, leaving the current process in charge: {reason}");
+    if let Some(parent) = &parent {
+      parent.warmup_failed(&reason).await;
+    }
+    return Err(io::Error::other(reason));
+  }
+  let warmup_elapsed = warmup_start_time.elapsed();
+
+  log::info!("HTTP server listening on {bind_addr} (ready after {warmup_elapsed:?})");
   log::info!("upgrade socket: {UPGRADE_SOCKET_PATH}");
   log::info!("stop socket: {STOP_SOCKET_PATH}");
   log::info!("partial stop socket: {PARTIAL_STOP_SOCKET_PATH}");
 
-  let server_handle = tokio::spawn(run_axum(listener_stream, app));
+  let server_handle = tokio::spawn(run_axum(
+    listener_stream,
+    app,
+    app_state.drain.clone(),
+    drain_timeout,
+  ));
 
   let (_tokio_ecdysis, ecdysis_fut) = ecdysis_builder.ready()?;
+  if let Some(parent) = &parent {
+    parent.ready(warmup_elapsed).await;
+  }
+
   let exit = ecdysis_fut.await;
-  let exit_start_time = Instant::now();
   log::info!("shutdown triggered: {exit:?}");
+  app_state.drain.start();
 
-  tokio::spawn(async {
-    tokio::time::sleep(EXIT_AFTER_UPGRADE_DURATION).await;
-    log::error!(
-      "force-exiting after {:?} waiting for draining connections",
-      EXIT_AFTER_UPGRADE_DURATION
+  let drain_report = server_handle.await??;
+  if drain_report.timed_out {
+    log::warn!(
+      "drain deadline of {drain_timeout:?} reached, closing {} remaining connections",
+      drain_report.forced
     );
-    std::process::exit(1);
-  });
+  }
+  if let Ok((ExitMode::Upgrade, _)) = &exit {
+    children.report_drain(drain_report.clone()).await;
+  }
+
+  log::info!("exit after draining {drain_report:?} (reason: {exit:?})");
+  Ok(())
+}
 
-  server_handle.await??;
+async fn warm_up(
+  app: &Router,
+  state: &AppState,
+  warmup: Duration,
+  fault_injection: bool,
+) -> Result<(), String> {
+  let started_at = Instant::now();
+  // Stand-in for priming caches, opening pools and so on.
+  tokio::time::sleep(warmup).await;
+  if fault_injection && state.reload_count > 0 && Path::new(WARMUP_CRASH_FILE).exists() {
+    panic!("simulated crash during warmup ({WARMUP_CRASH_FILE} exists)");
+  }
 
-  log::info!(
-    "graceful exit after {:?} (reason: {exit:?})",
-    exit_start_time.elapsed()
-  );
+  let _ = state.warmed_up.set(started_at.elapsed());
+
+  probe_readiness(app).await?;
+  state.ready.store(true, Ordering::Release);
   Ok(())
 }
 
+// Goes through the router rather than a socket: until `ready` the listening
+// socket still belongs to the parent, so a TCP probe could hit either process.
+async fn probe_readiness(app: &Router) -> Result<(), String> {
+  let deadline = Instant::now() + READINESS_TIMEOUT;
+  loop {
+    let request = Request::get("/readyz")
+      .body(Body::empty())
+      .map_err(|err| format!("cannot build readiness probe: {err}"))?;
+    let response = unwrap_infallible(app.clone().oneshot(request).await);
+    let status = response.status();
+    if status.is_success() {
+      return Ok(());
+    }
+    if Instant::now() >= deadline {
+      let body = axum::body::to_bytes(response.into_body(), READINESS_BODY_LIMIT)
+        .await
+        .unwrap_or_default();
+      return Err(format!(
+        "readiness probe still returned {status} after {READINESS_TIMEOUT:?}: {}",
+        String::from_utf8_lossy(&body)
+      ));
+    }
+    tokio::time::sleep(READINESS_PROBE_INTERVAL).await;
+  }
+}
+
 fn build_app(state: AppState) -> Router {
   Router::new()
     .route("/", get(root))
     .route("/healthz", get(healthz))
+    .route("/readyz", get(readyz))
+    .route("/ws", get(ws))
     .route("/slow/{secs}", get(slow))
     .with_state(state)
 }
@@ -172,6 +316,9 @@ async fn root(
     reload_count: state.reload_count,
     uptime_secs: state.started_at.elapsed().as_secs(),
     remote_addr: remote_addr.to_string(),
+    ready: state.ready.load(Ordering::Acquire),
+    draining: state.drain.is_draining(),
+    upgrade_history: state.history.snapshot(),
   })
 }
 
@@ -179,6 +326,97 @@ async fn healthz() -> &'static str {
   "ok"
 }
 
+async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+  let draining = state.drain.is_draining();
+  let checks = readiness_checks(&state);
+  let ready = !draining && checks.iter().all(|check| check.ok);
+  let status = if ready {
+    StatusCode::OK
+  } else {
+    StatusCode::SERVICE_UNAVAILABLE
+  };
+  (
+    status,
+    Json(ReadinessResponse {
+      ready,
+      draining,
+      checks,
+    }),
+  )
+}
+
+fn readiness_checks(state: &AppState) -> Vec<ReadinessCheck> {
+  let warmup = match state.warmed_up.get() {
+    Some(elapsed) => ReadinessCheck {
+      name: "warmup",
+      ok: true,
+      detail: format!("finished in {elapsed:?}"),
+    },
+    None => ReadinessCheck {
+      name: "warmup",
+      ok: false,
+      detail: "still warming up".to_owned(),
+    },
+  };
+
+  // Without these the process can be neither upgraded nor stopped again
+  let missing: Vec<&str> = CONTROL_SOCKETS
+    .into_iter()
+    .filter(|path| {
+      !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+    })
+    .collect();
+  let control_sockets = ReadinessCheck {
+    name: "control_sockets",
+    ok: missing.is_empty(),
+    detail: if missing.is_empty() {
+      "all present".to_owned()
+    } else {
+      format!("missing {}", missing.join(", "))
+    },
+  };
+
+  vec![warmup, control_sockets]
+}
+
+async fn ws(State(state): State<AppState>, upgrade: WebSocketUpgrade) -> impl IntoResponse {
+  // Track the session before the upgrade completes so the HTTP connection and
+  // the WebSocket are never both untracked.
+  let session = state.drain.track_websocket();
+  upgrade.on_upgrade(move |socket| echo_websocket(socket, state, session))
+}
+
+async fn echo_websocket(mut socket: WebSocket, state: AppState, _session: InflightGuard) {
+  loop {
+    tokio::select! {
+      message = socket.recv() => match message {
+        Some(Ok(Message::Text(text))) => {
+          let reply = format!("[pid {}] {text}", state.pid);
+          if socket.send(Message::Text(reply.into())).await.is_err() {
+            break;
+          }
+        }
+        Some(Ok(Message::Binary(bytes))) => {
+          if socket.send(Message::Binary(bytes)).await.is_err() {
+            break;
+          }
+        }
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+        Some(Ok(_)) => {}
+      },
+      () = state.drain.started() => {
+        // 1012 tells clients to reconnect, which lands them on the new process
+        let close = CloseFrame {
+          code: close_code::RESTART,
+          reason: "server upgrading".into(),
+        };
+        let _ = socket.send(Message::Close(Some(close))).await;
+        break;
+      }
+    }
+  }
+}
+
 async fn slow(
   State(state): State<AppState>,
   axum::extract::Path(secs): axum::extract::Path<u64>,
@@ -194,12 +432,21 @@ async fn slow(
   })
 }
 
-async fn run_axum<S>(mut listener_stream: S, app: Router) -> io::Result<()>
+async fn run_axum<S>(
+  listener_stream: S,
+  app: Router,
+  drain: Drain,
+  drain_timeout: Duration,
+) -> io::Result<DrainReport>
 where
   S: Stream<Item = io::Result<TcpStream>> + Unpin,
 {
   let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
-  let connection_wait_group = waitgroup::WaitGroup::new();
+  let stopped = {
+    let drain = drain.clone();
+    async move { drain.started().await }
+  };
+  let mut listener_stream = pin!(listener_stream.take_until(stopped));
 
   while let Some(stream_result) = listener_stream.next().await {
     let stream = match stream_result {
@@ -219,7 +466,8 @@ where
     };
 
     let tower_service = unwrap_infallible(make_service.call(remote_addr).await);
-    let wait_worker = connection_wait_group.worker();
+    let connection_guard = drain.track_connection();
+    let drain = drain.clone();
 
     tokio::spawn(async move {
       let socket = TokioIo::new(stream);
@@ -227,19 +475,26 @@ where
         tower_service.clone().oneshot(request)
       });
 
-      if let Err(err) = server::conn::auto::Builder::new(TokioExecutor::new())
-        .serve_connection_with_upgrades(socket, hyper_service)
-        .await
-      {
+      let builder = server::conn::auto::Builder::new(TokioExecutor::new());
+      let mut connection = pin!(builder.serve_connection_with_upgrades(socket, hyper_service));
+      // Upgraded connections resolve here once hyper hands the socket over, so
+      // WebSockets are drained separately by their own handlers.
+      let result = tokio::select! {
+        result = connection.as_mut() => result,
+        () = drain.started() => {
+          connection.as_mut().graceful_shutdown();
+          connection.await
+        }
+      };
+      if let Err(err) = result {
         log::error!("connection error for {remote_addr}: {err:#}");
       }
 
-      drop(wait_worker);
+      drop(connection_guard);
     });
   }
 
-  connection_wait_group.wait().await;
-  Ok(())
+  Ok(drain.finish(drain_timeout).await)
 }
 
 fn parse_bind_addr() -> io::Result<SocketAddr> {
@@ -252,6 +507,22 @@ fn parse_bind_addr() -> io::Result<SocketAddr> {
   })
 }
 
+fn parse_duration_env(
+  key: &str,
+  default: Duration,
+  from_value: impl FnOnce(u64) -> Duration,
+) -> io::Result<Duration> {
+  match env::var(key) {
+    Err(_) => Ok(default),
+    Ok(value) => value.parse::<u64>().map(from_value).map_err(|err| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid {key} `{value}`: {err}"),
+      )
+    }),
+  }
+}
+
 fn update_reload_count() -> io::Result<u32> {
   let reload_count = match env::var("ECDYSIS_RELOADS") {
     Err(_) => 0,
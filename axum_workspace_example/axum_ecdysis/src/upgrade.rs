@@ -0,0 +1,435 @@
+use std::{
+  io,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use nix::{
+  errno::Errno,
+  sys::wait::{WaitPidFlag, WaitStatus, waitpid},
+  unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::UnixDatagram;
+
+use crate::drain::DrainReport;
+
+/// Name of the ecdysis datagram pair linking each process to its successor.
+pub const UPGRADE_CHANNEL: &str = "axum_ecdysis_upgrade";
+
+const HISTORY_LIMIT: usize = 20;
+const HISTORY_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpgradeOutcome {
+  WarmingUp,
+  Promoted,
+  RolledBack,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpgradeRecord {
+  pub from_pid: u32,
+  pub to_pid: u32,
+  pub reload_count: u32,
+  pub started_at_unix_ms: u64,
+  pub outcome: UpgradeOutcome,
+  pub warmup_ms: Option<u64>,
+  pub detail: Option<String>,
+  pub drain: Option<DrainReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum UpgradeMessage {
+  WarmupStarted { pid: u32, reload_count: u32 },
+  WarmupFailed { pid: u32, reason: String },
+  Ready { pid: u32, warmup_ms: u64 },
+  History { records: Vec<UpgradeRecord> },
+  Drained { to_pid: u32, report: DrainReport },
+}
+
+/// Upgrade history shared by every generation; each child inherits its
+/// parent's records when it starts warming up.
+#[derive(Clone, Default)]
+pub struct UpgradeHistory {
+  records: Arc<Mutex<Vec<UpgradeRecord>>>,
+}
+
+impl UpgradeHistory {
+  pub fn snapshot(&self) -> Vec<UpgradeRecord> {
+    self.records.lock().unwrap().clone()
+  }
+
+  fn replace(&self, records: Vec<UpgradeRecord>) {
+    *self.records.lock().unwrap() = records;
+  }
+
+  fn push(&self, record: UpgradeRecord) {
+    let mut records = self.records.lock().unwrap();
+    records.push(record);
+    let overflow = records.len().saturating_sub(HISTORY_LIMIT);
+    records.drain(.. overflow);
+  }
+
+  // Latest attempt towards `to_pid`; a pid is only ever upgraded to once.
+  fn update(&self, to_pid: u32, update: impl FnOnce(&mut UpgradeRecord)) {
+    let mut records = self.records.lock().unwrap();
+    if let Some(record) = records
+      .iter_mut()
+      .rev()
+      .find(|record| record.to_pid == to_pid)
+    {
+      update(record);
+    }
+  }
+
+  fn outcome(&self, to_pid: u32) -> Option<UpgradeOutcome> {
+    let records = self.records.lock().unwrap();
+    records
+      .iter()
+      .rev()
+      .find(|record| record.to_pid == to_pid)
+      .map(|record| record.outcome)
+  }
+
+  fn promoted_from(&self, from_pid: u32) -> Option<u32> {
+    let records = self.records.lock().unwrap();
+    records
+      .iter()
+      .rev()
+      .find(|record| record.from_pid == from_pid && record.outcome == UpgradeOutcome::Promoted)
+      .map(|record| record.to_pid)
+  }
+}
+
+/// Child side of the link: reports warmup progress to the process being replaced.
+pub struct ParentLink {
+  socket: Arc<UnixDatagram>,
+  history: UpgradeHistory,
+  pid: u32,
+}
+
+impl ParentLink {
+  pub async fn connect(
+    socket: UnixDatagram,
+    history: UpgradeHistory,
+    pid: u32,
+    reload_count: u32,
+  ) -> Self {
+    let link = Self {
+      socket: Arc::new(socket),
+      history,
+      pid,
+    };
+
+    link
+      .send(&UpgradeMessage::WarmupStarted { pid, reload_count })
+      .await;
+    match tokio::time::timeout(HISTORY_REPLY_TIMEOUT, recv(&link.socket)).await {
+      Ok(Some(UpgradeMessage::History { records })) => link.history.replace(records),
+      other => {
+        log::warn!("parent did not send upgrade history ({other:?}), starting a new one");
+        link.history.push(UpgradeRecord {
+          from_pid: 0,
+          to_pid: pid,
+          reload_count,
+          started_at_unix_ms: unix_millis(),
+          outcome: UpgradeOutcome::WarmingUp,
+          warmup_ms: None,
+          detail: None,
+          drain: None,
+        });
+      }
+    }
+
+    tokio::spawn(listen_to_parent(
+      link.socket.clone(),
+      link.history.clone(),
+      pid,
+    ));
+    link
+  }
+
+  pub async fn warmup_failed(&self, reason: &str) {
+    self.history.update(self.pid, |record| {
+      record.outcome = UpgradeOutcome::RolledBack;
+      record.detail = Some(reason.to_owned());
+    });
+    self
+      .send(&UpgradeMessage::WarmupFailed {
+        pid: self.pid,
+        reason: reason.to_owned(),
+      })
+      .await;
+  }
+
+  pub async fn ready(&self, warmup: Duration) {
+    let warmup_ms = warmup.as_millis() as u64;
+    self.history.update(self.pid, |record| {
+      record.outcome = UpgradeOutcome::Promoted;
+      record.warmup_ms = Some(warmup_ms);
+    });
+    self
+      .send(&UpgradeMessage::Ready {
+        pid: self.pid,
+        warmup_ms,
+      })
+      .await;
+  }
+
+  async fn send(&self, message: &UpgradeMessage) {
+    if let Err(err) = send(&self.socket, message).await {
+      log::warn!("failed to report {message:?} to parent: {err}");
+    }
+  }
+}
+
+async fn listen_to_parent(socket: Arc<UnixDatagram>, history: UpgradeHistory, pid: u32) {
+  while let Some(message) = recv(&socket).await {
+    match message {
+      UpgradeMessage::History { records } => history.replace(records),
+      UpgradeMessage::Drained { to_pid, report } if to_pid == pid => {
+        log::info!("parent finished draining: {report:?}");
+        history.update(pid, |record| record.drain = Some(report));
+      }
+      other => log::debug!("ignoring upgrade message from parent: {other:?}"),
+    }
+  }
+}
+
+/// Parent side of the link: tracks children warming up and rolls the history
+/// back when one dies before becoming ready.
+#[derive(Clone)]
+pub struct ChildLink {
+  socket: Arc<UnixDatagram>,
+  history: UpgradeHistory,
+  pid: u32,
+}
+
+impl ChildLink {
+  pub fn new(socket: UnixDatagram, history: UpgradeHistory, pid: u32) -> Self {
+    Self {
+      socket: Arc::new(socket),
+      history,
+      pid,
+    }
+  }
+
+  pub async fn watch(self) {
+    while let Some(message) = recv(&self.socket).await {
+      match message {
+        UpgradeMessage::WarmupStarted { pid, reload_count } => {
+          log::info!("child {pid} started warming up (reload count: {reload_count})");
+          self.history.push(UpgradeRecord {
+            from_pid: self.pid,
+            to_pid: pid,
+            reload_count,
+            started_at_unix_ms: unix_millis(),
+            outcome: UpgradeOutcome::WarmingUp,
+            warmup_ms: None,
+            detail: None,
+            drain: None,
+          });
+          let records = self.history.snapshot();
+          if let Err(err) = send(&self.socket, &UpgradeMessage::History { records }).await {
+            log::warn!("failed to send upgrade history to child {pid}: {err}");
+          }
+          tokio::spawn(self.clone().supervise_warmup(pid));
+        }
+        UpgradeMessage::WarmupFailed { pid, reason } => {
+          log::warn!("child {pid} failed warmup, rolling back: {reason}");
+          self.roll_back(pid, reason);
+        }
+        UpgradeMessage::Ready { pid, warmup_ms } => {
+          log::info!("child {pid} passed readiness after {warmup_ms}ms");
+          self.history.update(pid, |record| {
+            record.outcome = UpgradeOutcome::Promoted;
+            record.warmup_ms = Some(warmup_ms);
+          });
+        }
+        other => log::debug!("ignoring upgrade message from child: {other:?}"),
+      }
+    }
+  }
+
+  /// Forwards the drain outcome to the child that took over, if any.
+  pub async fn report_drain(&self, report: DrainReport) {
+    let Some(to_pid) = self.history.promoted_from(self.pid) else {
+      return;
+    };
+    self
+      .history
+      .update(to_pid, |record| record.drain = Some(report.clone()));
+    if let Err(err) = send(&self.socket, &UpgradeMessage::Drained { to_pid, report }).await {
+      log::warn!("failed to report drain to child {to_pid}: {err}");
+    }
+  }
+
+  // ecdysis already keeps serving from this process when the child dies before
+  // `ready`; this only records it, since a crash leaves no chance to report.
+  async fn supervise_warmup(self, pid: u32) {
+    let started_at = Instant::now();
+    while self.history.outcome(pid) == Some(UpgradeOutcome::WarmingUp) {
+      if let Some(exit) = child_exit(pid) {
+        let reason = format!(
+          "child {exit} during warmup after {}ms",
+          started_at.elapsed().as_millis()
+        );
+        log::warn!("child {pid} {reason}, still serving from {}", self.pid);
+        self.roll_back(pid, reason);
+        return;
+      }
+      tokio::time::sleep(CHILD_POLL_INTERVAL).await;
+    }
+  }
+
+  fn roll_back(&self, pid: u32, reason: String) {
+    self.history.update(pid, |record| {
+      if record.outcome == UpgradeOutcome::WarmingUp {
+        record.outcome = UpgradeOutcome::RolledBack;
+        record.detail = Some(reason);
+      }
+    });
+  }
+}
+
+// Signal 0 still succeeds for a child that died but was not reaped yet, so ask
+// `waitpid` instead. ecdysis polls the same child and may reap it first, which
+// shows up here as `ECHILD`.
+fn child_exit(pid: u32) -> Option<String> {
+  match waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG)) {
+    Ok(WaitStatus::Exited(_, code)) => Some(format!("exited with status {code}")),
+    Ok(WaitStatus::Signaled(_, signal, _)) => Some(format!("was killed by {signal}")),
+    Ok(_) => None,
+    Err(Errno::ECHILD) => Some("exited".to_owned()),
+    Err(err) => {
+      log::warn!("cannot check on child {pid}: {err}");
+      None
+    }
+  }
+}
+
+async fn send(socket: &UnixDatagram, message: &UpgradeMessage) -> io::Result<()> {
+  let payload = serde_json::to_vec(message).map_err(io::Error::other)?;
+  socket.send(&payload).await.map(|_| ())
+}
+
+// `None` once the other side is gone; malformed datagrams are skipped.
+async fn recv(socket: &UnixDatagram) -> Option<UpgradeMessage> {
+  let mut buf = vec![0; MAX_MESSAGE_SIZE];
+  loop {
+    let len = match socket.recv(&mut buf).await {
+      Ok(0) => return None,
+      Ok(len) => len,
+      Err(err) => {
+        log::warn!("upgrade channel closed: {err}");
+        return None;
+      }
+    };
+    match serde_json::from_slice(&buf[.. len]) {
+      Ok(message) => return Some(message),
+      Err(err) => log::warn!("discarding malformed upgrade message: {err}"),
+    }
+  }
+}
+
+fn unix_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::process::{Child, Command};
+
+  use super::*;
+
+  const PARENT_PID: u32 = 1;
+
+  fn spawn_child(script: &str) -> Child {
+    Command::new("sh").args(["-c", script]).spawn().unwrap()
+  }
+
+  fn record(history: &UpgradeHistory, to_pid: u32) -> Option<UpgradeRecord> {
+    history
+      .snapshot()
+      .into_iter()
+      .rev()
+      .find(|record| record.to_pid == to_pid)
+  }
+
+  async fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+      assert!(Instant::now() < deadline, "timed out waiting for {what}");
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  }
+
+  #[tokio::test]
+  async fn child_crashing_during_warmup_is_rolled_back() {
+    let (to_child, to_parent) = UnixDatagram::pair().unwrap();
+    let history = UpgradeHistory::default();
+    tokio::spawn(ChildLink::new(to_child, history.clone(), PARENT_PID).watch());
+
+    // Left unreaped on purpose: a zombie must still count as exited
+    let mut child = spawn_child("exit 3");
+    let pid = child.id();
+    let _link = ParentLink::connect(to_parent, UpgradeHistory::default(), pid, 1).await;
+
+    wait_for("rollback", || {
+      history.outcome(pid) == Some(UpgradeOutcome::RolledBack)
+    })
+    .await;
+    let detail = record(&history, pid).unwrap().detail.unwrap();
+    assert!(detail.contains("exited with status 3"), "{detail}");
+    // `supervise_warmup` reaped it already
+    assert!(child.wait().is_err());
+  }
+
+  #[tokio::test]
+  async fn promoted_child_receives_the_drain_report() {
+    let (to_child, to_parent) = UnixDatagram::pair().unwrap();
+    let parent_history = UpgradeHistory::default();
+    let children = ChildLink::new(to_child, parent_history.clone(), PARENT_PID);
+    tokio::spawn(children.clone().watch());
+
+    let mut child = spawn_child("sleep 30");
+    let pid = child.id();
+    let child_history = UpgradeHistory::default();
+    let link = ParentLink::connect(to_parent, child_history.clone(), pid, 1).await;
+    link.ready(Duration::from_millis(7)).await;
+    wait_for("promotion", || {
+      parent_history.outcome(pid) == Some(UpgradeOutcome::Promoted)
+    })
+    .await;
+
+    let report = DrainReport {
+      connections: 2,
+      websockets: 1,
+      forced: 1,
+      timed_out: true,
+      duration_ms: 30_000,
+    };
+    children.report_drain(report).await;
+    wait_for("drain report", || {
+      record(&child_history, pid).is_some_and(|record| record.drain.is_some())
+    })
+    .await;
+
+    let record = record(&child_history, pid).unwrap();
+    assert_eq!(record.from_pid, PARENT_PID);
+    assert_eq!(record.outcome, UpgradeOutcome::Promoted);
+    assert_eq!(record.warmup_ms, Some(7));
+    assert_eq!(record.drain.unwrap().forced, 1);
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+  }
+}
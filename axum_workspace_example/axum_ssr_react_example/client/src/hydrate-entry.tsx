@@ -0,0 +1,19 @@
+import { StrictMode } from "react";
+import { hydrateRoot } from "react-dom/client";
+import { PAYLOAD_ELEMENT_ID, PagePayload, ROOT_ELEMENT_ID, SsrRoot } from "./ssr-pages";
+
+// Props were serialized by the server stream entry next to the rendered markup
+const payloadElement = document.getElementById(PAYLOAD_ELEMENT_ID);
+const root = document.getElementById(ROOT_ELEMENT_ID);
+
+if (payloadElement && root) {
+  const payload: PagePayload = JSON.parse(payloadElement.textContent ?? "{}");
+  hydrateRoot(
+    root,
+    <StrictMode>
+      <SsrRoot {...payload} />
+    </StrictMode>,
+  );
+} else {
+  console.warn("SSR payload or root element missing, skipping hydration");
+}
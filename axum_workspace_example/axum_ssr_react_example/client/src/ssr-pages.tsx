@@ -0,0 +1,223 @@
+import { useState } from "react";
+
+// Props shapes mirror the serde structs in src/main.rs (WeatherData, UserProfile)
+export interface WeatherProps {
+  city: string;
+  temperature: number;
+  humidity: number;
+  conditions: string;
+  wind: { speed: number; direction: string };
+  forecast: { day: string; high: number; low: number; condition: string }[];
+  timestamp: string;
+}
+
+export interface ProfileProps {
+  id: number;
+  username: string;
+  email: string;
+  profile: {
+    firstName: string;
+    lastName: string;
+    avatar: string;
+    bio: string;
+    location: string;
+    joinDate: string;
+  };
+  preferences: { theme: string; language: string; notifications: boolean };
+  stats: {
+    projectsCreated: number;
+    linesOfCode: number;
+    contributionsThisYear: number;
+  };
+}
+
+export interface HomeProps {
+  renderedAt: string;
+  path: string;
+  userAgent: string;
+}
+
+// Serialized alongside the markup so the client can hydrate with identical props
+export interface PagePayload {
+  page: string;
+  props: unknown;
+}
+
+const NAV_LINKS: [string, string][] = [
+  ["/", "Home"],
+  ["/weather", "Weather"],
+  ["/profile", "Profile"],
+  ["/dashboard", "Dashboard"],
+  ["/v8", "V8 Demo"],
+  ["/stream-chat", "Stream Chat"],
+];
+
+function Navigation() {
+  return (
+    <nav style={{ padding: 10, background: "#f0f0f0", marginBottom: 20, fontSize: 14 }}>
+      {NAV_LINKS.map(([href, label]) => (
+        <a key={href} href={href} style={{ margin: "0 8px" }}>
+          {label}
+        </a>
+      ))}
+    </nav>
+  );
+}
+
+function HomePage({ renderedAt, path, userAgent }: HomeProps) {
+  const [count, setCount] = useState(0);
+
+  return (
+    <section>
+      <h1>React SSR streamed from Rust</h1>
+      <p>
+        Rendered by <code>renderToReadableStream</code> inside V8 at {renderedAt} for{" "}
+        <code>{path}</code>.
+      </p>
+      <p>User agent: {userAgent || "unknown"}</p>
+      <button onClick={() => setCount((value) => value + 1)}>
+        hydrated clicks: {count}
+      </button>
+    </section>
+  );
+}
+
+function WeatherPage(weather: WeatherProps) {
+  const [celsius, setCelsius] = useState(false);
+  const format = (fahrenheit: number) =>
+    celsius ? `${Math.round(((fahrenheit - 32) * 5) / 9)}°C` : `${fahrenheit}°F`;
+
+  return (
+    <section
+      style={{
+        background: "linear-gradient(135deg, #74b9ff, #0984e3)",
+        color: "white",
+        padding: 20,
+        borderRadius: 10,
+      }}
+    >
+      <h2>🌤️ Weather in {weather.city}</h2>
+      <div style={{ fontSize: "3em", fontWeight: "bold" }}>{format(weather.temperature)}</div>
+      <div style={{ fontSize: "1.2em", margin: "10px 0" }}>{weather.conditions}</div>
+      <p>
+        <strong>Humidity:</strong> {weather.humidity}%
+      </p>
+      <p>
+        <strong>Wind:</strong> {weather.wind.speed} mph {weather.wind.direction}
+      </p>
+      <button onClick={() => setCelsius((value) => !value)}>
+        Show {celsius ? "Fahrenheit" : "Celsius"}
+      </button>
+      <h3>3-Day Forecast</h3>
+      <div style={{ display: "flex", gap: 15 }}>
+        {weather.forecast.map((day) => (
+          <div
+            key={day.day}
+            style={{ background: "rgba(255,255,255,0.2)", padding: 10, borderRadius: 5 }}
+          >
+            <div>{day.day}</div>
+            <div>
+              {format(day.high)}/{format(day.low)}
+            </div>
+            <div>{day.condition}</div>
+          </div>
+        ))}
+      </div>
+      <div style={{ fontSize: "0.9em", opacity: 0.8, marginTop: 15 }}>
+        Updated: {weather.timestamp}
+      </div>
+    </section>
+  );
+}
+
+function ProfilePage(user: ProfileProps) {
+  const [showDetails, setShowDetails] = useState(true);
+
+  return (
+    <section style={{ background: "#fff", border: "1px solid #ddd", borderRadius: 10, padding: 20 }}>
+      <div style={{ display: "flex", alignItems: "center", marginBottom: 20 }}>
+        <img
+          src={user.profile.avatar}
+          alt="Avatar"
+          style={{ width: 80, height: 80, borderRadius: "50%", marginRight: 20 }}
+        />
+        <div>
+          <h2>
+            {user.profile.firstName} {user.profile.lastName}
+          </h2>
+          <p style={{ color: "#666" }}>@{user.username}</p>
+          <p>{user.email}</p>
+        </div>
+      </div>
+      <button onClick={() => setShowDetails((value) => !value)}>
+        {showDetails ? "Hide" : "Show"} details
+      </button>
+      {showDetails && (
+        <div>
+          <p style={{ fontStyle: "italic" }}>{user.profile.bio}</p>
+          <p>
+            <strong>📍 Location:</strong> {user.profile.location}
+          </p>
+          <p>
+            <strong>📅 Joined:</strong> {user.profile.joinDate}
+          </p>
+          <p>
+            <strong>Preferences:</strong> {user.preferences.theme} theme,{" "}
+            {user.preferences.language}, notifications{" "}
+            {user.preferences.notifications ? "enabled" : "disabled"}
+          </p>
+        </div>
+      )}
+      <div style={{ display: "flex", justifyContent: "space-around", margin: "20px 0" }}>
+        <Stat value={user.stats.projectsCreated} label="Projects" />
+        <Stat value={user.stats.linesOfCode} label="Lines of Code" />
+        <Stat value={user.stats.contributionsThisYear} label="Contributions" />
+      </div>
+    </section>
+  );
+}
+
+function Stat({ value, label }: { value: number; label: string }) {
+  return (
+    <div style={{ textAlign: "center" }}>
+      <strong style={{ display: "block", fontSize: "1.5em", color: "#007acc" }}>{value}</strong>
+      <span>{label}</span>
+    </div>
+  );
+}
+
+function NotFoundPage({ page }: { page: string }) {
+  return <p>Unknown page: {page}</p>;
+}
+
+// Shared by the server stream entry and the hydration entry, so both sides
+// produce the same tree from the same payload
+export function SsrRoot({ page, props }: PagePayload) {
+  let content;
+  switch (page) {
+    case "home":
+      content = <HomePage {...(props as HomeProps)} />;
+      break;
+    case "weather":
+      content = <WeatherPage {...(props as WeatherProps)} />;
+      break;
+    case "profile":
+      content = <ProfilePage {...(props as ProfileProps)} />;
+      break;
+    default:
+      content = <NotFoundPage page={page} />;
+  }
+
+  return (
+    <>
+      <Navigation />
+      {content}
+      <footer style={{ marginTop: 20, padding: 10, background: "#f9f9f9", textAlign: "center" }}>
+        <small>Streamed by React in V8 • hydrated in the browser</small>
+      </footer>
+    </>
+  );
+}
+
+export const PAYLOAD_ELEMENT_ID = "__SSR_PROPS__";
+export const ROOT_ELEMENT_ID = "root";
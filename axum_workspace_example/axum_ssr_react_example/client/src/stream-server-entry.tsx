@@ -0,0 +1,76 @@
+// Streaming SSR entry executed by src/react_ssr.rs inside a bare V8 isolate.
+// V8 ships no web streams or text codecs, so polyfill them before React loads.
+import "web-streams-polyfill/polyfill";
+import "fast-text-encoding";
+import { renderToReadableStream } from "react-dom/server.browser";
+import { PAYLOAD_ELEMENT_ID, PagePayload, ROOT_ELEMENT_ID, SsrRoot } from "./ssr-pages";
+
+// Mirrors `RenderRequest` in src/react_ssr.rs
+interface RenderRequest extends PagePayload {
+  title: string;
+  path: string;
+  bootstrapModules: string[];
+}
+
+// JSON inside <script> must not be able to close the tag or break JS parsing
+const serializePayload = (payload: PagePayload) =>
+  JSON.stringify(payload)
+    .replace(/</g, "\\u003c")
+    .replace(/\u2028/g, "\\u2028")
+    .replace(/\u2029/g, "\\u2029");
+
+function Document({ request }: { request: RenderRequest }) {
+  const payload: PagePayload = { page: request.page, props: request.props };
+
+  return (
+    <html lang="en">
+      <head>
+        <meta charSet="UTF-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+        <title>{request.title}</title>
+      </head>
+      <body style={{ fontFamily: "Arial, sans-serif", margin: 20 }}>
+        <div id={ROOT_ELEMENT_ID}>
+          <SsrRoot {...payload} />
+        </div>
+        <script
+          id={PAYLOAD_ELEMENT_ID}
+          type="application/json"
+          dangerouslySetInnerHTML={{ __html: serializePayload(payload) }}
+        />
+      </body>
+    </html>
+  );
+}
+
+// `emit` is the Rust host callback; it returns false once the HTTP client has gone away
+export async function renderPage(
+  requestJson: string,
+  emit: (chunk: string) => boolean,
+): Promise<void> {
+  const request: RenderRequest = JSON.parse(requestJson);
+  const stream = await renderToReadableStream(<Document request={request} />, {
+    bootstrapModules: request.bootstrapModules,
+    onError(error) {
+      console.error("[react-ssr] render error:", error);
+    },
+  });
+
+  const reader = stream.getReader();
+  const decoder = new TextDecoder();
+  for (;;) {
+    const { done, value } = await reader.read();
+    if (done) {
+      break;
+    }
+    if (!emit(decoder.decode(value, { stream: true }))) {
+      await reader.cancel("client disconnected");
+      return;
+    }
+  }
+
+  const tail = decoder.decode();
+  if (tail) {
+    emit(tail);
+  }
+}
@@ -7,9 +7,13 @@ export default defineConfig({
   build: {
     outDir: "dist/client",
     rollupOptions: {
-      input: "./src/main.tsx",
+      input: {
+        index: "./src/main.tsx",
+        // Hydrates pages streamed by the Rust server (served as /client/hydrate.js)
+        hydrate: "./src/hydrate-entry.tsx",
+      },
       output: {
-        entryFileNames: "index.js",
+        entryFileNames: "[name].js",
         assetFileNames: "assets/[name][extname]",
       },
     },
@@ -0,0 +1,24 @@
+import { defineConfig } from "vite";
+import react from "@vitejs/plugin-react-swc";
+
+// Streaming SSR bundle loaded by src/react_ssr.rs; exposes `ReactStreamSSR.renderPage`
+export default defineConfig({
+  build: {
+    ssr: true,
+    outDir: "dist/stream",
+    emptyOutDir: true,
+    rollupOptions: {
+      input: "./src/stream-server-entry.tsx",
+      output: {
+        format: "iife",
+        entryFileNames: "index.js",
+        name: "ReactStreamSSR",
+      },
+    },
+  },
+  ssr: {
+    target: "webworker",
+    noExternal: true,
+  },
+  plugins: [react()],
+});
@@ -1,4 +1,4 @@
-use std::env;
+use std::{env, time::Duration};
 
 use once_cell::sync::Lazy;
 
@@ -30,3 +30,34 @@ impl StreamChatConfig {
 }
 
 pub static STREAM_CONFIG: Lazy<StreamChatConfig> = Lazy::new(StreamChatConfig::from_env);
+
+// Limits for the shared V8 isolate pool (see v8_pool.rs)
+#[derive(Debug, Clone)]
+pub struct V8PoolConfig {
+  pub workers: usize,
+  pub render_timeout: Duration,
+  pub max_heap_bytes: usize,
+  pub max_renders_per_isolate: u64,
+}
+
+impl V8PoolConfig {
+  pub fn from_env() -> Self {
+    dotenvy::dotenv().ok();
+
+    fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> T {
+      env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+    }
+
+    Self {
+      workers: parse_env("V8_POOL_WORKERS", 2).max(1),
+      render_timeout: Duration::from_millis(parse_env("V8_RENDER_TIMEOUT_MS", 2_000)),
+      max_heap_bytes: parse_env::<usize>("V8_MAX_HEAP_MB", 128) * 1024 * 1024,
+      max_renders_per_isolate: parse_env("V8_MAX_RENDERS_PER_ISOLATE", 500).max(1),
+    }
+  }
+}
+
+pub static V8_POOL_CONFIG: Lazy<V8PoolConfig> = Lazy::new(V8PoolConfig::from_env);
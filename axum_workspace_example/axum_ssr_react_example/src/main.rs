@@ -1,66 +1,27 @@
-use std::{cell::RefCell, fs::read_to_string, time::Instant};
-
-use axum::{Router, extract::Query, response::Html, routing::get};
+use std::time::Instant;
+
+use axum::{
+  Router,
+  extract::{OriginalUri, Query},
+  http::{HeaderMap, StatusCode, Uri},
+  response::{Html, IntoResponse, Json, Response},
+  routing::get,
+};
 use chrono::Datelike;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use ssr_rs::Ssr;
+use tower_http::services::ServeDir;
+use v8_processor::{HttpRequest, StringHttpRequest, V8TypeScriptProcessor};
 
 mod config;
+mod react_ssr;
 mod real_v8_executor;
-// mod simple_v8_executor; // Replaced with real_v8_executor
+// mod simple_v8_executor; // Replaced with real_v8_executor, never runs V8 (see the file)
+mod v8_pool;
 mod v8_processor;
 mod v8_stream_executor;
 
-thread_local! {
-    static SSR: RefCell<Ssr<'static, 'static>> = RefCell::new({
-        let js_code = match read_to_string("client/dist/ssr/index.js") {
-            Ok(code) => code,
-            Err(e) => {
-                eprintln!("Failed to read SSR file: {}", e);
-                eprintln!("Current directory: {:?}", std::env::current_dir());
-                panic!("Cannot initialize SSR without the JS bundle");
-            }
-        };
-        let polyfill = r#"
-// Polyfills for V8 environment
-if (typeof MessageChannel === 'undefined') {
-    globalThis.MessageChannel = function() {
-        const channel = {};
-        channel.port1 = { postMessage: function() {}, onmessage: null };
-        channel.port2 = { postMessage: function() {}, onmessage: null };
-        return channel;
-    };
-}
-
-// Mock fetch for demonstration (in real scenarios, you'd use a proper fetch polyfill)
-if (typeof fetch === 'undefined') {
-    globalThis.fetch = async function(url) {
-        return {
-            ok: true,
-            status: 200,
-            json: async () => ({
-                url: url,
-                method: 'GET',
-                timestamp: new Date().toISOString(),
-                message: 'Mock response from V8 environment',
-                data: { users: [{ id: 1, name: 'John' }, { id: 2, name: 'Jane' }] }
-            })
-        };
-    };
-}
-"#;
-        let enhanced_js = format!("{}\n{}", polyfill, js_code);
-        match Ssr::from(enhanced_js, "SSR") {
-            Ok(ssr) => ssr,
-            Err(e) => {
-                eprintln!("Failed to initialize SSR: {}", e);
-                panic!("Cannot create SSR instance");
-            }
-        }
-    })
-}
-
 #[derive(Deserialize)]
 struct QueryParams {
   demo: Option<String>,
@@ -159,6 +120,7 @@ async fn main() {
     .route("/business", get(business_demo))
     .route("/dashboard", get(dashboard_demo))
     .route("/v8", get(v8_demo_safe))
+    .route("/v8/status", get(v8_status))
     .route("/v8/typescript", get(v8_typescript_demo))
     .route("/v8/jsonplaceholder", get(v8_jsonplaceholder_demo))
     .route("/stream-chat", get(stream_chat_demo))
@@ -166,7 +128,9 @@ async fn main() {
     .route("/stream-chat/user-context", get(stream_chat_user_context))
     .route("/stream-chat/analytics", get(stream_chat_analytics))
     .route("/stream-chat/setup", get(stream_chat_setup))
-    .route("/stream-chat/token", get(stream_chat_token_demo));
+    .route("/stream-chat/token", get(stream_chat_token_demo))
+    // Hydration bundle for the streamed React pages
+    .nest_service("/client", ServeDir::new("client/dist/client"));
 
   // run our app with hyper, listening globally on port 3000
   let listener = match tokio::net::TcpListener::bind("0.0.0.0:8080").await {
@@ -182,13 +146,18 @@ async fn main() {
   }
 }
 
-async fn root() -> Html<String> {
-  render_page("Index", None, "Basic SSR Demo")
+async fn root(OriginalUri(uri): OriginalUri, headers: HeaderMap) -> Response {
+  let props = serde_json::json!({
+    "renderedAt": chrono::Utc::now().to_rfc3339(),
+    "path": uri.path(),
+    "userAgent": StringHttpRequest::from_parts(&uri, &headers).user_agent(),
+  });
+  stream_react_page(&uri, &headers, "home", "React Streaming SSR Demo", props).await
 }
 
 // Test route to verify ssr_rs function calling
 async fn test_route() -> Html<String> {
-  let result = SSR.with(|ssr| ssr.borrow_mut().render_to_string(Some("test")));
+  let result = react_ssr::call_ssr_function("test").await;
 
   match result {
     Ok(test_result) => Html(format!(
@@ -207,10 +176,7 @@ async fn calc_demo(Query(_params): Query<QueryParams>) -> Html<String> {
   let start = Instant::now();
 
   // Call TypeScript calculate function from Rust
-  let result = SSR.with(|ssr| {
-    let mut ssr_instance = ssr.borrow_mut();
-    ssr_instance.render_to_string(Some("calculate"))
-  });
+  let result = react_ssr::call_ssr_function("calculate").await;
 
   match result {
     Ok(calc_result) => {
@@ -312,10 +278,7 @@ async fn fetch_demo(Query(params): Query<QueryParams>) -> Html<String> {
   };
 
   // Call the appropriate TypeScript function from Rust
-  let result = SSR.with(|ssr| {
-    let mut ssr_instance = ssr.borrow_mut();
-    ssr_instance.render_to_string(Some(ts_function))
-  });
+  let result = react_ssr::call_ssr_function(ts_function).await;
 
   match result {
     Ok(fetch_result) => {
@@ -1005,84 +968,33 @@ async fn time_demo() -> Html<String> {
   render_custom_html(&time_html, "Time Synchronization Demo")
 }
 
-// Helper function to render pages with consistent structure
-fn render_page(function_name: &str, data: Option<String>, title: &str) -> Html<String> {
-  let start = Instant::now();
-
-  let result = SSR.with(|ssr| {
-    let mut ssr_instance = ssr.borrow_mut();
-    match (function_name, &data) {
-      ("renderWithData", Some(_data_str)) => {
-        // First set the data, then render
-        let _set_result = ssr_instance.render_to_string(Some("setRustData"));
-        // For now, render without data since we need a different approach
-        ssr_instance.render_to_string(Some("renderWithData"))
-      }
-      _ => {
-        // For simple functions
-        ssr_instance.render_to_string(Some(function_name))
-      }
-    }
-  });
-
-  println!("SSR elapsed: {:?}", start.elapsed());
+// DEMONSTRATION: JS → Rust → HTML Processing Routes
 
-  match result {
-    Ok(html) => {
-      let navigation = r#"
-            <nav style="padding: 10px; background: #f0f0f0; margin-bottom: 20px; font-size: 14px;">
-                <a href="/" style="margin-right: 8px;">Home</a> |
-                <a href="/calc" style="margin: 0 8px;">Calculator</a> |
-                <a href="/fetch" style="margin: 0 8px;">Fetch</a> |
-                <a href="/data" style="margin: 0 8px;">Data</a> |
-                <a href="/time" style="margin: 0 8px;">Time</a> |
-                <a href="/weather" style="margin: 0 8px;">Weather</a> |
-                <a href="/profile" style="margin: 0 8px;">Profile</a> |
-                <a href="/system" style="margin: 0 8px;">System</a> |
-                <a href="/business" style="margin: 0 8px;">Business</a> |
-                <a href="/dashboard" style="margin: 0 8px;">Dashboard</a> |
-                <a href="/v8" style="margin: 0 8px;">V8 Demo</a> |
-                <a href="/v8/typescript" style="margin: 0 8px;">V8 TypeScript</a> |
-                <a href="/v8/jsonplaceholder" style="margin: 0 8px;">JSONPlaceholder</a> |
-                <a href="/stream-chat" style="margin-left: 8px;">Stream Chat</a>
-            </nav>
-            "#;
-
-      let full_html = format!(
-        r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>{}</title>
-</head>
-<body>
-    {}
-    <div id="root">{}</div>
-    <footer style="margin-top: 20px; padding: 10px; background: #f9f9f9; text-align: center;">
-        <small>Powered by ssr_rs • Rust ↔ TypeScript Integration Demo</small>
-    </footer>
-</body>
-</html>"#,
-        title, navigation, html
-      );
-      Html(full_html)
-    }
-    Err(e) => {
-      eprintln!("SSR Error: {}", e);
+// Helper to stream a React page rendered in V8, with props embedded for client hydration
+async fn stream_react_page(
+  uri: &Uri,
+  headers: &HeaderMap,
+  page: &str,
+  title: &str,
+  props: Value,
+) -> Response {
+  let request = StringHttpRequest::from_parts(uri, headers);
+  match V8TypeScriptProcessor::process_http_request(&request, page, title, props) {
+    Ok(stream) => stream.into_response().await,
+    Err(e) => (
+      StatusCode::SERVICE_UNAVAILABLE,
       Html(format!(
         "<html><body><h1>SSR Error</h1><p>Failed to render: {}</p></body></html>",
         e
-      ))
-    }
+      )),
+    )
+      .into_response(),
   }
 }
 
-// DEMONSTRATION: JS → Rust → HTML Processing Routes
-
-// Weather Demo: Rust generates data → Processes → HTML generation
-async fn weather_demo() -> Html<String> {
-  let title = "Weather Dashboard (Rust Data → HTML)";
+// Weather Demo: Rust generates data → React renders and streams it → browser hydrates
+async fn weather_demo(OriginalUri(uri): OriginalUri, headers: HeaderMap) -> Response {
+  let title = "Weather Dashboard (Rust Data → React SSR)";
 
   // Since ssr_rs doesn't support calling custom functions reliably,
   // let's generate the data in Rust and demonstrate the HTML generation
@@ -1119,13 +1031,13 @@ async fn weather_demo() -> Html<String> {
   };
 
   println!("Generated weather data: {:?}", weather);
-  let weather_html = generate_weather_html(&weather);
-  render_custom_html(&weather_html, title)
+  let props = serde_json::to_value(&weather).unwrap_or_default();
+  stream_react_page(&uri, &headers, "weather", title, props).await
 }
 
-// Profile Demo: Rust → HTML
-async fn profile_demo() -> Html<String> {
-  let title = "User Profile (Rust Data → HTML)";
+// Profile Demo: Rust → React SSR
+async fn profile_demo(OriginalUri(uri): OriginalUri, headers: HeaderMap) -> Response {
+  let title = "User Profile (Rust Data → React SSR)";
 
   // Generate profile data in Rust
   let profile = UserProfile {
@@ -1153,8 +1065,8 @@ async fn profile_demo() -> Html<String> {
   };
 
   println!("Generated profile data: {:?}", profile);
-  let profile_html = generate_profile_html(&profile);
-  render_custom_html(&profile_html, title)
+  let props = serde_json::to_value(&profile).unwrap_or_default();
+  stream_react_page(&uri, &headers, "profile", title, props).await
 }
 
 // System Demo: Rust → HTML
@@ -1370,122 +1282,6 @@ async fn dashboard_demo() -> Html<String> {
 
 // HTML Generation Functions
 
-fn generate_weather_html(weather: &WeatherData) -> String {
-  format!(
-    r#"
-    <div class="weather-card">
-        <h2>🌤️ Weather in {city}</h2>
-        <div class="current">
-            <div class="temp">{temperature}°F</div>
-            <div class="condition">{conditions}</div>
-            <div class="details">
-                <p><strong>Humidity:</strong> {humidity}%</p>
-                <p><strong>Wind:</strong> {wind_speed} mph {wind_direction}</p>
-            </div>
-        </div>
-        <div class="forecast">
-            <h3>3-Day Forecast</h3>
-            {forecast_html}
-        </div>
-        <div class="timestamp">Updated: {timestamp}</div>
-    </div>
-    <style>
-        .weather-card {{ background: linear-gradient(135deg, #74b9ff, #0984e3); color: white; padding: 20px; border-radius: 10px; }}
-        .temp {{ font-size: 3em; font-weight: bold; }}
-        .condition {{ font-size: 1.2em; margin: 10px 0; }}
-        .forecast {{ display: flex; gap: 15px; margin: 15px 0; }}
-        .forecast-day {{ background: rgba(255,255,255,0.2); padding: 10px; border-radius: 5px; text-align: center; }}
-        .timestamp {{ font-size: 0.9em; opacity: 0.8; margin-top: 15px; }}
-    </style>
-    "#,
-    city = weather.city,
-    temperature = weather.temperature,
-    conditions = weather.conditions,
-    humidity = weather.humidity,
-    wind_speed = weather.wind.speed,
-    wind_direction = weather.wind.direction,
-    forecast_html = weather
-      .forecast
-      .iter()
-      .map(|day| format!(
-        "<div class='forecast-day'><div>{}</div><div>{}°/{}</div><div>{}</div></div>",
-        day.day, day.high, day.low, day.condition
-      ))
-      .collect::<Vec<_>>()
-      .join(""),
-    timestamp = chrono::DateTime::parse_from_rfc3339(&weather.timestamp)
-      .unwrap_or_else(|_| chrono::Utc::now().into())
-      .format("%Y-%m-%d %H:%M:%S")
-  )
-}
-
-fn generate_profile_html(profile: &UserProfile) -> String {
-  format!(
-    r#"
-    <div class="profile-card">
-        <div class="profile-header">
-            <img src="{avatar}" alt="Avatar" class="avatar">
-            <div class="profile-info">
-                <h2>{first_name} {last_name}</h2>
-                <p class="username">@{username}</p>
-                <p class="email">{email}</p>
-            </div>
-        </div>
-        <div class="profile-details">
-            <p class="bio">{bio}</p>
-            <p><strong>📍 Location:</strong> {location}</p>
-            <p><strong>📅 Joined:</strong> {join_date}</p>
-        </div>
-        <div class="profile-stats">
-            <div class="stat">
-                <strong>{projects_created}</strong>
-                <span>Projects</span>
-            </div>
-            <div class="stat">
-                <strong>{lines_of_code}</strong>
-                <span>Lines of Code</span>
-            </div>
-            <div class="stat">
-                <strong>{contributions_this_year}</strong>
-                <span>Contributions</span>
-            </div>
-        </div>
-        <div class="preferences">
-            <p><strong>Preferences:</strong> {theme} theme, {language}, notifications {notifications}</p>
-        </div>
-    </div>
-    <style>
-        .profile-card {{ background: #fff; border: 1px solid #ddd; border-radius: 10px; padding: 20px; }}
-        .profile-header {{ display: flex; align-items: center; margin-bottom: 20px; }}
-        .avatar {{ width: 80px; height: 80px; border-radius: 50%; margin-right: 20px; background: #f0f0f0; }}
-        .profile-stats {{ display: flex; justify-content: space-around; margin: 20px 0; }}
-        .stat {{ text-align: center; }}
-        .stat strong {{ display: block; font-size: 1.5em; color: #007acc; }}
-        .bio {{ font-style: italic; margin: 15px 0; }}
-        .username {{ color: #666; }}
-    </style>
-    "#,
-    avatar = profile.profile.avatar,
-    first_name = profile.profile.first_name,
-    last_name = profile.profile.last_name,
-    username = profile.username,
-    email = profile.email,
-    bio = profile.profile.bio,
-    location = profile.profile.location,
-    join_date = profile.profile.join_date,
-    projects_created = profile.stats.projects_created,
-    lines_of_code = profile.stats.lines_of_code,
-    contributions_this_year = profile.stats.contributions_this_year,
-    theme = profile.preferences.theme,
-    language = profile.preferences.language,
-    notifications = if profile.preferences.notifications {
-      "enabled"
-    } else {
-      "disabled"
-    }
-  )
-}
-
 fn generate_system_html(system_info: &Value) -> String {
   format!(
     r#"
@@ -1810,12 +1606,28 @@ async fn v8_demo_safe() -> Html<String> {
   render_custom_html(&v8_html, "V8 JavaScript Engine Demo")
 }
 
+// Live isolate pool stats plus which TypeScript bundles are loaded
+fn v8_status_json() -> Value {
+  serde_json::json!({
+    "pool": v8_pool::V8_POOL.stats(),
+    "typescript_code": v8_processor::v8_code_status(),
+  })
+}
+
+fn v8_status_text() -> String {
+  serde_json::to_string_pretty(&v8_status_json()).unwrap_or_default()
+}
+
+async fn v8_status() -> Json<Value> {
+  Json(v8_status_json())
+}
+
 // V8 TypeScript Demo route - executes actual TypeScript logic with once_cell
 async fn v8_typescript_demo() -> Html<String> {
   let start = Instant::now();
 
   // Get V8 code status using once_cell
-  let v8_status = v8_processor::get_v8_code_status();
+  let v8_status = v8_status_text();
 
   // Process HTTP requests using the global V8 processor
   let http_processing_results = v8_processor::process_sample_requests();
@@ -1955,7 +1767,7 @@ async fn v8_jsonplaceholder_demo() -> Html<String> {
   let start = Instant::now();
 
   // Get V8 code status using once_cell
-  let v8_status = v8_processor::get_v8_code_status();
+  let v8_status = v8_status_text();
 
   // Process JSONPlaceholder requests using the global V8 processor
   let jsonplaceholder_results = v8_processor::process_jsonplaceholder_samples();
@@ -2201,7 +2013,7 @@ async fn stream_chat_demo(Query(params): Query<QueryParams>) -> Html<String> {
   let start = Instant::now();
 
   // Get V8 processor status
-  let v8_status = v8_processor::get_v8_code_status();
+  let v8_status = v8_status_text();
 
   // Create a V8 TypeScript processor
   let mut processor = match v8_processor::V8TypeScriptProcessor::new() {
@@ -2230,10 +2042,11 @@ async fn stream_chat_demo(Query(params): Query<QueryParams>) -> Html<String> {
       println!("file: {}, line :{}", file!(), line!());
       let user_id = params.data.as_deref().unwrap_or("john");
       println!("file: {}, line :{}", file!(), line!());
-      let auth_result = real_v8_executor::RealV8Executor::execute_stream_chat_js_sync(
+      let auth_result = real_v8_executor::RealV8Executor::execute_stream_chat_request(
         "authenticate",
         Some(user_id),
       )
+      .await
       .ok();
       (
         format!("Stream Chat Authentication - User: {}", user_id),
@@ -2242,10 +2055,11 @@ async fn stream_chat_demo(Query(params): Query<QueryParams>) -> Html<String> {
     }
     "user-context" => {
       let user_id = params.data.as_deref().unwrap_or("john");
-      let context_result = real_v8_executor::RealV8Executor::execute_stream_chat_js_sync(
+      let context_result = real_v8_executor::RealV8Executor::execute_stream_chat_request(
         "user-context",
         Some(user_id),
       )
+      .await
       .ok();
       (
         format!("Stream Chat User Context - User: {}", user_id),
@@ -2254,7 +2068,9 @@ async fn stream_chat_demo(Query(params): Query<QueryParams>) -> Html<String> {
     }
     "analytics" => {
       let analytics_result =
-        real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("analytics", None).ok();
+        real_v8_executor::RealV8Executor::execute_stream_chat_request("analytics", None)
+          .await
+          .ok();
       (
         "Stream Chat Analytics".to_string(),
         vec![analytics_result.unwrap_or_else(|| "Failed to get analytics".to_string())],
@@ -2263,7 +2079,9 @@ async fn stream_chat_demo(Query(params): Query<QueryParams>) -> Html<String> {
     _ => {
       // Default to setup demo
       let setup_result =
-        real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("setup", None).ok();
+        real_v8_executor::RealV8Executor::execute_stream_chat_request("setup", None)
+          .await
+          .ok();
       (
         "Stream Chat Demo Setup & Configuration".to_string(),
         vec![setup_result.unwrap_or_else(|| "Failed to get setup info".to_string())],
@@ -2484,7 +2302,8 @@ async fn stream_chat_authenticate(Query(params): Query<QueryParams>) -> Html<Str
 
   let user_id = params.data.as_deref().unwrap_or("john");
   let auth_result =
-    real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("authenticate", Some(user_id));
+    real_v8_executor::RealV8Executor::execute_stream_chat_request("authenticate", Some(user_id))
+      .await;
 
   match auth_result {
     Ok(html_result) => {
@@ -2531,7 +2350,8 @@ async fn stream_chat_user_context(Query(params): Query<QueryParams>) -> Html<Str
 
   let user_id = params.data.as_deref().unwrap_or("john");
   let context_result =
-    real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("user-context", Some(user_id));
+    real_v8_executor::RealV8Executor::execute_stream_chat_request("user-context", Some(user_id))
+      .await;
 
   match context_result {
     Ok(html_result) => {
@@ -2577,7 +2397,7 @@ async fn stream_chat_analytics() -> Html<String> {
   let start = Instant::now();
 
   let analytics_result =
-    real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("analytics", None);
+    real_v8_executor::RealV8Executor::execute_stream_chat_request("analytics", None).await;
 
   match analytics_result {
     Ok(html_result) => {
@@ -2616,7 +2436,8 @@ async fn stream_chat_analytics() -> Html<String> {
 async fn stream_chat_setup() -> Html<String> {
   let start = Instant::now();
 
-  let setup_result = real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("setup", None);
+  let setup_result =
+    real_v8_executor::RealV8Executor::execute_stream_chat_request("setup", None).await;
 
   match setup_result {
     Ok(html_result) => {
@@ -2660,7 +2481,8 @@ async fn stream_chat_token_demo(Query(params): Query<QueryParams>) -> Html<Strin
 
   // Get authentication result using real V8 executor
   let auth_result =
-    real_v8_executor::RealV8Executor::execute_stream_chat_js_sync("authenticate", Some(user_id))
+    real_v8_executor::RealV8Executor::execute_stream_chat_request("authenticate", Some(user_id))
+      .await
       .ok();
 
   let (token, token_details) = match auth_result {
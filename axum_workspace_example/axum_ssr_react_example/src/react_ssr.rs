@@ -0,0 +1,462 @@
+use std::{
+  collections::{HashMap, hash_map::DefaultHasher},
+  fs,
+  hash::{Hash, Hasher},
+  io,
+  sync::Mutex,
+  time::{Instant, SystemTime},
+};
+
+use axum::{
+  body::{Body, Bytes},
+  http::{StatusCode, header},
+  response::{Html, IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use ssr_rs::v8;
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::UnboundedReceiverStream};
+
+use crate::{v8_pool::V8_POOL, v8_processor::HttpRequest};
+
+// Built by `npm run build:ssr-stream` (client/vite.stream.config.ts)
+const REACT_STREAM_BUNDLE: &str = "client/dist/stream/index.js";
+// Built by `npm run build` (client/vite.config.ts), served under /client
+const HYDRATE_MODULE: &str = "/client/hydrate.js";
+// Built by `npm run build:ssr` (client/vite.ssr.config.ts); the `SSR` global holds the functions
+// the demo routes call, see `call_ssr_function`
+const SSR_FUNCTIONS_BUNDLE: &str = "client/dist/ssr/index.js";
+
+const SSR_FUNCTIONS_PRELUDE: &str = r#"
+// Polyfills for V8 environment
+if (typeof MessageChannel === 'undefined') {
+    globalThis.MessageChannel = function() {
+        const channel = {};
+        channel.port1 = { postMessage: function() {}, onmessage: null };
+        channel.port2 = { postMessage: function() {}, onmessage: null };
+        return channel;
+    };
+}
+
+// Mock fetch for demonstration (in real scenarios, you'd use a proper fetch polyfill)
+if (typeof fetch === 'undefined') {
+    globalThis.fetch = async function(url) {
+        return {
+            ok: true,
+            status: 200,
+            json: async () => ({
+                url: url,
+                method: 'GET',
+                timestamp: new Date().toISOString(),
+                message: 'Mock response from V8 environment',
+                data: { users: [{ id: 1, name: 'John' }, { id: 2, name: 'Jane' }] }
+            })
+        };
+    };
+}
+"#;
+
+// Minimal event loop for a bare isolate: React's browser stream build schedules work through
+// setTimeout / MessageChannel, which V8 does not provide. Tasks are queued here and drained by
+// `drive_event_loop` in Rust.
+const EVENT_LOOP_PRELUDE: &str = r#"
+(function (global) {
+  const tasks = [];
+  const cancelled = new Set();
+  let nextId = 1;
+
+  const enqueue = (callback, args) => {
+    const id = nextId++;
+    tasks.push({ id, run: () => callback(...args) });
+    return id;
+  };
+
+  global.self = global.self || global;
+  global.setTimeout = (callback, _delay, ...args) => enqueue(callback, args);
+  global.clearTimeout = (id) => cancelled.add(id);
+  global.setImmediate = (callback, ...args) => enqueue(callback, args);
+  global.clearImmediate = global.clearTimeout;
+  global.queueMicrotask = global.queueMicrotask || ((callback) => Promise.resolve().then(callback));
+  global.performance = global.performance || { now: () => Date.now() };
+
+  global.MessageChannel = function () {
+    const port1 = { onmessage: null };
+    const port2 = { onmessage: null };
+    port1.postMessage = (data) => enqueue(() => port2.onmessage && port2.onmessage({ data }), []);
+    port2.postMessage = (data) => enqueue(() => port1.onmessage && port1.onmessage({ data }), []);
+    this.port1 = port1;
+    this.port2 = port2;
+  };
+
+  // Returns false when there was nothing left to run
+  global.__rustRunNextTask = () => {
+    while (tasks.length > 0) {
+      const task = tasks.shift();
+      if (cancelled.delete(task.id)) {
+        continue;
+      }
+      task.run();
+      return true;
+    }
+    return false;
+  };
+})(globalThis);
+"#;
+
+// Page description handed to `ReactStreamSSR.renderPage` (see client/src/stream-server-entry.tsx)
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderRequest {
+  pub page: String,
+  pub title: String,
+  pub path: String,
+  pub props: Value,
+  pub bootstrap_modules: Vec<String>,
+}
+
+impl RenderRequest {
+  pub fn new(request: &dyn HttpRequest, page: &str, title: &str, props: Value) -> Self {
+    Self {
+      page: page.to_string(),
+      title: title.to_string(),
+      path: request.path(),
+      props,
+      bootstrap_modules: vec![HYDRATE_MODULE.to_string()],
+    }
+  }
+}
+
+type Chunk = Result<String, String>;
+
+// Receiving half of a render; chunks arrive as React flushes them
+pub struct RenderStream {
+  chunks: mpsc::UnboundedReceiver<Chunk>,
+}
+
+impl RenderStream {
+  // Waits for the shell so render failures still map to a proper error status, then streams
+  // the remaining chunks as they are produced.
+  pub async fn into_response(mut self) -> Response {
+    let shell = match self.chunks.recv().await {
+      Some(Ok(shell)) => shell,
+      Some(Err(e)) => return render_error(&e),
+      None => return render_error("React renderer finished without producing output"),
+    };
+
+    let rest = UnboundedReceiverStream::new(self.chunks)
+      .map(|chunk| chunk.map(Bytes::from).map_err(io::Error::other));
+    let body = Body::from_stream(tokio_stream::once(Ok(Bytes::from(shell))).chain(rest));
+
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+  }
+}
+
+fn render_error(message: &str) -> Response {
+  eprintln!("React SSR error: {}", message);
+  (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    Html(format!(
+      "<html><body><h1>SSR Error</h1><p>Failed to render: {}</p></body></html>",
+      message
+    )),
+  )
+    .into_response()
+}
+
+// Queue a render on the shared isolate pool; chunks flow back while it runs
+pub fn render(request: RenderRequest) -> Result<RenderStream, String> {
+  let request_json = serde_json::to_string(&request)
+    .map_err(|e| format!("Failed to serialize render request: {}", e))?;
+  let (chunks, receiver) = mpsc::unbounded_channel();
+  let errors = chunks.clone();
+  let page = request.page;
+  let start = Instant::now();
+
+  V8_POOL.submit(
+    move |isolate| render_on_isolate(isolate, &request_json, chunks),
+    move |result| match result {
+      Ok(()) => println!("React SSR [{}] streamed in {:?}", page, start.elapsed()),
+      Err(e) => {
+        let _ = errors.send(Err(e));
+      }
+    },
+  )?;
+  Ok(RenderStream { chunks: receiver })
+}
+
+// Call one of the functions the `SSR` bundle exports on a pooled isolate and return its result
+// as a string. A returned promise only gets microtasks to settle, which is all the mock fetch
+// needs.
+pub async fn call_ssr_function(name: &str) -> Result<String, String> {
+  let source = fs::read_to_string(SSR_FUNCTIONS_BUNDLE).map_err(|e| {
+    format!(
+      "Failed to read {} ({}); run `npm run build:ssr` in client/",
+      SSR_FUNCTIONS_BUNDLE, e
+    )
+  })?;
+  let name = name.to_string();
+  V8_POOL
+    .execute(move |isolate| call_on_isolate(isolate, &source, &name))
+    .await
+}
+
+fn call_on_isolate(
+  isolate: &mut v8::OwnedIsolate,
+  source: &str,
+  name: &str,
+) -> Result<String, String> {
+  let scope = &mut v8::HandleScope::new(isolate);
+  let context = v8::Context::new(scope, v8::ContextOptions::default());
+  let scope = &mut v8::ContextScope::new(scope, context);
+  let scope = &mut v8::TryCatch::new(scope);
+
+  for (script_name, script_source) in [
+    ("ssr-functions-prelude", SSR_FUNCTIONS_PRELUDE),
+    (SSR_FUNCTIONS_BUNDLE, source),
+  ] {
+    let script = compile_cached(scope, script_name, script_source)
+      .ok_or_else(|| format!("Failed to compile {}", script_name))?;
+    if script.bind_to_current_context(scope).run(scope).is_none() {
+      return Err(exception_message(scope));
+    }
+  }
+
+  let function = global_function(scope, &["SSR", name])
+    .ok_or_else(|| format!("SSR bundle does not export {}", name))?;
+  let receiver = v8::undefined(scope).into();
+  let mut result = function
+    .call(scope, receiver, &[])
+    .ok_or_else(|| exception_message(scope))?;
+  if let Ok(promise) = v8::Local::<v8::Promise>::try_from(result) {
+    scope.perform_microtask_checkpoint();
+    result = match promise.state() {
+      v8::PromiseState::Fulfilled => promise.result(scope),
+      v8::PromiseState::Rejected => return Err(promise.result(scope).to_rust_string_lossy(scope)),
+      v8::PromiseState::Pending => return Err(format!("{} never settled", name)),
+    };
+  }
+  Ok(result.to_rust_string_lossy(scope))
+}
+
+// Compiled code cache shared by every isolate, keyed by script name and invalidated when the
+// source changes. Lets a fresh isolate skip parsing/compiling the bundle.
+struct CachedScript {
+  source_hash: u64,
+  code_cache: Vec<u8>,
+}
+
+static CODE_CACHE: Lazy<Mutex<HashMap<String, CachedScript>>> = Lazy::new(Default::default);
+
+fn source_hash(source: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  source.hash(&mut hasher);
+  hasher.finish()
+}
+
+fn compile_cached<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  name: &str,
+  source: &str,
+) -> Option<v8::Local<'s, v8::UnboundScript>> {
+  use v8::script_compiler::{self, CachedData, CompileOptions, NoCacheReason, Source};
+
+  let hash = source_hash(source);
+  let code = v8::String::new(scope, source)?;
+
+  let cached_bytes = CODE_CACHE.lock().ok().and_then(|cache| {
+    cache
+      .get(name)
+      .filter(|entry| entry.source_hash == hash)
+      .map(|entry| entry.code_cache.clone())
+  });
+  if let Some(bytes) = cached_bytes {
+    let mut source = Source::new_with_cached_data(code, None, CachedData::new(&bytes));
+    let script = script_compiler::compile_unbound_script(
+      scope,
+      &mut source,
+      CompileOptions::ConsumeCodeCache,
+      NoCacheReason::NoReason,
+    )?;
+    if !source.get_cached_data().is_some_and(|data| data.rejected()) {
+      return Some(script);
+    }
+    println!("⚠️  V8 rejected code cache for {}, recompiling", name);
+  }
+
+  let mut source = Source::new(code, None);
+  let script = script_compiler::compile_unbound_script(
+    scope,
+    &mut source,
+    CompileOptions::EagerCompile,
+    NoCacheReason::NoReason,
+  )?;
+  if let Some(code_cache) = script.create_code_cache()
+    && let Ok(mut cache) = CODE_CACHE.lock()
+  {
+    cache.insert(
+      name.to_string(),
+      CachedScript {
+        source_hash: hash,
+        code_cache: code_cache.to_vec(),
+      },
+    );
+  }
+  Some(script)
+}
+
+// Scripts compiled once per isolate and re-bound to a fresh context for every render. Kept in an
+// isolate slot, so they are dropped together with a recycled isolate.
+struct LoadedBundle {
+  modified: Option<SystemTime>,
+  prelude: v8::Global<v8::UnboundScript>,
+  bundle: v8::Global<v8::UnboundScript>,
+}
+
+impl LoadedBundle {
+  fn load(scope: &mut v8::HandleScope, modified: Option<SystemTime>) -> Result<Self, String> {
+    let source = fs::read_to_string(REACT_STREAM_BUNDLE).map_err(|e| {
+      format!(
+        "Failed to read {} ({}); run `npm run build:ssr-stream` in client/",
+        REACT_STREAM_BUNDLE, e
+      )
+    })?;
+
+    let prelude = compile_cached(scope, "react-ssr-prelude", EVENT_LOOP_PRELUDE)
+      .ok_or("Failed to compile event loop prelude")?;
+    let bundle = compile_cached(scope, REACT_STREAM_BUNDLE, &source)
+      .ok_or_else(|| format!("Failed to compile {}", REACT_STREAM_BUNDLE))?;
+    println!("📦 Loaded React stream bundle ({} bytes)", source.len());
+
+    Ok(Self {
+      modified,
+      prelude: v8::Global::new(scope, prelude),
+      bundle: v8::Global::new(scope, bundle),
+    })
+  }
+}
+
+// Sender for the render currently running on this isolate, read by `emit_chunk`
+struct ChunkSink(mpsc::UnboundedSender<Chunk>);
+
+fn emit_chunk(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  let chunk = args.get(0).to_rust_string_lossy(scope);
+  let delivered = scope
+    .get_slot::<ChunkSink>()
+    .is_some_and(|sink| sink.0.send(Ok(chunk)).is_ok());
+  rv.set_bool(delivered);
+}
+
+fn render_on_isolate(
+  isolate: &mut v8::OwnedIsolate,
+  request_json: &str,
+  chunks: mpsc::UnboundedSender<Chunk>,
+) -> Result<(), String> {
+  let mut bundle = isolate.remove_slot::<LoadedBundle>();
+  isolate.set_slot(ChunkSink(chunks));
+  let result = run_render(isolate, &mut bundle, request_json);
+  isolate.remove_slot::<ChunkSink>();
+  if let Some(bundle) = bundle {
+    isolate.set_slot(bundle);
+  }
+  result
+}
+
+fn run_render(
+  isolate: &mut v8::OwnedIsolate,
+  bundle: &mut Option<LoadedBundle>,
+  request_json: &str,
+) -> Result<(), String> {
+  let scope = &mut v8::HandleScope::new(isolate);
+  let context = v8::Context::new(scope, v8::ContextOptions::default());
+  let scope = &mut v8::ContextScope::new(scope, context);
+  let scope = &mut v8::TryCatch::new(scope);
+
+  let modified = fs::metadata(REACT_STREAM_BUNDLE)
+    .and_then(|metadata| metadata.modified())
+    .ok();
+  if bundle
+    .as_ref()
+    .is_none_or(|loaded| loaded.modified != modified)
+  {
+    *bundle = Some(LoadedBundle::load(scope, modified)?);
+  }
+  let Some(loaded) = bundle.as_ref() else {
+    return Err("React stream bundle is not loaded".to_string());
+  };
+
+  for script in [&loaded.prelude, &loaded.bundle] {
+    let script = v8::Local::new(scope, script).bind_to_current_context(scope);
+    if script.run(scope).is_none() {
+      return Err(exception_message(scope));
+    }
+  }
+
+  let render_page = global_function(scope, &["ReactStreamSSR", "renderPage"])
+    .ok_or("Bundle does not expose ReactStreamSSR.renderPage")?;
+  let emit = v8::Function::new(scope, emit_chunk).ok_or("Failed to create emit callback")?;
+  let request = v8::String::new(scope, request_json).ok_or("Failed to create JS string")?;
+
+  let receiver = v8::undefined(scope).into();
+  let promise = render_page
+    .call(scope, receiver, &[request.into(), emit.into()])
+    .ok_or_else(|| exception_message(scope))?;
+  let promise = v8::Local::<v8::Promise>::try_from(promise)
+    .map_err(|_| "renderPage did not return a Promise".to_string())?;
+
+  drive_event_loop(scope, promise)
+}
+
+// Alternate between microtasks and queued macrotasks until the render promise settles
+fn drive_event_loop(
+  scope: &mut v8::TryCatch<v8::HandleScope>,
+  promise: v8::Local<v8::Promise>,
+) -> Result<(), String> {
+  let run_next_task =
+    global_function(scope, &["__rustRunNextTask"]).ok_or("Event loop prelude is not installed")?;
+
+  loop {
+    scope.perform_microtask_checkpoint();
+    match promise.state() {
+      v8::PromiseState::Fulfilled => return Ok(()),
+      v8::PromiseState::Rejected => {
+        let reason = promise.result(scope);
+        return Err(reason.to_rust_string_lossy(scope));
+      }
+      v8::PromiseState::Pending => {
+        let receiver = v8::undefined(scope).into();
+        let ran_task = run_next_task
+          .call(scope, receiver, &[])
+          .ok_or_else(|| exception_message(scope))?;
+        if !ran_task.is_true() {
+          return Err("Render stalled: promise pending with no queued tasks".to_string());
+        }
+      }
+    }
+  }
+}
+
+fn global_function<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  path: &[&str],
+) -> Option<v8::Local<'s, v8::Function>> {
+  let context = scope.get_current_context();
+  let mut value: v8::Local<v8::Value> = context.global(scope).into();
+  for name in path {
+    let key = v8::String::new(scope, name)?;
+    value = value.to_object(scope)?.get(scope, key.into())?;
+  }
+  v8::Local::<v8::Function>::try_from(value).ok()
+}
+
+fn exception_message(scope: &mut v8::TryCatch<v8::HandleScope>) -> String {
+  match scope.exception() {
+    Some(exception) => exception.to_rust_string_lossy(scope),
+    None => "Unknown JavaScript error".to_string(),
+  }
+}
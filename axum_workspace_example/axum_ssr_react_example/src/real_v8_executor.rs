@@ -1,15 +1,17 @@
 use std::fs;
 
+use once_cell::sync::Lazy;
 use ssr_rs::v8;
 
-use crate::config::STREAM_CONFIG;
+use crate::{config::STREAM_CONFIG, v8_pool::V8_POOL};
 
 pub struct RealV8Executor;
 
 impl RealV8Executor {
   pub fn initialize() -> Result<(), String> {
-    // V8 initialization is handled by ssr_rs::Ssr::create_platform()
-    // which is already called in main(). No additional initialization needed.
+    // The V8 platform is created by ssr_rs::Ssr::create_platform() in main(); start the
+    // isolate pool threads eagerly so the first request does not pay for it
+    Lazy::force(&V8_POOL);
     Ok(())
   }
 
@@ -103,10 +105,23 @@ impl RealV8Executor {
     "#.to_string())
   }
 
-  // Execute Stream Chat JavaScript and render to HTML
-  pub fn execute_stream_chat_js(action: &str, user_id: Option<&str>) -> Result<String, String> {
-    // Create a new isolate for each request to avoid thread safety issues
-    let isolate = &mut v8::Isolate::new(v8::CreateParams::default());
+  // Execute Stream Chat JavaScript and render to HTML on a pooled isolate
+  pub async fn execute_stream_chat_js(
+    action: &str,
+    user_id: Option<&str>,
+  ) -> Result<String, String> {
+    let action = action.to_string();
+    let user_id = user_id.map(str::to_string);
+    V8_POOL
+      .execute(move |isolate| Self::run_stream_chat_js(isolate, &action, user_id.as_deref()))
+      .await
+  }
+
+  fn run_stream_chat_js(
+    isolate: &mut v8::OwnedIsolate,
+    action: &str,
+    user_id: Option<&str>,
+  ) -> Result<String, String> {
     let scope = &mut v8::HandleScope::new(isolate);
     let context = v8::Context::new(scope, v8::ContextOptions::default());
     let scope = &mut v8::ContextScope::new(scope, context);
@@ -197,8 +212,21 @@ impl RealV8Executor {
     }
   }
 
-  // Synchronous version that handles async functions by converting them to sync
-  pub fn execute_stream_chat_js_sync(
+  // Run `processStreamChatRequestSync(action, ...)` on a pooled isolate and return its JSON
+  // result; unlike `execute_stream_chat_js` nothing is awaited in JS, so no event loop is needed
+  pub async fn execute_stream_chat_request(
+    action: &str,
+    user_id: Option<&str>,
+  ) -> Result<String, String> {
+    let action = action.to_string();
+    let user_id = user_id.map(str::to_string);
+    V8_POOL
+      .execute(move |isolate| Self::run_stream_chat_request(isolate, &action, user_id.as_deref()))
+      .await
+  }
+
+  fn run_stream_chat_request(
+    isolate: &mut v8::OwnedIsolate,
     action: &str,
     user_id: Option<&str>,
   ) -> Result<String, String> {
@@ -207,8 +235,6 @@ impl RealV8Executor {
       action, user_id
     );
     println!("file: {}, line :{}", file!(), line!());
-    // Create a new isolate for each request to avoid thread safety issues
-    let isolate = &mut v8::Isolate::new(v8::CreateParams::default());
     let scope = &mut v8::HandleScope::new(isolate);
     let context = v8::Context::new(scope, v8::ContextOptions::default());
     let scope = &mut v8::ContextScope::new(scope, context);
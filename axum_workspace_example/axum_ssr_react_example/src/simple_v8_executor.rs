@@ -1,6 +1,11 @@
 use crate::config::STREAM_CONFIG;
 
 // Simple V8 executor that directly runs JavaScript without SSR
+//
+// Not compiled: its `mod` line in main.rs stays commented out. Despite the name it never creates
+// an isolate or runs JavaScript, it only builds canned Stream Chat JSON in Rust, so there is
+// nothing to move onto the shared isolate pool. Kept as a reference for the responses
+// `RealV8Executor` produces.
 pub struct SimpleV8Executor;
 
 impl SimpleV8Executor {
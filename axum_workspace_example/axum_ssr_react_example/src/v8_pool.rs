@@ -0,0 +1,456 @@
+use std::{
+  any::Any,
+  ffi::c_void,
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    Arc, Condvar, Mutex,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    mpsc as std_mpsc,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use ssr_rs::v8;
+use tokio::sync::oneshot;
+
+use crate::config::{V8_POOL_CONFIG, V8PoolConfig};
+
+// Shared isolate pool used by every V8 executor. Isolates live on dedicated threads so a
+// runaway script never blocks a tokio worker.
+pub static V8_POOL: Lazy<V8IsolatePool> = Lazy::new(|| V8IsolatePool::new(V8_POOL_CONFIG.clone()));
+
+// What a job reports back to its worker: whether it succeeded, and how to deliver the result
+// once the worker knows if the isolate was terminated underneath it
+struct JobOutcome {
+  ok: bool,
+  panicked: bool,
+  finish: Box<dyn FnOnce(Option<String>) + Send>,
+}
+
+type Job = Box<dyn FnOnce(&mut v8::OwnedIsolate) -> JobOutcome + Send>;
+
+#[derive(Default)]
+struct PoolCounters {
+  submitted: AtomicU64,
+  started: AtomicU64,
+  completed: AtomicU64,
+  failed: AtomicU64,
+  timeouts: AtomicU64,
+  heap_limit_hits: AtomicU64,
+  panics: AtomicU64,
+  isolates_created: AtomicU64,
+  isolates_recycled: AtomicU64,
+}
+
+#[derive(Default)]
+struct WorkerStatus {
+  busy: AtomicBool,
+  renders_on_isolate: AtomicU64,
+  used_heap_bytes: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct V8WorkerStats {
+  pub id: usize,
+  pub busy: bool,
+  pub renders_on_isolate: u64,
+  pub used_heap_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct V8PoolStats {
+  pub workers: usize,
+  pub busy_workers: usize,
+  pub queued_jobs: u64,
+  pub submitted: u64,
+  pub completed: u64,
+  pub failed: u64,
+  pub timeouts: u64,
+  pub heap_limit_hits: u64,
+  pub panics: u64,
+  pub isolates_created: u64,
+  pub isolates_recycled: u64,
+  pub render_timeout_ms: u128,
+  pub max_heap_bytes: usize,
+  pub max_renders_per_isolate: u64,
+  pub worker_details: Vec<V8WorkerStats>,
+}
+
+pub struct V8IsolatePool {
+  jobs: std_mpsc::Sender<Job>,
+  counters: Arc<PoolCounters>,
+  workers: Vec<Arc<WorkerStatus>>,
+  config: V8PoolConfig,
+}
+
+impl V8IsolatePool {
+  pub fn new(config: V8PoolConfig) -> Self {
+    let (jobs, receiver) = std_mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let counters = Arc::new(PoolCounters::default());
+
+    let workers = (0 .. config.workers)
+      .map(|id| {
+        let status = Arc::new(WorkerStatus::default());
+        let worker = Worker {
+          config: config.clone(),
+          counters: counters.clone(),
+          status: status.clone(),
+          watchdog: Watchdog::spawn(id),
+        };
+        let receiver = receiver.clone();
+        thread::Builder::new()
+          .name(format!("v8-pool-{}", id))
+          .spawn(move || worker.run(receiver))
+          .expect("failed to spawn V8 pool worker");
+        status
+      })
+      .collect();
+
+    println!(
+      "✅ V8 isolate pool: {} workers, {:?} timeout, {} MiB heap, recycle after {} renders",
+      config.workers,
+      config.render_timeout,
+      config.max_heap_bytes / (1024 * 1024),
+      config.max_renders_per_isolate
+    );
+
+    Self {
+      jobs,
+      counters,
+      workers,
+      config,
+    }
+  }
+
+  // Queue `job` on a pooled isolate; `on_complete` runs on the worker thread with the job's
+  // result, or with an error if the watchdog or heap guard terminated it
+  pub fn submit<F, T, C>(&self, job: F, on_complete: C) -> Result<(), String>
+  where
+    F: FnOnce(&mut v8::OwnedIsolate) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+    C: FnOnce(Result<T, String>) + Send + 'static,
+  {
+    let job: Job = Box::new(move |isolate| {
+      // A panicking job still answers its caller; the worker then discards the isolate
+      let (result, panicked) = match panic::catch_unwind(AssertUnwindSafe(|| job(isolate))) {
+        Ok(result) => (result, false),
+        Err(payload) => (
+          Err(format!(
+            "V8 render job panicked: {}",
+            panic_message(&*payload)
+          )),
+          true,
+        ),
+      };
+      JobOutcome {
+        ok: result.is_ok(),
+        panicked,
+        finish: Box::new(move |aborted| match aborted {
+          Some(reason) => on_complete(Err(reason)),
+          None => on_complete(result),
+        }),
+      }
+    });
+
+    self.counters.submitted.fetch_add(1, Ordering::Relaxed);
+    self
+      .jobs
+      .send(job)
+      .map_err(|_| "V8 isolate pool is shut down".to_string())
+  }
+
+  // Run `job` on a pooled isolate and wait for its result without blocking the runtime
+  pub async fn execute<F, T>(&self, job: F) -> Result<T, String>
+  where
+    F: FnOnce(&mut v8::OwnedIsolate) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+  {
+    let (tx, rx) = oneshot::channel();
+    self.submit(job, move |result| {
+      let _ = tx.send(result);
+    })?;
+    rx.await
+      .map_err(|_| "V8 pool worker dropped the job".to_string())?
+  }
+
+  pub fn stats(&self) -> V8PoolStats {
+    let counters = &self.counters;
+    let worker_details: Vec<V8WorkerStats> = self
+      .workers
+      .iter()
+      .enumerate()
+      .map(|(id, status)| V8WorkerStats {
+        id,
+        busy: status.busy.load(Ordering::Relaxed),
+        renders_on_isolate: status.renders_on_isolate.load(Ordering::Relaxed),
+        used_heap_bytes: status.used_heap_bytes.load(Ordering::Relaxed),
+      })
+      .collect();
+    let submitted = counters.submitted.load(Ordering::Relaxed);
+
+    V8PoolStats {
+      workers: self.workers.len(),
+      busy_workers: worker_details.iter().filter(|worker| worker.busy).count(),
+      queued_jobs: submitted.saturating_sub(counters.started.load(Ordering::Relaxed)),
+      submitted,
+      completed: counters.completed.load(Ordering::Relaxed),
+      failed: counters.failed.load(Ordering::Relaxed),
+      timeouts: counters.timeouts.load(Ordering::Relaxed),
+      heap_limit_hits: counters.heap_limit_hits.load(Ordering::Relaxed),
+      panics: counters.panics.load(Ordering::Relaxed),
+      isolates_created: counters.isolates_created.load(Ordering::Relaxed),
+      isolates_recycled: counters.isolates_recycled.load(Ordering::Relaxed),
+      render_timeout_ms: self.config.render_timeout.as_millis(),
+      max_heap_bytes: self.config.max_heap_bytes,
+      max_renders_per_isolate: self.config.max_renders_per_isolate,
+      worker_details,
+    }
+  }
+}
+
+// Shared with the near-heap-limit callback, so it must outlive the isolate
+struct HeapGuard {
+  handle: v8::IsolateHandle,
+  hit: AtomicBool,
+}
+
+unsafe extern "C" fn near_heap_limit(
+  data: *mut c_void,
+  current_heap_limit: usize,
+  _initial_heap_limit: usize,
+) -> usize {
+  // SAFETY: `data` points at the `HeapGuard` boxed next to this isolate in `PooledIsolate`
+  let guard = unsafe { &*(data as *const HeapGuard) };
+  guard.hit.store(true, Ordering::SeqCst);
+  guard.handle.terminate_execution();
+  // Give V8 headroom to unwind the terminated script instead of aborting the process
+  current_heap_limit * 2
+}
+
+struct PooledIsolate {
+  // Dropped before the guard the heap callback points at
+  isolate: v8::OwnedIsolate,
+  heap_guard: Box<HeapGuard>,
+  renders: u64,
+}
+
+impl PooledIsolate {
+  fn new(config: &V8PoolConfig) -> Self {
+    let params = v8::CreateParams::default().heap_limits(0, config.max_heap_bytes);
+    let mut isolate = v8::Isolate::new(params);
+    let heap_guard = Box::new(HeapGuard {
+      handle: isolate.thread_safe_handle(),
+      hit: AtomicBool::new(false),
+    });
+    isolate.add_near_heap_limit_callback(
+      near_heap_limit,
+      &*heap_guard as *const HeapGuard as *mut c_void,
+    );
+
+    Self {
+      isolate,
+      heap_guard,
+      renders: 0,
+    }
+  }
+}
+
+// Per-worker wall-clock guard; terminates the isolate if a job runs past its deadline
+struct Watchdog {
+  state: Mutex<WatchState>,
+  wake: Condvar,
+}
+
+#[derive(Default)]
+struct WatchState {
+  deadline: Option<Instant>,
+  handle: Option<v8::IsolateHandle>,
+  // Job the deadline belongs to, and the last job the watchdog terminated
+  job: u64,
+  terminated: Option<u64>,
+}
+
+impl Watchdog {
+  fn spawn(id: usize) -> Arc<Self> {
+    let watchdog = Arc::new(Self {
+      state: Mutex::new(WatchState::default()),
+      wake: Condvar::new(),
+    });
+    let watcher = watchdog.clone();
+    thread::Builder::new()
+      .name(format!("v8-watchdog-{}", id))
+      .spawn(move || watcher.watch())
+      .expect("failed to spawn V8 watchdog");
+    watchdog
+  }
+
+  fn watch(&self) {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      match state.deadline {
+        None => state = self.wake.wait(state).unwrap(),
+        Some(deadline) => {
+          let now = Instant::now();
+          if now >= deadline {
+            // Terminating under the lock means `disarm` can never race a late termination
+            if let Some(handle) = state.handle.take() {
+              handle.terminate_execution();
+            }
+            state.terminated = Some(state.job);
+            state.deadline = None;
+          } else {
+            state = self.wake.wait_timeout(state, deadline - now).unwrap().0;
+          }
+        }
+      }
+    }
+  }
+
+  fn arm(&self, job: u64, handle: v8::IsolateHandle, timeout: Duration) {
+    let mut state = self.state.lock().unwrap();
+    state.deadline = Some(Instant::now() + timeout);
+    state.handle = Some(handle);
+    state.job = job;
+    self.wake.notify_one();
+  }
+
+  // Returns true if `job` was terminated for running too long
+  fn disarm(&self, job: u64) -> bool {
+    let mut state = self.state.lock().unwrap();
+    state.deadline = None;
+    state.handle = None;
+    state.terminated == Some(job)
+  }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+  payload
+    .downcast_ref::<&str>()
+    .copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("non-string panic payload")
+}
+
+struct Worker {
+  config: V8PoolConfig,
+  counters: Arc<PoolCounters>,
+  status: Arc<WorkerStatus>,
+  watchdog: Arc<Watchdog>,
+}
+
+impl Worker {
+  fn run(self, jobs: Arc<Mutex<std_mpsc::Receiver<Job>>>) {
+    let mut current: Option<PooledIsolate> = None;
+    let mut job_id = 0;
+
+    loop {
+      let job = match jobs.lock().unwrap().recv() {
+        Ok(job) => job,
+        Err(_) => return,
+      };
+      job_id += 1;
+      self.counters.started.fetch_add(1, Ordering::Relaxed);
+      self.status.busy.store(true, Ordering::Relaxed);
+
+      // Panics outside the job itself (isolate setup, the completion callback) must not take
+      // the worker thread down with them, or the pool would silently lose a slot
+      let run = panic::catch_unwind(AssertUnwindSafe(|| self.run_job(&mut current, job_id, job)));
+      if let Err(payload) = run {
+        eprintln!(
+          "❌ V8 pool worker panicked outside a render job: {}",
+          panic_message(&*payload)
+        );
+        self.watchdog.disarm(job_id);
+        self.counters.panics.fetch_add(1, Ordering::Relaxed);
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+        self.retire(&mut current);
+      }
+      self.status.busy.store(false, Ordering::Relaxed);
+    }
+  }
+
+  fn run_job(&self, current: &mut Option<PooledIsolate>, job_id: u64, job: Job) {
+    let pooled = current.get_or_insert_with(|| {
+      self
+        .counters
+        .isolates_created
+        .fetch_add(1, Ordering::Relaxed);
+      PooledIsolate::new(&self.config)
+    });
+
+    // Clear anything a previous job left behind so it cannot abort this one
+    pooled.isolate.cancel_terminate_execution();
+    pooled.heap_guard.hit.store(false, Ordering::SeqCst);
+
+    self.watchdog.arm(
+      job_id,
+      pooled.isolate.thread_safe_handle(),
+      self.config.render_timeout,
+    );
+    let outcome = job(&mut pooled.isolate);
+    let timed_out = self.watchdog.disarm(job_id);
+    let heap_exceeded = pooled.heap_guard.hit.swap(false, Ordering::SeqCst);
+
+    pooled.renders += 1;
+    let used_heap = pooled.isolate.get_heap_statistics().used_heap_size() as u64;
+    self
+      .status
+      .renders_on_isolate
+      .store(pooled.renders, Ordering::Relaxed);
+    self
+      .status
+      .used_heap_bytes
+      .store(used_heap, Ordering::Relaxed);
+    let worn_out = pooled.renders >= self.config.max_renders_per_isolate;
+
+    let aborted = if timed_out {
+      self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+      Some(format!(
+        "V8 execution exceeded {:?} and was terminated",
+        self.config.render_timeout
+      ))
+    } else if heap_exceeded {
+      self
+        .counters
+        .heap_limit_hits
+        .fetch_add(1, Ordering::Relaxed);
+      Some(format!(
+        "V8 heap limit of {} MiB reached and execution was terminated",
+        self.config.max_heap_bytes / (1024 * 1024)
+      ))
+    } else {
+      None
+    };
+    if outcome.panicked {
+      self.counters.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if outcome.ok && aborted.is_none() {
+      self.counters.completed.fetch_add(1, Ordering::Relaxed);
+    } else {
+      self.counters.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A terminated or panicked isolate is never reused; healthy ones are replaced after N
+    // renders
+    let recycle = aborted.is_some() || outcome.panicked || worn_out;
+    if recycle {
+      self.retire(current);
+    }
+    (outcome.finish)(aborted);
+  }
+
+  fn retire(&self, current: &mut Option<PooledIsolate>) {
+    if current.take().is_some() {
+      self
+        .counters
+        .isolates_recycled
+        .fetch_add(1, Ordering::Relaxed);
+    }
+    self.status.renders_on_isolate.store(0, Ordering::Relaxed);
+  }
+}
@@ -1,9 +1,13 @@
 use std::{fs, sync::Mutex};
 
+use axum::http::{HeaderMap, Uri, header};
 use once_cell::sync::Lazy;
 
 // use ssr_rs::v8;
-use crate::config::STREAM_CONFIG;
+use crate::{
+  config::STREAM_CONFIG,
+  react_ssr::{self, RenderRequest, RenderStream},
+};
 
 pub trait HttpRequest {
   fn path(&self) -> String;
@@ -20,6 +24,21 @@ pub struct StringHttpRequest {
 }
 
 impl StringHttpRequest {
+  pub fn from_parts(uri: &Uri, headers: &HeaderMap) -> Self {
+    let header = |name: header::HeaderName| {
+      headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+    };
+    Self::new(
+      uri.path(),
+      header(header::REFERER),
+      header(header::HOST),
+      header(header::USER_AGENT),
+    )
+  }
+
   pub fn new(path: &str, referrer: &str, host: &str, user_agent: &str) -> Self {
     Self {
       path: path.to_string(),
@@ -105,7 +124,7 @@ impl V8TypeScriptProcessor {
   }
 
   // Execute Stream Chat TypeScript code with real credentials
-  pub fn execute_stream_chat_with_credentials(
+  pub async fn execute_stream_chat_with_credentials(
     &mut self,
     request_type: &str,
     params: Option<serde_json::Value>,
@@ -127,7 +146,9 @@ impl V8TypeScriptProcessor {
     match crate::v8_stream_executor::StreamChatExecutor::execute_function(
       request_type,
       enhanced_params,
-    ) {
+    )
+    .await
+    {
       Ok(result) => Some(result),
       Err(e) => {
         eprintln!("Failed to execute Stream Chat function: {}", e);
@@ -136,8 +157,19 @@ impl V8TypeScriptProcessor {
     }
   }
 
+  // Render a React page for this request with the streaming SSR bundle; the props are embedded
+  // in the document so the client can hydrate
+  pub fn process_http_request(
+    request: &dyn HttpRequest,
+    page: &str,
+    title: &str,
+    props: serde_json::Value,
+  ) -> Result<RenderStream, String> {
+    react_ssr::render(RenderRequest::new(request, page, title, props))
+  }
+
   // Simulate processing using the loaded TypeScript code
-  pub fn process_http_request(&self, request: &dyn HttpRequest) -> Option<String> {
+  pub fn analyze_http_request(&self, request: &dyn HttpRequest) -> Option<String> {
     let code_guard = V8_CODE.lock().ok()?;
     let _code = code_guard.as_ref()?;
 
@@ -747,7 +779,7 @@ impl V8TypeScriptProcessor {
   }
 
   // Stream Chat processing methods - Using proper Stream Chat API pattern
-  pub fn authenticate_stream_user(
+  pub async fn authenticate_stream_user(
     &mut self,
     user_id: &str,
     api_key: Option<&str>,
@@ -764,35 +796,43 @@ impl V8TypeScriptProcessor {
       "api_secret": api_secret
     });
 
-    self.execute_stream_chat_with_credentials("authenticate", Some(params))
+    self
+      .execute_stream_chat_with_credentials("authenticate", Some(params))
+      .await
   }
 
-  pub fn get_user_chat_context(&mut self, user_id: &str) -> Option<String> {
+  pub async fn get_user_chat_context(&mut self, user_id: &str) -> Option<String> {
     let params = serde_json::json!({
       "user_id": user_id,
       "api_key": &STREAM_CONFIG.api_key,
       "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    self.execute_stream_chat_with_credentials("user-context", Some(params))
+    self
+      .execute_stream_chat_with_credentials("user-context", Some(params))
+      .await
   }
 
-  pub fn analyze_stream_chat_data(&mut self) -> Option<String> {
+  pub async fn analyze_stream_chat_data(&mut self) -> Option<String> {
     let params = serde_json::json!({
       "api_key": &STREAM_CONFIG.api_key,
       "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    self.execute_stream_chat_with_credentials("analytics", Some(params))
+    self
+      .execute_stream_chat_with_credentials("analytics", Some(params))
+      .await
   }
 
-  pub fn get_stream_chat_demo_setup(&mut self) -> Option<String> {
+  pub async fn get_stream_chat_demo_setup(&mut self) -> Option<String> {
     let params = serde_json::json!({
       "api_key": &STREAM_CONFIG.api_key,
       "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    self.execute_stream_chat_with_credentials("setup", Some(params))
+    self
+      .execute_stream_chat_with_credentials("setup", Some(params))
+      .await
   }
 }
 
@@ -1137,7 +1177,7 @@ pub fn process_sample_requests() -> Vec<String> {
 
   requests
     .iter()
-    .filter_map(|req| processor.process_http_request(req))
+    .filter_map(|req| processor.analyze_http_request(req))
     .collect()
 }
 
@@ -1186,43 +1226,62 @@ pub fn process_jsonplaceholder_samples() -> Vec<String> {
 }
 
 // Stream Chat processing functions
-pub fn process_stream_chat_samples() -> Vec<String> {
+pub async fn process_stream_chat_samples() -> Vec<String> {
   let mut processor = match V8TypeScriptProcessor::new() {
     Some(p) => p,
     None => return vec!["Failed to create V8 processor - TypeScript files not found".to_string()],
   };
 
   vec![
-    processor.get_stream_chat_demo_setup().unwrap_or_default(),
+    processor
+      .get_stream_chat_demo_setup()
+      .await
+      .unwrap_or_default(),
     processor
       .authenticate_stream_user("john", None, None)
+      .await
       .unwrap_or_default(),
     processor
       .authenticate_stream_user("jane", None, None)
+      .await
+      .unwrap_or_default(),
+    processor
+      .get_user_chat_context("john")
+      .await
+      .unwrap_or_default(),
+    processor
+      .get_user_chat_context("jane")
+      .await
+      .unwrap_or_default(),
+    processor
+      .analyze_stream_chat_data()
+      .await
       .unwrap_or_default(),
-    processor.get_user_chat_context("john").unwrap_or_default(),
-    processor.get_user_chat_context("jane").unwrap_or_default(),
-    processor.analyze_stream_chat_data().unwrap_or_default(),
   ]
 }
 
-// Get information about the V8 code loading status
-pub fn get_v8_code_status() -> String {
+// Which compiled TypeScript files are loaded, reported next to the pool stats on /v8/status
+pub fn v8_code_status() -> serde_json::Value {
   match V8_CODE.lock() {
     Ok(guard) => match guard.as_ref() {
-      Some(code) => format!(
-        "✅ V8 TypeScript code loaded successfully\n📁 v8-processing.js: {} characters\n📁 \
-         data-generators.js: {} characters\n📁 jsonplaceholder-demo.js: {} characters\n🔄 Using \
-         once_cell for global storage",
-        code.v8_processing_js.len(),
-        code.data_generators_js.len(),
-        code.jsonplaceholder_demo_js.len()
-      ),
-      None => {
-        "❌ V8 TypeScript code failed to load - check if client/dist/v8/ files exist".to_string()
-      }
+      Some(code) => serde_json::json!({
+        "loaded": true,
+        "files": {
+          "v8-processing.js": code.v8_processing_js.len(),
+          "data-generators.js": code.data_generators_js.len(),
+          "jsonplaceholder-demo.js": code.jsonplaceholder_demo_js.len(),
+          "stream-chat-demo.js": code.stream_chat_demo_js.len(),
+        },
+      }),
+      None => serde_json::json!({
+        "loaded": false,
+        "error": "check if client/dist/v8/ files exist",
+      }),
     },
-    Err(_) => "❌ V8 TypeScript code mutex is poisoned".to_string(),
+    Err(_) => serde_json::json!({
+      "loaded": false,
+      "error": "V8 TypeScript code mutex is poisoned",
+    }),
   }
 }
 
@@ -1,12 +1,12 @@
-use ssr_rs::Ssr;
+use ssr_rs::v8;
 
-use crate::config::STREAM_CONFIG;
+use crate::{config::STREAM_CONFIG, v8_pool::V8_POOL};
 
-// Execute Stream Chat TypeScript functions using ssr_rs
+// Execute Stream Chat TypeScript functions on the shared V8 isolate pool
 pub struct StreamChatExecutor;
 
 impl StreamChatExecutor {
-  pub fn execute_function(
+  pub async fn execute_function(
     function_name: &str,
     params: serde_json::Value,
   ) -> Result<String, String> {
@@ -20,75 +20,69 @@ impl StreamChatExecutor {
       }
     };
 
-    // Create the JavaScript to execute - wrap in a function that returns the result
+    // The script's completion value is the JSON result
     let js_code = format!(
       r#"
             // Load the Stream Chat implementation
             {}
-            
-            // Create a function that SSR can execute
-            function App() {{
-              const params = {};
-              const result = processStreamChatRequest('{}', params);
-              return JSON.stringify(result);
-            }}
-            
-            // Export for SSR
-            if (typeof module !== 'undefined') {{
-              module.exports = {{ default: App }};
-            }}
+
+            JSON.stringify(processStreamChatRequest('{}', {}));
             "#,
       typescript_code,
-      serde_json::to_string(&params).unwrap(),
-      function_name
+      function_name,
+      serde_json::to_string(&params).unwrap()
     );
 
-    // Create SSR instance and execute
-    let mut ssr = Ssr::from(js_code, "stream-chat")
-      .map_err(|e| format!("Failed to create SSR instance: {:?}", e))?;
-
-    let result = ssr
-      .render_to_string(None)
-      .map_err(|e| format!("Failed to execute: {:?}", e))?;
+    // Run on a pooled isolate instead of spinning up a new ssr_rs instance per call
+    V8_POOL
+      .execute(move |isolate| {
+        let scope = &mut v8::HandleScope::new(isolate);
+        let context = v8::Context::new(scope, v8::ContextOptions::default());
+        let scope = &mut v8::ContextScope::new(scope, context);
 
-    Ok(result)
+        let code = v8::String::new(scope, &js_code).ok_or("Failed to create JS string")?;
+        let script = v8::Script::compile(scope, code, None).ok_or("Failed to compile JS")?;
+        let result = script.run(scope).ok_or("Failed to execute JS")?;
+        Ok(result.to_rust_string_lossy(scope))
+      })
+      .await
   }
 
-  pub fn authenticate_user(user_id: &str) -> Result<String, String> {
+  pub async fn authenticate_user(user_id: &str) -> Result<String, String> {
     let params = serde_json::json!({
         "user_id": user_id,
         "api_key": &STREAM_CONFIG.api_key,
         "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    Self::execute_function("authenticate", params)
+    Self::execute_function("authenticate", params).await
   }
 
-  pub fn get_user_context(user_id: &str) -> Result<String, String> {
+  pub async fn get_user_context(user_id: &str) -> Result<String, String> {
     let params = serde_json::json!({
         "user_id": user_id,
         "api_key": &STREAM_CONFIG.api_key,
         "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    Self::execute_function("user-context", params)
+    Self::execute_function("user-context", params).await
   }
 
-  pub fn get_analytics() -> Result<String, String> {
+  pub async fn get_analytics() -> Result<String, String> {
     let params = serde_json::json!({
         "api_key": &STREAM_CONFIG.api_key,
         "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    Self::execute_function("analytics", params)
+    Self::execute_function("analytics", params).await
   }
 
-  pub fn get_setup() -> Result<String, String> {
+  pub async fn get_setup() -> Result<String, String> {
     let params = serde_json::json!({
         "api_key": &STREAM_CONFIG.api_key,
         "api_secret": &STREAM_CONFIG.api_secret
     });
 
-    Self::execute_function("setup", params)
+    Self::execute_function("setup", params).await
   }
 }
@@ -0,0 +1,161 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Executor tags, shared by `custom_executor`, which advertises them, and
+//! `custom_scheduler`, which places tasks by them.
+//!
+//! Ballista's executor registration only carries task slots, so tags travel
+//! over a small HTTP endpoint next to the scheduler. The scheduler joins an
+//! advertisement to the registered executor by host and port.
+
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Where executors post their [`Advertisement`].
+pub const EXECUTORS_PATH: &str = "/executors";
+/// Per executor gauges in the Prometheus text format.
+pub const METRICS_PATH: &str = "/metrics";
+/// Default port of the placement endpoint, the scheduler's gRPC port is 50050.
+pub const DEFAULT_PLACEMENT_PORT: u16 = 50060;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryClass {
+  Small,
+  Medium,
+  Large,
+}
+
+impl fmt::Display for MemoryClass {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Small => "small",
+      Self::Medium => "medium",
+      Self::Large => "large",
+    })
+  }
+}
+
+impl FromStr for MemoryClass {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "small" => Ok(Self::Small),
+      "medium" => Ok(Self::Medium),
+      "large" => Ok(Self::Large),
+      _ => Err(format!(
+        "unknown memory class `{s}`, expected small, medium or large"
+      )),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutorTags {
+  pub memory_class: MemoryClass,
+  /// Directories on the executor's local disk holding input files.
+  pub data_dirs: Vec<PathBuf>,
+}
+
+impl ExecutorTags {
+  /// How many of `files` are under one of the data directories. Files are
+  /// object store locations, which are absolute paths without the leading
+  /// `/`.
+  pub fn local_files<S: AsRef<str>>(&self, files: &[S]) -> usize {
+    let dirs: Vec<String> = self
+      .data_dirs
+      .iter()
+      .map(|dir| dir.to_string_lossy().trim_matches('/').to_string())
+      .collect();
+    files
+      .iter()
+      .filter(|file| {
+        let file = file.as_ref().trim_start_matches('/');
+        dirs.iter().any(|dir| {
+          file
+            .strip_prefix(dir.as_str())
+            .is_some_and(|rest| rest.starts_with('/'))
+        })
+      })
+      .count()
+  }
+}
+
+/// What an executor posts to [`EXECUTORS_PATH`]. `host` and `port` are the
+/// ones the executor registers with, the scheduler matches on them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advertisement {
+  pub host: String,
+  pub port: u16,
+  pub tags: ExecutorTags,
+}
+
+impl Advertisement {
+  pub fn endpoint(&self) -> String {
+    format!("{}:{}", self.host, self.port)
+  }
+}
+
+/// Posts `advertisement` to the placement endpoint at `placement_url` every
+/// `interval`, so a restarted scheduler learns the tags again. Never returns,
+/// spawn it next to the executor.
+pub async fn advertise(placement_url: String, advertisement: Advertisement, interval: Duration) {
+  let client = reqwest::Client::new();
+  let url = format!("{}{EXECUTORS_PATH}", placement_url.trim_end_matches('/'));
+  loop {
+    let response = client
+      .put(&url)
+      .json(&advertisement)
+      .send()
+      .await
+      .and_then(reqwest::Response::error_for_status);
+    if let Err(e) = response {
+      log::warn!("failed to advertise executor tags to {url}: {e}");
+    }
+    tokio::time::sleep(interval).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_files_under_data_dirs() {
+    let tags = ExecutorTags {
+      memory_class: MemoryClass::Small,
+      data_dirs: vec![PathBuf::from("/data/a"), PathBuf::from("/mnt/b/")],
+    };
+    let files = [
+      "data/a/part-0.csv",
+      "data/a/nested/part-1.csv",
+      "data/ab/part-2.csv",
+      "mnt/b/part-3.csv",
+      "data/part-4.csv",
+    ];
+    assert_eq!(tags.local_files(&files), 3);
+  }
+
+  #[test]
+  fn parses_memory_class() {
+    assert_eq!("large".parse(), Ok(MemoryClass::Large));
+    assert!("huge".parse::<MemoryClass>().is_err());
+    assert!(MemoryClass::Small < MemoryClass::Large);
+  }
+}
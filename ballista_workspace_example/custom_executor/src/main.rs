@@ -15,9 +15,16 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::sync::Arc;
+use std::{env, sync::Arc, time::Duration};
 
-use ballista_core::object_store::{runtime_env_with_s3_support, session_config_with_s3_support};
+use ballista_core::{
+  config::TaskSchedulingPolicy,
+  error::BallistaError,
+  object_store::{runtime_env_with_s3_support, session_config_with_s3_support},
+};
+use ballista_examples::placement::{
+  Advertisement, DEFAULT_PLACEMENT_PORT, ExecutorTags, MemoryClass, advertise,
+};
 use ballista_executor::executor_process::{ExecutorProcessConfig, start_executor_process};
 /// # Custom Ballista Executor
 ///
@@ -29,6 +36,8 @@ async fn main() -> ballista_core::error::Result<()> {
     .is_test(true)
     .try_init();
 
+  let host = env::var("EXECUTOR_HOST").unwrap_or_else(|_| "localhost".to_string());
+
   let config: ExecutorProcessConfig = ExecutorProcessConfig {
     // overriding default config producer with custom producer
     // which has required S3 configuration options
@@ -36,8 +45,37 @@ async fn main() -> ballista_core::error::Result<()> {
     // overriding default runtime producer with custom producer
     // which knows how to create S3 connections
     override_runtime_producer: Some(Arc::new(runtime_env_with_s3_support)),
+    // the scheduler advertisement below is matched on this host
+    external_host: Some(host.clone()),
+    // the scheduler's distribution policy only applies to pushed tasks
+    task_scheduling_policy: TaskSchedulingPolicy::PushStaged,
     ..Default::default()
   };
 
+  // tags the scheduler places tasks by, e.g.
+  // EXECUTOR_MEMORY_CLASS=large EXECUTOR_DATA_DIRS=/data/a:/data/b
+  let memory_class = match env::var("EXECUTOR_MEMORY_CLASS") {
+    Ok(class) => class.parse().map_err(BallistaError::Configuration)?,
+    Err(_) => MemoryClass::Medium,
+  };
+  let data_dirs = env::var_os("EXECUTOR_DATA_DIRS")
+    .map(|dirs| env::split_paths(&dirs).collect())
+    .unwrap_or_default();
+  let placement_url = env::var("PLACEMENT_URL")
+    .unwrap_or_else(|_| format!("http://{}:{DEFAULT_PLACEMENT_PORT}", config.scheduler_host));
+  let advertisement = Advertisement {
+    host,
+    port: config.port,
+    tags: ExecutorTags {
+      memory_class,
+      data_dirs,
+    },
+  };
+  tokio::spawn(advertise(
+    placement_url,
+    advertisement,
+    Duration::from_secs(30),
+  ));
+
   start_executor_process(Arc::new(config)).await
 }
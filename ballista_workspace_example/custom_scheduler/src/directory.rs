@@ -0,0 +1,290 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! What the scheduler knows about each executor beyond its registration:
+//! the tags it advertised and how the last scheduling rounds went.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::{self, Write},
+  sync::{Arc, Mutex, OnceLock, PoisonError, RwLock},
+};
+
+use ballista_core::serde::protobuf::AvailableTaskSlots;
+use ballista_examples::placement::{Advertisement, ExecutorTags, MemoryClass};
+use ballista_scheduler::cluster::ClusterState;
+
+/// An executor as the policy sees it during one scheduling round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Candidate {
+  pub(crate) executor_id: String,
+  pub(crate) endpoint: String,
+  pub(crate) tags: Option<ExecutorTags>,
+  pub(crate) task_slots: u32,
+  pub(crate) free_slots: u32,
+}
+
+impl Candidate {
+  pub(crate) fn memory_class(&self) -> Option<MemoryClass> {
+    self.tags.as_ref().map(|tags| tags.memory_class)
+  }
+}
+
+/// Outcome of one scheduling round, indexed like the candidates.
+#[derive(Debug)]
+pub(crate) struct Round {
+  pub(crate) queue_depth: Vec<usize>,
+  pub(crate) local_tasks: Vec<u64>,
+  pub(crate) remote_tasks: Vec<u64>,
+  /// Runnable tasks left unbound which no executor holds input files for.
+  pub(crate) unplaced: usize,
+}
+
+impl Round {
+  pub(crate) fn new(candidates: usize) -> Self {
+    Self {
+      queue_depth: vec![0; candidates],
+      local_tasks: vec![0; candidates],
+      remote_tasks: vec![0; candidates],
+      unplaced: 0,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutorStats {
+  pub endpoint: String,
+  pub memory_class: Option<MemoryClass>,
+  pub task_slots: u32,
+  pub available_slots: u32,
+  /// Runnable tasks waiting for a free slot whose input files are on this
+  /// executor.
+  pub queue_depth: usize,
+  /// Tasks bound to this executor that read files from its data dirs.
+  pub local_tasks: u64,
+  /// Tasks bound to this executor that read files held elsewhere, or held
+  /// by no executor.
+  pub remote_tasks: u64,
+}
+
+impl ExecutorStats {
+  pub fn slot_utilization(&self) -> f64 {
+    if self.task_slots == 0 {
+      return 0.0;
+    }
+    f64::from(self.task_slots.saturating_sub(self.available_slots)) / f64::from(self.task_slots)
+  }
+}
+
+#[derive(Default)]
+struct Stats {
+  executors: BTreeMap<String, ExecutorStats>,
+  unplaced: usize,
+}
+
+#[derive(Default)]
+pub struct ExecutorDirectory {
+  cluster: OnceLock<Arc<dyn ClusterState>>,
+  /// Tags by the `host:port` the executor registered with.
+  advertised: RwLock<HashMap<String, ExecutorTags>>,
+  stats: Mutex<Stats>,
+}
+
+impl fmt::Debug for ExecutorDirectory {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ExecutorDirectory")
+      .field("advertised", &self.advertised)
+      .finish_non_exhaustive()
+  }
+}
+
+impl ExecutorDirectory {
+  /// Registered executors are looked up in `cluster`. Only the first call
+  /// has an effect.
+  pub fn attach(&self, cluster: Arc<dyn ClusterState>) {
+    let _ = self.cluster.set(cluster);
+  }
+
+  pub fn advertise(&self, advertisement: Advertisement) {
+    log::info!(
+      "executor {} advertised {:?}",
+      advertisement.endpoint(),
+      advertisement.tags
+    );
+    self
+      .advertised
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+      .insert(advertisement.endpoint(), advertisement.tags);
+  }
+
+  /// Tags by the `host:port` they were advertised for.
+  pub fn advertised(&self) -> HashMap<String, ExecutorTags> {
+    self
+      .advertised
+      .read()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clone()
+  }
+
+  /// Registered executors which advertised tags, by executor id.
+  pub async fn tagged_executors(&self) -> BTreeMap<String, ExecutorTags> {
+    let Some(cluster) = self.cluster.get() else {
+      return BTreeMap::new();
+    };
+    let registered = cluster.registered_executor_metadata().await;
+    let advertised = self.advertised();
+    registered
+      .into_iter()
+      .filter_map(|metadata| {
+        let tags = advertised.get(&format!("{}:{}", metadata.host, metadata.port))?;
+        Some((metadata.id, tags.clone()))
+      })
+      .collect()
+  }
+
+  /// Every registered executor, with the free slots offered this round. An
+  /// executor the cluster offers no slots for is full.
+  pub(crate) async fn candidates(&self, slots: &[&mut AvailableTaskSlots]) -> Vec<Candidate> {
+    let registered = match self.cluster.get() {
+      Some(cluster) => cluster.registered_executor_metadata().await,
+      None => Vec::new(),
+    };
+    let advertised = self.advertised();
+    let mut candidates: Vec<Candidate> = registered
+      .into_iter()
+      .map(|metadata| {
+        let endpoint = format!("{}:{}", metadata.host, metadata.port);
+        Candidate {
+          tags: advertised.get(&endpoint).cloned(),
+          executor_id: metadata.id,
+          endpoint,
+          task_slots: metadata.specification.task_slots,
+          free_slots: 0,
+        }
+      })
+      .collect();
+    for slot in slots {
+      match candidates
+        .iter_mut()
+        .find(|candidate| candidate.executor_id == slot.executor_id)
+      {
+        Some(candidate) => candidate.free_slots = slot.slots,
+        None => candidates.push(Candidate {
+          executor_id: slot.executor_id.clone(),
+          endpoint: String::new(),
+          tags: None,
+          task_slots: slot.slots,
+          free_slots: slot.slots,
+        }),
+      }
+    }
+    candidates
+  }
+
+  pub(crate) fn record(&self, candidates: &[Candidate], round: &Round) {
+    let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+    stats.executors.retain(|id, _| {
+      candidates
+        .iter()
+        .any(|candidate| &candidate.executor_id == id)
+    });
+    for (i, candidate) in candidates.iter().enumerate() {
+      let executor = stats
+        .executors
+        .entry(candidate.executor_id.clone())
+        .or_default();
+      executor.endpoint = candidate.endpoint.clone();
+      executor.memory_class = candidate.memory_class();
+      executor.task_slots = candidate.task_slots;
+      executor.available_slots = candidate.free_slots;
+      executor.queue_depth = round.queue_depth[i];
+      executor.local_tasks += round.local_tasks[i];
+      executor.remote_tasks += round.remote_tasks[i];
+    }
+    stats.unplaced = round.unplaced;
+  }
+
+  /// Stats as of the last scheduling round, by executor id.
+  pub fn stats(&self) -> BTreeMap<String, ExecutorStats> {
+    self
+      .stats
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .executors
+      .clone()
+  }
+
+  /// The stats in the Prometheus text exposition format.
+  pub fn render_metrics(&self) -> String {
+    let stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut out = String::new();
+    let series: [(&str, &str, &str, fn(&ExecutorStats) -> f64); 5] = [
+      (
+        "ballista_executor_task_slots",
+        "gauge",
+        "Task slots the executor registered with.",
+        |s| f64::from(s.task_slots),
+      ),
+      (
+        "ballista_executor_slot_utilization",
+        "gauge",
+        "Share of task slots running tasks.",
+        ExecutorStats::slot_utilization,
+      ),
+      (
+        "ballista_executor_queue_depth",
+        "gauge",
+        "Runnable tasks waiting for a slot whose input files are on the executor.",
+        |s| s.queue_depth as f64,
+      ),
+      (
+        "ballista_executor_local_tasks_total",
+        "counter",
+        "Tasks bound to the executor holding their input files.",
+        |s| s.local_tasks as f64,
+      ),
+      (
+        "ballista_executor_remote_tasks_total",
+        "counter",
+        "Tasks with input files bound to an executor not holding them.",
+        |s| s.remote_tasks as f64,
+      ),
+    ];
+    for (name, kind, help, value) in series {
+      let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+      for (id, executor) in &stats.executors {
+        let memory_class = executor
+          .memory_class
+          .map_or_else(String::new, |class| class.to_string());
+        let _ = writeln!(
+          out,
+          "{name}{{executor_id=\"{id}\",endpoint=\"{}\",memory_class=\"{memory_class}\"}} {}",
+          executor.endpoint,
+          value(executor)
+        );
+      }
+    }
+    let _ = writeln!(
+      out,
+      "# HELP ballista_unplaced_tasks Runnable tasks waiting for a slot with no input files on \
+       any executor.\n# TYPE ballista_unplaced_tasks gauge\nballista_unplaced_tasks {}",
+      stats.unplaced
+    );
+    out
+  }
+}
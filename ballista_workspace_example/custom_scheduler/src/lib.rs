@@ -0,0 +1,59 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! A scheduler placing tasks on the executors holding their input files,
+//! see [`policy::LocalityPolicy`].
+
+pub mod directory;
+pub mod policy;
+pub mod server;
+
+use std::{net::AddrParseError, sync::Arc};
+
+use ballista_core::error::{BallistaError, Result};
+use ballista_scheduler::{
+  cluster::BallistaCluster,
+  config::{SchedulerConfig, TaskDistributionPolicy},
+  scheduler_process::start_server,
+};
+use tokio::net::TcpListener;
+
+use crate::{directory::ExecutorDirectory, policy::LocalityPolicy};
+
+/// Runs the scheduler described by `config` with the locality policy, and
+/// the placement endpoint on `placement`. Returns when either stops.
+pub async fn start_scheduler(
+  mut config: SchedulerConfig,
+  placement: TcpListener,
+  directory: Arc<ExecutorDirectory>,
+) -> Result<()> {
+  config.task_distribution =
+    TaskDistributionPolicy::Custom(Arc::new(LocalityPolicy::new(directory.clone())));
+
+  let addr = format!("{}:{}", config.bind_host, config.bind_port);
+  let addr = addr
+    .parse()
+    .map_err(|e: AddrParseError| BallistaError::Configuration(e.to_string()))?;
+
+  let cluster = BallistaCluster::new_from_config(&config).await?;
+  directory.attach(cluster.cluster_state());
+
+  tokio::select! {
+    result = start_server(cluster, addr, Arc::new(config)) => result,
+    result = server::serve(placement, directory) => result.map_err(BallistaError::IoError),
+  }
+}
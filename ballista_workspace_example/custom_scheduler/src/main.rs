@@ -15,15 +15,17 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::{net::AddrParseError, sync::Arc};
+use std::{env, sync::Arc};
 
 use ballista_core::{
+  config::TaskSchedulingPolicy,
   error::BallistaError,
   object_store::{session_config_with_s3_support, session_state_with_s3_support},
 };
-use ballista_scheduler::{
-  cluster::BallistaCluster, config::SchedulerConfig, scheduler_process::start_server,
-};
+use ballista_examples::placement::DEFAULT_PLACEMENT_PORT;
+use ballista_scheduler::config::SchedulerConfig;
+use custom_scheduler::{directory::ExecutorDirectory, start_scheduler};
+use tokio::net::TcpListener;
 
 /// # Custom Ballista Scheduler
 ///
@@ -42,16 +44,23 @@ async fn main() -> ballista_core::error::Result<()> {
     // overriding default session builder, which has custom session configuration
     // runtime environment and session state.
     override_session_builder: Some(Arc::new(session_state_with_s3_support)),
+    // the distribution policy only binds tasks pushed to executors,
+    // executors polling for work take whatever comes next
+    scheduling_policy: TaskSchedulingPolicy::PushStaged,
     ..Default::default()
   };
 
-  let addr = format!("{}:{}", config.bind_host, config.bind_port);
-  let addr = addr
-    .parse()
-    .map_err(|e: AddrParseError| BallistaError::Configuration(e.to_string()))?;
+  // executors advertise their tags (memory class, data dirs) here, and
+  // per executor slot utilization and queue depth are served as metrics
+  let placement_port = match env::var("PLACEMENT_PORT") {
+    Ok(port) => port
+      .parse()
+      .map_err(|_| BallistaError::Configuration(format!("invalid PLACEMENT_PORT `{port}`")))?,
+    Err(_) => DEFAULT_PLACEMENT_PORT,
+  };
+  let placement = TcpListener::bind((config.bind_host.as_str(), placement_port)).await?;
 
-  let cluster = BallistaCluster::new_from_config(&config).await?;
-  start_server(cluster, addr, Arc::new(config)).await?;
+  start_scheduler(config, placement, Arc::new(ExecutorDirectory::default())).await?;
 
   Ok(())
 }
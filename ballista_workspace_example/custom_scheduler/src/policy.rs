@@ -0,0 +1,282 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Locality aware task distribution. A task goes to the executor whose data
+//! dirs hold most of its input files when that executor has a free slot.
+//! Otherwise, and for tasks reading shuffle output, it goes to the executor
+//! with the largest memory class, then the most free slots.
+
+use std::{collections::HashMap, sync::Arc};
+
+use ballista::datafusion::{
+  datasource::{physical_plan::FileScanConfig, source::DataSourceExec},
+  error::Result,
+  physical_plan::ExecutionPlan,
+};
+use ballista_core::serde::{
+  protobuf::{AvailableTaskSlots, job_status},
+  scheduler::PartitionId,
+};
+use ballista_scheduler::{
+  cluster::{BoundTask, DistributionPolicy},
+  state::{
+    execution_graph::{TaskDescription, create_task_info},
+    task_manager::JobInfoCache,
+  },
+};
+
+use crate::directory::{Candidate, ExecutorDirectory, Round};
+
+#[derive(Debug)]
+pub struct LocalityPolicy {
+  directory: Arc<ExecutorDirectory>,
+}
+
+impl LocalityPolicy {
+  pub fn new(directory: Arc<ExecutorDirectory>) -> Self {
+    Self { directory }
+  }
+}
+
+#[async_trait::async_trait]
+impl DistributionPolicy for LocalityPolicy {
+  async fn bind_tasks(
+    &self,
+    mut slots: Vec<&mut AvailableTaskSlots>,
+    running_jobs: Arc<HashMap<String, JobInfoCache>>,
+  ) -> Result<Vec<BoundTask>> {
+    let mut candidates = self.directory.candidates(&slots).await;
+    let mut round = Round::new(candidates.len());
+    let mut bound = Vec::new();
+
+    for (job_id, job_info) in running_jobs.iter() {
+      if !matches!(job_info.status, Some(job_status::Status::Running(_))) {
+        continue;
+      }
+      let mut graph = job_info.execution_graph.write().await;
+      let session_id = graph.session_id().to_string();
+      // Every stage is visited once, its tasks left unbound stay queued.
+      let mut visited = Vec::new();
+      while let Some((stage, task_id_gen)) = graph.fetch_running_stage(&visited) {
+        visited.push(stage.stage_id);
+        let input_files = input_files(&stage.plan, stage.task_infos.len());
+        for (partition_id, task_info) in stage.task_infos.iter_mut().enumerate() {
+          if task_info.is_some() {
+            continue;
+          }
+          let files = input_files
+            .as_ref()
+            .map_or(&[][..], |files| files[partition_id].as_slice());
+          let placement = place(&candidates, files);
+          let Some(executor) = placement.executor else {
+            match placement.preferred {
+              Some(preferred) => round.queue_depth[preferred] += 1,
+              None => round.unplaced += 1,
+            }
+            continue;
+          };
+          if placement.preferred == Some(executor) {
+            round.local_tasks[executor] += 1;
+          } else if !files.is_empty() {
+            round.remote_tasks[executor] += 1;
+          }
+          candidates[executor].free_slots -= 1;
+
+          let executor_id = candidates[executor].executor_id.clone();
+          let task_id = *task_id_gen;
+          *task_id_gen += 1;
+          *task_info = Some(create_task_info(executor_id.clone(), task_id));
+          let task = TaskDescription {
+            session_id: session_id.clone(),
+            partition: PartitionId {
+              job_id: job_id.clone(),
+              stage_id: stage.stage_id,
+              partition_id,
+            },
+            stage_attempt_num: stage.stage_attempt_num,
+            task_id,
+            task_attempt: stage.task_failure_numbers[partition_id],
+            plan: stage.plan.clone(),
+            session_config: stage.session_config.clone(),
+          };
+          bound.push((executor_id, task));
+        }
+      }
+    }
+
+    for slot in slots.iter_mut() {
+      if let Some(candidate) = candidates
+        .iter()
+        .find(|candidate| candidate.executor_id == slot.executor_id)
+      {
+        slot.slots = candidate.free_slots;
+      }
+    }
+    self.directory.record(&candidates, &round);
+    Ok(bound)
+  }
+
+  fn name(&self) -> &str {
+    "locality"
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Placement {
+  /// The executor holding most of the task's input files.
+  preferred: Option<usize>,
+  /// Where the task goes, `None` when no executor has a free slot.
+  executor: Option<usize>,
+}
+
+fn place(candidates: &[Candidate], files: &[String]) -> Placement {
+  let preferred = candidates
+    .iter()
+    .enumerate()
+    .filter_map(|(i, candidate)| {
+      let local = candidate.tags.as_ref()?.local_files(files);
+      (local > 0).then_some((i, local))
+    })
+    // Ties go to the first executor, so placement is stable across rounds.
+    .max_by_key(|&(i, local)| (local, std::cmp::Reverse(i)))
+    .map(|(i, _)| i);
+  let executor = preferred
+    .filter(|&i| candidates[i].free_slots > 0)
+    .or_else(|| {
+      candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.free_slots > 0)
+        .max_by_key(|(_, candidate)| (candidate.memory_class(), candidate.free_slots))
+        .map(|(i, _)| i)
+    });
+  Placement {
+    preferred,
+    executor,
+  }
+}
+
+/// The files each partition of a stage scans, `None` when the stage scans
+/// no files or its scans are partitioned differently from the stage.
+fn input_files(plan: &Arc<dyn ExecutionPlan>, partitions: usize) -> Option<Vec<Vec<String>>> {
+  let mut files = vec![Vec::new(); partitions];
+  let mut scans = 0;
+  let mut pending = vec![plan];
+  while let Some(plan) = pending.pop() {
+    pending.extend(plan.children());
+    let Some(config) = plan
+      .as_any()
+      .downcast_ref::<DataSourceExec>()
+      .and_then(|exec| exec.data_source().as_any().downcast_ref::<FileScanConfig>())
+    else {
+      continue;
+    };
+    if config.file_groups.len() != partitions {
+      return None;
+    }
+    scans += 1;
+    for (partition, group) in config.file_groups.iter().enumerate() {
+      files[partition].extend(
+        group
+          .files()
+          .iter()
+          .map(|file| file.object_meta.location.to_string()),
+      );
+    }
+  }
+  (scans > 0).then_some(files)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use ballista_examples::placement::{ExecutorTags, MemoryClass};
+
+  use super::*;
+
+  fn candidate(data_dir: &str, memory_class: MemoryClass, free_slots: u32) -> Candidate {
+    Candidate {
+      executor_id: data_dir.to_string(),
+      endpoint: String::new(),
+      tags: Some(ExecutorTags {
+        memory_class,
+        data_dirs: vec![PathBuf::from(data_dir)],
+      }),
+      task_slots: 4,
+      free_slots,
+    }
+  }
+
+  fn files(files: &[&str]) -> Vec<String> {
+    files.iter().map(|file| file.to_string()).collect()
+  }
+
+  #[test]
+  fn prefers_executor_holding_input_files() {
+    let candidates = [
+      candidate("/data/a", MemoryClass::Large, 4),
+      candidate("/data/b", MemoryClass::Small, 1),
+    ];
+    assert_eq!(
+      place(
+        &candidates,
+        &files(&["data/b/0.csv", "data/b/1.csv", "data/a/2.csv"])
+      ),
+      Placement {
+        preferred: Some(1),
+        executor: Some(1),
+      }
+    );
+  }
+
+  #[test]
+  fn falls_back_to_largest_memory_class() {
+    let candidates = [
+      candidate("/data/a", MemoryClass::Small, 4),
+      candidate("/data/b", MemoryClass::Large, 1),
+      candidate("/data/c", MemoryClass::Medium, 2),
+    ];
+    // Shuffle reading tasks have no input files.
+    assert_eq!(
+      place(&candidates, &[]),
+      Placement {
+        preferred: None,
+        executor: Some(1),
+      }
+    );
+    // The executor holding the files is full.
+    let mut full = candidates.clone();
+    full[0].free_slots = 0;
+    full[1].free_slots = 0;
+    assert_eq!(
+      place(&full, &files(&["data/a/0.csv"])),
+      Placement {
+        preferred: Some(0),
+        executor: Some(2),
+      }
+    );
+    full[2].free_slots = 0;
+    assert_eq!(
+      place(&full, &files(&["data/a/0.csv"])),
+      Placement {
+        preferred: Some(0),
+        executor: None,
+      }
+    );
+  }
+}
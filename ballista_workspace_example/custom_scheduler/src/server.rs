@@ -0,0 +1,59 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! HTTP endpoint executors advertise their tags to, which also serves the
+//! per executor metrics.
+
+use std::{io, sync::Arc};
+
+use axum::{
+  Json, Router,
+  extract::State,
+  http::{StatusCode, header},
+  response::IntoResponse,
+  routing::{get, put},
+};
+use ballista_examples::placement::{Advertisement, EXECUTORS_PATH, METRICS_PATH};
+use tokio::net::TcpListener;
+
+use crate::directory::ExecutorDirectory;
+
+pub fn router(directory: Arc<ExecutorDirectory>) -> Router {
+  Router::new()
+    .route(EXECUTORS_PATH, put(advertise))
+    .route(METRICS_PATH, get(metrics))
+    .with_state(directory)
+}
+
+pub async fn serve(listener: TcpListener, directory: Arc<ExecutorDirectory>) -> io::Result<()> {
+  axum::serve(listener, router(directory)).await
+}
+
+async fn advertise(
+  State(directory): State<Arc<ExecutorDirectory>>,
+  Json(advertisement): Json<Advertisement>,
+) -> StatusCode {
+  directory.advertise(advertisement);
+  StatusCode::NO_CONTENT
+}
+
+async fn metrics(State(directory): State<Arc<ExecutorDirectory>>) -> impl IntoResponse {
+  (
+    [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+    directory.render_metrics(),
+  )
+}
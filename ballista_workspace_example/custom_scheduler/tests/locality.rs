@@ -0,0 +1,187 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! # Locality Aware Placement
+//!
+//! Runs the scheduler and two executors in process. Each executor holds
+//! half of a table's files in its data dir, and every scan task is expected
+//! to run on the executor holding its file.
+
+use std::{net::TcpListener as StdTcpListener, path::Path, sync::Arc, time::Duration};
+
+use ballista::{
+  datafusion::{
+    assert_batches_eq,
+    datasource::{
+      file_format::csv::CsvFormat,
+      listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+    },
+    error::Result,
+    execution::SessionStateBuilder,
+    prelude::{SessionConfig, SessionContext},
+  },
+  prelude::*,
+};
+use ballista_core::config::TaskSchedulingPolicy;
+use ballista_examples::placement::{Advertisement, ExecutorTags, MemoryClass, advertise};
+use ballista_executor::executor_process::{ExecutorProcessConfig, start_executor_process};
+use ballista_scheduler::config::SchedulerConfig;
+use custom_scheduler::{directory::ExecutorDirectory, start_scheduler};
+use tokio::net::TcpListener;
+
+fn free_port() -> u16 {
+  StdTcpListener::bind("127.0.0.1:0")
+    .and_then(|listener| listener.local_addr())
+    .expect("free port")
+    .port()
+}
+
+fn write_files(dir: &Path, name: &str, first: i64) {
+  for part in 0 .. 2 {
+    let id = first + 2 * part;
+    std::fs::write(
+      dir.join(format!("{name}-{part}.csv")),
+      format!("id,v\n{id},{}\n{},{}\n", id * 10, id + 1, (id + 1) * 10),
+    )
+    .expect("test data written");
+  }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn should_run_scan_tasks_on_executors_holding_their_files() -> Result<()> {
+  let data_dirs = [tempfile::tempdir()?, tempfile::tempdir()?];
+  write_files(data_dirs[0].path(), "a", 1);
+  write_files(data_dirs[1].path(), "b", 5);
+
+  let scheduler_port = free_port();
+  let placement = TcpListener::bind("127.0.0.1:0").await?;
+  let placement_url = format!("http://{}", placement.local_addr()?);
+  let directory = Arc::new(ExecutorDirectory::default());
+  let config = SchedulerConfig {
+    bind_host: "127.0.0.1".to_string(),
+    bind_port: scheduler_port,
+    scheduling_policy: TaskSchedulingPolicy::PushStaged,
+    ..Default::default()
+  };
+  tokio::spawn(start_scheduler(config, placement, directory.clone()));
+
+  let work_dirs = [tempfile::tempdir()?, tempfile::tempdir()?];
+  for (i, (data_dir, work_dir)) in data_dirs.iter().zip(&work_dirs).enumerate() {
+    let port = free_port();
+    let config = ExecutorProcessConfig {
+      bind_host: "127.0.0.1".to_string(),
+      external_host: Some("localhost".to_string()),
+      port,
+      grpc_port: free_port(),
+      scheduler_host: "localhost".to_string(),
+      scheduler_port,
+      concurrent_tasks: 2,
+      task_scheduling_policy: TaskSchedulingPolicy::PushStaged,
+      work_dir: Some(work_dir.path().display().to_string()),
+      ..Default::default()
+    };
+    tokio::spawn(start_executor_process(Arc::new(config)));
+    tokio::spawn(advertise(
+      placement_url.clone(),
+      Advertisement {
+        host: "localhost".to_string(),
+        port,
+        tags: ExecutorTags {
+          memory_class: if i == 0 {
+            MemoryClass::Large
+          } else {
+            MemoryClass::Small
+          },
+          data_dirs: vec![data_dir.path().to_path_buf()],
+        },
+      },
+      Duration::from_millis(200),
+    ));
+  }
+
+  let mut retry = 100;
+  while directory.tagged_executors().await.len() < 2 {
+    assert!(
+      retry > 0,
+      "executors did not register and advertise their tags"
+    );
+    retry -= 1;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+
+  let config = SessionConfig::new_with_ballista().with_target_partitions(4);
+  let state = SessionStateBuilder::new()
+    .with_config(config)
+    .with_default_features()
+    .build();
+  let ctx =
+    SessionContext::remote_with_state(&format!("df://localhost:{scheduler_port}"), state).await?;
+
+  let table_paths = data_dirs
+    .iter()
+    .map(|dir| ListingTableUrl::parse(format!("{}/", dir.path().display())))
+    .collect::<Result<Vec<_>>>()?;
+  let options = ListingOptions::new(Arc::new(CsvFormat::default())).with_file_extension(".csv");
+  let table_config = ListingTableConfig::new_with_multi_paths(table_paths)
+    .with_listing_options(options)
+    .infer_schema(&ctx.state())
+    .await?;
+  ctx.register_table("t", Arc::new(ListingTable::try_new(table_config)?))?;
+
+  let result = ctx
+    .sql("SELECT count(*) AS n, sum(v) AS total FROM t")
+    .await?
+    .collect()
+    .await?;
+  let expected = [
+    "+---+-------+",
+    "| n | total |",
+    "+---+-------+",
+    "| 8 | 360   |",
+    "+---+-------+",
+  ];
+  assert_batches_eq!(expected, &result);
+
+  let stats = directory.stats();
+  for executor_id in directory.tagged_executors().await.keys() {
+    let executor = &stats[executor_id];
+    assert_eq!(executor.local_tasks, 2, "{executor_id}: {executor:?}");
+    assert_eq!(executor.remote_tasks, 0, "{executor_id}: {executor:?}");
+  }
+
+  let metrics = reqwest::get(format!("{placement_url}/metrics"))
+    .await
+    .and_then(reqwest::Response::error_for_status)
+    .expect("metrics served")
+    .text()
+    .await
+    .expect("metrics body");
+  assert!(metrics.contains("ballista_executor_slot_utilization{"));
+  assert!(metrics.contains("ballista_executor_queue_depth{"));
+
+  Ok(())
+}
+
+#[ctor::ctor]
+fn init() {
+  // Enable RUST_LOG logging configuration for test
+  let _ = env_logger::builder()
+    .filter_level(log::LevelFilter::Info)
+    .parse_filters("ballista=debug,ballista_scheduler=debug,ballista_executor=debug")
+    .is_test(true)
+    .try_init();
+}
@@ -1,4 +1,5 @@
 mod net;
+mod prediction;
 pub mod protocol;
 mod replication;
 
@@ -12,9 +13,11 @@ use bevy::{
   camera::ScalingMode, prelude::*, window::PresentMode, world_serialization::WorldInstanceReady,
 };
 use bevy_hanabi::prelude::*;
+use game_shared::movement::INPUT_STEP_SECONDS;
 
 use crate::{
   net::NetworkClient,
+  prediction::ClientPrediction,
   protocol::{
     ActorKind, ActorState, ClientEnvelope, MapState, ObstacleShape, ObstacleState, PlayerInput,
     client_envelope,
@@ -28,7 +31,6 @@ const WALL_THICKNESS: f32 = 20.0;
 const WALL_HEIGHT: f32 = 48.0;
 const PLAYER_SIZE: Vec3 = Vec3::new(28.0, 36.0, 28.0);
 const MONSTER_SIZE: Vec3 = Vec3::new(24.0, 32.0, 24.0);
-const INPUT_SEND_SECONDS: f32 = 1.0 / 30.0;
 const FOX_GLTF_PATH: &str = "models/Fox.glb";
 const CAMERA_VIEW_WIDTH: f32 = 1080.0;
 const CAMERA_VIEW_HEIGHT: f32 = 820.0;
@@ -64,7 +66,7 @@ struct InputSendClock(Timer);
 impl Default for InputSendClock {
   fn default() -> Self {
     Self(Timer::from_seconds(
-      INPUT_SEND_SECONDS,
+      INPUT_STEP_SECONDS,
       TimerMode::Repeating,
     ))
   }
@@ -157,20 +159,22 @@ pub fn run() {
     .add_plugins(HanabiPlugin)
     .insert_resource(Gravity::ZERO)
     .add_plugins(lightyear::prelude::client::ClientPlugins {
-      tick_duration: Duration::from_secs_f64(INPUT_SEND_SECONDS as f64),
+      tick_duration: Duration::from_secs_f32(INPUT_STEP_SECONDS),
     })
     .add_plugins(protocol::GameProtocolPlugin)
     .add_plugins(replication::GameReplicationPlugin)
     .init_resource::<ClientWorld>()
     .init_resource::<InputSendClock>()
+    .init_resource::<ClientPrediction>()
     .add_systems(Startup, (setup_scene, net::start_network_client))
     .add_systems(
       Update,
       (
         net::drain_network_events,
         send_player_input.after(net::drain_network_events),
+        prediction::advance_prediction.after(send_player_input),
         sync_obstacle_meshes.after(net::drain_network_events),
-        sync_actor_meshes.after(net::drain_network_events),
+        sync_actor_meshes.after(prediction::advance_prediction),
         sync_actor_animations.after(sync_actor_meshes),
         cleanup_timed_effects,
         update_status_text.after(net::drain_network_events),
@@ -438,6 +442,8 @@ fn send_player_input(
   keyboard: Res<ButtonInput<KeyCode>>,
   time: Res<Time>,
   mut clock: ResMut<InputSendClock>,
+  mut prediction: ResMut<ClientPrediction>,
+  world: Res<ClientWorld>,
   network: Option<ResMut<NetworkClient>>,
 ) {
   let Some(mut network) = network else {
@@ -454,12 +460,20 @@ fn send_player_input(
 
   let direction = input_direction(&keyboard);
   network.sequence += 1;
+  prediction.predict_input(network.sequence, direction);
+  let attack = keyboard
+    .pressed(KeyCode::Space)
+    .then(|| prediction.attack_target(network.sequence, world.actors.values()))
+    .flatten();
+  let (attack_target, view_tick) = attack.unwrap_or_default();
   let envelope = ClientEnvelope {
     payload: Some(client_envelope::Payload::Input(PlayerInput {
       sequence: network.sequence,
       x: direction.x,
       y: direction.y,
       z: direction.z,
+      attack_target,
+      view_tick,
     })),
   };
   let _ = network.sender.send(envelope);
@@ -529,6 +543,7 @@ fn sync_obstacle_meshes(
 fn sync_actor_meshes(
   mut commands: Commands,
   world: Res<ClientWorld>,
+  prediction: Res<ClientPrediction>,
   asset_server: Res<AssetServer>,
   vfx_assets: Res<VfxAssets>,
   mut actors: Query<(
@@ -550,7 +565,7 @@ fn sync_actor_meshes(
 
     rendered_ids.insert(remote_actor.id);
     apply_actor_visual(
-      actor,
+      &prediction.render_state(actor, world.local_actor_id),
       &mut transform,
       physics_position,
       &mut visual_state,
@@ -564,11 +579,20 @@ fn sync_actor_meshes(
       continue;
     }
 
-    spawn_animated_actor(&mut commands, &asset_server, &vfx_assets, actor);
+    spawn_animated_actor(
+      &mut commands,
+      &asset_server,
+      &vfx_assets,
+      &prediction.render_state(actor, world.local_actor_id),
+    );
   }
 }
 
-fn update_status_text(world: Res<ClientWorld>, mut text_query: Query<&mut Text, With<StatusText>>) {
+fn update_status_text(
+  world: Res<ClientWorld>,
+  prediction: Res<ClientPrediction>,
+  mut text_query: Query<&mut Text, With<StatusText>>,
+) {
   for mut text in &mut text_query {
     let player = world
       .local_actor_id
@@ -579,13 +603,14 @@ fn update_status_text(world: Res<ClientWorld>, mut text_query: Query<&mut Text,
       .map_or("loading", |map| map.name.as_str());
     let obstacles = world.map.as_ref().map_or(0, |map| map.obstacles.len());
     text.0 = format!(
-      "{} | map {} | tick {} | local actor {} | actors {} | obstacles {}",
+      "{} | map {} | tick {} | local actor {} | actors {} | obstacles {} | {}",
       world.status,
       map,
       world.tick,
       player,
       world.actors.len(),
-      obstacles
+      obstacles,
+      prediction.status()
     );
   }
 }
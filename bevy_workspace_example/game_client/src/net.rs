@@ -13,6 +13,7 @@ use tokio::sync::mpsc;
 
 use crate::{
   ClientWorld,
+  prediction::ClientPrediction,
   protocol::{
     ClientEnvelope, ClientPacket, DEFAULT_SERVER_ADDR, GameChannel, Hello, NETCODE_PRIVATE_KEY,
     NETCODE_PROTOCOL_ID, ServerEnvelope, ServerPacket, client_envelope, decode_server_packet,
@@ -81,6 +82,7 @@ pub(crate) fn drain_network_events(
   mut commands: ResMut<NetworkCommands>,
   mut network: ResMut<NetworkClient>,
   mut world: ResMut<ClientWorld>,
+  mut prediction: ResMut<ClientPrediction>,
   mut client_query: Query<
     (
       &mut MessageSender<ClientPacket>,
@@ -97,7 +99,7 @@ pub(crate) fn drain_network_events(
 
   for packet in receiver.receive() {
     match decode_server_packet(packet) {
-      Ok(message) => handle_server_message(message, &mut world),
+      Ok(message) => handle_server_message(message, &mut world, &mut prediction),
       Err(err) => world.status = format!("decode error: {err:#}"),
     }
   }
@@ -143,7 +145,11 @@ fn send_hello(sender: &mut MessageSender<ClientPacket>, world: &mut ClientWorld)
   }
 }
 
-fn handle_server_message(message: ServerEnvelope, world: &mut ClientWorld) {
+fn handle_server_message(
+  message: ServerEnvelope,
+  world: &mut ClientWorld,
+  prediction: &mut ClientPrediction,
+) {
   match message.payload {
     Some(server_envelope::Payload::Welcome(welcome)) => {
       world.local_actor_id = Some(welcome.actor_id);
@@ -152,6 +158,7 @@ fn handle_server_message(message: ServerEnvelope, world: &mut ClientWorld) {
     }
     Some(server_envelope::Payload::Snapshot(snapshot)) => {
       world.tick = snapshot.tick;
+      prediction.apply_snapshot(&snapshot, world.local_actor_id);
       if let Some(map) = snapshot.map {
         world.map = Some(map);
       }
@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+use game_shared::{
+  movement::{
+    MovementMap, PLAYER_ATTACK_COOLDOWN_INPUTS, PLAYER_ATTACK_RANGE, horizontal_distance,
+  },
+  prediction::{PredictedPlayer, ServerClock, SnapshotInterpolator},
+};
+
+use crate::protocol::{ActorKind, ActorState, WorldSnapshot};
+
+/// Predicted local player plus interpolation state for everything else.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct ClientPrediction {
+  movement: Option<MovementMap>,
+  local: Option<PredictedPlayer>,
+  remote: SnapshotInterpolator,
+  clock: ServerClock,
+  last_attack_sequence: Option<u64>,
+}
+
+impl ClientPrediction {
+  pub(crate) fn apply_snapshot(&mut self, snapshot: &WorldSnapshot, local_actor_id: Option<u64>) {
+    if let Some(map) = &snapshot.map {
+      self.movement = Some(MovementMap::from_map_state(map));
+    }
+    self.clock.observe(snapshot.tick);
+    self.remote.push_snapshot(
+      snapshot.tick,
+      snapshot
+        .actors
+        .iter()
+        .filter(|actor| Some(actor.id) != local_actor_id),
+    );
+
+    let Some(actor) =
+      local_actor_id.and_then(|id| snapshot.actors.iter().find(|actor| actor.id == id))
+    else {
+      self.local = None;
+      return;
+    };
+    if let (Some(predicted), Some(movement)) = (&mut self.local, &self.movement) {
+      predicted.reconcile(movement, actor);
+    } else {
+      self.local = Some(PredictedPlayer::new(actor));
+    }
+  }
+
+  /// Applies an input the moment it is sent instead of waiting a round trip.
+  pub(crate) fn predict_input(&mut self, sequence: u64, direction: Vec3) {
+    if let (Some(predicted), Some(movement)) = (&mut self.local, &self.movement) {
+      predicted.apply_input(movement, sequence, direction);
+    }
+  }
+
+  /// Picks the nearest live monster in range, as drawn on screen, and returns
+  /// it with the server tick it is drawn at so the server can rewind to it.
+  pub(crate) fn attack_target<'a>(
+    &mut self,
+    sequence: u64,
+    actors: impl IntoIterator<Item = &'a ActorState>,
+  ) -> Option<(u64, u64)> {
+    if self
+      .last_attack_sequence
+      .is_some_and(|last| sequence < last + PLAYER_ATTACK_COOLDOWN_INPUTS)
+    {
+      return None;
+    }
+    let origin = self.local.as_ref()?.render_position();
+    let view_tick = self.clock.interpolation_tick().round().max(0.0) as u64;
+
+    let target = actors
+      .into_iter()
+      .filter(|actor| actor.kind == ActorKind::Monster as i32 && actor.blue > 0)
+      .filter_map(|actor| {
+        let position = self
+          .remote
+          .sample(actor.id, self.clock.interpolation_tick())?;
+        Some((actor.id, horizontal_distance(origin, position)))
+      })
+      .filter(|(_, distance)| *distance <= PLAYER_ATTACK_RANGE)
+      .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    self.last_attack_sequence = Some(sequence);
+    Some((target.0, view_tick))
+  }
+
+  /// `actor` moved to where it should be drawn this frame.
+  pub(crate) fn render_state(&self, actor: &ActorState, local_actor_id: Option<u64>) -> ActorState {
+    let position = if Some(actor.id) == local_actor_id {
+      self.local.as_ref().map(PredictedPlayer::render_position)
+    } else {
+      self
+        .remote
+        .sample(actor.id, self.clock.interpolation_tick())
+    };
+
+    let mut actor = *actor;
+    if let Some(position) = position {
+      actor.x = position.x;
+      actor.y = position.y;
+      actor.z = position.z;
+    }
+    actor
+  }
+
+  pub(crate) fn status(&self) -> String {
+    self.local.as_ref().map_or_else(
+      || "prediction idle".to_string(),
+      |predicted| {
+        format!(
+          "unacked inputs {} | corrections {}",
+          predicted.pending_inputs(),
+          predicted.corrections()
+        )
+      },
+    )
+  }
+}
+
+pub(crate) fn advance_prediction(time: Res<Time>, mut prediction: ResMut<ClientPrediction>) {
+  prediction.clock.advance(time.delta_secs_f64());
+  if let Some(predicted) = &mut prediction.local {
+    predicted.smooth(time.delta_secs());
+  }
+}
@@ -0,0 +1,305 @@
+//! Validation of what clients claim: input rate, movement and attacks.
+//!
+//! Gameplay systems only push [`Violation`]s into the [`ViolationLog`];
+//! [`report_violations`] scores them per player, forwards them to the player
+//! registry and queues moderation once a player crosses the configured
+//! thresholds.
+
+use std::{collections::HashMap, env};
+
+use bevy::prelude::*;
+use game_shared::movement::{INPUT_STEP_SECONDS, PLAYER_SPEED};
+
+use crate::{
+  game::{ActorMapping, ServerTick},
+  player_registry::{ModerationRequest, PlayerServerRegistry, PlayerViolationRecord},
+  terrain::TerrainMap,
+};
+
+/// How far (in inputs) a client may run ahead of server time, which absorbs
+/// network bursts after a stall.
+const INPUT_SLACK_TICKS: u64 = 30;
+const INPUT_REBASE_TICKS: u64 = 150;
+const INPUT_REBASE_TOLERANCE: u64 = 3;
+const MOVEMENT_TOLERANCE: f32 = 0.01;
+/// Repeats of the same violation within this window are folded into one report.
+const REPORT_WINDOW_TICKS: u64 = 30;
+const DEFAULT_REVIEW_SCORE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ViolationKind {
+  /// More inputs than real time allows, i.e. a speed hack.
+  InputFlood,
+  /// Direction outside the unit circle, unknown target or similar.
+  MalformedInput,
+  /// Moved further in one tick than the player's speed allows.
+  SpeedHack,
+  /// Ended a tick inside terrain.
+  TerrainClip,
+  /// Attacked again before the cooldown elapsed.
+  AttackRate,
+  /// Claimed to be viewing a tick the server has not simulated yet.
+  FutureViewTick,
+  /// Hit claim far out of range even after rewinding the target.
+  ImpossibleHit,
+}
+
+impl ViolationKind {
+  pub(crate) fn as_str(self) -> &'static str {
+    match self {
+      Self::InputFlood => "input_flood",
+      Self::MalformedInput => "malformed_input",
+      Self::SpeedHack => "speed_hack",
+      Self::TerrainClip => "terrain_clip",
+      Self::AttackRate => "attack_rate",
+      Self::FutureViewTick => "future_view_tick",
+      Self::ImpossibleHit => "impossible_hit",
+    }
+  }
+
+  /// Contribution to the player's score; things an honest client on a bad
+  /// connection can trigger weigh less.
+  fn weight(self) -> u32 {
+    match self {
+      Self::InputFlood | Self::AttackRate => 2,
+      Self::MalformedInput | Self::FutureViewTick | Self::ImpossibleHit => 4,
+      Self::SpeedHack | Self::TerrainClip => 5,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Violation {
+  pub(crate) client_id: u64,
+  pub(crate) kind: ViolationKind,
+  pub(crate) tick: u64,
+  pub(crate) occurrences: u32,
+  pub(crate) detail: String,
+}
+
+/// Violations recorded this tick, waiting for [`report_violations`].
+#[derive(Resource, Debug, Default)]
+pub(crate) struct ViolationLog {
+  pending: Vec<Violation>,
+}
+
+impl ViolationLog {
+  pub(crate) fn record(
+    &mut self,
+    client_id: u64,
+    kind: ViolationKind,
+    tick: u64,
+    detail: impl Into<String>,
+  ) {
+    self.pending.push(Violation {
+      client_id,
+      kind,
+      tick,
+      occurrences: 1,
+      detail: detail.into(),
+    });
+  }
+}
+
+/// Per-session violation tally, kept on the player entity.
+#[derive(Component, Debug, Default, Clone, PartialEq)]
+pub(crate) struct PlayerViolations {
+  counts: HashMap<ViolationKind, u32>,
+  unreported: HashMap<ViolationKind, Violation>,
+  last_reported: HashMap<ViolationKind, u64>,
+  pub(crate) score: u32,
+  pub(crate) flagged: bool,
+  pub(crate) kicked: bool,
+}
+
+impl PlayerViolations {
+  pub(crate) fn count(&self, kind: ViolationKind) -> u32 {
+    self.counts.get(&kind).copied().unwrap_or_default()
+  }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub(crate) struct AnticheatPolicy {
+  /// Score at which a player is flagged for operator review.
+  pub(crate) review_score: u32,
+  /// Score at which a player is kicked; `None` leaves kicking to operators.
+  pub(crate) kick_score: Option<u32>,
+}
+
+impl Default for AnticheatPolicy {
+  fn default() -> Self {
+    Self {
+      review_score: env_u32("ANTICHEAT_REVIEW_SCORE")
+        .filter(|score| *score > 0)
+        .unwrap_or(DEFAULT_REVIEW_SCORE),
+      kick_score: env_u32("ANTICHEAT_KICK_SCORE").filter(|score| *score > 0),
+    }
+  }
+}
+
+/// Kicks and review flags waiting to be applied by the network layer, whether
+/// they came from an operator or from [`AnticheatPolicy`].
+#[derive(Resource, Debug, Default)]
+pub(crate) struct ModerationQueue {
+  pub(crate) pending: Vec<ModerationRequest>,
+}
+
+/// Rejects inputs whose sequence numbers run ahead of server time: an honest
+/// client sends exactly one input per tick, so anything beyond the slack is
+/// extra movement.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct InputRate {
+  baseline: Option<(u64, u64)>,
+}
+
+impl InputRate {
+  pub(crate) fn admit(&mut self, tick: u64, sequence: u64) -> bool {
+    let (baseline_tick, baseline_sequence) = *self
+      .baseline
+      .get_or_insert((tick, sequence.saturating_sub(1)));
+    let elapsed = tick.saturating_sub(baseline_tick);
+    let ahead = sequence.saturating_sub(baseline_sequence);
+    if ahead > elapsed + INPUT_SLACK_TICKS {
+      return false;
+    }
+
+    // Rebase only while the client is on pace, so slow server ticks never
+    // accumulate into a false positive and a flooding client never earns
+    // fresh slack.
+    if elapsed >= INPUT_REBASE_TICKS && ahead <= elapsed + INPUT_REBASE_TOLERANCE {
+      self.baseline = Some((tick, sequence));
+    }
+    true
+  }
+}
+
+/// Checks one tick of player movement against max velocity and terrain.
+pub(crate) fn validate_movement(
+  terrain_map: &TerrainMap,
+  from: Vec3,
+  to: Vec3,
+  inputs: u32,
+  speed_mult: f32,
+) -> Result<(), (ViolationKind, String)> {
+  let max_distance = PLAYER_SPEED * speed_mult.max(0.0) * INPUT_STEP_SECONDS * inputs as f32;
+  let distance = Vec2::new(to.x - from.x, to.z - from.z).length();
+  if distance > max_distance + MOVEMENT_TOLERANCE {
+    return Err((
+      ViolationKind::SpeedHack,
+      format!("moved {distance:.1} with a limit of {max_distance:.1}"),
+    ));
+  }
+  if to != from && !terrain_map.movement().is_walkable(to) {
+    return Err((
+      ViolationKind::TerrainClip,
+      format!("ended at ({:.1}, {:.1})", to.x, to.z),
+    ));
+  }
+  Ok(())
+}
+
+pub(crate) fn report_violations(
+  tick: Res<ServerTick>,
+  policy: Res<AnticheatPolicy>,
+  actor_mapping: Res<ActorMapping>,
+  registry: Option<Res<PlayerServerRegistry>>,
+  mut log: ResMut<ViolationLog>,
+  mut moderation: ResMut<ModerationQueue>,
+  mut players: Query<&mut PlayerViolations>,
+) {
+  for violation in log.pending.drain(..) {
+    let Some(mut violations) = actor_mapping
+      .lookup(violation.client_id)
+      .and_then(|entity| players.get_mut(entity).ok())
+    else {
+      continue;
+    };
+
+    *violations.counts.entry(violation.kind).or_default() += 1;
+    violations.score = violations.score.saturating_add(violation.kind.weight());
+    violations
+      .unreported
+      .entry(violation.kind)
+      .and_modify(|folded| {
+        folded.occurrences += 1;
+        folded.tick = violation.tick;
+        folded.detail = violation.detail.clone();
+      })
+      .or_insert(violation);
+  }
+
+  for (client_id, entity) in actor_mapping.entries() {
+    let Ok(mut violations) = players.get_mut(entity) else {
+      continue;
+    };
+    flush_reports(&mut violations, client_id, tick.0, registry.as_deref());
+
+    if !violations.flagged && violations.score >= policy.review_score {
+      violations.flagged = true;
+      moderation.pending.push(ModerationRequest::Flag {
+        player_id: client_id,
+        reason: Some(format!("violation score {}", violations.score)),
+      });
+    }
+    if !violations.kicked
+      && policy
+        .kick_score
+        .is_some_and(|kick_score| violations.score >= kick_score)
+    {
+      violations.kicked = true;
+      moderation.pending.push(ModerationRequest::Kick {
+        player_id: client_id,
+        reason: Some(format!("violation score {}", violations.score)),
+      });
+    }
+  }
+}
+
+fn flush_reports(
+  violations: &mut PlayerViolations,
+  client_id: u64,
+  tick: u64,
+  registry: Option<&PlayerServerRegistry>,
+) {
+  let due: Vec<ViolationKind> = violations
+    .unreported
+    .keys()
+    .copied()
+    .filter(|kind| {
+      violations
+        .last_reported
+        .get(kind)
+        .is_none_or(|last| tick.saturating_sub(*last) >= REPORT_WINDOW_TICKS)
+    })
+    .collect();
+
+  for kind in due {
+    let Some(violation) = violations.unreported.remove(&kind) else {
+      continue;
+    };
+    violations.last_reported.insert(kind, tick);
+    eprintln!(
+      "game_server anticheat: client {client_id} {} x{} ({} total, score {}) at tick {}: {}",
+      kind.as_str(),
+      violation.occurrences,
+      violations.count(kind),
+      violations.score,
+      violation.tick,
+      violation.detail
+    );
+    if let Some(registry) = registry {
+      registry.record_violation(PlayerViolationRecord::new(
+        client_id,
+        kind.as_str(),
+        violation.occurrences,
+        violations.score,
+        violation.tick,
+        violation.detail,
+      ));
+    }
+  }
+}
+
+fn env_u32(name: &str) -> Option<u32> {
+  env::var(name).ok()?.trim().parse::<u32>().ok()
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use avian3d::prelude::{Collider, LinearVelocity, Position, RigidBody};
 use bevior_tree::prelude::{BehaviorTree, BehaviorTreeRoot};
@@ -6,7 +6,9 @@ use bevy::prelude::*;
 
 use crate::{
   actor::bridge::{ActorBridge, BridgeOut},
+  anticheat::{InputRate, PlayerViolations, ViolationKind, ViolationLog, validate_movement},
   behavior::monster_behavior_tree,
+  lag_compensation::{AttackClaim, PositionHistory},
   protocol::{ActorKind, ActorState},
   terrain::{LevelMap, TerrainMap, clamp_to_playable_area},
 };
@@ -16,7 +18,6 @@ pub(crate) const PLAYER_SIZE: Vec3 = Vec3::new(28.0, 36.0, 28.0);
 pub(crate) const MONSTER_SIZE: Vec3 = Vec3::new(24.0, 32.0, 24.0);
 pub(crate) const PLAYER_CENTER_Y: f32 = PLAYER_SIZE.y * 0.5;
 pub(crate) const MONSTER_CENTER_Y: f32 = MONSTER_SIZE.y * 0.5;
-pub(crate) const PLAYER_SPEED: f32 = 260.0;
 pub(crate) const MONSTER_ATTACK_RANGE: f32 = 44.0;
 pub(crate) const PLAYER_START_RED: i32 = 18;
 pub(crate) const PLAYER_START_BLUE: i32 = 140;
@@ -24,6 +25,13 @@ const SNAPSHOT_SECONDS: f32 = 0.1;
 const COMBAT_TICK_SECONDS: f32 = 0.35;
 const MONSTER_DAMAGE: i32 = 8;
 const ANIMATION_STRIDE_LENGTH: f32 = 80.0;
+/// Inputs applied per player per tick; the extra slots let a burst delayed by
+/// the network catch up without granting a speed boost for long.
+const MAX_INPUTS_PER_TICK: usize = 4;
+const MAX_QUEUED_INPUTS: usize = 32;
+/// Inputs are built from analog sticks and normalized key axes, so anything
+/// meaningfully longer than a unit vector was crafted.
+const MAX_INPUT_LENGTH_SQUARED: f32 = 1.0 + 1e-3;
 
 #[derive(Resource, Debug)]
 pub(crate) struct NextActorId(u64);
@@ -68,6 +76,13 @@ impl ActorMapping {
   pub(crate) fn lookup(&self, client_id: u64) -> Option<Entity> {
     self.client_to_entity.get(&client_id).copied()
   }
+
+  pub(crate) fn entries(&self) -> impl Iterator<Item = (u64, Entity)> + '_ {
+    self
+      .client_to_entity
+      .iter()
+      .map(|(client_id, entity)| (*client_id, *entity))
+  }
 }
 
 #[derive(Resource, Debug, Default)]
@@ -129,16 +144,75 @@ pub(crate) struct Vitals {
   pub(crate) blue: i32,
 }
 
-#[derive(Component, Debug, Clone, Copy, PartialEq)]
-pub(crate) struct PlayerInputState {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct QueuedInput {
+  pub(crate) sequence: u64,
   pub(crate) direction: Vec3,
+  /// Target actor id and the server tick the client was viewing it at.
+  pub(crate) attack: Option<(u64, u64)>,
 }
 
-impl Default for PlayerInputState {
-  fn default() -> Self {
-    Self {
-      direction: Vec3::ZERO,
+/// Inputs received from the client but not simulated yet. Each input is one
+/// fixed movement step, and `last_processed_sequence` is echoed back in
+/// snapshots so the client knows which of its predictions to replay.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub(crate) struct PlayerInputState {
+  pub(crate) pending: VecDeque<QueuedInput>,
+  pub(crate) last_received_sequence: u64,
+  pub(crate) last_processed_sequence: u64,
+  pub(crate) rate: InputRate,
+  /// Attacks from processed inputs, resolved by `resolve_player_attacks`.
+  pub(crate) attacks: Vec<AttackClaim>,
+  pub(crate) last_attack_sequence: Option<u64>,
+  /// Problems found while queueing, logged on the next movement tick.
+  pub(crate) rejected: Vec<(ViolationKind, String)>,
+}
+
+impl PlayerInputState {
+  /// Queues an input; stale or duplicate sequence numbers are dropped, and
+  /// inputs arriving faster than server time or with an oversized direction
+  /// are rejected.
+  pub(crate) fn queue(
+    &mut self,
+    tick: u64,
+    sequence: u64,
+    direction: Vec3,
+    attack: Option<(u64, u64)>,
+  ) {
+    if sequence <= self.last_received_sequence {
+      return;
+    }
+
+    self.last_received_sequence = sequence;
+    if !self.rate.admit(tick, sequence) {
+      self.rejected.push((
+        ViolationKind::InputFlood,
+        format!("input {sequence} ahead of server tick {tick}"),
+      ));
+      return;
+    }
+
+    let direction =
+      if direction.is_finite() && direction.length_squared() <= MAX_INPUT_LENGTH_SQUARED {
+        direction
+      } else {
+        self.rejected.push((
+          ViolationKind::MalformedInput,
+          format!("input {sequence} direction {direction}"),
+        ));
+        Vec3::ZERO
+      };
+    if self.pending.len() == MAX_QUEUED_INPUTS {
+      // The client will be corrected for whatever is dropped here.
+      self.pending.pop_front();
     }
+    // Not normalized here: `step_player` does that, exactly as the client's
+    // prediction does, so both sides stay bit-for-bit identical.
+    self.pending.push_back(QueuedInput {
+      sequence,
+      direction,
+      attack,
+    });
   }
 }
 
@@ -220,6 +294,7 @@ pub(crate) fn spawn_player(
       PlayerInputState::default(),
       CombatModifiers::default(),
       Player { client_id },
+      (PositionHistory::default(), PlayerViolations::default()),
     ))
     .id();
   actor_mapping.register(client_id, entity);
@@ -227,30 +302,58 @@ pub(crate) fn spawn_player(
 }
 
 pub(crate) fn apply_player_movement(
-  time: Res<Time>,
   terrain_map: Res<TerrainMap>,
   mut server_tick: ResMut<ServerTick>,
-  mut players: Query<
-    (
-      &mut ArenaPosition,
-      &PlayerInputState,
-      &Vitals,
-      Option<&CombatModifiers>,
-    ),
-    With<Player>,
-  >,
+  mut log: ResMut<ViolationLog>,
+  mut players: Query<(
+    &Player,
+    &mut ArenaPosition,
+    &mut PlayerInputState,
+    &Vitals,
+    Option<&CombatModifiers>,
+  )>,
 ) {
   server_tick.0 += 1;
 
-  for (mut position, input, vitals, modifiers) in &mut players {
-    if vitals.blue <= 0 {
-      continue;
+  for (player, mut position, mut input, vitals, modifiers) in &mut players {
+    for (kind, detail) in input.rejected.drain(..) {
+      log.record(player.client_id, kind, server_tick.0, detail);
+    }
+
+    let speed_mult = modifiers.map_or(1.0, |m| m.move_speed_mult);
+    let start = position.0;
+    let mut steps = 0;
+    for _ in 0 .. MAX_INPUTS_PER_TICK {
+      let Some(queued) = input.pending.pop_front() else {
+        break;
+      };
+
+      // Dead players still acknowledge inputs so prediction stops replaying them.
+      if vitals.blue > 0 {
+        position.0 = terrain_map
+          .movement()
+          .step_player(position.0, queued.direction, speed_mult);
+        steps += 1;
+      }
+      input.last_processed_sequence = queued.sequence;
+      if let Some((target, view_tick)) = queued.attack {
+        input.attacks.push(AttackClaim {
+          sequence: queued.sequence,
+          target,
+          view_tick,
+          origin: position.0,
+        });
+      }
     }
 
-    let speed = PLAYER_SPEED * modifiers.map_or(1.0, |m| m.move_speed_mult);
-    let direction = Vec3::new(input.direction.x, 0.0, input.direction.z).normalize_or_zero();
-    let movement = direction * speed * time.delta_secs();
-    position.0 = terrain_map.try_move(position.0, movement);
+    // Movement is simulated here, so this only trips if something else moved
+    // the player illegally; it is the backstop the input checks rely on.
+    if let Err((kind, detail)) =
+      validate_movement(&terrain_map, start, position.0, steps, speed_mult)
+    {
+      position.0 = start;
+      log.record(player.client_id, kind, server_tick.0, detail);
+    }
   }
 }
 
@@ -344,6 +447,8 @@ pub(crate) fn actor_state(
   position: ArenaPosition,
   vitals: Vitals,
   presentation: ActorPresentation,
+  input: Option<&PlayerInputState>,
+  modifiers: Option<&CombatModifiers>,
 ) -> ActorState {
   ActorState {
     id: actor_id.0,
@@ -356,6 +461,8 @@ pub(crate) fn actor_state(
     animation_phase: presentation.animation_phase,
     motion_speed: presentation.motion_speed,
     vfx_pulse: presentation.vfx_pulse,
+    last_input_sequence: input.map_or(0, |input| input.last_processed_sequence),
+    move_speed_mult: modifiers.map_or(1.0, |modifiers| modifiers.move_speed_mult),
   }
 }
 
@@ -382,9 +489,460 @@ fn spawn_monster(
     vitals,
     Monster { speed },
     behavior_tree,
+    PositionHistory::default(),
   ));
 }
 
 fn horizontal_distance(from: Vec3, to: Vec3) -> f32 {
   Vec2::new(to.x - from.x, to.z - from.z).length()
 }
+
+#[cfg(test)]
+mod tests {
+  use game_shared::{
+    movement::{MovementMap, PLAYER_ATTACK_COOLDOWN_INPUTS},
+    prediction::{PredictedPlayer, SnapshotInterpolator},
+  };
+
+  use super::*;
+  use crate::{
+    anticheat::{AnticheatPolicy, ModerationQueue, report_violations},
+    lag_compensation::{PLAYER_DAMAGE, record_position_history, resolve_player_attacks},
+    player_registry::ModerationRequest,
+    protocol::{
+      ClientEnvelope, ClientPacket, PlayerInput, ServerEnvelope, ServerPacket, WorldSnapshot,
+      client_envelope, decode_client_packet, decode_server_packet, encode_client_envelope,
+      encode_server_envelope, server_envelope,
+    },
+    terrain::map_state,
+  };
+
+  const LATENCY_TICKS: u64 = 4;
+  const SNAPSHOT_EVERY_TICKS: u64 = 3;
+  const FLUSH_TICKS: usize = 60;
+
+  /// Delivers every packet exactly `latency_ticks` after it was sent.
+  struct SimulatedLink<T> {
+    latency_ticks: u64,
+    in_flight: VecDeque<(u64, T)>,
+  }
+
+  impl<T> SimulatedLink<T> {
+    fn new(latency_ticks: u64) -> Self {
+      Self {
+        latency_ticks,
+        in_flight: VecDeque::new(),
+      }
+    }
+
+    fn send(&mut self, now: u64, packet: T) {
+      self.in_flight.push_back((now + self.latency_ticks, packet));
+    }
+
+    fn deliver(&mut self, now: u64) -> Vec<T> {
+      let mut delivered = Vec::new();
+      while self.in_flight.front().is_some_and(|(due, _)| *due <= now) {
+        delivered.extend(self.in_flight.pop_front().map(|(_, packet)| packet));
+      }
+      delivered
+    }
+  }
+
+  /// Headless server world plus a predicting client, joined by protobuf
+  /// packets over simulated links.
+  struct Session {
+    app: App,
+    player: Entity,
+    map: MovementMap,
+    client: PredictedPlayer,
+    uplink: SimulatedLink<ClientPacket>,
+    downlink: SimulatedLink<ServerPacket>,
+    now: u64,
+    sequence: u64,
+  }
+
+  impl Session {
+    fn new() -> Self {
+      let level = LevelMap::default();
+      let mut app = App::new();
+      app
+        .insert_resource(TerrainMap::for_level(&level))
+        .insert_resource(AnticheatPolicy {
+          review_score: 10,
+          kick_score: None,
+        })
+        .init_resource::<ServerTick>()
+        .init_resource::<ViolationLog>()
+        .init_resource::<ModerationQueue>()
+        .init_resource::<ActorMapping>()
+        .add_systems(
+          Update,
+          (
+            apply_player_movement,
+            record_position_history,
+            resolve_player_attacks,
+            report_violations,
+          )
+            .chain(),
+        );
+      let player = app
+        .world_mut()
+        .spawn((
+          ActorId(1),
+          ActorType(ActorKind::Player),
+          ArenaPosition(level.player_spawn),
+          ActorPresentation::spawned(),
+          Vitals {
+            red: PLAYER_START_RED,
+            blue: PLAYER_START_BLUE,
+          },
+          PlayerInputState::default(),
+          CombatModifiers::default(),
+          Player { client_id: 1 },
+          PositionHistory::default(),
+          PlayerViolations::default(),
+        ))
+        .id();
+      app
+        .world_mut()
+        .resource_mut::<ActorMapping>()
+        .register(1, player);
+
+      let mut session = Self {
+        app,
+        player,
+        map: MovementMap::from_map_state(&map_state(&level)),
+        client: PredictedPlayer::new(&ActorState::default()),
+        uplink: SimulatedLink::new(LATENCY_TICKS),
+        downlink: SimulatedLink::new(LATENCY_TICKS),
+        now: 0,
+        sequence: 0,
+      };
+      session.client = PredictedPlayer::new(&session.server_state());
+      session
+    }
+
+    fn server_state(&self) -> ActorState {
+      let entity = self.app.world().entity(self.player);
+      actor_state(
+        *entity.get::<ActorId>().unwrap(),
+        *entity.get::<ActorType>().unwrap(),
+        *entity.get::<ArenaPosition>().unwrap(),
+        *entity.get::<Vitals>().unwrap(),
+        *entity.get::<ActorPresentation>().unwrap(),
+        entity.get::<PlayerInputState>(),
+        entity.get::<CombatModifiers>(),
+      )
+    }
+
+    fn server_position(&self) -> Vec3 {
+      self
+        .app
+        .world()
+        .get::<ArenaPosition>(self.player)
+        .unwrap()
+        .0
+    }
+
+    fn spawn_monster(&mut self, id: u64, position: Vec3) -> Entity {
+      assert!(
+        self
+          .app
+          .world()
+          .resource::<TerrainMap>()
+          .movement()
+          .is_walkable(position)
+      );
+      self
+        .app
+        .world_mut()
+        .spawn((
+          ActorId(id),
+          ArenaPosition(position),
+          ActorPresentation::spawned(),
+          Vitals { red: 10, blue: 60 },
+          Monster { speed: 0.0 },
+          PositionHistory::default(),
+        ))
+        .id()
+    }
+
+    fn server_tick(&self) -> u64 {
+      self.app.world().resource::<ServerTick>().0
+    }
+
+    fn violations(&self) -> &PlayerViolations {
+      self
+        .app
+        .world()
+        .get::<PlayerViolations>(self.player)
+        .unwrap()
+    }
+
+    /// Queues an input straight into the server, as a modified client would.
+    fn inject(&mut self, direction: Vec3, attack: Option<(u64, u64)>) {
+      self.sequence += 1;
+      let tick = self.server_tick();
+      self
+        .app
+        .world_mut()
+        .get_mut::<PlayerInputState>(self.player)
+        .unwrap()
+        .queue(tick, self.sequence, direction, attack);
+    }
+
+    fn set_move_speed_mult(&mut self, move_speed_mult: f32) {
+      let mut entity = self.app.world_mut().entity_mut(self.player);
+      entity.get_mut::<CombatModifiers>().unwrap().move_speed_mult = move_speed_mult;
+    }
+
+    /// One client frame and one server tick.
+    fn tick(&mut self, direction: Vec3) {
+      self.sequence += 1;
+      self.client.apply_input(&self.map, self.sequence, direction);
+      let input = ClientEnvelope {
+        payload: Some(client_envelope::Payload::Input(PlayerInput {
+          sequence: self.sequence,
+          x: direction.x,
+          y: 0.0,
+          z: direction.z,
+          ..Default::default()
+        })),
+      };
+      self
+        .uplink
+        .send(self.now, encode_client_envelope(&input).unwrap());
+
+      for packet in self.uplink.deliver(self.now) {
+        let Some(client_envelope::Payload::Input(input)) =
+          decode_client_packet(packet).unwrap().payload
+        else {
+          continue;
+        };
+        let tick = self.server_tick();
+        let mut entity = self.app.world_mut().entity_mut(self.player);
+        entity.get_mut::<PlayerInputState>().unwrap().queue(
+          tick,
+          input.sequence,
+          Vec3::new(input.x, 0.0, input.z),
+          None,
+        );
+      }
+      self.app.update();
+
+      if self.now % SNAPSHOT_EVERY_TICKS == 0 {
+        let snapshot = ServerEnvelope {
+          payload: Some(server_envelope::Payload::Snapshot(WorldSnapshot {
+            tick: self.server_tick(),
+            actors: vec![self.server_state()],
+            map: None,
+          })),
+        };
+        self
+          .downlink
+          .send(self.now, encode_server_envelope(&snapshot).unwrap());
+      }
+
+      for packet in self.downlink.deliver(self.now) {
+        let Some(server_envelope::Payload::Snapshot(snapshot)) =
+          decode_server_packet(packet).unwrap().payload
+        else {
+          continue;
+        };
+        for actor in &snapshot.actors {
+          self.client.reconcile(&self.map, actor);
+        }
+      }
+      self
+        .client
+        .smooth(game_shared::movement::INPUT_STEP_SECONDS);
+      self.now += 1;
+    }
+  }
+
+  #[test]
+  fn prediction_runs_ahead_of_latency_and_converges() {
+    let mut session = Session::new();
+    let start = session.server_position();
+
+    session.tick(Vec3::X);
+
+    assert_ne!(session.client.position(), start);
+    assert_eq!(session.server_position(), start);
+
+    for _ in 0 .. 30 {
+      session.tick(Vec3::new(1.0, 0.0, 0.4).normalize());
+    }
+    assert!(session.client.acked_sequence() > 0);
+    assert!(session.client.pending_inputs() > 0);
+
+    for _ in 0 .. FLUSH_TICKS {
+      session.tick(Vec3::ZERO);
+    }
+
+    assert_eq!(session.client.corrections(), 0);
+    // Idle inputs keep flowing, so only the last round trip is unacknowledged.
+    assert!(session.client.pending_inputs() as u64 <= 2 * LATENCY_TICKS + SNAPSHOT_EVERY_TICKS);
+    assert_eq!(session.client.position(), session.server_position());
+    assert_eq!(session.client.render_position(), session.server_position());
+  }
+
+  #[test]
+  fn speed_change_is_reconciled_and_smoothed() {
+    let mut session = Session::new();
+    for _ in 0 .. 10 {
+      session.tick(Vec3::NEG_Z);
+    }
+
+    // The client keeps predicting at full speed until a snapshot says otherwise.
+    session.set_move_speed_mult(0.5);
+    for _ in 0 .. 20 {
+      session.tick(Vec3::NEG_Z);
+    }
+    assert!(session.client.corrections() > 0);
+
+    for _ in 0 .. FLUSH_TICKS {
+      session.tick(Vec3::ZERO);
+    }
+
+    assert_eq!(session.client.position(), session.server_position());
+    assert_eq!(session.client.render_position(), session.server_position());
+  }
+
+  #[test]
+  fn input_flood_is_rejected_and_flagged() {
+    let mut session = Session::new();
+    let start = session.server_position();
+    let ticks = 60;
+    for _ in 0 .. ticks {
+      // Six inputs per tick is a 6x speed hack.
+      for _ in 0 .. 6 {
+        session.inject(Vec3::Z, None);
+      }
+      session.app.update();
+    }
+
+    let step = game_shared::movement::PLAYER_SPEED * game_shared::movement::INPUT_STEP_SECONDS;
+    let travelled = start.distance(session.server_position());
+    assert!(travelled <= (ticks + 32) as f32 * step + 0.1);
+    assert!(session.violations().count(ViolationKind::InputFlood) > 0);
+    assert!(session.violations().flagged);
+    assert!(
+      session
+        .app
+        .world()
+        .resource::<ModerationQueue>()
+        .pending
+        .iter()
+        .any(|request| matches!(request, ModerationRequest::Flag { player_id: 1, .. }))
+    );
+  }
+
+  #[test]
+  fn malformed_direction_is_ignored() {
+    let mut session = Session::new();
+    let start = session.server_position();
+    session.inject(Vec3::new(40.0, 0.0, 0.0), None);
+    session.inject(Vec3::new(f32::NAN, 0.0, 0.0), None);
+    session.app.update();
+
+    assert_eq!(session.server_position(), start);
+    assert_eq!(session.violations().count(ViolationKind::MalformedInput), 2);
+    assert_eq!(
+      session
+        .app
+        .world()
+        .get::<PlayerInputState>(session.player)
+        .unwrap()
+        .last_processed_sequence,
+      2
+    );
+  }
+
+  #[test]
+  fn attacks_rewind_the_target_to_the_view_tick() {
+    let mut session = Session::new();
+    let origin = session.server_position();
+    let monster = session.spawn_monster(2, origin + Vec3::new(40.0, 0.0, 0.0));
+    for _ in 0 .. 5 {
+      session.inject(Vec3::ZERO, None);
+      session.app.update();
+    }
+    let seen_at = session.server_tick();
+
+    // The monster walks away before the attack reaches the server.
+    session
+      .app
+      .world_mut()
+      .get_mut::<ArenaPosition>(monster)
+      .unwrap()
+      .0 = origin + Vec3::new(200.0, 0.0, 0.0);
+    session.app.update();
+    session.app.update();
+    session.inject(Vec3::ZERO, Some((2, seen_at)));
+    session.app.update();
+
+    let vitals = *session.app.world().get::<Vitals>(monster).unwrap();
+    assert_eq!(vitals.blue, 60 - PLAYER_DAMAGE);
+    assert_eq!(session.violations().score, 0);
+
+    // Claiming the present, where the monster is far away, is not a hit.
+    for _ in 0 .. PLAYER_ATTACK_COOLDOWN_INPUTS {
+      session.inject(Vec3::ZERO, None);
+      session.app.update();
+    }
+    let now = session.server_tick();
+    session.inject(Vec3::ZERO, Some((2, now)));
+    session.app.update();
+
+    assert_eq!(
+      session.app.world().get::<Vitals>(monster).unwrap().blue,
+      60 - PLAYER_DAMAGE
+    );
+    assert_eq!(session.violations().count(ViolationKind::ImpossibleHit), 1);
+  }
+
+  #[test]
+  fn attack_rules_are_enforced() {
+    let mut session = Session::new();
+    let origin = session.server_position();
+    let monster = session.spawn_monster(2, origin + Vec3::new(0.0, 0.0, 40.0));
+    session.inject(Vec3::ZERO, None);
+    session.app.update();
+
+    let future = session.server_tick() + 100;
+    session.inject(Vec3::ZERO, Some((2, future)));
+    session.inject(Vec3::ZERO, Some((2, future)));
+    session.inject(Vec3::ZERO, Some((99, 1)));
+    session.app.update();
+
+    // The first claim is clamped to the present and still lands.
+    assert_eq!(
+      session.app.world().get::<Vitals>(monster).unwrap().blue,
+      60 - PLAYER_DAMAGE
+    );
+    let violations = session.violations();
+    assert_eq!(violations.count(ViolationKind::FutureViewTick), 1);
+    assert_eq!(violations.count(ViolationKind::AttackRate), 2);
+  }
+
+  #[test]
+  fn remote_actors_are_interpolated_between_snapshots() {
+    let mut interpolator = SnapshotInterpolator::default();
+    let actor_at = |x: f32| ActorState {
+      id: 7,
+      x,
+      ..Default::default()
+    };
+
+    interpolator.push_snapshot(3, [&actor_at(0.0)]);
+    interpolator.push_snapshot(6, [&actor_at(30.0)]);
+
+    assert_eq!(interpolator.sample(7, 2.0), Some(Vec3::ZERO));
+    assert_eq!(interpolator.sample(7, 4.5), Some(Vec3::new(15.0, 0.0, 0.0)));
+    assert_eq!(interpolator.sample(7, 9.0), Some(Vec3::new(30.0, 0.0, 0.0)));
+
+    interpolator.push_snapshot(9, []);
+    assert_eq!(interpolator.sample(7, 9.0), None);
+  }
+}
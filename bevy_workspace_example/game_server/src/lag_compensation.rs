@@ -0,0 +1,175 @@
+//! Per-tick position history and attack resolution against the world as the
+//! attacker saw it.
+//!
+//! Clients draw other actors `INTERPOLATION_DELAY_TICKS` plus their latency in
+//! the past, so an attack is checked against where its target was at the
+//! `view_tick` the client reports, bounded by [`MAX_REWIND_TICKS`].
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use game_shared::movement::{PLAYER_ATTACK_COOLDOWN_INPUTS, PLAYER_ATTACK_RANGE};
+
+use crate::{
+  anticheat::{ViolationKind, ViolationLog},
+  game::{
+    ActorId, ActorPresentation, ArenaPosition, Monster, Player, PlayerInputState, ServerTick,
+    Vitals,
+  },
+  terrain::TerrainMap,
+};
+
+/// One second of history at the 30 Hz server tick.
+const HISTORY_TICKS: usize = 30;
+/// Longest rewind granted to a laggy attacker (about 330 ms).
+pub(crate) const MAX_REWIND_TICKS: u64 = 10;
+/// Client clocks are estimates; allow this much drift before calling a view
+/// tick impossible.
+const FUTURE_VIEW_TOLERANCE_TICKS: u64 = 3;
+/// Claims beyond this multiple of the range cannot come from an honest client.
+const IMPOSSIBLE_HIT_FACTOR: f32 = 2.0;
+pub(crate) const PLAYER_DAMAGE: i32 = 12;
+
+#[derive(Component, Debug, Default, Clone, PartialEq)]
+pub(crate) struct PositionHistory {
+  samples: VecDeque<(u64, Vec3)>,
+}
+
+impl PositionHistory {
+  pub(crate) fn record(&mut self, tick: u64, position: Vec3) {
+    if self.samples.back().is_some_and(|(last, _)| *last >= tick) {
+      return;
+    }
+    if self.samples.len() == HISTORY_TICKS {
+      self.samples.pop_front();
+    }
+    self.samples.push_back((tick, position));
+  }
+
+  /// Position at the end of `tick`, or the oldest sample we still have.
+  pub(crate) fn at(&self, tick: u64) -> Option<Vec3> {
+    self
+      .samples
+      .iter()
+      .rev()
+      .find(|(sample_tick, _)| *sample_tick <= tick)
+      .or(self.samples.front())
+      .map(|(_, position)| *position)
+  }
+}
+
+/// An attack taken from a processed input, resolved after movement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct AttackClaim {
+  pub(crate) sequence: u64,
+  pub(crate) target: u64,
+  pub(crate) view_tick: u64,
+  /// Attacker position right after the input carrying the attack.
+  pub(crate) origin: Vec3,
+}
+
+pub(crate) fn record_position_history(
+  tick: Res<ServerTick>,
+  mut actors: Query<(&ArenaPosition, &mut PositionHistory)>,
+) {
+  for (position, mut history) in &mut actors {
+    history.record(tick.0, position.0);
+  }
+}
+
+pub(crate) fn resolve_player_attacks(
+  tick: Res<ServerTick>,
+  terrain_map: Res<TerrainMap>,
+  mut log: ResMut<ViolationLog>,
+  mut players: Query<(&Player, &Vitals, &mut PlayerInputState)>,
+  mut targets: Query<
+    (
+      &ActorId,
+      &PositionHistory,
+      &mut Vitals,
+      &mut ActorPresentation,
+    ),
+    (With<Monster>, Without<Player>),
+  >,
+) {
+  for (player, vitals, mut input) in &mut players {
+    let attacks: Vec<AttackClaim> = input.attacks.drain(..).collect();
+    for attack in attacks {
+      if vitals.blue <= 0 {
+        continue;
+      }
+      if input
+        .last_attack_sequence
+        .is_some_and(|last| attack.sequence < last + PLAYER_ATTACK_COOLDOWN_INPUTS)
+      {
+        log.record(
+          player.client_id,
+          ViolationKind::AttackRate,
+          tick.0,
+          format!("attack at input {} inside cooldown", attack.sequence),
+        );
+        continue;
+      }
+      input.last_attack_sequence = Some(attack.sequence);
+
+      let Some((_, history, mut target_vitals, mut presentation)) = targets
+        .iter_mut()
+        .find(|(actor_id, ..)| actor_id.0 == attack.target)
+      else {
+        log.record(
+          player.client_id,
+          ViolationKind::MalformedInput,
+          tick.0,
+          format!("attack on unknown actor {}", attack.target),
+        );
+        continue;
+      };
+
+      let rewind_tick = rewind_tick(tick.0, attack.view_tick, player.client_id, &mut log);
+      let Some(target_position) = history.at(rewind_tick) else {
+        continue;
+      };
+      let distance = Vec2::new(
+        target_position.x - attack.origin.x,
+        target_position.z - attack.origin.z,
+      )
+      .length();
+
+      if distance > PLAYER_ATTACK_RANGE * IMPOSSIBLE_HIT_FACTOR {
+        log.record(
+          player.client_id,
+          ViolationKind::ImpossibleHit,
+          tick.0,
+          format!(
+            "actor {} was {distance:.1} away at tick {rewind_tick}",
+            attack.target
+          ),
+        );
+        continue;
+      }
+      if distance > PLAYER_ATTACK_RANGE
+        || target_vitals.blue <= 0
+        || !terrain_map.segment_is_walkable(attack.origin, target_position)
+      {
+        continue;
+      }
+
+      target_vitals.blue = (target_vitals.blue - PLAYER_DAMAGE).max(0);
+      presentation.vfx_pulse = presentation.vfx_pulse.saturating_add(1);
+    }
+  }
+}
+
+/// Clamps the claimed view tick into the window we are willing to rewind.
+fn rewind_tick(now: u64, view_tick: u64, client_id: u64, log: &mut ViolationLog) -> u64 {
+  if view_tick > now + FUTURE_VIEW_TOLERANCE_TICKS {
+    log.record(
+      client_id,
+      ViolationKind::FutureViewTick,
+      now,
+      format!("view tick {view_tick} ahead of server tick {now}"),
+    );
+    return now;
+  }
+  view_tick.clamp(now.saturating_sub(MAX_REWIND_TICKS), now)
+}
@@ -1,7 +1,9 @@
 mod actor;
 mod agones;
+mod anticheat;
 mod behavior;
 mod game;
+mod lag_compensation;
 mod net;
 mod player_registry;
 pub mod protocol;
@@ -1,4 +1,8 @@
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{
+  collections::{HashMap, HashSet},
+  net::SocketAddr,
+  time::Duration,
+};
 
 use anyhow::{Context, Result};
 use avian3d::prelude::{Gravity, PhysicsPlugins};
@@ -7,25 +11,31 @@ use bevy::{
   app::ScheduleRunnerPlugin, ecs::schedule::ApplyDeferred, log::LogPlugin, prelude::*,
   state::app::StatesPlugin, transform::TransformPlugin,
 };
-use lightyear::prelude::{
-  Authentication, Connected, Disconnected, LinkOf, LocalAddr, MessageReceiver, MessageSender,
-  RemoteId, ReplicationMetadata, ReplicationReceiver, ReplicationSender, UdpIo,
-  client::{ClientPlugins, Connect, NetcodeClient, NetcodeConfig as ClientNetcodeConfig},
-  server::{
-    ClientOf, NetcodeConfig as ServerNetcodeConfig, NetcodeServer, ServerPlugins, ServerUdpIo,
-    Start,
+use game_shared::movement::SERVER_TICK_SECONDS;
+use lightyear::{
+  connection::client::Disconnecting,
+  prelude::{
+    Authentication, Connected, Disconnected, LinkOf, LocalAddr, MessageReceiver, MessageSender,
+    RemoteId, ReplicationMetadata, ReplicationReceiver, ReplicationSender, UdpIo,
+    client::{ClientPlugins, Connect, NetcodeClient, NetcodeConfig as ClientNetcodeConfig},
+    server::{
+      ClientOf, NetcodeConfig as ServerNetcodeConfig, NetcodeServer, ServerPlugins, ServerUdpIo,
+      Start,
+    },
   },
 };
 
 use crate::{
   actor::bridge::{ActorBridge, ActorBridgePlugin, BridgeOut},
   agones::AgonesGameServerInfo,
+  anticheat::{self, AnticheatPolicy, ModerationQueue, ViolationLog},
   behavior, game,
   game::{
-    ActorId, ActorMapping, ActorPresentation, ActorType, ArenaPosition, NextActorId,
-    PlayerInputState, ServerTick, SnapshotClock, Vitals, actor_state, spawn_player,
+    ActorId, ActorMapping, ActorPresentation, ActorType, ArenaPosition, CombatModifiers,
+    NextActorId, PlayerInputState, ServerTick, SnapshotClock, Vitals, actor_state, spawn_player,
   },
-  player_registry::{self, PlayerServerRegistry},
+  lag_compensation,
+  player_registry::{self, ModerationRequest, PlayerServerRegistry},
   protocol::{
     ClientEnvelope, ClientPacket, GameChannel, GameProtocolPlugin, NETCODE_PRIVATE_KEY,
     NETCODE_PROTOCOL_ID, ServerEnvelope, ServerPacket, Welcome, WorldSnapshot, client_envelope,
@@ -35,7 +45,6 @@ use crate::{
   terrain::{LevelMap, map_state},
 };
 
-const SERVER_TICK_SECONDS: f64 = 1.0 / 30.0;
 const REPLICATION_SEND_SECONDS: f64 = 0.1;
 const MAX_CLIENT_MESSAGES_PER_TICK: usize = 4_096;
 
@@ -47,6 +56,8 @@ struct ServerConfig {
 #[derive(Resource, Default)]
 struct ServerClients {
   clients: HashMap<u64, ServerClientState>,
+  /// Kicked client ids, refused until the server restarts.
+  kicked: HashSet<u64>,
 }
 
 struct ServerClientState {
@@ -98,6 +109,9 @@ pub(crate) fn run_server(bind_addr: &str) -> Result<()> {
     .init_resource::<terrain::LevelMap>()
     .init_resource::<terrain::TerrainMap>()
     .init_resource::<ServerClients>()
+    .init_resource::<ViolationLog>()
+    .init_resource::<AnticheatPolicy>()
+    .init_resource::<ModerationQueue>()
     .add_systems(
       Startup,
       (
@@ -118,6 +132,12 @@ pub(crate) fn run_server(bind_addr: &str) -> Result<()> {
           .before(BehaviorTreeSystemSet::Update),
         game::apply_player_movement.before(BehaviorTreeSystemSet::Update),
         behavior::move_chasing_monsters.after(BehaviorTreeSystemSet::Update),
+        lag_compensation::record_position_history.after(behavior::move_chasing_monsters),
+        lag_compensation::resolve_player_attacks
+          .after(lag_compensation::record_position_history)
+          .before(game::resolve_combat),
+        anticheat::report_violations.after(lag_compensation::resolve_player_attacks),
+        apply_moderation.after(anticheat::report_violations),
         game::sync_actor_transforms.after(behavior::move_chasing_monsters),
         game::update_actor_presentation.after(game::sync_actor_transforms),
         game::resolve_combat.after(game::update_actor_presentation),
@@ -283,6 +303,46 @@ fn disconnect_client(
   }
 }
 
+/// Applies kicks and review flags, whether raised by the anticheat thresholds
+/// or pushed by an operator onto this server's moderation list.
+fn apply_moderation(
+  mut commands: Commands,
+  mut clients: ResMut<ServerClients>,
+  mut moderation: ResMut<ModerationQueue>,
+  player_registry: Res<PlayerServerRegistry>,
+  mut senders: Query<&mut MessageSender<ServerPacket>, With<ClientOf>>,
+) {
+  let mut requests: Vec<ModerationRequest> = moderation.pending.drain(..).collect();
+  requests.extend(player_registry.drain_moderation());
+
+  for request in requests {
+    match request {
+      ModerationRequest::Flag { player_id, reason } => {
+        let reason = reason.unwrap_or_else(|| "flagged by operator".to_string());
+        println!("client {player_id} flagged for review: {reason}");
+        player_registry.flag_for_review(player_id, &reason);
+      }
+      ModerationRequest::Kick { player_id, reason } => {
+        let reason = reason.unwrap_or_else(|| "kicked by operator".to_string());
+        clients.kicked.insert(player_id);
+        let Some(client) = clients.clients.get(&player_id) else {
+          continue;
+        };
+        println!("client {player_id} kicked: {reason}");
+        let notice = ServerEnvelope {
+          payload: Some(server_envelope::Payload::Notice(crate::protocol::Notice {
+            message: format!("kicked: {reason}"),
+          })),
+        };
+        send_to_client(&mut senders, client.entity, player_id, notice);
+        // Lightyear keeps the link for one more frame so the notice goes out,
+        // then despawns it; `disconnect_client` removes the player.
+        commands.entity(client.entity).insert(Disconnecting);
+      }
+    }
+  }
+}
+
 fn broadcast_snapshots(
   time: Res<Time>,
   tick: Res<ServerTick>,
@@ -296,6 +356,8 @@ fn broadcast_snapshots(
     &ArenaPosition,
     &Vitals,
     &ActorPresentation,
+    Option<&PlayerInputState>,
+    Option<&CombatModifiers>,
   )>,
   mut senders: Query<&mut MessageSender<ServerPacket>, With<ClientOf>>,
 ) {
@@ -314,9 +376,19 @@ fn broadcast_snapshots(
     tick: tick.0,
     actors: actors
       .iter()
-      .map(|(id, kind, position, vitals, presentation)| {
-        actor_state(*id, *kind, *position, *vitals, *presentation)
-      })
+      .map(
+        |(id, kind, position, vitals, presentation, input, modifiers)| {
+          actor_state(
+            *id,
+            *kind,
+            *position,
+            *vitals,
+            *presentation,
+            input,
+            modifiers,
+          )
+        },
+      )
       .collect(),
     map: Some(map_state(&level_map)),
   };
@@ -348,6 +420,16 @@ fn handle_client_message(
 ) {
   match message.payload {
     Some(client_envelope::Payload::Hello(hello)) => {
+      if clients.kicked.contains(&client_id) {
+        let notice = ServerEnvelope {
+          payload: Some(server_envelope::Payload::Notice(crate::protocol::Notice {
+            message: "kicked from this server".to_string(),
+          })),
+        };
+        send_to_client(senders, client_entity, client_id, notice);
+        commands.entity(client_entity).insert(Disconnecting);
+        return;
+      }
       let room = hello.room.trim().to_string();
       let connected_players = clients
         .clients
@@ -368,7 +450,14 @@ fn handle_client_message(
 
       if client.player.is_none() {
         let actor_id = actor_ids.next();
-        let player = spawn_player(commands, actor_id, client_id, connected_players, level_map, actor_mapping);
+        let player = spawn_player(
+          commands,
+          actor_id,
+          client_id,
+          connected_players,
+          level_map,
+          actor_mapping,
+        );
         client.player = Some(player);
         client.actor_id = Some(actor_id);
 
@@ -416,7 +505,12 @@ fn handle_client_message(
         return;
       };
 
-      player_input.direction = Vec3::new(input.x, 0.0, input.z).clamp_length_max(1.0);
+      player_input.queue(
+        tick,
+        input.sequence,
+        Vec3::new(input.x, 0.0, input.z),
+        (input.attack_target != 0).then_some((input.attack_target, input.view_tick)),
+      );
     }
     Some(client_envelope::Payload::Ping(ping)) => {
       let pong = ServerEnvelope {
@@ -1,13 +1,15 @@
 use std::{
   env,
   net::SocketAddr,
+  num::NonZeroUsize,
+  sync::Mutex,
   thread,
-  time::{SystemTime, UNIX_EPOCH},
+  time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use bevy::prelude::*;
-use redis::AsyncCommands;
-use serde::Serialize;
+use redis::{AsyncCommands, aio::ConnectionManager};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
 use crate::agones::AgonesGameServerInfo;
@@ -16,6 +18,10 @@ const DEFAULT_KEY_PREFIX: &str = "player";
 const DEFAULT_TTL_SECONDS: u64 = 6 * 60 * 60;
 const DEFAULT_NAMESPACE: &str = "default";
 const DEFAULT_LOCAL_HOST: &str = "127.0.0.1";
+const DEFAULT_GAME_SERVER_NAME: &str = "local-game-server";
+const VIOLATION_LOG_LIMIT: isize = 50;
+const MODERATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MODERATION_BATCH: usize = 32;
 
 pub(crate) struct PlayerServerRegistryPlugin;
 
@@ -30,6 +36,7 @@ impl Plugin for PlayerServerRegistryPlugin {
 #[derive(Resource, Default)]
 pub(crate) struct PlayerServerRegistry {
   sender: Option<mpsc::UnboundedSender<PlayerServerCommand>>,
+  moderation: Option<Mutex<mpsc::UnboundedReceiver<ModerationRequest>>>,
 }
 
 impl PlayerServerRegistry {
@@ -54,6 +61,92 @@ impl PlayerServerRegistry {
       eprintln!("game_server redis player registry remove queue closed: {err}");
     }
   }
+
+  pub(crate) fn record_violation(&self, record: PlayerViolationRecord) {
+    let Some(sender) = self.sender.as_ref() else {
+      return;
+    };
+
+    if let Err(err) = sender.send(PlayerServerCommand::Violation { record }) {
+      eprintln!("game_server redis player registry violation queue closed: {err}");
+    }
+  }
+
+  pub(crate) fn flag_for_review(&self, player_id: u64, reason: &str) {
+    let Some(sender) = self.sender.as_ref() else {
+      return;
+    };
+
+    if let Err(err) = sender.send(PlayerServerCommand::FlagForReview {
+      player_id: player_id.to_string(),
+      reason: reason.to_string(),
+    }) {
+      eprintln!("game_server redis player registry review queue closed: {err}");
+    }
+  }
+
+  /// Operator requests pushed to this server's moderation list since the last call.
+  pub(crate) fn drain_moderation(&self) -> Vec<ModerationRequest> {
+    let Some(Ok(mut receiver)) = self.moderation.as_ref().map(Mutex::lock) else {
+      return Vec::new();
+    };
+
+    let mut requests = Vec::new();
+    while let Ok(request) = receiver.try_recv() {
+      requests.push(request);
+    }
+    requests
+  }
+}
+
+/// Moderation action, either pushed by an operator as JSON onto the server's
+/// moderation list or raised by the anticheat thresholds, e.g.
+/// `{"action":"kick","player_id":42,"reason":"speed hack"}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub(crate) enum ModerationRequest {
+  Kick {
+    player_id: u64,
+    #[serde(default)]
+    reason: Option<String>,
+  },
+  Flag {
+    player_id: u64,
+    #[serde(default)]
+    reason: Option<String>,
+  },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct PlayerViolationRecord {
+  pub(crate) player_id: String,
+  pub(crate) kind: &'static str,
+  pub(crate) occurrences: u32,
+  pub(crate) score: u32,
+  pub(crate) tick: u64,
+  pub(crate) detail: String,
+  pub(crate) recorded_at_ms: u64,
+}
+
+impl PlayerViolationRecord {
+  pub(crate) fn new(
+    player_id: u64,
+    kind: &'static str,
+    occurrences: u32,
+    score: u32,
+    tick: u64,
+    detail: String,
+  ) -> Self {
+    Self {
+      player_id: player_id.to_string(),
+      kind,
+      occurrences,
+      score,
+      tick,
+      detail,
+      recorded_at_ms: unix_time_ms(),
+    }
+  }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -74,12 +167,15 @@ struct PlayerServerRegistryConfig {
   redis_url: String,
   key_prefix: String,
   ttl_seconds: u64,
+  moderation_key: String,
 }
 
 #[derive(Debug)]
 enum PlayerServerCommand {
   Upsert { record: PlayerServerRecord },
   Remove { player_id: String },
+  Violation { record: PlayerViolationRecord },
+  FlagForReview { player_id: String, reason: String },
 }
 
 pub(crate) fn player_server_record(
@@ -110,11 +206,16 @@ fn start_player_server_registry(mut registry: ResMut<PlayerServerRegistry>) {
     return;
   };
 
-  registry.sender = spawn_player_server_registry(config);
+  let (moderation_sender, moderation_receiver) = mpsc::unbounded_channel();
+  registry.sender = spawn_player_server_registry(config, moderation_sender);
+  if registry.sender.is_some() {
+    registry.moderation = Some(Mutex::new(moderation_receiver));
+  }
 }
 
 fn spawn_player_server_registry(
   config: PlayerServerRegistryConfig,
+  moderation: mpsc::UnboundedSender<ModerationRequest>,
 ) -> Option<mpsc::UnboundedSender<PlayerServerCommand>> {
   let (sender, receiver) = mpsc::unbounded_channel();
 
@@ -132,7 +233,7 @@ fn spawn_player_server_registry(
         }
       };
 
-      runtime.block_on(run_player_server_registry(config, receiver));
+      runtime.block_on(run_player_server_registry(config, receiver, moderation));
     }) {
     Ok(_thread) => Some(sender),
     Err(err) => {
@@ -145,6 +246,7 @@ fn spawn_player_server_registry(
 async fn run_player_server_registry(
   config: PlayerServerRegistryConfig,
   mut receiver: mpsc::UnboundedReceiver<PlayerServerCommand>,
+  moderation: mpsc::UnboundedSender<ModerationRequest>,
 ) {
   println!(
     "game_server redis player registry enabled, ttl={}s",
@@ -165,6 +267,15 @@ async fn run_player_server_registry(
       return;
     }
   };
+  println!(
+    "game_server redis player registry polling moderation list {}",
+    config.moderation_key
+  );
+  tokio::spawn(poll_moderation(
+    connection.clone(),
+    config.moderation_key.clone(),
+    moderation,
+  ));
 
   while let Some(command) = receiver.recv().await {
     match command {
@@ -191,6 +302,78 @@ async fn run_player_server_registry(
           eprintln!("game_server redis player registry DEL {key} error: {err}");
         }
       }
+      PlayerServerCommand::Violation { record } => {
+        let key = player_violations_key(&config.key_prefix, record.player_id.as_str());
+        let log_key = player_violation_log_key(&config.key_prefix, record.player_id.as_str());
+        let value = match serde_json::to_string(&record) {
+          Ok(value) => value,
+          Err(err) => {
+            eprintln!("game_server redis player registry encode error: {err}");
+            continue;
+          }
+        };
+        let ttl = config.ttl_seconds as i64;
+        let result: redis::RedisResult<()> = redis::pipe()
+          .atomic()
+          .hincr(key.as_str(), record.kind, record.occurrences)
+          .hset(key.as_str(), "score", record.score)
+          .hset(key.as_str(), "last_violation_ms", record.recorded_at_ms)
+          .expire(key.as_str(), ttl)
+          .lpush(log_key.as_str(), value)
+          .ltrim(log_key.as_str(), 0, VIOLATION_LOG_LIMIT - 1)
+          .expire(log_key.as_str(), ttl)
+          .query_async(&mut connection)
+          .await;
+        if let Err(err) = result {
+          eprintln!("game_server redis player registry violation {key} error: {err}");
+        }
+      }
+      PlayerServerCommand::FlagForReview { player_id, reason } => {
+        let key = player_violations_key(&config.key_prefix, player_id.as_str());
+        let result: redis::RedisResult<()> = redis::pipe()
+          .atomic()
+          .sadd(review_set_key(&config.key_prefix), player_id.as_str())
+          .hset(key.as_str(), "review_reason", reason)
+          .hset(key.as_str(), "flagged_at_ms", unix_time_ms())
+          .expire(key.as_str(), config.ttl_seconds as i64)
+          .query_async(&mut connection)
+          .await;
+        if let Err(err) = result {
+          eprintln!("game_server redis player registry review {player_id} error: {err}");
+        }
+      }
+    }
+  }
+}
+
+async fn poll_moderation(
+  mut connection: ConnectionManager,
+  key: String,
+  moderation: mpsc::UnboundedSender<ModerationRequest>,
+) {
+  let batch = NonZeroUsize::new(MODERATION_BATCH);
+  let mut interval = tokio::time::interval(MODERATION_POLL_INTERVAL);
+  loop {
+    interval.tick().await;
+    let result: redis::RedisResult<Option<Vec<String>>> =
+      connection.lpop(key.as_str(), batch).await;
+    let requests = match result {
+      Ok(requests) => requests.unwrap_or_default(),
+      Err(err) => {
+        eprintln!("game_server redis player registry LPOP {key} error: {err}");
+        continue;
+      }
+    };
+
+    for raw in requests {
+      match serde_json::from_str::<ModerationRequest>(&raw) {
+        Ok(request) => {
+          if moderation.send(request).is_err() {
+            return;
+          }
+        }
+        Err(err) => eprintln!("game_server ignored moderation request {raw}: {err}"),
+      }
     }
   }
 }
@@ -208,11 +391,23 @@ impl PlayerServerRegistryConfig {
     let ttl_seconds = env_u64("PLAYER_SERVER_REDIS_TTL_SECONDS")
       .filter(|ttl| *ttl > 0)
       .unwrap_or(DEFAULT_TTL_SECONDS);
+    // Agones names the pod after the GameServer, so HOSTNAME matches the
+    // `gs_name` operators see in player records.
+    let moderation_key = first_env(&["PLAYER_MODERATION_KEY"]).unwrap_or_else(|| {
+      let gs_name = first_env(&[
+        "PLAYER_SERVER_GAME_SERVER_NAME",
+        "GAME_SERVER_NAME",
+        "HOSTNAME",
+      ])
+      .unwrap_or_else(|| DEFAULT_GAME_SERVER_NAME.to_string());
+      moderation_key(&key_prefix, &gs_name)
+    });
 
     Some(Self {
       redis_url,
       key_prefix,
       ttl_seconds,
+      moderation_key,
     })
   }
 }
@@ -228,7 +423,7 @@ fn server_endpoint(agones_info: &AgonesGameServerInfo, bind_addr: SocketAddr) ->
   let gs_name = first_env(&["PLAYER_SERVER_GAME_SERVER_NAME", "GAME_SERVER_NAME"])
     .or_else(|| agones_info.name.clone())
     .or_else(|| first_env(&["HOSTNAME"]))
-    .unwrap_or_else(|| "local-game-server".to_string());
+    .unwrap_or_else(|| DEFAULT_GAME_SERVER_NAME.to_string());
   let namespace = first_env(&["PLAYER_SERVER_NAMESPACE"])
     .or_else(|| agones_info.namespace.clone())
     .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
@@ -258,6 +453,22 @@ fn player_server_key(prefix: &str, player_id: &str) -> String {
   format!("{prefix}_{player_id}_server")
 }
 
+fn player_violations_key(prefix: &str, player_id: &str) -> String {
+  format!("{prefix}_{player_id}_violations")
+}
+
+fn player_violation_log_key(prefix: &str, player_id: &str) -> String {
+  format!("{prefix}_{player_id}_violation_log")
+}
+
+fn review_set_key(prefix: &str) -> String {
+  format!("{prefix}_review")
+}
+
+fn moderation_key(prefix: &str, gs_name: &str) -> String {
+  format!("{prefix}_moderation_{gs_name}")
+}
+
 fn first_env(names: &[&str]) -> Option<String> {
   names.iter().find_map(|name| {
     let value = env::var(name).ok()?;
@@ -292,10 +503,50 @@ fn unix_time_ms() -> u64 {
 
 #[cfg(test)]
 mod tests {
-  use super::player_server_key;
+  use super::{
+    ModerationRequest, moderation_key, player_server_key, player_violation_log_key,
+    player_violations_key,
+  };
 
   #[test]
   fn player_server_key_matches_documented_shape() {
     assert_eq!(player_server_key("player", "12345"), "player_12345_server");
   }
+
+  #[test]
+  fn moderation_keys_match_documented_shape() {
+    assert_eq!(
+      player_violations_key("player", "12345"),
+      "player_12345_violations"
+    );
+    assert_eq!(
+      player_violation_log_key("player", "12345"),
+      "player_12345_violation_log"
+    );
+    assert_eq!(
+      moderation_key("player", "arena-abcde"),
+      "player_moderation_arena-abcde"
+    );
+  }
+
+  #[test]
+  fn operator_moderation_requests_parse() {
+    assert_eq!(
+      serde_json::from_str::<ModerationRequest>(
+        r#"{"action":"kick","player_id":42,"reason":"speed hack"}"#
+      )
+      .unwrap(),
+      ModerationRequest::Kick {
+        player_id: 42,
+        reason: Some("speed hack".to_string()),
+      }
+    );
+    assert_eq!(
+      serde_json::from_str::<ModerationRequest>(r#"{"action":"flag","player_id":7}"#).unwrap(),
+      ModerationRequest::Flag {
+        player_id: 7,
+        reason: None,
+      }
+    );
+  }
 }
@@ -2,6 +2,7 @@ use std::collections::VecDeque;
 
 use avian3d::prelude::{Collider, Position, RigidBody};
 use bevy::prelude::*;
+use game_shared::movement::{MovementMap, Obstacle};
 
 use crate::{
   game::{ARENA_HALF_EXTENTS, PLAYER_CENTER_Y},
@@ -9,9 +10,7 @@ use crate::{
 };
 
 const CELL_SIZE: f32 = 40.0;
-const ACTOR_RADIUS: f32 = 18.0;
 const BOUNDARY_WALL_THICKNESS: f32 = 20.0;
-const PLAYABLE_PADDING: f32 = ACTOR_RADIUS + BOUNDARY_WALL_THICKNESS;
 const FLOOR_THICKNESS: f32 = 2.0;
 const BOUNDARY_WALL_HEIGHT: f32 = 48.0;
 const OBSTACLE_HEIGHT: f32 = 48.0;
@@ -20,7 +19,7 @@ const OBSTACLE_HEIGHT: f32 = 48.0;
 pub(crate) struct LevelMap {
   pub(crate) name: &'static str,
   pub(crate) player_spawn: Vec3,
-  pub(crate) obstacles: Vec<TerrainSpec>,
+  pub(crate) obstacles: Vec<Obstacle>,
   pub(crate) monsters: Vec<MonsterSpawn>,
 }
 
@@ -37,7 +36,7 @@ impl Default for LevelMap {
 
 #[derive(Resource, Debug, Clone)]
 pub(crate) struct TerrainMap {
-  obstacles: Vec<TerrainSpec>,
+  movement: MovementMap,
   blocked: Vec<bool>,
   columns: i32,
   rows: i32,
@@ -53,50 +52,31 @@ impl TerrainMap {
   pub(crate) fn for_level(map: &LevelMap) -> Self {
     let columns = ((ARENA_HALF_EXTENTS.x * 2.0) / CELL_SIZE).ceil() as i32;
     let rows = ((ARENA_HALF_EXTENTS.z * 2.0) / CELL_SIZE).ceil() as i32;
-    let obstacles = map.obstacles.clone();
+    let movement = arena_movement_map(map.obstacles.clone());
     let mut blocked = Vec::with_capacity((columns * rows) as usize);
 
     for y in 0 .. rows {
       for x in 0 .. columns {
         let position = cell_center(columns, rows, IVec2::new(x, y), 0.0);
-        blocked.push(
-          !inside_playable_area(position)
-            || point_overlaps_any_obstacle(position, &obstacles, ACTOR_RADIUS),
-        );
+        blocked.push(!movement.is_walkable(position));
       }
     }
 
     Self {
-      obstacles,
+      movement,
       blocked,
       columns,
       rows,
     }
   }
 
-  pub(crate) fn try_move(&self, from: Vec3, delta: Vec3) -> Vec3 {
-    let from = clamp_to_playable_area(from);
-    let target = clamp_to_playable_area(from + Vec3::new(delta.x, 0.0, delta.z));
-    if self.segment_is_walkable(from, target) {
-      return target;
-    }
-
-    let mut moved = from;
-    if delta.x != 0.0 {
-      let x_target = Vec3::new(target.x, moved.y, moved.z);
-      if self.segment_is_walkable(moved, x_target) {
-        moved = x_target;
-      }
-    }
-
-    if delta.z != 0.0 {
-      let z_target = Vec3::new(moved.x, moved.y, target.z);
-      if self.segment_is_walkable(moved, z_target) {
-        moved = z_target;
-      }
-    }
+  /// Walkability shared with client-side prediction.
+  pub(crate) fn movement(&self) -> &MovementMap {
+    &self.movement
+  }
 
-    moved
+  pub(crate) fn try_move(&self, from: Vec3, delta: Vec3) -> Vec3 {
+    self.movement.try_move(from, delta)
   }
 
   pub(crate) fn next_waypoint(&self, from: Vec3, goal: Vec3) -> Vec3 {
@@ -121,18 +101,7 @@ impl TerrainMap {
   }
 
   pub(crate) fn segment_is_walkable(&self, from: Vec3, to: Vec3) -> bool {
-    let distance = horizontal_distance(from, to);
-    let steps = (distance / (CELL_SIZE * 0.5)).ceil().max(1.0) as usize;
-
-    (0 ..= steps).all(|step| {
-      let amount = step as f32 / steps as f32;
-      self.is_walkable(lerp_horizontal(from, to, amount))
-    })
-  }
-
-  fn is_walkable(&self, position: Vec3) -> bool {
-    inside_playable_area(position)
-      && !point_overlaps_any_obstacle(position, &self.obstacles, ACTOR_RADIUS)
+    self.movement.segment_is_walkable(from, to)
   }
 
   fn walkable_cell_near(&self, position: Vec3) -> Option<IVec2> {
@@ -225,7 +194,7 @@ impl TerrainMap {
   }
 
   fn world_to_cell(&self, position: Vec3) -> Option<IVec2> {
-    if !inside_playable_area(position) {
+    if !self.movement.inside(position) {
       return None;
     }
 
@@ -252,39 +221,6 @@ impl TerrainMap {
   }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub(crate) struct TerrainSpec {
-  pub(crate) center: Vec3,
-  pub(crate) size: Vec3,
-  pub(crate) shape: ObstacleShape,
-}
-
-impl TerrainSpec {
-  fn contains(&self, position: Vec3, padding: f32) -> bool {
-    let half_size = Vec2::new(
-      (self.size.x * 0.5 + padding).max(1.0),
-      (self.size.z * 0.5 + padding).max(1.0),
-    );
-    let delta = Vec2::new(position.x - self.center.x, position.z - self.center.z);
-    let abs_delta = delta.abs();
-
-    match self.shape {
-      ObstacleShape::Cuboid => abs_delta.x <= half_size.x && abs_delta.y <= half_size.y,
-      ObstacleShape::DiamondPrism => abs_delta.x / half_size.x + abs_delta.y / half_size.y <= 1.0,
-      ObstacleShape::Cylinder => {
-        let x = abs_delta.x / half_size.x;
-        let y = abs_delta.y / half_size.y;
-        x * x + y * y <= 1.0
-      }
-      ObstacleShape::Cross => {
-        let bar = (half_size.x.min(half_size.y) * 0.35 + padding).max(8.0);
-        (abs_delta.x <= half_size.x && abs_delta.y <= bar)
-          || (abs_delta.y <= half_size.y && abs_delta.x <= bar)
-      }
-    }
-  }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) struct MonsterSpawn {
   pub(crate) position: Vec3,
@@ -330,30 +266,14 @@ pub(crate) fn map_state(map: &LevelMap) -> MapState {
 }
 
 pub(crate) fn clamp_to_playable_area(position: Vec3) -> Vec3 {
-  let half_size = playable_half_size();
-  Vec3::new(
-    position.x.clamp(-half_size.x, half_size.x),
-    position.y,
-    position.z.clamp(-half_size.y, half_size.y),
-  )
-}
-
-fn playable_half_size() -> Vec2 {
-  Vec2::new(ARENA_HALF_EXTENTS.x, ARENA_HALF_EXTENTS.z) - Vec2::splat(PLAYABLE_PADDING)
+  arena_movement_map(Vec::new()).clamp(position)
 }
 
-fn inside_playable_area(position: Vec3) -> bool {
-  let half_size = playable_half_size();
-  position.x >= -half_size.x
-    && position.x <= half_size.x
-    && position.z >= -half_size.y
-    && position.z <= half_size.y
-}
-
-fn point_overlaps_any_obstacle(position: Vec3, obstacles: &[TerrainSpec], padding: f32) -> bool {
-  obstacles
-    .iter()
-    .any(|obstacle| obstacle.contains(position, padding))
+fn arena_movement_map(obstacles: Vec<Obstacle>) -> MovementMap {
+  MovementMap::new(
+    Vec2::new(ARENA_HALF_EXTENTS.x, ARENA_HALF_EXTENTS.z),
+    obstacles,
+  )
 }
 
 fn cell_center(columns: i32, rows: i32, cell: IVec2, y: f32) -> Vec3 {
@@ -368,18 +288,6 @@ fn cell_center(columns: i32, rows: i32, cell: IVec2, y: f32) -> Vec3 {
   ))
 }
 
-fn horizontal_distance(from: Vec3, to: Vec3) -> f32 {
-  Vec2::new(to.x - from.x, to.z - from.z).length()
-}
-
-fn lerp_horizontal(from: Vec3, to: Vec3, amount: f32) -> Vec3 {
-  Vec3::new(
-    from.x + (to.x - from.x) * amount,
-    from.y,
-    from.z + (to.z - from.z) * amount,
-  )
-}
-
 fn spawn_floor_collider(commands: &mut Commands) {
   let size = Vec3::new(
     ARENA_HALF_EXTENTS.x * 2.0,
@@ -453,7 +361,7 @@ fn spawn_boundary_colliders(commands: &mut Commands) {
   }
 }
 
-fn obstacle_collider(obstacle: &TerrainSpec) -> Collider {
+fn obstacle_collider(obstacle: &Obstacle) -> Collider {
   match obstacle.shape {
     ObstacleShape::Cylinder => {
       Collider::cylinder(obstacle.size.x.max(obstacle.size.z) * 0.5, obstacle.size.y)
@@ -464,7 +372,7 @@ fn obstacle_collider(obstacle: &TerrainSpec) -> Collider {
   }
 }
 
-fn obstacle_rotation(obstacle: &TerrainSpec) -> Quat {
+fn obstacle_rotation(obstacle: &Obstacle) -> Quat {
   match obstacle.shape {
     ObstacleShape::DiamondPrism => Quat::from_rotation_y(std::f32::consts::FRAC_PI_4),
     _ => Quat::IDENTITY,
@@ -477,15 +385,15 @@ const fn roadblock(
   size_x: f32,
   size_z: f32,
   shape: ObstacleShape,
-) -> TerrainSpec {
-  TerrainSpec {
+) -> Obstacle {
+  Obstacle {
     center: Vec3::new(center_x, OBSTACLE_HEIGHT * 0.5, center_z),
     size: Vec3::new(size_x, OBSTACLE_HEIGHT, size_z),
     shape,
   }
 }
 
-const CROSSROADS_OBSTACLES: [TerrainSpec; 4] = [
+const CROSSROADS_OBSTACLES: [Obstacle; 4] = [
   roadblock(-255.0, -170.0, 150.0, 70.0, ObstacleShape::Cuboid),
   roadblock(240.0, 160.0, 120.0, 120.0, ObstacleShape::Cylinder),
   roadblock(-90.0, 115.0, 130.0, 90.0, ObstacleShape::DiamondPrism),
@@ -521,6 +429,8 @@ const CROSSROADS_MONSTERS: [MonsterSpawn; 4] = [
 
 #[cfg(test)]
 mod tests {
+  use game_shared::movement::ACTOR_RADIUS;
+
   use super::*;
 
   #[test]
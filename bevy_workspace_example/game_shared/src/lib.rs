@@ -1,2 +1,4 @@
+pub mod movement;
+pub mod prediction;
 pub mod protocol;
 pub mod replication;
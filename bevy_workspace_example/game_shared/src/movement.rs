@@ -0,0 +1,174 @@
+use bevy::math::{Vec2, Vec3};
+
+use crate::protocol::{MapState, ObstacleShape};
+
+pub const SERVER_TICK_SECONDS: f64 = 1.0 / 30.0;
+/// Every `PlayerInput` moves its player by exactly one step of this length, on
+/// the server and in client prediction alike, so replaying unacknowledged
+/// inputs lands the client where the server will.
+pub const INPUT_STEP_SECONDS: f32 = SERVER_TICK_SECONDS as f32;
+pub const PLAYER_SPEED: f32 = 260.0;
+pub const ACTOR_RADIUS: f32 = 18.0;
+pub const PLAYER_ATTACK_RANGE: f32 = 56.0;
+/// Minimum number of inputs between two attacks from the same player.
+pub const PLAYER_ATTACK_COOLDOWN_INPUTS: u64 = 15;
+
+const BOUNDARY_WALL_THICKNESS: f32 = 20.0;
+const PLAYABLE_PADDING: f32 = ACTOR_RADIUS + BOUNDARY_WALL_THICKNESS;
+const SEGMENT_SAMPLE_SPACING: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+  pub center: Vec3,
+  pub size: Vec3,
+  pub shape: ObstacleShape,
+}
+
+impl Obstacle {
+  pub fn contains(&self, position: Vec3, padding: f32) -> bool {
+    let half_size = Vec2::new(
+      (self.size.x * 0.5 + padding).max(1.0),
+      (self.size.z * 0.5 + padding).max(1.0),
+    );
+    let delta = Vec2::new(position.x - self.center.x, position.z - self.center.z);
+    let abs_delta = delta.abs();
+
+    match self.shape {
+      ObstacleShape::Cuboid => abs_delta.x <= half_size.x && abs_delta.y <= half_size.y,
+      ObstacleShape::DiamondPrism => abs_delta.x / half_size.x + abs_delta.y / half_size.y <= 1.0,
+      ObstacleShape::Cylinder => {
+        let x = abs_delta.x / half_size.x;
+        let y = abs_delta.y / half_size.y;
+        x * x + y * y <= 1.0
+      }
+      ObstacleShape::Cross => {
+        let bar = (half_size.x.min(half_size.y) * 0.35 + padding).max(8.0);
+        (abs_delta.x <= half_size.x && abs_delta.y <= bar)
+          || (abs_delta.y <= half_size.y && abs_delta.x <= bar)
+      }
+    }
+  }
+}
+
+/// Walkable area of an arena: the server builds it from its level data and the
+/// client from the `MapState` in snapshots, which carries the same geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovementMap {
+  half_extents: Vec2,
+  obstacles: Vec<Obstacle>,
+}
+
+impl MovementMap {
+  pub fn new(half_extents: Vec2, obstacles: Vec<Obstacle>) -> Self {
+    Self {
+      half_extents,
+      obstacles,
+    }
+  }
+
+  pub fn from_map_state(map: &MapState) -> Self {
+    Self::new(
+      Vec2::new(map.half_width, map.half_depth),
+      map
+        .obstacles
+        .iter()
+        .map(|obstacle| Obstacle {
+          center: Vec3::new(obstacle.x, obstacle.y, obstacle.z),
+          size: Vec3::new(obstacle.width, obstacle.height, obstacle.depth),
+          shape: ObstacleShape::try_from(obstacle.shape).unwrap_or(ObstacleShape::Cuboid),
+        })
+        .collect(),
+    )
+  }
+
+  pub fn obstacles(&self) -> &[Obstacle] {
+    &self.obstacles
+  }
+
+  pub fn clamp(&self, position: Vec3) -> Vec3 {
+    let half_size = self.playable_half_size();
+    Vec3::new(
+      position.x.clamp(-half_size.x, half_size.x),
+      position.y,
+      position.z.clamp(-half_size.y, half_size.y),
+    )
+  }
+
+  pub fn inside(&self, position: Vec3) -> bool {
+    let half_size = self.playable_half_size();
+    position.x >= -half_size.x
+      && position.x <= half_size.x
+      && position.z >= -half_size.y
+      && position.z <= half_size.y
+  }
+
+  pub fn is_walkable(&self, position: Vec3) -> bool {
+    self.inside(position)
+      && !self
+        .obstacles
+        .iter()
+        .any(|obstacle| obstacle.contains(position, ACTOR_RADIUS))
+  }
+
+  pub fn segment_is_walkable(&self, from: Vec3, to: Vec3) -> bool {
+    let distance = horizontal_distance(from, to);
+    let steps = (distance / SEGMENT_SAMPLE_SPACING).ceil().max(1.0) as usize;
+
+    (0 ..= steps).all(|step| {
+      let amount = step as f32 / steps as f32;
+      self.is_walkable(lerp_horizontal(from, to, amount))
+    })
+  }
+
+  /// Moves as far along `delta` as the terrain allows, sliding along an axis
+  /// when the straight move is blocked.
+  pub fn try_move(&self, from: Vec3, delta: Vec3) -> Vec3 {
+    let from = self.clamp(from);
+    let target = self.clamp(from + Vec3::new(delta.x, 0.0, delta.z));
+    if self.segment_is_walkable(from, target) {
+      return target;
+    }
+
+    let mut moved = from;
+    if delta.x != 0.0 {
+      let x_target = Vec3::new(target.x, moved.y, moved.z);
+      if self.segment_is_walkable(moved, x_target) {
+        moved = x_target;
+      }
+    }
+
+    if delta.z != 0.0 {
+      let z_target = Vec3::new(moved.x, moved.y, target.z);
+      if self.segment_is_walkable(moved, z_target) {
+        moved = z_target;
+      }
+    }
+
+    moved
+  }
+
+  /// Applies one `PlayerInput` worth of movement.
+  pub fn step_player(&self, from: Vec3, direction: Vec3, speed_mult: f32) -> Vec3 {
+    let direction = Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero();
+    self.try_move(
+      from,
+      direction * PLAYER_SPEED * speed_mult * INPUT_STEP_SECONDS,
+    )
+  }
+
+  fn playable_half_size(&self) -> Vec2 {
+    self.half_extents - Vec2::splat(PLAYABLE_PADDING)
+  }
+}
+
+pub fn horizontal_distance(from: Vec3, to: Vec3) -> f32 {
+  Vec2::new(to.x - from.x, to.z - from.z).length()
+}
+
+fn lerp_horizontal(from: Vec3, to: Vec3, amount: f32) -> Vec3 {
+  Vec3::new(
+    from.x + (to.x - from.x) * amount,
+    from.y,
+    from.z + (to.z - from.z) * amount,
+  )
+}
@@ -0,0 +1,231 @@
+use std::collections::{HashMap, VecDeque};
+
+use bevy::math::Vec3;
+
+use crate::{
+  movement::{MovementMap, SERVER_TICK_SECONDS},
+  protocol::ActorState,
+};
+
+/// Remote actors are drawn this far behind the newest snapshot so there are
+/// usually two snapshots to blend between (snapshots go out every 3 ticks).
+pub const INTERPOLATION_DELAY_TICKS: f64 = 6.0;
+
+const MAX_PENDING_INPUTS: usize = 256;
+const MAX_SNAPSHOTS_PER_ACTOR: usize = 32;
+const RESYNC_TICKS: f64 = 15.0;
+const CLOCK_CATCH_UP: f64 = 0.1;
+const CORRECTION_EPSILON: f32 = 0.01;
+const SNAP_CORRECTION_DISTANCE: f32 = 96.0;
+const CORRECTION_SMOOTHING_PER_SECOND: f32 = 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingInput {
+  pub sequence: u64,
+  pub direction: Vec3,
+}
+
+/// Client-side prediction for the local player.
+///
+/// Inputs are applied locally as soon as they are sent and kept until a
+/// snapshot acknowledges them; each snapshot resets the prediction to the
+/// authoritative position and replays whatever the server has not seen yet.
+#[derive(Debug, Clone)]
+pub struct PredictedPlayer {
+  position: Vec3,
+  visual_offset: Vec3,
+  speed_mult: f32,
+  can_move: bool,
+  pending: VecDeque<PendingInput>,
+  acked_sequence: u64,
+  corrections: u64,
+}
+
+impl PredictedPlayer {
+  pub fn new(authoritative: &ActorState) -> Self {
+    Self {
+      position: actor_position(authoritative),
+      visual_offset: Vec3::ZERO,
+      speed_mult: speed_mult(authoritative),
+      can_move: authoritative.blue > 0,
+      pending: VecDeque::new(),
+      acked_sequence: authoritative.last_input_sequence,
+      corrections: 0,
+    }
+  }
+
+  pub fn position(&self) -> Vec3 {
+    self.position
+  }
+
+  /// Predicted position plus whatever is left of the last smoothed correction.
+  pub fn render_position(&self) -> Vec3 {
+    self.position + self.visual_offset
+  }
+
+  pub fn acked_sequence(&self) -> u64 {
+    self.acked_sequence
+  }
+
+  pub fn pending_inputs(&self) -> usize {
+    self.pending.len()
+  }
+
+  pub fn corrections(&self) -> u64 {
+    self.corrections
+  }
+
+  pub fn apply_input(&mut self, map: &MovementMap, sequence: u64, direction: Vec3) -> Vec3 {
+    if sequence <= self.acked_sequence {
+      return self.position;
+    }
+
+    if self.can_move {
+      self.position = map.step_player(self.position, direction, self.speed_mult);
+    }
+    if self.pending.len() == MAX_PENDING_INPUTS {
+      self.pending.pop_front();
+    }
+    self.pending.push_back(PendingInput {
+      sequence,
+      direction,
+    });
+    self.position
+  }
+
+  /// Reconciles against an authoritative state and returns how far the
+  /// prediction was off.
+  pub fn reconcile(&mut self, map: &MovementMap, authoritative: &ActorState) -> f32 {
+    // Snapshots travel on an ordered channel, but never step backwards anyway.
+    if authoritative.last_input_sequence < self.acked_sequence {
+      return 0.0;
+    }
+
+    self.acked_sequence = authoritative.last_input_sequence;
+    self.speed_mult = speed_mult(authoritative);
+    self.can_move = authoritative.blue > 0;
+    self
+      .pending
+      .retain(|input| input.sequence > authoritative.last_input_sequence);
+
+    let mut replayed = actor_position(authoritative);
+    if self.can_move {
+      for input in &self.pending {
+        replayed = map.step_player(replayed, input.direction, self.speed_mult);
+      }
+    }
+
+    let error = replayed - self.position;
+    let error_distance = error.length();
+    if error_distance > SNAP_CORRECTION_DISTANCE {
+      self.visual_offset = Vec3::ZERO;
+      self.corrections += 1;
+    } else if error_distance > CORRECTION_EPSILON {
+      // Keep the rendered position where it was and bleed the error off.
+      self.visual_offset -= error;
+      self.corrections += 1;
+    }
+    self.position = replayed;
+    error_distance
+  }
+
+  pub fn smooth(&mut self, delta_secs: f32) {
+    self.visual_offset *= (-CORRECTION_SMOOTHING_PER_SECOND * delta_secs).exp();
+    if self.visual_offset.length_squared() < CORRECTION_EPSILON * CORRECTION_EPSILON {
+      self.visual_offset = Vec3::ZERO;
+    }
+  }
+}
+
+/// Estimate of the server tick, advanced locally between snapshots.
+#[derive(Debug, Clone, Default)]
+pub struct ServerClock {
+  tick: f64,
+  synced: bool,
+}
+
+impl ServerClock {
+  pub fn observe(&mut self, server_tick: u64) {
+    let server_tick = server_tick as f64;
+    if !self.synced || (server_tick - self.tick).abs() > RESYNC_TICKS {
+      self.tick = server_tick;
+      self.synced = true;
+    } else if server_tick > self.tick {
+      self.tick += (server_tick - self.tick) * CLOCK_CATCH_UP;
+    }
+  }
+
+  pub fn advance(&mut self, delta_secs: f64) {
+    if self.synced {
+      self.tick += delta_secs / SERVER_TICK_SECONDS;
+    }
+  }
+
+  pub fn tick(&self) -> f64 {
+    self.tick
+  }
+
+  pub fn interpolation_tick(&self) -> f64 {
+    self.tick - INTERPOLATION_DELAY_TICKS
+  }
+}
+
+/// Recent snapshot positions of remote actors, sampled at a delayed tick.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotInterpolator {
+  actors: HashMap<u64, VecDeque<(u64, Vec3)>>,
+}
+
+impl SnapshotInterpolator {
+  pub fn push_snapshot<'a>(&mut self, tick: u64, actors: impl IntoIterator<Item = &'a ActorState>) {
+    let mut seen = Vec::new();
+    for actor in actors {
+      seen.push(actor.id);
+      let samples = self.actors.entry(actor.id).or_default();
+      if samples
+        .back()
+        .is_some_and(|(last_tick, _)| *last_tick >= tick)
+      {
+        continue;
+      }
+      if samples.len() == MAX_SNAPSHOTS_PER_ACTOR {
+        samples.pop_front();
+      }
+      samples.push_back((tick, actor_position(actor)));
+    }
+    self.actors.retain(|id, _| seen.contains(id));
+  }
+
+  /// Position at `tick`, held at the oldest/newest sample outside the buffer.
+  pub fn sample(&self, actor_id: u64, tick: f64) -> Option<Vec3> {
+    let samples = self.actors.get(&actor_id)?;
+    let (first_tick, first) = *samples.front()?;
+    if tick <= first_tick as f64 {
+      return Some(first);
+    }
+
+    for window in samples.iter().collect::<Vec<_>>().windows(2) {
+      let (from_tick, from) = *window[0];
+      let (to_tick, to) = *window[1];
+      if tick <= to_tick as f64 {
+        let amount = (tick - from_tick as f64) / (to_tick - from_tick) as f64;
+        return Some(from.lerp(to, amount as f32));
+      }
+    }
+
+    samples.back().map(|(_, position)| *position)
+  }
+}
+
+pub fn actor_position(actor: &ActorState) -> Vec3 {
+  Vec3::new(actor.x, actor.y, actor.z)
+}
+
+fn speed_mult(actor: &ActorState) -> f32 {
+  // Older servers leave the field at its protobuf default.
+  if actor.move_speed_mult > 0.0 {
+    actor.move_speed_mult
+  } else {
+    1.0
+  }
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use super::{
+  DEFAULT_INPUT_DIR, DEFAULT_OUTPUT_FILE, DEFAULT_SIMILARITY_THRESHOLD, DEFAULT_STATE_FILE,
+};
+
+/// Finds lines that are similar but not identical across the `.txt` files of a
+/// directory and writes them out in groups.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub(crate) struct Args {
+  /// Directory with the `.txt` files to compare, `~/` is expanded
+  #[arg(short, long, default_value = DEFAULT_INPUT_DIR)]
+  pub(crate) input: String,
+
+  /// File the match groups are written to
+  #[arg(short, long, default_value = DEFAULT_OUTPUT_FILE)]
+  pub(crate) output: PathBuf,
+
+  #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+  pub(crate) format: OutputFormat,
+
+  /// Minimum normalized Levenshtein similarity for two lines to match
+  #[arg(short, long, default_value_t = DEFAULT_SIMILARITY_THRESHOLD, value_parser = parse_score)]
+  pub(crate) threshold: f64,
+
+  /// Only keep matches scoring at least this much
+  #[arg(long, value_parser = parse_score)]
+  pub(crate) min_score: Option<f64>,
+
+  /// Only keep groups with a line from this document (file name without
+  /// `.txt`), can be repeated
+  #[arg(long = "document", value_name = "NAME")]
+  pub(crate) documents: Vec<String>,
+
+  /// Only keep groups with a line containing this text, ignoring case
+  #[arg(long, value_name = "TEXT")]
+  pub(crate) contains: Option<String>,
+
+  /// Only keep groups spanning more than one document
+  #[arg(long)]
+  pub(crate) cross_document: bool,
+
+  /// Reuse the candidate index and matches of the previous run and only
+  /// compare lines of new or changed files
+  #[arg(long)]
+  pub(crate) incremental: bool,
+
+  /// State file of incremental mode
+  #[arg(long, value_name = "FILE", default_value = DEFAULT_STATE_FILE)]
+  pub(crate) state: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+  /// `text : document` lines, one blank line between groups
+  Text,
+  /// An array of groups with per-pair similarity scores
+  Json,
+  /// One row per line: group, kind, document, text, best score
+  Csv,
+}
+
+fn parse_score(value: &str) -> Result<f64, String> {
+  let score = value
+    .parse::<f64>()
+    .map_err(|err| format!("{value:?} is not a number: {err}"))?;
+  if (0.0 ..= 1.0).contains(&score) {
+    Ok(score)
+  } else {
+    Err(format!("{score} is not between 0 and 1"))
+  }
+}
@@ -0,0 +1,236 @@
+//! State kept between runs in incremental mode.
+//!
+//! The state records a [`MerkleSearchTree`] root over the content digest of
+//! every document, the candidate index and the scored matches of the last run.
+//! When the root is unchanged the old matches are reused as they are.
+//! Otherwise only pairs touching a new or changed document, or sharing a key
+//! that was not in the old candidate index, are compared again. Old matches
+//! between unchanged documents are kept while they still share a candidate
+//! key, so the result equals that of a full run.
+
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt::Write as _,
+  fs::{self, File},
+  io::{self, BufReader, BufWriter, Write},
+  path::Path,
+};
+
+use merkle_search_tree::MerkleSearchTree;
+use serde::{Deserialize, Serialize};
+
+use super::{CompareScope, IndexedDocuments, LineMatch, LinePair, build_global_candidate_index};
+
+/// Bumped whenever the layout of [`State`] or the candidate keys change.
+const STATE_VERSION: u32 = 1;
+
+/// A line as (document index, line index) into [`State::documents`].
+type SavedLine = (u32, u32);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct State {
+  version: u32,
+  threshold: f64,
+  /// Hex encoded root of the tree mapping document names to digests.
+  root_hash: String,
+  documents: Vec<SavedDocument>,
+  candidate_index: BTreeMap<String, Vec<SavedLine>>,
+  matches: Vec<SavedMatch>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedDocument {
+  name: String,
+  digest: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedMatch {
+  left: SavedLine,
+  right: SavedLine,
+  score: f64,
+}
+
+/// What is left to do after taking over the results of the previous run.
+#[derive(Debug)]
+pub(crate) struct Plan {
+  pub(crate) reused: Vec<LineMatch>,
+  /// Pairs still to compare, `None` if nothing changed.
+  pub(crate) scope: Option<CompareScope>,
+  pub(crate) changed_documents: usize,
+}
+
+impl Plan {
+  fn full(documents: &IndexedDocuments) -> Self {
+    Self {
+      reused: Vec::new(),
+      scope: Some(CompareScope::All),
+      changed_documents: documents.documents.len(),
+    }
+  }
+}
+
+/// Reads the state of the previous run. A missing or unreadable state file
+/// just means a full run.
+pub(crate) fn load(path: &Path) -> io::Result<Option<State>> {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(err),
+  };
+
+  match serde_json::from_reader(BufReader::new(file)) {
+    Ok(state) => Ok(Some(state)),
+    Err(err) => {
+      eprintln!("ignoring unreadable state file {}: {err}", path.display());
+      Ok(None)
+    }
+  }
+}
+
+pub(crate) fn save(
+  path: &Path,
+  documents: &IndexedDocuments,
+  matches: &[LineMatch],
+  threshold: f64,
+) -> io::Result<()> {
+  let saved_line = |line_index: usize| {
+    let line_ref = documents.lines[line_index];
+    (line_ref.document as u32, line_ref.line as u32)
+  };
+
+  let state = State {
+    version: STATE_VERSION,
+    threshold,
+    root_hash: documents_root_hash(documents),
+    documents: documents
+      .documents
+      .iter()
+      .map(|document| SavedDocument {
+        name: document.name.clone(),
+        digest: document.digest.clone(),
+      })
+      .collect(),
+    candidate_index: build_global_candidate_index(documents)
+      .into_iter()
+      .map(|(key, line_indices)| (key, line_indices.into_iter().map(saved_line).collect()))
+      .collect(),
+    matches: matches
+      .iter()
+      .map(|item| SavedMatch {
+        left: saved_line(item.pair.left),
+        right: saved_line(item.pair.right),
+        score: item.score,
+      })
+      .collect(),
+  };
+
+  // Write next to the old state and rename, so an interrupted run keeps it.
+  let tmp_path = path.with_extension("tmp");
+  let mut writer = BufWriter::new(File::create(&tmp_path)?);
+  serde_json::to_writer(&mut writer, &state)?;
+  writer.flush()?;
+  drop(writer);
+  fs::rename(tmp_path, path)
+}
+
+pub(crate) fn plan(previous: Option<&State>, documents: &IndexedDocuments, threshold: f64) -> Plan {
+  let Some(previous) =
+    previous.filter(|state| state.version == STATE_VERSION && state.threshold == threshold)
+  else {
+    return Plan::full(documents);
+  };
+
+  // Saved document index -> current document index, for unchanged documents.
+  let current = documents
+    .documents
+    .iter()
+    .enumerate()
+    .map(|(index, document)| ((document.name.as_str(), document.digest.as_str()), index))
+    .collect::<HashMap<_, _>>();
+  let unchanged = previous
+    .documents
+    .iter()
+    .map(|document| {
+      current
+        .get(&(document.name.as_str(), document.digest.as_str()))
+        .copied()
+    })
+    .collect::<Vec<_>>();
+  let current_line = |(document, line): SavedLine| {
+    let document = (*unchanged.get(document as usize)?)?;
+    documents.line_index(document, line as usize)
+  };
+  let reused_matches = |keep: &dyn Fn(LinePair) -> bool| {
+    previous
+      .matches
+      .iter()
+      .filter_map(|item| {
+        let pair = LinePair {
+          left: current_line(item.left)?,
+          right: current_line(item.right)?,
+        };
+        keep(pair).then_some(LineMatch {
+          pair,
+          score: item.score,
+        })
+      })
+      .collect::<Vec<_>>()
+  };
+
+  if documents_root_hash(documents) == previous.root_hash {
+    return Plan {
+      reused: reused_matches(&|_| true),
+      scope: None,
+      changed_documents: 0,
+    };
+  }
+
+  let clean_documents = unchanged.iter().flatten().copied().collect::<HashSet<_>>();
+  let dirty_lines = (0 .. documents.lines.len())
+    .filter(|&line_index| !clean_documents.contains(&documents.lines[line_index].document))
+    .collect::<HashSet<_>>();
+  let candidate_index = build_global_candidate_index(documents);
+  let fresh_keys = candidate_index
+    .keys()
+    .filter(|key| !previous.candidate_index.contains_key(*key))
+    .cloned()
+    .collect::<HashSet<_>>();
+
+  // A kept match must still be found by a full run, i.e. its lines must still
+  // share a key of the candidate index.
+  let shares_candidate_key = |pair: LinePair| {
+    let right_keys = &documents.line(pair.right).candidate_keys;
+    documents
+      .line(pair.left)
+      .candidate_keys
+      .iter()
+      .any(|key| right_keys.contains(key) && candidate_index.contains_key(key))
+  };
+
+  Plan {
+    reused: reused_matches(&shares_candidate_key),
+    changed_documents: documents.documents.len() - clean_documents.len(),
+    scope: Some(CompareScope::Changed {
+      dirty_lines,
+      fresh_keys,
+    }),
+  }
+}
+
+fn documents_root_hash(documents: &IndexedDocuments) -> String {
+  let mut tree = MerkleSearchTree::<String, String>::default();
+
+  for document in &documents.documents {
+    tree.upsert(document.name.clone(), &document.digest);
+  }
+
+  tree
+    .root_hash()
+    .as_bytes()
+    .iter()
+    .fold(String::new(), |mut hex, byte| {
+      let _ = write!(hex, "{byte:02x}");
+      hex
+    })
+}
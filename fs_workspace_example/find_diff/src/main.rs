@@ -10,12 +10,21 @@ use std::{
   sync::Arc,
 };
 
+use clap::Parser;
 use merkle_search_tree::MerkleSearchTree;
 use strsim::normalized_levenshtein;
 
-const INPUT_DIR: &str = "~/disk_files";
-const OUTPUT_FILE: &str = "out.txt";
-const SIMILARITY_THRESHOLD: f64 = 0.85;
+mod cli;
+mod incremental;
+mod report;
+
+use cli::{Args, OutputFormat};
+use report::GroupFilter;
+
+const DEFAULT_INPUT_DIR: &str = "~/disk_files";
+const DEFAULT_OUTPUT_FILE: &str = "out.txt";
+const DEFAULT_STATE_FILE: &str = "find_diff_state.json";
+const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.85;
 const MAX_BROAD_KEY_POSTINGS: usize = 256;
 const FIXED_FILE_SUFFIXES: &[&str] = &[".Code.zip"];
 const POSSIBLE_FORMAT_DUPLICATE_HEADER: &str =
@@ -24,6 +33,8 @@ const POSSIBLE_FORMAT_DUPLICATE_HEADER: &str =
 #[derive(Debug)]
 struct Document {
   name: String,
+  /// Hash of the file content, used by incremental mode to spot changes.
+  digest: String,
   lines: Vec<Line>,
 }
 
@@ -43,6 +54,8 @@ struct Line {
 struct IndexedDocuments {
   documents: Vec<Document>,
   lines: Vec<LineRef>,
+  /// Global index of the first line of each document.
+  offsets: Vec<usize>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -57,16 +70,93 @@ struct LinePair {
   right: usize,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LineMatch {
+  pair: LinePair,
+  /// Best normalized Levenshtein similarity of the comparison variants.
+  score: f64,
+}
+
+/// Which candidate pairs to compare. Incremental runs skip pairs whose result
+/// is already known from the previous run.
+#[derive(Debug, Default)]
+enum CompareScope {
+  #[default]
+  All,
+  Changed {
+    /// Lines of new or changed documents.
+    dirty_lines: HashSet<usize>,
+    /// Candidate keys that were not in the previous candidate index, so none
+    /// of their pairs were compared before.
+    fresh_keys: HashSet<String>,
+  },
+}
+
+impl CompareScope {
+  fn includes(&self, key: &str, pair: LinePair) -> bool {
+    match self {
+      Self::All => true,
+      Self::Changed {
+        dirty_lines,
+        fresh_keys,
+      } => {
+        dirty_lines.contains(&pair.left)
+          || dirty_lines.contains(&pair.right)
+          || fresh_keys.contains(key)
+      }
+    }
+  }
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> io::Result<()> {
-  let input_dir = expand_home(INPUT_DIR)?;
+  let args = Args::parse();
+  let input_dir = expand_home(&args.input)?;
   let documents = read_documents(&input_dir)?;
   let documents = Arc::new(IndexedDocuments::new(documents));
-  let matches = find_similar_lines(Arc::clone(&documents)).await?;
-  let groups = group_matches(&matches);
-  let written_groups = write_groups(OUTPUT_FILE, &documents, &groups)?;
 
-  println!("wrote {} groups to {}", written_groups, OUTPUT_FILE);
+  let mut matches = if args.incremental {
+    let previous = incremental::load(&args.state)?;
+    let plan = incremental::plan(previous.as_ref(), &documents, args.threshold);
+    println!(
+      "incremental: {} of {} documents new or changed, reusing {} matches",
+      plan.changed_documents,
+      documents.documents.len(),
+      plan.reused.len()
+    );
+    let mut matches = plan.reused;
+    if let Some(scope) = plan.scope {
+      matches.extend(
+        find_similar_lines_in_scope(Arc::clone(&documents), args.threshold, Arc::new(scope))
+          .await?,
+      );
+    }
+    sort_matches(&mut matches);
+    incremental::save(&args.state, &documents, &matches, args.threshold)?;
+    matches
+  } else {
+    find_similar_lines(Arc::clone(&documents), args.threshold).await?
+  };
+
+  if let Some(min_score) = args.min_score {
+    matches.retain(|item| item.score >= min_score);
+  }
+  let pairs = matches.iter().map(|item| item.pair).collect::<Vec<_>>();
+  let filter = GroupFilter::from_args(&args);
+  let mut groups = group_matches(&pairs);
+  groups.retain(|group| filter.keeps(&documents, group));
+
+  let written_groups = match args.format {
+    OutputFormat::Text => write_groups(&args.output, &documents, &groups)?,
+    OutputFormat::Json => report::write_json(&args.output, &documents, &groups, &matches)?,
+    OutputFormat::Csv => report::write_csv(&args.output, &documents, &groups, &matches)?,
+  };
+
+  println!(
+    "wrote {} groups to {}",
+    written_groups,
+    args.output.display()
+  );
   Ok(())
 }
 
@@ -85,12 +175,23 @@ fn expand_home(path: &str) -> io::Result<PathBuf> {
 impl IndexedDocuments {
   fn new(documents: Vec<Document>) -> Self {
     let mut lines = Vec::new();
+    let mut offsets = Vec::with_capacity(documents.len());
 
     for (document, item) in documents.iter().enumerate() {
+      offsets.push(lines.len());
       lines.extend((0 .. item.lines.len()).map(|line| LineRef { document, line }));
     }
 
-    Self { documents, lines }
+    Self {
+      documents,
+      lines,
+      offsets,
+    }
+  }
+
+  fn line_index(&self, document: usize, line: usize) -> Option<usize> {
+    let item = self.documents.get(document)?;
+    (line < item.lines.len()).then(|| self.offsets[document] + line)
   }
 
   fn line(&self, line_index: usize) -> &Line {
@@ -163,10 +264,25 @@ fn read_document(path: &Path) -> io::Result<Document> {
     lines.push(Line::new(text));
   }
 
-  Ok(Document { name, lines })
+  Ok(Document {
+    name,
+    digest: blake3::hash(content.as_bytes()).to_hex().to_string(),
+    lines,
+  })
+}
+
+async fn find_similar_lines(
+  documents: Arc<IndexedDocuments>,
+  threshold: f64,
+) -> io::Result<Vec<LineMatch>> {
+  find_similar_lines_in_scope(documents, threshold, Arc::new(CompareScope::All)).await
 }
 
-async fn find_similar_lines(documents: Arc<IndexedDocuments>) -> io::Result<Vec<LinePair>> {
+async fn find_similar_lines_in_scope(
+  documents: Arc<IndexedDocuments>,
+  threshold: f64,
+  scope: Arc<CompareScope>,
+) -> io::Result<Vec<LineMatch>> {
   let candidate_index = build_global_candidate_index(&documents);
   let shard_keys = mst_ordered_keys(candidate_index.keys());
   let shard_count = worker_count().min(shard_keys.len().max(1));
@@ -175,6 +291,7 @@ async fn find_similar_lines(documents: Arc<IndexedDocuments>) -> io::Result<Vec<
 
   for keys in key_shards {
     let documents = Arc::clone(&documents);
+    let scope = Arc::clone(&scope);
     let shard = keys
       .into_iter()
       .filter_map(|key| {
@@ -185,7 +302,7 @@ async fn find_similar_lines(documents: Arc<IndexedDocuments>) -> io::Result<Vec<
       .collect::<Vec<_>>();
 
     handles.push(tokio::task::spawn_blocking(move || {
-      collect_shard_matches(&documents, shard)
+      collect_shard_matches(&documents, shard, threshold, &scope)
     }));
   }
 
@@ -195,11 +312,15 @@ async fn find_similar_lines(documents: Arc<IndexedDocuments>) -> io::Result<Vec<
     matches.append(&mut shard_matches);
   }
 
-  matches.sort_unstable();
-  matches.dedup();
+  sort_matches(&mut matches);
   Ok(matches)
 }
 
+fn sort_matches(matches: &mut Vec<LineMatch>) {
+  matches.sort_unstable_by_key(|item| item.pair);
+  matches.dedup_by_key(|item| item.pair);
+}
+
 fn build_global_candidate_index(documents: &IndexedDocuments) -> HashMap<String, Vec<usize>> {
   let mut index: HashMap<String, Vec<usize>> = HashMap::new();
 
@@ -216,7 +337,7 @@ fn build_global_candidate_index(documents: &IndexedDocuments) -> HashMap<String,
       line_indices.sort_unstable();
       line_indices.dedup();
 
-      if (2 ..= MAX_BROAD_KEY_POSTINGS).contains(&line_indices.len()) {
+      if is_eligible_posting_count(line_indices.len()) {
         Some((key, line_indices))
       } else {
         None
@@ -225,6 +346,12 @@ fn build_global_candidate_index(documents: &IndexedDocuments) -> HashMap<String,
     .collect()
 }
 
+/// Keys with a single line have nothing to compare and keys shared by too
+/// many lines are too broad to be worth comparing.
+fn is_eligible_posting_count(count: usize) -> bool {
+  (2 ..= MAX_BROAD_KEY_POSTINGS).contains(&count)
+}
+
 fn mst_ordered_keys<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<String> {
   let mut tree = MerkleSearchTree::<String, ()>::default();
 
@@ -259,22 +386,27 @@ fn worker_count() -> usize {
 fn collect_shard_matches(
   documents: &IndexedDocuments,
   shard: Vec<(String, Vec<usize>)>,
-) -> Vec<LinePair> {
+  threshold: f64,
+  scope: &CompareScope,
+) -> Vec<LineMatch> {
   let mut seen = HashSet::new();
   let mut matches = Vec::new();
 
-  for (_key, line_indices) in shard {
+  for (key, line_indices) in shard {
     for left_pos in 0 .. line_indices.len() {
       for right_pos in (left_pos + 1) .. line_indices.len() {
         let left = line_indices[left_pos];
         let right = line_indices[right_pos];
         let pair = LinePair { left, right };
 
-        if !seen.insert(pair) || !is_similar_pair(documents, pair) {
+        if !scope.includes(&key, pair) || !seen.insert(pair) {
           continue;
         }
+        let Some(score) = pair_similarity(documents, pair, threshold) else {
+          continue;
+        };
 
-        matches.push(pair);
+        matches.push(LineMatch { pair, score });
       }
     }
   }
@@ -282,35 +414,57 @@ fn collect_shard_matches(
   matches
 }
 
-fn is_similar_pair(documents: &IndexedDocuments, pair: LinePair) -> bool {
+/// The best similarity of the pair's comparison variants, if any of them
+/// reaches `threshold`.
+fn pair_similarity(documents: &IndexedDocuments, pair: LinePair, threshold: f64) -> Option<f64> {
   let left = documents.line(pair.left);
   let right = documents.line(pair.right);
 
   if left.text == right.text {
-    return false;
+    return None;
   }
 
-  is_similar_text(
-    &left.comparison_text,
-    left.comparison_char_len,
-    &right.comparison_text,
-    right.comparison_char_len,
-  ) || is_similar_text(
-    &left.normalized_comparison_text,
-    left.normalized_comparison_char_len,
-    &right.normalized_comparison_text,
-    right.normalized_comparison_char_len,
-  ) || is_similar_text(
-    &left.title_comparison_text,
-    left.title_comparison_char_len,
-    &right.title_comparison_text,
-    right.title_comparison_char_len,
-  )
+  [
+    similarity_score(
+      &left.comparison_text,
+      left.comparison_char_len,
+      &right.comparison_text,
+      right.comparison_char_len,
+      threshold,
+    ),
+    similarity_score(
+      &left.normalized_comparison_text,
+      left.normalized_comparison_char_len,
+      &right.normalized_comparison_text,
+      right.normalized_comparison_char_len,
+      threshold,
+    ),
+    similarity_score(
+      &left.title_comparison_text,
+      left.title_comparison_char_len,
+      &right.title_comparison_text,
+      right.title_comparison_char_len,
+      threshold,
+    ),
+  ]
+  .into_iter()
+  .flatten()
+  .max_by(f64::total_cmp)
 }
 
-fn is_similar_text(left: &str, left_len: usize, right: &str, right_len: usize) -> bool {
-  can_reach_threshold(left_len, right_len)
-    && normalized_levenshtein(left, right) >= SIMILARITY_THRESHOLD
+fn similarity_score(
+  left: &str,
+  left_len: usize,
+  right: &str,
+  right_len: usize,
+  threshold: f64,
+) -> Option<f64> {
+  if !can_reach_threshold(left_len, right_len, threshold) {
+    return None;
+  }
+
+  let score = normalized_levenshtein(left, right);
+  (score >= threshold).then_some(score)
 }
 
 fn comparison_slice(text: &str) -> &str {
@@ -495,7 +649,7 @@ fn push_significant_token(tokens: &mut Vec<String>, current: &mut String) {
   }
 }
 
-fn can_reach_threshold(left_len: usize, right_len: usize) -> bool {
+fn can_reach_threshold(left_len: usize, right_len: usize, threshold: f64) -> bool {
   let max_len = left_len.max(right_len);
   if max_len == 0 {
     return false;
@@ -504,7 +658,7 @@ fn can_reach_threshold(left_len: usize, right_len: usize) -> bool {
   let min_distance = left_len.abs_diff(right_len);
   let best_possible_score = 1.0 - (min_distance as f64 / max_len as f64);
 
-  best_possible_score >= SIMILARITY_THRESHOLD
+  best_possible_score >= threshold
 }
 
 fn group_matches(matches: &[LinePair]) -> Vec<Vec<usize>> {
@@ -655,13 +809,12 @@ fn last_separator_index(text: &str) -> Option<usize> {
   text.rfind('/').into_iter().chain(text.rfind('\\')).max()
 }
 
-fn write_groups(
-  path: impl AsRef<Path>,
+/// Sorts the lines of each group and the groups themselves, and splits off the
+/// groups that only differ by file suffix within one document.
+fn arrange_groups(
   documents: &IndexedDocuments,
   groups: &[Vec<usize>],
-) -> io::Result<usize> {
-  let file = File::create(path)?;
-  let mut writer = BufWriter::new(file);
+) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
   let mut groups = groups.to_vec();
   let mut possible_format_duplicates = Vec::new();
 
@@ -680,6 +833,18 @@ fn write_groups(
   groups.sort_by(|left, right| match_group_cmp(documents, left, right));
   possible_format_duplicates.sort_by(|left, right| match_group_cmp(documents, left, right));
 
+  (groups, possible_format_duplicates)
+}
+
+fn write_groups(
+  path: impl AsRef<Path>,
+  documents: &IndexedDocuments,
+  groups: &[Vec<usize>],
+) -> io::Result<usize> {
+  let file = File::create(path)?;
+  let mut writer = BufWriter::new(file);
+  let (groups, possible_format_duplicates) = arrange_groups(documents, groups);
+
   for group in &groups {
     write_group(&mut writer, documents, group)?;
   }
@@ -810,7 +975,7 @@ mod tests {
     };
 
     assert_eq!(
-      expand_home("~/disk_files").unwrap(),
+      expand_home(DEFAULT_INPUT_DIR).unwrap(),
       PathBuf::from(home).join("disk_files")
     );
     assert_eq!(expand_home("files").unwrap(), PathBuf::from("files"));
@@ -821,7 +986,7 @@ mod tests {
     let documents = test_documents(&[("a", &["abc.2025"][..]), ("b", &["abc.2026"][..])]);
     let pair = LinePair { left: 0, right: 1 };
 
-    assert!(is_similar_pair(&documents, pair));
+    assert!(pair_similarity(&documents, pair, DEFAULT_SIMILARITY_THRESHOLD).is_some());
   }
 
   #[test]
@@ -835,7 +1000,7 @@ mod tests {
     ]);
     let pair = LinePair { left: 0, right: 1 };
 
-    assert!(is_similar_pair(&documents, pair));
+    assert!(pair_similarity(&documents, pair, DEFAULT_SIMILARITY_THRESHOLD).is_some());
   }
 
   #[test]
@@ -852,7 +1017,7 @@ mod tests {
     ]);
     let pair = LinePair { left: 0, right: 1 };
 
-    assert!(is_similar_pair(&documents, pair));
+    assert!(pair_similarity(&documents, pair, DEFAULT_SIMILARITY_THRESHOLD).is_some());
   }
 
   #[test]
@@ -866,7 +1031,7 @@ mod tests {
     )]);
     let pair = LinePair { left: 0, right: 1 };
 
-    assert!(is_similar_pair(&documents, pair));
+    assert!(pair_similarity(&documents, pair, DEFAULT_SIMILARITY_THRESHOLD).is_some());
   }
 
   #[test]
@@ -1033,9 +1198,11 @@ mod tests {
       &["Object-Oriented 2025.pdf", "Object-Oriented 2026.pdf"][..],
     )]));
 
-    let matches = find_similar_lines(Arc::clone(&documents)).await.unwrap();
+    let matches = find_similar_lines(Arc::clone(&documents), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
 
-    assert_eq!(matches, vec![LinePair { left: 0, right: 1 }]);
+    assert_eq!(pairs(&matches), vec![LinePair { left: 0, right: 1 }]);
   }
 
   #[tokio::test]
@@ -1048,9 +1215,11 @@ mod tests {
       ),
     ]));
 
-    let matches = find_similar_lines(Arc::clone(&documents)).await.unwrap();
+    let matches = find_similar_lines(Arc::clone(&documents), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
 
-    assert_eq!(matches, vec![LinePair { left: 0, right: 1 }]);
+    assert_eq!(pairs(&matches), vec![LinePair { left: 0, right: 1 }]);
   }
 
   #[tokio::test]
@@ -1069,11 +1238,12 @@ mod tests {
       ),
     ]));
 
-    let mut matches = find_similar_lines(Arc::clone(&documents)).await.unwrap();
-    matches.sort_unstable();
+    let matches = find_similar_lines(Arc::clone(&documents), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
 
     assert_eq!(
-      matches,
+      pairs(&matches),
       vec![
         LinePair { left: 0, right: 1 },
         LinePair { left: 0, right: 2 },
@@ -1115,10 +1285,108 @@ mod tests {
       .iter()
       .map(|(name, lines)| Document {
         name: (*name).to_owned(),
+        digest: lines.join("\n"),
         lines: lines.iter().map(|text| Line::new(text)).collect(),
       })
       .collect();
 
     IndexedDocuments::new(documents)
   }
+
+  #[tokio::test]
+  async fn incremental_run_matches_full_run() {
+    let before = Arc::new(test_documents(&[
+      ("a", &["Object-Oriented 2025.pdf"][..]),
+      ("b", &["Object-Oriented 2026.pdf"][..]),
+    ]));
+    let matches = find_similar_lines(Arc::clone(&before), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
+    let path = std::env::temp_dir().join("find_diff_incremental_test.json");
+    incremental::save(&path, &before, &matches, DEFAULT_SIMILARITY_THRESHOLD).unwrap();
+    let previous = incremental::load(&path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    let after = Arc::new(test_documents(&[
+      ("a", &["Object-Oriented 2025.pdf"][..]),
+      ("b", &["Object-Oriented 2026.pdf"][..]),
+      (
+        "c",
+        &["Object-Oriented 2024.pdf", "Rust in Action 2021.pdf"][..],
+      ),
+      ("d", &["Rust in Action 2022.pdf"][..]),
+    ]));
+    let plan = incremental::plan(previous.as_ref(), &after, DEFAULT_SIMILARITY_THRESHOLD);
+    assert_eq!(plan.changed_documents, 2);
+    assert_eq!(pairs(&plan.reused), vec![LinePair { left: 0, right: 1 }]);
+
+    let mut incremental_matches = plan.reused;
+    incremental_matches.extend(
+      find_similar_lines_in_scope(
+        Arc::clone(&after),
+        DEFAULT_SIMILARITY_THRESHOLD,
+        Arc::new(plan.scope.unwrap()),
+      )
+      .await
+      .unwrap(),
+    );
+    sort_matches(&mut incremental_matches);
+    let full_matches = find_similar_lines(Arc::clone(&after), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
+
+    assert_eq!(incremental_matches, full_matches);
+  }
+
+  #[tokio::test]
+  async fn incremental_run_without_changes_reuses_all_matches() {
+    let documents = Arc::new(test_documents(&[
+      ("a", &["Object-Oriented 2025.pdf"][..]),
+      ("b", &["Object-Oriented 2026.pdf"][..]),
+    ]));
+    let matches = find_similar_lines(Arc::clone(&documents), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
+    let path = std::env::temp_dir().join("find_diff_unchanged_test.json");
+    incremental::save(&path, &documents, &matches, DEFAULT_SIMILARITY_THRESHOLD).unwrap();
+    let previous = incremental::load(&path).unwrap();
+    fs::remove_file(path).unwrap();
+
+    let plan = incremental::plan(previous.as_ref(), &documents, DEFAULT_SIMILARITY_THRESHOLD);
+    assert!(plan.scope.is_none());
+    assert_eq!(plan.reused, matches);
+
+    let plan = incremental::plan(previous.as_ref(), &documents, 0.9);
+    assert!(matches!(plan.scope, Some(CompareScope::All)));
+    assert!(plan.reused.is_empty());
+  }
+
+  #[tokio::test]
+  async fn writes_json_groups_with_scores() {
+    let documents = Arc::new(test_documents(&[
+      ("a", &["Object-Oriented 2025.pdf"][..]),
+      ("b", &["Object-Oriented 2026.pdf"][..]),
+    ]));
+    let matches = find_similar_lines(Arc::clone(&documents), DEFAULT_SIMILARITY_THRESHOLD)
+      .await
+      .unwrap();
+    let groups = group_matches(&pairs(&matches));
+    let path = std::env::temp_dir().join("find_diff_json_test.json");
+
+    let written_groups = report::write_json(&path, &documents, &groups, &matches).unwrap();
+
+    let output: serde_json::Value =
+      serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    fs::remove_file(path).unwrap();
+    assert_eq!(written_groups, 1);
+    assert_eq!(output[0]["kind"], "similar");
+    assert_eq!(output[0]["lines"][0]["document"], "a");
+    assert_eq!(output[0]["lines"][1]["text"], "Object-Oriented 2026.pdf");
+    assert_eq!(output[0]["pairs"][0]["score"], matches[0].score);
+    assert!(matches[0].score >= DEFAULT_SIMILARITY_THRESHOLD);
+  }
+
+  fn pairs(matches: &[LineMatch]) -> Vec<LinePair> {
+    matches.iter().map(|item| item.pair).collect()
+  }
 }
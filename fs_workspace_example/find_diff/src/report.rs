@@ -0,0 +1,200 @@
+//! Group filters and the JSON and CSV output formats.
+
+use std::{
+  collections::{HashMap, HashSet},
+  fs::File,
+  io::{self, BufWriter, Write},
+  path::Path,
+};
+
+use serde::Serialize;
+
+use super::{IndexedDocuments, LineMatch, arrange_groups, cli::Args};
+
+/// Which match groups end up in the output.
+#[derive(Debug, Default)]
+pub(crate) struct GroupFilter {
+  documents: HashSet<String>,
+  contains: Option<String>,
+  cross_document: bool,
+}
+
+impl GroupFilter {
+  pub(crate) fn from_args(args: &Args) -> Self {
+    Self {
+      documents: args.documents.iter().cloned().collect(),
+      contains: args.contains.as_ref().map(|text| text.to_lowercase()),
+      cross_document: args.cross_document,
+    }
+  }
+
+  pub(crate) fn keeps(&self, documents: &IndexedDocuments, group: &[usize]) -> bool {
+    let names = group
+      .iter()
+      .map(|&line_index| documents.file_name(line_index))
+      .collect::<HashSet<_>>();
+
+    if self.cross_document && names.len() < 2 {
+      return false;
+    }
+    if !self.documents.is_empty() && !names.iter().any(|name| self.documents.contains(*name)) {
+      return false;
+    }
+    match &self.contains {
+      Some(text) => group.iter().any(|&line_index| {
+        documents
+          .line(line_index)
+          .text
+          .to_lowercase()
+          .contains(text)
+      }),
+      None => true,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum GroupKind {
+  Similar,
+  /// The same file in one document, only differing by file suffix.
+  PossibleFormatDuplicate,
+}
+
+#[derive(Debug, Serialize)]
+struct GroupReport<'a> {
+  kind: GroupKind,
+  lines: Vec<LineReport<'a>>,
+  pairs: Vec<PairReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct LineReport<'a> {
+  document: &'a str,
+  text: &'a str,
+  /// Highest score of the matches this line is part of.
+  best_score: f64,
+}
+
+/// A match between two lines of the group, by position in `lines`.
+#[derive(Debug, Serialize)]
+struct PairReport {
+  left: usize,
+  right: usize,
+  score: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CsvRow<'a> {
+  group: usize,
+  kind: GroupKind,
+  document: &'a str,
+  text: &'a str,
+  best_score: f64,
+}
+
+pub(crate) fn write_json(
+  path: impl AsRef<Path>,
+  documents: &IndexedDocuments,
+  groups: &[Vec<usize>],
+  matches: &[LineMatch],
+) -> io::Result<usize> {
+  let reports = group_reports(documents, groups, matches);
+  let mut writer = BufWriter::new(File::create(path)?);
+  serde_json::to_writer_pretty(&mut writer, &reports)?;
+  writeln!(writer)?;
+  writer.flush()?;
+  Ok(reports.len())
+}
+
+pub(crate) fn write_csv(
+  path: impl AsRef<Path>,
+  documents: &IndexedDocuments,
+  groups: &[Vec<usize>],
+  matches: &[LineMatch],
+) -> io::Result<usize> {
+  let reports = group_reports(documents, groups, matches);
+  let mut writer = csv::Writer::from_path(path)?;
+  for (group, report) in reports.iter().enumerate() {
+    for line in &report.lines {
+      writer.serialize(CsvRow {
+        group,
+        kind: report.kind,
+        document: line.document,
+        text: line.text,
+        best_score: line.best_score,
+      })?;
+    }
+  }
+  writer.flush()?;
+  Ok(reports.len())
+}
+
+/// Groups in the order of the text output, each with the matches between its
+/// lines.
+fn group_reports<'a>(
+  documents: &'a IndexedDocuments,
+  groups: &[Vec<usize>],
+  matches: &[LineMatch],
+) -> Vec<GroupReport<'a>> {
+  let (similar, possible_format_duplicates) = arrange_groups(documents, groups);
+  let arranged = similar
+    .into_iter()
+    .map(|group| (GroupKind::Similar, group))
+    .chain(
+      possible_format_duplicates
+        .into_iter()
+        .map(|group| (GroupKind::PossibleFormatDuplicate, group)),
+    )
+    .collect::<Vec<_>>();
+
+  let mut positions = HashMap::new();
+  let mut reports = Vec::with_capacity(arranged.len());
+  for (group_index, (kind, group)) in arranged.iter().enumerate() {
+    let lines = group
+      .iter()
+      .enumerate()
+      .map(|(position, &line_index)| {
+        positions.insert(line_index, (group_index, position));
+        LineReport {
+          document: documents.file_name(line_index),
+          text: &documents.line(line_index).text,
+          best_score: 0.0,
+        }
+      })
+      .collect();
+    reports.push(GroupReport {
+      kind: *kind,
+      lines,
+      pairs: Vec::new(),
+    });
+  }
+
+  for item in matches {
+    let (Some(&(left_group, left)), Some(&(right_group, right))) = (
+      positions.get(&item.pair.left),
+      positions.get(&item.pair.right),
+    ) else {
+      continue;
+    };
+    if left_group != right_group {
+      continue;
+    }
+
+    let report = &mut reports[left_group];
+    for position in [left, right] {
+      let line = &mut report.lines[position];
+      line.best_score = line.best_score.max(item.score);
+    }
+    report.pairs.push(PairReport {
+      left: left.min(right),
+      right: left.max(right),
+      score: item.score,
+    });
+  }
+
+  for report in &mut reports {
+    report.pairs.sort_by_key(|pair| (pair.left, pair.right));
+  }
+  reports
+}
@@ -1,4 +1,5 @@
 mod map;
+mod migration;
 mod player;
 mod tcp_monitor;
 
@@ -6,10 +7,15 @@ use std::{collections::HashSet, time::Duration};
 
 use clap::Parser;
 use kameo::{actor::RemoteActorRef, prelude::*, remote};
-use map::{GetAllPlayers, GivePlayerBuff, HitPlayer, MapActor, MapConfig};
+use kameo_supervisor::{
+  ChildSpec, Cluster, GetChild, StartChild, Supervisor, SupervisorConfig, WatchRemote,
+  WhichChildren,
+};
+use map::{GetAllPlayers, GivePlayerBuff, HitPlayer, MapActor, MapConfig, MapRuntime};
+use migration::{Migrator, PlayerHost, host_name, map_name, node_available, player_name};
 use player::{
-  Damage, EnterMap, GetPlayerView, InitialCombatStats, InitialMapStats, PlayerActor, PlayerBuff,
-  PlayerId, PlayerProfile,
+  Damage, GetPlayerView, InitialCombatStats, InitialMapStats, PlayerActor, PlayerBuff, PlayerId,
+  PlayerProfile, PlayerSeed,
 };
 use rand::{RngExt, SeedableRng, rngs::StdRng};
 use tcp_monitor::{BindTcpConnection, GetTcpConnections, TcpConnectionMonitor, TcpDisconnected};
@@ -36,6 +42,29 @@ struct Cli {
   /// Run the startup demo once and exit instead of keeping the node alive.
   #[arg(long, default_value_t = false)]
   run_once: bool,
+
+  /// Rebalance after the startup demo: move local players to other nodes,
+  /// e.g. 1004=node-b.
+  #[arg(long, value_delimiter = ',', value_parser = parse_migration)]
+  migrate: Vec<Migration>,
+}
+
+#[derive(Clone, Debug)]
+struct Migration {
+  player_id: PlayerId,
+  target_node: String,
+}
+
+fn parse_migration(value: &str) -> Result<Migration, String> {
+  let (player_id, target_node) = value
+    .split_once('=')
+    .ok_or_else(|| format!("expected <PLAYER_ID>=<NODE_ID>, got {value:?}"))?;
+  Ok(Migration {
+    player_id: player_id
+      .parse()
+      .map_err(|err| format!("invalid player id {player_id:?}: {err}"))?,
+    target_node: target_node.to_string(),
+  })
 }
 
 #[derive(Clone, Copy)]
@@ -55,12 +84,6 @@ struct PlayerRuntime {
   connection_id: String,
 }
 
-struct MapRuntime {
-  id: String,
-  actor: ActorRef<MapActor>,
-  remote: RemoteActorRef<MapActor>,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let cli = Cli::parse();
@@ -79,6 +102,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let tcp_monitor =
     TcpConnectionMonitor::spawn(TcpConnectionMonitor::new(Duration::from_secs(180)));
 
+  // Players on this node are supervised here; players on peer nodes are
+  // watched so one survivor can adopt them if their node goes away.
+  let supervisor = Supervisor::spawn(
+    SupervisorConfig::new(format!("players@{}", cli.node_id))
+      .cluster(Cluster::new(cli.node_id.clone(), cli.nodes.clone())),
+  );
+
+  // Peers hand players to this node through its host.
+  let migrator = Migrator::new(
+    cli.node_id.clone(),
+    supervisor.clone(),
+    maps.clone(),
+    tcp_monitor.clone(),
+  );
+  let host = PlayerHost::spawn(PlayerHost::new(migrator.clone()));
+  host.register(host_name(&cli.node_id).as_str()).await?;
+
   let assignments = assign_players(&player_configs(), &cli.nodes, cli.seed);
   print_assignments(&cli.node_id, &assignments);
 
@@ -86,31 +126,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .iter()
     .filter_map(|(player, node_id)| (node_id == &cli.node_id).then_some(*player))
     .collect();
+  let peer_players: Vec<(PlayerConfig, String)> = assignments
+    .into_iter()
+    .filter(|(_, node_id)| node_id != &cli.node_id)
+    .collect();
 
-  let mut players = spawn_local_players(&cli.node_id, local_players, &maps, &tcp_monitor).await?;
-
-  for runtime in &players {
-    let map = find_map(&maps, &runtime.map_id).expect("player map exists");
-    let join_info = runtime
-      .actor
-      .ask(EnterMap {
-        map: map.remote.clone(),
-      })
-      .await?;
-    println!(
-      "[node:{}] player:{} joined map:{}; own state: {:?} | {} other(s): {:?}",
-      cli.node_id,
-      runtime.id,
-      join_info.map_id,
-      join_info.own_state,
-      join_info.other_players.len(),
-      join_info
-        .other_players
-        .iter()
-        .map(|p| p.profile.name.as_str())
-        .collect::<Vec<_>>(),
-    );
-  }
+  let mut players = spawn_local_players(
+    &cli.node_id,
+    local_players,
+    &maps,
+    &tcp_monitor,
+    &supervisor,
+  )
+  .await?;
+  let peer_watch = spawn_peer_player_watch(&cli.node_id, peer_players, &maps, &supervisor);
 
   if let Some(first_player) = players.first() {
     let map = find_map(&maps, &first_player.map_id).expect("player map exists");
@@ -171,6 +200,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
   }
 
+  if let Some(crashed_player) = players.first_mut() {
+    simulate_player_crash(&cli.node_id, &supervisor, crashed_player).await?;
+  }
+
   if players.len() >= 2 {
     let reconnect_player = &mut players[1];
     let disconnected = tcp_monitor
@@ -234,9 +267,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
   }
 
+  if !cli.migrate.is_empty() {
+    rebalance(&cli.node_id, &cli.migrate, &migrator, &maps, &players).await?;
+  }
+
   if cli.run_once {
     abort_peer_discovery(peer_discovery);
-    shutdown(maps, tcp_monitor, players).await?;
+    abort_peer_discovery(peer_watch);
+    shutdown(maps, tcp_monitor, host, supervisor).await?;
     return Ok(());
   }
 
@@ -248,7 +286,139 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   println!("[node:{}] shutting down ...", cli.node_id);
 
   abort_peer_discovery(peer_discovery);
-  shutdown(maps, tcp_monitor, players).await?;
+  abort_peer_discovery(peer_watch);
+  drain_players(&cli, &migrator, &supervisor).await?;
+  shutdown(maps, tcp_monitor, host, supervisor).await?;
+  Ok(())
+}
+
+/// Moves the requested players while the maps keep taking commands for them.
+/// One buff and one hit are sent to each player mid-flight; the map holds
+/// them during the handoff and forwards them to the player's new map.
+async fn rebalance(
+  node_id: &str,
+  migrations: &[Migration],
+  migrator: &Migrator,
+  maps: &[MapRuntime],
+  players: &[PlayerRuntime],
+) -> Result<(), Box<dyn std::error::Error>> {
+  for Migration {
+    player_id,
+    target_node,
+  } in migrations
+  {
+    let Some(runtime) = players.iter().find(|player| player.id == *player_id) else {
+      println!("[node:{node_id}] player:{player_id} is not local; skipping its migration");
+      continue;
+    };
+    if !wait_for_node(target_node).await {
+      println!("[node:{node_id}] {target_node} never came up; player:{player_id} stays here");
+      continue;
+    }
+    let map = find_map(maps, &runtime.map_id).expect("player map exists");
+
+    let in_flight = {
+      let map = map.actor.clone();
+      let attacker_id = find_hit_target(players, &runtime.map_id, *player_id);
+      let player_id = *player_id;
+      async move {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let buff = map.ask(GivePlayerBuff {
+          player_id,
+          buff: PlayerBuff::new(
+            "farewell-blessing",
+            Damage { red: 1, blue: 1 },
+            Damage { red: 1, blue: 1 },
+          ),
+        });
+        let hit = map.ask(HitPlayer {
+          attacker_id,
+          target_id: player_id,
+          bonus_damage: Damage { red: 2, blue: 2 },
+        });
+        tokio::join!(buff, hit)
+      }
+    };
+    let (migrated, (buff, hit)) =
+      tokio::join!(migrator.migrate(*player_id, target_node), in_flight);
+    match migrated {
+      Ok(()) => println!("[node:{node_id}] player:{player_id} migrated to {target_node}"),
+      Err(err) => println!("[node:{node_id}] player:{player_id} did not migrate: {err}"),
+    }
+    println!(
+      "[node:{node_id}] in-flight buff for player:{player_id}: {:?}",
+      buff?.map(|report| report.state_after_buff.buffs.len())
+    );
+    println!(
+      "[node:{node_id}] in-flight hit on player:{player_id}: {:?}",
+      hit?.map(|report| report.state_after_hit.stats)
+    );
+  }
+
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  for map in maps {
+    let members: Vec<String> = map
+      .actor
+      .ask(GetAllPlayers)
+      .await?
+      .into_iter()
+      .map(|player| player.profile.name)
+      .collect();
+    println!(
+      "[node:{node_id}] map:{} members after rebalance: {members:?}",
+      map.id
+    );
+  }
+  Ok(())
+}
+
+async fn wait_for_node(node_id: &str) -> bool {
+  for _ in 0 .. 15 {
+    if node_available(node_id).await {
+      return true;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+  }
+  false
+}
+
+/// A planned leave: every player supervised here moves to the node that
+/// rendezvous hashing picks among the others, so nothing is lost when this
+/// node stops.
+async fn drain_players(
+  cli: &Cli,
+  migrator: &Migrator,
+  supervisor: &ActorRef<Supervisor>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut cluster = Cluster::new(cli.node_id.clone(), cli.nodes.clone());
+  cluster.mark_down(&cli.node_id);
+
+  for child in supervisor.ask(WhichChildren).await? {
+    let Some(player_id) = child
+      .watched_on
+      .is_none()
+      .then(|| child.id.strip_prefix("player:")?.parse::<PlayerId>().ok())
+      .flatten()
+    else {
+      continue;
+    };
+    let Some(target_node) = cluster.owner(&child.id).map(str::to_string) else {
+      break;
+    };
+    if !node_available(&target_node).await {
+      println!(
+        "[node:{}] {target_node} is not reachable; player:{player_id} stops with this node",
+        cli.node_id
+      );
+      continue;
+    }
+    if let Err(err) = migrator.migrate(player_id, &target_node).await {
+      println!(
+        "[node:{}] could not hand off player:{player_id}: {err}",
+        cli.node_id
+      );
+    }
+  }
   Ok(())
 }
 
@@ -260,7 +430,7 @@ async fn spawn_maps(
 
   for config in configs {
     let map_id = config.id.clone();
-    let map_name = format!("map:{node_id}:{map_id}");
+    let map_name = map_name(node_id, &map_id);
     let map = MapActor::spawn(MapActor::new(config));
     map.register(map_name.as_str()).await?;
     let remote = map.into_remote_ref().await;
@@ -301,7 +471,7 @@ fn spawn_peer_map_discovery(
     loop {
       for peer_node in &peer_nodes {
         for map_id in &map_ids {
-          let map_name = format!("map:{peer_node}:{map_id}");
+          let map_name = map_name(peer_node, map_id);
           match RemoteActorRef::<MapActor>::lookup(map_name.as_str()).await {
             Ok(Some(peer_map)) => match peer_map.ask(&GetAllPlayers).await {
               Ok(players) => {
@@ -472,29 +642,17 @@ async fn spawn_local_players(
   players: Vec<PlayerConfig>,
   maps: &[MapRuntime],
   tcp_monitor: &ActorRef<TcpConnectionMonitor>,
+  supervisor: &ActorRef<Supervisor>,
 ) -> Result<Vec<PlayerRuntime>, Box<dyn std::error::Error>> {
   let mut runtimes = Vec::new();
   for config in players {
     let map_id = assign_player_map(config.id, maps).to_string();
-    let player = PlayerActor::spawn(PlayerActor {
-      profile: PlayerProfile {
-        id: config.id,
-        name: config.name.to_string(),
-      },
-      initial_map_stats: InitialMapStats {
-        red: config.red,
-        blue: config.blue,
-      },
-      initial_combat_stats: InitialCombatStats {
-        base_attack: config.base_attack,
-        base_damage: config.base_damage,
-      },
-      map_mirror: None,
-    });
-
-    let player_name = format!("player:{}", config.id);
-    player.register(player_name.as_str()).await?;
-    println!("[node:{node_id}] PlayerActor registered as \"{player_name}\"");
+    let spec = player_spec(node_id, config, maps);
+    let player = supervisor.ask(StartChild(spec)).await?;
+    println!(
+      "[node:{node_id}] PlayerActor registered as \"{}\"",
+      player_name(config.id)
+    );
 
     let connection_id = format!("tcp:{node_id}:{}:session-1", config.id);
     let bind = tcp_monitor
@@ -516,6 +674,111 @@ async fn spawn_local_players(
   Ok(runtimes)
 }
 
+fn player_spec(node_id: &str, config: PlayerConfig, maps: &[MapRuntime]) -> ChildSpec<PlayerActor> {
+  let map = find_map(maps, assign_player_map(config.id, maps)).expect("player map exists");
+  migration::player_spec(node_id, player_seed(config), map.remote.clone())
+}
+
+fn player_seed(config: PlayerConfig) -> PlayerSeed {
+  PlayerSeed {
+    profile: PlayerProfile {
+      id: config.id,
+      name: config.name.to_string(),
+    },
+    initial_map_stats: InitialMapStats {
+      red: config.red,
+      blue: config.blue,
+    },
+    initial_combat_stats: InitialCombatStats {
+      base_attack: config.base_attack,
+      base_damage: config.base_damage,
+    },
+  }
+}
+
+/// Watches every player assigned to a peer node once it shows up in the
+/// registry. If that node disconnects, the supervisor adopts the player here
+/// when rendezvous hashing picks this node.
+fn spawn_peer_player_watch(
+  node_id: &str,
+  players: Vec<(PlayerConfig, String)>,
+  maps: &[MapRuntime],
+  supervisor: &ActorRef<Supervisor>,
+) -> Option<JoinHandle<()>> {
+  if players.is_empty() {
+    return None;
+  }
+
+  let node_id = node_id.to_string();
+  let supervisor = supervisor.clone();
+  let mut pending: Vec<(PlayerId, String, ChildSpec<PlayerActor>)> = players
+    .into_iter()
+    .map(|(config, home_node)| (config.id, home_node, player_spec(&node_id, config, maps)))
+    .collect();
+
+  Some(tokio::spawn(async move {
+    while !pending.is_empty() {
+      let mut waiting = Vec::new();
+      for (player_id, home_node, failover) in pending {
+        let name = player_name(player_id);
+        let watched = match RemoteActorRef::<PlayerActor>::lookup(name.as_str()).await {
+          Ok(Some(actor)) => supervisor
+            .ask(WatchRemote {
+              name: name.clone(),
+              home_node: home_node.clone(),
+              actor,
+              failover: failover.clone(),
+            })
+            .await
+            .inspect_err(|err| eprintln!("[node:{node_id}] cannot watch {name}: {err}"))
+            .is_ok(),
+          Ok(None) | Err(_) => false,
+        };
+        if !watched {
+          waiting.push((player_id, home_node, failover));
+        }
+      }
+      pending = waiting;
+      tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+  }))
+}
+
+/// Kills a player as if it had crashed and waits for the supervisor to bring
+/// it back under the same name.
+async fn simulate_player_crash(
+  node_id: &str,
+  supervisor: &ActorRef<Supervisor>,
+  runtime: &mut PlayerRuntime,
+) -> Result<(), Box<dyn std::error::Error>> {
+  println!(
+    "[node:{node_id}] simulating a crash of player:{}",
+    runtime.id
+  );
+  runtime.actor.kill();
+  runtime.actor.wait_for_shutdown().await;
+
+  for _ in 0 .. 50 {
+    let restarted = supervisor
+      .ask(GetChild::<PlayerActor>::new(player_name(runtime.id)))
+      .await?;
+    if let Some(restarted) = restarted
+      && restarted.id() != runtime.actor.id()
+    {
+      let view = restarted.ask(GetPlayerView).await?;
+      println!(
+        "[node:{node_id}] player:{} restarted by its supervisor; state from map: {:?}",
+        runtime.id,
+        view.map_mirror.map(|mirror| mirror.own_state)
+      );
+      runtime.actor = restarted;
+      return Ok(());
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  Err(format!("player:{} was not restarted", runtime.id).into())
+}
+
 fn assign_player_map(player_id: PlayerId, maps: &[MapRuntime]) -> &str {
   let index = player_id as usize % maps.len();
   maps[index].id.as_str()
@@ -535,15 +798,19 @@ fn find_hit_target(players: &[PlayerRuntime], map_id: &str, attacker_id: PlayerI
 async fn shutdown(
   maps: Vec<MapRuntime>,
   tcp_monitor: ActorRef<TcpConnectionMonitor>,
-  players: Vec<PlayerRuntime>,
+  host: ActorRef<PlayerHost>,
+  supervisor: ActorRef<Supervisor>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+  host.stop_gracefully().await?;
+  host.wait_for_shutdown().await;
+
   tcp_monitor.stop_gracefully().await?;
   tcp_monitor.wait_for_shutdown().await;
 
-  for runtime in players {
-    runtime.actor.stop_gracefully().await?;
-    runtime.actor.wait_for_shutdown().await;
-  }
+  // Stopping the supervisor stops its players in reverse start order; a
+  // player stopped directly would just be restarted.
+  supervisor.stop_gracefully().await?;
+  supervisor.wait_for_shutdown().await;
 
   for map in maps {
     map.actor.stop_gracefully().await?;
@@ -3,8 +3,13 @@
 //! Player actors send commands (join, hit in this demo). The map validates and
 //! mutates map-owned state, then emits events back to player actors. Player
 //! actors keep only a mirror for display/session purposes.
+//!
+//! When a player migrates, its map state is handed to the same map on the
+//! target node. Commands that touch the player while the handoff is running
+//! are held, and once it completes they (and any later ones) are forwarded to
+//! the map that now owns the player.
 
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, mem, time::Duration};
 
 use kameo::{actor::RemoteActorRef, prelude::*};
 use serde::{Deserialize, Serialize};
@@ -23,6 +28,18 @@ pub(crate) struct MapActor {
   map_id: String,
   entry_buffs: Vec<PlayerBuff>,
   players: HashMap<PlayerId, PlayerInMap>,
+  /// Commands waiting for a player's handoff to finish, in arrival order.
+  held: Vec<HeldCommand>,
+  /// Where players that migrated away now live.
+  forwards: HashMap<PlayerId, RemoteActorRef<MapActor>>,
+}
+
+/// A local map actor and its remote handle, as registered on this node.
+#[derive(Clone)]
+pub(crate) struct MapRuntime {
+  pub(crate) id: String,
+  pub(crate) actor: ActorRef<MapActor>,
+  pub(crate) remote: RemoteActorRef<MapActor>,
 }
 
 #[derive(Clone, Debug)]
@@ -44,7 +61,11 @@ impl MapConfig {
 
 enum PlayerStatus {
   Online,
-  Offline { abort_handle: AbortHandle },
+  Offline {
+    abort_handle: AbortHandle,
+  },
+  /// Frozen while its state is being handed to another node.
+  Migrating,
 }
 
 struct PlayerInMap {
@@ -54,7 +75,7 @@ struct PlayerInMap {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct MapPlayerState {
+pub(crate) struct MapPlayerState {
   profile: PlayerProfile,
   stats: MapStats,
   base_attack: Damage,
@@ -74,6 +95,13 @@ impl MapPlayerState {
     }
   }
 
+  fn hit_attacker(&self) -> HitAttacker {
+    HitAttacker {
+      name: self.profile.name.clone(),
+      effective_combat: self.effective_combat(),
+    }
+  }
+
   fn add_buff(&mut self, buff: PlayerBuff) {
     self.buffs.push(buff);
   }
@@ -225,13 +253,60 @@ pub(crate) struct BuffReport {
 
 #[remote_message("kameo_game_sync::MapActor::GivePlayerBuff")]
 impl Message<GivePlayerBuff> for MapActor {
-  type Reply = Option<BuffReport>;
+  type Reply = DelegatedReply<Option<BuffReport>>;
 
   async fn handle(
     &mut self,
-    GivePlayerBuff { player_id, buff }: GivePlayerBuff,
+    msg: GivePlayerBuff,
     ctx: &mut Context<Self, Self::Reply>,
   ) -> Self::Reply {
+    let (delegated, reply) = ctx.reply_sender();
+    self.dispatch_buff(ctx.actor_ref(), msg, reply);
+    delegated
+  }
+}
+
+impl MapActor {
+  fn dispatch_buff(
+    &mut self,
+    map_ref: &ActorRef<Self>,
+    msg: GivePlayerBuff,
+    reply: Option<ReplySender<Option<BuffReport>>>,
+  ) {
+    if self.is_migrating(msg.player_id) {
+      println!(
+        "[map:{}] holding buff \"{}\" for player:{} until its handoff completes",
+        self.map_id, msg.buff.name, msg.player_id
+      );
+      self.held.push(HeldCommand::Buff(msg, reply));
+      return;
+    }
+
+    let Some(target_map) = self.forwards.get(&msg.player_id).cloned() else {
+      send_reply(reply, self.apply_buff(map_ref, msg));
+      return;
+    };
+    let map_id = self.map_id.clone();
+    tokio::spawn(async move {
+      println!(
+        "[map:{map_id}] forwarding buff \"{}\" for player:{} to its new map",
+        msg.buff.name, msg.player_id
+      );
+      let report = target_map
+        .ask(&msg)
+        .await
+        .inspect_err(|err| println!("[map:{map_id}] forwarded buff failed: {err}"))
+        .ok()
+        .flatten();
+      send_reply(reply, report);
+    });
+  }
+
+  fn apply_buff(
+    &mut self,
+    map_ref: &ActorRef<Self>,
+    GivePlayerBuff { player_id, buff }: GivePlayerBuff,
+  ) -> Option<BuffReport> {
     let player = self.players.get_mut(&player_id)?;
     player.state.add_buff(buff.clone());
     let state_after_buff = player.state.view();
@@ -249,7 +324,7 @@ impl Message<GivePlayerBuff> for MapActor {
     );
 
     self.broadcast_event(
-      ctx.actor_ref(),
+      map_ref,
       player_id,
       MapEvent::PlayerStateChanged {
         map_id: self.map_id.clone(),
@@ -284,7 +359,7 @@ pub(crate) struct HitReport {
 
 #[remote_message("kameo_game_sync::MapActor::HitPlayer")]
 impl Message<HitPlayer> for MapActor {
-  type Reply = Option<HitReport>;
+  type Reply = DelegatedReply<Option<HitReport>>;
 
   async fn handle(
     &mut self,
@@ -295,9 +370,141 @@ impl Message<HitPlayer> for MapActor {
     }: HitPlayer,
     ctx: &mut Context<Self, Self::Reply>,
   ) -> Self::Reply {
-    let attacker = self.players.get(&attacker_id)?;
-    let attacker_name = attacker.state.profile.name.clone();
-    let effective_combat = attacker.state.effective_combat();
+    let (delegated, reply) = ctx.reply_sender();
+    self.dispatch_hit(
+      ctx.actor_ref(),
+      PendingHit {
+        attacker_id,
+        target_id,
+        bonus_damage,
+        attacker: None,
+      },
+      reply,
+    );
+    delegated
+  }
+}
+
+/// The attacker's side of a hit, resolved by whichever map holds the attacker.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct HitAttacker {
+  pub(crate) name: String,
+  pub(crate) effective_combat: EffectiveCombat,
+}
+
+/// A hit whose attacker was resolved on another node's map, sent to the map
+/// that holds the target after one of the two migrated.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ApplyHit {
+  pub(crate) attacker_id: PlayerId,
+  pub(crate) attacker: HitAttacker,
+  pub(crate) target_id: PlayerId,
+  pub(crate) bonus_damage: Damage,
+}
+
+#[remote_message("kameo_game_sync::MapActor::ApplyHit")]
+impl Message<ApplyHit> for MapActor {
+  type Reply = DelegatedReply<Option<HitReport>>;
+
+  async fn handle(
+    &mut self,
+    ApplyHit {
+      attacker_id,
+      attacker,
+      target_id,
+      bonus_damage,
+    }: ApplyHit,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let (delegated, reply) = ctx.reply_sender();
+    self.dispatch_hit(
+      ctx.actor_ref(),
+      PendingHit {
+        attacker_id,
+        target_id,
+        bonus_damage,
+        attacker: Some(attacker),
+      },
+      reply,
+    );
+    delegated
+  }
+}
+
+struct PendingHit {
+  attacker_id: PlayerId,
+  target_id: PlayerId,
+  bonus_damage: Damage,
+  /// Already resolved by another map; otherwise looked up here.
+  attacker: Option<HitAttacker>,
+}
+
+impl MapActor {
+  fn dispatch_hit(
+    &mut self,
+    map_ref: &ActorRef<Self>,
+    mut hit: PendingHit,
+    reply: Option<ReplySender<Option<HitReport>>>,
+  ) {
+    let attacker_migrating = hit.attacker.is_none() && self.is_migrating(hit.attacker_id);
+    if attacker_migrating || self.is_migrating(hit.target_id) {
+      println!(
+        "[map:{}] holding hit player:{} -> player:{} until the handoff completes",
+        self.map_id, hit.attacker_id, hit.target_id
+      );
+      self.held.push(HeldCommand::Hit(hit, reply));
+      return;
+    }
+
+    if hit.attacker.is_none() {
+      hit.attacker = self
+        .players
+        .get(&hit.attacker_id)
+        .map(|attacker| attacker.state.hit_attacker());
+    }
+    let target_map = self.forwards.get(&hit.target_id).cloned();
+    if let (Some(attacker), None) = (&hit.attacker, &target_map) {
+      let report = self.apply_hit(map_ref, &hit, attacker.clone());
+      send_reply(reply, report);
+      return;
+    }
+
+    let attacker_map = match hit.attacker {
+      Some(_) => None,
+      None => match self.forwards.get(&hit.attacker_id) {
+        Some(attacker_map) => Some(attacker_map.clone()),
+        None => {
+          send_reply(reply, None);
+          return;
+        }
+      },
+    };
+    let map_ref = map_ref.clone();
+    let map_id = self.map_id.clone();
+    tokio::spawn(async move {
+      println!(
+        "[map:{map_id}] forwarding hit player:{} -> player:{} across nodes",
+        hit.attacker_id, hit.target_id
+      );
+      let report = forward_hit(map_ref, attacker_map, target_map, hit).await;
+      send_reply(reply, report);
+    });
+  }
+
+  fn apply_hit(
+    &mut self,
+    map_ref: &ActorRef<Self>,
+    &PendingHit {
+      attacker_id,
+      target_id,
+      bonus_damage,
+      ..
+    }: &PendingHit,
+    HitAttacker {
+      name: attacker_name,
+      effective_combat,
+    }: HitAttacker,
+  ) -> Option<HitReport> {
     let total_damage = effective_combat.total_damage(bonus_damage);
 
     let target = self.players.get_mut(&target_id)?;
@@ -322,7 +529,7 @@ impl Message<HitPlayer> for MapActor {
     );
 
     self.broadcast_event(
-      ctx.actor_ref(),
+      map_ref,
       target_id,
       MapEvent::PlayerStateChanged {
         map_id: self.map_id.clone(),
@@ -340,6 +547,241 @@ impl Message<HitPlayer> for MapActor {
   }
 }
 
+/// Resolves a hit when the attacker, the target or both have moved to other
+/// nodes: the attacker's combat stats come from the map that holds it, and
+/// the damage is applied by the map that holds the target.
+async fn forward_hit(
+  map_ref: ActorRef<MapActor>,
+  attacker_map: Option<RemoteActorRef<MapActor>>,
+  target_map: Option<RemoteActorRef<MapActor>>,
+  hit: PendingHit,
+) -> Option<HitReport> {
+  let attacker = match (hit.attacker, attacker_map) {
+    (Some(attacker), _) => attacker,
+    (None, Some(attacker_map)) => {
+      let view = attacker_map
+        .ask(&GetMapPlayer {
+          player_id: hit.attacker_id,
+        })
+        .await
+        .ok()
+        .flatten()?;
+      HitAttacker {
+        name: view.profile.name,
+        effective_combat: view.effective_combat,
+      }
+    }
+    (None, None) => return None,
+  };
+
+  let apply = ApplyHit {
+    attacker_id: hit.attacker_id,
+    attacker,
+    target_id: hit.target_id,
+    bonus_damage: hit.bonus_damage,
+  };
+  let report = match target_map {
+    Some(target_map) => target_map.ask(&apply).await.map_err(|err| err.to_string()),
+    None => map_ref.ask(apply).await.map_err(|err| err.to_string()),
+  };
+  report
+    .inspect_err(|err| println!("[map] forwarded hit failed: {err}"))
+    .ok()
+    .flatten()
+}
+
+// ── Handoff ──────────────────────────────────────────────────────────────────
+
+/// Freezes a player for migration and returns its authoritative state. From
+/// now on commands touching the player are held. `None` if the player is not
+/// here or is already migrating.
+pub(crate) struct BeginHandoff {
+  pub(crate) player_id: PlayerId,
+}
+
+impl Message<BeginHandoff> for MapActor {
+  type Reply = Option<MapPlayerState>;
+
+  async fn handle(
+    &mut self,
+    BeginHandoff { player_id }: BeginHandoff,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let player = self.players.get_mut(&player_id)?;
+    match &player.status {
+      PlayerStatus::Migrating => return None,
+      PlayerStatus::Offline { abort_handle } => abort_handle.abort(),
+      PlayerStatus::Online => {}
+    }
+    player.status = PlayerStatus::Migrating;
+    println!(
+      "[map:{}] {} is migrating; holding commands that touch it",
+      self.map_id, player.state.profile.name
+    );
+    Some(player.state.clone())
+  }
+}
+
+/// Target side: takes over a migrating player's state. The player actor joins
+/// right after and finds its stats and buffs already here.
+pub(crate) struct AcceptHandoff {
+  pub(crate) from_node: String,
+  pub(crate) state: MapPlayerState,
+}
+
+impl Message<AcceptHandoff> for MapActor {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    AcceptHandoff { from_node, state }: AcceptHandoff,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let player_id = state.profile.id;
+    self.forwards.remove(&player_id);
+    let view = state.view();
+    println!(
+      "[map:{}] {} handed over from {from_node}: red={}, blue={}, buffs={}",
+      self.map_id,
+      view.profile.name,
+      view.stats.red,
+      view.stats.blue,
+      view.buffs.len(),
+    );
+
+    let previous = self.players.insert(
+      player_id,
+      PlayerInMap {
+        player_name: format!("player:{player_id}"),
+        state,
+        status: PlayerStatus::Online,
+      },
+    );
+    let event = match previous {
+      Some(previous) => {
+        if let PlayerStatus::Offline { abort_handle } = previous.status {
+          abort_handle.abort();
+        }
+        MapEvent::PlayerStateChanged {
+          map_id: self.map_id.clone(),
+          player: view,
+        }
+      }
+      None => MapEvent::PlayerJoined {
+        map_id: self.map_id.clone(),
+        player: view,
+      },
+    };
+    self.broadcast_event(ctx.actor_ref(), player_id, event);
+  }
+}
+
+/// Source side: the player lives in `target_map` now. It leaves this map, and
+/// held and future commands for it are forwarded there.
+pub(crate) struct CompleteHandoff {
+  pub(crate) player_id: PlayerId,
+  pub(crate) target_node: String,
+  pub(crate) target_map: RemoteActorRef<MapActor>,
+}
+
+impl Message<CompleteHandoff> for MapActor {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    CompleteHandoff {
+      player_id,
+      target_node,
+      target_map,
+    }: CompleteHandoff,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    if let Some(player) = self.players.remove(&player_id) {
+      println!(
+        "[map:{}] {} migrated to {target_node}; {} held command(s) will follow it",
+        self.map_id,
+        player.state.profile.name,
+        self.held.len()
+      );
+      self.broadcast_event(
+        ctx.actor_ref(),
+        player_id,
+        MapEvent::PlayerLeft {
+          map_id: self.map_id.clone(),
+          player_id,
+        },
+      );
+    }
+    self.forwards.insert(player_id, target_map);
+    self.release_held(ctx.actor_ref());
+  }
+}
+
+/// Source side: the handoff failed and the player stays. Held commands are
+/// applied here in order.
+pub(crate) struct AbortHandoff {
+  pub(crate) player_id: PlayerId,
+}
+
+impl Message<AbortHandoff> for MapActor {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    AbortHandoff { player_id }: AbortHandoff,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    if let Some(player) = self.players.get_mut(&player_id)
+      && matches!(player.status, PlayerStatus::Migrating)
+    {
+      println!(
+        "[map:{}] handoff of {} aborted; it stays here",
+        self.map_id, player.state.profile.name
+      );
+      player.status = PlayerStatus::Online;
+    }
+    self.release_held(ctx.actor_ref());
+  }
+}
+
+/// Target side: undoes an [`AcceptHandoff`] whose player actor could not be
+/// started, so the source keeps the only copy.
+pub(crate) struct DiscardHandoff {
+  pub(crate) player_id: PlayerId,
+}
+
+impl Message<DiscardHandoff> for MapActor {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    DiscardHandoff { player_id }: DiscardHandoff,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    if self.players.remove(&player_id).is_some() {
+      self.broadcast_event(
+        ctx.actor_ref(),
+        player_id,
+        MapEvent::PlayerLeft {
+          map_id: self.map_id.clone(),
+          player_id,
+        },
+      );
+    }
+  }
+}
+
+enum HeldCommand {
+  Hit(PendingHit, Option<ReplySender<Option<HitReport>>>),
+  Buff(GivePlayerBuff, Option<ReplySender<Option<BuffReport>>>),
+}
+
+fn send_reply<R: Reply>(reply: Option<ReplySender<R>>, value: R) {
+  if let Some(reply) = reply {
+    reply.send(value);
+  }
+}
+
 // ── PlayerDisconnected ───────────────────────────────────────────────────────
 
 pub(crate) struct PlayerDisconnected {
@@ -452,6 +894,26 @@ impl MapActor {
       map_id: config.id,
       entry_buffs: config.entry_buffs,
       players: HashMap::new(),
+      held: Vec::new(),
+      forwards: HashMap::new(),
+    }
+  }
+
+  fn is_migrating(&self, player_id: PlayerId) -> bool {
+    self
+      .players
+      .get(&player_id)
+      .is_some_and(|player| matches!(player.status, PlayerStatus::Migrating))
+  }
+
+  /// Re-dispatches held commands in arrival order. Any that still touch a
+  /// migrating player are held again.
+  fn release_held(&mut self, map_ref: &ActorRef<Self>) {
+    for command in mem::take(&mut self.held) {
+      match command {
+        HeldCommand::Hit(hit, reply) => self.dispatch_hit(map_ref, hit, reply),
+        HeldCommand::Buff(buff, reply) => self.dispatch_buff(map_ref, buff, reply),
+      }
     }
   }
 
@@ -461,6 +923,10 @@ impl MapActor {
       if matches!(event, MapEvent::PlayerJoined { .. }) && player_id == source_player_id {
         continue;
       }
+      // Its actor is being moved; the new map sends it a fresh view.
+      if matches!(player.status, PlayerStatus::Migrating) {
+        continue;
+      }
       let player_name = player.player_name.clone();
       let event = event.clone();
       let map_ref = map_ref.clone();
@@ -522,4 +988,54 @@ mod tests {
     state.stats.apply_damage(total_damage);
     assert_eq!(state.stats, MapStats { red: 78, blue: 63 });
   }
+
+  #[tokio::test]
+  async fn commands_are_held_during_a_handoff_and_applied_if_it_aborts() {
+    let map = MapActor::spawn(MapActor::new(MapConfig::new("test-map", Vec::new())));
+    map
+      .ask(EnterPlayer {
+        profile: PlayerProfile {
+          id: 1,
+          name: "Tester".to_string(),
+        },
+        initial_stats: InitialMapStats { red: 100, blue: 80 },
+        initial_combat: InitialCombatStats {
+          base_attack: Damage { red: 10, blue: 3 },
+          base_damage: Damage { red: 2, blue: 4 },
+        },
+      })
+      .await
+      .unwrap();
+
+    let handed_off = map.ask(BeginHandoff { player_id: 1 }).await.unwrap();
+    assert!(handed_off.is_some_and(|state| state.buffs.is_empty()));
+    assert!(
+      map
+        .ask(BeginHandoff { player_id: 1 })
+        .await
+        .unwrap()
+        .is_none()
+    );
+
+    let buff = tokio::spawn({
+      let map = map.clone();
+      async move {
+        map
+          .ask(GivePlayerBuff {
+            player_id: 1,
+            buff: PlayerBuff::new("held", Damage { red: 1, blue: 1 }, Damage::default()),
+          })
+          .await
+          .unwrap()
+      }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!buff.is_finished());
+    let player = map.ask(GetMapPlayer { player_id: 1 }).await.unwrap();
+    assert!(player.is_some_and(|player| player.buffs.is_empty()));
+
+    map.ask(AbortHandoff { player_id: 1 }).await.unwrap();
+    let report = buff.await.unwrap().expect("the player stayed on this map");
+    assert_eq!(report.state_after_buff.buffs.len(), 1);
+  }
 }
@@ -0,0 +1,363 @@
+//! Live player migration between nodes.
+//!
+//! A migration moves one player from this node to `target_node` in five steps:
+//!
+//! 1. the local map freezes the player (`BeginHandoff`) and starts holding hits and buffs that
+//!    touch it;
+//! 2. the player actor hands over its [`PlayerSeed`] and is terminated, so its registered name is
+//!    free;
+//! 3. the target node's [`PlayerHost`] receives the seed plus the map state (stats, buffs, base
+//!    combat), seeds its own copy of the map and starts the player under its supervisor;
+//! 4. the local map drops the player and forwards held and later commands for it to the target map
+//!    (`CompleteHandoff`);
+//! 5. if step 3 fails, the local map unfreezes the player and applies the held commands instead
+//!    (`AbortHandoff`), and the player is started here again.
+//!
+//! The player is a member of exactly one map copy at every point, except that
+//! the frozen source copy lingers until the target confirms.
+
+use std::fmt;
+
+use kameo::{actor::RemoteActorRef, error::RemoteSendError, prelude::*};
+use kameo_supervisor::{
+  ChildSpec, GetChild, RestartPolicy, StartChild, Supervisor, TerminateChild,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  map::{
+    AbortHandoff, AcceptHandoff, BeginHandoff, CompleteHandoff, DiscardHandoff, MapActor,
+    MapPlayerState, MapRuntime,
+  },
+  player::{EnterMap, GetPlayerView, PlayerActor, PlayerId, PlayerSeed, PrepareHandoff},
+  tcp_monitor::{BindTcpConnection, HandOffConnection, TcpConnectionMonitor},
+};
+
+pub(crate) fn player_name(player_id: PlayerId) -> String {
+  format!("player:{player_id}")
+}
+
+pub(crate) fn map_name(node_id: &str, map_id: &str) -> String {
+  format!("map:{node_id}:{map_id}")
+}
+
+pub(crate) fn host_name(node_id: &str) -> String {
+  format!("host:{node_id}")
+}
+
+/// Whether `node_id`'s [`PlayerHost`] is reachable, i.e. it can adopt players.
+pub(crate) async fn node_available(node_id: &str) -> bool {
+  matches!(
+    RemoteActorRef::<PlayerHost>::lookup(host_name(node_id).as_str()).await,
+    Ok(Some(_))
+  )
+}
+
+/// Rebuilds the player from its seed on every start, then registers it and
+/// enters the local copy of its map. The map is authoritative, so a restarted
+/// or migrated player gets its stats and buffs back from the map.
+pub(crate) fn player_spec(
+  node_id: &str,
+  seed: PlayerSeed,
+  map: RemoteActorRef<MapActor>,
+) -> ChildSpec<PlayerActor> {
+  let node_id = node_id.to_string();
+  let player_id = seed.profile.id;
+
+  ChildSpec::new(player_name(player_id), move || {
+    PlayerActor::new(seed.clone())
+  })
+  .restart(RestartPolicy::Transient)
+  .register_as(player_name(player_id))
+  .after_start(move |player: ActorRef<PlayerActor>| {
+    let node_id = node_id.clone();
+    let map = map.clone();
+    async move {
+      let join_info = player.ask(EnterMap { map }).await?;
+      println!(
+        "[node:{node_id}] player:{player_id} joined map:{}; own state: {:?} | {} other(s): {:?}",
+        join_info.map_id,
+        join_info.own_state,
+        join_info.other_players.len(),
+        join_info
+          .other_players
+          .iter()
+          .map(|p| p.profile.name.as_str())
+          .collect::<Vec<_>>(),
+      );
+      Ok(())
+    }
+  })
+}
+
+/// What travels over the wire: the player's seed and its authoritative map
+/// state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PlayerHandoff {
+  pub(crate) player: PlayerSeed,
+  pub(crate) map_id: String,
+  pub(crate) map_state: MapPlayerState,
+}
+
+/// The node-local pieces a migration touches on either side.
+#[derive(Clone)]
+pub(crate) struct Migrator {
+  node_id: String,
+  supervisor: ActorRef<Supervisor>,
+  maps: Vec<MapRuntime>,
+  tcp_monitor: ActorRef<TcpConnectionMonitor>,
+}
+
+impl Migrator {
+  pub(crate) fn new(
+    node_id: impl Into<String>,
+    supervisor: ActorRef<Supervisor>,
+    maps: Vec<MapRuntime>,
+    tcp_monitor: ActorRef<TcpConnectionMonitor>,
+  ) -> Self {
+    Self {
+      node_id: node_id.into(),
+      supervisor,
+      maps,
+      tcp_monitor,
+    }
+  }
+
+  /// Moves a local player to `target_node`. On error the player keeps
+  /// running here.
+  pub(crate) async fn migrate(
+    &self,
+    player_id: PlayerId,
+    target_node: &str,
+  ) -> Result<(), MigrationError> {
+    let name = player_name(player_id);
+    let unavailable = || MigrationError::NodeUnavailable(target_node.to_string());
+    let target_host = RemoteActorRef::<PlayerHost>::lookup(host_name(target_node).as_str())
+      .await
+      .ok()
+      .flatten()
+      .ok_or_else(unavailable)?;
+
+    let player = self
+      .supervisor
+      .ask(GetChild::<PlayerActor>::new(name.clone()))
+      .await
+      .map_err(failed)?
+      .ok_or(MigrationError::PlayerNotFound(player_id))?;
+    let map_id = player
+      .ask(GetPlayerView)
+      .await
+      .map_err(failed)?
+      .map_mirror
+      .map(|mirror| mirror.current_map)
+      .ok_or(MigrationError::NotInMap(player_id))?;
+    let map = self.map(&map_id)?.clone();
+    let target_map = RemoteActorRef::<MapActor>::lookup(map_name(target_node, &map_id).as_str())
+      .await
+      .ok()
+      .flatten()
+      .ok_or_else(unavailable)?;
+
+    let seed = player.ask(PrepareHandoff).await.map_err(failed)?;
+    let map_state = map
+      .actor
+      .ask(BeginHandoff { player_id })
+      .await
+      .map_err(failed)?
+      .ok_or(MigrationError::NotInMap(player_id))?;
+    println!(
+      "[node:{}] migrating player:{player_id} to {target_node}",
+      self.node_id
+    );
+
+    // The old actor must be gone before the new one registers its name.
+    let _ = self.tcp_monitor.ask(HandOffConnection { player_id }).await;
+    if let Err(err) = self.supervisor.ask(TerminateChild(name)).await {
+      let _ = map.actor.ask(AbortHandoff { player_id }).await;
+      return Err(failed(err));
+    }
+
+    let adopted = target_host
+      .ask(&AdoptPlayer {
+        from_node: self.node_id.clone(),
+        handoff: PlayerHandoff {
+          player: seed.clone(),
+          map_id,
+          map_state,
+        },
+      })
+      .await
+      .map_err(|err| match err {
+        RemoteSendError::HandlerError(err) => err,
+        err => failed(err),
+      });
+
+    match adopted {
+      Ok(()) => {
+        map
+          .actor
+          .ask(CompleteHandoff {
+            player_id,
+            target_node: target_node.to_string(),
+            target_map,
+          })
+          .await
+          .map_err(failed)?;
+        println!(
+          "[node:{}] player:{player_id} now lives on {target_node}",
+          self.node_id
+        );
+        Ok(())
+      }
+      Err(err) => {
+        println!(
+          "[node:{}] migration of player:{player_id} failed ({err}); keeping it here",
+          self.node_id
+        );
+        map
+          .actor
+          .ask(AbortHandoff { player_id })
+          .await
+          .map_err(failed)?;
+        self.start_player(seed, &map, "session-restored").await?;
+        Err(err)
+      }
+    }
+  }
+
+  /// Target side of [`Migrator::migrate`].
+  async fn adopt(&self, from_node: &str, handoff: PlayerHandoff) -> Result<(), MigrationError> {
+    let PlayerHandoff {
+      player,
+      map_id,
+      map_state,
+    } = handoff;
+    let player_id = player.profile.id;
+    let map = self.map(&map_id)?;
+
+    map
+      .actor
+      .ask(AcceptHandoff {
+        from_node: from_node.to_string(),
+        state: map_state,
+      })
+      .await
+      .map_err(failed)?;
+    if let Err(err) = self.start_player(player, map, "session-migrated").await {
+      let _ = map.actor.ask(DiscardHandoff { player_id }).await;
+      return Err(err);
+    }
+    println!(
+      "[node:{}] adopted player:{player_id} from {from_node}",
+      self.node_id
+    );
+    Ok(())
+  }
+
+  async fn start_player(
+    &self,
+    seed: PlayerSeed,
+    map: &MapRuntime,
+    session: &str,
+  ) -> Result<(), MigrationError> {
+    let player_id = seed.profile.id;
+    let player = self
+      .supervisor
+      .ask(StartChild(player_spec(
+        &self.node_id,
+        seed,
+        map.remote.clone(),
+      )))
+      .await
+      .map_err(failed)?;
+    self
+      .tcp_monitor
+      .ask(BindTcpConnection {
+        player_id,
+        connection_id: format!("tcp:{}:{player_id}:{session}", self.node_id),
+        player,
+      })
+      .await
+      .map_err(failed)?
+      .log();
+    Ok(())
+  }
+
+  fn map(&self, map_id: &str) -> Result<&MapRuntime, MigrationError> {
+    self
+      .maps
+      .iter()
+      .find(|map| map.id == map_id)
+      .ok_or_else(|| MigrationError::UnknownMap(map_id.to_string()))
+  }
+}
+
+// ── PlayerHost ───────────────────────────────────────────────────────────────
+
+/// Registered as `host:<node-id>` so peers can hand players to this node.
+#[derive(Actor, RemoteActor)]
+#[remote_actor(id = "kameo_game_sync::PlayerHost")]
+pub(crate) struct PlayerHost {
+  migrator: Migrator,
+}
+
+impl PlayerHost {
+  pub(crate) fn new(migrator: Migrator) -> Self {
+    Self { migrator }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct AdoptPlayer {
+  pub(crate) from_node: String,
+  pub(crate) handoff: PlayerHandoff,
+}
+
+#[remote_message("kameo_game_sync::PlayerHost::AdoptPlayer")]
+impl Message<AdoptPlayer> for PlayerHost {
+  type Reply = Result<(), MigrationError>;
+
+  async fn handle(
+    &mut self,
+    AdoptPlayer { from_node, handoff }: AdoptPlayer,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    self.migrator.adopt(&from_node, handoff).await
+  }
+}
+
+// ── Errors ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum MigrationError {
+  NodeUnavailable(String),
+  PlayerNotFound(PlayerId),
+  NotInMap(PlayerId),
+  UnknownMap(String),
+  Failed(String),
+}
+
+impl fmt::Display for MigrationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MigrationError::NodeUnavailable(node_id) => write!(f, "node {node_id} is not reachable"),
+      MigrationError::PlayerNotFound(player_id) => {
+        write!(f, "player:{player_id} is not running on this node")
+      }
+      MigrationError::NotInMap(player_id) => {
+        write!(
+          f,
+          "player:{player_id} is not in a map or is already migrating"
+        )
+      }
+      MigrationError::UnknownMap(map_id) => write!(f, "no map {map_id} on this node"),
+      MigrationError::Failed(reason) => f.write_str(reason),
+    }
+  }
+}
+
+impl std::error::Error for MigrationError {}
+
+fn failed(err: impl fmt::Display) -> MigrationError {
+  MigrationError::Failed(err.to_string())
+}
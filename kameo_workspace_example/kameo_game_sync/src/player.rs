@@ -1,4 +1,6 @@
-use kameo::{actor::RemoteActorRef, prelude::*};
+use std::ops::ControlFlow;
+
+use kameo::{actor::RemoteActorRef, error::Infallible, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use crate::map::{EnterPlayer, MapActor, MapJoinInfo, MapPlayerView};
@@ -106,9 +108,19 @@ pub(crate) struct InitialCombatStats {
   pub(crate) base_damage: Damage,
 }
 
+/// Everything a player needs to be rebuilt on any node: what it is, not what
+/// happened to it. Stats and buffs earned since are map-owned and travel with
+/// the map's handoff instead.
+#[derive(Clone, Debug, Reply, Serialize, Deserialize)]
+pub(crate) struct PlayerSeed {
+  pub(crate) profile: PlayerProfile,
+  pub(crate) initial_map_stats: InitialMapStats,
+  pub(crate) initial_combat_stats: InitialCombatStats,
+}
+
 // ── Actor ────────────────────────────────────────────────────────────────────
 
-#[derive(Actor, RemoteActor)]
+#[derive(RemoteActor)]
 #[remote_actor(id = "kameo_game_sync::PlayerActor")]
 pub(crate) struct PlayerActor {
   pub(crate) profile: PlayerProfile,
@@ -117,6 +129,45 @@ pub(crate) struct PlayerActor {
   pub(crate) map_mirror: Option<PlayerMapMirror>,
 }
 
+impl PlayerActor {
+  pub(crate) fn new(seed: PlayerSeed) -> Self {
+    Self {
+      profile: seed.profile,
+      initial_map_stats: seed.initial_map_stats,
+      initial_combat_stats: seed.initial_combat_stats,
+      map_mirror: None,
+    }
+  }
+}
+
+impl Actor for PlayerActor {
+  type Args = Self;
+  type Error = Infallible;
+
+  async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+    Ok(args)
+  }
+
+  /// Supervisors on other nodes link this player to watch it for failover.
+  /// Losing one of those peers says nothing about this player's health.
+  async fn on_link_died(
+    &mut self,
+    _actor_ref: WeakActorRef<Self>,
+    id: ActorId,
+    reason: ActorStopReason,
+  ) -> Result<ControlFlow<ActorStopReason>, Self::Error> {
+    match reason {
+      ActorStopReason::Normal
+      | ActorStopReason::SupervisorRestart
+      | ActorStopReason::PeerDisconnected => Ok(ControlFlow::Continue(())),
+      reason => Ok(ControlFlow::Break(ActorStopReason::LinkDied {
+        id,
+        reason: Box::new(reason),
+      })),
+    }
+  }
+}
+
 // ── GetPlayerView ────────────────────────────────────────────────────────────
 
 #[derive(Serialize, Deserialize)]
@@ -138,6 +189,28 @@ impl Message<GetPlayerView> for PlayerActor {
   }
 }
 
+// ── PrepareHandoff ───────────────────────────────────────────────────────────
+
+/// Asked before the player moves to another node. The reply is sent over the
+/// wire and the actor is stopped right after.
+pub(crate) struct PrepareHandoff;
+
+impl Message<PrepareHandoff> for PlayerActor {
+  type Reply = PlayerSeed;
+
+  async fn handle(
+    &mut self,
+    _msg: PrepareHandoff,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    PlayerSeed {
+      profile: self.profile.clone(),
+      initial_map_stats: self.initial_map_stats,
+      initial_combat_stats: self.initial_combat_stats,
+    }
+  }
+}
+
 // ── EnterMap ─────────────────────────────────────────────────────────────────
 
 /// Tells the player to join a map.
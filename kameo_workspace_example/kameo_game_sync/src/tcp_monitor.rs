@@ -235,6 +235,31 @@ impl Message<RetentionExpired> for TcpConnectionMonitor {
   }
 }
 
+/// The player's session moves to another node with the player: forget it
+/// here without stopping the player or starting a retention timer.
+pub(crate) struct HandOffConnection {
+  pub(crate) player_id: PlayerId,
+}
+
+impl Message<HandOffConnection> for TcpConnectionMonitor {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    HandOffConnection { player_id }: HandOffConnection,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let Some(player) = self.players.remove(&player_id) else {
+      return;
+    };
+    if let ConnectionState::Retained { abort_handle, .. } = &player.state {
+      abort_handle.abort();
+    }
+    ctx.actor_ref().unlink(&player.player).await;
+    println!("[tcp_monitor] player:{player_id} session handed off to another node");
+  }
+}
+
 pub(crate) struct GetTcpConnections;
 
 #[derive(Clone, Debug)]
@@ -0,0 +1,1127 @@
+//! A durable, acknowledging variant of `kameo_actors::message_queue::MessageQueue`.
+//!
+//! It accepts the same `ExchangeDeclare`, `QueueDeclare`, `QueueBind` and
+//! `BasicPublish` messages, but every published message is written to redb
+//! before the publish is confirmed, and stays there until a consumer acks it:
+//!
+//! - consumers subscribe with `BasicConsume<Delivery<M>>` and must reply with [`BasicAck`] or
+//!   [`BasicNack`];
+//! - an unacked delivery is redelivered once its visibility timeout expires;
+//! - after `max_deliveries` attempts a message moves to `<queue>.dlq`;
+//! - [`BasicQos`] caps how many unacked deliveries each consumer holds.
+//!
+//! Exchanges, queues and bindings are durable too. Consumers are not: after a
+//! restart they subscribe again and receive everything still unacked.
+
+mod routing;
+mod store;
+
+use std::{
+  any::type_name,
+  collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+  fmt,
+  path::Path,
+  time::{Duration, Instant},
+};
+
+use kameo::{error::Infallible, prelude::*};
+use kameo_actors::message_queue::{
+  AmqpError, BasicCancel, BasicConsume, BasicPublish, ExchangeDeclare, FilterFn, QueueBind,
+  QueueDeclare,
+};
+use serde::{Serialize, de::DeserializeOwned};
+pub use store::StoreError;
+use store::{
+  NewMessage, Snapshot, Store, StoredBinding, StoredExchange, StoredExchangeKind, StoredMessage,
+  StoredQueue,
+};
+
+const DEAD_LETTER_SUFFIX: &str = ".dlq";
+
+/// Broker-wide delivery settings. `prefetch_count` is the default for new
+/// queues; [`BasicQos`] changes it per queue.
+#[derive(Clone, Copy, Debug)]
+pub struct QueuePolicy {
+  pub visibility_timeout: Duration,
+  pub max_deliveries: u32,
+  pub prefetch_count: usize,
+}
+
+impl Default for QueuePolicy {
+  fn default() -> Self {
+    Self {
+      visibility_timeout: Duration::from_secs(30),
+      max_deliveries: 5,
+      prefetch_count: 10,
+    }
+  }
+}
+
+/// What a consumer receives. Ack or nack it by `queue` and `delivery_tag`.
+#[derive(Clone, Debug)]
+pub struct Delivery<M> {
+  pub queue: String,
+  pub delivery_tag: u64,
+  pub redelivered: bool,
+  pub delivery_count: u32,
+  pub headers: HashMap<String, String>,
+  pub message: M,
+}
+
+#[derive(Debug)]
+pub struct BasicAck {
+  pub queue: String,
+  pub delivery_tag: u64,
+}
+
+/// Rejects a delivery. With `requeue` it goes back to the front of the queue
+/// (until it runs out of attempts); without, it is dead-lettered at once.
+#[derive(Debug)]
+pub struct BasicNack {
+  pub queue: String,
+  pub delivery_tag: u64,
+  pub requeue: bool,
+}
+
+#[derive(Debug)]
+pub struct BasicQos {
+  pub queue: String,
+  pub prefetch_count: usize,
+}
+
+#[derive(Debug)]
+pub struct GetQueueStats {
+  pub queue: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reply)]
+pub struct QueueStats {
+  pub ready: usize,
+  pub unacked: usize,
+  pub consumers: usize,
+}
+
+struct RedeliverExpired;
+
+pub struct DurableMessageQueue {
+  store: Store,
+  policy: QueuePolicy,
+  exchanges: HashMap<String, StoredExchange>,
+  queues: HashMap<String, Queue>,
+}
+
+struct Queue {
+  prefetch_count: usize,
+  dead_letter_queue: Option<String>,
+  next_tag: u64,
+  /// Every message not yet acked, ready or in flight, keyed by delivery tag.
+  messages: BTreeMap<u64, QueuedMessage>,
+  ready: VecDeque<u64>,
+  unacked: HashMap<u64, InFlight>,
+  consumers: Vec<Consumer>,
+  next_consumer: usize,
+}
+
+struct QueuedMessage {
+  stored: StoredMessage,
+  /// Publish-time filter on consumer tags. Function pointers can't be stored,
+  /// so messages reloaded after a restart go to any consumer.
+  filter: Option<FilterFn>,
+}
+
+struct InFlight {
+  consumer: ActorId,
+  deadline: Instant,
+}
+
+struct Consumer {
+  actor_id: ActorId,
+  message_type: &'static str,
+  tags: HashMap<String, String>,
+  in_flight: usize,
+  deliver: DeliverFn,
+}
+
+struct Envelope<'a> {
+  queue: &'a str,
+  delivery_tag: u64,
+  delivery_count: u32,
+  message: &'a StoredMessage,
+}
+
+type DeliverFn = Box<dyn Fn(Envelope<'_>) -> Result<(), DeliverError> + Send + Sync>;
+
+enum DeliverError {
+  /// The consumer's mailbox is full; try another consumer or later.
+  Busy,
+  /// The consumer stopped; cancel it and requeue what it held.
+  Gone,
+  Undecodable(serde_json::Error),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum DeadLetterReason {
+  Rejected,
+  DeliveryLimit,
+  Undecodable,
+}
+
+impl DeadLetterReason {
+  fn as_str(self) -> &'static str {
+    match self {
+      DeadLetterReason::Rejected => "rejected",
+      DeadLetterReason::DeliveryLimit => "delivery-limit",
+      DeadLetterReason::Undecodable => "undecodable",
+    }
+  }
+}
+
+impl DurableMessageQueue {
+  /// Opens (or creates) the queue database at `path` and restores its
+  /// exchanges, queues and unacked messages. Messages that were in flight
+  /// when the broker stopped are ready again.
+  pub fn open(path: impl AsRef<Path>, policy: QueuePolicy) -> Result<Self, StoreError> {
+    let store = Store::open(path)?;
+    let Snapshot {
+      exchanges,
+      queues: stored_queues,
+    } = store.load()?;
+
+    let mut queues = HashMap::new();
+    for state in stored_queues {
+      let ready = state.messages.iter().map(|(tag, _)| *tag).collect();
+      let messages = state
+        .messages
+        .into_iter()
+        .map(|(tag, stored)| {
+          (
+            tag,
+            QueuedMessage {
+              stored,
+              filter: None,
+            },
+          )
+        })
+        .collect();
+      queues.insert(
+        state.name,
+        Queue {
+          prefetch_count: state.queue.prefetch_count,
+          dead_letter_queue: state.queue.dead_letter_queue,
+          next_tag: state.next_tag,
+          messages,
+          ready,
+          unacked: HashMap::new(),
+          consumers: Vec::new(),
+          next_consumer: 0,
+        },
+      );
+    }
+
+    Ok(Self {
+      store,
+      policy,
+      exchanges: exchanges.into_iter().collect(),
+      queues,
+    })
+  }
+
+  fn declare_queue(
+    &mut self,
+    name: &str,
+    dead_letter_queue: Option<String>,
+  ) -> Result<(), DurableError> {
+    if self.queues.contains_key(name) {
+      return Ok(());
+    }
+    let stored = StoredQueue {
+      prefetch_count: self.policy.prefetch_count,
+      dead_letter_queue,
+    };
+    self.store.put_queue(name, &stored)?;
+    self.queues.insert(
+      name.to_string(),
+      Queue {
+        prefetch_count: stored.prefetch_count,
+        dead_letter_queue: stored.dead_letter_queue,
+        next_tag: 1,
+        messages: BTreeMap::new(),
+        ready: VecDeque::new(),
+        unacked: HashMap::new(),
+        consumers: Vec::new(),
+        next_consumer: 0,
+      },
+    );
+    Ok(())
+  }
+
+  fn save_queue(&self, name: &str) -> Result<(), DurableError> {
+    let queue = &self.queues[name];
+    self.store.put_queue(
+      name,
+      &StoredQueue {
+        prefetch_count: queue.prefetch_count,
+        dead_letter_queue: queue.dead_letter_queue.clone(),
+      },
+    )?;
+    Ok(())
+  }
+
+  /// AMQP's default exchange: an empty exchange name routes straight to the
+  /// queue named by the routing key.
+  fn route(
+    &self,
+    exchange: &str,
+    routing_key: &str,
+    headers: Option<&HashMap<String, String>>,
+  ) -> Result<BTreeSet<String>, DurableError> {
+    if exchange.is_empty() {
+      return Ok(
+        self
+          .queues
+          .contains_key(routing_key)
+          .then(|| routing_key.to_string())
+          .into_iter()
+          .collect(),
+      );
+    }
+
+    let exchange = self
+      .exchanges
+      .get(exchange)
+      .ok_or(AmqpError::ExchangeNotFound)?;
+    let empty = HashMap::new();
+    let headers = match (exchange.kind, headers) {
+      (StoredExchangeKind::Headers, None) => return Err(AmqpError::HeadersRequired.into()),
+      (_, headers) => headers.unwrap_or(&empty),
+    };
+    Ok(
+      exchange
+        .bindings
+        .iter()
+        .filter(|binding| routing::binding_matches(exchange.kind, binding, routing_key, headers))
+        .map(|binding| binding.queue.clone())
+        .collect(),
+    )
+  }
+
+  /// Hands ready messages to consumers with spare prefetch capacity, round
+  /// robin, keeping the order of anything that cannot be delivered yet.
+  fn dispatch(&mut self, queue_name: &str) {
+    let Self {
+      store,
+      policy,
+      queues,
+      ..
+    } = self;
+    let Some(queue) = queues.get_mut(queue_name) else {
+      return;
+    };
+
+    let mut waiting = VecDeque::new();
+    let mut gone = Vec::new();
+    let mut undecodable = Vec::new();
+
+    while let Some(tag) = queue.ready.pop_front() {
+      let message = queue
+        .messages
+        .get_mut(&tag)
+        .expect("ready messages are stored");
+      let delivery_count = message.stored.deliveries + 1;
+      let consumer_count = queue.consumers.len();
+      let mut handled = false;
+
+      for offset in 0 .. consumer_count {
+        let index = (queue.next_consumer + offset) % consumer_count;
+        let consumer = &mut queue.consumers[index];
+        if consumer.in_flight >= queue.prefetch_count
+          || consumer.message_type != message.stored.message_type
+          || gone.contains(&consumer.actor_id)
+          || message.filter.is_some_and(|filter| !filter(&consumer.tags))
+        {
+          continue;
+        }
+
+        match (consumer.deliver)(Envelope {
+          queue: queue_name,
+          delivery_tag: tag,
+          delivery_count,
+          message: &message.stored,
+        }) {
+          Ok(()) => {
+            message.stored.deliveries = delivery_count;
+            if let Err(err) = store.update_message(queue_name, tag, &message.stored) {
+              eprintln!("[durable] failed to record delivery of {queue_name}#{tag}: {err}");
+            }
+            consumer.in_flight += 1;
+            queue.unacked.insert(
+              tag,
+              InFlight {
+                consumer: consumer.actor_id,
+                deadline: Instant::now() + policy.visibility_timeout,
+              },
+            );
+            queue.next_consumer = index + 1;
+            handled = true;
+            break;
+          }
+          Err(DeliverError::Busy) => {}
+          Err(DeliverError::Gone) => gone.push(consumer.actor_id),
+          Err(DeliverError::Undecodable(err)) => {
+            eprintln!("[durable] {queue_name}#{tag} cannot be decoded: {err}");
+            undecodable.push(tag);
+            handled = true;
+            break;
+          }
+        }
+      }
+
+      if !handled {
+        waiting.push_back(tag);
+      }
+    }
+    queue.ready = waiting;
+
+    for tag in undecodable {
+      self.dead_letter(queue_name, tag, DeadLetterReason::Undecodable);
+    }
+    if !gone.is_empty() {
+      for consumer in gone {
+        self.cancel_consumer(queue_name, consumer);
+      }
+      self.dispatch(queue_name);
+    }
+  }
+
+  /// Removes a consumer and puts everything it held back at the front of the
+  /// queue.
+  fn cancel_consumer(&mut self, queue_name: &str, actor_id: ActorId) {
+    let Some(queue) = self.queues.get_mut(queue_name) else {
+      return;
+    };
+    queue
+      .consumers
+      .retain(|consumer| consumer.actor_id != actor_id);
+    let mut held: Vec<u64> = queue
+      .unacked
+      .iter()
+      .filter(|(_, in_flight)| in_flight.consumer == actor_id)
+      .map(|(tag, _)| *tag)
+      .collect();
+    held.sort_unstable();
+    for tag in held.into_iter().rev() {
+      queue.unacked.remove(&tag);
+      queue.ready.push_front(tag);
+    }
+  }
+
+  /// Takes `tag` out of flight, releasing its consumer's prefetch slot.
+  fn settle(&mut self, queue_name: &str, tag: u64) -> Result<(), DurableError> {
+    let queue = self
+      .queues
+      .get_mut(queue_name)
+      .ok_or(AmqpError::QueueNotFound)?;
+    let in_flight = queue
+      .unacked
+      .remove(&tag)
+      .ok_or_else(|| DurableError::UnknownDeliveryTag {
+        queue: queue_name.to_string(),
+        delivery_tag: tag,
+      })?;
+    if let Some(consumer) = queue
+      .consumers
+      .iter_mut()
+      .find(|consumer| consumer.actor_id == in_flight.consumer)
+    {
+      consumer.in_flight -= 1;
+    }
+    Ok(())
+  }
+
+  /// Puts a settled message back at the front of its queue, or dead-letters
+  /// it once it has used up its deliveries.
+  fn requeue(&mut self, queue_name: &str, tag: u64) {
+    let queue = &self.queues[queue_name];
+    let exhausted = queue.messages[&tag].stored.deliveries >= self.policy.max_deliveries;
+    if exhausted && queue.dead_letter_queue.is_some() {
+      self.dead_letter(queue_name, tag, DeadLetterReason::DeliveryLimit);
+    } else {
+      self
+        .queues
+        .get_mut(queue_name)
+        .expect("queue exists")
+        .ready
+        .push_front(tag);
+    }
+  }
+
+  /// Moves a message to the queue's dead-letter queue, recording why in its
+  /// `x-death-*` headers. Queues without one (dead-letter queues themselves)
+  /// drop rejected messages instead.
+  fn dead_letter(&mut self, queue_name: &str, tag: u64, reason: DeadLetterReason) {
+    let queue = self.queues.get_mut(queue_name).expect("queue exists");
+    queue.ready.retain(|ready| *ready != tag);
+    let Some(message) = queue.messages.remove(&tag) else {
+      return;
+    };
+    let Some(dead_letter_queue) = queue.dead_letter_queue.clone() else {
+      if let Err(err) = self.store.delete_message(queue_name, tag) {
+        eprintln!("[durable] failed to drop {queue_name}#{tag}: {err}");
+      }
+      return;
+    };
+
+    let mut stored = message.stored;
+    stored
+      .headers
+      .insert("x-death-queue".to_string(), queue_name.to_string());
+    stored
+      .headers
+      .insert("x-death-reason".to_string(), reason.as_str().to_string());
+    stored.headers.insert(
+      "x-death-deliveries".to_string(),
+      stored.deliveries.to_string(),
+    );
+    stored.deliveries = 0;
+
+    let dlq = self
+      .queues
+      .get_mut(&dead_letter_queue)
+      .expect("dead-letter queues are declared with their queue");
+    let dlq_tag = dlq.next_tag;
+    let moved = self.store.move_message(
+      (queue_name, tag),
+      NewMessage {
+        queue: &dead_letter_queue,
+        delivery_tag: dlq_tag,
+        message: &stored,
+      },
+    );
+    if let Err(err) = moved {
+      eprintln!("[durable] failed to dead-letter {queue_name}#{tag}: {err}");
+      return;
+    }
+
+    println!(
+      "[durable] {queue_name}#{tag} dead-lettered to {dead_letter_queue}#{dlq_tag} ({})",
+      reason.as_str()
+    );
+    dlq.next_tag += 1;
+    dlq.messages.insert(
+      dlq_tag,
+      QueuedMessage {
+        stored,
+        filter: None,
+      },
+    );
+    dlq.ready.push_back(dlq_tag);
+    self.dispatch(&dead_letter_queue);
+  }
+}
+
+impl Actor for DurableMessageQueue {
+  type Args = Self;
+  type Error = Infallible;
+
+  async fn on_start(state: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+    // Check for expired deliveries a few times per visibility timeout.
+    let period = (state.policy.visibility_timeout / 4)
+      .clamp(Duration::from_millis(10), Duration::from_secs(1));
+    let broker = actor_ref.downgrade();
+    tokio::spawn(async move {
+      let mut ticks = tokio::time::interval(period);
+      loop {
+        ticks.tick().await;
+        let Some(broker) = broker.upgrade() else {
+          break;
+        };
+        if broker.tell(RedeliverExpired).await.is_err() {
+          break;
+        }
+      }
+    });
+    Ok(state)
+  }
+
+  async fn on_stop(
+    &mut self,
+    _actor_ref: WeakActorRef<Self>,
+    _reason: ActorStopReason,
+  ) -> Result<(), Self::Error> {
+    self.store.close();
+    Ok(())
+  }
+}
+
+impl Message<ExchangeDeclare> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  /// Idempotent, so an application can declare its topology on every start;
+  /// redeclaring with a different kind fails.
+  async fn handle(
+    &mut self,
+    msg: ExchangeDeclare,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let kind = StoredExchangeKind::from(msg.kind);
+    if let Some(existing) = self.exchanges.get(&msg.exchange) {
+      return if existing.kind == kind {
+        Ok(())
+      } else {
+        Err(AmqpError::ExchangeAlreadyExists.into())
+      };
+    }
+
+    let exchange = StoredExchange {
+      kind,
+      bindings: Vec::new(),
+    };
+    self.store.put_exchange(&msg.exchange, &exchange)?;
+    self.exchanges.insert(msg.exchange, exchange);
+    Ok(())
+  }
+}
+
+impl Message<QueueDeclare> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  /// Also declares `<queue>.dlq`. Durable queues are never auto-deleted.
+  async fn handle(
+    &mut self,
+    msg: QueueDeclare,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let dead_letter_queue = format!("{}{DEAD_LETTER_SUFFIX}", msg.queue);
+    self.declare_queue(&dead_letter_queue, None)?;
+    self.declare_queue(&msg.queue, Some(dead_letter_queue))
+  }
+}
+
+impl Message<QueueBind> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  async fn handle(&mut self, msg: QueueBind, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+    if !self.queues.contains_key(&msg.queue) {
+      return Err(AmqpError::QueueNotFound.into());
+    }
+    let exchange = self
+      .exchanges
+      .get_mut(&msg.exchange)
+      .ok_or(AmqpError::ExchangeNotFound)?;
+    if exchange.kind == StoredExchangeKind::Headers
+      && !routing::valid_header_arguments(&msg.arguments)
+    {
+      return Err(AmqpError::InvalidHeaderMatch.into());
+    }
+
+    let binding = StoredBinding {
+      queue: msg.queue,
+      routing_key: msg.routing_key,
+      arguments: msg.arguments,
+    };
+    if exchange.bindings.contains(&binding) {
+      return Ok(());
+    }
+    exchange.bindings.push(binding);
+    self.store.put_exchange(&msg.exchange, exchange)?;
+    Ok(())
+  }
+}
+
+impl<M> Message<BasicPublish<M>> for DurableMessageQueue
+where
+  M: Clone + Serialize + Send + 'static,
+{
+  type Reply = Result<(), DurableError>;
+
+  /// Replies only after the message is committed to every routed queue, which
+  /// makes an `ask` a publisher confirm.
+  async fn handle(
+    &mut self,
+    msg: BasicPublish<M>,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let targets = self.route(
+      &msg.exchange,
+      &msg.routing_key,
+      msg.properties.headers.as_ref(),
+    )?;
+    if targets.is_empty() {
+      return Ok(());
+    }
+
+    let stored = StoredMessage {
+      message_type: type_name::<M>().to_string(),
+      payload: serde_json::to_vec(&msg.message).map_err(StoreError::Codec)?,
+      headers: msg.properties.headers.unwrap_or_default(),
+      deliveries: 0,
+    };
+    let tagged: Vec<(&str, u64)> = targets
+      .iter()
+      .map(|queue| (queue.as_str(), self.queues[queue].next_tag))
+      .collect();
+    self.store.insert_messages(
+      &tagged
+        .iter()
+        .map(|&(queue, delivery_tag)| NewMessage {
+          queue,
+          delivery_tag,
+          message: &stored,
+        })
+        .collect::<Vec<_>>(),
+    )?;
+
+    for (queue_name, tag) in tagged {
+      let queue = self
+        .queues
+        .get_mut(queue_name)
+        .expect("routed queue exists");
+      queue.next_tag = tag + 1;
+      queue.messages.insert(
+        tag,
+        QueuedMessage {
+          stored: stored.clone(),
+          filter: msg.properties.filter,
+        },
+      );
+      queue.ready.push_back(tag);
+    }
+    for queue_name in &targets {
+      self.dispatch(queue_name);
+    }
+    Ok(())
+  }
+}
+
+impl<M> Message<BasicConsume<Delivery<M>>> for DurableMessageQueue
+where
+  M: DeserializeOwned + Send + 'static,
+{
+  type Reply = Result<(), DurableError>;
+
+  async fn handle(
+    &mut self,
+    msg: BasicConsume<Delivery<M>>,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let queue = self
+      .queues
+      .get_mut(&msg.queue)
+      .ok_or(AmqpError::QueueNotFound)?;
+    let actor_id = msg.recipient.id();
+    if queue
+      .consumers
+      .iter()
+      .any(|consumer| consumer.actor_id == actor_id)
+    {
+      return Ok(());
+    }
+
+    let recipient = msg.recipient;
+    let deliver: DeliverFn = Box::new(move |envelope| {
+      let message =
+        serde_json::from_slice(&envelope.message.payload).map_err(DeliverError::Undecodable)?;
+      let delivery = Delivery {
+        queue: envelope.queue.to_string(),
+        delivery_tag: envelope.delivery_tag,
+        redelivered: envelope.delivery_count > 1,
+        delivery_count: envelope.delivery_count,
+        headers: envelope.message.headers.clone(),
+        message,
+      };
+      match recipient.tell(delivery).try_send() {
+        Ok(()) => Ok(()),
+        Err(SendError::ActorNotRunning(_)) => Err(DeliverError::Gone),
+        Err(_) => Err(DeliverError::Busy),
+      }
+    });
+    queue.consumers.push(Consumer {
+      actor_id,
+      message_type: type_name::<M>(),
+      tags: msg.tags,
+      in_flight: 0,
+      deliver,
+    });
+    self.dispatch(&msg.queue);
+    Ok(())
+  }
+}
+
+impl<M> Message<BasicCancel<Delivery<M>>> for DurableMessageQueue
+where
+  M: Send + 'static,
+{
+  type Reply = Result<(), DurableError>;
+
+  /// Unacked deliveries of the cancelled consumer go back to the queue.
+  async fn handle(
+    &mut self,
+    msg: BasicCancel<Delivery<M>>,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    if !self.queues.contains_key(&msg.queue) {
+      return Err(AmqpError::QueueNotFound.into());
+    }
+    self.cancel_consumer(&msg.queue, msg.recipient.id());
+    self.dispatch(&msg.queue);
+    Ok(())
+  }
+}
+
+impl Message<BasicAck> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  async fn handle(&mut self, msg: BasicAck, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+    self.settle(&msg.queue, msg.delivery_tag)?;
+    self.store.delete_message(&msg.queue, msg.delivery_tag)?;
+    self
+      .queues
+      .get_mut(&msg.queue)
+      .expect("settled queue exists")
+      .messages
+      .remove(&msg.delivery_tag);
+    self.dispatch(&msg.queue);
+    Ok(())
+  }
+}
+
+impl Message<BasicNack> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  async fn handle(&mut self, msg: BasicNack, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+    self.settle(&msg.queue, msg.delivery_tag)?;
+    if msg.requeue {
+      self.requeue(&msg.queue, msg.delivery_tag);
+    } else {
+      self.dead_letter(&msg.queue, msg.delivery_tag, DeadLetterReason::Rejected);
+    }
+    self.dispatch(&msg.queue);
+    Ok(())
+  }
+}
+
+impl Message<BasicQos> for DurableMessageQueue {
+  type Reply = Result<(), DurableError>;
+
+  async fn handle(&mut self, msg: BasicQos, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+    self
+      .queues
+      .get_mut(&msg.queue)
+      .ok_or(AmqpError::QueueNotFound)?
+      .prefetch_count = msg.prefetch_count.max(1);
+    self.save_queue(&msg.queue)?;
+    self.dispatch(&msg.queue);
+    Ok(())
+  }
+}
+
+impl Message<GetQueueStats> for DurableMessageQueue {
+  type Reply = Result<QueueStats, DurableError>;
+
+  async fn handle(
+    &mut self,
+    msg: GetQueueStats,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let queue = self
+      .queues
+      .get(&msg.queue)
+      .ok_or(AmqpError::QueueNotFound)?;
+    Ok(QueueStats {
+      ready: queue.ready.len(),
+      unacked: queue.unacked.len(),
+      consumers: queue.consumers.len(),
+    })
+  }
+}
+
+impl Message<RedeliverExpired> for DurableMessageQueue {
+  type Reply = ();
+
+  async fn handle(
+    &mut self,
+    _msg: RedeliverExpired,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let now = Instant::now();
+    let queue_names: Vec<String> = self.queues.keys().cloned().collect();
+    for queue_name in queue_names {
+      let mut expired: Vec<u64> = self.queues[&queue_name]
+        .unacked
+        .iter()
+        .filter(|(_, in_flight)| in_flight.deadline <= now)
+        .map(|(tag, _)| *tag)
+        .collect();
+      if expired.is_empty() {
+        continue;
+      }
+
+      expired.sort_unstable();
+      for &tag in expired.iter().rev() {
+        self
+          .settle(&queue_name, tag)
+          .expect("expired deliveries are in flight");
+        println!("[durable] {queue_name}#{tag} not acked in time; redelivering");
+        self.requeue(&queue_name, tag);
+      }
+      self.dispatch(&queue_name);
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum DurableError {
+  Amqp(AmqpError),
+  UnknownDeliveryTag { queue: String, delivery_tag: u64 },
+  Store(StoreError),
+}
+
+impl fmt::Display for DurableError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DurableError::Amqp(err) => err.fmt(f),
+      DurableError::UnknownDeliveryTag {
+        queue,
+        delivery_tag,
+      } => write!(f, "no unacked delivery {delivery_tag} on queue {queue}"),
+      DurableError::Store(err) => err.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for DurableError {}
+
+impl From<AmqpError> for DurableError {
+  fn from(err: AmqpError) -> Self {
+    DurableError::Amqp(err)
+  }
+}
+
+impl From<StoreError> for DurableError {
+  fn from(err: StoreError) -> Self {
+    DurableError::Store(err)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::Deserialize;
+  use tokio::sync::mpsc;
+
+  use super::*;
+
+  #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+  struct Job(u32);
+
+  enum Behaviour {
+    Ack,
+    Ignore,
+    Requeue,
+  }
+
+  /// Forwards every delivery to the test and settles it per `behaviour`.
+  struct Worker {
+    broker: ActorRef<DurableMessageQueue>,
+    behaviour: Behaviour,
+    seen: mpsc::UnboundedSender<Delivery<Job>>,
+  }
+
+  impl Actor for Worker {
+    type Args = Self;
+    type Error = Infallible;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+      Ok(args)
+    }
+  }
+
+  impl Message<Delivery<Job>> for Worker {
+    type Reply = ();
+
+    async fn handle(
+      &mut self,
+      delivery: Delivery<Job>,
+      _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+      let (queue, delivery_tag) = (delivery.queue.clone(), delivery.delivery_tag);
+      let _ = self.seen.send(delivery);
+      match self.behaviour {
+        Behaviour::Ack => {
+          let _ = self
+            .broker
+            .tell(BasicAck {
+              queue,
+              delivery_tag,
+            })
+            .await;
+        }
+        Behaviour::Ignore => {}
+        Behaviour::Requeue => {
+          let _ = self
+            .broker
+            .tell(BasicNack {
+              queue,
+              delivery_tag,
+              requeue: true,
+            })
+            .await;
+        }
+      }
+    }
+  }
+
+  fn policy() -> QueuePolicy {
+    QueuePolicy {
+      visibility_timeout: Duration::from_millis(100),
+      max_deliveries: 3,
+      prefetch_count: 10,
+    }
+  }
+
+  async fn open_broker(path: &Path) -> ActorRef<DurableMessageQueue> {
+    let broker = DurableMessageQueue::spawn(DurableMessageQueue::open(path, policy()).unwrap());
+    broker
+      .ask(QueueDeclare {
+        queue: "jobs".to_string(),
+        ..Default::default()
+      })
+      .await
+      .unwrap();
+    broker
+  }
+
+  async fn publish(broker: &ActorRef<DurableMessageQueue>, job: u32) {
+    broker
+      .ask(BasicPublish {
+        exchange: String::new(),
+        routing_key: "jobs".to_string(),
+        message: Job(job),
+        properties: Default::default(),
+      })
+      .await
+      .unwrap();
+  }
+
+  async fn consume(
+    broker: &ActorRef<DurableMessageQueue>,
+    queue: &str,
+    behaviour: Behaviour,
+  ) -> (ActorRef<Worker>, mpsc::UnboundedReceiver<Delivery<Job>>) {
+    let (seen, deliveries) = mpsc::unbounded_channel();
+    let worker = Worker::spawn(Worker {
+      broker: broker.clone(),
+      behaviour,
+      seen,
+    });
+    broker
+      .ask(BasicConsume {
+        queue: queue.to_string(),
+        recipient: worker.clone().recipient(),
+        tags: Default::default(),
+      })
+      .await
+      .unwrap();
+    (worker, deliveries)
+  }
+
+  async fn stats(broker: &ActorRef<DurableMessageQueue>, queue: &str) -> QueueStats {
+    broker
+      .ask(GetQueueStats {
+        queue: queue.to_string(),
+      })
+      .await
+      .unwrap()
+  }
+
+  #[tokio::test]
+  async fn messages_survive_a_restart_until_acked() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("queue.redb");
+
+    let broker = open_broker(&path).await;
+    publish(&broker, 1).await;
+    publish(&broker, 2).await;
+    let (_ignorer, mut deliveries) = consume(&broker, "jobs", Behaviour::Ignore).await;
+    assert_eq!(deliveries.recv().await.unwrap().message, Job(1));
+    broker.stop_gracefully().await.unwrap();
+    broker.wait_for_shutdown_result().await.unwrap();
+
+    let broker = open_broker(&path).await;
+    assert_eq!(stats(&broker, "jobs").await.ready, 2);
+    let (_worker, mut deliveries) = consume(&broker, "jobs", Behaviour::Ack).await;
+    let first = deliveries.recv().await.unwrap();
+    assert_eq!(first.message, Job(1));
+    assert!(first.redelivered);
+    assert_eq!(deliveries.recv().await.unwrap().message, Job(2));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+      stats(&broker, "jobs").await,
+      QueueStats {
+        ready: 0,
+        unacked: 0,
+        consumers: 1,
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn unacked_deliveries_are_redelivered_then_dead_lettered() {
+    let dir = tempfile::tempdir().unwrap();
+    let broker = open_broker(&dir.path().join("queue.redb")).await;
+    publish(&broker, 7).await;
+
+    let (_ignorer, mut deliveries) = consume(&broker, "jobs", Behaviour::Ignore).await;
+    for attempt in 1 ..= 3 {
+      let delivery = deliveries.recv().await.unwrap();
+      assert_eq!(delivery.delivery_count, attempt);
+    }
+
+    let (_dead, mut dead_letters) = consume(&broker, "jobs.dlq", Behaviour::Ack).await;
+    let dead = dead_letters.recv().await.unwrap();
+    assert_eq!(dead.message, Job(7));
+    assert_eq!(dead.headers["x-death-reason"], "delivery-limit");
+    assert_eq!(dead.headers["x-death-deliveries"], "3");
+    assert_eq!(stats(&broker, "jobs").await.ready, 0);
+  }
+
+  #[tokio::test]
+  async fn requeued_nacks_count_towards_the_delivery_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let broker = open_broker(&dir.path().join("queue.redb")).await;
+    publish(&broker, 9).await;
+
+    let (_worker, mut deliveries) = consume(&broker, "jobs", Behaviour::Requeue).await;
+    let (_dead, mut dead_letters) = consume(&broker, "jobs.dlq", Behaviour::Ack).await;
+    for _ in 1 ..= 3 {
+      deliveries.recv().await.unwrap();
+    }
+    assert_eq!(dead_letters.recv().await.unwrap().message, Job(9));
+  }
+
+  #[tokio::test]
+  async fn prefetch_limits_unacked_deliveries_per_consumer() {
+    let dir = tempfile::tempdir().unwrap();
+    let broker = open_broker(&dir.path().join("queue.redb")).await;
+    broker
+      .ask(BasicQos {
+        queue: "jobs".to_string(),
+        prefetch_count: 2,
+      })
+      .await
+      .unwrap();
+    for job in 1 ..= 5 {
+      publish(&broker, job).await;
+    }
+
+    let (_ignorer, _deliveries) = consume(&broker, "jobs", Behaviour::Ignore).await;
+    assert_eq!(
+      stats(&broker, "jobs").await,
+      QueueStats {
+        ready: 3,
+        unacked: 2,
+        consumers: 1,
+      }
+    );
+  }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use kameo_actors::message_queue::ExchangeType;
+
+use super::store::{StoredBinding, StoredExchangeKind};
+
+impl From<ExchangeType> for StoredExchangeKind {
+  fn from(kind: ExchangeType) -> Self {
+    match kind {
+      ExchangeType::Direct => StoredExchangeKind::Direct,
+      ExchangeType::Topic => StoredExchangeKind::Topic,
+      ExchangeType::Fanout => StoredExchangeKind::Fanout,
+      ExchangeType::Headers => StoredExchangeKind::Headers,
+    }
+  }
+}
+
+/// Whether `binding` on an exchange of `kind` receives a message published
+/// with `routing_key` and `headers`.
+pub(crate) fn binding_matches(
+  kind: StoredExchangeKind,
+  binding: &StoredBinding,
+  routing_key: &str,
+  headers: &HashMap<String, String>,
+) -> bool {
+  match kind {
+    StoredExchangeKind::Direct => binding.routing_key == routing_key,
+    StoredExchangeKind::Topic => topic_matches(&binding.routing_key, routing_key),
+    StoredExchangeKind::Fanout => true,
+    StoredExchangeKind::Headers => headers_match(&binding.arguments, headers),
+  }
+}
+
+/// AMQP topic matching on `.`-separated words: `*` matches exactly one word
+/// and `#` matches zero or more.
+pub(crate) fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+  let pattern: Vec<&str> = pattern.split('.').collect();
+  let key: Vec<&str> = routing_key.split('.').collect();
+  words_match(&pattern, &key)
+}
+
+fn words_match(pattern: &[&str], key: &[&str]) -> bool {
+  match pattern.split_first() {
+    None => key.is_empty(),
+    Some((&"#", rest)) => (0 ..= key.len()).any(|skip| words_match(rest, &key[skip ..])),
+    Some((&word, rest)) => key
+      .split_first()
+      .is_some_and(|(first, key)| (word == "*" || word == *first) && words_match(rest, key)),
+  }
+}
+
+/// `x-match` is `all` (the default) or `any`; other `x-` arguments are not
+/// matched against headers.
+fn headers_match(arguments: &HashMap<String, String>, headers: &HashMap<String, String>) -> bool {
+  let mut rules = arguments.iter().filter(|(key, _)| !key.starts_with("x-"));
+  let matches = |(key, value): (&String, &String)| headers.get(key) == Some(value);
+  match arguments.get("x-match").map(String::as_str) {
+    Some("any") => rules.any(matches),
+    _ => rules.all(matches),
+  }
+}
+
+pub(crate) fn valid_header_arguments(arguments: &HashMap<String, String>) -> bool {
+  matches!(
+    arguments.get("x-match").map(String::as_str),
+    None | Some("all") | Some("any")
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn topic_wildcards_follow_amqp() {
+    assert!(topic_matches("temperature.*", "temperature.kitchen"));
+    assert!(!topic_matches("temperature.*", "temperature.kitchen.floor"));
+    assert!(!topic_matches("temperature.*", "temperature"));
+    assert!(topic_matches("temperature.#", "temperature"));
+    assert!(topic_matches("temperature.#", "temperature.kitchen.floor"));
+    assert!(topic_matches("#.floor", "temperature.kitchen.floor"));
+    assert!(topic_matches("*.kitchen.*", "humidity.kitchen.floor"));
+    assert!(!topic_matches("order.created", "order.paid"));
+  }
+
+  #[test]
+  fn headers_match_all_or_any() {
+    let headers = HashMap::from([
+      ("region".to_string(), "eu".to_string()),
+      ("tier".to_string(), "gold".to_string()),
+    ]);
+    let mut arguments = HashMap::from([
+      ("region".to_string(), "eu".to_string()),
+      ("tier".to_string(), "silver".to_string()),
+    ]);
+    assert!(!headers_match(&arguments, &headers));
+
+    arguments.insert("x-match".to_string(), "any".to_string());
+    assert!(headers_match(&arguments, &headers));
+  }
+}
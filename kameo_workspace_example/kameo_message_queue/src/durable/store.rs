@@ -0,0 +1,260 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+const EXCHANGES: TableDefinition<&str, &[u8]> = TableDefinition::new("exchanges");
+const QUEUES: TableDefinition<&str, &[u8]> = TableDefinition::new("queues");
+const MESSAGES: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("messages");
+/// Next delivery tag per queue, so tags are never reused across restarts and
+/// a late ack from before a restart cannot hit a different message.
+const NEXT_TAGS: TableDefinition<&str, u64> = TableDefinition::new("next_tags");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StoredExchangeKind {
+  Direct,
+  Topic,
+  Fanout,
+  Headers,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StoredExchange {
+  pub(crate) kind: StoredExchangeKind,
+  pub(crate) bindings: Vec<StoredBinding>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredBinding {
+  pub(crate) queue: String,
+  pub(crate) routing_key: String,
+  pub(crate) arguments: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StoredQueue {
+  pub(crate) prefetch_count: usize,
+  pub(crate) dead_letter_queue: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StoredMessage {
+  /// `std::any::type_name` of the payload, matched against consumers.
+  pub(crate) message_type: String,
+  pub(crate) payload: Vec<u8>,
+  pub(crate) headers: HashMap<String, String>,
+  /// Deliveries so far. Persisted on every hand-out, so a message that keeps
+  /// crashing its consumer is dead-lettered even across broker restarts.
+  pub(crate) deliveries: u32,
+}
+
+pub(crate) struct StoredQueueState {
+  pub(crate) name: String,
+  pub(crate) queue: StoredQueue,
+  pub(crate) next_tag: u64,
+  pub(crate) messages: Vec<(u64, StoredMessage)>,
+}
+
+pub(crate) struct Snapshot {
+  pub(crate) exchanges: Vec<(String, StoredExchange)>,
+  pub(crate) queues: Vec<StoredQueueState>,
+}
+
+/// One message written to one queue inside a publish transaction.
+pub(crate) struct NewMessage<'a> {
+  pub(crate) queue: &'a str,
+  pub(crate) delivery_tag: u64,
+  pub(crate) message: &'a StoredMessage,
+}
+
+pub(crate) struct Store {
+  /// `None` once closed. redb locks the file until the database is dropped,
+  /// and kameo drops a stopped actor some time after its shutdown completes,
+  /// so the broker closes the store in `on_stop` to allow an immediate reopen.
+  db: Option<Database>,
+}
+
+impl Store {
+  pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+    let db = Database::create(path)?;
+    let txn = db.begin_write()?;
+    txn.open_table(EXCHANGES)?;
+    txn.open_table(QUEUES)?;
+    txn.open_table(MESSAGES)?;
+    txn.open_table(NEXT_TAGS)?;
+    txn.commit()?;
+    Ok(Self { db: Some(db) })
+  }
+
+  pub(crate) fn close(&mut self) {
+    self.db = None;
+  }
+
+  fn db(&self) -> Result<&Database, StoreError> {
+    self.db.as_ref().ok_or(StoreError::Closed)
+  }
+
+  pub(crate) fn load(&self) -> Result<Snapshot, StoreError> {
+    let txn = self.db()?.begin_read()?;
+
+    let mut exchanges = Vec::new();
+    for entry in txn.open_table(EXCHANGES)?.iter()? {
+      let (name, exchange) = entry?;
+      exchanges.push((name.value().to_string(), decode(exchange.value())?));
+    }
+
+    let next_tags = txn.open_table(NEXT_TAGS)?;
+    let messages = txn.open_table(MESSAGES)?;
+    let mut queues = Vec::new();
+    for entry in txn.open_table(QUEUES)?.iter()? {
+      let (name, queue) = entry?;
+      let name = name.value().to_string();
+      let mut queued = Vec::new();
+      for entry in messages.range((name.as_str(), 0) ..= (name.as_str(), u64::MAX))? {
+        let (key, message) = entry?;
+        queued.push((key.value().1, decode(message.value())?));
+      }
+      let next_tag = next_tags
+        .get(name.as_str())?
+        .map_or(1, |next_tag| next_tag.value());
+      queues.push(StoredQueueState {
+        queue: decode(queue.value())?,
+        next_tag,
+        messages: queued,
+        name,
+      });
+    }
+
+    Ok(Snapshot { exchanges, queues })
+  }
+
+  pub(crate) fn put_exchange(
+    &self,
+    name: &str,
+    exchange: &StoredExchange,
+  ) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    txn
+      .open_table(EXCHANGES)?
+      .insert(name, encode(exchange)?.as_slice())?;
+    txn.commit()?;
+    Ok(())
+  }
+
+  pub(crate) fn put_queue(&self, name: &str, queue: &StoredQueue) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    txn
+      .open_table(QUEUES)?
+      .insert(name, encode(queue)?.as_slice())?;
+    txn.commit()?;
+    Ok(())
+  }
+
+  /// Writes a published message to every routed queue in one transaction: the
+  /// publisher's confirm means all copies are on disk, or none are.
+  pub(crate) fn insert_messages(&self, messages: &[NewMessage<'_>]) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    {
+      let mut table = txn.open_table(MESSAGES)?;
+      let mut next_tags = txn.open_table(NEXT_TAGS)?;
+      for new in messages {
+        table.insert(
+          (new.queue, new.delivery_tag),
+          encode(new.message)?.as_slice(),
+        )?;
+        next_tags.insert(new.queue, new.delivery_tag + 1)?;
+      }
+    }
+    txn.commit()?;
+    Ok(())
+  }
+
+  pub(crate) fn update_message(
+    &self,
+    queue: &str,
+    delivery_tag: u64,
+    message: &StoredMessage,
+  ) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    txn
+      .open_table(MESSAGES)?
+      .insert((queue, delivery_tag), encode(message)?.as_slice())?;
+    txn.commit()?;
+    Ok(())
+  }
+
+  pub(crate) fn delete_message(&self, queue: &str, delivery_tag: u64) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    txn.open_table(MESSAGES)?.remove((queue, delivery_tag))?;
+    txn.commit()?;
+    Ok(())
+  }
+
+  /// Moves a message to its dead-letter queue atomically, so it is never lost
+  /// or duplicated between the two.
+  pub(crate) fn move_message(
+    &self,
+    from: (&str, u64),
+    to: NewMessage<'_>,
+  ) -> Result<(), StoreError> {
+    let txn = self.db()?.begin_write()?;
+    {
+      let mut table = txn.open_table(MESSAGES)?;
+      table.remove(from)?;
+      table.insert((to.queue, to.delivery_tag), encode(to.message)?.as_slice())?;
+      txn
+        .open_table(NEXT_TAGS)?
+        .insert(to.queue, to.delivery_tag + 1)?;
+    }
+    txn.commit()?;
+    Ok(())
+  }
+}
+
+fn encode(value: &impl Serialize) -> Result<Vec<u8>, StoreError> {
+  serde_json::to_vec(value).map_err(StoreError::Codec)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StoreError> {
+  serde_json::from_slice(bytes).map_err(StoreError::Codec)
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+  Redb(Box<redb::Error>),
+  Codec(serde_json::Error),
+  Closed,
+}
+
+impl fmt::Display for StoreError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StoreError::Redb(err) => write!(f, "storage error: {err}"),
+      StoreError::Codec(err) => write!(f, "encoding error: {err}"),
+      StoreError::Closed => write!(f, "store is closed"),
+    }
+  }
+}
+
+impl std::error::Error for StoreError {}
+
+macro_rules! from_redb_error {
+  ($($error:ty),* $(,)?) => {
+    $(
+      impl From<$error> for StoreError {
+        fn from(err: $error) -> Self {
+          StoreError::Redb(Box::new(err.into()))
+        }
+      }
+    )*
+  };
+}
+
+from_redb_error!(
+  redb::Error,
+  redb::DatabaseError,
+  redb::TransactionError,
+  redb::TableError,
+  redb::StorageError,
+  redb::CommitError,
+);
@@ -1,3 +1,8 @@
+mod durable;
+
+use std::time::Duration;
+
+use durable::{BasicAck, BasicNack, Delivery, DurableMessageQueue, GetQueueStats, QueuePolicy};
 use kameo::prelude::*;
 use kameo_actors::{
   DeliveryStrategy,
@@ -6,6 +11,7 @@ use kameo_actors::{
     QueueDeclare,
   },
 };
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone)]
 struct TemperatureUpdate(f32);
@@ -21,8 +27,104 @@ impl Message<TemperatureUpdate> for TemperatureDisplay {
   }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Order {
+  id: u32,
+  amount: u32,
+}
+
+/// Bills orders. It "forgets" to ack order 2 the first time, and order 3 is
+/// poison: it always fails and gets nacked until it is dead-lettered.
+#[derive(Actor)]
+struct BillingWorker {
+  broker: ActorRef<DurableMessageQueue>,
+}
+
+impl Message<Delivery<Order>> for BillingWorker {
+  type Reply = ();
+
+  async fn handle(&mut self, delivery: Delivery<Order>, _ctx: &mut Context<Self, Self::Reply>) {
+    let Delivery {
+      queue,
+      delivery_tag,
+      redelivered,
+      delivery_count,
+      message: order,
+      ..
+    } = delivery;
+    println!(
+      "[billing] order {} (${}) attempt {delivery_count}{}",
+      order.id,
+      order.amount,
+      if redelivered { " (redelivered)" } else { "" }
+    );
+    let result = match (order.id, delivery_count) {
+      (2, 1) => {
+        println!("[billing] order 2: worker stalled, no ack");
+        return;
+      }
+      (3, _) => {
+        println!("[billing] order 3: payment failed, nack");
+        self
+          .broker
+          .tell(BasicNack {
+            queue,
+            delivery_tag,
+            requeue: true,
+          })
+          .await
+          .map_err(|err| err.to_string())
+      }
+      _ => self
+        .broker
+        .tell(BasicAck {
+          queue,
+          delivery_tag,
+        })
+        .await
+        .map_err(|err| err.to_string()),
+    };
+    if let Err(err) = result {
+      eprintln!("[billing] failed to settle order {}: {err}", order.id);
+    }
+  }
+}
+
+#[derive(Actor)]
+struct DeadLetterLogger {
+  broker: ActorRef<DurableMessageQueue>,
+}
+
+impl Message<Delivery<Order>> for DeadLetterLogger {
+  type Reply = ();
+
+  async fn handle(&mut self, delivery: Delivery<Order>, _ctx: &mut Context<Self, Self::Reply>) {
+    // Messages published straight to the dead-letter queue carry no x-death headers
+    let header = |name: &str| delivery.headers.get(name).map_or("?", String::as_str);
+    println!(
+      "[dlq] order {} from {} ({})",
+      delivery.message.id,
+      header("x-death-queue"),
+      header("x-death-reason")
+    );
+    let _ = self
+      .broker
+      .tell(BasicAck {
+        queue: delivery.queue,
+        delivery_tag: delivery.delivery_tag,
+      })
+      .await;
+  }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+  best_effort_demo().await?;
+  durable_demo().await?;
+  Ok(())
+}
+
+async fn best_effort_demo() -> Result<(), Box<dyn std::error::Error>> {
   let amqp = MessageQueue::spawn(MessageQueue::new(DeliveryStrategy::BestEffort));
 
   amqp
@@ -72,3 +174,106 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   display.wait_for_shutdown().await;
   Ok(())
 }
+
+/// Publishes orders while nobody is consuming, restarts the broker, and only
+/// then attaches a worker, which still receives every order.
+async fn durable_demo() -> Result<(), Box<dyn std::error::Error>> {
+  let dir = std::env::temp_dir().join(format!("kameo_message_queue-{}", std::process::id()));
+  std::fs::create_dir_all(&dir)?;
+  let path = dir.join("orders.redb");
+  let policy = QueuePolicy {
+    visibility_timeout: Duration::from_millis(500),
+    max_deliveries: 3,
+    prefetch_count: 2,
+  };
+
+  let broker = DurableMessageQueue::spawn(DurableMessageQueue::open(&path, policy)?);
+  broker
+    .ask(ExchangeDeclare {
+      exchange: "orders".to_string(),
+      kind: ExchangeType::Topic,
+      ..Default::default()
+    })
+    .await?;
+  broker
+    .ask(QueueDeclare {
+      queue: "billing".to_string(),
+      ..Default::default()
+    })
+    .await?;
+  broker
+    .ask(QueueBind {
+      queue: "billing".to_string(),
+      exchange: "orders".to_string(),
+      routing_key: "order.*".to_string(),
+      ..Default::default()
+    })
+    .await?;
+  for (id, amount) in [(1, 30), (2, 45), (3, 99)] {
+    broker
+      .ask(BasicPublish {
+        exchange: "orders".to_string(),
+        routing_key: "order.created".to_string(),
+        message: Order { id, amount },
+        properties: Default::default(),
+      })
+      .await?;
+  }
+  print_stats(&broker, "billing").await?;
+  broker.stop_gracefully().await?;
+  // Unlike `wait_for_shutdown`, this waits for `on_stop`, which releases the
+  // database file.
+  broker.wait_for_shutdown_result().await?;
+  println!("[durable] broker restarted");
+
+  let broker = DurableMessageQueue::spawn(DurableMessageQueue::open(&path, policy)?);
+  print_stats(&broker, "billing").await?;
+  let billing = BillingWorker::spawn(BillingWorker {
+    broker: broker.clone(),
+  });
+  let dead_letters = DeadLetterLogger::spawn(DeadLetterLogger {
+    broker: broker.clone(),
+  });
+  broker
+    .ask(BasicConsume {
+      queue: "billing".to_string(),
+      recipient: billing.clone().recipient(),
+      tags: Default::default(),
+    })
+    .await?;
+  broker
+    .ask(BasicConsume {
+      queue: "billing.dlq".to_string(),
+      recipient: dead_letters.clone().recipient(),
+      tags: Default::default(),
+    })
+    .await?;
+
+  // Long enough for order 2's visibility timeout to expire once.
+  tokio::time::sleep(Duration::from_millis(1500)).await;
+  print_stats(&broker, "billing").await?;
+  print_stats(&broker, "billing.dlq").await?;
+
+  broker.stop_gracefully().await?;
+  broker.wait_for_shutdown().await;
+  billing.stop_gracefully().await?;
+  dead_letters.stop_gracefully().await?;
+  std::fs::remove_dir_all(&dir)?;
+  Ok(())
+}
+
+async fn print_stats(
+  broker: &ActorRef<DurableMessageQueue>,
+  queue: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let stats = broker
+    .ask(GetQueueStats {
+      queue: queue.to_string(),
+    })
+    .await?;
+  println!(
+    "[durable] {queue}: {} ready, {} unacked, {} consumers",
+    stats.ready, stats.unacked, stats.consumers
+  );
+  Ok(())
+}
@@ -0,0 +1,183 @@
+use std::{any::Any, future::Future, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use kameo::{prelude::*, remote::RemoteActor, supervision::RestartPolicy};
+
+use crate::supervisor::Supervisor;
+
+/// How long a child gets to stop gracefully before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type Init<A> = Arc<dyn Fn() -> <A as Actor>::Args + Send + Sync>;
+type Hook<A> = Arc<dyn Fn(ActorRef<A>) -> BoxFuture<'static, Result<(), BoxError>> + Send + Sync>;
+pub(crate) type StartFn = Arc<
+  dyn Fn(ActorRef<Supervisor>) -> BoxFuture<'static, Result<Box<dyn RunningChild>, BoxError>>
+    + Send
+    + Sync,
+>;
+
+/// Describes how to build, and rebuild, one supervised actor.
+///
+/// `init` runs again on every restart, so a restarted child never inherits
+/// the state of the instance that crashed.
+pub struct ChildSpec<A: Actor> {
+  id: String,
+  restart: RestartPolicy,
+  init: Init<A>,
+  hooks: Vec<Hook<A>>,
+}
+
+impl<A: Actor> Clone for ChildSpec<A> {
+  fn clone(&self) -> Self {
+    Self {
+      id: self.id.clone(),
+      restart: self.restart,
+      init: self.init.clone(),
+      hooks: self.hooks.clone(),
+    }
+  }
+}
+
+impl<A: Actor> ChildSpec<A> {
+  /// A permanent child built by `init`.
+  pub fn new(id: impl Into<String>, init: impl Fn() -> A::Args + Send + Sync + 'static) -> Self {
+    Self {
+      id: id.into(),
+      restart: RestartPolicy::Permanent,
+      init: Arc::new(init),
+      hooks: Vec::new(),
+    }
+  }
+
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  pub fn restart_policy(&self) -> RestartPolicy {
+    self.restart
+  }
+
+  pub fn restart(mut self, restart: RestartPolicy) -> Self {
+    self.restart = restart;
+    self
+  }
+
+  /// Runs after every start, in the order hooks were added. A failing hook
+  /// stops the new instance and counts as a failed start.
+  pub fn after_start<F, Fut>(mut self, hook: F) -> Self
+  where
+    F: Fn(ActorRef<A>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+  {
+    self
+      .hooks
+      .push(Arc::new(move |actor| Box::pin(hook(actor))));
+    self
+  }
+
+  pub(crate) fn into_child(self) -> Child {
+    let Self {
+      id,
+      restart,
+      init,
+      hooks,
+    } = self;
+    let start: StartFn = Arc::new(move |supervisor| {
+      let init = init.clone();
+      let hooks = hooks.clone();
+      Box::pin(async move {
+        let actor = A::spawn_link(&supervisor, init()).await;
+        for hook in &hooks {
+          if let Err(err) = hook(actor.clone()).await {
+            RunningChild::stop(&actor).await;
+            return Err(err);
+          }
+        }
+        Ok(Box::new(actor) as Box<dyn RunningChild>)
+      })
+    });
+
+    Child {
+      id,
+      restart,
+      start,
+      running: None,
+    }
+  }
+}
+
+impl<A: Actor + RemoteActor> ChildSpec<A> {
+  /// Registers every instance under `name`. The previous instance has always
+  /// unregistered by the time its replacement starts.
+  pub fn register_as(self, name: impl Into<String>) -> Self {
+    let name = name.into();
+    self.after_start(move |actor: ActorRef<A>| {
+      let name = name.clone();
+      async move { actor.register(name).await.map_err(BoxError::from) }
+    })
+  }
+}
+
+/// A type-erased child owned by a supervisor.
+pub(crate) struct Child {
+  pub(crate) id: String,
+  pub(crate) restart: RestartPolicy,
+  pub(crate) start: StartFn,
+  pub(crate) running: Option<Box<dyn RunningChild>>,
+}
+
+impl Child {
+  pub(crate) fn running_id(&self) -> Option<ActorId> {
+    self.running.as_ref().map(|running| running.id())
+  }
+}
+
+/// The live instance of a child, without its actor type.
+pub(crate) trait RunningChild: Send + Sync {
+  fn id(&self) -> ActorId;
+
+  fn as_any(&self) -> &dyn Any;
+
+  fn stop(&self) -> BoxFuture<'static, ()>;
+
+  fn wait_for_shutdown(&self) -> BoxFuture<'static, ()>;
+}
+
+impl<A: Actor> RunningChild for ActorRef<A> {
+  fn id(&self) -> ActorId {
+    ActorRef::id(self)
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn stop(&self) -> BoxFuture<'static, ()> {
+    let actor = self.clone();
+    Box::pin(async move {
+      let _ = actor.stop_gracefully().await;
+      if tokio::time::timeout(SHUTDOWN_TIMEOUT, actor.wait_for_shutdown())
+        .await
+        .is_err()
+      {
+        actor.kill();
+        actor.wait_for_shutdown().await;
+      }
+    })
+  }
+
+  fn wait_for_shutdown(&self) -> BoxFuture<'static, ()> {
+    let actor = self.clone();
+    Box::pin(async move { actor.wait_for_shutdown().await })
+  }
+}
+
+pub(crate) fn should_restart(restart: RestartPolicy, reason: &ActorStopReason) -> bool {
+  match restart {
+    RestartPolicy::Permanent => true,
+    RestartPolicy::Transient => !reason.is_normal(),
+    RestartPolicy::Never => false,
+  }
+}
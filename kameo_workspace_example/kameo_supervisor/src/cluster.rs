@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+/// The logical nodes that may take over each other's watched actors.
+///
+/// Every node computes the same owner for a name from the same node list, so
+/// when a node disconnects exactly one survivor adopts each of its actors
+/// without any coordination. Nodes marked down stay down until the
+/// supervisor restarts.
+#[derive(Clone, Debug)]
+pub struct Cluster {
+  node_id: String,
+  nodes: Vec<String>,
+  down: HashSet<String>,
+}
+
+impl Cluster {
+  pub fn new(
+    node_id: impl Into<String>,
+    nodes: impl IntoIterator<Item = impl Into<String>>,
+  ) -> Self {
+    Self {
+      node_id: node_id.into(),
+      nodes: nodes.into_iter().map(Into::into).collect(),
+      down: HashSet::new(),
+    }
+  }
+
+  pub fn node_id(&self) -> &str {
+    &self.node_id
+  }
+
+  pub fn mark_down(&mut self, node: &str) {
+    self.down.insert(node.to_string());
+  }
+
+  /// Picks the live node with the highest rendezvous score for `name`.
+  pub fn owner(&self, name: &str) -> Option<&str> {
+    self
+      .nodes
+      .iter()
+      .filter(|node| !self.down.contains(node.as_str()))
+      .max_by_key(|node| (rendezvous_score(name, node), node.as_str()))
+      .map(String::as_str)
+  }
+}
+
+fn rendezvous_score(name: &str, node: &str) -> u64 {
+  const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+  const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+  let mut hash = FNV_OFFSET;
+  for byte in name.bytes().chain([0xff]).chain(node.bytes()) {
+    hash ^= u64::from(byte);
+    hash = hash.wrapping_mul(FNV_PRIME);
+  }
+
+  // FNV alone spreads similar keys poorly; finish with the splitmix64 mixer.
+  hash ^= hash >> 30;
+  hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  hash ^= hash >> 27;
+  hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+  hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn owner_moves_only_off_the_down_node() {
+    let mut cluster = Cluster::new("node-a", ["node-a", "node-b", "node-c"]);
+    let names: Vec<String> = (1000 .. 1100).map(|id| format!("player:{id}")).collect();
+    let before: Vec<String> = names
+      .iter()
+      .map(|name| cluster.owner(name).unwrap().to_string())
+      .collect();
+    assert!(before.iter().any(|owner| owner == "node-b"));
+
+    cluster.mark_down("node-b");
+    for (name, previous) in names.iter().zip(&before) {
+      let owner = cluster.owner(name).unwrap();
+      assert_ne!(owner, "node-b");
+      if previous != "node-b" {
+        assert_eq!(owner, previous, "{name} moved although its node is up");
+      }
+    }
+
+    cluster.mark_down("node-a");
+    cluster.mark_down("node-c");
+    assert_eq!(cluster.owner("player:1000"), None);
+  }
+
+  #[test]
+  fn every_node_agrees_on_the_owner() {
+    let a = Cluster::new("node-a", ["node-a", "node-b"]);
+    let b = Cluster::new("node-b", ["node-b", "node-a"]);
+    for id in 1000 .. 1010 {
+      let name = format!("player:{id}");
+      assert_eq!(a.owner(&name), b.owner(&name));
+    }
+  }
+}
@@ -0,0 +1,27 @@
+//! Erlang-style supervision trees for the kameo examples.
+//!
+//! kameo's built-in `Spawn::supervise` fixes the strategy per supervisor type
+//! and only covers local children. [`Supervisor`] picks its strategy at
+//! runtime, rebuilds every child from the init function in its [`ChildSpec`],
+//! and can also watch actors living on other nodes: when a watched actor's
+//! node disconnects, exactly one surviving node (chosen by rendezvous hashing
+//! over the [`Cluster`]) recreates it locally.
+//!
+//! ```text
+//! Supervisor (one-for-one, 3 restarts / 5s)
+//!   |- player:1001   local child, restarted from its ChildSpec
+//!   |- player:1003   local child
+//!   `- player:1002   watched on node-b, adopted here if node-b goes away
+//! ```
+
+mod child;
+mod cluster;
+mod supervisor;
+
+pub use child::{BoxError, ChildSpec};
+pub use cluster::Cluster;
+pub use kameo::supervision::{RestartPolicy, SupervisionStrategy};
+pub use supervisor::{
+  ChildInfo, GetChild, RestartIntensity, StartChild, Supervisor, SupervisorConfig, SupervisorError,
+  TerminateChild, WatchRemote, WhichChildren,
+};
@@ -0,0 +1,852 @@
+use std::{
+  collections::VecDeque,
+  fmt,
+  marker::PhantomData,
+  ops::{ControlFlow, Range},
+  time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use kameo::{
+  actor::RemoteActorRef,
+  prelude::*,
+  remote::RemoteActor,
+  supervision::{RestartPolicy, SupervisionStrategy},
+};
+
+use crate::{
+  Cluster,
+  child::{Child, ChildSpec, should_restart},
+};
+
+/// How often, and how many times, a watcher looks for the replacement of a
+/// remote actor that its home node is restarting.
+const REWATCH_INTERVAL: Duration = Duration::from_millis(500);
+const REWATCH_ATTEMPTS: u32 = 20;
+
+/// The supervisor gives up, stopping all of its children and then itself,
+/// once more than `max_restarts` restarts happen within `within`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartIntensity {
+  pub max_restarts: u32,
+  pub within: Duration,
+}
+
+impl Default for RestartIntensity {
+  fn default() -> Self {
+    Self {
+      max_restarts: 3,
+      within: Duration::from_secs(5),
+    }
+  }
+}
+
+pub struct SupervisorConfig {
+  name: String,
+  strategy: SupervisionStrategy,
+  intensity: RestartIntensity,
+  cluster: Option<Cluster>,
+  children: Vec<Child>,
+}
+
+impl SupervisorConfig {
+  pub fn new(name: impl Into<String>) -> Self {
+    Self {
+      name: name.into(),
+      strategy: SupervisionStrategy::OneForOne,
+      intensity: RestartIntensity::default(),
+      cluster: None,
+      children: Vec::new(),
+    }
+  }
+
+  pub fn strategy(mut self, strategy: SupervisionStrategy) -> Self {
+    self.strategy = strategy;
+    self
+  }
+
+  pub fn restart_intensity(mut self, max_restarts: u32, within: Duration) -> Self {
+    self.intensity = RestartIntensity {
+      max_restarts,
+      within,
+    };
+    self
+  }
+
+  /// Enables failover of watched remote actors between these nodes.
+  pub fn cluster(mut self, cluster: Cluster) -> Self {
+    self.cluster = Some(cluster);
+    self
+  }
+
+  /// A child started, in order, whenever the supervisor starts. Nested
+  /// supervisors declare their children this way so a restart rebuilds the
+  /// whole subtree.
+  pub fn child<A: Actor>(mut self, spec: ChildSpec<A>) -> Self {
+    self.children.push(spec.into_child());
+    self
+  }
+}
+
+/// Restarts its children according to a [`SupervisionStrategy`] chosen at
+/// runtime.
+///
+/// Children are linked to the supervisor, so their deaths arrive through
+/// `on_link_died`. Children the supervisor stops on purpose are detached
+/// first, which is why their stop signals are ignored.
+#[derive(RemoteActor)]
+#[remote_actor(id = "kameo_supervisor::Supervisor")]
+pub struct Supervisor {
+  name: String,
+  strategy: SupervisionStrategy,
+  intensity: RestartIntensity,
+  restarts: VecDeque<Instant>,
+  cluster: Option<Cluster>,
+  children: Vec<Child>,
+  watches: Vec<Watch>,
+}
+
+impl Actor for Supervisor {
+  type Args = SupervisorConfig;
+  type Error = SupervisorError;
+
+  async fn on_start(config: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+    let SupervisorConfig {
+      name,
+      strategy,
+      intensity,
+      cluster,
+      children,
+    } = config;
+    let mut supervisor = Self {
+      name,
+      strategy,
+      intensity,
+      restarts: VecDeque::new(),
+      cluster,
+      children: Vec::with_capacity(children.len()),
+      watches: Vec::new(),
+    };
+
+    for mut child in children {
+      match (child.start)(actor_ref.clone()).await {
+        Ok(running) => {
+          child.running = Some(running);
+          supervisor.children.push(child);
+        }
+        Err(err) => {
+          supervisor.stop_children().await;
+          return Err(SupervisorError::StartFailed {
+            id: child.id,
+            reason: err.to_string(),
+          });
+        }
+      }
+    }
+
+    println!(
+      "[supervisor:{}] started with {:?} and {} child(ren)",
+      supervisor.name,
+      supervisor.strategy,
+      supervisor.children.len()
+    );
+    Ok(supervisor)
+  }
+
+  async fn on_link_died(
+    &mut self,
+    actor_ref: WeakActorRef<Self>,
+    id: ActorId,
+    reason: ActorStopReason,
+  ) -> Result<ControlFlow<ActorStopReason>, Self::Error> {
+    let Some(actor_ref) = actor_ref.upgrade() else {
+      return Ok(ControlFlow::Continue(()));
+    };
+
+    if let Some(index) = self
+      .children
+      .iter()
+      .position(|child| child.running_id() == Some(id))
+    {
+      return Ok(self.child_exited(&actor_ref, index, id, reason).await);
+    }
+
+    if let Some(index) = self.watches.iter().position(|watch| watch.actor_id == id) {
+      let watch = self.watches.remove(index);
+      self.watched_exited(&actor_ref, watch, reason).await;
+    }
+    Ok(ControlFlow::Continue(()))
+  }
+
+  async fn on_stop(
+    &mut self,
+    actor_ref: WeakActorRef<Self>,
+    reason: ActorStopReason,
+  ) -> Result<(), Self::Error> {
+    // Remote links run both ways; unlink first so watched actors don't see
+    // this supervisor's death as their own failure.
+    if let Some(actor_ref) = actor_ref.upgrade() {
+      for watch in self.watches.drain(..) {
+        watch.remote.unlink(&actor_ref).await;
+      }
+    }
+    self.stop_children().await;
+    println!("[supervisor:{}] stopped: {reason}", self.name);
+    Ok(())
+  }
+}
+
+impl Supervisor {
+  async fn child_exited(
+    &mut self,
+    actor_ref: &ActorRef<Self>,
+    index: usize,
+    id: ActorId,
+    reason: ActorStopReason,
+  ) -> ControlFlow<ActorStopReason> {
+    let child = &mut self.children[index];
+    if let Some(running) = child.running.take() {
+      running.wait_for_shutdown().await;
+    }
+
+    if !should_restart(child.restart, &reason) {
+      println!(
+        "[supervisor:{}] {} exited ({reason}); not restarting",
+        self.name, child.id
+      );
+      if child.restart == RestartPolicy::Never {
+        self.children.remove(index);
+      }
+      return ControlFlow::Continue(());
+    }
+
+    println!(
+      "[supervisor:{}] {} exited ({reason}); {:?} restart",
+      self.name, child.id, self.strategy
+    );
+    if !self.record_restart() {
+      return self.give_up(id, reason);
+    }
+
+    let affected = match self.strategy {
+      SupervisionStrategy::OneForOne => index .. index + 1,
+      SupervisionStrategy::OneForAll => 0 .. self.children.len(),
+      SupervisionStrategy::RestForOne => index .. self.children.len(),
+    };
+    self.restart_children(actor_ref, affected, id, reason).await
+  }
+
+  /// Stops the still-running children in `affected` in reverse start order,
+  /// then starts them all again in start order.
+  async fn restart_children(
+    &mut self,
+    actor_ref: &ActorRef<Self>,
+    affected: Range<usize>,
+    id: ActorId,
+    reason: ActorStopReason,
+  ) -> ControlFlow<ActorStopReason> {
+    for child in self.children[affected.clone()].iter_mut().rev() {
+      if let Some(running) = child.running.take() {
+        running.stop().await;
+      }
+    }
+
+    let mut index = affected.start;
+    let mut end = affected.end;
+    while index < end {
+      // Temporary children are not brought back by a sibling's failure.
+      if self.children[index].restart == RestartPolicy::Never {
+        self.children.remove(index);
+        end -= 1;
+        continue;
+      }
+
+      loop {
+        let child = &mut self.children[index];
+        match (child.start)(actor_ref.clone()).await {
+          Ok(running) => {
+            child.running = Some(running);
+            break;
+          }
+          Err(err) => {
+            println!(
+              "[supervisor:{}] failed to restart {}: {err}",
+              self.name, child.id
+            );
+            if !self.record_restart() {
+              return self.give_up(id, reason);
+            }
+          }
+        }
+      }
+      index += 1;
+    }
+    ControlFlow::Continue(())
+  }
+
+  async fn watched_exited(
+    &mut self,
+    actor_ref: &ActorRef<Self>,
+    watch: Watch,
+    reason: ActorStopReason,
+  ) {
+    let Watch {
+      name, home_node, ..
+    } = &watch;
+
+    if !matches!(reason, ActorStopReason::PeerDisconnected) {
+      if should_restart(watch.restart, &reason) {
+        println!(
+          "[supervisor:{}] watched {name} on {home_node} exited ({reason}); waiting for its \
+           replacement",
+          self.name
+        );
+        watch.remote.rewatch(actor_ref.clone(), watch.actor_id);
+      } else {
+        println!(
+          "[supervisor:{}] watched {name} on {home_node} exited ({reason}); no longer watching",
+          self.name
+        );
+      }
+      return;
+    }
+
+    let Some(cluster) = &mut self.cluster else {
+      println!(
+        "[supervisor:{}] lost {name} with {home_node}; no cluster configured for failover",
+        self.name
+      );
+      return;
+    };
+    cluster.mark_down(home_node);
+    let owner = cluster.owner(name).map(str::to_string);
+    let local_node = cluster.node_id().to_string();
+
+    match owner {
+      Some(owner) if owner == local_node => {
+        let mut child = watch.remote.failover();
+        if self.children.iter().any(|existing| existing.id == child.id) {
+          return;
+        }
+        println!(
+          "[supervisor:{}] {home_node} is down; adopting {name} on {local_node}",
+          self.name
+        );
+        // Adoption is a takeover, not a failure, so it does not count
+        // against the restart intensity.
+        match (child.start)(actor_ref.clone()).await {
+          Ok(running) => {
+            child.running = Some(running);
+            self.children.push(child);
+          }
+          Err(err) => println!("[supervisor:{}] failed to adopt {name}: {err}", self.name),
+        }
+      }
+      Some(owner) => println!(
+        "[supervisor:{}] {home_node} is down; {owner} takes over {name}",
+        self.name
+      ),
+      None => println!(
+        "[supervisor:{}] {home_node} is down and no node is left to take over {name}",
+        self.name
+      ),
+    }
+  }
+
+  fn record_restart(&mut self) -> bool {
+    let now = Instant::now();
+    while self
+      .restarts
+      .front()
+      .is_some_and(|at| now.duration_since(*at) > self.intensity.within)
+    {
+      self.restarts.pop_front();
+    }
+    if self.restarts.len() >= self.intensity.max_restarts as usize {
+      return false;
+    }
+    self.restarts.push_back(now);
+    true
+  }
+
+  fn give_up(&self, id: ActorId, reason: ActorStopReason) -> ControlFlow<ActorStopReason> {
+    println!(
+      "[supervisor:{}] more than {} restart(s) within {:?}; shutting down",
+      self.name, self.intensity.max_restarts, self.intensity.within
+    );
+    ControlFlow::Break(ActorStopReason::LinkDied {
+      id,
+      reason: Box::new(reason),
+    })
+  }
+
+  async fn stop_children(&mut self) {
+    for child in self.children.iter_mut().rev() {
+      if let Some(running) = child.running.take() {
+        running.stop().await;
+      }
+    }
+  }
+}
+
+// ── StartChild ───────────────────────────────────────────────────────────────
+
+/// Starts a child and keeps it supervised. Send it with `ask`: a failed start
+/// replies with an error, which would stop the supervisor if sent with `tell`.
+pub struct StartChild<A: Actor>(pub ChildSpec<A>);
+
+impl<A: Actor> Message<StartChild<A>> for Supervisor {
+  type Reply = Result<ActorRef<A>, SupervisorError>;
+
+  async fn handle(
+    &mut self,
+    StartChild(spec): StartChild<A>,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    if self.children.iter().any(|child| child.id == spec.id()) {
+      return Err(SupervisorError::AlreadyStarted(spec.id().to_string()));
+    }
+
+    let mut child = spec.into_child();
+    let running = (child.start)(ctx.actor_ref().clone())
+      .await
+      .map_err(|err| SupervisorError::StartFailed {
+        id: child.id.clone(),
+        reason: err.to_string(),
+      })?;
+    let actor = running
+      .as_any()
+      .downcast_ref::<ActorRef<A>>()
+      .cloned()
+      .expect("a child spec starts its own actor type");
+    child.running = Some(running);
+    self.children.push(child);
+    Ok(actor)
+  }
+}
+
+// ── TerminateChild ───────────────────────────────────────────────────────────
+
+/// Stops a local child on purpose and stops supervising it, whatever its
+/// restart policy, e.g. before it is handed off to another node.
+pub struct TerminateChild(pub String);
+
+impl Message<TerminateChild> for Supervisor {
+  type Reply = Result<(), SupervisorError>;
+
+  async fn handle(
+    &mut self,
+    TerminateChild(id): TerminateChild,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let index = self
+      .children
+      .iter()
+      .position(|child| child.id == id)
+      .ok_or_else(|| SupervisorError::NotFound(id.clone()))?;
+    // Removed before stopping, so `on_link_died` no longer knows the child.
+    let child = self.children.remove(index);
+    if let Some(running) = child.running {
+      running.stop().await;
+    }
+    println!("[supervisor:{}] {id} terminated", self.name);
+    Ok(())
+  }
+}
+
+// ── GetChild ─────────────────────────────────────────────────────────────────
+
+/// The current instance of a local child; `None` while it is restarting.
+pub struct GetChild<A> {
+  id: String,
+  actor: PhantomData<fn() -> A>,
+}
+
+impl<A> GetChild<A> {
+  pub fn new(id: impl Into<String>) -> Self {
+    Self {
+      id: id.into(),
+      actor: PhantomData,
+    }
+  }
+}
+
+impl<A: Actor> Message<GetChild<A>> for Supervisor {
+  type Reply = Option<ActorRef<A>>;
+
+  async fn handle(
+    &mut self,
+    GetChild { id, .. }: GetChild<A>,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    self
+      .children
+      .iter()
+      .find(|child| child.id == id)?
+      .running
+      .as_ref()?
+      .as_any()
+      .downcast_ref::<ActorRef<A>>()
+      .cloned()
+  }
+}
+
+// ── WhichChildren ────────────────────────────────────────────────────────────
+
+pub struct WhichChildren;
+
+#[derive(Clone, Debug)]
+pub struct ChildInfo {
+  pub id: String,
+  pub actor_id: Option<ActorId>,
+  pub restart: RestartPolicy,
+  /// `Some(node)` for a remote actor this supervisor only watches.
+  pub watched_on: Option<String>,
+}
+
+impl Message<WhichChildren> for Supervisor {
+  type Reply = Vec<ChildInfo>;
+
+  async fn handle(
+    &mut self,
+    _msg: WhichChildren,
+    _ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    let local = self.children.iter().map(|child| ChildInfo {
+      id: child.id.clone(),
+      actor_id: child.running_id(),
+      restart: child.restart,
+      watched_on: None,
+    });
+    let watched = self.watches.iter().map(|watch| ChildInfo {
+      id: watch.name.clone(),
+      actor_id: Some(watch.actor_id),
+      restart: watch.restart,
+      watched_on: Some(watch.home_node.clone()),
+    });
+    local.chain(watched).collect()
+  }
+}
+
+// ── WatchRemote ──────────────────────────────────────────────────────────────
+
+/// Links a remote actor registered as `name` on `home_node`. Its home node's
+/// supervisor restarts it after a crash; if `home_node` disconnects instead
+/// and the [`Cluster`] picks this node, `failover` starts it here.
+pub struct WatchRemote<A: Actor + RemoteActor> {
+  pub name: String,
+  pub home_node: String,
+  pub actor: RemoteActorRef<A>,
+  pub failover: ChildSpec<A>,
+}
+
+impl<A: Actor + RemoteActor> Message<WatchRemote<A>> for Supervisor {
+  type Reply = Result<(), SupervisorError>;
+
+  async fn handle(
+    &mut self,
+    WatchRemote {
+      name,
+      home_node,
+      actor,
+      failover,
+    }: WatchRemote<A>,
+    ctx: &mut Context<Self, Self::Reply>,
+  ) -> Self::Reply {
+    ctx
+      .actor_ref()
+      .link_remote(&actor)
+      .await
+      .map_err(|err| SupervisorError::Watch {
+        name: name.clone(),
+        reason: err.to_string(),
+      })?;
+
+    println!("[supervisor:{}] watching {name} on {home_node}", self.name);
+    self.watches.push(Watch {
+      actor_id: actor.id(),
+      restart: failover.restart_policy(),
+      remote: Box::new(Watched {
+        name: name.clone(),
+        home_node: home_node.clone(),
+        actor,
+        failover,
+      }),
+      name,
+      home_node,
+    });
+    Ok(())
+  }
+}
+
+struct Watch {
+  name: String,
+  home_node: String,
+  actor_id: ActorId,
+  restart: RestartPolicy,
+  remote: Box<dyn WatchedActor>,
+}
+
+/// A watched remote actor, without its actor type.
+trait WatchedActor: Send + Sync {
+  fn unlink(&self, supervisor: &ActorRef<Supervisor>) -> BoxFuture<'static, ()>;
+
+  /// Re-links the replacement once the home node has re-registered it.
+  fn rewatch(&self, supervisor: ActorRef<Supervisor>, dead: ActorId);
+
+  fn failover(&self) -> Child;
+}
+
+struct Watched<A: Actor + RemoteActor> {
+  name: String,
+  home_node: String,
+  actor: RemoteActorRef<A>,
+  failover: ChildSpec<A>,
+}
+
+impl<A: Actor + RemoteActor> WatchedActor for Watched<A> {
+  fn unlink(&self, supervisor: &ActorRef<Supervisor>) -> BoxFuture<'static, ()> {
+    let supervisor = supervisor.clone();
+    let actor = self.actor.clone();
+    Box::pin(async move {
+      let _ = supervisor.unlink_remote(&actor).await;
+    })
+  }
+
+  fn rewatch(&self, supervisor: ActorRef<Supervisor>, dead: ActorId) {
+    let name = self.name.clone();
+    let home_node = self.home_node.clone();
+    let failover = self.failover.clone();
+    tokio::spawn(async move {
+      for _ in 0 .. REWATCH_ATTEMPTS {
+        tokio::time::sleep(REWATCH_INTERVAL).await;
+        let Ok(Some(actor)) = RemoteActorRef::<A>::lookup(name.as_str()).await else {
+          continue;
+        };
+        if actor.id() == dead {
+          continue;
+        }
+        let _ = supervisor
+          .ask(WatchRemote {
+            name,
+            home_node,
+            actor,
+            failover,
+          })
+          .await;
+        return;
+      }
+      println!("[supervisor] gave up waiting for a replacement of {name}");
+    });
+  }
+
+  fn failover(&self) -> Child {
+    self.failover.clone().into_child()
+  }
+}
+
+// ── Errors ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug)]
+pub enum SupervisorError {
+  AlreadyStarted(String),
+  NotFound(String),
+  StartFailed { id: String, reason: String },
+  Watch { name: String, reason: String },
+}
+
+impl fmt::Display for SupervisorError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SupervisorError::AlreadyStarted(id) => write!(f, "child {id} is already supervised"),
+      SupervisorError::NotFound(id) => write!(f, "no child {id}"),
+      SupervisorError::StartFailed { id, reason } => {
+        write!(f, "child {id} failed to start: {reason}")
+      }
+      SupervisorError::Watch { name, reason } => write!(f, "cannot watch {name}: {reason}"),
+    }
+  }
+}
+
+impl std::error::Error for SupervisorError {}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+  };
+
+  use kameo::error::Infallible;
+
+  use super::*;
+
+  /// Counts its own starts so tests can tell a restart from a survivor.
+  struct Worker {
+    generation: u32,
+  }
+
+  impl Actor for Worker {
+    type Args = Arc<AtomicU32>;
+    type Error = Infallible;
+
+    async fn on_start(starts: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+      Ok(Self {
+        generation: starts.fetch_add(1, Ordering::SeqCst) + 1,
+      })
+    }
+  }
+
+  struct Generation;
+
+  impl Message<Generation> for Worker {
+    type Reply = u32;
+
+    async fn handle(
+      &mut self,
+      _msg: Generation,
+      _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+      self.generation
+    }
+  }
+
+  async fn start_workers(
+    strategy: SupervisionStrategy,
+    intensity: u32,
+  ) -> (ActorRef<Supervisor>, Vec<Arc<AtomicU32>>) {
+    let starts: Vec<Arc<AtomicU32>> = (0 .. 3).map(|_| Arc::default()).collect();
+    let mut config = SupervisorConfig::new("test")
+      .strategy(strategy)
+      .restart_intensity(intensity, Duration::from_secs(5));
+    for (index, counter) in starts.iter().enumerate() {
+      let counter = counter.clone();
+      config = config.child(ChildSpec::<Worker>::new(
+        format!("worker-{index}"),
+        move || counter.clone(),
+      ));
+    }
+    let supervisor = Supervisor::spawn(config);
+    supervisor.wait_for_startup().await;
+    (supervisor, starts)
+  }
+
+  async fn crash(supervisor: &ActorRef<Supervisor>, id: &str) {
+    let worker = supervisor
+      .ask(GetChild::<Worker>::new(id))
+      .await
+      .unwrap()
+      .unwrap();
+    worker.kill();
+    worker.wait_for_shutdown().await;
+    for _ in 0 .. 50 {
+      if let Some(next) = supervisor.ask(GetChild::<Worker>::new(id)).await.unwrap()
+        && next.id() != worker.id()
+      {
+        return;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("{id} was not restarted");
+  }
+
+  fn counts(starts: &[Arc<AtomicU32>]) -> Vec<u32> {
+    starts
+      .iter()
+      .map(|counter| counter.load(Ordering::SeqCst))
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn one_for_one_restarts_only_the_failed_child() {
+    let (supervisor, starts) = start_workers(SupervisionStrategy::OneForOne, 3).await;
+    crash(&supervisor, "worker-1").await;
+    assert_eq!(counts(&starts), vec![1, 2, 1]);
+
+    let worker = supervisor
+      .ask(GetChild::<Worker>::new("worker-1"))
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(worker.ask(Generation).await.unwrap(), 2);
+  }
+
+  #[tokio::test]
+  async fn one_for_all_restarts_every_child() {
+    let (supervisor, starts) = start_workers(SupervisionStrategy::OneForAll, 3).await;
+    crash(&supervisor, "worker-1").await;
+    assert_eq!(counts(&starts), vec![2, 2, 2]);
+  }
+
+  #[tokio::test]
+  async fn rest_for_one_restarts_the_failed_child_and_later_ones() {
+    let (supervisor, starts) = start_workers(SupervisionStrategy::RestForOne, 3).await;
+    crash(&supervisor, "worker-1").await;
+    assert_eq!(counts(&starts), vec![1, 2, 2]);
+  }
+
+  #[tokio::test]
+  async fn exceeding_the_restart_intensity_stops_the_tree() {
+    let (supervisor, _starts) = start_workers(SupervisionStrategy::OneForOne, 1).await;
+    crash(&supervisor, "worker-0").await;
+    let survivor = supervisor
+      .ask(GetChild::<Worker>::new("worker-2"))
+      .await
+      .unwrap()
+      .unwrap();
+
+    let worker = supervisor
+      .ask(GetChild::<Worker>::new("worker-0"))
+      .await
+      .unwrap()
+      .unwrap();
+    worker.kill();
+    supervisor.wait_for_shutdown().await;
+    survivor.wait_for_shutdown().await;
+    assert!(!survivor.is_alive());
+  }
+
+  #[tokio::test]
+  async fn temporary_children_are_not_restarted() {
+    let supervisor = Supervisor::spawn(SupervisorConfig::new("test"));
+    let starts = Arc::new(AtomicU32::new(0));
+    let counter = starts.clone();
+    let worker = supervisor
+      .ask(StartChild(
+        ChildSpec::<Worker>::new("once", move || counter.clone()).restart(RestartPolicy::Never),
+      ))
+      .await
+      .unwrap();
+    worker.kill();
+    worker.wait_for_shutdown().await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(starts.load(Ordering::SeqCst), 1);
+    assert!(supervisor.ask(WhichChildren).await.unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn terminated_children_stay_down() {
+    let (supervisor, starts) = start_workers(SupervisionStrategy::OneForAll, 3).await;
+    let worker = supervisor
+      .ask(GetChild::<Worker>::new("worker-1"))
+      .await
+      .unwrap()
+      .unwrap();
+    supervisor
+      .ask(TerminateChild("worker-1".to_string()))
+      .await
+      .unwrap();
+    assert!(!worker.is_alive());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(counts(&starts), vec![1, 1, 1]);
+    assert_eq!(supervisor.ask(WhichChildren).await.unwrap().len(), 2);
+    assert!(
+      supervisor
+        .ask(TerminateChild("worker-1".to_string()))
+        .await
+        .is_err()
+    );
+  }
+}
@@ -52,6 +52,13 @@ pub struct GetState;
 #[derive(Debug, Clone)]
 pub struct StopServer;
 
+/// Fails the handler, which stops the actor as panicked when sent with `tell`
+/// — the gen_server equivalent of an unexpected crash.
+#[derive(Debug, Clone)]
+pub struct Crash {
+  pub reason: String,
+}
+
 impl GenServerActor {
   pub fn new(
     name: impl Into<String>,
@@ -283,6 +290,15 @@ impl Message<StopServer> for GenServerActor {
   }
 }
 
+impl Message<Crash> for GenServerActor {
+  type Reply = anyhow::Result<()>;
+
+  async fn handle(&mut self, msg: Crash, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+    warn!(name = %self.name, reason = %msg.reason, "gen_server crashing");
+    Err(anyhow!("{} crashed: {}", self.name, msg.reason))
+  }
+}
+
 pub async fn run_gen_server_demo() -> anyhow::Result<()> {
   let (server, external_tx) = GenServerActor::new("gen-server-demo", Duration::from_millis(200));
   let server = GenServerActor::spawn(server);
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use kameo_supervisor::{ChildSpec, GetChild, SupervisionStrategy, Supervisor, SupervisorConfig};
+use tokio::time::sleep;
+
+use crate::gen_server_actor::{CastAdd, Crash, GenServerActor, GetState};
+
+const COUNTERS: [&str; 3] = ["counter-a", "counter-b", "counter-c"];
+
+/// Each start builds a fresh gen_server, exactly like an Erlang child spec's
+/// `start` function. The external event sender is dropped because nothing in
+/// this demo feeds it.
+fn counter_spec(name: &'static str) -> ChildSpec<GenServerActor> {
+  ChildSpec::new(name, move || {
+    GenServerActor::new(name, Duration::from_secs(60)).0
+  })
+}
+
+fn counter_tree(strategy: SupervisionStrategy, max_restarts: u32) -> SupervisorConfig {
+  COUNTERS.into_iter().fold(
+    SupervisorConfig::new(format!("{strategy:?}"))
+      .strategy(strategy)
+      .restart_intensity(max_restarts, Duration::from_secs(5)),
+    |config, name| config.child(counter_spec(name)),
+  )
+}
+
+pub async fn run_supervisor_demo() -> anyhow::Result<()> {
+  for strategy in [
+    SupervisionStrategy::OneForOne,
+    SupervisionStrategy::OneForAll,
+    SupervisionStrategy::RestForOne,
+  ] {
+    let supervisor = Supervisor::spawn(counter_tree(strategy, 3));
+    supervisor.wait_for_startup().await;
+
+    for (amount, name) in (1 ..).zip(COUNTERS) {
+      counter(&supervisor, name)
+        .await?
+        .tell(CastAdd {
+          amount: amount * 10,
+          reason: "before crash".to_string(),
+        })
+        .await
+        .map_err(|err| anyhow!("cast add failed: {err:?}"))?;
+    }
+    println!(
+      "{strategy:?}: before crash {:?}",
+      counter_values(&supervisor).await?
+    );
+
+    crash_and_wait_for_restart(&supervisor, "counter-b").await?;
+    println!(
+      "{strategy:?}: after counter-b crashed {:?}",
+      counter_values(&supervisor).await?
+    );
+
+    supervisor.stop_gracefully().await?;
+    supervisor.wait_for_shutdown().await;
+  }
+
+  // Two restarts are allowed within five seconds; the third crash makes the
+  // supervisor give up and take the remaining counters down with it.
+  let supervisor = Supervisor::spawn(counter_tree(SupervisionStrategy::OneForOne, 2));
+  supervisor.wait_for_startup().await;
+  let survivor = counter(&supervisor, "counter-a").await?;
+  let mut crashes = 0;
+  while let Ok(Some(counter)) = supervisor
+    .ask(GetChild::<GenServerActor>::new("counter-b"))
+    .await
+  {
+    crashes += 1;
+    counter
+      .tell(Crash {
+        reason: format!("crash #{crashes}"),
+      })
+      .await
+      .map_err(|err| anyhow!("crash failed: {err:?}"))?;
+    counter.wait_for_shutdown().await;
+    sleep(Duration::from_millis(20)).await;
+  }
+  supervisor.wait_for_shutdown().await;
+  println!(
+    "restart intensity exceeded after {crashes} crashes; counter-a alive: {}",
+    survivor.is_alive()
+  );
+
+  Ok(())
+}
+
+async fn counter(
+  supervisor: &ActorRef<Supervisor>,
+  name: &str,
+) -> anyhow::Result<ActorRef<GenServerActor>> {
+  supervisor
+    .ask(GetChild::<GenServerActor>::new(name))
+    .await
+    .map_err(|err| anyhow!("get child failed: {err:?}"))?
+    .ok_or_else(|| anyhow!("{name} is not running"))
+}
+
+async fn counter_values(
+  supervisor: &ActorRef<Supervisor>,
+) -> anyhow::Result<Vec<(&'static str, i64)>> {
+  let mut values = Vec::new();
+  for name in COUNTERS {
+    let state = counter(supervisor, name)
+      .await?
+      .ask(GetState)
+      .await
+      .map_err(|err| anyhow!("get state failed: {err:?}"))?;
+    values.push((name, state.value));
+  }
+  Ok(values)
+}
+
+async fn crash_and_wait_for_restart(
+  supervisor: &ActorRef<Supervisor>,
+  name: &str,
+) -> anyhow::Result<()> {
+  let crashed = counter(supervisor, name).await?;
+  crashed
+    .tell(Crash {
+      reason: "demo".to_string(),
+    })
+    .await
+    .map_err(|err| anyhow!("crash failed: {err:?}"))?;
+  crashed.wait_for_shutdown().await;
+
+  for _ in 0 .. 50 {
+    if let Ok(restarted) = counter(supervisor, name).await
+      && restarted.id() != crashed.id()
+    {
+      return Ok(());
+    }
+    sleep(Duration::from_millis(10)).await;
+  }
+  Err(anyhow!("{name} was not restarted"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn rest_for_one_resets_the_crashed_counter_and_later_ones() -> anyhow::Result<()> {
+    let supervisor = Supervisor::spawn(counter_tree(SupervisionStrategy::RestForOne, 3));
+    supervisor.wait_for_startup().await;
+
+    for name in COUNTERS {
+      counter(&supervisor, name)
+        .await?
+        .tell(CastAdd {
+          amount: 5,
+          reason: "test cast".to_string(),
+        })
+        .await
+        .map_err(|err| anyhow!("cast add failed: {err:?}"))?;
+    }
+    crash_and_wait_for_restart(&supervisor, "counter-b").await?;
+
+    assert_eq!(
+      counter_values(&supervisor).await?,
+      vec![("counter-a", 5), ("counter-b", 0), ("counter-c", 0)]
+    );
+
+    supervisor.stop_gracefully().await?;
+    supervisor.wait_for_shutdown().await;
+    Ok(())
+  }
+}
@@ -1,4 +1,5 @@
 mod gen_server_actor;
+mod gen_server_supervisor;
 mod raft_counter;
 
 use std::{future::Future, net::SocketAddr, time::Duration};
@@ -73,6 +74,8 @@ enum Command {
   },
   /// Runs a local kameo actor that uses tokio::select! like an Erlang gen_server loop.
   GenServerDemo,
+  /// Supervises gen_server actors with each restart strategy and crashes one.
+  SupervisorDemo,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -532,6 +535,7 @@ async fn main() -> anyhow::Result<()> {
       caller,
     } => run_rpc_prost_client(server_addr, kind, payload, caller).await,
     Command::GenServerDemo => gen_server_actor::run_gen_server_demo().await,
+    Command::SupervisorDemo => gen_server_supervisor::run_supervisor_demo().await,
   }
 }
 
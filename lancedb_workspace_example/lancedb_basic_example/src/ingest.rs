@@ -0,0 +1,329 @@
+//! # 增量写入流水线
+//!
+//! 把“写入 + 索引维护”封装成可复用的一层：
+//!   - 按主键 upsert（merge-insert：已有的行整行更新，新行插入）
+//!   - 未进入索引的行数攒够阈值后，重建向量索引和全文索引
+//!   - 定期合并小碎片（compaction）并清理旧版本
+//!   - 嵌入函数版本变化后，把旧版本算出的向量重新计算一遍
+//!
+//! 向量由流水线自己调用嵌入函数计算，同时在 `embedding_version` 列记下版本号，
+//! 这样换了嵌入模型之后能用一个过滤条件找出所有需要重算的行。
+
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use lancedb::{
+  Error, Result, Table,
+  arrow::{
+    arrow_array::{Array, ArrayRef, RecordBatch, RecordBatchIterator, StringArray},
+    arrow_schema::{DataType, Field, Schema, SchemaRef},
+  },
+  connection::Connection,
+  embeddings::EmbeddingFunction,
+  index::{Index, scalar::FtsIndexBuilder, vector::IvfFlatIndexBuilder},
+  query::{ExecutableQuery, QueryBase},
+  table::{CompactionOptions, OptimizeAction},
+};
+use tokio::task::JoinHandle;
+
+/// 记录每行向量由哪个版本的嵌入函数算出
+pub const EMBEDDING_VERSION_COLUMN: &str = "embedding_version";
+
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+  /// 主键列，upsert 按它匹配已有的行
+  pub key: String,
+  /// 需要向量化、同时建全文索引的文本列
+  pub text_column: String,
+  /// 向量列
+  pub vector_column: String,
+  /// 嵌入函数在连接的注册表中的名字
+  pub embedding: String,
+  /// 嵌入函数的版本。EmbeddingFunction 本身没有版本，注册表里换了实现时改这里
+  pub embedding_version: String,
+  /// 未进入索引的行数达到该值时重建索引
+  pub reindex_threshold: usize,
+  /// 向量索引（IVF-Flat）的分区数
+  pub ivf_partitions: u32,
+  /// 定期整理（合并碎片 + 清理旧版本）的间隔
+  pub maintenance_interval: Duration,
+  /// 旧版本保留多久，更早的在清理时删除
+  pub keep_versions_for: Duration,
+  /// 重新向量化时每批读出的行数
+  pub reembed_batch_size: usize,
+}
+
+impl IngestConfig {
+  pub fn new(key: &str, text_column: &str, embedding: &str, embedding_version: &str) -> Self {
+    Self {
+      key: key.to_string(),
+      text_column: text_column.to_string(),
+      vector_column: "vector".to_string(),
+      embedding: embedding.to_string(),
+      embedding_version: embedding_version.to_string(),
+      reindex_threshold: 256,
+      ivf_partitions: 4,
+      maintenance_interval: Duration::from_secs(600),
+      keep_versions_for: Duration::from_secs(3600),
+      reembed_batch_size: 1024,
+    }
+  }
+}
+
+/// 一次 upsert 的结果
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpsertStats {
+  pub inserted: u64,
+  pub updated: u64,
+  /// 这次写入之后是否重建了索引
+  pub reindexed: bool,
+}
+
+/// 一次整理的结果
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaintenanceStats {
+  pub fragments_removed: usize,
+  pub fragments_added: usize,
+  pub versions_removed: u64,
+  pub bytes_removed: u64,
+}
+
+/// 写入一张表的流水线。克隆很便宜（表句柄和嵌入函数都是共享的）
+#[derive(Debug, Clone)]
+pub struct Ingestor {
+  table: Table,
+  schema: SchemaRef,
+  embedding: Arc<dyn EmbeddingFunction>,
+  config: Arc<IngestConfig>,
+}
+
+impl Ingestor {
+  /// 打开表，不存在时按 `source_schema`（写入数据的列）加上向量列和版本列建空表
+  pub async fn open(
+    db: &Connection,
+    name: &str,
+    source_schema: SchemaRef,
+    config: IngestConfig,
+  ) -> Result<Self> {
+    let embedding = db
+      .embedding_registry()
+      .get(&config.embedding)
+      .ok_or_else(|| Error::InvalidInput {
+        message: format!("embedding function {} is not registered", config.embedding),
+      })?;
+
+    let table = if db.table_names().execute().await?.iter().any(|t| t == name) {
+      db.open_table(name).execute().await?
+    } else {
+      let mut fields: Vec<Field> = source_schema
+        .fields()
+        .iter()
+        .map(|f| f.as_ref().clone())
+        .collect();
+      fields.push(Field::new(
+        &config.vector_column,
+        embedding.dest_type()?.into_owned(),
+        true,
+      ));
+      fields.push(Field::new(EMBEDDING_VERSION_COLUMN, DataType::Utf8, true));
+      db.create_empty_table(name, Arc::new(Schema::new(fields)))
+        .execute()
+        .await?
+    };
+    let schema = table.schema().await?;
+
+    Ok(Self {
+      table,
+      schema,
+      embedding,
+      config: Arc::new(config),
+    })
+  }
+
+  pub fn table(&self) -> &Table {
+    &self.table
+  }
+
+  /// 按主键 upsert 一批数据（不含向量列），之后按需重建索引
+  pub async fn upsert(&self, batch: RecordBatch) -> Result<UpsertStats> {
+    let (inserted, updated) = self.merge(self.embed(&batch)?).await?;
+    let reindexed = self.maintain_indexes().await?;
+    Ok(UpsertStats {
+      inserted,
+      updated,
+      reindexed,
+    })
+  }
+
+  /// 未进入索引的行数达到阈值时重建向量索引和全文索引，返回是否重建过。
+  /// 索引还不存在时，表里的行数就是未索引行数
+  pub async fn maintain_indexes(&self) -> Result<bool> {
+    let rows = self.table.count_rows(None).await?;
+    let indexes = [
+      (
+        self.config.vector_column.as_str(),
+        Index::IvfFlat(IvfFlatIndexBuilder::default().num_partitions(self.config.ivf_partitions)),
+      ),
+      (
+        self.config.text_column.as_str(),
+        Index::FTS(FtsIndexBuilder::default()),
+      ),
+    ];
+
+    let mut rebuilt = false;
+    for (column, index) in indexes {
+      let unindexed = match self.table.index_stats(format!("{column}_idx")).await? {
+        Some(stats) => stats.num_unindexed_rows,
+        None => rows,
+      };
+      if unindexed >= self.config.reindex_threshold {
+        self
+          .table
+          .create_index(&[column], index)
+          .replace(true)
+          .execute()
+          .await?;
+        rebuilt = true;
+      }
+    }
+    Ok(rebuilt)
+  }
+
+  /// 重新计算所有不是当前版本嵌入函数算出的向量，返回处理的行数
+  pub async fn reembed_stale(&self) -> Result<usize> {
+    let filter = format!(
+      "{EMBEDDING_VERSION_COLUMN} IS NULL OR {EMBEDDING_VERSION_COLUMN} != '{}'",
+      self.config.embedding_version.replace('\'', "''")
+    );
+
+    // 处理过的行不再满足过滤条件，所以每次都从头取一批，直到取不到为止
+    let mut total = 0;
+    loop {
+      let batches = self
+        .table
+        .query()
+        .only_if(filter.clone())
+        .limit(self.config.reembed_batch_size)
+        .execute()
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+      let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+      if rows == 0 {
+        break;
+      }
+      for batch in batches.iter().filter(|b| b.num_rows() > 0) {
+        self.merge(self.embed(batch)?).await?;
+      }
+      total += rows;
+    }
+
+    if total > 0 {
+      self.maintain_indexes().await?;
+    }
+    Ok(total)
+  }
+
+  /// 合并小碎片，再删除早于 `keep_versions_for` 的旧版本
+  pub async fn compact_and_cleanup(&self) -> Result<MaintenanceStats> {
+    let older_than = chrono::Duration::from_std(self.config.keep_versions_for).map_err(|e| {
+      Error::InvalidInput {
+        message: format!("keep_versions_for out of range: {e}"),
+      }
+    })?;
+
+    let compacted = self
+      .table
+      .optimize(OptimizeAction::Compact {
+        options: CompactionOptions::default(),
+        remap_options: None,
+      })
+      .await?;
+    let pruned = self
+      .table
+      .optimize(OptimizeAction::Prune {
+        older_than: Some(older_than),
+        delete_unverified: None,
+        error_if_tagged_old_versions: None,
+      })
+      .await?;
+
+    let mut stats = MaintenanceStats::default();
+    if let Some(compaction) = compacted.compaction {
+      stats.fragments_removed = compaction.fragments_removed;
+      stats.fragments_added = compaction.fragments_added;
+    }
+    if let Some(prune) = pruned.prune {
+      stats.versions_removed = prune.old_versions;
+      stats.bytes_removed = prune.bytes_removed;
+    }
+    Ok(stats)
+  }
+
+  /// 在后台按 `maintenance_interval` 定期整理，abort 返回的句柄即停止
+  pub fn spawn_maintenance(&self) -> JoinHandle<()> {
+    let this = self.clone();
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(this.config.maintenance_interval);
+      // 第一次 tick 立即返回，刚写完的表不急着整理
+      ticker.tick().await;
+      loop {
+        ticker.tick().await;
+        match this.compact_and_cleanup().await {
+          Ok(stats) => println!("[定期整理] {}: {stats:?}", this.table.name()),
+          Err(e) => eprintln!("[定期整理] {} 失败: {e}", this.table.name()),
+        }
+      }
+    })
+  }
+
+  /// 按表结构的列顺序重新拼一批数据：向量列和版本列用当前嵌入函数重新计算，
+  /// 其余列从 `batch` 里按名字取
+  fn embed(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+    let text = batch
+      .column_by_name(&self.config.text_column)
+      .ok_or_else(|| Error::InvalidInput {
+        message: format!("missing text column {}", self.config.text_column),
+      })?;
+    if text.null_count() > 0 {
+      return Err(Error::InvalidInput {
+        message: format!("text column {} has nulls", self.config.text_column),
+      });
+    }
+    let vectors = self.embedding.compute_source_embeddings(text.clone())?;
+    let versions: ArrayRef = Arc::new(StringArray::from_iter_values(std::iter::repeat_n(
+      self.config.embedding_version.as_str(),
+      batch.num_rows(),
+    )));
+
+    let columns = self
+      .schema
+      .fields()
+      .iter()
+      .map(|field| match field.name().as_str() {
+        name if name == self.config.vector_column => Ok(vectors.clone()),
+        EMBEDDING_VERSION_COLUMN => Ok(versions.clone()),
+        name => batch
+          .column_by_name(name)
+          .cloned()
+          .ok_or_else(|| Error::InvalidInput {
+            message: format!("missing column {name}"),
+          }),
+      })
+      .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+  }
+
+  /// merge-insert：主键命中的整行更新，没命中的插入。返回（插入行数, 更新行数）
+  async fn merge(&self, batch: RecordBatch) -> Result<(u64, u64)> {
+    let schema = batch.schema();
+    let mut merge = self.table.merge_insert(&[self.config.key.as_str()]);
+    merge
+      .when_matched_update_all(None)
+      .when_not_matched_insert_all();
+    let result = merge
+      .execute(Box::new(RecordBatchIterator::new([Ok(batch)], schema)))
+      .await?;
+    Ok((result.num_inserted_rows, result.num_updated_rows))
+  }
+}
@@ -17,8 +17,11 @@
 //!   2. 向量相似度检索 + 向量索引（IVF-Flat 近似最近邻）
 //!   3. AI Embedding：自定义 EmbeddingFunction + 注册表，文本自动转向量
 //!   4. 全文检索（BM25）+ 混合检索（向量 + 全文 + RRF 重排）
+//!   5. 增量写入流水线：主键 upsert + 索引维护 + 定期整理 + 嵌入函数升级后重新向量化
 
-use std::{borrow::Cow, sync::Arc};
+mod ingest;
+
+use std::{borrow::Cow, sync::Arc, time::Duration};
 
 use lancedb::arrow::arrow_array::{
   Array, FixedSizeListArray, Float32Array, Float64Array, Int32Array, RecordBatch, StringArray,
@@ -35,6 +38,8 @@ use lancedb::{
   query::{ExecutableQuery, QueryBase, QueryExecutionOptions, Select},
 };
 
+use crate::ingest::{IngestConfig, Ingestor};
+
 /// 商品向量的维度
 const VECTOR_DIM: usize = 16;
 /// 文本嵌入向量的维度（本示例用 HashEmbedding，真实项目可换成预训练模型）
@@ -55,6 +60,7 @@ async fn main() -> Result<()> {
   demo_vector_search(&db).await?;
   demo_embeddings(&db).await?;
   demo_fulltext_hybrid(&db).await?;
+  demo_ingestion(&db).await?;
   demo_cleanup(&db).await?;
   Ok(())
 }
@@ -329,11 +335,91 @@ async fn demo_fulltext_hybrid(db: &Connection) -> Result<()> {
 }
 
 // ---------------------------------------------------------------------------
-// 5. 清理
+// 5. 增量写入流水线（见 ingest 模块）
+// ---------------------------------------------------------------------------
+async fn demo_ingestion(db: &Connection) -> Result<()> {
+  println!("======================================");
+  println!("5. 增量写入流水线：upsert + 索引维护 + 整理 + 重新向量化");
+  println!("======================================\n");
+
+  // 5.1 注册第 1 版嵌入函数，打开（不存在则创建）catalog 表
+  let embedding = Arc::new(HashEmbedding::new("catalog-embedding", EMBEDDING_DIM));
+  db.embedding_registry()
+    .register(embedding.name(), embedding.clone())?;
+  let mut config = IngestConfig::new("id", "text", embedding.name(), "1");
+  // 演示数据量小，阈值也调小，攒够 20 行未索引数据就重建索引
+  config.reindex_threshold = 20;
+  config.ivf_partitions = 2;
+  config.keep_versions_for = Duration::ZERO;
+  let first = make_catalog_batch(0, &article_facts())?;
+  let schema = first.schema();
+  let ingestor = Ingestor::open(db, "catalog", schema.clone(), config.clone()).await?;
+
+  // 5.2 首次写入：全部是新行，未索引行数达到阈值，建立向量索引和全文索引
+  let stats = ingestor.upsert(first).await?;
+  println!(
+    "[upsert] 插入 {} 行，更新 {} 行，重建索引: {}",
+    stats.inserted, stats.updated, stats.reindexed
+  );
+
+  // 5.3 再写一批：id 20..25 已存在（整行更新），25..28 是新行；未索引行数不够，不重建
+  let stats = ingestor
+    .upsert(make_catalog_batch(20, &extra_catalog_facts())?)
+    .await?;
+  println!(
+    "[upsert] 插入 {} 行，更新 {} 行，重建索引: {}",
+    stats.inserted, stats.updated, stats.reindexed
+  );
+  println!(
+    "         当前行数 = {}\n",
+    ingestor.table().count_rows(None).await?
+  );
+
+  // 5.4 嵌入模型升级：同名注册第 2 版，版本号改为 "2"，旧向量全部重算
+  let upgraded = Arc::new(HashEmbedding::new("catalog-embedding", EMBEDDING_DIM).with_seed(1));
+  db.embedding_registry()
+    .register(upgraded.name(), upgraded.clone())?;
+  config.embedding_version = "2".to_string();
+  let ingestor = Ingestor::open(db, "catalog", schema, config).await?;
+  println!(
+    "[重新向量化] 嵌入函数升级到版本 2，重算了 {} 行\n",
+    ingestor.reembed_stale().await?
+  );
+
+  // 5.5 整理：多次写入留下的小碎片合并成大文件，旧版本（保留时长 0）全部清理
+  let stats = ingestor.compact_and_cleanup().await?;
+  println!(
+    "[整理] 合并碎片 {} -> {}，清理旧版本 {} 个（{} 字节）",
+    stats.fragments_removed, stats.fragments_added, stats.versions_removed, stats.bytes_removed
+  );
+
+  // 5.6 真实服务里整理放在后台定期跑；这里启动后立即停止
+  ingestor.spawn_maintenance().abort();
+
+  // 升级后的查询向量和表里的向量出自同一版本，语义检索照常工作
+  let query = "Who wrote Hamlet?";
+  let qv = upgraded.compute_query_embeddings(Arc::new(StringArray::from_iter_values([query])))?;
+  println!("\n[语义检索] 问题: \"{}\"\n最相似的 3 条:", query);
+  let rows = ingestor
+    .table()
+    .query()
+    .nearest_to(qv)?
+    .limit(3)
+    .execute()
+    .await?
+    .try_collect::<Vec<_>>()
+    .await?;
+  print_cols(&rows, &["id", "text", "embedding_version"]);
+  println!();
+  Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// 6. 清理
 // ---------------------------------------------------------------------------
 async fn demo_cleanup(db: &Connection) -> Result<()> {
   println!("======================================");
-  println!("5. 清理：删除表");
+  println!("6. 清理：删除表");
   println!("======================================\n");
   for t in db.table_names().execute().await? {
     db.drop_table(&t, &[]).await?;
@@ -407,7 +493,12 @@ fn vector_for_id(id: i32) -> Vec<f32> {
 
 /// 一批 AI 知识事实（25 条）
 fn make_articles_batch() -> Result<RecordBatch> {
-  let facts = vec![
+  make_text_batch(article_facts())
+}
+
+/// 25 条知识事实，articles 表和 catalog 表共用
+fn article_facts() -> Vec<&'static str> {
+  vec![
     "Albert Einstein was a theoretical physicist.",
     "The capital of France is Paris.",
     "The Great Wall of China is one of the Seven Wonders of the World.",
@@ -433,8 +524,7 @@ fn make_articles_batch() -> Result<RecordBatch> {
     "IBM's Watson won Jeopardy! in 2011.",
     "The first computer programmer was Ada Lovelace.",
     "The first chatbot was ELIZA, created in the 1960s.",
-  ];
-  make_text_batch(facts)
+  ]
 }
 
 /// 追加的一批文章（演示“追加时自动向量化”）
@@ -447,6 +537,37 @@ fn make_articles_batch_extra() -> Result<RecordBatch> {
   make_text_batch(facts)
 }
 
+/// catalog 表追加的一批：前 5 条改写已有的事实，后 3 条是新事实
+fn extra_catalog_facts() -> Vec<&'static str> {
+  vec![
+    "Artificial neural networks are loosely inspired by the human brain.",
+    "Deep learning is a subset of machine learning based on neural networks.",
+    "IBM's Watson won the Jeopardy! quiz show in 2011.",
+    "Ada Lovelace is considered the first computer programmer.",
+    "ELIZA, one of the first chatbots, was created in the 1960s.",
+    "Rust is a memory-safe systems programming language.",
+    "LanceDB is an embedded vector database built for AI applications.",
+    "Retrieval augmented generation combines search with large language models.",
+  ]
+}
+
+/// 构造 id + text 两列的 RecordBatch，id 从 start 开始连续编号
+fn make_catalog_batch(start: i32, facts: &[&str]) -> Result<RecordBatch> {
+  let schema = Arc::new(Schema::new(vec![
+    Field::new("id", DataType::Int32, false),
+    Field::new("text", DataType::Utf8, true),
+  ]));
+  Ok(RecordBatch::try_new(
+    schema,
+    vec![
+      Arc::new(Int32Array::from_iter_values(
+        start .. start + facts.len() as i32,
+      )),
+      Arc::new(StringArray::from_iter_values(facts.iter().copied())),
+    ],
+  )?)
+}
+
 /// 构造只有 text 列的 RecordBatch（向量列由 add_embedding 自动生成）
 fn make_text_batch(facts: Vec<&str>) -> Result<RecordBatch> {
   let schema = Arc::new(Schema::new(vec![Field::new("text", DataType::Utf8, true)]));
@@ -472,6 +593,8 @@ fn make_text_batch(facts: Vec<&str>) -> Result<RecordBatch> {
 struct HashEmbedding {
   name: String,
   dim: usize,
+  /// 混入哈希的种子，换种子相当于换了一个嵌入模型
+  seed: u64,
 }
 
 impl HashEmbedding {
@@ -479,9 +602,15 @@ impl HashEmbedding {
     Self {
       name: name.to_string(),
       dim,
+      seed: 0,
     }
   }
 
+  fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+
   fn embed_text(&self, text: &str) -> Vec<f32> {
     let mut vec = vec![0.0f32; self.dim];
     let clean: String = text
@@ -495,7 +624,7 @@ impl HashEmbedding {
       })
       .collect();
     for word in clean.split_whitespace() {
-      vec[fnv1a(word) % self.dim] += 1.0;
+      vec[(fnv1a(word) ^ self.seed as usize) % self.dim] += 1.0;
     }
     let norm = vec.iter().map(|x| x * x).sum::<f32>().sqrt();
     if norm > 0.0 {
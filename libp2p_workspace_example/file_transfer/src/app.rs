@@ -20,7 +20,9 @@ use tokio::{
 
 use crate::{
   file_protocol::{FilePartialMessage, FileWriteOutcome, hex_id},
+  merkle::parse_root,
   network::{self, ChatBehaviorEvent, FILE_TOPIC, MessageResponse},
+  sync_protocol::FileSync,
 };
 
 pub async fn run() -> anyhow::Result<()> {
@@ -52,6 +54,8 @@ pub async fn run() -> anyhow::Result<()> {
   let mut stdin = BufReader::new(tokio::io::stdin()).lines();
   let mut file_messages: HashMap<Vec<u8>, FilePartialMessage> = HashMap::new();
   let mut completed_files: HashSet<Vec<u8>> = HashSet::new();
+  let mut file_sync = FileSync::default();
+  file_sync.resume(&mut swarm).await?;
 
   loop {
     select! {
@@ -62,6 +66,7 @@ pub async fn run() -> anyhow::Result<()> {
                 }
                 SwarmEvent::ConnectionEstablished { peer_id, .. } => {
                     println!("Connected to peer: {}", peer_id);
+                    file_sync.on_connected(&mut swarm);
                 }
                 SwarmEvent::Behaviour(event) => match event {
                     ChatBehaviorEvent::Ping(_) => {}
@@ -121,7 +126,9 @@ pub async fn run() -> anyhow::Result<()> {
                     }
                     ChatBehaviorEvent::Kademlia(event) => match event {
                         kad::Event::InboundRequest { .. } => {}
-                        kad::Event::OutboundQueryProgressed {..} => {}
+                        kad::Event::OutboundQueryProgressed { id, result, step, .. } => {
+                            file_sync.on_query_progressed(&mut swarm, id, &result, step.last);
+                        }
                         kad::Event::RoutingUpdated{
                             addresses,
                             peer,
@@ -159,6 +166,9 @@ pub async fn run() -> anyhow::Result<()> {
                     ChatBehaviorEvent::Dcutr(event) => {
                         println!("Dcutr: {:?}", event);
                     },
+                    ChatBehaviorEvent::FileSync(event) => {
+                        file_sync.on_event(&mut swarm, event).await;
+                    }
                     ChatBehaviorEvent::Gossipsub(event) => match event {
                         gossipsub::Event::Message {
                             propagation_source,
@@ -346,8 +356,32 @@ pub async fn run() -> anyhow::Result<()> {
                 continue;
             }
 
+            if line == "/files" {
+                file_sync.print_status();
+                continue;
+            }
+
+            if let Some(path) = line.strip_prefix("/share ") {
+                if let Err(error) = file_sync.share(&mut swarm, PathBuf::from(path.trim())).await {
+                    println!("Failed to share {}: {error}", path.trim());
+                }
+                continue;
+            }
+
+            if let Some(root) = line.strip_prefix("/fetch ") {
+                match parse_root(root) {
+                    Ok(root) => {
+                        if let Err(error) = file_sync.fetch(&mut swarm, root).await {
+                            println!("Failed to start fetch: {error}");
+                        }
+                    }
+                    Err(error) => println!("Invalid root hash {}: {error}", root.trim()),
+                }
+                continue;
+            }
+
             let Some(path) = line.strip_prefix("/send ") else {
-                println!("Unknown command. Use /send <path>, /peers, or /help.");
+                println!("Unknown command. Use /send <path>, /share <path>, /fetch <root>, /files, /peers, or /help.");
                 continue;
             };
 
@@ -388,6 +422,9 @@ pub async fn run() -> anyhow::Result<()> {
 fn print_commands() {
   println!("Commands:");
   println!("  /send <path>  send a file through gossipsub partial messages");
+  println!("  /share <path> serve a file by Merkle root over request/response");
+  println!("  /fetch <root> download a shared file from every provider of <root>");
+  println!("  /files        print shared files and downloads in progress");
   println!("  /peers        print connected peers");
   println!("  /help         print commands");
 }
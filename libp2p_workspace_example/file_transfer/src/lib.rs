@@ -1,5 +1,7 @@
 mod app;
 mod file_protocol;
+mod merkle;
 mod network;
+mod sync_protocol;
 
 pub use app::run;
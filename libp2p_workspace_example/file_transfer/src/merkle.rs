@@ -0,0 +1,355 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+/// Chunk size of the request/response sync mode.
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Most chunks a shared file can have, i.e. files up to 256 GiB. The whole
+/// manifest travels in one sync response, so this bounds its size.
+pub(crate) const MAX_CHUNKS: usize = 1 << 20;
+
+/// Upper bound on an encoded [`Manifest`]: 32 bytes per leaf plus room for
+/// the file name and the CBOR framing.
+pub(crate) const MAX_MANIFEST_BYTES: u64 = MAX_CHUNKS as u64 * 32 + 64 * 1024;
+
+pub(crate) type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const ROOT_PREFIX: u8 = 2;
+
+/// Everything a downloader needs to verify a file chunk by chunk: the hash of
+/// every chunk, plus the size they add up to. The root hash commits to both,
+/// so a manifest fetched from any peer can be checked against the root the
+/// user asked for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+  pub(crate) file_name: String,
+  pub(crate) file_size: u64,
+  pub(crate) chunk_size: u32,
+  #[serde(with = "packed_hashes")]
+  pub(crate) leaves: Vec<Hash>,
+}
+
+impl Manifest {
+  /// Hashes the file at `path` one chunk at a time, so large files never need
+  /// to fit in memory.
+  pub(crate) async fn from_path(path: &Path) -> anyhow::Result<Self> {
+    let file_name = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .ok_or_else(|| anyhow::anyhow!("file path has no valid UTF-8 file name"))?
+      .to_string();
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut leaves = Vec::new();
+    let mut file_size = 0u64;
+    loop {
+      let len = read_full(&mut file, &mut buffer).await?;
+      if len == 0 && !leaves.is_empty() {
+        break;
+      }
+      if leaves.len() == MAX_CHUNKS {
+        anyhow::bail!(
+          "file is larger than {} bytes, the most that can be shared",
+          MAX_CHUNKS * CHUNK_SIZE
+        );
+      }
+      leaves.push(leaf_hash(&buffer[.. len]));
+      file_size += len as u64;
+      if len < CHUNK_SIZE {
+        break;
+      }
+    }
+
+    Ok(Self {
+      file_name,
+      file_size,
+      chunk_size: CHUNK_SIZE as u32,
+      leaves,
+    })
+  }
+
+  pub(crate) fn root(&self) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[ROOT_PREFIX]);
+    hasher.update(&self.file_size.to_be_bytes());
+    hasher.update(&self.chunk_size.to_be_bytes());
+    hasher.update(&merkle_root(&self.leaves));
+    *hasher.finalize().as_bytes()
+  }
+
+  pub(crate) fn chunk_count(&self) -> u32 {
+    self.leaves.len() as u32
+  }
+
+  /// Byte offset and length of chunk `index`.
+  pub(crate) fn chunk_range(&self, index: u32) -> (u64, usize) {
+    let offset = u64::from(index) * u64::from(self.chunk_size);
+    let len = self
+      .file_size
+      .saturating_sub(offset)
+      .min(u64::from(self.chunk_size));
+    (offset, len as usize)
+  }
+
+  pub(crate) fn verify_chunk(&self, index: u32, data: &[u8]) -> bool {
+    let Some(expected) = self.leaves.get(index as usize) else {
+      return false;
+    };
+    data.len() == self.chunk_range(index).1 && leaf_hash(data) == *expected
+  }
+
+  /// Checks that the manifest is well formed and hashes to `root`.
+  pub(crate) fn validate(&self, root: &Hash) -> anyhow::Result<()> {
+    if self.chunk_size as usize != CHUNK_SIZE {
+      anyhow::bail!("unsupported chunk size {}", self.chunk_size);
+    }
+    if self.leaves.len() > MAX_CHUNKS {
+      anyhow::bail!(
+        "manifest lists {} chunks; max supported is {MAX_CHUNKS}",
+        self.leaves.len()
+      );
+    }
+    let expected_chunks = self.file_size.div_ceil(u64::from(self.chunk_size)).max(1);
+    if self.leaves.len() as u64 != expected_chunks {
+      anyhow::bail!(
+        "manifest lists {} chunks for {} bytes; expected {expected_chunks}",
+        self.leaves.len(),
+        self.file_size
+      );
+    }
+    if self.file_name.is_empty() || self.file_name.contains(['/', '\\']) {
+      anyhow::bail!("manifest has an invalid file name");
+    }
+    if self.root() != *root {
+      anyhow::bail!("manifest does not hash to the requested root");
+    }
+    Ok(())
+  }
+}
+
+/// Encodes the leaf hashes as one byte string instead of a list of arrays,
+/// which keeps a manifest at 32 bytes per chunk on the wire.
+mod packed_hashes {
+  use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+  use super::Hash;
+
+  pub(super) fn serialize<S: Serializer>(
+    hashes: &[Hash],
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serde_bytes::serialize(hashes.as_flattened(), serializer)
+  }
+
+  pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Vec<Hash>, D::Error> {
+    let bytes: serde_bytes::ByteBuf = Deserialize::deserialize(deserializer)?;
+    if !bytes.len().is_multiple_of(32) {
+      return Err(D::Error::custom("leaf hashes must be 32 bytes each"));
+    }
+    Ok(
+      bytes
+        .chunks_exact(32)
+        .map(|hash| hash.try_into().expect("chunks_exact yields 32 bytes"))
+        .collect(),
+    )
+  }
+}
+
+fn leaf_hash(data: &[u8]) -> Hash {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update(&[LEAF_PREFIX]);
+  hasher.update(data);
+  *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+  let mut hasher = blake3::Hasher::new();
+  hasher.update(&[NODE_PREFIX]);
+  hasher.update(left);
+  hasher.update(right);
+  *hasher.finalize().as_bytes()
+}
+
+/// Binary Merkle tree over `leaves`; an odd node at the end of a level is
+/// carried up unchanged.
+fn merkle_root(leaves: &[Hash]) -> Hash {
+  let mut level = leaves.to_vec();
+  while level.len() > 1 {
+    level = level
+      .chunks(2)
+      .map(|pair| match pair {
+        [left, right] => node_hash(left, right),
+        [single] => *single,
+        _ => unreachable!("chunks(2) yields one or two hashes"),
+      })
+      .collect();
+  }
+  level.first().copied().unwrap_or_default()
+}
+
+async fn read_full(file: &mut tokio::fs::File, buffer: &mut [u8]) -> std::io::Result<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    let read = file.read(&mut buffer[filled ..]).await?;
+    if read == 0 {
+      break;
+    }
+    filled += read;
+  }
+  Ok(filled)
+}
+
+pub(crate) fn parse_root(hex: &str) -> anyhow::Result<Hash> {
+  Ok(*blake3::Hash::from_hex(hex.trim())?.as_bytes())
+}
+
+/// One bit per chunk, set once the chunk is verified and on disk. The byte
+/// layout matches the partial-message bitmap in `file_protocol`.
+#[derive(Clone, Debug)]
+pub(crate) struct ChunkBitmap {
+  bits: Vec<u8>,
+  len: u32,
+}
+
+impl ChunkBitmap {
+  pub(crate) fn new(len: u32) -> Self {
+    Self {
+      bits: vec![0; (len as usize).div_ceil(8)],
+      len,
+    }
+  }
+
+  pub(crate) fn from_bytes(len: u32, bits: Vec<u8>) -> anyhow::Result<Self> {
+    if bits.len() != (len as usize).div_ceil(8) {
+      anyhow::bail!("bitmap has {} bytes for {len} chunks", bits.len());
+    }
+    Ok(Self { bits, len })
+  }
+
+  pub(crate) fn as_bytes(&self) -> &[u8] {
+    &self.bits
+  }
+
+  pub(crate) fn has(&self, index: u32) -> bool {
+    index < self.len && self.bits[index as usize / 8] & (1 << (index % 8)) != 0
+  }
+
+  pub(crate) fn set(&mut self, index: u32) {
+    if index < self.len {
+      self.bits[index as usize / 8] |= 1 << (index % 8);
+    }
+  }
+
+  pub(crate) fn count(&self) -> u32 {
+    (0 .. self.len).filter(|index| self.has(*index)).count() as u32
+  }
+
+  pub(crate) fn is_complete(&self) -> bool {
+    self.count() == self.len
+  }
+
+  pub(crate) fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+    (0 .. self.len).filter(|index| !self.has(*index))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Two full chunks and a short last one.
+  fn sample() -> Vec<u8> {
+    (0 .. CHUNK_SIZE * 2 + 100)
+      .map(|i| (i % 251) as u8)
+      .collect()
+  }
+
+  async fn manifest_of(data: &[u8]) -> (tempfile::TempDir, Manifest) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sample.bin");
+    tokio::fs::write(&path, data).await.unwrap();
+    let manifest = Manifest::from_path(&path).await.unwrap();
+    (dir, manifest)
+  }
+
+  #[tokio::test]
+  async fn manifest_covers_every_chunk() {
+    let data = sample();
+    let (_dir, manifest) = manifest_of(&data).await;
+    assert_eq!(manifest.file_name, "sample.bin");
+    assert_eq!(manifest.chunk_count(), 3);
+    assert_eq!(manifest.chunk_range(2), (2 * CHUNK_SIZE as u64, 100));
+    manifest.validate(&manifest.root()).unwrap();
+
+    let (_dir, empty) = manifest_of(&[]).await;
+    assert_eq!(empty.chunk_count(), 1);
+    assert!(empty.verify_chunk(0, &[]));
+  }
+
+  #[tokio::test]
+  async fn tampered_chunks_are_rejected() {
+    let data = sample();
+    let (_dir, manifest) = manifest_of(&data).await;
+    let first = &data[.. CHUNK_SIZE];
+    assert!(manifest.verify_chunk(0, first));
+
+    let mut tampered = first.to_vec();
+    tampered[17] ^= 1;
+    assert!(!manifest.verify_chunk(0, &tampered));
+    // Right bytes, wrong slot, and a chunk cut short
+    assert!(!manifest.verify_chunk(1, first));
+    assert!(!manifest.verify_chunk(0, &first[.. CHUNK_SIZE - 1]));
+    assert!(!manifest.verify_chunk(3, first));
+  }
+
+  #[tokio::test]
+  async fn manifests_must_hash_to_the_requested_root() {
+    let (_dir, manifest) = manifest_of(&sample()).await;
+    let root = manifest.root();
+    let (_dir, other) = manifest_of(b"something else").await;
+    assert!(other.validate(&root).is_err());
+
+    let mut swapped = manifest.clone();
+    swapped.leaves.swap(0, 1);
+    assert!(swapped.validate(&root).is_err());
+
+    let mut resized = manifest.clone();
+    resized.file_size -= 1;
+    assert!(resized.validate(&root).is_err());
+
+    let mut truncated = manifest;
+    truncated.leaves.pop();
+    assert!(truncated.validate(&root).is_err());
+  }
+
+  #[test]
+  fn bitmap_round_trips_through_bytes() {
+    let mut bitmap = ChunkBitmap::new(10);
+    for index in [0, 3, 9, 10] {
+      bitmap.set(index);
+    }
+    assert_eq!(bitmap.count(), 3);
+    assert!(!bitmap.has(10));
+
+    let restored = ChunkBitmap::from_bytes(10, bitmap.as_bytes().to_vec()).unwrap();
+    assert_eq!(
+      restored.missing().collect::<Vec<_>>(),
+      [1, 2, 4, 5, 6, 7, 8]
+    );
+    assert!(ChunkBitmap::from_bytes(17, bitmap.as_bytes().to_vec()).is_err());
+
+    let mut restored = restored;
+    assert!(!restored.is_complete());
+    for index in 0 .. 10 {
+      restored.set(index);
+    }
+    assert!(restored.is_complete());
+  }
+}
@@ -13,13 +13,19 @@ use libp2p::{
   noise,
   ping::{self, Config},
   relay,
-  request_response::{self, json},
+  request_response::{self, cbor, json},
   swarm::{NetworkBehaviour, Swarm, behaviour::toggle::Toggle},
   tcp, yamux,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::{
+  merkle::MAX_MANIFEST_BYTES,
+  sync_protocol::{SyncRequest, SyncResponse},
+};
+
 pub(crate) const FILE_TOPIC: &str = "file-transfer";
+const FILE_SYNC_PROTOCOL: &str = "/awesome-chat/file-sync/1";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub(crate) struct MessageRequest {
@@ -43,6 +49,7 @@ pub(crate) struct ChatBehavior {
   pub(crate) relay_client: relay::client::Behaviour,
   pub(crate) dcutr: dcutr::Behaviour,
   pub(crate) gossipsub: gossipsub::Behaviour,
+  pub(crate) file_sync: cbor::Behaviour<SyncRequest, SyncResponse>,
 }
 
 pub(crate) fn build_swarm(mdns_enabled: bool) -> anyhow::Result<Swarm<ChatBehavior>> {
@@ -114,6 +121,16 @@ pub(crate) fn build_swarm(mdns_enabled: bool) -> anyhow::Result<Swarm<ChatBehavi
           MessageAuthenticity::Signed(key_pair.clone()),
           gossipsub_config,
         )?,
+        // The default codec caps responses at 10 MiB, less than the manifest
+        // of a large file.
+        file_sync: request_response::Behaviour::with_codec(
+          cbor::codec::Codec::default().set_response_size_maximum(MAX_MANIFEST_BYTES),
+          [(
+            StreamProtocol::new(FILE_SYNC_PROTOCOL),
+            request_response::ProtocolSupport::Full,
+          )],
+          request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+        ),
       })
     })?
     .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(30)))
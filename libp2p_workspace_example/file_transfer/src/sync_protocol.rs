@@ -0,0 +1,806 @@
+use std::{
+  collections::HashMap,
+  io::{ErrorKind, SeekFrom},
+  path::{Path, PathBuf},
+};
+
+use libp2p::{
+  PeerId, Swarm, kad,
+  request_response::{self, OutboundRequestId},
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::{
+  file_protocol::hex_id,
+  merkle::{ChunkBitmap, Hash, Manifest, parse_root},
+  network::ChatBehavior,
+};
+
+/// Chunk requests kept in flight to one provider at a time.
+const MAX_IN_FLIGHT_PER_PEER: usize = 4;
+const SYNC_DIR: &str = "sync";
+const RECEIVED_DIR: &str = "received";
+const MANIFEST_FILE: &str = "manifest.json";
+const BITMAP_FILE: &str = "bitmap";
+const DATA_FILE: &str = "data";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum SyncRequest {
+  Manifest { root: Hash },
+  Chunk { root: Hash, index: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum SyncResponse {
+  Manifest(Manifest),
+  Chunk {
+    index: u32,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+  },
+  NotFound,
+}
+
+/// A file this node serves in full.
+struct Seed {
+  manifest: Manifest,
+  path: PathBuf,
+}
+
+/// A file being fetched by root hash. Everything needed to resume lives in
+/// `sync/<root>/`: the manifest once a provider sent it, the chunks written so
+/// far and a bitmap of which of them are verified.
+struct Download {
+  root: Hash,
+  dir: PathBuf,
+  manifest: Option<Manifest>,
+  bitmap: Option<ChunkBitmap>,
+  providers: Vec<PeerId>,
+  manifest_request: Option<PeerId>,
+  in_flight: HashMap<u32, PeerId>,
+}
+
+/// What a `sync/<root>/` directory left by an earlier run turns into.
+enum Resumed {
+  Download(Box<Download>),
+  Seed(Seed),
+}
+
+impl Download {
+  fn new(root: Hash) -> Self {
+    Self::in_dir(root, Path::new(SYNC_DIR).join(hex_id(&root)))
+  }
+
+  fn in_dir(root: Hash, dir: PathBuf) -> Self {
+    Self {
+      root,
+      dir,
+      manifest: None,
+      bitmap: None,
+      providers: Vec::new(),
+      manifest_request: None,
+      in_flight: HashMap::new(),
+    }
+  }
+
+  fn add_provider(&mut self, peer: PeerId) -> bool {
+    if self.providers.contains(&peer) {
+      return false;
+    }
+    self.providers.push(peer);
+    true
+  }
+
+  /// Forgets `peer` as a provider, e.g. after it sent a bad chunk, and frees
+  /// everything it still had in flight.
+  fn drop_provider(&mut self, peer: &PeerId) {
+    self.providers.retain(|provider| provider != peer);
+    self.in_flight.retain(|_, provider| provider != peer);
+    if self.manifest_request.as_ref() == Some(peer) {
+      self.manifest_request = None;
+    }
+  }
+
+  /// Requests to send next: the manifest from one provider, then missing
+  /// chunks spread over every provider, least busy first.
+  fn schedule(&mut self) -> Vec<(PeerId, SyncRequest)> {
+    let root = self.root;
+    let Some(bitmap) = &self.bitmap else {
+      if self.manifest_request.is_some() {
+        return Vec::new();
+      }
+      let Some(peer) = self.providers.first().copied() else {
+        return Vec::new();
+      };
+      self.manifest_request = Some(peer);
+      return vec![(peer, SyncRequest::Manifest { root })];
+    };
+
+    let mut load = self
+      .providers
+      .iter()
+      .map(|peer| (*peer, 0))
+      .collect::<HashMap<_, _>>();
+    for peer in self.in_flight.values() {
+      if let Some(count) = load.get_mut(peer) {
+        *count += 1;
+      }
+    }
+
+    let mut requests = Vec::new();
+    for index in bitmap.missing() {
+      if self.in_flight.contains_key(&index) {
+        continue;
+      }
+      let Some((peer, count)) = load
+        .iter_mut()
+        .filter(|(_, count)| **count < MAX_IN_FLIGHT_PER_PEER)
+        .min_by_key(|(_, count)| **count)
+      else {
+        break;
+      };
+      *count += 1;
+      self.in_flight.insert(index, *peer);
+      requests.push((*peer, SyncRequest::Chunk { root, index }));
+    }
+    requests
+  }
+
+  /// Reads back what `accept_manifest` and `accept_chunk` left in `dir`. A
+  /// manifest without a bitmap is either a finished download, if `received`
+  /// exists, or a crash right after the manifest was written, in which case
+  /// the fetch starts over.
+  async fn resume(root: Hash, dir: PathBuf, received: PathBuf) -> anyhow::Result<Resumed> {
+    let mut download = Self::in_dir(root, dir);
+    let Some(bytes) = read_if_exists(&download.dir.join(MANIFEST_FILE)).await? else {
+      return Ok(Resumed::Download(Box::new(download)));
+    };
+    let manifest = serde_json::from_slice::<Manifest>(&bytes)?;
+    manifest.validate(&root)?;
+
+    match read_if_exists(&download.dir.join(BITMAP_FILE)).await? {
+      Some(bits) => {
+        download.bitmap = Some(ChunkBitmap::from_bytes(manifest.chunk_count(), bits)?);
+        download.manifest = Some(manifest);
+        Ok(Resumed::Download(Box::new(download)))
+      }
+      None if tokio::fs::try_exists(&received).await? => Ok(Resumed::Seed(Seed {
+        manifest,
+        path: received,
+      })),
+      None => Ok(Resumed::Download(Box::new(download))),
+    }
+  }
+
+  fn progress(&self) -> String {
+    match (&self.manifest, &self.bitmap) {
+      (Some(manifest), Some(bitmap)) => format!(
+        "{} chunks={}/{} providers={}",
+        manifest.file_name,
+        bitmap.count(),
+        manifest.chunk_count(),
+        self.providers.len()
+      ),
+      _ => format!("waiting for manifest, providers={}", self.providers.len()),
+    }
+  }
+
+  async fn accept_manifest(&mut self, manifest: Manifest) -> anyhow::Result<()> {
+    manifest.validate(&self.root)?;
+    tokio::fs::create_dir_all(&self.dir).await?;
+    write_atomically(
+      &self.dir.join(MANIFEST_FILE),
+      &serde_json::to_vec(&manifest)?,
+    )
+    .await?;
+    let data = tokio::fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(self.dir.join(DATA_FILE))
+      .await?;
+    data.set_len(manifest.file_size).await?;
+
+    let bitmap = ChunkBitmap::new(manifest.chunk_count());
+    write_atomically(&self.dir.join(BITMAP_FILE), bitmap.as_bytes()).await?;
+    self.bitmap = Some(bitmap);
+    self.manifest = Some(manifest);
+    Ok(())
+  }
+
+  /// Verifies and stores one chunk. Returns `false` if the chunk does not
+  /// match the manifest.
+  async fn accept_chunk(&mut self, index: u32, data: &[u8]) -> anyhow::Result<bool> {
+    let (Some(manifest), Some(bitmap)) = (&self.manifest, &mut self.bitmap) else {
+      anyhow::bail!("chunk arrived before the manifest");
+    };
+    if !manifest.verify_chunk(index, data) {
+      return Ok(false);
+    }
+    if bitmap.has(index) {
+      return Ok(true);
+    }
+
+    let (offset, _) = manifest.chunk_range(index);
+    let mut file = tokio::fs::OpenOptions::new()
+      .write(true)
+      .open(self.dir.join(DATA_FILE))
+      .await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    file.write_all(data).await?;
+    file.sync_data().await?;
+
+    bitmap.set(index);
+    write_atomically(&self.dir.join(BITMAP_FILE), bitmap.as_bytes()).await?;
+    Ok(true)
+  }
+
+  fn is_complete(&self) -> bool {
+    self.bitmap.as_ref().is_some_and(ChunkBitmap::is_complete)
+  }
+
+  async fn read_chunk(&self, index: u32) -> Option<Vec<u8>> {
+    let (manifest, bitmap) = (self.manifest.as_ref()?, self.bitmap.as_ref()?);
+    if !bitmap.has(index) {
+      return None;
+    }
+    read_chunk(&self.dir.join(DATA_FILE), manifest, index)
+      .await
+      .ok()
+  }
+}
+
+struct PendingRequest {
+  root: Hash,
+  peer: PeerId,
+  chunk: Option<u32>,
+}
+
+/// Request/response file sync addressed by Merkle root.
+///
+/// Unlike the gossipsub partial mode, files are not pushed to everyone:
+/// `/share` announces the root as a Kademlia provider record, and `/fetch`
+/// looks up the providers and pulls verified chunks from all of them in
+/// parallel.
+#[derive(Default)]
+pub(crate) struct FileSync {
+  seeds: HashMap<Hash, Seed>,
+  downloads: HashMap<Hash, Download>,
+  provider_queries: HashMap<kad::QueryId, Hash>,
+  requests: HashMap<OutboundRequestId, PendingRequest>,
+}
+
+impl FileSync {
+  /// Picks up where a previous run stopped: completed downloads are served
+  /// again and unfinished ones continue once providers are found. Entries
+  /// that cannot be read are reported and skipped.
+  pub(crate) async fn resume(&mut self, swarm: &mut Swarm<ChatBehavior>) -> anyhow::Result<()> {
+    let mut entries = match tokio::fs::read_dir(SYNC_DIR).await {
+      Ok(entries) => entries,
+      Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+      Err(error) => return Err(error.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+      let Some(root) = entry
+        .file_name()
+        .to_str()
+        .and_then(|name| parse_root(name).ok())
+      else {
+        continue;
+      };
+      match Download::resume(root, entry.path(), received_path(&root)).await {
+        Ok(Resumed::Download(download)) => {
+          println!("Resuming fetch {}: {}", hex_id(&root), download.progress());
+          self.downloads.insert(root, *download);
+          self.find_providers(swarm, root);
+        }
+        Ok(Resumed::Seed(seed)) => {
+          println!(
+            "Seeding {} from {}",
+            seed.manifest.file_name,
+            seed.path.display()
+          );
+          self.seeds.insert(root, seed);
+          self.provide(swarm, root);
+        }
+        Err(error) => println!("Skipping {}: {error:#}", entry.path().display()),
+      }
+    }
+    Ok(())
+  }
+
+  /// Hashes the file at `path` and announces this node as its provider.
+  pub(crate) async fn share(
+    &mut self,
+    swarm: &mut Swarm<ChatBehavior>,
+    path: PathBuf,
+  ) -> anyhow::Result<Hash> {
+    let manifest = Manifest::from_path(&path).await?;
+    let root = manifest.root();
+    println!(
+      "Sharing {} ({} bytes, {} chunks) as {}",
+      manifest.file_name,
+      manifest.file_size,
+      manifest.chunk_count(),
+      hex_id(&root)
+    );
+    self.seeds.insert(root, Seed { manifest, path });
+    self.provide(swarm, root);
+    Ok(root)
+  }
+
+  pub(crate) async fn fetch(
+    &mut self,
+    swarm: &mut Swarm<ChatBehavior>,
+    root: Hash,
+  ) -> anyhow::Result<()> {
+    if self.seeds.contains_key(&root) {
+      println!("Already have {}", hex_id(&root));
+      return Ok(());
+    }
+    let download = self
+      .downloads
+      .entry(root)
+      .or_insert_with(|| Download::new(root));
+    // The directory alone is enough for a restart to resume the fetch.
+    tokio::fs::create_dir_all(&download.dir).await?;
+    println!("Fetching {}: {}", hex_id(&root), download.progress());
+    self.find_providers(swarm, root);
+    Ok(())
+  }
+
+  pub(crate) fn print_status(&self) {
+    for (root, seed) in &self.seeds {
+      println!("  seeding {} {}", hex_id(root), seed.manifest.file_name);
+    }
+    for (root, download) in &self.downloads {
+      println!("  fetching {} {}", hex_id(root), download.progress());
+    }
+  }
+
+  /// Retries provider lookups that found nobody, e.g. because the routing
+  /// table was still empty.
+  pub(crate) fn on_connected(&mut self, swarm: &mut Swarm<ChatBehavior>) {
+    let idle = self
+      .downloads
+      .values()
+      .filter(|download| download.providers.is_empty())
+      .map(|download| download.root)
+      .filter(|root| !self.provider_queries.values().any(|query| query == root))
+      .collect::<Vec<_>>();
+    for root in idle {
+      self.find_providers(swarm, root);
+    }
+  }
+
+  /// Feeds a step of one of our provider lookups; other queries are ignored.
+  pub(crate) fn on_query_progressed(
+    &mut self,
+    swarm: &mut Swarm<ChatBehavior>,
+    id: kad::QueryId,
+    result: &kad::QueryResult,
+    last: bool,
+  ) {
+    let Some(root) = self.provider_queries.get(&id).copied() else {
+      return;
+    };
+    if last {
+      self.provider_queries.remove(&id);
+    }
+
+    let local_peer_id = *swarm.local_peer_id();
+    let Some(download) = self.downloads.get_mut(&root) else {
+      return;
+    };
+    match result {
+      kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+        providers, ..
+      })) => {
+        for peer in providers.iter().filter(|peer| **peer != local_peer_id) {
+          if download.add_provider(*peer) {
+            println!("Found provider {peer} for {}", hex_id(&root));
+          }
+        }
+      }
+      kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FinishedWithNoAdditionalRecord {
+        ..
+      })) => {}
+      kad::QueryResult::GetProviders(Err(error)) => {
+        println!("Provider lookup for {} failed: {error}", hex_id(&root));
+      }
+      _ => {}
+    }
+    if last && download.providers.is_empty() {
+      println!(
+        "No providers for {} yet; will retry on the next connection",
+        hex_id(&root)
+      );
+    }
+    self.pump(swarm);
+  }
+
+  pub(crate) async fn on_event(
+    &mut self,
+    swarm: &mut Swarm<ChatBehavior>,
+    event: request_response::Event<SyncRequest, SyncResponse>,
+  ) {
+    match event {
+      request_response::Event::Message { peer, message, .. } => match message {
+        request_response::Message::Request {
+          request, channel, ..
+        } => {
+          let response = self.respond(request).await;
+          if swarm
+            .behaviour_mut()
+            .file_sync
+            .send_response(channel, response)
+            .is_err()
+          {
+            println!("Failed to answer sync request from {peer}: channel closed");
+          }
+        }
+        request_response::Message::Response {
+          request_id,
+          response,
+        } => {
+          if let Some(pending) = self.requests.remove(&request_id) {
+            self.on_response(swarm, pending, response).await;
+          }
+        }
+      },
+      request_response::Event::OutboundFailure {
+        peer,
+        request_id,
+        error,
+        ..
+      } => {
+        if let Some(pending) = self.requests.remove(&request_id) {
+          println!(
+            "Sync request to {peer} for {} failed: {error}",
+            hex_id(&pending.root)
+          );
+          if let Some(download) = self.downloads.get_mut(&pending.root) {
+            download.drop_provider(&peer);
+          }
+          self.pump(swarm);
+        }
+      }
+      request_response::Event::InboundFailure { peer, error, .. } => {
+        println!("Inbound sync failure from {peer}: {error}");
+      }
+      request_response::Event::ResponseSent { .. } => {}
+    }
+  }
+
+  async fn respond(&self, request: SyncRequest) -> SyncResponse {
+    match request {
+      SyncRequest::Manifest { root } => self
+        .seeds
+        .get(&root)
+        .map(|seed| &seed.manifest)
+        .or_else(|| self.downloads.get(&root)?.manifest.as_ref())
+        .map(|manifest| SyncResponse::Manifest(manifest.clone()))
+        .unwrap_or(SyncResponse::NotFound),
+      SyncRequest::Chunk { root, index } => {
+        let data = if let Some(seed) = self.seeds.get(&root) {
+          read_chunk(&seed.path, &seed.manifest, index).await.ok()
+        } else if let Some(download) = self.downloads.get(&root) {
+          download.read_chunk(index).await
+        } else {
+          None
+        };
+        match data {
+          Some(data) => SyncResponse::Chunk { index, data },
+          None => SyncResponse::NotFound,
+        }
+      }
+    }
+  }
+
+  async fn on_response(
+    &mut self,
+    swarm: &mut Swarm<ChatBehavior>,
+    pending: PendingRequest,
+    response: SyncResponse,
+  ) {
+    let PendingRequest { root, peer, chunk } = pending;
+    let Some(download) = self.downloads.get_mut(&root) else {
+      return;
+    };
+    if let Some(index) = chunk {
+      download.in_flight.remove(&index);
+    } else {
+      download.manifest_request = None;
+    }
+
+    match response {
+      SyncResponse::Manifest(manifest) if download.manifest.is_none() => {
+        match download.accept_manifest(manifest).await {
+          Ok(()) => println!("Fetching {}: {}", hex_id(&root), download.progress()),
+          Err(error) => {
+            println!(
+              "Rejected manifest for {} from {peer}: {error}",
+              hex_id(&root)
+            );
+            download.drop_provider(&peer);
+          }
+        }
+      }
+      SyncResponse::Manifest(_) => {}
+      SyncResponse::Chunk { index, data } if chunk == Some(index) => {
+        match download.accept_chunk(index, &data).await {
+          Ok(true) => {
+            if download.is_complete() {
+              self.complete(swarm, root).await;
+            } else if index % 64 == 0 {
+              println!("Fetching {}: {}", hex_id(&root), download.progress());
+            }
+          }
+          Ok(false) => {
+            println!(
+              "Chunk {index} of {} from {peer} failed verification",
+              hex_id(&root)
+            );
+            download.drop_provider(&peer);
+          }
+          Err(error) => println!(
+            "Failed to store chunk {index} of {}: {error}",
+            hex_id(&root)
+          ),
+        }
+      }
+      SyncResponse::Chunk { .. } | SyncResponse::NotFound => {
+        println!("{peer} cannot serve {}", hex_id(&root));
+        download.drop_provider(&peer);
+      }
+    }
+    self.pump(swarm);
+  }
+
+  /// Moves a finished download to `received/<root>` and starts serving it.
+  async fn complete(&mut self, swarm: &mut Swarm<ChatBehavior>, root: Hash) {
+    let Some(download) = self.downloads.remove(&root) else {
+      return;
+    };
+    let manifest = download
+      .manifest
+      .expect("a complete download has a manifest");
+    let path = received_path(&root);
+    let result = async {
+      tokio::fs::create_dir_all(RECEIVED_DIR).await?;
+      tokio::fs::rename(download.dir.join(DATA_FILE), &path).await?;
+      tokio::fs::remove_file(download.dir.join(BITMAP_FILE)).await
+    }
+    .await;
+    if let Err(error) = result {
+      println!("Failed to finish {}: {error}", hex_id(&root));
+      return;
+    }
+
+    println!(
+      "Completed file {} ({} bytes, {} chunks, {} providers) -> {}",
+      manifest.file_name,
+      manifest.file_size,
+      manifest.chunk_count(),
+      download.providers.len(),
+      path.display()
+    );
+    self.seeds.insert(root, Seed { manifest, path });
+    self.provide(swarm, root);
+  }
+
+  fn pump(&mut self, swarm: &mut Swarm<ChatBehavior>) {
+    for download in self.downloads.values_mut() {
+      for (peer, request) in download.schedule() {
+        let chunk = match &request {
+          SyncRequest::Manifest { .. } => None,
+          SyncRequest::Chunk { index, .. } => Some(*index),
+        };
+        let request_id = swarm.behaviour_mut().file_sync.send_request(&peer, request);
+        self.requests.insert(
+          request_id,
+          PendingRequest {
+            root: download.root,
+            peer,
+            chunk,
+          },
+        );
+      }
+    }
+  }
+
+  fn provide(&mut self, swarm: &mut Swarm<ChatBehavior>, root: Hash) {
+    if let Err(error) = swarm
+      .behaviour_mut()
+      .kademlia
+      .start_providing(kad::RecordKey::new(&root))
+    {
+      println!("Failed to announce {}: {error}", hex_id(&root));
+    }
+  }
+
+  fn find_providers(&mut self, swarm: &mut Swarm<ChatBehavior>, root: Hash) {
+    let query_id = swarm
+      .behaviour_mut()
+      .kademlia
+      .get_providers(kad::RecordKey::new(&root));
+    self.provider_queries.insert(query_id, root);
+  }
+}
+
+fn received_path(root: &Hash) -> PathBuf {
+  Path::new(RECEIVED_DIR).join(hex_id(root))
+}
+
+async fn read_chunk(path: &Path, manifest: &Manifest, index: u32) -> std::io::Result<Vec<u8>> {
+  if index >= manifest.chunk_count() {
+    return Err(ErrorKind::InvalidInput.into());
+  }
+  let (offset, len) = manifest.chunk_range(index);
+  let mut file = tokio::fs::File::open(path).await?;
+  file.seek(SeekFrom::Start(offset)).await?;
+  let mut data = vec![0; len];
+  file.read_exact(&mut data).await?;
+  Ok(data)
+}
+
+async fn read_if_exists(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+  match tokio::fs::read(path).await {
+    Ok(bytes) => Ok(Some(bytes)),
+    Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+    Err(error) => Err(error),
+  }
+}
+
+/// Replaces `path` so that a crash leaves either the old or the new
+/// contents, never a torn file.
+async fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+  let tmp = path.with_extension("tmp");
+  tokio::fs::write(&tmp, bytes).await?;
+  tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::merkle::CHUNK_SIZE;
+
+  async fn sample(dir: &Path, chunks: usize) -> (Vec<u8>, Manifest) {
+    let data = (0 .. CHUNK_SIZE * chunks - 10)
+      .map(|i| (i % 253) as u8)
+      .collect::<Vec<_>>();
+    let path = dir.join("sample.bin");
+    tokio::fs::write(&path, &data).await.unwrap();
+    let manifest = Manifest::from_path(&path).await.unwrap();
+    (data, manifest)
+  }
+
+  fn chunk<'a>(data: &'a [u8], manifest: &Manifest, index: u32) -> &'a [u8] {
+    let (offset, len) = manifest.chunk_range(index);
+    &data[offset as usize ..][.. len]
+  }
+
+  async fn resume(download: &Download, received: &Path) -> anyhow::Result<Resumed> {
+    Download::resume(download.root, download.dir.clone(), received.to_path_buf()).await
+  }
+
+  #[tokio::test]
+  async fn verified_chunks_survive_a_restart() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (data, manifest) = sample(tmp.path(), 3).await;
+    let root = manifest.root();
+    let mut download = Download::in_dir(root, tmp.path().join("sync"));
+    download.accept_manifest(manifest.clone()).await.unwrap();
+
+    assert!(
+      download
+        .accept_chunk(0, chunk(&data, &manifest, 0))
+        .await
+        .unwrap()
+    );
+    let mut tampered = chunk(&data, &manifest, 1).to_vec();
+    tampered[0] ^= 0xff;
+    assert!(!download.accept_chunk(1, &tampered).await.unwrap());
+
+    let Resumed::Download(resumed) = resume(&download, &tmp.path().join("received"))
+      .await
+      .unwrap()
+    else {
+      panic!("an unfinished fetch resumes as a download");
+    };
+    let bitmap = resumed.bitmap.as_ref().unwrap();
+    assert_eq!(bitmap.missing().collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(
+      resumed.read_chunk(0).await.as_deref(),
+      Some(chunk(&data, &manifest, 0))
+    );
+    assert_eq!(resumed.read_chunk(1).await, None);
+
+    let mut resumed = *resumed;
+    for index in 1 .. 3 {
+      assert!(
+        resumed
+          .accept_chunk(index, chunk(&data, &manifest, index))
+          .await
+          .unwrap()
+      );
+    }
+    assert!(resumed.is_complete());
+  }
+
+  #[tokio::test]
+  async fn manifest_without_bitmap_restarts_unless_received() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (_, manifest) = sample(tmp.path(), 2).await;
+    let download = Download::in_dir(manifest.root(), tmp.path().join("sync"));
+    tokio::fs::create_dir_all(&download.dir).await.unwrap();
+    // What a crash between the manifest and the bitmap write leaves behind
+    write_atomically(
+      &download.dir.join(MANIFEST_FILE),
+      &serde_json::to_vec(&manifest).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let received = tmp.path().join("received");
+    match resume(&download, &received).await.unwrap() {
+      Resumed::Download(fresh) => assert!(fresh.manifest.is_none() && fresh.bitmap.is_none()),
+      Resumed::Seed(_) => panic!("nothing was received yet"),
+    }
+
+    tokio::fs::write(&received, b"finished").await.unwrap();
+    match resume(&download, &received).await.unwrap() {
+      Resumed::Seed(seed) => assert_eq!(seed.path, received),
+      Resumed::Download(_) => panic!("a received file is seeded"),
+    }
+
+    tokio::fs::write(download.dir.join(MANIFEST_FILE), b"{\"file_na")
+      .await
+      .unwrap();
+    assert!(resume(&download, &received).await.is_err());
+  }
+
+  #[test]
+  fn schedule_spreads_chunks_within_the_per_peer_limit() {
+    let mut download = Download::in_dir([7; 32], PathBuf::from("unused"));
+    let providers = [PeerId::random(), PeerId::random(), PeerId::random()];
+    for peer in providers {
+      download.add_provider(peer);
+    }
+
+    // Without a manifest only the first provider is asked, once
+    assert!(matches!(
+      download.schedule().as_slice(),
+      [(peer, SyncRequest::Manifest { .. })] if *peer == providers[0]
+    ));
+    assert!(download.schedule().is_empty());
+
+    download.bitmap = Some(ChunkBitmap::new(20));
+    let requests = download.schedule();
+    assert_eq!(requests.len(), providers.len() * MAX_IN_FLIGHT_PER_PEER);
+    for peer in providers {
+      let load = requests.iter().filter(|(to, _)| *to == peer).count();
+      assert_eq!(load, MAX_IN_FLIGHT_PER_PEER);
+    }
+    assert!(download.schedule().is_empty());
+
+    // A provider that goes away frees its slots for the others
+    download.drop_provider(&providers[0]);
+    assert!(download.schedule().is_empty());
+    let finished = download
+      .in_flight
+      .iter()
+      .filter(|(_, peer)| **peer == providers[1])
+      .map(|(index, _)| *index)
+      .collect::<Vec<_>>();
+    for index in &finished {
+      download.in_flight.remove(index);
+      download.bitmap.as_mut().unwrap().set(*index);
+    }
+    let requests = download.schedule();
+    assert_eq!(requests.len(), MAX_IN_FLIGHT_PER_PEER);
+    assert!(requests.iter().all(|(peer, _)| *peer == providers[1]));
+  }
+}
@@ -0,0 +1,822 @@
+//! Blocks and the block tree.
+//!
+//! Every valid block is kept, not only those on the best chain, so a side
+//! chain that overtakes the best chain in cumulative work becomes the best
+//! chain (a reorg) without having to fetch it again.
+
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+};
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::transaction::{parse_address, Ledger, SignedTransaction, TransactionError};
+
+/// Difficulty is the number of leading zero bits a block hash needs, so every
+/// extra bit doubles the expected work.
+pub const GENESIS_DIFFICULTY: u32 = 16;
+pub const MIN_DIFFICULTY: u32 = 8;
+pub const MAX_DIFFICULTY: u32 = 64;
+/// Difficulty is adjusted every `RETARGET_INTERVAL` blocks, by one bit at a
+/// time, towards one block every `TARGET_BLOCK_SECS`.
+pub const RETARGET_INTERVAL: u64 = 10;
+pub const TARGET_BLOCK_SECS: i64 = 10;
+pub const MAX_FUTURE_DRIFT_SECS: i64 = 2 * 60 * 60;
+pub const MAX_TRANSACTIONS_PER_BLOCK: usize = 100;
+pub const MAX_DATA_LEN: usize = 1024;
+const MAX_ORPHANS: usize = 256;
+const LOCATOR_DENSE_ENTRIES: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+  pub height: u64,
+  pub previous_hash: String,
+  pub timestamp: i64,
+  pub difficulty: u32,
+  pub body_hash: String,
+  pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockBody {
+  /// Address credited with the block reward and fees.
+  pub miner: String,
+  pub data: String,
+  pub transactions: Vec<SignedTransaction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+  pub hash: String,
+  pub header: BlockHeader,
+  pub body: BlockBody,
+}
+
+impl BlockHeader {
+  pub fn hash(&self) -> String {
+    hex::encode(self.hash_bytes())
+  }
+
+  fn hash_bytes(&self) -> Vec<u8> {
+    let json = serde_json::to_vec(self).expect("header serializes to json");
+    Sha256::digest(json).to_vec()
+  }
+
+  pub fn meets_difficulty(&self) -> bool {
+    leading_zero_bits(&self.hash_bytes()) >= self.difficulty
+  }
+
+  pub fn work(&self) -> u128 {
+    1 << self.difficulty
+  }
+}
+
+impl BlockBody {
+  pub fn hash(&self) -> String {
+    let json = serde_json::to_vec(self).expect("body serializes to json");
+    hex::encode(Sha256::digest(json))
+  }
+}
+
+impl Block {
+  pub fn genesis() -> Self {
+    let body = BlockBody {
+      miner: String::new(),
+      data: String::from("genesis!"),
+      transactions: vec![],
+    };
+    let header = BlockHeader {
+      height: 0,
+      previous_hash: String::from("genesis"),
+      timestamp: 1_700_000_000,
+      difficulty: GENESIS_DIFFICULTY,
+      body_hash: body.hash(),
+      nonce: 0,
+    };
+    Self {
+      hash: header.hash(),
+      header,
+      body,
+    }
+  }
+
+  /// Searches for a nonce that meets `header.difficulty`. This blocks the
+  /// calling thread until it finds one.
+  pub fn mine(mut header: BlockHeader, body: BlockBody) -> Self {
+    info!(
+      "mining block {} at difficulty {}...",
+      header.height, header.difficulty
+    );
+    while !header.meets_difficulty() {
+      header.nonce += 1;
+      if header.nonce.is_multiple_of(100_000) {
+        info!("nonce: {}", header.nonce);
+      }
+    }
+    let hash = header.hash();
+    info!("mined! nonce: {}, hash: {}", header.nonce, hash);
+    Self { hash, header, body }
+  }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+  let mut bits = 0;
+  for byte in hash {
+    bits += byte.leading_zeros();
+    if *byte != 0 {
+      break;
+    }
+  }
+  bits
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+  HashMismatch,
+  BodyMismatch,
+  InsufficientWork,
+  DifficultyOutOfRange(u32),
+  WrongDifficulty { expected: u32, got: u32 },
+  WrongHeight { expected: u64, got: u64 },
+  TimestampBeforeParent,
+  TimestampInFuture,
+  TooManyTransactions,
+  DataTooLong,
+  BadMiner(String),
+  Transaction(TransactionError),
+  KnownInvalid,
+  UnknownParent,
+  HeadersNotConnected,
+}
+
+impl fmt::Display for ChainError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::HashMismatch => write!(f, "hash does not match the header"),
+      Self::BodyMismatch => write!(f, "body does not match the header"),
+      Self::InsufficientWork => write!(f, "hash does not meet the difficulty"),
+      Self::DifficultyOutOfRange(difficulty) => {
+        write!(f, "difficulty {difficulty} is out of range")
+      }
+      Self::WrongDifficulty { expected, got } => {
+        write!(f, "difficulty is {got} but should be {expected}")
+      }
+      Self::WrongHeight { expected, got } => write!(f, "height is {got} but should be {expected}"),
+      Self::TimestampBeforeParent => write!(f, "timestamp is before the parent block"),
+      Self::TimestampInFuture => write!(f, "timestamp is too far in the future"),
+      Self::TooManyTransactions => write!(f, "too many transactions"),
+      Self::DataTooLong => write!(f, "data is too long"),
+      Self::BadMiner(miner) => write!(f, "miner {miner:?} is not a peer id"),
+      Self::Transaction(err) => write!(f, "invalid transaction: {err}"),
+      Self::KnownInvalid => write!(f, "block or one of its ancestors is invalid"),
+      Self::UnknownParent => write!(f, "parent block is unknown"),
+      Self::HeadersNotConnected => write!(f, "headers do not form a chain"),
+    }
+  }
+}
+
+impl std::error::Error for ChainError {}
+
+/// How the best chain moved. Blocks are in chain order.
+#[derive(Debug, Default)]
+pub struct TipChange {
+  pub disconnected: Vec<Block>,
+  pub connected: Vec<Block>,
+}
+
+impl TipChange {
+  pub fn is_reorg(&self) -> bool {
+    !self.disconnected.is_empty()
+  }
+}
+
+#[derive(Debug)]
+pub enum Inserted {
+  Known,
+  /// The parent is missing; the block is held until it arrives.
+  Orphan,
+  /// `blocks` were added to the tree: the inserted block plus any orphans
+  /// that were waiting for it.
+  Added {
+    blocks: Vec<Block>,
+    tip_change: Option<TipChange>,
+  },
+}
+
+struct Entry {
+  block: Block,
+  total_work: u128,
+}
+
+pub struct BlockTree {
+  entries: HashMap<String, Entry>,
+  /// Hashes of the best chain, indexed by height.
+  best_chain: Vec<String>,
+  /// Account state at the tip of the best chain.
+  ledger: Ledger,
+  invalid: HashSet<String>,
+  /// Blocks whose parent has not arrived yet, by parent hash.
+  orphans: HashMap<String, Vec<Block>>,
+}
+
+impl BlockTree {
+  pub fn new() -> Self {
+    Self::with_genesis(Block::genesis())
+  }
+
+  fn with_genesis(genesis: Block) -> Self {
+    let hash = genesis.hash.clone();
+    let entry = Entry {
+      total_work: genesis.header.work(),
+      block: genesis,
+    };
+    Self {
+      entries: HashMap::from([(hash.clone(), entry)]),
+      best_chain: vec![hash],
+      ledger: Ledger::default(),
+      invalid: HashSet::new(),
+      orphans: HashMap::new(),
+    }
+  }
+
+  pub fn tip(&self) -> &Block {
+    self
+      .block(self.best_chain.last().expect("best chain has genesis"))
+      .expect("tip is in the tree")
+  }
+
+  pub fn best_work(&self) -> u128 {
+    self.entries[&self.tip().hash].total_work
+  }
+
+  pub fn ledger(&self) -> &Ledger {
+    &self.ledger
+  }
+
+  pub fn block(&self, hash: &str) -> Option<&Block> {
+    self.entries.get(hash).map(|entry| &entry.block)
+  }
+
+  pub fn contains(&self, hash: &str) -> bool {
+    self.entries.contains_key(hash)
+      || self.invalid.contains(hash)
+      || self
+        .orphans
+        .values()
+        .flatten()
+        .any(|block| block.hash == hash)
+  }
+
+  pub fn best_chain(&self) -> impl Iterator<Item = &Block> {
+    self.best_chain.iter().map(|hash| &self.entries[hash].block)
+  }
+
+  /// Blocks without children, with their cumulative work, best first.
+  pub fn tips(&self) -> Vec<(&Block, u128)> {
+    let parents = self
+      .entries
+      .values()
+      .map(|entry| entry.block.header.previous_hash.as_str())
+      .collect::<HashSet<_>>();
+    let mut tips = self
+      .entries
+      .values()
+      .filter(|entry| !parents.contains(entry.block.hash.as_str()))
+      .map(|entry| (&entry.block, entry.total_work))
+      .collect::<Vec<_>>();
+    tips.sort_by_key(|(_, work)| std::cmp::Reverse(*work));
+    tips
+  }
+
+  /// The header of the next block on top of the best chain.
+  pub fn next_header(&self, body: &BlockBody) -> BlockHeader {
+    let tip = self.tip();
+    BlockHeader {
+      height: tip.header.height + 1,
+      previous_hash: tip.hash.clone(),
+      timestamp: Utc::now().timestamp().max(tip.header.timestamp),
+      difficulty: self.next_difficulty(&tip.hash),
+      body_hash: body.hash(),
+      nonce: 0,
+    }
+  }
+
+  /// Difficulty of a child of `parent_hash`. Every `RETARGET_INTERVAL`
+  /// blocks it goes up a bit if the last blocks came in faster than half the
+  /// target time and down a bit if they took more than twice as long.
+  pub fn next_difficulty(&self, parent_hash: &str) -> u32 {
+    let parent = &self.entries[parent_hash].block.header;
+    if !(parent.height + 1).is_multiple_of(RETARGET_INTERVAL) {
+      return parent.difficulty;
+    }
+
+    // The genesis timestamp is fixed, so the window starts at height 1.
+    let mut first = parent;
+    let mut steps = 0;
+    while steps < RETARGET_INTERVAL && first.height > 1 {
+      first = &self.entries[&first.previous_hash].block.header;
+      steps += 1;
+    }
+    if steps == 0 {
+      return parent.difficulty;
+    }
+
+    let actual = parent.timestamp - first.timestamp;
+    let expected = steps as i64 * TARGET_BLOCK_SECS;
+    let difficulty = if actual < expected / 2 {
+      parent.difficulty + 1
+    } else if actual > expected * 2 {
+      parent.difficulty - 1
+    } else {
+      parent.difficulty
+    };
+    difficulty.clamp(MIN_DIFFICULTY, MAX_DIFFICULTY)
+  }
+
+  /// Adds `block` to the tree and moves the best chain to the tip with the
+  /// most cumulative work.
+  pub fn insert(&mut self, block: Block) -> Result<Inserted, ChainError> {
+    if self.entries.contains_key(&block.hash) {
+      return Ok(Inserted::Known);
+    }
+    if self.invalid.contains(&block.hash) || self.invalid.contains(&block.header.previous_hash) {
+      return Err(ChainError::KnownInvalid);
+    }
+    check_block(&block)?;
+
+    if !self.entries.contains_key(&block.header.previous_hash) {
+      let siblings = self
+        .orphans
+        .entry(block.header.previous_hash.clone())
+        .or_default();
+      if siblings.iter().any(|orphan| orphan.hash == block.hash) {
+        return Ok(Inserted::Known);
+      }
+      siblings.push(block);
+      self.trim_orphans();
+      return Ok(Inserted::Orphan);
+    }
+
+    self.add_entry(block.clone())?;
+    let mut added = vec![block];
+    let mut index = 0;
+    while index < added.len() {
+      for orphan in self.orphans.remove(&added[index].hash).unwrap_or_default() {
+        match self.add_entry(orphan.clone()) {
+          Ok(()) => added.push(orphan),
+          Err(err) => warn!("dropping orphan block {}: {err}", orphan.hash),
+        }
+      }
+      index += 1;
+    }
+
+    let best_work = self.best_work();
+    let candidate = added
+      .iter()
+      .map(|block| (&block.hash, self.entries[&block.hash].total_work))
+      .filter(|(_, work)| *work > best_work)
+      .max_by_key(|(_, work)| *work)
+      .map(|(hash, _)| hash.clone());
+    let tip_change = candidate.and_then(|candidate| self.switch_to_best(candidate));
+    Ok(Inserted::Added {
+      blocks: added,
+      tip_change,
+    })
+  }
+
+  fn add_entry(&mut self, block: Block) -> Result<(), ChainError> {
+    let parent = &self.entries[&block.header.previous_hash];
+    let header = &block.header;
+    let expected_height = parent.block.header.height + 1;
+    if header.height != expected_height {
+      return Err(ChainError::WrongHeight {
+        expected: expected_height,
+        got: header.height,
+      });
+    }
+    let expected_difficulty = self.next_difficulty(&header.previous_hash);
+    if header.difficulty != expected_difficulty {
+      return Err(ChainError::WrongDifficulty {
+        expected: expected_difficulty,
+        got: header.difficulty,
+      });
+    }
+    if header.timestamp < parent.block.header.timestamp {
+      return Err(ChainError::TimestampBeforeParent);
+    }
+    if header.timestamp > Utc::now().timestamp() + MAX_FUTURE_DRIFT_SECS {
+      return Err(ChainError::TimestampInFuture);
+    }
+
+    let total_work = parent.total_work + header.work();
+    self
+      .entries
+      .insert(block.hash.clone(), Entry { block, total_work });
+    Ok(())
+  }
+
+  fn trim_orphans(&mut self) {
+    while self.orphans.values().map(Vec::len).sum::<usize>() > MAX_ORPHANS {
+      let Some(parent) = self.orphans.keys().next().cloned() else {
+        return;
+      };
+      self.orphans.remove(&parent);
+    }
+  }
+
+  /// Makes `candidate` the tip. If a block on the way turns out to spend
+  /// coins that do not exist, that block and its descendants are dropped and
+  /// the next best tip is tried instead.
+  fn switch_to_best(&mut self, mut candidate: String) -> Option<TipChange> {
+    loop {
+      match self.activate(&candidate) {
+        Ok(change) => return Some(change),
+        Err((bad, err)) => {
+          warn!("block {bad} is invalid: {err}");
+          self.invalidate(&bad);
+        }
+      }
+      let best_work = self.best_work();
+      candidate = self
+        .entries
+        .values()
+        .filter(|entry| entry.total_work > best_work)
+        .max_by_key(|entry| entry.total_work)?
+        .block
+        .hash
+        .clone();
+    }
+  }
+
+  fn activate(&mut self, candidate: &str) -> Result<TipChange, (String, ChainError)> {
+    let mut path = Vec::new();
+    let mut hash = candidate.to_string();
+    while !self.is_on_best_chain(&hash) {
+      let parent = self.entries[&hash].block.header.previous_hash.clone();
+      path.push(hash);
+      hash = parent;
+    }
+    path.reverse();
+    let fork_height = self.entries[&hash].block.header.height as usize;
+
+    let mut ledger = if fork_height + 1 == self.best_chain.len() {
+      self.ledger.clone()
+    } else {
+      self.ledger_at(fork_height)
+    };
+    for hash in &path {
+      let block = &self.entries[hash].block;
+      ledger
+        .apply_block(block.header.height, &block.body)
+        .map_err(|err| (hash.clone(), ChainError::Transaction(err)))?;
+    }
+
+    let disconnected = self
+      .best_chain
+      .split_off(fork_height + 1)
+      .into_iter()
+      .map(|hash| self.entries[&hash].block.clone())
+      .collect();
+    let connected = path
+      .iter()
+      .map(|hash| self.entries[hash].block.clone())
+      .collect();
+    self.best_chain.extend(path);
+    self.ledger = ledger;
+    Ok(TipChange {
+      disconnected,
+      connected,
+    })
+  }
+
+  fn is_on_best_chain(&self, hash: &str) -> bool {
+    let height = self.entries[hash].block.header.height as usize;
+    self.best_chain.get(height).is_some_and(|best| best == hash)
+  }
+
+  fn ledger_at(&self, height: usize) -> Ledger {
+    let mut ledger = Ledger::default();
+    for hash in &self.best_chain[..= height] {
+      let block = &self.entries[hash].block;
+      ledger
+        .apply_block(block.header.height, &block.body)
+        .expect("best chain was applied before");
+    }
+    ledger
+  }
+
+  fn invalidate(&mut self, hash: &str) {
+    let mut doomed = HashSet::from([hash.to_string()]);
+    loop {
+      let children = self
+        .entries
+        .values()
+        .filter(|entry| {
+          doomed.contains(&entry.block.header.previous_hash) && !doomed.contains(&entry.block.hash)
+        })
+        .map(|entry| entry.block.hash.clone())
+        .collect::<Vec<_>>();
+      if children.is_empty() {
+        break;
+      }
+      doomed.extend(children);
+    }
+    for hash in &doomed {
+      self.entries.remove(hash);
+    }
+    self.invalid.extend(doomed);
+  }
+
+  /// Hashes of the best chain a peer can use to find where its own best chain
+  /// forks off ours: the last few blocks, then exponentially sparser back to
+  /// genesis.
+  pub fn locator(&self) -> Vec<String> {
+    let mut locator = Vec::new();
+    let mut height = self.best_chain.len() - 1;
+    let mut step = 1;
+    loop {
+      locator.push(self.best_chain[height].clone());
+      if height == 0 {
+        return locator;
+      }
+      if locator.len() >= LOCATOR_DENSE_ENTRIES {
+        step *= 2;
+      }
+      height = height.saturating_sub(step);
+    }
+  }
+
+  /// Up to `limit` headers of the best chain following the first `locator`
+  /// hash that is on it.
+  pub fn headers_after(&self, locator: &[String], limit: usize) -> Vec<BlockHeader> {
+    let start = locator
+      .iter()
+      .find(|hash| self.entries.contains_key(*hash) && self.is_on_best_chain(hash))
+      .map(|hash| self.entries[hash].block.header.height as usize)
+      .unwrap_or(0);
+    self.best_chain[start + 1 ..]
+      .iter()
+      .take(limit)
+      .map(|hash| self.entries[hash].block.header.clone())
+      .collect()
+  }
+
+  /// Checks that `headers` extend a block we have and carry valid proof of
+  /// work, and returns the cumulative work their last block would have. This
+  /// is what lets sync skip downloading bodies of a chain that cannot win.
+  pub fn header_chain_work(&self, headers: &[BlockHeader]) -> Result<u128, ChainError> {
+    let first = headers.first().ok_or(ChainError::HeadersNotConnected)?;
+    if self.invalid.contains(&first.previous_hash) {
+      return Err(ChainError::KnownInvalid);
+    }
+    let parent = self
+      .entries
+      .get(&first.previous_hash)
+      .ok_or(ChainError::UnknownParent)?;
+
+    let mut work = parent.total_work;
+    let mut previous_hash = parent.block.hash.clone();
+    let mut height = parent.block.header.height;
+    for header in headers {
+      if header.previous_hash != previous_hash || header.height != height + 1 {
+        return Err(ChainError::HeadersNotConnected);
+      }
+      check_header(header)?;
+      work += header.work();
+      previous_hash = header.hash();
+      height = header.height;
+    }
+    Ok(work)
+  }
+}
+
+fn check_header(header: &BlockHeader) -> Result<(), ChainError> {
+  if !(MIN_DIFFICULTY ..= MAX_DIFFICULTY).contains(&header.difficulty) {
+    return Err(ChainError::DifficultyOutOfRange(header.difficulty));
+  }
+  if !header.meets_difficulty() {
+    return Err(ChainError::InsufficientWork);
+  }
+  Ok(())
+}
+
+/// Checks that do not depend on the rest of the chain.
+fn check_block(block: &Block) -> Result<(), ChainError> {
+  if block.hash != block.header.hash() {
+    return Err(ChainError::HashMismatch);
+  }
+  check_header(&block.header)?;
+  if block.body.hash() != block.header.body_hash {
+    return Err(ChainError::BodyMismatch);
+  }
+  if block.body.transactions.len() > MAX_TRANSACTIONS_PER_BLOCK {
+    return Err(ChainError::TooManyTransactions);
+  }
+  if block.body.data.len() > MAX_DATA_LEN {
+    return Err(ChainError::DataTooLong);
+  }
+  parse_address(&block.body.miner).map_err(|_| ChainError::BadMiner(block.body.miner.clone()))?;
+  for tx in &block.body.transactions {
+    tx.verify().map_err(ChainError::Transaction)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use libp2p::identity::Keypair;
+
+  use super::*;
+  use crate::transaction::{Transaction, BLOCK_REWARD};
+
+  /// A tree whose genesis asks for `MIN_DIFFICULTY`, so mining in tests takes
+  /// a few hundred hashes per block.
+  fn tree() -> BlockTree {
+    let mut genesis = Block::genesis();
+    genesis.header.difficulty = MIN_DIFFICULTY;
+    genesis.hash = genesis.header.hash();
+    BlockTree::with_genesis(genesis)
+  }
+
+  fn address(keypair: &Keypair) -> String {
+    keypair.public().to_peer_id().to_string()
+  }
+
+  /// An unmined child of `parent` at the parent's difficulty.
+  fn child(
+    parent: &Block,
+    miner: &str,
+    data: &str,
+    transactions: Vec<SignedTransaction>,
+  ) -> (BlockHeader, BlockBody) {
+    let body = BlockBody {
+      miner: miner.to_string(),
+      data: data.to_string(),
+      transactions,
+    };
+    let header = BlockHeader {
+      height: parent.header.height + 1,
+      previous_hash: parent.hash.clone(),
+      timestamp: parent.header.timestamp + TARGET_BLOCK_SECS,
+      difficulty: parent.header.difficulty,
+      body_hash: body.hash(),
+      nonce: 0,
+    };
+    (header, body)
+  }
+
+  fn mine_on(
+    parent: &Block,
+    miner: &str,
+    data: &str,
+    transactions: Vec<SignedTransaction>,
+  ) -> Block {
+    let (header, body) = child(parent, miner, data, transactions);
+    Block::mine(header, body)
+  }
+
+  fn transfer(from: &Keypair, to: &Keypair, amount: u64) -> SignedTransaction {
+    let transaction = Transaction {
+      from: address(from),
+      to: address(to),
+      amount,
+      fee: 1,
+      nonce: 0,
+    };
+    SignedTransaction::sign(from, transaction).unwrap()
+  }
+
+  fn hashes(blocks: &[Block]) -> Vec<&str> {
+    blocks.iter().map(|block| block.hash.as_str()).collect()
+  }
+
+  #[test]
+  fn heavier_side_chain_reorgs_the_best_chain() {
+    let mut tree = tree();
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let genesis = tree.tip().clone();
+
+    let a1 = mine_on(&genesis, &address(&alice), "a1", vec![]);
+    tree.insert(a1.clone()).unwrap();
+    let b1 = mine_on(&genesis, &address(&bob), "b1", vec![]);
+    let b2 = mine_on(&b1, &address(&bob), "b2", vec![]);
+
+    // Equal work does not move the tip.
+    let Inserted::Added { tip_change, .. } = tree.insert(b1.clone()).unwrap() else {
+      panic!("b1 extends genesis");
+    };
+    assert!(tip_change.is_none());
+    assert_eq!(tree.tip().hash, a1.hash);
+
+    let Inserted::Added {
+      tip_change: Some(change),
+      ..
+    } = tree.insert(b2.clone()).unwrap()
+    else {
+      panic!("b2 has more work than a1");
+    };
+    assert!(change.is_reorg());
+    assert_eq!(hashes(&change.disconnected), [a1.hash.as_str()]);
+    assert_eq!(
+      hashes(&change.connected),
+      [b1.hash.as_str(), b2.hash.as_str()]
+    );
+    assert_eq!(tree.tip().hash, b2.hash);
+    assert_eq!(tree.ledger().account(&address(&alice)).balance, 0);
+    assert_eq!(
+      tree.ledger().account(&address(&bob)).balance,
+      2 * BLOCK_REWARD
+    );
+  }
+
+  #[test]
+  fn block_spending_missing_funds_is_invalidated_with_its_descendants() {
+    let mut tree = tree();
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let genesis = tree.tip().clone();
+
+    let a1 = mine_on(&genesis, &address(&alice), "a1", vec![]);
+    tree.insert(a1.clone()).unwrap();
+
+    // Alice only has coins on the a1 chain, so b2 spends money she lacks.
+    let b1 = mine_on(&genesis, &address(&bob), "b1", vec![]);
+    let b2 = mine_on(&b1, &address(&bob), "b2", vec![transfer(&alice, &bob, 10)]);
+    let b3 = mine_on(&b2, &address(&bob), "b3", vec![]);
+
+    // The children arrive first and wait for b1 as orphans.
+    assert!(matches!(tree.insert(b3.clone()), Ok(Inserted::Orphan)));
+    assert!(matches!(tree.insert(b2.clone()), Ok(Inserted::Orphan)));
+    let Inserted::Added { blocks, tip_change } = tree.insert(b1.clone()).unwrap() else {
+      panic!("b1 extends genesis");
+    };
+    assert_eq!(blocks.len(), 3);
+    assert!(tip_change.is_none());
+
+    assert_eq!(tree.tip().hash, a1.hash);
+    assert!(tree.block(&b1.hash).is_some());
+    assert!(tree.block(&b2.hash).is_none());
+    assert!(tree.block(&b3.hash).is_none());
+    assert_eq!(
+      tree.insert(b3.clone()).unwrap_err(),
+      ChainError::KnownInvalid
+    );
+    let b4 = mine_on(&b3, &address(&bob), "b4", vec![]);
+    assert_eq!(tree.insert(b4).unwrap_err(), ChainError::KnownInvalid);
+  }
+
+  #[test]
+  fn difficulty_retargets_by_one_bit() {
+    let mut tree = tree();
+    let miner = address(&Keypair::generate_ed25519());
+    let mut tip = tree.tip().clone();
+
+    // A whole interval in no time at all makes the next block one bit harder,
+    // not more.
+    for height in 1 .. RETARGET_INTERVAL {
+      let (mut header, body) = child(&tip, &miner, &height.to_string(), vec![]);
+      header.timestamp = tip.header.timestamp;
+      tip = Block::mine(header, body);
+      tree.insert(tip.clone()).unwrap();
+    }
+    assert_eq!(tree.next_difficulty(&tip.hash), MIN_DIFFICULTY + 1);
+
+    let easy = mine_on(&tip, &miner, "easy", vec![]);
+    assert_eq!(
+      tree.insert(easy).unwrap_err(),
+      ChainError::WrongDifficulty {
+        expected: MIN_DIFFICULTY + 1,
+        got: MIN_DIFFICULTY
+      }
+    );
+
+    // Ten times slower than the target makes it one bit easier again.
+    for height in RETARGET_INTERVAL .. 2 * RETARGET_INTERVAL {
+      let (mut header, body) = child(&tip, &miner, &height.to_string(), vec![]);
+      header.difficulty = tree.next_difficulty(&tip.hash);
+      header.timestamp = tip.header.timestamp + 10 * TARGET_BLOCK_SECS;
+      assert_eq!(header.difficulty, MIN_DIFFICULTY + 1);
+      tip = Block::mine(header, body);
+      tree.insert(tip.clone()).unwrap();
+    }
+    assert_eq!(tree.next_difficulty(&tip.hash), MIN_DIFFICULTY);
+  }
+
+  #[test]
+  fn blocks_with_tampered_transactions_are_rejected() {
+    let mut tree = tree();
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let mut tx = transfer(&alice, &bob, 1);
+    tx.transaction.amount = 1_000;
+
+    let genesis = tree.tip().clone();
+    let block = mine_on(&genesis, &address(&bob), "tampered", vec![tx]);
+    assert_eq!(
+      tree.insert(block).unwrap_err(),
+      ChainError::Transaction(TransactionError::BadSignature)
+    );
+  }
+}
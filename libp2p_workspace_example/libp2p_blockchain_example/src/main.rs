@@ -1,257 +1,207 @@
-use std::time::Duration;
-
-use chrono::prelude::*;
-use libp2p::{
-  core::transport::upgrade::Version,
-  futures::StreamExt,
-  // mplex,
-  identity,
-  noise,
-  swarm::{Swarm, SwarmBuilder},
-  tcp,
-  yamux,
-  Transport,
+use std::{
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+  time::Duration,
 };
+
+use libp2p::{futures::StreamExt, identity, mdns, noise, swarm::SwarmEvent, tcp, yamux};
 use log::{error, info, warn};
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use tokio::{
   io::{stdin, AsyncBufReadExt, BufReader},
-  select, spawn,
+  select,
   sync::mpsc,
-  time::sleep,
+  task::spawn_blocking,
 };
 
-const DIFFICULTY_PREFIX: &str = "00";
-
+mod chain;
+mod mempool;
 mod p2p;
+mod store;
+mod transaction;
 
-pub struct App {
-  pub blocks: Vec<Block>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
-  pub id: u64,
-  pub hash: String,
-  pub previous_hash: String,
-  pub timestamp: i64,
-  pub data: String,
-  pub nonce: u64,
-}
-
-impl Block {
-  pub fn new(id: u64, previous_hash: String, data: String) -> Self {
-    let now = Utc::now();
-    let (nonce, hash) = mine_block(id, now.timestamp(), &previous_hash, &data);
-    Self {
-      id,
-      hash,
-      timestamp: now.timestamp(),
-      previous_hash,
-      data,
-      nonce,
-    }
-  }
-}
-
-fn calculate_hash(id: u64, timestamp: i64, previous_hash: &str, data: &str, nonce: u64) -> Vec<u8> {
-  let data = serde_json::json!({
-      "id": id,
-      "previous_hash": previous_hash,
-      "data": data,
-      "timestamp": timestamp,
-      "nonce": nonce
-  });
-  let mut hasher = Sha256::new();
-  hasher.update(data.to_string().as_bytes());
-  hasher.finalize().as_slice().to_owned()
-}
+use chain::{Block, BlockBody, BlockTree, ChainError, Inserted, TipChange};
+use mempool::Mempool;
+use store::BlockStore;
+use transaction::{SignedTransaction, Transaction};
 
-fn mine_block(id: u64, timestamp: i64, previous_hash: &str, data: &str) -> (u64, String) {
-  info!("mining block...");
-  let mut nonce = 0;
-
-  loop {
-    if nonce % 100000 == 0 {
-      info!("nonce: {}", nonce);
-    }
-    let hash = calculate_hash(id, timestamp, previous_hash, data, nonce);
-    let binary_hash = hash_to_binary_representation(&hash);
-    if binary_hash.starts_with(DIFFICULTY_PREFIX) {
-      info!(
-        "mined! nonce: {}, hash: {}, binary hash: {}",
-        nonce,
-        hex::encode(&hash),
-        binary_hash
-      );
-      return (nonce, hex::encode(hash));
-    }
-    nonce += 1;
-  }
-}
-
-fn hash_to_binary_representation(hash: &[u8]) -> String {
-  let mut res: String = String::default();
-  for c in hash {
-    res.push_str(&format!("{:b}", c));
-  }
-  res
+pub struct App {
+  pub chain: BlockTree,
+  pub mempool: Mempool,
+  pub keypair: identity::Keypair,
+  /// Wallet address of this node: its peer id.
+  pub address: String,
+  store: BlockStore,
 }
 
 impl App {
-  fn new() -> Self {
-    Self { blocks: vec![] }
-  }
-
-  fn genesis(&mut self) {
-    let genesis_block = Block {
-      id: 0,
-      timestamp: Utc::now().timestamp(),
-      previous_hash: String::from("genesis"),
-      data: String::from("genesis!"),
-      nonce: 2836,
-      hash: "0000f816a87f806bb0073dcf026a64fb40c946b5abee2573702828694d5b4c43".to_string(),
-    };
-    self.blocks.push(genesis_block);
+  fn open(data_dir: &Path) -> Result<Self, Box<dyn Error>> {
+    fs::create_dir_all(data_dir)?;
+    let keypair = load_or_create_identity(data_dir)?;
+    let (store, blocks) = BlockStore::open(&data_dir.join("blocks.jsonl"))?;
+
+    let mut chain = BlockTree::new();
+    for block in blocks {
+      if let Err(err) = chain.insert(block) {
+        warn!("skipping stored block: {err}");
+      }
+    }
+    let tip = chain.tip();
+    info!(
+      "loaded chain from {}: tip {} at height {}",
+      data_dir.display(),
+      tip.hash,
+      tip.header.height
+    );
+
+    Ok(Self {
+      chain,
+      mempool: Mempool::default(),
+      address: keypair.public().to_peer_id().to_string(),
+      keypair,
+      store,
+    })
   }
 
-  fn try_add_block(&mut self, block: Block) {
-    let latest_block = self.blocks.last().expect("there is at least one block");
-    if self.is_block_valid(&block, latest_block) {
-      self.blocks.push(block);
-    } else {
-      error!("could not add block - invalid");
+  /// Adds a block from any source to the tree, persists whatever the tree
+  /// took in and moves transactions between the chain and the mempool when
+  /// the best chain changes.
+  pub fn accept_block(&mut self, block: Block) -> Result<Inserted, ChainError> {
+    let inserted = self.chain.insert(block)?;
+    if let Inserted::Added { blocks, tip_change } = &inserted {
+      for block in blocks {
+        if let Err(err) = self.store.append(block) {
+          error!("failed to persist block {}: {err}", block.hash);
+        }
+      }
+      if let Some(change) = tip_change {
+        self.on_tip_changed(change);
+      }
     }
+    Ok(inserted)
   }
 
-  fn is_block_valid(&self, block: &Block, previous_block: &Block) -> bool {
-    if block.previous_hash != previous_block.hash {
-      warn!("block with id: {} has wrong previous hash", block.id);
-      return false;
-    } else if !hash_to_binary_representation(
-      &hex::decode(&block.hash).expect("can decode from hex"),
-    )
-    .starts_with(DIFFICULTY_PREFIX)
-    {
-      warn!("block with id: {} has invalid difficulty", block.id);
-      return false;
-    } else if block.id != previous_block.id + 1 {
+  fn on_tip_changed(&mut self, change: &TipChange) {
+    if change.is_reorg() {
       warn!(
-        "block with id: {} is not the next block after the latest: {}",
-        block.id, previous_block.id
+        "reorg: {} block(s) replaced by {}",
+        change.disconnected.len(),
+        change.connected.len()
       );
-      return false;
-    } else if hex::encode(calculate_hash(
-      block.id,
-      block.timestamp,
-      &block.previous_hash,
-      &block.data,
-      block.nonce,
-    )) != block.hash
-    {
-      warn!("block with id: {} has invalid hash", block.id);
-      return false;
     }
-    true
+    let tip = self.chain.tip();
+    info!("new tip {} at height {}", tip.hash, tip.header.height);
+    self.mempool.on_tip_changed(
+      change
+        .disconnected
+        .iter()
+        .flat_map(|block| &block.body.transactions),
+      self.chain.ledger(),
+    );
   }
 
-  fn is_chain_valid(&self, chain: &[Block]) -> bool {
-    for i in 0 .. chain.len() {
-      if i == 0 {
-        continue;
-      }
-      let first = chain.get(i - 1).expect("has to exist");
-      let second = chain.get(i).expect("has to exist");
-      if !self.is_block_valid(second, first) {
-        return false;
-      }
-    }
-    true
+  /// A block on top of the best chain paying this node, with the best
+  /// transactions from the mempool. Still needs to be mined.
+  fn block_template(&self, data: String) -> (chain::BlockHeader, BlockBody) {
+    let body = BlockBody {
+      miner: self.address.clone(),
+      data,
+      transactions: self.mempool.select(self.chain.ledger()),
+    };
+    (self.chain.next_header(&body), body)
   }
 
-  // We always choose the longest valid chain
-  fn choose_chain(&mut self, local: Vec<Block>, remote: Vec<Block>) -> Vec<Block> {
-    let is_local_valid = self.is_chain_valid(&local);
-    let is_remote_valid = self.is_chain_valid(&remote);
+  pub fn create_transaction(
+    &mut self,
+    to: &str,
+    amount: u64,
+    fee: u64,
+  ) -> Result<SignedTransaction, Box<dyn Error>> {
+    let transaction = Transaction {
+      from: self.address.clone(),
+      to: to.to_string(),
+      amount,
+      fee,
+      nonce: self.mempool.next_nonce(&self.address, self.chain.ledger()),
+    };
+    let tx = SignedTransaction::sign(&self.keypair, transaction)?;
+    self.mempool.insert(tx.clone(), self.chain.ledger())?;
+    Ok(tx)
+  }
+}
 
-    if is_local_valid && is_remote_valid {
-      if local.len() >= remote.len() {
-        local
-      } else {
-        remote
-      }
-    } else if is_remote_valid && !is_local_valid {
-      remote
-    } else if !is_remote_valid && is_local_valid {
-      local
-    } else {
-      panic!("local and remote chains are both invalid");
+fn load_or_create_identity(data_dir: &Path) -> Result<identity::Keypair, Box<dyn Error>> {
+  let path = data_dir.join("identity.key");
+  match fs::read(&path) {
+    Ok(bytes) => Ok(identity::Keypair::from_protobuf_encoding(&bytes)?),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      let keypair = identity::Keypair::generate_ed25519();
+      fs::write(&path, keypair.to_protobuf_encoding()?)?;
+      Ok(keypair)
     }
+    Err(err) => Err(err.into()),
   }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Box<dyn Error>> {
   pretty_env_logger::init();
 
-  info!("Peer Id: {}", p2p::PEER_ID.clone());
-  let (response_sender, mut response_rcv) = mpsc::unbounded_channel();
-  let (init_sender, mut init_rcv) = mpsc::unbounded_channel();
-
-  // let auth_keys = Keypair::<X25519Spec>::new()
-  //     .into_authentic(&p2p::KEYS)
-  //     .expect("can create auth keys");
-  let auth_keys = identity::Keypair::generate_secp256k1();
-
-  // let transp = TokioTcpConfig::new()
-  //     .upgrade(upgrade::Version::V1)
-  //     .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
-  //     .multiplex(lib_p2p_mplex::MplexConfig::new())
-  //     .boxed();
-  let noise = noise::Config::new(&auth_keys).unwrap();
-  let transp = tcp::tokio::Transport::default()
-    .upgrade(Version::V1Lazy)
-    .authenticate(noise)
-    .multiplex(yamux::Config::default())
-    .boxed();
-
-  let behaviour = p2p::AppBehaviour::new(App::new(), response_sender, init_sender.clone()).await;
-
-  let mut swarm = SwarmBuilder::new(transp, behaviour, *p2p::PEER_ID)
-    .executor(Box::new(|fut| {
-      spawn(fut);
-    }))
+  // The node key is also the wallet key, so keep one data directory per node.
+  let data_dir = std::env::args()
+    .nth(1)
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from("blockchain-data"));
+  let mut app = App::open(&data_dir)?;
+  info!("Peer Id / address: {}", app.address);
+
+  let mut swarm = libp2p::SwarmBuilder::with_existing_identity(app.keypair.clone())
+    .with_tokio()
+    .with_tcp(
+      tcp::Config::default(),
+      noise::Config::new,
+      yamux::Config::default,
+    )?
+    .with_behaviour(p2p::AppBehaviour::new)?
+    .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
     .build();
 
-  let mut stdin = BufReader::new(stdin()).lines();
-
-  Swarm::listen_on(
-    &mut swarm,
-    "/ip4/0.0.0.0/tcp/0"
-      .parse()
-      .expect("can get a local socket"),
-  )
-  .expect("swarm can be started");
+  swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
 
-  spawn(async move {
-    sleep(Duration::from_secs(1)).await;
-    info!("sending init event");
-    init_sender.send(true).expect("can send init event");
-  });
+  let (mined_sender, mut mined_rcv) = mpsc::unbounded_channel();
+  let mut mining = false;
+  let mut sync = p2p::ChainSync::default();
+  let mut stdin = BufReader::new(stdin()).lines();
 
   loop {
     let evt = {
       select! {
           line = stdin.next_line() => Some(p2p::EventType::Input(line.expect("can get line").expect("can read line from stdin"))),
-          response = response_rcv.recv() => {
-              Some(p2p::EventType::LocalChainResponse(response.expect("response exists")))
-          },
-          _init = init_rcv.recv() => {
-              Some(p2p::EventType::Init)
-          }
+          block = mined_rcv.recv() => Some(p2p::EventType::Mined(block.expect("miner sender is kept alive"))),
           event = swarm.select_next_some() => {
-              info!("Unhandled Swarm Event: {:?}", event);
+              match event {
+                  SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {address}"),
+                  SwarmEvent::Behaviour(p2p::AppBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+                      for (peer_id, _multiaddr) in list {
+                          swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                      }
+                  }
+                  SwarmEvent::Behaviour(p2p::AppBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+                      for (peer_id, _multiaddr) in list {
+                          swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                      }
+                  }
+                  SwarmEvent::ConnectionEstablished { peer_id, num_established, .. } if num_established.get() == 1 => {
+                      sync.start(&mut swarm, &app, peer_id);
+                  }
+                  SwarmEvent::Behaviour(p2p::AppBehaviourEvent::Gossipsub(event)) => {
+                      p2p::handle_gossip(&mut swarm, &mut app, &mut sync, event);
+                  }
+                  SwarmEvent::Behaviour(p2p::AppBehaviourEvent::Sync(event)) => {
+                      sync.handle_event(&mut swarm, &mut app, event);
+                  }
+                  event => info!("Unhandled Swarm Event: {:?}", event),
+              }
               None
           },
       }
@@ -259,34 +209,38 @@ async fn main() {
 
     if let Some(event) = evt {
       match event {
-        p2p::EventType::Init => {
-          let peers = p2p::get_list_peers(&swarm);
-          swarm.behaviour_mut().app.genesis();
-
-          info!("connected nodes: {}", peers.len());
-          if !peers.is_empty() {
-            let req = p2p::LocalChainRequest {
-              from_peer_id: peers.iter().last().expect("at least one peer").to_string(),
-            };
-
-            let json = serde_json::to_string(&req).expect("can jsonify request");
-            swarm
-              .behaviour_mut()
-              .floodsub
-              .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
+        p2p::EventType::Mined(block) => {
+          mining = false;
+          let json = serde_json::to_vec(&block).expect("can jsonify block");
+          match app.accept_block(block) {
+            Ok(Inserted::Added { .. }) => {
+              info!("broadcasting new block");
+              p2p::publish(&mut swarm, &p2p::BLOCK_TOPIC, json);
+            }
+            Ok(_) => {}
+            Err(err) => error!("mined an invalid block: {err}"),
           }
         }
-        p2p::EventType::LocalChainResponse(resp) => {
-          let json = serde_json::to_string(&resp).expect("can jsonify response");
-          swarm
-            .behaviour_mut()
-            .floodsub
-            .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
-        }
         p2p::EventType::Input(line) => match line.as_str() {
           "ls p" => p2p::handle_print_peers(&swarm),
-          cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(&swarm),
-          cmd if cmd.starts_with("create b") => p2p::handle_create_block(cmd, &mut swarm),
+          "ls f" => p2p::handle_print_tips(&app),
+          "ls t" => p2p::handle_print_mempool(&app),
+          cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(&app),
+          cmd if cmd.starts_with("balance") => p2p::handle_print_balance(cmd, &app),
+          cmd if cmd.starts_with("send ") => p2p::handle_send(cmd, &mut swarm, &mut app),
+          cmd if cmd.starts_with("create b") => {
+            if mining {
+              warn!("already mining a block");
+              continue;
+            }
+            let data = cmd.strip_prefix("create b").unwrap_or_default().trim().to_owned();
+            let (header, body) = app.block_template(data);
+            let sender = mined_sender.clone();
+            mining = true;
+            spawn_blocking(move || {
+              let _ = sender.send(Block::mine(header, body));
+            });
+          }
           _ => error!("unknown command"),
         },
       }
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use super::{
+  chain::MAX_TRANSACTIONS_PER_BLOCK,
+  transaction::{Ledger, SignedTransaction, TransactionError},
+};
+
+pub const MAX_MEMPOOL_SIZE: usize = 1_000;
+
+/// Verified transactions waiting to be mined, keyed by id.
+///
+/// A transaction may have a nonce ahead of its sender's account as long as
+/// the gap is filled by other pending transactions of the same sender, so a
+/// wallet can send several transfers before the first one is mined.
+#[derive(Debug, Default)]
+pub struct Mempool {
+  transactions: HashMap<String, SignedTransaction>,
+}
+
+impl Mempool {
+  pub fn len(&self) -> usize {
+    self.transactions.len()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &SignedTransaction> {
+    self.transactions.values()
+  }
+
+  /// Adds `tx` if it is valid on top of `ledger`. Returns `Ok(false)` if the
+  /// pool already had it. A transaction reusing a pending nonce of the same
+  /// sender replaces it only if it pays a higher fee.
+  pub fn insert(
+    &mut self,
+    tx: SignedTransaction,
+    ledger: &Ledger,
+  ) -> Result<bool, TransactionError> {
+    let id = tx.id();
+    if self.transactions.contains_key(&id) {
+      return Ok(false);
+    }
+    tx.verify()?;
+
+    let sender = &tx.transaction.from;
+    let next_nonce = self.next_nonce(sender, ledger);
+    let account = ledger.account(sender);
+    if tx.transaction.nonce < account.nonce {
+      return Err(TransactionError::StaleNonce {
+        expected: account.nonce,
+        got: tx.transaction.nonce,
+      });
+    }
+    if tx.transaction.nonce > next_nonce {
+      return Err(TransactionError::FutureNonce {
+        expected: next_nonce,
+        got: tx.transaction.nonce,
+      });
+    }
+    let replaced = self
+      .iter()
+      .find(|pending| {
+        pending.transaction.from == *sender && pending.transaction.nonce == tx.transaction.nonce
+      })
+      .cloned();
+    let mut pending_spend = self.pending_spend(sender);
+    if let Some(replaced) = &replaced {
+      if tx.transaction.fee <= replaced.transaction.fee {
+        return Err(TransactionError::Underpriced);
+      }
+      pending_spend -= replaced.transaction.amount + replaced.transaction.fee;
+    }
+    let needed = pending_spend
+      .checked_add(tx.transaction.amount + tx.transaction.fee)
+      .ok_or(TransactionError::Overflow)?;
+    if needed > account.balance {
+      return Err(TransactionError::InsufficientFunds {
+        balance: account.balance,
+        needed,
+      });
+    }
+
+    if let Some(replaced) = replaced {
+      self.transactions.remove(&replaced.id());
+    } else if self.transactions.len() >= MAX_MEMPOOL_SIZE {
+      self.evict_cheapest(tx.transaction.fee)?;
+    }
+
+    self.transactions.insert(id, tx);
+    Ok(true)
+  }
+
+  /// The nonce the next transaction of `address` should use.
+  pub fn next_nonce(&self, address: &str, ledger: &Ledger) -> u64 {
+    let mut nonce = ledger.account(address).nonce;
+    while self
+      .iter()
+      .any(|tx| tx.transaction.from == address && tx.transaction.nonce == nonce)
+    {
+      nonce += 1;
+    }
+    nonce
+  }
+
+  fn pending_spend(&self, address: &str) -> u64 {
+    self
+      .iter()
+      .filter(|tx| tx.transaction.from == address)
+      .map(|tx| tx.transaction.amount + tx.transaction.fee)
+      .sum()
+  }
+
+  fn evict_cheapest(&mut self, fee: u64) -> Result<(), TransactionError> {
+    // Only a sender's last pending transaction can go without leaving a
+    // nonce gap behind.
+    let cheapest = self
+      .iter()
+      .filter(|tx| {
+        !self.iter().any(|other| {
+          other.transaction.from == tx.transaction.from
+            && other.transaction.nonce == tx.transaction.nonce + 1
+        })
+      })
+      .min_by_key(|tx| tx.transaction.fee)
+      .filter(|tx| tx.transaction.fee < fee)
+      .map(SignedTransaction::id)
+      .ok_or(TransactionError::MempoolFull)?;
+    self.transactions.remove(&cheapest);
+    Ok(())
+  }
+
+  /// Picks the transactions for a new block on top of `ledger`, highest fee
+  /// first among those whose nonce is next in line.
+  pub fn select(&self, ledger: &Ledger) -> Vec<SignedTransaction> {
+    let mut ledger = ledger.clone();
+    let mut remaining = self.iter().collect::<Vec<_>>();
+    remaining.sort_by_key(|tx| std::cmp::Reverse(tx.transaction.fee));
+
+    let mut selected = Vec::new();
+    while selected.len() < MAX_TRANSACTIONS_PER_BLOCK {
+      let Some(index) = remaining
+        .iter()
+        .position(|tx| ledger.check(&tx.transaction).is_ok())
+      else {
+        break;
+      };
+      let tx = remaining.remove(index);
+      ledger
+        .apply_transaction(&tx.transaction)
+        .expect("transaction was checked");
+      selected.push(tx.clone());
+    }
+    selected
+  }
+
+  /// Updates the pool after the best chain changed: transactions of blocks
+  /// that left the chain come back, then everything that no longer fits
+  /// `ledger` is dropped.
+  pub fn on_tip_changed<'a>(
+    &mut self,
+    disconnected: impl IntoIterator<Item = &'a SignedTransaction>,
+    ledger: &Ledger,
+  ) {
+    for tx in disconnected {
+      self.transactions.insert(tx.id(), tx.clone());
+    }
+
+    let mut pending = std::mem::take(&mut self.transactions)
+      .into_values()
+      .collect::<Vec<_>>();
+    pending.sort_by_key(|tx| tx.transaction.nonce);
+    for tx in pending {
+      let _ = self.insert(tx, ledger);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use libp2p::identity::Keypair;
+
+  use super::*;
+  use crate::{chain::BlockBody, transaction::Transaction};
+
+  fn address(keypair: &Keypair) -> String {
+    keypair.public().to_peer_id().to_string()
+  }
+
+  fn transfer(from: &Keypair, to: &Keypair, nonce: u64) -> SignedTransaction {
+    let transaction = Transaction {
+      from: address(from),
+      to: address(to),
+      amount: 10,
+      fee: 1,
+      nonce,
+    };
+    SignedTransaction::sign(from, transaction).unwrap()
+  }
+
+  fn mined_by(miner: &Keypair, transactions: Vec<SignedTransaction>) -> BlockBody {
+    BlockBody {
+      miner: address(miner),
+      data: String::new(),
+      transactions,
+    }
+  }
+
+  #[test]
+  fn disconnected_transactions_return_until_mined_again() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let mut funded = Ledger::default();
+    funded.apply_block(1, &mined_by(&alice, vec![])).unwrap();
+    let tx = transfer(&alice, &bob, 0);
+
+    // The block with tx left the best chain; the new tip still funds alice.
+    let mut pool = Mempool::default();
+    pool.on_tip_changed([&tx], &funded);
+    assert_eq!(pool.len(), 1);
+    assert_eq!(pool.select(&funded), std::slice::from_ref(&tx));
+
+    // Mined again on the new chain, so its nonce is used up.
+    let mut spent = funded.clone();
+    spent.apply_block(2, &mined_by(&bob, vec![tx])).unwrap();
+    pool.on_tip_changed([], &spent);
+    assert_eq!(pool.len(), 0);
+  }
+
+  #[test]
+  fn pending_transactions_cannot_overspend() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let mut ledger = Ledger::default();
+    ledger.apply_block(1, &mined_by(&alice, vec![])).unwrap();
+
+    let mut pool = Mempool::default();
+    for nonce in 0 .. 4 {
+      assert_eq!(
+        pool.insert(transfer(&alice, &bob, nonce), &ledger),
+        Ok(true)
+      );
+    }
+    assert_eq!(
+      pool.insert(transfer(&alice, &bob, 4), &ledger),
+      Err(TransactionError::InsufficientFunds {
+        balance: 50,
+        needed: 55
+      })
+    );
+    assert_eq!(pool.next_nonce(&address(&alice), &ledger), 4);
+  }
+}
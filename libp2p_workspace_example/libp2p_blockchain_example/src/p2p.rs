@@ -1,179 +1,452 @@
-use std::collections::HashSet;
+use std::{
+  collections::{HashMap, VecDeque},
+  error::Error,
+  time::Duration,
+};
 
 use libp2p::{
-  floodsub::{Floodsub, FloodsubEvent, Topic},
+  gossipsub::{self, IdentTopic, MessageAcceptance},
   identity, mdns,
+  request_response::{self, cbor, OutboundRequestId, ProtocolSupport},
   swarm::{NetworkBehaviour, Swarm},
-  PeerId,
+  PeerId, StreamProtocol,
 };
-use log::info;
+use log::{info, warn};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use sha2::{Digest, Sha256};
+
+use super::{
+  chain::{Block, BlockHeader, ChainError, Inserted},
+  transaction::{SignedTransaction, TransactionError},
+  App,
+};
 
-use super::{App, Block};
+pub static BLOCK_TOPIC: Lazy<IdentTopic> = Lazy::new(|| IdentTopic::new("blocks"));
+pub static TRANSACTION_TOPIC: Lazy<IdentTopic> = Lazy::new(|| IdentTopic::new("transactions"));
+const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/blockchain/sync/1");
 
-pub static KEYS: Lazy<identity::Keypair> = Lazy::new(identity::Keypair::generate_ed25519);
-pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-pub static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chains"));
-pub static BLOCK_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blocks"));
+pub const MAX_HEADERS_PER_RESPONSE: usize = 500;
+pub const MAX_BLOCKS_PER_REQUEST: usize = 16;
+/// Headers collected from one peer before deciding whether its chain is worth
+/// downloading.
+const MAX_SYNC_HEADERS: usize = 50_000;
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ChainResponse {
-  pub blocks: Vec<Block>,
-  pub receiver: String,
+pub enum SyncRequest {
+  /// Best-chain headers after the first `locator` hash the peer has.
+  Headers {
+    locator: Vec<String>,
+  },
+  Blocks {
+    hashes: Vec<String>,
+  },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct LocalChainRequest {
-  pub from_peer_id: String,
+pub enum SyncResponse {
+  Headers(Vec<BlockHeader>),
+  Blocks(Vec<Block>),
 }
 
 pub enum EventType {
-  LocalChainResponse(ChainResponse),
   Input(String),
-  Init,
+  Mined(Block),
 }
 
 #[derive(NetworkBehaviour)]
-#[behaviour(to_swarm = "AppBehaviourEvent")]
 pub struct AppBehaviour {
-  pub floodsub: Floodsub,
+  pub gossipsub: gossipsub::Behaviour,
   pub mdns: mdns::tokio::Behaviour,
-  #[behaviour(ignore)]
-  pub response_sender: mpsc::UnboundedSender<ChainResponse>,
-  #[behaviour(ignore)]
-  pub init_sender: mpsc::UnboundedSender<bool>,
-  #[behaviour(ignore)]
-  pub app: App,
+  pub sync: cbor::Behaviour<SyncRequest, SyncResponse>,
 }
 
-enum AppBehaviourEvent {
-  FloodSub(FloodsubEvent),
-  Mdns(mdns::Event),
+impl AppBehaviour {
+  pub fn new(key: &identity::Keypair) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    // Content-addressed ids, so the same block or transaction relayed by
+    // several peers is only processed once.
+    let message_id_fn = |message: &gossipsub::Message| {
+      gossipsub::MessageId::from(hex::encode(Sha256::digest(&message.data)))
+    };
+    let gossipsub_config = gossipsub::ConfigBuilder::default()
+      .heartbeat_interval(Duration::from_secs(1))
+      .message_id_fn(message_id_fn)
+      .max_transmit_size(1024 * 1024)
+      // Messages are only relayed after the chain or mempool accepted them.
+      .validate_messages()
+      .build()?;
+    let mut gossipsub = gossipsub::Behaviour::new(
+      gossipsub::MessageAuthenticity::Signed(key.clone()),
+      gossipsub_config,
+    )?;
+    gossipsub.subscribe(&BLOCK_TOPIC)?;
+    gossipsub.subscribe(&TRANSACTION_TOPIC)?;
+
+    Ok(Self {
+      gossipsub,
+      mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?,
+      sync: cbor::Behaviour::new(
+        [(SYNC_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+      ),
+    })
+  }
+}
+
+pub fn handle_gossip(
+  swarm: &mut Swarm<AppBehaviour>,
+  app: &mut App,
+  sync: &mut ChainSync,
+  event: gossipsub::Event,
+) {
+  let gossipsub::Event::Message {
+    propagation_source,
+    message_id,
+    message,
+  } = event
+  else {
+    return;
+  };
+
+  let acceptance = if message.topic == BLOCK_TOPIC.hash() {
+    match serde_json::from_slice::<Block>(&message.data) {
+      Ok(block) => {
+        info!("received block {} from {propagation_source}", block.hash);
+        match app.accept_block(block) {
+          Ok(Inserted::Added { .. }) => MessageAcceptance::Accept,
+          Ok(Inserted::Known) => MessageAcceptance::Ignore,
+          Ok(Inserted::Orphan) => {
+            // We are missing its ancestors, so we cannot vouch for it yet.
+            sync.start(swarm, app, propagation_source);
+            MessageAcceptance::Ignore
+          }
+          Err(ChainError::TimestampInFuture) => MessageAcceptance::Ignore,
+          Err(err) => {
+            warn!("rejecting block from {propagation_source}: {err}");
+            MessageAcceptance::Reject
+          }
+        }
+      }
+      Err(_) => MessageAcceptance::Reject,
+    }
+  } else if message.topic == TRANSACTION_TOPIC.hash() {
+    match serde_json::from_slice::<SignedTransaction>(&message.data) {
+      Ok(tx) => handle_transaction(app, tx, propagation_source),
+      Err(_) => MessageAcceptance::Reject,
+    }
+  } else {
+    MessageAcceptance::Ignore
+  };
+
+  swarm
+    .behaviour_mut()
+    .gossipsub
+    .report_message_validation_result(&message_id, &propagation_source, acceptance);
 }
 
-impl From<FloodsubEvent> for AppBehaviourEvent {
-  fn from(event: FloodsubEvent) -> Self {
-    AppBehaviourEvent::FloodSub(event)
+fn handle_transaction(app: &mut App, tx: SignedTransaction, source: PeerId) -> MessageAcceptance {
+  let id = tx.id();
+  match app.mempool.insert(tx, app.chain.ledger()) {
+    Ok(true) => {
+      info!("added transaction {id} from {source} to the mempool");
+      MessageAcceptance::Accept
+    }
+    Ok(false) => MessageAcceptance::Ignore,
+    Err(
+      err @ (TransactionError::BadEncoding
+      | TransactionError::BadAddress(_)
+      | TransactionError::WrongSender
+      | TransactionError::BadSignature
+      | TransactionError::Overflow),
+    ) => {
+      warn!("rejecting transaction {id} from {source}: {err}");
+      MessageAcceptance::Reject
+    }
+    // Depends on what this node has seen so far, so the peer is not at fault.
+    Err(_) => MessageAcceptance::Ignore,
   }
 }
 
-impl From<mdns::Event> for AppBehaviourEvent {
-  fn from(event: mdns::Event) -> Self {
-    AppBehaviourEvent::Mdns(event)
+pub fn publish(swarm: &mut Swarm<AppBehaviour>, topic: &IdentTopic, data: Vec<u8>) {
+  if let Err(err) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+    warn!("failed to publish to {topic}: {err}");
   }
 }
 
-impl AppBehaviour {
-  pub async fn new(
-    app: App,
-    response_sender: mpsc::UnboundedSender<ChainResponse>,
-    init_sender: mpsc::UnboundedSender<bool>,
-  ) -> Self {
-    let mut behaviour = Self {
-      app,
-      floodsub: Floodsub::new(*PEER_ID),
-      mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), *PEER_ID).unwrap(),
-      response_sender,
-      init_sender,
+#[derive(Default)]
+struct PeerSync {
+  headers: Vec<BlockHeader>,
+  to_download: VecDeque<String>,
+}
+
+/// Headers-first sync: collect a peer's best-chain headers, check their proof
+/// of work and only download the bodies if that chain has more cumulative
+/// work than ours.
+#[derive(Default)]
+pub struct ChainSync {
+  peers: HashMap<PeerId, PeerSync>,
+  requests: HashMap<OutboundRequestId, PeerId>,
+}
+
+impl ChainSync {
+  pub fn start(&mut self, swarm: &mut Swarm<AppBehaviour>, app: &App, peer: PeerId) {
+    if self.peers.contains_key(&peer) {
+      return;
+    }
+    info!("syncing headers with {peer}");
+    self.peers.insert(peer, PeerSync::default());
+    self.send(
+      swarm,
+      peer,
+      SyncRequest::Headers {
+        locator: app.chain.locator(),
+      },
+    );
+  }
+
+  fn send(&mut self, swarm: &mut Swarm<AppBehaviour>, peer: PeerId, request: SyncRequest) {
+    let id = swarm.behaviour_mut().sync.send_request(&peer, request);
+    self.requests.insert(id, peer);
+  }
+
+  pub fn handle_event(
+    &mut self,
+    swarm: &mut Swarm<AppBehaviour>,
+    app: &mut App,
+    event: request_response::Event<SyncRequest, SyncResponse>,
+  ) {
+    match event {
+      request_response::Event::Message {
+        peer,
+        message: request_response::Message::Request {
+          request, channel, ..
+        },
+        ..
+      } => {
+        let response = match request {
+          SyncRequest::Headers { locator } => {
+            SyncResponse::Headers(app.chain.headers_after(&locator, MAX_HEADERS_PER_RESPONSE))
+          }
+          SyncRequest::Blocks { hashes } => SyncResponse::Blocks(
+            hashes
+              .iter()
+              .take(MAX_BLOCKS_PER_REQUEST)
+              .filter_map(|hash| app.chain.block(hash).cloned())
+              .collect(),
+          ),
+        };
+        if swarm
+          .behaviour_mut()
+          .sync
+          .send_response(channel, response)
+          .is_err()
+        {
+          warn!("sync response to {peer} failed: connection closed");
+        }
+      }
+      request_response::Event::Message {
+        peer,
+        message:
+          request_response::Message::Response {
+            request_id,
+            response,
+          },
+        ..
+      } => {
+        self.requests.remove(&request_id);
+        match response {
+          SyncResponse::Headers(headers) => self.on_headers(swarm, app, peer, headers),
+          SyncResponse::Blocks(blocks) => self.on_blocks(swarm, app, peer, blocks),
+        }
+      }
+      request_response::Event::OutboundFailure {
+        peer,
+        request_id,
+        error,
+        ..
+      } if self.requests.contains_key(&request_id) => {
+        self.requests.remove(&request_id);
+        warn!("sync with {peer} failed: {error}");
+        self.peers.remove(&peer);
+      }
+      _ => {}
+    }
+  }
+
+  fn on_headers(
+    &mut self,
+    swarm: &mut Swarm<AppBehaviour>,
+    app: &App,
+    peer: PeerId,
+    headers: Vec<BlockHeader>,
+  ) {
+    let Some(state) = self.peers.get_mut(&peer) else {
+      return;
     };
-    behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
-    behaviour.floodsub.subscribe(BLOCK_TOPIC.clone());
+    let full = headers.len() == MAX_HEADERS_PER_RESPONSE;
+    state.headers.extend(headers);
+    if full && state.headers.len() < MAX_SYNC_HEADERS {
+      let last = state.headers.last().expect("response was full").hash();
+      self.send(
+        swarm,
+        peer,
+        SyncRequest::Headers {
+          locator: vec![last],
+        },
+      );
+      return;
+    }
+
+    let headers = std::mem::take(&mut state.headers);
+    let known = headers
+      .iter()
+      .take_while(|header| app.chain.block(&header.hash()).is_some())
+      .count();
+    let headers = &headers[known ..];
+    if headers.is_empty() {
+      info!("in sync with {peer}");
+      self.peers.remove(&peer);
+      return;
+    }
 
-    behaviour
+    match app.chain.header_chain_work(headers) {
+      Ok(work) if work > app.chain.best_work() => {
+        info!(
+          "{peer} has {} new header(s) up to height {}, downloading blocks",
+          headers.len(),
+          headers.last().expect("not empty").height
+        );
+        state.to_download = headers
+          .iter()
+          .map(BlockHeader::hash)
+          .filter(|hash| !app.chain.contains(hash))
+          .collect();
+        self.request_blocks(swarm, app, peer);
+      }
+      Ok(_) => {
+        info!("{peer}'s chain has no more work than ours");
+        self.peers.remove(&peer);
+      }
+      Err(err) => {
+        warn!("invalid headers from {peer}: {err}");
+        self.peers.remove(&peer);
+      }
+    }
+  }
+
+  fn request_blocks(&mut self, swarm: &mut Swarm<AppBehaviour>, app: &App, peer: PeerId) {
+    let Some(state) = self.peers.get_mut(&peer) else {
+      return;
+    };
+    let count = state.to_download.len().min(MAX_BLOCKS_PER_REQUEST);
+    let hashes = state.to_download.drain(.. count).collect::<Vec<_>>();
+    if hashes.is_empty() {
+      let tip = app.chain.tip();
+      info!(
+        "synced with {peer}; tip is {} at height {}",
+        tip.hash, tip.header.height
+      );
+      self.peers.remove(&peer);
+      return;
+    }
+    self.send(swarm, peer, SyncRequest::Blocks { hashes });
   }
-}
 
-// // incoming event handler
-// impl NetworkBehaviourEventProcess<FloodsubEvent> for AppBehaviour {
-//     fn inject_event(&mut self, event: FloodsubEvent) {
-//         if let FloodsubEvent::Message(msg) = event {
-//             if let Ok(resp) = serde_json::from_slice::<ChainResponse>(&msg.data) {
-//                 if resp.receiver == PEER_ID.to_string() {
-//                     info!("Response from {}:", msg.source);
-//                     resp.blocks.iter().for_each(|r| info!("{:?}", r));
-
-//                     self.app.blocks = self.app.choose_chain(self.app.blocks.clone(),
-// resp.blocks);                 }
-//             } else if let Ok(resp) = serde_json::from_slice::<LocalChainRequest>(&msg.data) {
-//                 info!("sending local chain to {}", msg.source.to_string());
-//                 let peer_id = resp.from_peer_id;
-//                 if PEER_ID.to_string() == peer_id {
-//                     if let Err(e) = self.response_sender.send(ChainResponse {
-//                         blocks: self.app.blocks.clone(),
-//                         receiver: msg.source.to_string(),
-//                     }) {
-//                         error!("error sending response via channel, {}", e);
-//                     }
-//                 }
-//             } else if let Ok(block) = serde_json::from_slice::<Block>(&msg.data) {
-//                 info!("received new block from {}", msg.source.to_string());
-//                 self.app.try_add_block(block);
-//             }
-//         }
-//     }
-// }
-
-// impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
-//     fn inject_event(&mut self, event: MdnsEvent) {
-//         match event {
-//             MdnsEvent::Discovered(discovered_list) => {
-//                 for (peer, _addr) in discovered_list {
-//                     self.floodsub.add_node_to_partial_view(peer);
-//                 }
-//             }
-//             MdnsEvent::Expired(expired_list) => {
-//                 for (peer, _addr) in expired_list {
-//                     if !self.mdns.has_node(&peer) {
-//                         self.floodsub.remove_node_from_partial_view(&peer);
-//                     }
-//                 }
-//             }
-//         }
-//     }
-// }
-
-pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<String> {
-  info!("Discovered Peers:");
-  let nodes = swarm.behaviour().mdns.discovered_nodes();
-  let mut unique_peers = HashSet::new();
-  for peer in nodes {
-    unique_peers.insert(peer);
+  fn on_blocks(
+    &mut self,
+    swarm: &mut Swarm<AppBehaviour>,
+    app: &mut App,
+    peer: PeerId,
+    blocks: Vec<Block>,
+  ) {
+    if !self.peers.contains_key(&peer) {
+      return;
+    }
+    if blocks.is_empty() {
+      warn!("{peer} did not send the blocks it announced");
+      self.peers.remove(&peer);
+      return;
+    }
+    for block in blocks {
+      if let Err(err) = app.accept_block(block) {
+        warn!("invalid block from {peer}: {err}");
+        self.peers.remove(&peer);
+        return;
+      }
+    }
+    self.request_blocks(swarm, app, peer);
   }
-  unique_peers.iter().map(|p| p.to_string()).collect()
 }
 
 pub fn handle_print_peers(swarm: &Swarm<AppBehaviour>) {
-  let peers = get_list_peers(swarm);
-  peers.iter().for_each(|p| info!("{}", p));
-}
-
-pub fn handle_print_chain(swarm: &Swarm<AppBehaviour>) {
-  info!("Local Blockchain:");
-  let pretty_json =
-    serde_json::to_string_pretty(&swarm.behaviour().app.blocks).expect("can jsonify blocks");
-  info!("{}", pretty_json);
-}
-
-pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
-  if let Some(data) = cmd.strip_prefix("create b") {
-    let behaviour = swarm.behaviour_mut();
-    let latest_block = behaviour
-      .app
-      .blocks
-      .last()
-      .expect("there is at least one block");
-    let block = Block::new(
-      latest_block.id + 1,
-      latest_block.hash.clone(),
-      data.to_owned(),
+  info!("Connected Peers:");
+  swarm.connected_peers().for_each(|p| info!("{}", p));
+}
+
+pub fn handle_print_chain(app: &App) {
+  info!("Best chain (cumulative work {}):", app.chain.best_work());
+  for block in app.chain.best_chain() {
+    info!(
+      "#{} {} difficulty {} txs {} data {:?}",
+      block.header.height,
+      block.hash,
+      block.header.difficulty,
+      block.body.transactions.len(),
+      block.body.data
     );
-    let json = serde_json::to_string(&block).expect("can jsonify request");
-    behaviour.app.blocks.push(block);
-    info!("broadcasting new block");
-    behaviour
-      .floodsub
-      .publish(BLOCK_TOPIC.clone(), json.as_bytes());
+  }
+}
+
+pub fn handle_print_tips(app: &App) {
+  info!("Chain tips:");
+  let best = &app.chain.tip().hash;
+  for (block, work) in app.chain.tips() {
+    let marker = if block.hash == *best { " (best)" } else { "" };
+    info!(
+      "#{} {} cumulative work {work}{marker}",
+      block.header.height, block.hash
+    );
+  }
+}
+
+pub fn handle_print_mempool(app: &App) {
+  info!("Mempool ({} transaction(s)):", app.mempool.len());
+  for tx in app.mempool.iter() {
+    let t = &tx.transaction;
+    info!(
+      "{} -> {} amount {} fee {} nonce {}",
+      t.from, t.to, t.amount, t.fee, t.nonce
+    );
+  }
+}
+
+pub fn handle_print_balance(cmd: &str, app: &App) {
+  let address = cmd.strip_prefix("balance").unwrap_or_default().trim();
+  let address = if address.is_empty() {
+    app.address.as_str()
+  } else {
+    address
+  };
+  let account = app.chain.ledger().account(address);
+  info!(
+    "{address}: balance {} nonce {}",
+    account.balance, account.nonce
+  );
+}
+
+pub fn handle_send(cmd: &str, swarm: &mut Swarm<AppBehaviour>, app: &mut App) {
+  let mut args = cmd.split_whitespace().skip(1);
+  let (Some(to), Some(amount)) = (args.next(), args.next().and_then(|a| a.parse().ok())) else {
+    warn!("usage: send <address> <amount> [fee]");
+    return;
+  };
+  let fee = args.next().and_then(|fee| fee.parse().ok()).unwrap_or(1);
+  match app.create_transaction(to, amount, fee) {
+    Ok(tx) => {
+      info!("broadcasting transaction {}", tx.id());
+      let json = serde_json::to_vec(&tx).expect("can jsonify transaction");
+      publish(swarm, &TRANSACTION_TOPIC, json);
+    }
+    Err(err) => warn!("could not create transaction: {err}"),
   }
 }
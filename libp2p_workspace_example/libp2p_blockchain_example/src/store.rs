@@ -0,0 +1,103 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, BufRead, BufReader, Write},
+  path::Path,
+};
+
+use log::warn;
+
+use super::chain::Block;
+
+/// Every block the node accepted into its tree, one JSON object per line in
+/// the order they were accepted, so a parent always comes before its
+/// children. Side-chain blocks are kept too: they still count towards fork
+/// choice after a restart.
+pub struct BlockStore {
+  file: File,
+}
+
+impl BlockStore {
+  /// Opens the store at `path` and returns the blocks already in it. A torn
+  /// last line from a crash mid-write is cut off.
+  pub fn open(path: &Path) -> io::Result<(Self, Vec<Block>)> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .append(true)
+      .create(true)
+      .open(path)?;
+
+    let mut blocks = Vec::new();
+    let mut valid_len = 0u64;
+    let mut reader = BufReader::new(&mut file);
+    let mut line = String::new();
+    loop {
+      line.clear();
+      let read = reader.read_line(&mut line)?;
+      if read == 0 {
+        break;
+      }
+      match serde_json::from_str::<Block>(line.trim_end()) {
+        Ok(block) if line.ends_with('\n') => {
+          blocks.push(block);
+          valid_len += read as u64;
+        }
+        Ok(_) | Err(_) => {
+          warn!(
+            "dropping unreadable block data at byte {valid_len} of {}",
+            path.display()
+          );
+          break;
+        }
+      }
+    }
+    file.set_len(valid_len)?;
+
+    Ok((Self { file }, blocks))
+  }
+
+  pub fn append(&mut self, block: &Block) -> io::Result<()> {
+    let mut line = serde_json::to_vec(block)?;
+    line.push(b'\n');
+    self.file.write_all(&line)?;
+    self.file.sync_data()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+
+  #[test]
+  fn open_cuts_off_a_torn_last_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blocks.jsonl");
+    let genesis = Block::genesis();
+
+    let (mut store, blocks) = BlockStore::open(&path).unwrap();
+    assert!(blocks.is_empty());
+    store.append(&genesis).unwrap();
+    drop(store);
+    let intact = fs::metadata(&path).unwrap().len();
+
+    let mut torn = serde_json::to_vec(&genesis).unwrap();
+    torn.truncate(torn.len() / 2);
+    OpenOptions::new()
+      .append(true)
+      .open(&path)
+      .unwrap()
+      .write_all(&torn)
+      .unwrap();
+
+    let (mut store, blocks) = BlockStore::open(&path).unwrap();
+    assert_eq!(blocks, std::slice::from_ref(&genesis));
+    assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+
+    // New blocks start on a fresh line instead of gluing onto the torn one.
+    store.append(&genesis).unwrap();
+    drop(store);
+    let (_, blocks) = BlockStore::open(&path).unwrap();
+    assert_eq!(blocks.len(), 2);
+  }
+}
@@ -0,0 +1,250 @@
+//! Signed transfers and the account state they act on.
+//!
+//! An address is the `PeerId` of the key that owns it, so the node identity
+//! doubles as its wallet.
+
+use std::{collections::HashMap, fmt};
+
+use libp2p::{
+  identity::{Keypair, PublicKey, SigningError},
+  PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::chain::BlockBody;
+
+/// Coins credited to the miner of every block besides the genesis block, on
+/// top of the fees of the transactions it includes.
+pub const BLOCK_REWARD: u64 = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+  pub from: String,
+  pub to: String,
+  pub amount: u64,
+  pub fee: u64,
+  /// Must equal the number of transactions `from` has already sent, which
+  /// keeps a signed transaction from being replayed.
+  pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedTransaction {
+  pub transaction: Transaction,
+  /// Protobuf encoded public key of `transaction.from`, hex encoded.
+  pub public_key: String,
+  pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+  BadEncoding,
+  BadAddress(String),
+  WrongSender,
+  BadSignature,
+  StaleNonce { expected: u64, got: u64 },
+  FutureNonce { expected: u64, got: u64 },
+  InsufficientFunds { balance: u64, needed: u64 },
+  Overflow,
+  Underpriced,
+  MempoolFull,
+}
+
+impl fmt::Display for TransactionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::BadEncoding => write!(f, "public key or signature is not valid hex or protobuf"),
+      Self::BadAddress(address) => write!(f, "{address:?} is not a peer id"),
+      Self::WrongSender => write!(f, "public key does not match the sender address"),
+      Self::BadSignature => write!(f, "signature does not verify"),
+      Self::StaleNonce { expected, got } => {
+        write!(f, "nonce {got} was already used; next nonce is {expected}")
+      }
+      Self::FutureNonce { expected, got } => {
+        write!(f, "nonce {got} skips ahead; next nonce is {expected}")
+      }
+      Self::InsufficientFunds { balance, needed } => {
+        write!(f, "needs {needed} coins but the balance is {balance}")
+      }
+      Self::Overflow => write!(f, "amount overflows"),
+      Self::Underpriced => {
+        write!(
+          f,
+          "a pending transaction with the same nonce pays at least the same fee"
+        )
+      }
+      Self::MempoolFull => write!(f, "mempool is full of transactions paying a higher fee"),
+    }
+  }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl Transaction {
+  fn signing_bytes(&self) -> Vec<u8> {
+    serde_json::to_vec(self).expect("transaction serializes to json")
+  }
+}
+
+impl SignedTransaction {
+  pub fn sign(keypair: &Keypair, transaction: Transaction) -> Result<Self, SigningError> {
+    let signature = keypair.sign(&transaction.signing_bytes())?;
+    Ok(Self {
+      transaction,
+      public_key: hex::encode(keypair.public().encode_protobuf()),
+      signature: hex::encode(signature),
+    })
+  }
+
+  pub fn id(&self) -> String {
+    let json = serde_json::to_vec(self).expect("transaction serializes to json");
+    hex::encode(Sha256::digest(json))
+  }
+
+  /// Checks that the transaction is well formed and signed by its sender.
+  /// Whether the sender can afford it is up to the [`Ledger`].
+  pub fn verify(&self) -> Result<(), TransactionError> {
+    let tx = &self.transaction;
+    let from = parse_address(&tx.from)?;
+    parse_address(&tx.to)?;
+    tx.amount
+      .checked_add(tx.fee)
+      .ok_or(TransactionError::Overflow)?;
+
+    let public_key = hex::decode(&self.public_key)
+      .ok()
+      .and_then(|bytes| PublicKey::try_decode_protobuf(&bytes).ok())
+      .ok_or(TransactionError::BadEncoding)?;
+    if public_key.to_peer_id() != from {
+      return Err(TransactionError::WrongSender);
+    }
+    let signature = hex::decode(&self.signature).map_err(|_| TransactionError::BadEncoding)?;
+    if !public_key.verify(&tx.signing_bytes(), &signature) {
+      return Err(TransactionError::BadSignature);
+    }
+    Ok(())
+  }
+}
+
+pub fn parse_address(address: &str) -> Result<PeerId, TransactionError> {
+  address
+    .parse()
+    .map_err(|_| TransactionError::BadAddress(address.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Account {
+  pub balance: u64,
+  pub nonce: u64,
+}
+
+/// Balances and nonces after applying every block of a chain in order.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+  accounts: HashMap<String, Account>,
+}
+
+impl Ledger {
+  pub fn account(&self, address: &str) -> Account {
+    self.accounts.get(address).copied().unwrap_or_default()
+  }
+
+  /// Checks that `tx` can be applied right now, without applying it.
+  pub fn check(&self, tx: &Transaction) -> Result<(), TransactionError> {
+    let sender = self.account(&tx.from);
+    if tx.nonce < sender.nonce {
+      return Err(TransactionError::StaleNonce {
+        expected: sender.nonce,
+        got: tx.nonce,
+      });
+    }
+    if tx.nonce > sender.nonce {
+      return Err(TransactionError::FutureNonce {
+        expected: sender.nonce,
+        got: tx.nonce,
+      });
+    }
+    let needed = tx
+      .amount
+      .checked_add(tx.fee)
+      .ok_or(TransactionError::Overflow)?;
+    if sender.balance < needed {
+      return Err(TransactionError::InsufficientFunds {
+        balance: sender.balance,
+        needed,
+      });
+    }
+    Ok(())
+  }
+
+  /// Moves the coins of an already verified transaction and returns its fee.
+  pub fn apply_transaction(&mut self, tx: &Transaction) -> Result<u64, TransactionError> {
+    self.check(tx)?;
+    let sender = self.accounts.entry(tx.from.clone()).or_default();
+    sender.balance -= tx.amount + tx.fee;
+    sender.nonce += 1;
+    let receiver = self.accounts.entry(tx.to.clone()).or_default();
+    receiver.balance = receiver
+      .balance
+      .checked_add(tx.amount)
+      .ok_or(TransactionError::Overflow)?;
+    Ok(tx.fee)
+  }
+
+  /// Applies every transaction of a block plus the miner reward. On error the
+  /// ledger is left half applied, so callers work on a copy.
+  pub fn apply_block(&mut self, height: u64, body: &BlockBody) -> Result<(), TransactionError> {
+    let mut fees = 0u64;
+    for tx in &body.transactions {
+      let fee = self.apply_transaction(&tx.transaction)?;
+      fees = fees.checked_add(fee).ok_or(TransactionError::Overflow)?;
+    }
+    if height > 0 {
+      let miner = self.accounts.entry(body.miner.clone()).or_default();
+      miner.balance = miner
+        .balance
+        .checked_add(BLOCK_REWARD + fees)
+        .ok_or(TransactionError::Overflow)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn transfer(from: &Keypair, to: &Keypair) -> SignedTransaction {
+    let transaction = Transaction {
+      from: from.public().to_peer_id().to_string(),
+      to: to.public().to_peer_id().to_string(),
+      amount: 10,
+      fee: 1,
+      nonce: 0,
+    };
+    SignedTransaction::sign(from, transaction).unwrap()
+  }
+
+  #[test]
+  fn tampered_transactions_do_not_verify() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let tx = transfer(&alice, &bob);
+    assert_eq!(tx.verify(), Ok(()));
+
+    let mut raised = tx.clone();
+    raised.transaction.amount = 1_000;
+    assert_eq!(raised.verify(), Err(TransactionError::BadSignature));
+
+    let mut flipped = tx.clone();
+    let mut signature = hex::decode(&flipped.signature).unwrap();
+    signature[0] ^= 1;
+    flipped.signature = hex::encode(signature);
+    assert_eq!(flipped.verify(), Err(TransactionError::BadSignature));
+
+    // Signed by bob in alice's name.
+    let mut forged = transfer(&bob, &alice);
+    forged.transaction.from = tx.transaction.from.clone();
+    assert_eq!(forged.verify(), Err(TransactionError::WrongSender));
+  }
+}
@@ -1,30 +1,71 @@
-use std::{error::Error, time::Duration};
+mod persistent_store;
+mod signed_record;
+
+use std::{
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
 use futures::prelude::*;
 use libp2p::{
+  identity::Keypair,
   kad,
-  kad::{store::MemoryStore, Mode},
+  kad::{store::RecordStore, Mode},
   mdns, noise,
   swarm::{NetworkBehaviour, SwarmEvent},
-  tcp, yamux,
+  tcp, yamux, PeerId,
 };
+use persistent_store::{PersistentStore, StoreConfig};
 use tokio::{io, io::AsyncBufReadExt};
 use tracing_subscriber::EnvFilter;
 
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
   let _ = tracing_subscriber::fmt()
     .with_env_filter(EnvFilter::from_default_env())
     .try_init();
 
+  // Usage: [data-dir] [--signed]
+  let mut data_dir = PathBuf::from("kv-data");
+  let mut signed_records = false;
+  for arg in std::env::args().skip(1) {
+    match arg.as_str() {
+      "--signed" => signed_records = true,
+      dir => data_dir = PathBuf::from(dir),
+    }
+  }
+  fs::create_dir_all(&data_dir)?;
+
+  // A stable identity lets the node find its own records again after a
+  // restart, and is what signed records are checked against.
+  let keypair = load_or_create_identity(&data_dir)?;
+  let local_peer_id = keypair.public().to_peer_id();
+  let store = PersistentStore::open(
+    data_dir.join("records.redb"),
+    local_peer_id,
+    StoreConfig {
+      signed_records,
+      ..StoreConfig::default()
+    },
+  )?;
+  println!(
+    "Peer {local_peer_id} loaded {} record(s) from {} (signed records only: {signed_records})",
+    store.records().count(),
+    data_dir.display()
+  );
+
   // We create a custom network behaviour that combines Kademlia and mDNS.
   #[derive(NetworkBehaviour)]
   struct Behaviour {
-    kademlia: kad::Behaviour<MemoryStore>,
+    kademlia: kad::Behaviour<PersistentStore>,
     mdns: mdns::tokio::Behaviour,
   }
 
-  let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+  let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
     .with_tokio()
     .with_tcp(
       tcp::Config::default(),
@@ -32,11 +73,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
       yamux::Config::default,
     )?
     .with_behaviour(|key| {
+      // Let us see who sent a record before it is stored, so the store can
+      // charge it to the sender instead of the publisher the sender claims.
+      let mut kad_config = kad::Config::new(kad::PROTOCOL_NAME);
+      kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
       Ok(Behaviour {
-        kademlia: kad::Behaviour::new(
-          key.public().to_peer_id(),
-          MemoryStore::new(key.public().to_peer_id()),
-        ),
+        kademlia: kad::Behaviour::with_config(key.public().to_peer_id(), store, kad_config),
         mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?,
       })
     })?
@@ -51,10 +93,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
   // Listen on all interfaces and whatever port the OS assigns.
   swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
 
+  let mut republished = false;
+  let mut expiry_sweep = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+
   // Kick it off.
   loop {
     tokio::select! {
-        line = stdin.next_line() => handle_input_line(&mut swarm.behaviour_mut().kademlia, line.expect("Stdin not to close").expect("Stdin not to close")),
+        line = stdin.next_line() => handle_input_line(&mut swarm.behaviour_mut().kademlia, &keypair, line.expect("Stdin not to close").expect("Stdin not to close")),
+        _ = expiry_sweep.tick() => {
+            let removed = swarm.behaviour_mut().kademlia.store_mut().remove_expired();
+            if removed > 0 {
+                println!("Removed {removed} expired record(s)");
+            }
+        }
     event = swarm.select_next_some() => match event {
         SwarmEvent::NewListenAddr { address, .. } => {
             println!("Listening in {address:?}");
@@ -63,6 +114,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
             for (peer_id, multiaddr) in list {
                 swarm.behaviour_mut().kademlia.add_address(&peer_id, multiaddr);
             }
+            // Republish once there is someone to publish to.
+            if !republished {
+                republished = true;
+                republish(&mut swarm.behaviour_mut().kademlia, &local_peer_id);
+            }
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::InboundRequest { request })) => {
+            let store = swarm.behaviour_mut().kademlia.store_mut();
+            match request {
+                kad::InboundRequest::PutRecord { source, record: Some(record), .. } => {
+                    if let Err(reason) = store.put_from(source, record) {
+                        eprintln!("Rejected record from {source}: {reason}");
+                    }
+                }
+                kad::InboundRequest::AddProvider { record: Some(record) } => {
+                    if let Err(err) = store.add_provider(record) {
+                        eprintln!("Failed to store provider record: {err:?}");
+                    }
+                }
+                _ => {}
+            }
         }
         SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { result, ..})) => {
             match result {
@@ -78,17 +150,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     eprintln!("Failed to get providers: {err:?}");
                 }
                 kad::QueryResult::GetRecord(Ok(
-                    kad::GetRecordOk::FoundRecord(kad::PeerRecord {
-                        record: kad::Record { key, value, .. },
-                        ..
-                    })
-                )) => {
-                    println!(
-                        "Got record {:?} {:?}",
-                        std::str::from_utf8(key.as_ref()).unwrap(),
-                        std::str::from_utf8(&value).unwrap(),
-                    );
-                }
+                    kad::GetRecordOk::FoundRecord(kad::PeerRecord { record, .. })
+                )) => print_record(&record),
                 kad::QueryResult::GetRecord(Ok(_)) => {}
                 kad::QueryResult::GetRecord(Err(err)) => {
                     eprintln!("Failed to get record: {err:?}");
@@ -120,7 +183,67 @@ async fn main() -> Result<(), Box<dyn Error>> {
   }
 }
 
-fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>, line: String) {
+fn load_or_create_identity(data_dir: &Path) -> Result<Keypair, Box<dyn Error>> {
+  let path = data_dir.join("identity.key");
+  match fs::read(&path) {
+    Ok(bytes) => Ok(Keypair::from_protobuf_encoding(&bytes)?),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      let keypair = Keypair::generate_ed25519();
+      fs::write(&path, keypair.to_protobuf_encoding()?)?;
+      Ok(keypair)
+    }
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Pushes our own records and provider records loaded from disk back into the
+/// DHT, since other peers may have dropped them while we were away.
+fn republish(kademlia: &mut kad::Behaviour<PersistentStore>, local_peer_id: &PeerId) {
+  let records = kademlia.store_mut().published_by(local_peer_id);
+  let provided = kademlia
+    .store_mut()
+    .provided()
+    .map(|record| record.key.clone())
+    .collect::<Vec<_>>();
+  println!(
+    "Republishing {} record(s) and {} provider record(s)",
+    records.len(),
+    provided.len()
+  );
+
+  for record in records {
+    if let Err(err) = kademlia.put_record(record, kad::Quorum::One) {
+      eprintln!("Failed to republish record: {err:?}");
+    }
+  }
+  for key in provided {
+    if let Err(err) = kademlia.start_providing(key) {
+      eprintln!("Failed to republish provider record: {err:?}");
+    }
+  }
+}
+
+fn print_record(record: &kad::Record) {
+  let key = String::from_utf8_lossy(record.key.as_ref());
+  match signed_record::verify(record) {
+    Ok(signed) => println!(
+      "Got record {key:?} {:?} signed by its owner (sequence {})",
+      String::from_utf8_lossy(&signed.value),
+      signed.sequence
+    ),
+    Err(_) if signed_record::key_owner(&record.key).is_none() => println!(
+      "Got record {key:?} {:?}",
+      String::from_utf8_lossy(&record.value)
+    ),
+    Err(err) => eprintln!("Ignoring record {key:?}: {err}"),
+  }
+}
+
+fn handle_input_line(
+  kademlia: &mut kad::Behaviour<PersistentStore>,
+  keypair: &Keypair,
+  line: String,
+) {
   let mut args = line.split(' ');
 
   match args.next() {
@@ -173,9 +296,37 @@ fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>, line: String) {
         publisher: None,
         expires: None,
       };
-      kademlia
-        .put_record(record, kad::Quorum::One)
-        .expect("Failed to store record locally.");
+      if let Err(err) = kademlia.put_record(record, kad::Quorum::One) {
+        eprintln!("Failed to store record locally: {err:?}");
+      }
+    }
+    Some("PUT_SIGNED") => {
+      let Some(name) = args.next() else {
+        eprintln!("Expected name");
+        return;
+      };
+      let Some(value) = args.next() else {
+        eprintln!("Expected value");
+        return;
+      };
+      let key = signed_record::owned_key(&keypair.public().to_peer_id(), name);
+      let previous = kademlia
+        .store_mut()
+        .get(&key)
+        .and_then(|record| signed_record::verify(&record).ok())
+        .map(|signed| signed.sequence);
+      let sequence = signed_record::next_sequence(previous);
+      let value = match signed_record::sign_value(keypair, &key, sequence, value.as_bytes()) {
+        Ok(value) => value,
+        Err(err) => {
+          eprintln!("Failed to sign value: {err}");
+          return;
+        }
+      };
+      println!("Publishing {:?}", String::from_utf8_lossy(key.as_ref()));
+      if let Err(err) = kademlia.put_record(kad::Record::new(key, value), kad::Quorum::One) {
+        eprintln!("Failed to store record locally: {err:?}");
+      }
     }
     Some("PUT_PROVIDER") => {
       let key = {
@@ -193,7 +344,7 @@ fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>, line: String) {
         .expect("Failed to start providing key");
     }
     _ => {
-      eprintln!("expected GET, GET_PROVIDERS, PUT or PUT_PROVIDER");
+      eprintln!("expected GET, GET_PROVIDERS, PUT, PUT_SIGNED or PUT_PROVIDER");
     }
   }
 }
@@ -0,0 +1,698 @@
+//! A Kademlia `RecordStore` that survives restarts.
+//!
+//! Records and provider records are served from a `MemoryStore` and written
+//! through to redb. Opening the store loads everything that has not expired
+//! yet; expiry times are persisted as wall-clock time because kad's `Instant`s
+//! mean nothing to the next process.
+//!
+//! Quotas are charged to a peer we can trust: the connection a record arrived
+//! on, or in signed mode the owner of its key. `Record::publisher` is whatever
+//! the sender wrote into its PUT, so it is never used for accounting. Remote
+//! records come in through [`PersistentStore::put_from`], which needs kad to
+//! run with `StoreInserts::FilterBoth`.
+//!
+//! In signed mode a record only replaces a stored one with a higher sequence
+//! number. An exact copy of the stored record is still accepted, since that
+//! is what republishing and kad's replication send.
+
+use std::{
+  borrow::Cow,
+  collections::{HashMap, HashSet},
+  fmt,
+  path::Path,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{
+  kad::{
+    store::{self, MemoryStore, MemoryStoreConfig, RecordStore},
+    ProviderRecord, Record, RecordKey,
+  },
+  Multiaddr, PeerId,
+};
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+
+use crate::signed_record::{self, SignedRecordError};
+
+const RECORDS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("records");
+const PROVIDERS: TableDefinition<(&[u8], &[u8]), &[u8]> = TableDefinition::new("providers");
+
+#[derive(Debug, Clone)]
+pub struct StoreConfig {
+  /// Records one peer may have in this store.
+  pub max_records_per_peer: usize,
+  /// Total value bytes one peer may have in this store.
+  pub max_bytes_per_peer: usize,
+  /// Only accept records signed by the owner of their key, see
+  /// [`signed_record`].
+  pub signed_records: bool,
+  pub memory: MemoryStoreConfig,
+}
+
+impl Default for StoreConfig {
+  fn default() -> Self {
+    Self {
+      max_records_per_peer: 128,
+      max_bytes_per_peer: 1024 * 1024,
+      signed_records: false,
+      memory: MemoryStoreConfig::default(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+  Database(Box<redb::Error>),
+  Codec(serde_json::Error),
+  Corrupt(String),
+}
+
+impl fmt::Display for StoreError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Database(err) => write!(f, "record database error: {err}"),
+      Self::Codec(err) => write!(f, "record encoding error: {err}"),
+      Self::Corrupt(reason) => write!(f, "corrupt record database: {reason}"),
+    }
+  }
+}
+
+impl std::error::Error for StoreError {}
+
+impl<E: Into<redb::Error>> From<E> for StoreError {
+  fn from(err: E) -> Self {
+    Self::Database(Box::new(err.into()))
+  }
+}
+
+/// Why [`PersistentStore::put_from`] refused a record.
+#[derive(Debug)]
+pub enum Rejection {
+  Unsigned(SignedRecordError),
+  RecordQuota(PeerId),
+  ByteQuota(PeerId),
+  /// A signed record whose sequence does not beat the stored one.
+  Stale {
+    stored: u64,
+    received: u64,
+  },
+  Store(store::Error),
+}
+
+impl fmt::Display for Rejection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Unsigned(err) => write!(f, "record is not signed by its owner: {err}"),
+      Self::RecordQuota(peer) => write!(f, "record quota of {peer} exhausted"),
+      Self::ByteQuota(peer) => write!(f, "byte quota of {peer} exhausted"),
+      Self::Stale { stored, received } => write!(
+        f,
+        "sequence {received} is not newer than the stored sequence {stored}"
+      ),
+      Self::Store(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl std::error::Error for Rejection {}
+
+impl Rejection {
+  /// The closest match in kad's fixed error set, which has nothing for a bad
+  /// signature or a stale sequence.
+  fn into_store_error(self) -> store::Error {
+    match self {
+      Self::RecordQuota(_) => store::Error::MaxRecords,
+      Self::Unsigned(_) | Self::ByteQuota(_) | Self::Stale { .. } => store::Error::ValueTooLarge,
+      Self::Store(err) => err,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+  value: Vec<u8>,
+  publisher: Option<Vec<u8>>,
+  /// The peer whose quota the record counts against. Missing in databases
+  /// written before quotas were charged to the sender.
+  #[serde(default)]
+  charged_to: Option<Vec<u8>>,
+  expires_at_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+  addresses: Vec<Vec<u8>>,
+  expires_at_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Usage {
+  records: usize,
+  bytes: usize,
+}
+
+pub struct PersistentStore {
+  db: Database,
+  memory: MemoryStore,
+  config: StoreConfig,
+  local_id: PeerId,
+  usage: HashMap<PeerId, Usage>,
+  /// The peer each stored record is charged to.
+  charged_to: HashMap<RecordKey, PeerId>,
+  provider_keys: HashSet<RecordKey>,
+}
+
+impl PersistentStore {
+  pub fn open(
+    path: impl AsRef<Path>,
+    local_id: PeerId,
+    config: StoreConfig,
+  ) -> Result<Self, StoreError> {
+    let db = Database::create(path)?;
+    let txn = db.begin_write()?;
+    txn.open_table(RECORDS)?;
+    txn.open_table(PROVIDERS)?;
+    txn.commit()?;
+
+    let mut store = Self {
+      db,
+      memory: MemoryStore::with_config(local_id, config.memory.clone()),
+      config,
+      local_id,
+      usage: HashMap::new(),
+      charged_to: HashMap::new(),
+      provider_keys: HashSet::new(),
+    };
+    store.load()?;
+    Ok(store)
+  }
+
+  fn load(&mut self) -> Result<(), StoreError> {
+    let now = Instant::now();
+    let mut expired_records = Vec::new();
+    let mut expired_providers = Vec::new();
+
+    let txn = self.db.begin_read()?;
+    for entry in txn.open_table(RECORDS)?.iter()? {
+      let (key, value) = entry?;
+      let stored: StoredRecord =
+        serde_json::from_slice(value.value()).map_err(StoreError::Codec)?;
+      let Some(expires) = from_unix_ms(stored.expires_at_ms, now) else {
+        expired_records.push(key.value().to_vec());
+        continue;
+      };
+      let peer_id = |bytes: Option<Vec<u8>>| {
+        bytes
+          .map(|bytes| PeerId::from_bytes(&bytes))
+          .transpose()
+          .map_err(|err| StoreError::Corrupt(err.to_string()))
+      };
+      let publisher = peer_id(stored.publisher)?;
+      let account = peer_id(stored.charged_to)?
+        .or(publisher)
+        .unwrap_or(self.local_id);
+      let record = Record {
+        key: RecordKey::from(key.value().to_vec()),
+        value: stored.value,
+        publisher,
+        expires,
+      };
+      if self.memory.put(record.clone()).is_ok() {
+        self.charge(account, &record);
+      }
+    }
+    for entry in txn.open_table(PROVIDERS)?.iter()? {
+      let (id, value) = entry?;
+      let (key, provider) = id.value();
+      let stored: StoredProvider =
+        serde_json::from_slice(value.value()).map_err(StoreError::Codec)?;
+      let Some(expires) = from_unix_ms(stored.expires_at_ms, now) else {
+        expired_providers.push((key.to_vec(), provider.to_vec()));
+        continue;
+      };
+      let record = ProviderRecord {
+        key: RecordKey::from(key.to_vec()),
+        provider: PeerId::from_bytes(provider)
+          .map_err(|err| StoreError::Corrupt(err.to_string()))?,
+        expires,
+        addresses: stored
+          .addresses
+          .into_iter()
+          .filter_map(|bytes| Multiaddr::try_from(bytes).ok())
+          .collect(),
+      };
+      self.provider_keys.insert(record.key.clone());
+      let _ = self.memory.add_provider(record);
+    }
+    drop(txn);
+
+    if !expired_records.is_empty() || !expired_providers.is_empty() {
+      let txn = self.db.begin_write()?;
+      {
+        let mut records = txn.open_table(RECORDS)?;
+        for key in &expired_records {
+          records.remove(key.as_slice())?;
+        }
+        let mut providers = txn.open_table(PROVIDERS)?;
+        for (key, provider) in &expired_providers {
+          providers.remove((key.as_slice(), provider.as_slice()))?;
+        }
+      }
+      txn.commit()?;
+    }
+    Ok(())
+  }
+
+  /// Records `publisher` put into this store, e.g. to republish our own
+  /// records after a restart.
+  pub fn published_by(&self, publisher: &PeerId) -> Vec<Record> {
+    self
+      .memory
+      .records()
+      .filter(|record| record.publisher.as_ref() == Some(publisher))
+      .map(Cow::into_owned)
+      .collect()
+  }
+
+  /// Drops records and provider records whose expiry has passed. Kademlia only
+  /// notices an expired record when it is read or republished, so call this
+  /// periodically to keep the database from growing.
+  pub fn remove_expired(&mut self) -> usize {
+    let now = Instant::now();
+    let expired_records = self
+      .memory
+      .records()
+      .filter(|record| record.is_expired(now))
+      .map(|record| record.key.clone())
+      .collect::<Vec<_>>();
+    let expired_providers = self
+      .provider_keys
+      .iter()
+      .flat_map(|key| self.memory.providers(key))
+      .filter(|record| record.is_expired(now))
+      .map(|record| (record.key, record.provider))
+      .collect::<Vec<_>>();
+
+    let removed = expired_records.len() + expired_providers.len();
+    for key in expired_records {
+      self.remove(&key);
+    }
+    for (key, provider) in expired_providers {
+      self.remove_provider(&key, &provider);
+    }
+    removed
+  }
+
+  /// Stores a record a remote peer sent in a PUT. It counts against the
+  /// quota of `source`, the authenticated sender, or in signed mode against
+  /// the owner of its key.
+  pub fn put_from(&mut self, source: PeerId, record: Record) -> Result<(), Rejection> {
+    let account = self.account(source, &record)?;
+    self.insert(account, record)
+  }
+
+  fn account(&self, source: PeerId, record: &Record) -> Result<PeerId, Rejection> {
+    if !self.config.signed_records {
+      return Ok(source);
+    }
+    let signed = signed_record::verify(record).map_err(Rejection::Unsigned)?;
+    self.check_sequence(record, signed.sequence)?;
+    signed_record::key_owner(&record.key).ok_or(Rejection::Unsigned(SignedRecordError::UnownedKey))
+  }
+
+  fn check_sequence(&self, record: &Record, received: u64) -> Result<(), Rejection> {
+    let Some(previous) = self.memory.get(&record.key) else {
+      return Ok(());
+    };
+    // Left over from running unsigned; anything signed may replace it.
+    let Ok(stored) = signed_record::verify(&previous) else {
+      return Ok(());
+    };
+    let republished = received == stored.sequence && previous.value == record.value;
+    if received <= stored.sequence && !republished {
+      return Err(Rejection::Stale {
+        stored: stored.sequence,
+        received,
+      });
+    }
+    Ok(())
+  }
+
+  fn insert(&mut self, account: PeerId, record: Record) -> Result<(), Rejection> {
+    self.check_quota(account, &record)?;
+
+    let previous = self.memory.get(&record.key).map(Cow::into_owned);
+    self.memory.put(record.clone()).map_err(Rejection::Store)?;
+    if let Some(previous) = previous {
+      self.refund(&previous);
+    }
+    self.charge(account, &record);
+
+    let stored = StoredRecord {
+      value: record.value.clone(),
+      publisher: record.publisher.map(PeerId::to_bytes),
+      charged_to: Some(account.to_bytes()),
+      expires_at_ms: record.expires.map(to_unix_ms),
+    };
+    self.persist(|txn| {
+      let bytes = serde_json::to_vec(&stored).map_err(StoreError::Codec)?;
+      txn
+        .open_table(RECORDS)?
+        .insert(record.key.as_ref(), bytes.as_slice())?;
+      Ok(())
+    });
+    Ok(())
+  }
+
+  fn check_quota(&self, account: PeerId, record: &Record) -> Result<(), Rejection> {
+    let mut usage = self.usage.get(&account).copied().unwrap_or_default();
+    // Replacing one of the account's own records frees its old share first.
+    if let Some(previous) = self
+      .memory
+      .get(&record.key)
+      .filter(|_| self.charged_to.get(&record.key) == Some(&account))
+    {
+      usage.records -= 1;
+      usage.bytes -= previous.value.len();
+    }
+
+    if usage.records + 1 > self.config.max_records_per_peer {
+      return Err(Rejection::RecordQuota(account));
+    }
+    if usage.bytes + record.value.len() > self.config.max_bytes_per_peer {
+      return Err(Rejection::ByteQuota(account));
+    }
+    Ok(())
+  }
+
+  fn charge(&mut self, account: PeerId, record: &Record) {
+    let usage = self.usage.entry(account).or_default();
+    usage.records += 1;
+    usage.bytes += record.value.len();
+    self.charged_to.insert(record.key.clone(), account);
+  }
+
+  /// Takes a record that is being replaced or removed off its account.
+  fn refund(&mut self, previous: &Record) {
+    let Some(account) = self.charged_to.remove(&previous.key) else {
+      return;
+    };
+    let usage = self.usage.entry(account).or_default();
+    usage.records -= 1;
+    usage.bytes -= previous.value.len();
+  }
+
+  fn persist(&self, write: impl FnOnce(&redb::WriteTransaction) -> Result<(), StoreError>) {
+    let result = self
+      .db
+      .begin_write()
+      .map_err(StoreError::from)
+      .and_then(|txn| {
+        write(&txn)?;
+        txn.commit()?;
+        Ok(())
+      });
+    // `RecordStore` has no error for I/O failures; the record stays served
+    // from memory and is lost on restart.
+    if let Err(err) = result {
+      tracing::error!("failed to persist record store change: {err}");
+    }
+  }
+}
+
+impl RecordStore for PersistentStore {
+  type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+  type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+  fn get(&self, key: &RecordKey) -> Option<Cow<'_, Record>> {
+    self.memory.get(key)
+  }
+
+  /// Records put through kad directly are our own and count against the
+  /// local peer. A rejection is logged with its real reason before being
+  /// squeezed into kad's error set.
+  fn put(&mut self, record: Record) -> store::Result<()> {
+    let key = record.key.clone();
+    self
+      .account(self.local_id, &record)
+      .and_then(|account| self.insert(account, record))
+      .map_err(|rejection| {
+        tracing::warn!(?key, "rejected record: {rejection}");
+        rejection.into_store_error()
+      })
+  }
+
+  fn remove(&mut self, key: &RecordKey) {
+    let Some(previous) = self.memory.get(key).map(Cow::into_owned) else {
+      return;
+    };
+    self.memory.remove(key);
+    self.refund(&previous);
+
+    self.persist(|txn| {
+      txn.open_table(RECORDS)?.remove(key.as_ref())?;
+      Ok(())
+    });
+  }
+
+  fn records(&self) -> Self::RecordsIter<'_> {
+    self.memory.records()
+  }
+
+  fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+    self.memory.add_provider(record.clone())?;
+    self.provider_keys.insert(record.key.clone());
+
+    let stored = StoredProvider {
+      addresses: record.addresses.iter().map(|addr| addr.to_vec()).collect(),
+      expires_at_ms: record.expires.map(to_unix_ms),
+    };
+    let provider = record.provider.to_bytes();
+    self.persist(|txn| {
+      let bytes = serde_json::to_vec(&stored).map_err(StoreError::Codec)?;
+      txn
+        .open_table(PROVIDERS)?
+        .insert((record.key.as_ref(), provider.as_slice()), bytes.as_slice())?;
+      Ok(())
+    });
+    Ok(())
+  }
+
+  fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+    self.memory.providers(key)
+  }
+
+  fn provided(&self) -> Self::ProvidedIter<'_> {
+    self.memory.provided()
+  }
+
+  fn remove_provider(&mut self, key: &RecordKey, provider: &PeerId) {
+    self.memory.remove_provider(key, provider);
+    if self.memory.providers(key).is_empty() {
+      self.provider_keys.remove(key);
+    }
+
+    let provider = provider.to_bytes();
+    self.persist(|txn| {
+      txn
+        .open_table(PROVIDERS)?
+        .remove((key.as_ref(), provider.as_slice()))?;
+      Ok(())
+    });
+  }
+}
+
+fn to_unix_ms(expires: Instant) -> u64 {
+  let at = SystemTime::now() + expires.saturating_duration_since(Instant::now());
+  at.duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64
+}
+
+/// `Some(None)` for records that never expire, `None` for ones that already
+/// have.
+fn from_unix_ms(expires_at_ms: Option<u64>, now: Instant) -> Option<Option<Instant>> {
+  let Some(expires_at_ms) = expires_at_ms else {
+    return Some(None);
+  };
+  let remaining = (UNIX_EPOCH + Duration::from_millis(expires_at_ms))
+    .duration_since(SystemTime::now())
+    .ok()?;
+  Some(Some(now + remaining))
+}
+
+#[cfg(test)]
+mod tests {
+  use libp2p::identity::Keypair;
+  use redb::ReadableTableMetadata;
+
+  use super::*;
+
+  fn open(dir: &tempfile::TempDir, config: StoreConfig) -> PersistentStore {
+    PersistentStore::open(dir.path().join("records.redb"), PeerId::random(), config).unwrap()
+  }
+
+  fn record(key: &str, value: &[u8]) -> Record {
+    Record::new(RecordKey::new(&key), value.to_vec())
+  }
+
+  fn signed(keypair: &Keypair, name: &str, sequence: u64, value: &[u8]) -> Record {
+    let key = signed_record::owned_key(&keypair.public().to_peer_id(), name);
+    let value = signed_record::sign_value(keypair, &key, sequence, value).unwrap();
+    Record::new(key, value)
+  }
+
+  fn usage(store: &PersistentStore, peer: &PeerId) -> (usize, usize) {
+    let usage = store.usage.get(peer).copied().unwrap_or_default();
+    (usage.records, usage.bytes)
+  }
+
+  #[test]
+  fn reload_drops_expired_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let peer = PeerId::random();
+    let mut store = open(&dir, StoreConfig::default());
+    store.put_from(peer, record("kept", b"value")).unwrap();
+    let mut expiring = record("expiring", b"value");
+    expiring.expires = Some(Instant::now());
+    store.put_from(peer, expiring).unwrap();
+    drop(store);
+    std::thread::sleep(Duration::from_millis(10));
+
+    let store = open(&dir, StoreConfig::default());
+    assert!(store.get(&RecordKey::new(&"kept")).is_some());
+    assert!(store.get(&RecordKey::new(&"expiring")).is_none());
+    assert_eq!(usage(&store, &peer), (1, 5));
+    drop(store);
+
+    let txn = Database::open(dir.path().join("records.redb"))
+      .unwrap()
+      .begin_read()
+      .unwrap();
+    let records = txn.open_table(RECORDS).unwrap();
+    assert!(records.get(b"expiring".as_slice()).unwrap().is_none());
+    assert_eq!(records.len().unwrap(), 1);
+  }
+
+  #[test]
+  fn quotas_are_refunded_on_replace_and_remove() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(
+      &dir,
+      StoreConfig {
+        max_records_per_peer: 2,
+        max_bytes_per_peer: 10,
+        ..StoreConfig::default()
+      },
+    );
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+
+    store.put_from(alice, record("a", b"1234")).unwrap();
+    store.put_from(alice, record("b", b"1234")).unwrap();
+    assert!(matches!(
+      store.put_from(alice, record("c", b"1")),
+      Err(Rejection::RecordQuota(peer)) if peer == alice
+    ));
+
+    // Replacing frees the old value first, so this fits exactly.
+    store.put_from(alice, record("a", b"123456")).unwrap();
+    assert_eq!(usage(&store, &alice), (2, 10));
+    assert!(matches!(
+      store.put_from(alice, record("b", b"12345")),
+      Err(Rejection::ByteQuota(peer)) if peer == alice
+    ));
+
+    store.remove(&RecordKey::new(&"a"));
+    assert_eq!(usage(&store, &alice), (1, 4));
+    store.put_from(alice, record("c", b"123456")).unwrap();
+
+    // Another peer overwriting a record takes it off the old account.
+    store.put_from(bob, record("b", b"12")).unwrap();
+    assert_eq!(usage(&store, &alice), (1, 6));
+    assert_eq!(usage(&store, &bob), (1, 2));
+  }
+
+  #[test]
+  fn signed_mode_rejects_records_not_signed_by_the_key_owner() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(
+      &dir,
+      StoreConfig {
+        signed_records: true,
+        ..StoreConfig::default()
+      },
+    );
+    let (owner, other) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let owner_id = owner.public().to_peer_id();
+    let sender = PeerId::random();
+
+    let unsigned = Record::new(
+      signed_record::owned_key(&owner_id, "name"),
+      b"value".to_vec(),
+    );
+    assert!(matches!(
+      store.put_from(sender, unsigned),
+      Err(Rejection::Unsigned(SignedRecordError::Envelope(_)))
+    ));
+    assert!(matches!(
+      store.put_from(sender, record("plain", b"value")),
+      Err(Rejection::Unsigned(SignedRecordError::UnownedKey))
+    ));
+
+    let mut wrong_signer = signed(&other, "name", 1, b"value");
+    wrong_signer.key = signed_record::owned_key(&owner_id, "name");
+    assert!(matches!(
+      store.put_from(sender, wrong_signer),
+      Err(Rejection::Unsigned(SignedRecordError::WrongSigner { .. }))
+    ));
+
+    let mut wrong_key = signed(&owner, "name", 1, b"value");
+    wrong_key.key = signed_record::owned_key(&owner_id, "other");
+    assert!(matches!(
+      store.put_from(sender, wrong_key),
+      Err(Rejection::Unsigned(SignedRecordError::WrongKey))
+    ));
+
+    store
+      .put_from(sender, signed(&owner, "name", 1, b"value"))
+      .unwrap();
+    assert_eq!(usage(&store, &sender), (0, 0));
+    assert_eq!(usage(&store, &owner_id).0, 1);
+  }
+
+  #[test]
+  fn signed_records_only_move_forward() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(
+      &dir,
+      StoreConfig {
+        signed_records: true,
+        ..StoreConfig::default()
+      },
+    );
+    let owner = Keypair::generate_ed25519();
+    let sender = PeerId::random();
+    let current = signed(&owner, "name", 5, b"new");
+
+    store.put_from(sender, current.clone()).unwrap();
+    assert!(matches!(
+      store.put_from(sender, signed(&owner, "name", 4, b"old")),
+      Err(Rejection::Stale {
+        stored: 5,
+        received: 4
+      })
+    ));
+    assert!(matches!(
+      store.put_from(sender, signed(&owner, "name", 5, b"other")),
+      Err(Rejection::Stale { .. })
+    ));
+    store.put_from(sender, current.clone()).unwrap();
+
+    store
+      .put_from(sender, signed(&owner, "name", 6, b"newer"))
+      .unwrap();
+    let stored = store.get(&current.key).unwrap();
+    assert_eq!(signed_record::verify(&stored).unwrap().value, b"newer");
+  }
+}
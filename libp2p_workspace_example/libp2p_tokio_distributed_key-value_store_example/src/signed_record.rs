@@ -0,0 +1,145 @@
+//! Values signed by the peer that owns the key.
+//!
+//! A signed key has the form `<peer-id>/<name>`. Its value is a
+//! `SignedEnvelope` whose payload binds the key to the user value, so the
+//! envelope cannot be replayed under another key of the same owner. The
+//! payload also carries a sequence number that grows with every publish, so
+//! an old value of the same key cannot be replayed over a newer one.
+
+use std::{
+  fmt,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{
+  core::SignedEnvelope,
+  identity::{Keypair, SigningError},
+  kad, PeerId,
+};
+
+const DOMAIN: &str = "libp2p-kv-record";
+const PAYLOAD_TYPE: &[u8] = b"/kv-record/2";
+
+#[derive(Debug)]
+pub enum SignedRecordError {
+  /// The key is not `<peer-id>/<name>`.
+  UnownedKey,
+  Envelope(String),
+  WrongSigner {
+    owner: Box<PeerId>,
+    signer: Box<PeerId>,
+  },
+  WrongKey,
+}
+
+impl fmt::Display for SignedRecordError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnownedKey => write!(f, "key is not of the form <peer-id>/<name>"),
+      Self::Envelope(err) => write!(f, "value is not a valid signed envelope: {err}"),
+      Self::WrongSigner { owner, signer } => {
+        write!(
+          f,
+          "key belongs to {owner} but the value is signed by {signer}"
+        )
+      }
+      Self::WrongKey => write!(f, "value was signed for a different key"),
+    }
+  }
+}
+
+impl std::error::Error for SignedRecordError {}
+
+/// The user value of a verified record and the sequence it was signed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedValue {
+  pub sequence: u64,
+  pub value: Vec<u8>,
+}
+
+/// The key `name` gets when `owner` publishes it as a signed record.
+pub fn owned_key(owner: &PeerId, name: &str) -> kad::RecordKey {
+  kad::RecordKey::new(&format!("{owner}/{name}"))
+}
+
+/// The owner encoded in a signed key.
+pub fn key_owner(key: &kad::RecordKey) -> Option<PeerId> {
+  let key = std::str::from_utf8(key.as_ref()).ok()?;
+  let (owner, name) = key.split_once('/')?;
+  if name.is_empty() {
+    return None;
+  }
+  owner.parse().ok()
+}
+
+/// The sequence for the next publish of a key whose last known sequence is
+/// `previous`. Wall-clock milliseconds keep it growing across restarts that
+/// lost the old record; `previous + 1` covers clocks that went backwards.
+pub fn next_sequence(previous: Option<u64>) -> u64 {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as u64;
+  previous.map_or(now, |previous| now.max(previous + 1))
+}
+
+pub fn sign_value(
+  keypair: &Keypair,
+  key: &kad::RecordKey,
+  sequence: u64,
+  value: &[u8],
+) -> Result<Vec<u8>, SigningError> {
+  let envelope = SignedEnvelope::new(
+    keypair,
+    DOMAIN.to_string(),
+    PAYLOAD_TYPE.to_vec(),
+    payload(key, sequence, value),
+  )?;
+  Ok(envelope.into_protobuf_encoding())
+}
+
+/// Checks that `record` is signed by the owner of its key and returns the
+/// signed value.
+pub fn verify(record: &kad::Record) -> Result<SignedValue, SignedRecordError> {
+  let owner = key_owner(&record.key).ok_or(SignedRecordError::UnownedKey)?;
+  let envelope = SignedEnvelope::from_protobuf_encoding(&record.value)
+    .map_err(|err| SignedRecordError::Envelope(err.to_string()))?;
+  let (payload, signing_key) = envelope
+    .payload_and_signing_key(DOMAIN.to_string(), PAYLOAD_TYPE)
+    .map_err(|err| SignedRecordError::Envelope(err.to_string()))?;
+
+  let signer = signing_key.to_peer_id();
+  if signer != owner {
+    return Err(SignedRecordError::WrongSigner {
+      owner: Box::new(owner),
+      signer: Box::new(signer),
+    });
+  }
+
+  let key = record.key.as_ref();
+  let Some(rest) = payload
+    .strip_prefix(&(key.len() as u32).to_be_bytes()[..])
+    .and_then(|rest| rest.strip_prefix(key))
+  else {
+    return Err(SignedRecordError::WrongKey);
+  };
+  let Some((sequence, value)) = rest.split_first_chunk::<8>() else {
+    return Err(SignedRecordError::Envelope(
+      "payload has no sequence number".to_string(),
+    ));
+  };
+  Ok(SignedValue {
+    sequence: u64::from_be_bytes(*sequence),
+    value: value.to_vec(),
+  })
+}
+
+fn payload(key: &kad::RecordKey, sequence: u64, value: &[u8]) -> Vec<u8> {
+  let key = key.as_ref();
+  let mut payload = Vec::with_capacity(4 + key.len() + 8 + value.len());
+  payload.extend_from_slice(&(key.len() as u32).to_be_bytes());
+  payload.extend_from_slice(key);
+  payload.extend_from_slice(&sequence.to_be_bytes());
+  payload.extend_from_slice(value);
+  payload
+}
@@ -1,11 +1,10 @@
-use std::{borrow::Cow, net::SocketAddr, sync::Arc};
+use std::{borrow::Cow, net::SocketAddr, sync::Arc, time::Duration};
 
 use bincode::{
   config::standard,
   serde::{decode_from_slice, encode_to_vec},
 };
 use clap::Parser;
-use crossbeam_skiplist::SkipMap;
 use memberlist::{
   Options,
   agnostic::tokio::TokioRuntime,
@@ -22,11 +21,23 @@ use tokio::{
   sync::{mpsc::Sender, oneshot},
 };
 
+use self::store::{Digest, Entry, VersionedStore};
+
+mod store;
+
+/// How often tombstones past their TTL are removed.
+const TOMBSTONE_GC_INTERVAL: Duration = Duration::from_secs(30);
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
 
 struct Inner {
   meta: Meta,
-  store: SkipMap<Bytes, Bytes>,
+  /// The memberlist address of this node, peers send their entries there.
+  addr: SocketAddr,
+  store: VersionedStore,
+  /// Sync messages are applied by the event loop, the only writer of the
+  /// store.
+  events: Sender<Event>,
 }
 
 #[derive(Clone)]
@@ -39,15 +50,15 @@ impl NodeDelegate for MemDb {
     self.inner.meta.clone()
   }
 
+  /// Only the digest of the store is pushed and pulled, the entries of
+  /// differing buckets follow as user messages.
   async fn local_state(&self, _: bool) -> Bytes {
-    let all_data = self
-      .inner
-      .store
-      .iter()
-      .map(|ent| (ent.key().clone(), ent.value().clone()))
-      .collect::<Vec<_>>();
-
-    match encode_to_vec(&all_data, standard()) {
+    let msg = SyncMessage::Digest {
+      from: self.inner.addr,
+      digest: self.inner.store.digest(),
+    };
+
+    match encode_to_vec(&msg, standard()) {
       Ok(data) => Bytes::from(data),
       Err(e) => {
         tracing::error!(err=%e, "toydb: fail to encode local state");
@@ -57,12 +68,8 @@ impl NodeDelegate for MemDb {
   }
 
   async fn merge_remote_state(&self, buf: &[u8], _: bool) {
-    match decode_from_slice::<Vec<(Bytes, Bytes)>, _>(buf, standard()) {
-      Ok((pairs, _)) => {
-        for (key, value) in pairs {
-          self.inner.store.get_or_insert(key, value);
-        }
-      }
+    match decode_from_slice::<SyncMessage, _>(buf, standard()) {
+      Ok((msg, _)) => self.forward(msg).await,
       Err(e) => {
         tracing::error!(err=%e, "toydb: fail to decode remote state");
       }
@@ -70,12 +77,8 @@ impl NodeDelegate for MemDb {
   }
 
   async fn notify_message(&self, msg: Cow<'_, [u8]>) {
-    match decode_from_slice::<Vec<(Bytes, Bytes)>, _>(msg.as_ref(), standard()) {
-      Ok((pairs, _)) => {
-        for (key, value) in pairs {
-          self.inner.store.get_or_insert(key, value);
-        }
-      }
+    match decode_from_slice::<SyncMessage, _>(msg.as_ref(), standard()) {
+      Ok((msg, _)) => self.forward(msg).await,
       Err(e) => {
         tracing::error!(err=%e, "toydb: fail to decode remote message");
       }
@@ -83,6 +86,30 @@ impl NodeDelegate for MemDb {
   }
 }
 
+impl MemDb {
+  async fn forward(&self, msg: SyncMessage) {
+    if let Err(e) = self.inner.events.send(Event::Sync(msg)).await {
+      tracing::error!(err=%e, "toydb: fail to send sync event");
+    }
+  }
+}
+
+/// Anti-entropy exchange between two nodes. Push/pull is symmetric, both
+/// sides receive the digest of the other and send back only the entries of
+/// the buckets that differ, so both end up with the newest version of every
+/// key in those buckets.
+#[derive(serde::Serialize, serde::Deserialize)]
+enum SyncMessage {
+  Digest {
+    from: SocketAddr,
+    digest: Digest,
+  },
+  Entries {
+    from: SocketAddr,
+    entries: Vec<(Bytes, Entry)>,
+  },
+}
+
 struct ToyDb {
   tx: Sender<Event>,
 }
@@ -90,28 +117,41 @@ struct ToyDb {
 impl ToyDb {
   async fn new(
     meta: Meta,
+    addr: SocketAddr,
+    node: String,
+    tombstone_ttl: Duration,
     opts: Options,
     net_opts: NetTransportOptions<NodeId, DnsResolver<TokioRuntime>, Tcp<TokioRuntime>>,
   ) -> Result<Self> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+
     let memdb = MemDb {
       inner: Inner {
         meta,
-        store: SkipMap::new(),
+        addr,
+        store: VersionedStore::new(node),
+        events: tx.clone(),
       }
       .into(),
     };
 
-    let (tx, mut rx) = tokio::sync::mpsc::channel(100);
-
     let memdb1 = memdb.clone();
     let delegate = CompositeDelegate::<NodeId, SocketAddr>::default().with_node_delegate(memdb);
     let memberlist = TokioTcpMemberlist::with_delegate(delegate, net_opts, opts).await?;
     tokio::spawn(async move {
+      let store = &memdb1.inner.store;
+      let mut gc = tokio::time::interval(TOMBSTONE_GC_INTERVAL);
       loop {
         tokio::select! {
           _ = tokio::signal::ctrl_c() => {
             tracing::info!("toydb: shutting down db event listener");
           }
+          _ = gc.tick() => {
+            let removed = store.collect_garbage(tombstone_ttl);
+            if removed > 0 {
+              tracing::info!(removed, "toydb: collected expired tombstones");
+            }
+          }
           ev = rx.recv() => {
             if let Some(ev) = ev {
               match ev {
@@ -120,16 +160,48 @@ impl ToyDb {
                   let _ = tx.send(res.map_err(Into::into).map(|_| ()));
                 }
                 Event::Get { key, tx } => {
-                  let value = memdb1.inner.store.get(&key).map(|ent| ent.value().clone());
-                  let _ = tx.send(value);
+                  let _ = tx.send(store.get(&key));
                 }
                 Event::Set { key, value, tx } => {
-                  if memdb1.inner.store.get(&key).is_some() {
-                    let _ = tx.send(Err("key already exists".into()));
-                  } else {
-                    memdb1.inner.store.insert(key, value);
+                  let res = store.set_if_absent(key, value);
+                  if let Ok(version) = &res {
+                    tracing::debug!(%version, "toydb: set key");
+                  }
+                  let _ = tx.send(res.map(|_| ()).map_err(Into::into));
+                }
+                Event::Delete { key, tx } => {
+                  if store.delete(key) {
                     let _ = tx.send(Ok(()));
+                  } else {
+                    let _ = tx.send(Err("key not found".into()));
+                  }
+                }
+                Event::Sync(SyncMessage::Digest { from, digest }) => {
+                  let buckets = store.differing_buckets(&digest);
+                  if buckets.is_empty() {
+                    continue;
+                  }
+                  let entries = store.entries_in(&buckets);
+                  tracing::debug!(peer=%from, buckets=buckets.len(), entries=entries.len(), "toydb: sync differing buckets");
+                  let msg = SyncMessage::Entries { from: memdb1.inner.addr, entries };
+                  match encode_to_vec(&msg, standard()) {
+                    Ok(data) => {
+                      if let Err(e) = memberlist.send_reliable(&from, Bytes::from(data)).await {
+                        tracing::error!(err=%e, peer=%from, "toydb: fail to send entries");
+                      }
+                    }
+                    Err(e) => {
+                      tracing::error!(err=%e, "toydb: fail to encode entries");
+                    }
+                  }
+                }
+                Event::Sync(SyncMessage::Entries { from, entries }) => {
+                  let received = entries.len();
+                  let mut applied = 0;
+                  for (key, entry) in entries {
+                    applied += usize::from(store.merge(key, entry, tombstone_ttl));
                   }
+                  tracing::debug!(peer=%from, received, applied, "toydb: merged remote entries");
                 }
               }
             }
@@ -267,6 +339,31 @@ impl ToyDb {
     }
     Ok(())
   }
+
+  async fn handle_delete<W: tokio::io::AsyncWrite + Unpin>(
+    &self,
+    key: Bytes,
+    stream: &mut W,
+  ) -> Result<()> {
+    let (tx, rx) = oneshot::channel();
+    self.tx.send(Event::Delete { key, tx }).await?;
+
+    let res = rx.await?.map_err(|e| e.to_string());
+    match bincode::serde::encode_to_vec(&res, bincode::config::standard()) {
+      Ok(resp) => {
+        let mut prefixed_data = vec![0; resp.len() + 4];
+        prefixed_data[.. 4].copy_from_slice(&(resp.len() as u32).to_le_bytes());
+        prefixed_data[4 ..].copy_from_slice(&resp);
+        if let Err(e) = stream.write_all(&prefixed_data).await {
+          tracing::error!(err=%e, "toydb: fail to write rpc response");
+        }
+      }
+      Err(e) => {
+        tracing::error!(err=%e, "toydb: fail to encode rpc response");
+      }
+    }
+    Ok(())
+  }
 }
 
 #[derive(clap::Args)]
@@ -283,6 +380,10 @@ struct StartArgs {
   /// The rpc address to listen on commands
   #[clap(short, long)]
   rpc_addr: std::path::PathBuf,
+  /// Seconds a deleted key is remembered, it must be longer than any node
+  /// stays unreachable or the deleted value can come back
+  #[clap(long, default_value_t = 3600)]
+  tombstone_ttl: u64,
 }
 
 #[derive(clap::Subcommand)]
@@ -314,6 +415,13 @@ enum Commands {
     #[clap(short, long)]
     rpc_addr: std::path::PathBuf,
   },
+  /// Delete a key from the toydb
+  Del {
+    #[clap(short, long)]
+    key: String,
+    #[clap(short, long)]
+    rpc_addr: std::path::PathBuf,
+  },
 }
 
 #[derive(clap::Parser)]
@@ -328,6 +436,7 @@ struct Cli {
 enum Op {
   Get(Bytes),
   Set(Bytes, Bytes),
+  Del(Bytes),
   Join { addr: SocketAddr, id: NodeId },
 }
 
@@ -341,6 +450,11 @@ enum Event {
     value: Bytes,
     tx: oneshot::Sender<Result<()>>,
   },
+  Delete {
+    key: Bytes,
+    tx: oneshot::Sender<Result<()>>,
+  },
+  Sync(SyncMessage),
   Join {
     addr: SocketAddr,
     id: NodeId,
@@ -378,6 +492,9 @@ async fn main() -> Result<()> {
     } => {
       handle_set_cmd(key, value, rpc_addr).await?;
     }
+    Commands::Del { key, rpc_addr } => {
+      handle_del_cmd(key, rpc_addr).await?;
+    }
     Commands::Start(args) => {
       handle_start_cmd(args).await?;
     }
@@ -485,12 +602,53 @@ async fn handle_set_cmd(key: String, value: String, rpc_addr: std::path::PathBuf
   Ok(())
 }
 
+async fn handle_del_cmd(key: String, rpc_addr: std::path::PathBuf) -> Result<()> {
+  let conn = UnixStream::connect(rpc_addr).await?;
+  let data = encode_to_vec(Op::Del(key.into_bytes().into()), standard())?;
+
+  let (reader, mut writer) = conn.into_split();
+
+  let mut prefixed_data = vec![0; data.len() + 4];
+  prefixed_data[.. 4].copy_from_slice(&(data.len() as u32).to_le_bytes());
+  prefixed_data[4 ..].copy_from_slice(&data);
+
+  writer.write_all(&prefixed_data).await?;
+  writer.shutdown().await?;
+
+  let mut reader = tokio::io::BufReader::new(reader);
+  let mut len_buf = [0; 4];
+  reader.read_exact(&mut len_buf).await?;
+  let len = u32::from_le_bytes(len_buf) as usize;
+
+  let mut buf = vec![0; len];
+  reader.read_exact(&mut buf).await?;
+  let (res, _) = decode_from_slice::<std::result::Result<(), String>, _>(&buf, standard())?;
+  match res {
+    Ok(_) => {
+      println!("delete successfully");
+    }
+    Err(e) => {
+      println!("fail to delete {e}")
+    }
+  }
+  Ok(())
+}
+
 async fn handle_start_cmd(args: StartArgs) -> Result<()> {
   let opts = Options::local();
+  let node = args.id.to_string();
   let net_opts = NetTransportOptions::new(args.id)
     .with_bind_addresses([HostAddr::from(args.addr)].into_iter().collect());
 
-  let db = ToyDb::new(args.meta, opts, net_opts).await?;
+  let db = ToyDb::new(
+    args.meta,
+    args.addr,
+    node,
+    Duration::from_secs(args.tombstone_ttl),
+    opts,
+    net_opts,
+  )
+  .await?;
 
   struct Guard {
     sock: std::path::PathBuf,
@@ -545,6 +703,9 @@ async fn handle_start_cmd(args: StartArgs) -> Result<()> {
           Op::Set(key, value) => {
             db.handle_insert(key, value, &mut stream).await?;
           },
+          Op::Del(key) => {
+            db.handle_delete(key, &mut stream).await?;
+          },
         }
 
         if let Err(e) = stream.into_inner().shutdown().await {
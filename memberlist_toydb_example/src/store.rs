@@ -0,0 +1,465 @@
+//! Versioned key-value store that converges across nodes.
+//!
+//! Every write carries a hybrid logical clock [`Version`], the highest
+//! version of a key wins and deletes leave a tombstone so they win over older
+//! writes too. Keys are hashed into [`BUCKETS`] buckets, each summarized by
+//! a hash that is kept up to date on every write. Those bucket hashes are the
+//! leaves of a Merkle tree, so two nodes compare roots first and then only
+//! exchange the entries of buckets whose hashes differ.
+
+use std::{
+  fmt,
+  sync::{Mutex, MutexGuard, PoisonError},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_skiplist::SkipMap;
+use memberlist::bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// Number of hash buckets, a power of two so the tree is complete.
+pub const BUCKETS: usize = 64;
+/// Versions further ahead of the local clock than this are rejected, so a
+/// node with a broken clock cannot win every future write.
+const MAX_CLOCK_DRIFT: Duration = Duration::from_secs(60);
+
+pub type Hash = [u8; 32];
+
+/// Hybrid logical clock timestamp, ordered by wall time, then logical
+/// counter, then node id to break ties between nodes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Version {
+  pub wall_ms: u64,
+  pub logical: u32,
+  pub node: String,
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}@{}", self.wall_ms, self.logical, self.node)
+  }
+}
+
+/// A value or, if `value` is `None`, a tombstone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+  pub version: Version,
+  pub value: Option<Bytes>,
+}
+
+impl Entry {
+  fn hash(&self, key: &[u8]) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(&self.version.wall_ms.to_le_bytes());
+    hasher.update(&self.version.logical.to_le_bytes());
+    hasher.update(self.version.node.as_bytes());
+    hasher.update(&[u8::from(self.value.is_some())]);
+    *hasher.finalize().as_bytes()
+  }
+}
+
+/// Root and leaves of the Merkle tree over the buckets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Digest {
+  pub root: Hash,
+  pub leaves: Vec<Hash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+  KeyExists,
+}
+
+impl fmt::Display for StoreError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::KeyExists => write!(f, "key already exists"),
+    }
+  }
+}
+
+impl std::error::Error for StoreError {}
+
+struct HybridClock {
+  node: String,
+  /// Last issued (wall ms, logical) pair.
+  last: Mutex<(u64, u32)>,
+}
+
+impl HybridClock {
+  fn now(&self) -> Version {
+    let mut last = lock(&self.last);
+    let wall_ms = now_ms();
+    *last = if wall_ms > last.0 {
+      (wall_ms, 0)
+    } else {
+      (last.0, last.1 + 1)
+    };
+    Version {
+      wall_ms: last.0,
+      logical: last.1,
+      node: self.node.clone(),
+    }
+  }
+
+  /// Moves the clock past a version seen from another node, so the next
+  /// local write is ordered after it.
+  fn observe(&self, remote: &Version) {
+    let mut last = lock(&self.last);
+    if (remote.wall_ms, remote.logical) > *last {
+      *last = (remote.wall_ms, remote.logical);
+    }
+  }
+}
+
+pub struct VersionedStore {
+  clock: HybridClock,
+  entries: SkipMap<Bytes, Entry>,
+  /// XOR of the hashes of the entries in each bucket, so a write updates
+  /// its leaf in constant time.
+  leaves: Mutex<[Hash; BUCKETS]>,
+}
+
+impl VersionedStore {
+  pub fn new(node: impl Into<String>) -> Self {
+    Self {
+      clock: HybridClock {
+        node: node.into(),
+        last: Mutex::new((0, 0)),
+      },
+      entries: SkipMap::new(),
+      leaves: Mutex::new([[0; 32]; BUCKETS]),
+    }
+  }
+
+  /// The live value of `key`, `None` if it is missing or deleted.
+  pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+    self
+      .entries
+      .get(key)
+      .and_then(|entry| entry.value().value.clone())
+  }
+
+  /// Writes `value` unless `key` has a live value. A deleted key can be set
+  /// again.
+  pub fn set_if_absent(&self, key: Bytes, value: Bytes) -> Result<Version, StoreError> {
+    if self.get(&key).is_some() {
+      return Err(StoreError::KeyExists);
+    }
+    let version = self.clock.now();
+    self.put(
+      key,
+      Entry {
+        version: version.clone(),
+        value: Some(value),
+      },
+    );
+    Ok(version)
+  }
+
+  /// Replaces the value of `key` with a tombstone. Returns `false` if there
+  /// was no live value.
+  pub fn delete(&self, key: Bytes) -> bool {
+    if self.get(&key).is_none() {
+      return false;
+    }
+    let version = self.clock.now();
+    self.put(
+      key,
+      Entry {
+        version,
+        value: None,
+      },
+    );
+    true
+  }
+
+  /// Applies an entry from another node if it is newer than the local one.
+  /// Tombstones older than `tombstone_ttl` are dropped, they would only be
+  /// collected again.
+  pub fn merge(&self, key: Bytes, entry: Entry, tombstone_ttl: Duration) -> bool {
+    let now = now_ms();
+    if entry.version.wall_ms > now.saturating_add(MAX_CLOCK_DRIFT.as_millis() as u64) {
+      tracing::warn!(version=%entry.version, "toydb: rejecting entry from the future");
+      return false;
+    }
+    if entry.value.is_none() && is_expired(&entry.version, now, tombstone_ttl) {
+      return false;
+    }
+    if let Some(local) = self.entries.get(&key)
+      && local.value().version >= entry.version
+    {
+      return false;
+    }
+
+    self.clock.observe(&entry.version);
+    self.put(key, entry);
+    true
+  }
+
+  /// Callers are the single writer task, so nothing changes the key between
+  /// reading the old entry and inserting the new one.
+  fn put(&self, key: Bytes, entry: Entry) {
+    let bucket = bucket_of(&key);
+    let mut leaves = lock(&self.leaves);
+    if let Some(old) = self.entries.get(&key) {
+      xor_into(&mut leaves[bucket], &old.value().hash(&key));
+    }
+    xor_into(&mut leaves[bucket], &entry.hash(&key));
+    self.entries.insert(key, entry);
+  }
+
+  pub fn digest(&self) -> Digest {
+    let leaves = lock(&self.leaves).to_vec();
+    Digest {
+      root: merkle_root(&leaves),
+      leaves,
+    }
+  }
+
+  /// Buckets whose entries differ from the node that sent `remote`.
+  pub fn differing_buckets(&self, remote: &Digest) -> Vec<u16> {
+    let local = self.digest();
+    if local.root == remote.root || remote.leaves.len() != BUCKETS {
+      return Vec::new();
+    }
+    (0 .. BUCKETS)
+      .filter(|&bucket| local.leaves[bucket] != remote.leaves[bucket])
+      .map(|bucket| bucket as u16)
+      .collect()
+  }
+
+  /// Entries, tombstones included, of the given buckets.
+  pub fn entries_in(&self, buckets: &[u16]) -> Vec<(Bytes, Entry)> {
+    let mut wanted = [false; BUCKETS];
+    for &bucket in buckets {
+      if let Some(slot) = wanted.get_mut(bucket as usize) {
+        *slot = true;
+      }
+    }
+    self
+      .entries
+      .iter()
+      .filter(|entry| wanted[bucket_of(entry.key())])
+      .map(|entry| (entry.key().clone(), entry.value().clone()))
+      .collect()
+  }
+
+  /// Removes tombstones older than `ttl` and returns how many went. A node
+  /// that was away for longer than `ttl` can bring a deleted value back.
+  pub fn collect_garbage(&self, ttl: Duration) -> usize {
+    let now = now_ms();
+    let expired = self
+      .entries
+      .iter()
+      .filter(|entry| entry.value().value.is_none() && is_expired(&entry.value().version, now, ttl))
+      .map(|entry| entry.key().clone())
+      .collect::<Vec<_>>();
+
+    let mut leaves = lock(&self.leaves);
+    for key in &expired {
+      if let Some(old) = self.entries.remove(key) {
+        xor_into(&mut leaves[bucket_of(key)], &old.value().hash(key));
+      }
+    }
+    expired.len()
+  }
+}
+
+fn bucket_of(key: &[u8]) -> usize {
+  blake3::hash(key).as_bytes()[0] as usize % BUCKETS
+}
+
+fn xor_into(target: &mut Hash, hash: &Hash) {
+  for (target, byte) in target.iter_mut().zip(hash) {
+    *target ^= byte;
+  }
+}
+
+fn merkle_root(leaves: &[Hash]) -> Hash {
+  let mut level = leaves.to_vec();
+  while level.len() > 1 {
+    level = level
+      .chunks(2)
+      .map(|pair| {
+        let mut hasher = blake3::Hasher::new();
+        for hash in pair {
+          hasher.update(hash);
+        }
+        *hasher.finalize().as_bytes()
+      })
+      .collect();
+  }
+  level.first().copied().unwrap_or_default()
+}
+
+fn is_expired(version: &Version, now_ms: u64, ttl: Duration) -> bool {
+  version.wall_ms.saturating_add(ttl.as_millis() as u64) < now_ms
+}
+
+fn now_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TTL: Duration = Duration::from_secs(60 * 60);
+
+  fn version(wall_ms: u64, node: &str) -> Version {
+    Version {
+      wall_ms,
+      logical: 0,
+      node: node.to_string(),
+    }
+  }
+
+  fn write(wall_ms: u64, node: &str, value: &'static str) -> Entry {
+    Entry {
+      version: version(wall_ms, node),
+      value: Some(Bytes::from_static(value.as_bytes())),
+    }
+  }
+
+  fn tombstone(wall_ms: u64, node: &str) -> Entry {
+    Entry {
+      version: version(wall_ms, node),
+      value: None,
+    }
+  }
+
+  /// One anti-entropy round: `to` pulls the buckets that differ from `from`.
+  fn sync(from: &VersionedStore, to: &VersionedStore) {
+    for (key, entry) in from.entries_in(&to.differing_buckets(&from.digest())) {
+      to.merge(key, entry, TTL);
+    }
+  }
+
+  #[test]
+  fn concurrent_writes_converge_in_both_directions() {
+    let a = VersionedStore::new("a");
+    let b = VersionedStore::new("b");
+    let key = Bytes::from_static(b"key");
+    let from_a = a
+      .set_if_absent(key.clone(), Bytes::from_static(b"from a"))
+      .unwrap();
+    let from_b = b
+      .set_if_absent(key.clone(), Bytes::from_static(b"from b"))
+      .unwrap();
+    a.set_if_absent(Bytes::from_static(b"only a"), Bytes::from_static(b"1"))
+      .unwrap();
+    b.set_if_absent(Bytes::from_static(b"only b"), Bytes::from_static(b"2"))
+      .unwrap();
+
+    sync(&a, &b);
+    sync(&b, &a);
+
+    let winner = if from_a > from_b { "from a" } else { "from b" };
+    assert_eq!(a.get(&key).as_deref(), Some(winner.as_bytes()));
+    assert_eq!(b.get(&key).as_deref(), Some(winner.as_bytes()));
+    assert!(a.get(b"only b").is_some());
+    assert!(b.get(b"only a").is_some());
+    assert_eq!(a.digest(), b.digest());
+    assert!(a.differing_buckets(&b.digest()).is_empty());
+  }
+
+  #[test]
+  fn tombstone_beats_older_write() {
+    let store = VersionedStore::new("a");
+    let key = Bytes::from_static(b"key");
+    let now = now_ms();
+
+    assert!(store.merge(key.clone(), write(now - 2_000, "a", "old"), TTL));
+    assert!(store.merge(key.clone(), tombstone(now - 1_000, "b"), TTL));
+    assert_eq!(store.get(&key), None);
+
+    // The older write arriving late from another node does not resurrect it
+    assert!(!store.merge(key.clone(), write(now - 1_500, "c", "late"), TTL));
+    assert_eq!(store.get(&key), None);
+  }
+
+  #[test]
+  fn entries_from_the_future_are_rejected() {
+    let store = VersionedStore::new("a");
+    let key = Bytes::from_static(b"key");
+    let drift = MAX_CLOCK_DRIFT.as_millis() as u64;
+
+    assert!(!store.merge(
+      key.clone(),
+      write(now_ms() + drift + 60_000, "b", "future"),
+      TTL
+    ));
+    assert_eq!(store.get(&key), None);
+
+    assert!(store.merge(key.clone(), write(now_ms() + drift / 2, "b", "skewed"), TTL));
+    assert_eq!(store.get(&key).as_deref(), Some(&b"skewed"[..]));
+  }
+
+  #[test]
+  fn garbage_collection_drops_only_expired_tombstones() {
+    let store = VersionedStore::new("a");
+    let live_only = VersionedStore::new("b");
+    let all_buckets = (0 .. BUCKETS as u16).collect::<Vec<_>>();
+    let now = now_ms();
+    store.merge(
+      Bytes::from_static(b"deleted"),
+      tombstone(now - 10_000, "a"),
+      TTL,
+    );
+    store.merge(
+      Bytes::from_static(b"live"),
+      write(now - 10_000, "a", "value"),
+      TTL,
+    );
+    live_only.merge(
+      Bytes::from_static(b"live"),
+      write(now - 10_000, "a", "value"),
+      TTL,
+    );
+
+    assert_eq!(store.collect_garbage(TTL), 0);
+    assert_eq!(store.entries_in(&all_buckets).len(), 2);
+
+    assert_eq!(store.collect_garbage(Duration::from_secs(5)), 1);
+    assert_eq!(store.entries_in(&all_buckets).len(), 1);
+    assert!(store.get(b"live").is_some());
+    // The bucket hashes forget the tombstone too
+    assert_eq!(store.digest(), live_only.digest());
+  }
+
+  #[test]
+  fn differing_buckets_lists_only_changed_buckets() {
+    let a = VersionedStore::new("a");
+    let b = VersionedStore::new("b");
+    let now = now_ms();
+    for i in 0 .. 32u8 {
+      let key = Bytes::from(vec![i]);
+      a.merge(key.clone(), write(now - 1_000, "a", "same"), TTL);
+      b.merge(key, write(now - 1_000, "a", "same"), TTL);
+    }
+    assert!(a.differing_buckets(&b.digest()).is_empty());
+
+    let changed = Bytes::from_static(b"changed");
+    b.merge(changed.clone(), write(now - 500, "b", "new"), TTL);
+    assert_eq!(
+      a.differing_buckets(&b.digest()),
+      vec![bucket_of(&changed) as u16]
+    );
+    assert_eq!(
+      b.entries_in(&a.differing_buckets(&b.digest()))
+        .into_iter()
+        .filter(|(key, _)| *key == changed)
+        .count(),
+      1
+    );
+  }
+}
@@ -0,0 +1,177 @@
+//! Acceptor whose promises and accepted values survive a restart.
+//!
+//! State is written to disk and synced before a reply leaves the acceptor,
+//! so a restarted acceptor never accepts a ballot lower than one it promised
+//! before the crash.
+
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::{BufWriter, ErrorKind, Write},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::message::{
+  Accepted, AcceptedValue, Ballot, Nack, Prepare, Promise, Propose, Reply, Slot,
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AcceptorState {
+  promised: Ballot,
+  accepted: BTreeMap<Slot, AcceptedValue>,
+}
+
+pub struct Acceptor {
+  path: PathBuf,
+  state: AcceptorState,
+}
+
+impl Acceptor {
+  /// Loads the acceptor state at `path`, or starts a fresh acceptor.
+  pub fn open(path: &Path) -> Result<Self> {
+    let state = match fs::read(path) {
+      Ok(bytes) => serde_json::from_slice(&bytes)
+        .with_context(|| format!("failed to parse acceptor state {}", path.display()))?,
+      Err(err) if err.kind() == ErrorKind::NotFound => AcceptorState::default(),
+      Err(err) => {
+        return Err(err)
+          .with_context(|| format!("failed to read acceptor state {}", path.display()));
+      }
+    };
+
+    Ok(Self {
+      path: path.to_path_buf(),
+      state,
+    })
+  }
+
+  /// The whole state is rewritten on every change, which is fine for the
+  /// size of the logs in this example.
+  fn persist(&self) -> Result<()> {
+    if let Some(parent) = self.path.parent() {
+      fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create data directory {}", parent.display()))?;
+    }
+    let temp_path = self.path.with_extension("json.tmp");
+    let file = fs::File::create(&temp_path)
+      .with_context(|| format!("failed to create {}", temp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &self.state)?;
+    writer.flush()?;
+    writer
+      .get_ref()
+      .sync_all()
+      .with_context(|| format!("failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, &self.path)
+      .with_context(|| format!("failed to replace acceptor state {}", self.path.display()))
+  }
+
+  pub fn handle_prepare(&mut self, prepare: Prepare) -> Result<Reply<Promise>> {
+    if prepare.ballot < self.state.promised {
+      return Ok(Err(Nack {
+        promised: self.state.promised,
+      }));
+    }
+
+    if prepare.ballot > self.state.promised {
+      self.state.promised = prepare.ballot;
+      self.persist()?;
+    }
+
+    let accepted = self
+      .state
+      .accepted
+      .range(prepare.from_slot ..)
+      .map(|(slot, accepted)| (*slot, accepted.clone()))
+      .collect();
+    Ok(Ok(Promise {
+      ballot: prepare.ballot,
+      accepted,
+    }))
+  }
+
+  pub fn handle_propose(&mut self, propose: Propose) -> Result<Reply<Accepted>> {
+    if propose.ballot < self.state.promised {
+      return Ok(Err(Nack {
+        promised: self.state.promised,
+      }));
+    }
+
+    self.state.promised = propose.ballot;
+    self.state.accepted.insert(
+      propose.slot,
+      AcceptedValue {
+        ballot: propose.ballot,
+        value: propose.value,
+      },
+    );
+    self.persist()?;
+
+    Ok(Ok(Accepted {
+      ballot: propose.ballot,
+      slot: propose.slot,
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::message::Value;
+
+  fn ballot(round: u64, node: u64) -> Ballot {
+    Ballot { round, node }
+  }
+
+  #[test]
+  fn keeps_promises_across_restart() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("acceptor.json");
+
+    let mut acceptor = Acceptor::open(&path)?;
+    acceptor
+      .handle_prepare(Prepare {
+        ballot: ballot(1, 0),
+        from_slot: 0,
+      })?
+      .expect("first prepare is promised");
+    acceptor
+      .handle_propose(Propose {
+        ballot: ballot(1, 0),
+        slot: 3,
+        value: Value::Command(String::from("a")),
+      })?
+      .expect("propose of the promised ballot is accepted");
+    acceptor
+      .handle_prepare(Prepare {
+        ballot: ballot(2, 1),
+        from_slot: 0,
+      })?
+      .expect("higher prepare is promised");
+    drop(acceptor);
+
+    let mut acceptor = Acceptor::open(&path)?;
+    let nack = acceptor
+      .handle_propose(Propose {
+        ballot: ballot(1, 0),
+        slot: 4,
+        value: Value::Command(String::from("b")),
+      })?
+      .expect_err("the old leader is rejected after a restart");
+    assert_eq!(nack.promised, ballot(2, 1));
+
+    let promise = acceptor
+      .handle_prepare(Prepare {
+        ballot: ballot(3, 2),
+        from_slot: 1,
+      })?
+      .expect("higher prepare is promised");
+    assert_eq!(promise.accepted.len(), 1);
+    assert_eq!(promise.accepted[0].0, 3);
+    assert_eq!(promise.accepted[0].1.ballot, ballot(1, 0));
+    Ok(())
+  }
+}
@@ -0,0 +1,102 @@
+//! Learner that applies chosen values to a state machine in slot order.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::message::{Slot, Value};
+
+/// What the replicated log drives. Commands are applied exactly in slot
+/// order on every replica, no-ops are skipped.
+pub trait StateMachine: Send + 'static {
+  /// Returns the output that is sent back to the client.
+  fn apply(&mut self, slot: Slot, command: &str) -> String;
+}
+
+/// State machine that keeps every command it was given.
+#[derive(Debug, Default)]
+pub struct AppendLog {
+  entries: Vec<String>,
+}
+
+impl StateMachine for AppendLog {
+  fn apply(&mut self, _: Slot, command: &str) -> String {
+    self.entries.push(command.to_owned());
+    format!("entry {}", self.entries.len())
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogView {
+  pub next_slot: Slot,
+  pub chosen: Vec<(Slot, Value)>,
+}
+
+pub struct Learner<S> {
+  chosen: BTreeMap<Slot, Value>,
+  /// Every slot below was applied.
+  next_slot: Slot,
+  state_machine: S,
+}
+
+impl<S: StateMachine> Learner<S> {
+  pub fn new(state_machine: S) -> Self {
+    Self {
+      chosen: BTreeMap::new(),
+      next_slot: 0,
+      state_machine,
+    }
+  }
+
+  pub fn next_slot(&self) -> Slot {
+    self.next_slot
+  }
+
+  /// Records chosen values and applies every slot that no longer has a gap
+  /// before it. Returns the outputs of the slots applied by this call.
+  pub fn learn(
+    &mut self,
+    entries: impl IntoIterator<Item = (Slot, Value)>,
+  ) -> Result<Vec<(Slot, String)>> {
+    for (slot, value) in entries {
+      match self.chosen.get(&slot) {
+        Some(chosen) if *chosen != value => {
+          bail!("slot {slot} was chosen as {chosen:?}, now learned {value:?}")
+        }
+        Some(_) => {}
+        None => {
+          self.chosen.insert(slot, value);
+        }
+      }
+    }
+
+    let mut outputs = Vec::new();
+    while let Some(value) = self.chosen.get(&self.next_slot) {
+      if let Value::Command(command) = value {
+        outputs.push((
+          self.next_slot,
+          self.state_machine.apply(self.next_slot, command),
+        ));
+      }
+      self.next_slot += 1;
+    }
+    Ok(outputs)
+  }
+
+  /// Chosen values of `slots`, to bring a lagging learner up to date.
+  pub fn chosen_range(&self, slots: std::ops::Range<Slot>) -> Vec<(Slot, Value)> {
+    self
+      .chosen
+      .range(slots)
+      .map(|(slot, value)| (*slot, value.clone()))
+      .collect()
+  }
+
+  pub fn view(&self) -> LogView {
+    LogView {
+      next_slot: self.next_slot,
+      chosen: self.chosen_range(0 .. Slot::MAX),
+    }
+  }
+}
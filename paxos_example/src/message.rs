@@ -0,0 +1,93 @@
+//! Messages exchanged between proposers, acceptors and learners.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+pub type NodeId = u64;
+/// Position in the replicated log.
+pub type Slot = u64;
+
+/// Proposal number. Rounds are compared first, the node id makes the
+/// ballots of two proposers that picked the same round distinct.
+#[derive(
+  Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Ballot {
+  pub round: u64,
+  pub node: NodeId,
+}
+
+impl fmt::Display for Ballot {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}", self.round, self.node)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Value {
+  /// Fills a slot that a new leader found empty, so later slots can be
+  /// applied.
+  Noop,
+  Command(String),
+}
+
+/// A value an acceptor accepted and the ballot it accepted it in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptedValue {
+  pub ballot: Ballot,
+  pub value: Value,
+}
+
+/// Phase 1a, for every slot from `from_slot` on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Prepare {
+  pub ballot: Ballot,
+  pub from_slot: Slot,
+}
+
+/// Phase 1b: the acceptor will not accept lower ballots anymore and reports
+/// what it accepted so far.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Promise {
+  pub ballot: Ballot,
+  pub accepted: Vec<(Slot, AcceptedValue)>,
+}
+
+/// Phase 2a.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Propose {
+  pub ballot: Ballot,
+  pub slot: Slot,
+  pub value: Value,
+}
+
+/// Phase 2b.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Accepted {
+  pub ballot: Ballot,
+  pub slot: Slot,
+}
+
+/// Rejection of a prepare or propose, carrying the higher ballot the
+/// acceptor promised.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Nack {
+  pub promised: Ballot,
+}
+
+/// Answer of an acceptor, a rejection is not an error of the transport.
+pub type Reply<T> = std::result::Result<T, Nack>;
+
+/// Chosen values sent to learners.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Commit {
+  pub entries: Vec<(Slot, Value)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommitReply {
+  /// First slot the learner has not applied yet, the leader sends the
+  /// missing entries below its own slot.
+  pub next_slot: Slot,
+}
@@ -0,0 +1,284 @@
+//! A cluster member: proposer, acceptor and learner of one node.
+//!
+//! The proposer runs Multi-Paxos. Phase 1 is run once, for every slot from
+//! the first one this node has not applied. The promises carry every value
+//! the acceptors accepted in those slots. The highest-ballot value of each
+//! slot is proposed again and empty slots in between get a no-op, so the
+//! log has no gaps. After that the proposer is the leader and every new
+//! command only needs phase 2 in the next free slot, until an acceptor
+//! reports a higher ballot.
+
+use std::{
+  collections::{BTreeMap, btree_map},
+  sync::{Mutex, MutexGuard, PoisonError},
+  time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+  acceptor::Acceptor,
+  learner::{Learner, LogView, StateMachine},
+  message::{
+    Accepted, AcceptedValue, Ballot, Commit, CommitReply, NodeId, Prepare, Promise, Propose, Reply,
+    Slot, Value,
+  },
+  transport::Transport,
+};
+
+/// A client command is tried this many times, each failure costs the
+/// leadership and the next try starts with phase 1.
+const MAX_ATTEMPTS: u32 = 3;
+/// Grows with the attempt and the node id, so duelling proposers stop
+/// preempting each other.
+const RETRY_BACKOFF: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Default)]
+struct Proposer {
+  /// Highest round seen in any ballot, a new ballot goes above it.
+  max_round: u64,
+  /// Set while this node is the leader, phase 1 completed for this ballot.
+  leader_ballot: Option<Ballot>,
+  /// Next free slot while this node is the leader.
+  next_slot: Slot,
+}
+
+pub struct Replica<T, S> {
+  id: NodeId,
+  /// Every node of the cluster, this one included.
+  nodes: Vec<NodeId>,
+  transport: T,
+  /// Held for a whole proposal, a node proposes one command at a time.
+  proposer: tokio::sync::Mutex<Proposer>,
+  acceptor: Mutex<Acceptor>,
+  learner: Mutex<Learner<S>>,
+}
+
+impl<T: Transport, S: StateMachine> Replica<T, S> {
+  pub fn new(
+    id: NodeId,
+    nodes: Vec<NodeId>,
+    transport: T,
+    acceptor: Acceptor,
+    state_machine: S,
+  ) -> Self {
+    Self {
+      id,
+      nodes,
+      transport,
+      proposer: Default::default(),
+      acceptor: Mutex::new(acceptor),
+      learner: Mutex::new(Learner::new(state_machine)),
+    }
+  }
+
+  fn majority(&self) -> usize {
+    self.nodes.len() / 2 + 1
+  }
+
+  pub fn handle_prepare(&self, prepare: Prepare) -> Result<Reply<Promise>> {
+    lock(&self.acceptor).handle_prepare(prepare)
+  }
+
+  pub fn handle_propose(&self, propose: Propose) -> Result<Reply<Accepted>> {
+    lock(&self.acceptor).handle_propose(propose)
+  }
+
+  pub fn handle_commit(&self, commit: Commit) -> Result<CommitReply> {
+    let mut learner = lock(&self.learner);
+    learner.learn(commit.entries)?;
+    Ok(CommitReply {
+      next_slot: learner.next_slot(),
+    })
+  }
+
+  pub fn log(&self) -> LogView {
+    lock(&self.learner).view()
+  }
+
+  /// Gets `command` chosen in a slot of the log and applied, returning the
+  /// slot and the output of the state machine. A command that failed may
+  /// still be chosen later: a new leader finds it and proposes it again.
+  pub async fn propose(&self, command: String) -> Result<(Slot, String)> {
+    let mut proposer = self.proposer.lock().await;
+    let mut last_error = None;
+    for attempt in 0 .. MAX_ATTEMPTS {
+      if attempt > 0 {
+        tokio::time::sleep(RETRY_BACKOFF * attempt * (self.id as u32 + 1)).await;
+      }
+      match self.try_propose(&mut proposer, &command).await {
+        Ok(chosen) => return Ok(chosen),
+        Err(err) => {
+          proposer.leader_ballot = None;
+          last_error = Some(err);
+        }
+      }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("no attempt was made")))
+  }
+
+  async fn try_propose(&self, proposer: &mut Proposer, command: &str) -> Result<(Slot, String)> {
+    let ballot = match proposer.leader_ballot {
+      Some(ballot) => ballot,
+      None => self.become_leader(proposer).await?,
+    };
+
+    let slot = proposer.next_slot;
+    let value = Value::Command(command.to_owned());
+    self.choose(proposer, ballot, slot, value.clone()).await?;
+    proposer.next_slot += 1;
+
+    self
+      .commit(vec![(slot, value)])
+      .await?
+      .into_iter()
+      .find(|(applied, _)| *applied == slot)
+      .map(|(_, output)| (slot, output))
+      .ok_or_else(|| anyhow!("slot {slot} was chosen but could not be applied"))
+  }
+
+  /// Phase 1 for every slot this node has not applied, then phase 2 for the
+  /// slots the acceptors reported and the gaps between them.
+  async fn become_leader(&self, proposer: &mut Proposer) -> Result<Ballot> {
+    let ballot = Ballot {
+      round: proposer.max_round + 1,
+      node: self.id,
+    };
+    proposer.max_round = ballot.round;
+    let from_slot = lock(&self.learner).next_slot();
+    let prepare = Prepare { ballot, from_slot };
+
+    let replies = futures::future::join_all(
+      self
+        .nodes
+        .iter()
+        .map(|&node| self.transport.prepare(node, prepare.clone())),
+    )
+    .await;
+
+    let mut promises = 0;
+    let mut recovered = BTreeMap::<Slot, AcceptedValue>::new();
+    for reply in replies.into_iter().flatten() {
+      match reply {
+        Ok(promise) => {
+          promises += 1;
+          for (slot, accepted) in promise.accepted {
+            match recovered.entry(slot) {
+              btree_map::Entry::Vacant(entry) => {
+                entry.insert(accepted);
+              }
+              btree_map::Entry::Occupied(mut entry) if entry.get().ballot < accepted.ballot => {
+                entry.insert(accepted);
+              }
+              btree_map::Entry::Occupied(_) => {}
+            }
+          }
+        }
+        Err(nack) => proposer.max_round = proposer.max_round.max(nack.promised.round),
+      }
+    }
+    if promises < self.majority() {
+      bail!("ballot {ballot} was not promised by a majority");
+    }
+
+    let end = recovered
+      .keys()
+      .next_back()
+      .map_or(from_slot, |slot| slot + 1);
+    let mut entries = Vec::new();
+    for slot in from_slot .. end {
+      let value = recovered
+        .remove(&slot)
+        .map_or(Value::Noop, |accepted| accepted.value);
+      self.choose(proposer, ballot, slot, value.clone()).await?;
+      entries.push((slot, value));
+    }
+    if !entries.is_empty() {
+      self.commit(entries).await?;
+    }
+
+    println!(
+      "Node {} leads with ballot {ballot} from slot {end}",
+      self.id
+    );
+    proposer.leader_ballot = Some(ballot);
+    proposer.next_slot = end;
+    Ok(ballot)
+  }
+
+  /// Phase 2 for one slot.
+  async fn choose(
+    &self,
+    proposer: &mut Proposer,
+    ballot: Ballot,
+    slot: Slot,
+    value: Value,
+  ) -> Result<()> {
+    let propose = Propose {
+      ballot,
+      slot,
+      value,
+    };
+    let replies = futures::future::join_all(
+      self
+        .nodes
+        .iter()
+        .map(|&node| self.transport.propose(node, propose.clone())),
+    )
+    .await;
+
+    let mut accepted = 0;
+    for reply in replies.into_iter().flatten() {
+      match reply {
+        Ok(_) => accepted += 1,
+        Err(nack) => proposer.max_round = proposer.max_round.max(nack.promised.round),
+      }
+    }
+    if accepted < self.majority() {
+      bail!("slot {slot} was not accepted by a majority in ballot {ballot}");
+    }
+    Ok(())
+  }
+
+  /// Applies chosen entries here, then tells the other learners. A learner
+  /// that reports a gap below the entries gets the missing ones as well.
+  /// Lost commits are not retried, the learner catches up with a later one.
+  async fn commit(&self, entries: Vec<(Slot, Value)>) -> Result<Vec<(Slot, String)>> {
+    let outputs = lock(&self.learner).learn(entries.clone())?;
+    let first_slot = entries.first().map_or(0, |(slot, _)| *slot);
+
+    let replies = futures::future::join_all(
+      self
+        .nodes
+        .iter()
+        .filter(|&&node| node != self.id)
+        .map(|&node| {
+          let commit = Commit {
+            entries: entries.clone(),
+          };
+          async move { (node, self.transport.commit(node, commit).await) }
+        }),
+    )
+    .await;
+
+    for (node, reply) in replies {
+      let Ok(reply) = reply else {
+        continue;
+      };
+      if reply.next_slot < first_slot {
+        let missing = lock(&self.learner).chosen_range(reply.next_slot .. first_slot);
+        if !missing.is_empty() {
+          let _ = self
+            .transport
+            .commit(node, Commit { entries: missing })
+            .await;
+        }
+      }
+    }
+    Ok(outputs)
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,190 @@
+//! Runs a cluster over an in-memory network that loses requests and
+//! replies, lets every node propose at the same time and restarts nodes
+//! from their acceptor state, then checks that no two learners and no two
+//! clients ever saw different values for the same slot.
+
+use std::{
+  collections::HashMap,
+  path::Path,
+  sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+  acceptor::Acceptor,
+  learner::AppendLog,
+  message::{Accepted, Commit, CommitReply, NodeId, Prepare, Promise, Propose, Reply, Slot, Value},
+  replica::Replica,
+  transport::Transport,
+};
+
+const NODES: [NodeId; 5] = [0, 1, 2, 3, 4];
+const ROUNDS: usize = 8;
+const LOSS_PERCENT: u64 = 15;
+
+type SimReplica = Replica<SimTransport, AppendLog>;
+
+struct Network {
+  replicas: Mutex<HashMap<NodeId, Arc<SimReplica>>>,
+  /// xorshift state, so a seed replays the same losses.
+  rng: Mutex<u64>,
+}
+
+impl Network {
+  fn lost(&self) -> bool {
+    let mut state = self.rng.lock().unwrap();
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state % 100 < LOSS_PERCENT
+  }
+
+  fn replica(&self, id: NodeId) -> Result<Arc<SimReplica>> {
+    self
+      .replicas
+      .lock()
+      .unwrap()
+      .get(&id)
+      .cloned()
+      .ok_or_else(|| anyhow!("unknown node {id}"))
+  }
+
+  /// Either direction can be lost, a lost reply means the receiver acted on
+  /// the request but the sender does not know.
+  async fn deliver<R>(
+    &self,
+    to: NodeId,
+    handle: impl FnOnce(&SimReplica) -> Result<R>,
+  ) -> Result<R> {
+    tokio::task::yield_now().await;
+    if self.lost() {
+      bail!("request to node {to} lost");
+    }
+    let reply = handle(&*self.replica(to)?)?;
+    tokio::task::yield_now().await;
+    if self.lost() {
+      bail!("reply from node {to} lost");
+    }
+    Ok(reply)
+  }
+}
+
+struct SimTransport {
+  network: Weak<Network>,
+}
+
+impl SimTransport {
+  fn network(&self) -> Result<Arc<Network>> {
+    self
+      .network
+      .upgrade()
+      .ok_or_else(|| anyhow!("network is gone"))
+  }
+}
+
+impl Transport for SimTransport {
+  async fn prepare(&self, to: NodeId, prepare: Prepare) -> Result<Reply<Promise>> {
+    let network = self.network()?;
+    network
+      .deliver(to, |replica| replica.handle_prepare(prepare))
+      .await
+  }
+
+  async fn propose(&self, to: NodeId, propose: Propose) -> Result<Reply<Accepted>> {
+    let network = self.network()?;
+    network
+      .deliver(to, |replica| replica.handle_propose(propose))
+      .await
+  }
+
+  async fn commit(&self, to: NodeId, commit: Commit) -> Result<CommitReply> {
+    let network = self.network()?;
+    network
+      .deliver(to, |replica| replica.handle_commit(commit))
+      .await
+  }
+}
+
+/// A fresh process: the acceptor state comes from disk, the learner and
+/// the proposer start empty.
+fn start(network: &Arc<Network>, dir: &Path, id: NodeId) -> Result<Arc<SimReplica>> {
+  Ok(Arc::new(Replica::new(
+    id,
+    NODES.to_vec(),
+    SimTransport {
+      network: Arc::downgrade(network),
+    },
+    Acceptor::open(&dir.join(format!("acceptor-{id}.json")))?,
+    AppendLog::default(),
+  )))
+}
+
+/// Returns how many proposals were acknowledged.
+async fn run(seed: u64) -> Result<usize> {
+  let dir = tempfile::tempdir()?;
+  let network = Arc::new(Network {
+    replicas: Mutex::new(HashMap::new()),
+    rng: Mutex::new(seed),
+  });
+  for id in NODES {
+    let replica = start(&network, dir.path(), id)?;
+    network.replicas.lock().unwrap().insert(id, replica);
+  }
+
+  let mut acknowledged = HashMap::<Slot, String>::new();
+  let mut learned = HashMap::<Slot, Value>::new();
+  for round in 0 .. ROUNDS {
+    let proposals = NODES.map(|id| {
+      let network = network.clone();
+      async move {
+        let command = format!("round {round} from node {id}");
+        let result = network.replica(id)?.propose(command.clone()).await;
+        anyhow::Ok((command, result))
+      }
+    });
+    for proposal in futures::future::join_all(proposals).await {
+      let (command, result) = proposal?;
+      if let Ok((slot, _)) = result
+        && let Some(previous) = acknowledged.insert(slot, command.clone())
+      {
+        bail!("seed {seed}: slot {slot} acknowledged as {previous:?} and as {command:?}");
+      }
+    }
+
+    // Record what each learner saw before one of them loses its memory.
+    for id in NODES {
+      for (slot, value) in network.replica(id)?.log().chosen {
+        if let Some(previous) = learned.insert(slot, value.clone())
+          && previous != value
+        {
+          bail!("seed {seed}: slot {slot} learned as {previous:?} and as {value:?}");
+        }
+      }
+    }
+
+    let restarted = NODES[round % NODES.len()];
+    let replica = start(&network, dir.path(), restarted)?;
+    network.replicas.lock().unwrap().insert(restarted, replica);
+  }
+
+  for (slot, command) in &acknowledged {
+    let Some(value) = learned.get(slot) else {
+      bail!("seed {seed}: acknowledged slot {slot} was never learned");
+    };
+    if *value != Value::Command(command.clone()) {
+      bail!("seed {seed}: slot {slot} acknowledged as {command:?} but learned {value:?}");
+    }
+  }
+  Ok(acknowledged.len())
+}
+
+#[tokio::test(start_paused = true)]
+async fn log_is_safe_under_message_loss_and_duelling_proposers() -> Result<()> {
+  let mut acknowledged = 0;
+  for seed in 1 ..= 20 {
+    acknowledged += run(seed).await?;
+  }
+  assert!(acknowledged > 0, "no proposal ever succeeded");
+  Ok(())
+}
@@ -0,0 +1,82 @@
+//! How a replica reaches the acceptors and learners of the cluster,
+//! itself included.
+
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::{
+  Node,
+  message::{Accepted, Commit, CommitReply, NodeId, Prepare, Promise, Propose, Reply},
+};
+
+pub const PREPARE_PATH: &str = "/acceptor/handle-prepare-message";
+pub const PROPOSE_PATH: &str = "/acceptor/handle-propose";
+pub const COMMIT_PATH: &str = "/learner/commit";
+
+/// An `Err` is a lost message or unreachable node, not a rejection.
+pub trait Transport: Send + Sync + 'static {
+  fn prepare(
+    &self,
+    to: NodeId,
+    prepare: Prepare,
+  ) -> impl Future<Output = Result<Reply<Promise>>> + Send;
+
+  fn propose(
+    &self,
+    to: NodeId,
+    propose: Propose,
+  ) -> impl Future<Output = Result<Reply<Accepted>>> + Send;
+
+  fn commit(&self, to: NodeId, commit: Commit) -> impl Future<Output = Result<CommitReply>> + Send;
+}
+
+pub struct HttpTransport {
+  client: reqwest::Client,
+  nodes: HashMap<NodeId, Node>,
+}
+
+impl HttpTransport {
+  pub fn new(nodes: &[Node], timeout: Duration) -> Result<Self> {
+    Ok(Self {
+      client: reqwest::Client::builder().timeout(timeout).build()?,
+      nodes: nodes.iter().map(|node| (node.id, node.clone())).collect(),
+    })
+  }
+
+  async fn post<B, R>(&self, to: NodeId, path: &str, body: &B) -> Result<R>
+  where
+    B: serde::Serialize,
+    R: serde::de::DeserializeOwned,
+  {
+    let node = self
+      .nodes
+      .get(&to)
+      .ok_or_else(|| anyhow!("unknown node {to}"))?;
+    let response = self
+      .client
+      .post(node.endpoint(path))
+      .json(body)
+      .send()
+      .await?
+      .error_for_status()?;
+    response
+      .json()
+      .await
+      .with_context(|| format!("invalid response from node {to}"))
+  }
+}
+
+impl Transport for HttpTransport {
+  async fn prepare(&self, to: NodeId, prepare: Prepare) -> Result<Reply<Promise>> {
+    self.post(to, PREPARE_PATH, &prepare).await
+  }
+
+  async fn propose(&self, to: NodeId, propose: Propose) -> Result<Reply<Accepted>> {
+    self.post(to, PROPOSE_PATH, &propose).await
+  }
+
+  async fn commit(&self, to: NodeId, commit: Commit) -> Result<CommitReply> {
+    self.post(to, COMMIT_PATH, &commit).await
+  }
+}
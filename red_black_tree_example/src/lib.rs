@@ -1,6 +1,17 @@
-use std::{cell::RefCell, cmp, mem, rc::Rc};
-
-#[derive(Clone, Debug)]
+use std::{
+  cell::RefCell,
+  cmp,
+  fs::{self, File},
+  io::{self, Write},
+  mem,
+  ops::{Bound, RangeBounds},
+  path::Path,
+  rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IoTDevice {
   pub numerical_id: u64,
   pub path: String,
@@ -59,7 +70,7 @@ enum RBOperation {
   RightNode,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum Rotation {
   Left,
   Right,
@@ -71,6 +82,8 @@ struct Node {
   pub parent: Tree,
   left: Tree,
   right: Tree,
+  /// Number of nodes in the subtree rooted here, for rank and select.
+  size: usize,
 }
 
 impl PartialEq for Node {
@@ -87,6 +100,7 @@ impl Node {
       parent: None,
       left: None,
       right: None,
+      size: 1,
     })))
   }
 }
@@ -104,11 +118,17 @@ impl BetterDeviceRegistry {
     }
   }
 
-  pub fn add(&mut self, device: IoTDevice) {
+  /// Adds `device`. A device with the same `numerical_id` is replaced and
+  /// returned, so ids stay unique.
+  pub fn add(&mut self, device: IoTDevice) -> Option<IoTDevice> {
+    if let Some(existing) = self.find_node(device.numerical_id) {
+      return Some(mem::replace(&mut existing.borrow_mut().dev, device));
+    }
     self.length += 1;
     let root = mem::replace(&mut self.root, None);
     let new_tree = self.add_r(root, device);
     self.root = self.fix_tree(new_tree.1);
+    None
   }
 
   fn check(&self, a: &IoTDevice, b: &IoTDevice) -> RBOperation {
@@ -122,6 +142,7 @@ impl BetterDeviceRegistry {
   fn add_r(&mut self, mut node: Tree, device: IoTDevice) -> (Tree, BareTree) {
     if let Some(n) = node.take() {
       let new: BareTree;
+      n.borrow_mut().size += 1;
       let current_device = n.borrow().dev.clone();
 
       match self.check(&current_device, &device) {
@@ -326,6 +347,8 @@ impl BetterDeviceRegistry {
         }
         y.as_ref().unwrap().borrow_mut().right = Some(x.clone());
         x.borrow_mut().parent = y.clone();
+        update_size(&x);
+        update_size(y.as_ref().unwrap());
       }
       Rotation::Left => {
         let x = node;
@@ -356,6 +379,8 @@ impl BetterDeviceRegistry {
         }
         y.as_ref().unwrap().borrow_mut().left = Some(x.clone());
         x.borrow_mut().parent = y.clone();
+        update_size(&x);
+        update_size(y.as_ref().unwrap());
       }
     }
   }
@@ -418,4 +443,469 @@ impl BetterDeviceRegistry {
       self.walk_in_order(&n.right, callback);
     }
   }
+
+  // Larger ids are on the left (see `check`), so ascending order visits the
+  // right subtree first.
+  fn find_node(&self, numerical_id: u64) -> Tree {
+    let mut node = self.root.clone();
+    while let Some(n) = node {
+      let next = {
+        let current = n.borrow();
+        if current.dev.numerical_id == numerical_id {
+          return Some(n.clone());
+        }
+        if current.dev.numerical_id < numerical_id {
+          current.left.clone()
+        } else {
+          current.right.clone()
+        }
+      };
+      node = next;
+    }
+    None
+  }
+
+  /// Removes the device with `numerical_id` and rebalances the tree.
+  pub fn remove(&mut self, numerical_id: u64) -> Option<IoTDevice> {
+    let found = self.find_node(numerical_id)?;
+    let removed = found.borrow().dev.clone();
+
+    // a node with two children takes over the device of its in-order
+    // neighbour, which has at most one child and is unlinked instead
+    let has_both = {
+      let n = found.borrow();
+      n.left.is_some() && n.right.is_some()
+    };
+    let unlinked = if has_both {
+      let mut neighbour = found.borrow().right.clone().unwrap();
+      loop {
+        let left = neighbour.borrow().left.clone();
+        match left {
+          Some(l) => neighbour = l,
+          None => break,
+        }
+      }
+      mem::swap(&mut found.borrow_mut().dev, &mut neighbour.borrow_mut().dev);
+      neighbour
+    } else {
+      found
+    };
+
+    let (child, parent, color) = {
+      let n = unlinked.borrow();
+      (
+        n.left.clone().or_else(|| n.right.clone()),
+        n.parent.clone(),
+        n.color.clone(),
+      )
+    };
+    if let Some(ref c) = child {
+      c.borrow_mut().parent = parent.clone();
+    }
+    match parent {
+      Some(ref p) => {
+        {
+          let mut p = p.borrow_mut();
+          if is_child(&p.left, &unlinked) {
+            p.left = child.clone();
+          } else {
+            p.right = child.clone();
+          }
+        }
+        let mut ancestor = Some(p.clone());
+        while let Some(a) = ancestor {
+          a.borrow_mut().size -= 1;
+          ancestor = a.borrow().parent.clone();
+        }
+      }
+      None => self.root = child.clone(),
+    }
+    {
+      let mut n = unlinked.borrow_mut();
+      n.parent = None;
+      n.left = None;
+      n.right = None;
+    }
+
+    if color == Color::Black {
+      self.fix_remove(child, parent);
+    }
+    // rotations may have moved the root down
+    if let Some(mut root) = self.root.clone() {
+      loop {
+        let parent = root.borrow().parent.clone();
+        match parent {
+          Some(p) => root = p,
+          None => break,
+        }
+      }
+      root.borrow_mut().color = Color::Black;
+      self.root = Some(root);
+    }
+    self.length -= 1;
+    Some(removed)
+  }
+
+  // `node` took the place of a removed black node and is short one black.
+  // `parent` is passed separately since `node` may be empty.
+  fn fix_remove(&mut self, mut node: Tree, mut parent: Tree) {
+    while let Some(p) = parent.clone() {
+      if is_red(&node) {
+        break;
+      }
+      let node_is_left = match node {
+        Some(ref n) => is_child(&p.borrow().left, n),
+        None => p.borrow().left.is_none(),
+      };
+      let (towards_node, away_from_node) = if node_is_left {
+        (Rotation::Left, Rotation::Right)
+      } else {
+        (Rotation::Right, Rotation::Left)
+      };
+      // the sibling has a black height of at least one, so it exists
+      let sibling = |p: &BareTree| {
+        let p = p.borrow();
+        if node_is_left {
+          p.right.clone()
+        } else {
+          p.left.clone()
+        }
+        .unwrap()
+      };
+      let near = |s: &BareTree| {
+        let s = s.borrow();
+        if node_is_left {
+          s.left.clone()
+        } else {
+          s.right.clone()
+        }
+      };
+      let far = |s: &BareTree| {
+        let s = s.borrow();
+        if node_is_left {
+          s.right.clone()
+        } else {
+          s.left.clone()
+        }
+      };
+
+      let mut s = sibling(&p);
+      if s.borrow().color == Color::Red {
+        s.borrow_mut().color = Color::Black;
+        p.borrow_mut().color = Color::Red;
+        self.rotate(p.clone(), towards_node);
+        s = sibling(&p);
+      }
+
+      if !is_red(&near(&s)) && !is_red(&far(&s)) {
+        s.borrow_mut().color = Color::Red;
+        parent = p.borrow().parent.clone();
+        node = Some(p);
+      } else {
+        if !is_red(&far(&s)) {
+          near(&s).unwrap().borrow_mut().color = Color::Black;
+          s.borrow_mut().color = Color::Red;
+          self.rotate(s.clone(), away_from_node);
+          s = sibling(&p);
+        }
+        s.borrow_mut().color = p.borrow().color.clone();
+        p.borrow_mut().color = Color::Black;
+        far(&s).unwrap().borrow_mut().color = Color::Black;
+        self.rotate(p, towards_node);
+        return;
+      }
+    }
+    if let Some(n) = node {
+      n.borrow_mut().color = Color::Black;
+    }
+  }
+
+  /// Number of devices with a `numerical_id` below `numerical_id`.
+  pub fn rank(&self, numerical_id: u64) -> usize {
+    let mut rank = 0;
+    let mut node = self.root.clone();
+    while let Some(n) = node {
+      let n = n.borrow();
+      node = if n.dev.numerical_id < numerical_id {
+        rank += 1 + size(&n.right);
+        n.left.clone()
+      } else {
+        n.right.clone()
+      };
+    }
+    rank
+  }
+
+  /// The device at position `index` in ascending `numerical_id` order.
+  pub fn select(&self, mut index: usize) -> Option<IoTDevice> {
+    let mut node = self.root.clone();
+    while let Some(n) = node {
+      let n = n.borrow();
+      let smaller = size(&n.right);
+      node = match index.cmp(&smaller) {
+        cmp::Ordering::Less => n.right.clone(),
+        cmp::Ordering::Equal => return Some(n.dev.clone()),
+        cmp::Ordering::Greater => {
+          index -= smaller + 1;
+          n.left.clone()
+        }
+      };
+    }
+    None
+  }
+
+  /// All devices in ascending `numerical_id` order.
+  pub fn iter(&self) -> Iter<'_> {
+    self.range(..)
+  }
+
+  /// The devices with a `numerical_id` in `range`, in ascending order.
+  pub fn range(&self, range: impl RangeBounds<u64>) -> Iter<'_> {
+    let len = self.length as usize;
+    let front = match range.start_bound() {
+      Bound::Included(&id) => self.rank(id),
+      Bound::Excluded(&id) => id.checked_add(1).map_or(len, |id| self.rank(id)),
+      Bound::Unbounded => 0,
+    };
+    let back = match range.end_bound() {
+      Bound::Included(&id) => id.checked_add(1).map_or(len, |id| self.rank(id)),
+      Bound::Excluded(&id) => self.rank(id),
+      Bound::Unbounded => len,
+    };
+    Iter {
+      registry: self,
+      front,
+      back: cmp::max(front, back),
+    }
+  }
+
+  /// Writes all devices to `path` as JSON. The snapshot is written to a
+  /// temporary file first, so a crash leaves the previous one intact.
+  pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let devices: Vec<IoTDevice> = self.iter().collect();
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&serde_json::to_vec(&devices)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+  }
+
+  /// Rebuilds a registry from a snapshot written by [`save`](Self::save).
+  pub fn load(path: impl AsRef<Path>) -> io::Result<BetterDeviceRegistry> {
+    let devices: Vec<IoTDevice> = serde_json::from_slice(&fs::read(path)?)?;
+    let mut registry = BetterDeviceRegistry::new_empty();
+    for device in devices {
+      registry.add(device);
+    }
+    Ok(registry)
+  }
+}
+
+fn size(tree: &Tree) -> usize {
+  tree.as_ref().map_or(0, |n| n.borrow().size)
+}
+
+fn update_size(node: &BareTree) {
+  let size = {
+    let n = node.borrow();
+    1 + size(&n.left) + size(&n.right)
+  };
+  node.borrow_mut().size = size;
+}
+
+fn is_red(tree: &Tree) -> bool {
+  tree
+    .as_ref()
+    .is_some_and(|n| n.borrow().color == Color::Red)
+}
+
+fn is_child(child: &Tree, node: &BareTree) -> bool {
+  child.as_ref().is_some_and(|c| Rc::ptr_eq(c, node))
+}
+
+/// Iterator over a registry in ascending `numerical_id` order, created by
+/// [`BetterDeviceRegistry::iter`] and [`BetterDeviceRegistry::range`].
+/// Every step is a `select`, so both ends advance in `O(log n)`.
+pub struct Iter<'a> {
+  registry: &'a BetterDeviceRegistry,
+  front: usize,
+  back: usize,
+}
+
+impl Iterator for Iter<'_> {
+  type Item = IoTDevice;
+
+  fn next(&mut self) -> Option<IoTDevice> {
+    if self.front == self.back {
+      return None;
+    }
+    self.front += 1;
+    self.registry.select(self.front - 1)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.back - self.front;
+    (len, Some(len))
+  }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+  fn next_back(&mut self) -> Option<IoTDevice> {
+    if self.front == self.back {
+      return None;
+    }
+    self.back -= 1;
+    self.registry.select(self.back)
+  }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use proptest::prelude::*;
+
+  use super::*;
+
+  fn device(id: u64) -> IoTDevice {
+    IoTDevice::new(id, format!("10.0.0.{id}"), format!("/dev/{id}"))
+  }
+
+  // parent links, subtree sizes and the search order, returns the size
+  fn check_links(node: &Tree, parent: &Tree, low: Option<u64>, high: Option<u64>) -> usize {
+    let Some(n) = node else {
+      return 0;
+    };
+    let n = n.borrow();
+    match (&n.parent, parent) {
+      (Some(a), Some(b)) => assert!(Rc::ptr_eq(a, b)),
+      (None, None) => {}
+      _ => panic!("wrong parent of {}", n.dev.numerical_id),
+    }
+    let id = n.dev.numerical_id;
+    assert!(low.is_none_or(|low| id > low) && high.is_none_or(|high| id < high));
+    let size =
+      1 + check_links(&n.left, node, Some(id), high) + check_links(&n.right, node, low, Some(id));
+    assert_eq!(n.size, size);
+    size
+  }
+
+  #[derive(Clone, Debug)]
+  enum Op {
+    Add(u64),
+    Remove(u64),
+  }
+
+  fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+      (0 .. 64u64).prop_map(Op::Add),
+      (0 .. 64u64).prop_map(Op::Remove)
+    ]
+  }
+
+  proptest! {
+      #[test]
+      fn behaves_like_a_btree_map(ops in proptest::collection::vec(op(), 0..200)) {
+          let mut registry = BetterDeviceRegistry::new_empty();
+          let mut model = BTreeMap::new();
+
+          for op in ops {
+              match op {
+                  Op::Add(id) => {
+                      registry.add(device(id));
+                      model.insert(id, device(id));
+                  }
+                  Op::Remove(id) => {
+                      prop_assert_eq!(registry.remove(id), model.remove(&id));
+                  }
+              }
+              prop_assert!(registry.is_a_valid_red_black_tree());
+              check_links(&registry.root, &None, None, None);
+              prop_assert_eq!(registry.length, model.len() as u64);
+          }
+
+          let devices: Vec<IoTDevice> = registry.iter().collect();
+          prop_assert_eq!(&devices, &model.values().cloned().collect::<Vec<_>>());
+          for (index, device) in devices.iter().enumerate() {
+              prop_assert_eq!(registry.rank(device.numerical_id), index);
+              prop_assert_eq!(registry.select(index), Some(device.clone()));
+          }
+          prop_assert_eq!(registry.select(devices.len()), None);
+      }
+
+      #[test]
+      fn ranges_match_a_btree_map(
+          ids in proptest::collection::vec(0..64u64, 0..64),
+          lo in 0..70u64,
+          hi in 0..70u64,
+      ) {
+          let mut registry = BetterDeviceRegistry::new_empty();
+          let mut model = BTreeMap::new();
+          for id in ids {
+              registry.add(device(id));
+              model.insert(id, device(id));
+          }
+
+          let (lo, hi) = (lo.min(hi), lo.max(hi));
+          let expected: Vec<_> = model.range(lo..hi).map(|(_, d)| d.clone()).collect();
+          let range = registry.range(lo..hi);
+          prop_assert_eq!(range.len(), expected.len());
+          prop_assert_eq!(range.collect::<Vec<_>>(), expected.clone());
+          let reversed: Vec<_> = registry.range(lo..hi).rev().collect();
+          prop_assert_eq!(reversed, expected.into_iter().rev().collect::<Vec<_>>());
+          prop_assert_eq!(
+              registry.range((Bound::Excluded(lo), Bound::Included(hi))).count(),
+              model.range((Bound::Excluded(lo), Bound::Included(hi))).count()
+          );
+      }
+  }
+
+  #[test]
+  fn adding_an_existing_id_replaces_the_device() {
+    let mut registry = BetterDeviceRegistry::new_empty();
+    assert!(registry.add(device(1)).is_none());
+    let replaced = registry.add(IoTDevice::new(1, "10.0.0.9", "/dev/new"));
+    assert_eq!(replaced.unwrap().path, device(1).path);
+    assert_eq!(registry.length, 1);
+    assert_eq!(registry.find(1).unwrap().path, "/dev/new");
+  }
+
+  #[test]
+  fn iterators_meet_in_the_middle() {
+    let mut registry = BetterDeviceRegistry::new_empty();
+    for id in [5, 1, 4, 2, 3] {
+      registry.add(device(id));
+    }
+    let mut iter = registry.iter();
+    assert_eq!(iter.next().map(|d| d.numerical_id), Some(1));
+    assert_eq!(iter.next_back().map(|d| d.numerical_id), Some(5));
+    assert_eq!(
+      iter.map(|d| d.numerical_id).collect::<Vec<_>>(),
+      vec![2, 3, 4]
+    );
+  }
+
+  #[test]
+  fn snapshots_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("registry.json");
+    let mut registry = BetterDeviceRegistry::new_empty();
+    for id in 0 .. 100 {
+      registry.add(device(id * 7 % 100));
+    }
+    registry.remove(42);
+    registry.save(&path).unwrap();
+
+    let loaded = BetterDeviceRegistry::load(&path).unwrap();
+    assert!(loaded.is_a_valid_red_black_tree());
+    assert_eq!(loaded.length, 99);
+    assert_eq!(
+      loaded.iter().map(|d| d.path).collect::<Vec<_>>(),
+      registry.iter().map(|d| d.path).collect::<Vec<_>>()
+    );
+    assert!(!path.with_extension("tmp").exists());
+  }
 }
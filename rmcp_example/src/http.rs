@@ -0,0 +1,81 @@
+//! Streamable HTTP 传输：多个 AI client 通过网络共用一个 MCP server。
+//!
+//! 每个客户端在 `initialize` 时得到一个 `Mcp-Session-Id`，之后的请求都带着它，
+//! server 为每个会话创建一个独立的 [`RmcpDemoServer`] 实例（各自维护资源订阅）。
+//! 所有请求都必须带 `Authorization: Bearer <token>`，否则直接返回 401。
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+  Router,
+  extract::{Request, State},
+  http::{StatusCode, header},
+  middleware::{self, Next},
+  response::{IntoResponse, Response},
+};
+use rmcp::transport::streamable_http_server::{
+  StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+};
+
+use crate::RmcpDemoServer;
+
+/// MCP endpoint 的路径
+pub const MCP_PATH: &str = "/mcp";
+/// 会话空闲多久后关闭
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+pub async fn serve(
+  bind: SocketAddr,
+  token: String,
+  new_server: impl Fn() -> RmcpDemoServer + Send + Sync + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut sessions = LocalSessionManager::default();
+  sessions.session_config.keep_alive = Some(SESSION_IDLE_TIMEOUT);
+  // 默认就是有状态模式，每个客户端一个会话。取消这个 token 会结束所有会话
+  let config = StreamableHttpServerConfig::default();
+  let shutdown = config.cancellation_token.clone();
+  let service = StreamableHttpService::new(move || Ok(new_server()), Arc::new(sessions), config);
+
+  let app = Router::new()
+    .nest_service(MCP_PATH, service)
+    .layer(middleware::from_fn_with_state(
+      Arc::<str>::from(token),
+      require_bearer,
+    ));
+
+  let listener = tokio::net::TcpListener::bind(bind).await?;
+  eprintln!(
+    "rmcp demo server is listening on http://{}{MCP_PATH}",
+    listener.local_addr()?
+  );
+  axum::serve(listener, app)
+    .with_graceful_shutdown(async move {
+      let _ = tokio::signal::ctrl_c().await;
+      shutdown.cancel();
+    })
+    .await?;
+  Ok(())
+}
+
+async fn require_bearer(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+  let authorized = request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()));
+  if !authorized {
+    return (
+      StatusCode::UNAUTHORIZED,
+      [(header::WWW_AUTHENTICATE, "Bearer")],
+      "missing or invalid bearer token",
+    )
+      .into_response();
+  }
+  next.run(request).await
+}
+
+/// 比较耗时只和长度有关，不会因为前缀匹配得多而泄露 token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
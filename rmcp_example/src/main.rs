@@ -1,14 +1,42 @@
+mod http;
+mod resources;
+
+use std::{
+  collections::HashSet,
+  net::SocketAddr,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+
 use rmcp::{
-  ServerHandler, ServiceExt,
-  handler::server::{router::tool::ToolRouter, wrapper::Parameters},
+  ErrorData as McpError, RoleServer, ServerHandler, ServiceExt,
+  handler::server::{
+    router::{prompt::PromptRouter, tool::ToolRouter},
+    wrapper::Parameters,
+  },
   model::{
-    CallToolRequestParams, ClientInfo, Content, Implementation, ServerCapabilities, ServerInfo,
+    CallToolRequestParams, ClientInfo, Content, GetPromptRequestParams, GetPromptResult,
+    Implementation, ListPromptsResult, ListResourcesResult, PaginatedRequestParams, PromptMessage,
+    PromptMessageContent, PromptMessageRole, ReadResourceRequestParams, ReadResourceResult,
+    ResourceContents, ResourceUpdatedNotificationParam, ServerCapabilities, ServerInfo,
+    SubscribeRequestParams, UnsubscribeRequestParams,
   },
-  schemars, tool, tool_handler, tool_router,
+  prompt, prompt_handler, prompt_router, schemars,
+  service::{NotificationContext, RequestContext},
+  tool, tool_handler, tool_router,
   transport::stdio,
 };
 use serde::Deserialize;
 use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use crate::resources::{FileResources, ResourceChange};
+
+/// `http` 模式默认监听的地址
+const DEFAULT_BIND: &str = "127.0.0.1:8000";
+/// `http` 模式从这个环境变量读取 bearer token，不放在命令行参数里以免被 `ps` 看到
+const AUTH_TOKEN_ENV: &str = "RMCP_AUTH_TOKEN";
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 struct AnalyzeTextRequest {
@@ -16,25 +44,46 @@ struct AnalyzeTextRequest {
   text: String,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SummarizeFileArgs {
+  #[schemars(description = "URI of a file resource, as returned by resources/list")]
+  uri: String,
+  #[schemars(description = "Who the summary is written for, e.g. `a new contributor`")]
+  audience: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct ExplainMcpArgs {
+  #[schemars(description = "MCP concept to explain, e.g. `resources`, `prompts` or `transports`")]
+  topic: String,
+}
+
+/// 一个会话对应一个实例。`resources` 在所有会话间共享，订阅则是每个会话自己的
 #[derive(Debug, Clone)]
 struct RmcpDemoServer {
   tool_router: ToolRouter<Self>,
+  prompt_router: PromptRouter<Self>,
+  resources: Arc<FileResources>,
+  subscriptions: Arc<Mutex<HashSet<String>>>,
+  /// 最后一个克隆被 drop（会话结束）时取消，用来停掉转发变更通知的任务
+  closed: CancellationToken,
+  _close_on_drop: Arc<DropGuard>,
 }
 
 impl RmcpDemoServer {
-  fn new() -> Self {
+  fn new(resources: Arc<FileResources>) -> Self {
+    let closed = CancellationToken::new();
     Self {
       tool_router: Self::tool_router(),
+      prompt_router: Self::prompt_router(),
+      resources,
+      subscriptions: Arc::default(),
+      _close_on_drop: Arc::new(closed.clone().drop_guard()),
+      closed,
     }
   }
 }
 
-impl Default for RmcpDemoServer {
-  fn default() -> Self {
-    Self::new()
-  }
-}
-
 #[tool_router]
 impl RmcpDemoServer {
   #[tool(
@@ -47,7 +96,8 @@ impl RmcpDemoServer {
       "MCP standardizes how an AI client discovers and calls external capabilities.",
       "A server exposes tools, resources, and prompts; a client lists them and invokes them \
        through JSON-RPC messages.",
-      "This example exposes two tools: mcp_overview and analyze_text.",
+      "This example exposes two tools (mcp_overview, analyze_text), the files under its resource \
+       root as subscribable resources, and two prompts (summarize_file, explain_mcp).",
     ]
     .join("\n")
   }
@@ -76,38 +126,227 @@ impl RmcpDemoServer {
   }
 }
 
+#[prompt_router]
+impl RmcpDemoServer {
+  #[prompt(
+    name = "summarize_file",
+    description = "Ask the model to summarize one of the file resources for a given audience"
+  )]
+  async fn summarize_file(
+    &self,
+    Parameters(SummarizeFileArgs { uri, audience }): Parameters<SummarizeFileArgs>,
+  ) -> Result<Vec<PromptMessage>, McpError> {
+    let text = match self.resources.read(&uri)? {
+      ResourceContents::TextResourceContents { text, .. } => text,
+      _ => return Err(McpError::invalid_params(format!("{uri} is not text"), None)),
+    };
+    let audience = audience.unwrap_or_else(|| "a developer who is new to this project".to_string());
+    Ok(vec![PromptMessage::new_text(
+      PromptMessageRole::User,
+      format!(
+        "Summarize the file {uri} for {audience}. Explain what it is for, then list the points \
+         they need to know, citing line content where it helps.\n\n{text}"
+      ),
+    )])
+  }
+
+  #[prompt(
+    name = "explain_mcp",
+    description = "Ask the model to explain an MCP concept using this server as the example"
+  )]
+  async fn explain_mcp(
+    &self,
+    Parameters(ExplainMcpArgs { topic }): Parameters<ExplainMcpArgs>,
+  ) -> Vec<PromptMessage> {
+    vec![
+      PromptMessage::new_text(PromptMessageRole::Assistant, self.mcp_overview()),
+      PromptMessage::new_text(
+        PromptMessageRole::User,
+        format!(
+          "Explain MCP {topic} to me. Use the server described above as the running example and \
+           show the JSON-RPC messages a client would send."
+        ),
+      ),
+    ]
+  }
+}
+
 #[tool_handler(router = self.tool_router)]
+#[prompt_handler(router = self.prompt_router)]
 impl ServerHandler for RmcpDemoServer {
   fn get_info(&self) -> ServerInfo {
     let mut implementation = Implementation::new("rmcp-example-server", env!("CARGO_PKG_VERSION"));
     implementation.title = Some("rmcp 功能演示".to_string());
 
-    ServerInfo::new(ServerCapabilities::builder().enable_tools().build())
-      .with_server_info(implementation)
-      .with_instructions("演示 rmcp 如何把 Rust 函数发布成 MCP tools，供 AI client 发现和调用。")
+    ServerInfo::new(
+      ServerCapabilities::builder()
+        .enable_prompts()
+        .enable_resources()
+        .enable_resources_subscribe()
+        .enable_resources_list_changed()
+        .enable_tools()
+        .build(),
+    )
+    .with_server_info(implementation)
+    .with_instructions(format!(
+      "演示 rmcp 如何把 Rust 函数发布成 MCP tools 和 prompts，并把 {} 下的文件发布成可订阅的 \
+       resources，供 AI client 发现和调用。",
+      self.resources.root().display()
+    ))
+  }
+
+  async fn list_resources(
+    &self,
+    _request: Option<PaginatedRequestParams>,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<ListResourcesResult, McpError> {
+    let resources = self
+      .resources
+      .list()
+      .map_err(|e| McpError::internal_error(format!("failed to list resources: {e}"), None))?;
+    Ok(ListResourcesResult::with_all_items(resources))
+  }
+
+  async fn read_resource(
+    &self,
+    request: ReadResourceRequestParams,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<ReadResourceResult, McpError> {
+    let contents = self.resources.read(&request.uri)?;
+    Ok(ReadResourceResult::new(vec![contents]))
+  }
+
+  async fn subscribe(
+    &self,
+    request: SubscribeRequestParams,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<(), McpError> {
+    // 按规范化后的 URI 记录，和 watcher 发出的 URI 对得上
+    let path = self.resources.resolve(&request.uri)?;
+    self
+      .subscriptions
+      .lock()
+      .unwrap()
+      .insert(resources::uri_for(&path));
+    Ok(())
+  }
+
+  async fn unsubscribe(
+    &self,
+    request: UnsubscribeRequestParams,
+    _context: RequestContext<RoleServer>,
+  ) -> Result<(), McpError> {
+    let uri = match self.resources.resolve(&request.uri) {
+      Ok(path) => resources::uri_for(&path),
+      // 文件已经被删掉时也要能退订
+      Err(_) => request.uri,
+    };
+    self.subscriptions.lock().unwrap().remove(&uri);
+    Ok(())
+  }
+
+  /// 会话建立后开始转发根目录的变化：订阅过的文件变了发 `resources/updated`，
+  /// 文件增删发 `resources/list_changed`
+  async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+    let peer = context.peer;
+    let subscriptions = self.subscriptions.clone();
+    let closed = self.closed.clone();
+    let mut changes = self.resources.changes();
+    tokio::spawn(async move {
+      loop {
+        let change = tokio::select! {
+          _ = closed.cancelled() => break,
+          change = changes.recv() => change,
+        };
+        let sent = match change {
+          Ok(ResourceChange::Updated(uri)) => {
+            if !subscriptions.lock().unwrap().contains(&uri) {
+              continue;
+            }
+            peer
+              .notify_resource_updated(ResourceUpdatedNotificationParam::new(uri))
+              .await
+          }
+          Ok(ResourceChange::ListChanged) => peer.notify_resource_list_changed().await,
+          Err(RecvError::Lagged(_)) => continue,
+          Err(RecvError::Closed) => break,
+        };
+        // 发送失败说明客户端已经断开
+        if sent.is_err() {
+          break;
+        }
+      }
+    });
   }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-  match std::env::args().nth(1).as_deref() {
-    Some("serve") => serve_stdio().await,
-    Some("help" | "-h" | "--help") => {
+  let mut args = std::env::args().skip(1);
+  let command = args.next();
+  if matches!(command.as_deref(), Some("help" | "-h" | "--help")) {
+    print_help();
+    return Ok(());
+  }
+  let options = match Options::parse(args) {
+    Ok(options) => options,
+    Err(message) => {
+      eprintln!("{message}");
       print_help();
-      Ok(())
+      return Ok(());
     }
+  };
+  let resources = Arc::new(FileResources::new(&options.root)?);
+
+  match command.as_deref() {
+    Some("serve") => serve_stdio(resources).await,
+    Some("http") => serve_http(resources, options.bind).await,
     Some(other) => {
       eprintln!("unknown command: {other}");
       print_help();
       Ok(())
     }
-    None => run_in_process_demo().await,
+    None => run_in_process_demo(resources).await,
+  }
+}
+
+struct Options {
+  /// 发布成 resources 的目录
+  root: PathBuf,
+  bind: SocketAddr,
+}
+
+impl Options {
+  fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+    let mut options = Self {
+      root: PathBuf::from("."),
+      bind: DEFAULT_BIND.parse().expect("default bind address is valid"),
+    };
+    while let Some(flag) = args.next() {
+      let value = args
+        .next()
+        .ok_or_else(|| format!("missing value for {flag}"))?;
+      match flag.as_str() {
+        "--root" => options.root = PathBuf::from(value),
+        "--bind" => {
+          options.bind = value
+            .parse()
+            .map_err(|e| format!("invalid --bind `{value}`: {e}"))?;
+        }
+        _ => return Err(format!("unknown option: {flag}")),
+      }
+    }
+    Ok(options)
   }
 }
 
-async fn serve_stdio() -> Result<(), Box<dyn std::error::Error>> {
-  eprintln!("rmcp demo server is running on stdio");
-  RmcpDemoServer::new()
+async fn serve_stdio(resources: Arc<FileResources>) -> Result<(), Box<dyn std::error::Error>> {
+  let _watcher = resources.watch()?;
+  eprintln!(
+    "rmcp demo server is running on stdio, serving resources from {}",
+    resources.root().display()
+  );
+  RmcpDemoServer::new(resources)
     .serve(stdio())
     .await?
     .waiting()
@@ -115,16 +354,33 @@ async fn serve_stdio() -> Result<(), Box<dyn std::error::Error>> {
   Ok(())
 }
 
-async fn run_in_process_demo() -> Result<(), Box<dyn std::error::Error>> {
+async fn serve_http(
+  resources: Arc<FileResources>,
+  bind: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let token = std::env::var(AUTH_TOKEN_ENV)
+    .ok()
+    .filter(|token| !token.is_empty())
+    .ok_or_else(|| format!("set {AUTH_TOKEN_ENV} to the bearer token clients must present"))?;
+  let _watcher = resources.watch()?;
+  eprintln!("serving resources from {}", resources.root().display());
+  http::serve(bind, token, move || RmcpDemoServer::new(resources.clone())).await
+}
+
+async fn run_in_process_demo(
+  resources: Arc<FileResources>,
+) -> Result<(), Box<dyn std::error::Error>> {
   println!("rmcp / MCP in-process demo\n");
-  println!("1. Start a Rust MCP server that exposes tools.");
+  println!("1. Start a Rust MCP server that exposes tools, resources and prompts.");
   println!("2. Start an MCP client over an in-memory duplex transport.");
-  println!("3. Let the client discover tools and call one of them.\n");
+  println!("3. Let the client discover tools and call one of them.");
+  println!("4. Let the client list and read file resources, then render a prompt.\n");
 
+  let readme = resources.root().join("README.md");
   let (server_transport, client_transport) = tokio::io::duplex(4096);
 
   let server_task = tokio::spawn(async move {
-    match RmcpDemoServer::new().serve(server_transport).await {
+    match RmcpDemoServer::new(resources).serve(server_transport).await {
       Ok(server) => {
         if let Err(error) = server.waiting().await {
           eprintln!("server task failed: {error}");
@@ -166,11 +422,66 @@ async fn run_in_process_demo() -> Result<(), Box<dyn std::error::Error>> {
     println!("structured JSON: {}", serde_json::to_string_pretty(&value)?);
   }
 
+  let listed = client.peer().list_all_resources().await?;
+  println!("\ndiscovered resources ({}):", listed.len());
+  for resource in listed.iter().take(5) {
+    println!(
+      "- {} [{}]",
+      resource.name,
+      resource.mime_type.as_deref().unwrap_or("unknown")
+    );
+  }
+
+  if readme.is_file() {
+    let uri = resources::uri_for(&readme.canonicalize()?);
+    let read = client
+      .peer()
+      .read_resource(ReadResourceRequestParams::new(uri.as_str()))
+      .await?;
+    println!("\nresources/read {uri}:");
+    for contents in &read.contents {
+      if let ResourceContents::TextResourceContents { text, .. } = contents {
+        println!("{}", preview(text, 3));
+      }
+    }
+
+    let prompts = client.peer().list_all_prompts().await?;
+    println!("\ndiscovered prompts:");
+    for prompt in &prompts {
+      println!(
+        "- {}: {}",
+        prompt.name,
+        prompt.description.as_deref().unwrap_or("no description")
+      );
+    }
+
+    let arguments = serde_json::from_value(json!({ "uri": uri, "audience": "a Rust beginner" }))?;
+    let prompt = client
+      .peer()
+      .get_prompt(GetPromptRequestParams::new("summarize_file").with_arguments(arguments))
+      .await?;
+    println!("\nsummarize_file prompt:");
+    for message in &prompt.messages {
+      if let PromptMessageContent::Text { text } = &message.content {
+        println!("[{:?}] {}", message.role, preview(text, 2));
+      }
+    }
+  }
+
   client.cancel().await?;
   server_task.await?;
   Ok(())
 }
 
+/// 文本的前 `lines` 行，后面还有内容时补一个省略号
+fn preview(text: &str, lines: usize) -> String {
+  let mut shown: Vec<&str> = text.lines().take(lines).collect();
+  if text.lines().count() > lines {
+    shown.push("...");
+  }
+  shown.join("\n")
+}
+
 fn print_tool_text(content: &[Content]) {
   for item in content {
     if let Some(text) = item.as_text() {
@@ -183,4 +494,11 @@ fn print_help() {
   println!("Usage:");
   println!("  cargo run          # run the self-contained MCP client/server demo");
   println!("  cargo run -- serve # run an MCP server over stdio for external clients");
+  println!("  cargo run -- http  # run an MCP server over Streamable HTTP for several clients");
+  println!();
+  println!("Options:");
+  println!("  --root <DIR>   directory published as resources (default: .)");
+  println!("  --bind <ADDR>  address for `http` (default: {DEFAULT_BIND})");
+  println!();
+  println!("`http` requires {AUTH_TOKEN_ENV}; clients send it as `Authorization: Bearer <token>`.");
 }
@@ -0,0 +1,241 @@
+//! 把一个目录下的文件发布成 MCP resources。
+//!
+//! 文件的 URI 是 `file://` 加上规范化后的绝对路径。读取时会把 URI 还原成路径并再次
+//! 规范化，只有仍落在根目录之下的普通文本文件才允许读取，`../` 或符号链接都绕不出去。
+//! 隐藏文件和 `target` 目录既不会列出，也不能按 URI 直接读取。
+//! 目录的变化由 notify 监听，通过 broadcast 通道分发给每个会话，
+//! 会话再按自己的订阅决定是否给客户端发 `notifications/resources/updated`。
+
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind};
+use rmcp::{
+  ErrorData as McpError,
+  model::{AnnotateAble, RawResource, Resource, ResourceContents},
+};
+use tokio::sync::broadcast;
+
+/// 列出的资源数上限，避免把一个巨大的目录整个塞给客户端
+const MAX_LISTED_RESOURCES: usize = 500;
+/// 单个资源的大小上限
+const MAX_RESOURCE_BYTES: u64 = 1024 * 1024;
+const URI_PREFIX: &str = "file://";
+
+/// 根目录下发生的变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceChange {
+  /// 某个文件的内容变了
+  Updated(String),
+  /// 有文件新建、删除或改名，资源列表变了
+  ListChanged,
+}
+
+#[derive(Debug)]
+pub struct FileResources {
+  root: PathBuf,
+  changes: broadcast::Sender<ResourceChange>,
+}
+
+impl FileResources {
+  pub fn new(root: &Path) -> io::Result<Self> {
+    let root = root.canonicalize()?;
+    if !root.is_dir() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is not a directory", root.display()),
+      ));
+    }
+    let (changes, _) = broadcast::channel(64);
+    Ok(Self { root, changes })
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// 订阅根目录下的变化。只有调用过 [`FileResources::watch`] 才会收到事件
+  pub fn changes(&self) -> broadcast::Receiver<ResourceChange> {
+    self.changes.subscribe()
+  }
+
+  /// 开始监听根目录。返回的 watcher 被 drop 后监听停止
+  pub fn watch(&self) -> notify::Result<RecommendedWatcher> {
+    let root = self.root.clone();
+    let changes = self.changes.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+      let Ok(event) = event else {
+        return;
+      };
+      // 没有会话在听时 send 会返回错误，直接忽略
+      match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+          let _ = changes.send(ResourceChange::ListChanged);
+        }
+        EventKind::Modify(_) => {
+          for path in event.paths.iter().filter(|path| path.starts_with(&root)) {
+            let _ = changes.send(ResourceChange::Updated(uri_for(path)));
+          }
+        }
+        _ => {}
+      }
+    })?;
+    watcher.watch(&self.root, RecursiveMode::Recursive)?;
+    Ok(watcher)
+  }
+
+  /// 按路径排序列出根目录下的文件，跳过隐藏文件和 `target` 目录
+  pub fn list(&self) -> io::Result<Vec<Resource>> {
+    let mut files = Vec::new();
+    collect_files(&self.root, &mut files)?;
+    files.sort();
+    files.truncate(MAX_LISTED_RESOURCES);
+
+    let mut resources = Vec::with_capacity(files.len());
+    for path in files {
+      let metadata = fs::metadata(&path)?;
+      let name = path
+        .strip_prefix(&self.root)
+        .unwrap_or(&path)
+        .display()
+        .to_string();
+      let mut resource = RawResource::new(uri_for(&path), name);
+      resource.mime_type = Some(mime_type(&path).to_string());
+      resource.size = u32::try_from(metadata.len()).ok();
+      resources.push(resource.no_annotation());
+    }
+    Ok(resources)
+  }
+
+  /// 读取一个资源的文本内容
+  pub fn read(&self, uri: &str) -> Result<ResourceContents, McpError> {
+    let path = self.resolve(uri)?;
+    let metadata = fs::metadata(&path).map_err(|e| io_error(uri, &e))?;
+    if metadata.len() > MAX_RESOURCE_BYTES {
+      return Err(McpError::invalid_params(
+        format!("{uri} is larger than {MAX_RESOURCE_BYTES} bytes"),
+        None,
+      ));
+    }
+    let bytes = fs::read(&path).map_err(|e| io_error(uri, &e))?;
+    let text = String::from_utf8(bytes)
+      .map_err(|_| McpError::invalid_params(format!("{uri} is not a UTF-8 text file"), None))?;
+    Ok(ResourceContents::text(text, uri))
+  }
+
+  /// URI 还原成根目录下的一个普通文件，越界、被隐藏或不存在都当作找不到
+  pub fn resolve(&self, uri: &str) -> Result<PathBuf, McpError> {
+    let not_found = || McpError::resource_not_found(format!("no such resource: {uri}"), None);
+    let path = uri.strip_prefix(URI_PREFIX).ok_or_else(not_found)?;
+    let path = Path::new(path).canonicalize().map_err(|_| not_found())?;
+    let relative = path.strip_prefix(&self.root).map_err(|_| not_found())?;
+    if relative
+      .components()
+      .any(|component| is_hidden(&component.as_os_str().to_string_lossy()))
+      || !path.is_file()
+    {
+      return Err(not_found());
+    }
+    Ok(path)
+  }
+}
+
+pub fn uri_for(path: &Path) -> String {
+  format!("{URI_PREFIX}{}", path.display())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+  for entry in fs::read_dir(dir)? {
+    if files.len() >= MAX_LISTED_RESOURCES {
+      break;
+    }
+    let entry = entry?;
+    let name = entry.file_name();
+    if is_hidden(&name.to_string_lossy()) {
+      continue;
+    }
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+      collect_files(&entry.path(), files)?;
+    } else if file_type.is_file() {
+      files.push(entry.path());
+    }
+  }
+  Ok(())
+}
+
+/// `list` 跳过、`resolve` 拒绝的目录项：隐藏文件和构建产物
+fn is_hidden(name: &str) -> bool {
+  name.starts_with('.') || name == "target"
+}
+
+fn mime_type(path: &Path) -> &'static str {
+  match path.extension().and_then(|ext| ext.to_str()) {
+    Some("md") => "text/markdown",
+    Some("rs") => "text/x-rust",
+    Some("toml") => "application/toml",
+    Some("json") => "application/json",
+    Some("html") => "text/html",
+    _ => "text/plain",
+  }
+}
+
+fn io_error(uri: &str, error: &io::Error) -> McpError {
+  McpError::internal_error(format!("failed to read {uri}: {error}"), None)
+}
+
+#[cfg(test)]
+mod tests {
+  use rmcp::model::ErrorCode;
+
+  use super::*;
+
+  fn fixture() -> (tempfile::TempDir, FileResources) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    fs::create_dir_all(root.join("target")).unwrap();
+    fs::write(root.join("notes.md"), "# notes").unwrap();
+    fs::write(root.join(".env"), "TOKEN=secret").unwrap();
+    fs::write(root.join("target").join("build.log"), "log").unwrap();
+    fs::write(dir.path().join("outside.txt"), "outside").unwrap();
+    let resources = FileResources::new(&root).unwrap();
+    (dir, resources)
+  }
+
+  fn assert_not_found(resources: &FileResources, uri: &str) {
+    let error = resources.resolve(uri).unwrap_err();
+    assert_eq!(error.code, ErrorCode::RESOURCE_NOT_FOUND, "{uri}");
+  }
+
+  #[test]
+  fn listed_files_can_be_read() {
+    let (_dir, resources) = fixture();
+    let listed = resources.list().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert!(resources.read(&listed[0].uri).is_ok());
+  }
+
+  #[test]
+  fn hidden_files_are_not_found() {
+    let (_dir, resources) = fixture();
+    let root = resources.root().to_path_buf();
+    assert_not_found(&resources, &uri_for(&root.join(".env")));
+    assert_not_found(&resources, &uri_for(&root.join("target").join("build.log")));
+  }
+
+  #[test]
+  fn parent_traversal_is_not_found() {
+    let (_dir, resources) = fixture();
+    let root = resources.root().to_path_buf();
+    assert_not_found(
+      &resources,
+      &format!("{URI_PREFIX}{}/../outside.txt", root.display()),
+    );
+    assert_not_found(
+      &resources,
+      &format!("{URI_PREFIX}{}/../../etc/passwd", root.display()),
+    );
+  }
+}
@@ -0,0 +1,380 @@
+//! Ext types: the timestamp type of the MessagePack spec and application
+//! types registered on the command line with `--ext TYPE=DECODER`.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{HexDump, json, value::read_value};
+
+/// The only ext type the spec defines.
+pub(crate) const TIMESTAMP_TYPE: i8 = -1;
+
+/// How the payload of a registered ext type is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ExtDecoder {
+  /// 16 bytes, shown in the usual hyphenated form.
+  Uuid,
+  Utf8,
+  /// Big-endian signed integer of 1, 2, 4 or 8 bytes.
+  Int,
+  /// The payload is itself a MessagePack value.
+  MessagePack,
+  Hex,
+}
+
+impl ExtDecoder {
+  pub(crate) const NAMES: &'static str = "uuid, utf8, int, msgpack, hex";
+
+  pub(crate) fn name(self) -> &'static str {
+    match self {
+      Self::Uuid => "uuid",
+      Self::Utf8 => "utf8",
+      Self::Int => "int",
+      Self::MessagePack => "msgpack",
+      Self::Hex => "hex",
+    }
+  }
+
+  /// `None` if the payload does not fit the decoder.
+  pub(crate) fn decode(self, data: &[u8], exts: &ExtRegistry) -> Option<String> {
+    match self {
+      Self::Uuid => {
+        let data: &[u8; 16] = data.try_into().ok()?;
+        let hex = data.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        Some(format!(
+          "{}-{}-{}-{}-{}",
+          &hex[.. 8],
+          &hex[8 .. 12],
+          &hex[12 .. 16],
+          &hex[16 .. 20],
+          &hex[20 ..]
+        ))
+      }
+      Self::Utf8 => std::str::from_utf8(data).ok().map(str::to_owned),
+      Self::Int => {
+        let value = match data.len() {
+          1 => i8::from_be_bytes(data.try_into().ok()?).into(),
+          2 => i16::from_be_bytes(data.try_into().ok()?).into(),
+          4 => i32::from_be_bytes(data.try_into().ok()?).into(),
+          8 => i64::from_be_bytes(data.try_into().ok()?),
+          _ => return None,
+        };
+        Some(value.to_string())
+      }
+      Self::MessagePack => {
+        let mut rd = data;
+        let value = read_value(&mut rd).ok()?;
+        if !rd.is_empty() {
+          return None;
+        }
+        serde_json::to_string(&json::to_json(&value, exts)).ok()
+      }
+      Self::Hex => Some(HexDump(data).to_string()),
+    }
+  }
+}
+
+impl FromStr for ExtDecoder {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "uuid" => Ok(Self::Uuid),
+      "utf8" => Ok(Self::Utf8),
+      "int" => Ok(Self::Int),
+      "msgpack" => Ok(Self::MessagePack),
+      "hex" => Ok(Self::Hex),
+      _ => Err(format!("unknown decoder, expected one of {}", Self::NAMES)),
+    }
+  }
+}
+
+/// Parses `TYPE=DECODER`, e.g. `5=uuid`. Negative types are reserved by the
+/// spec.
+pub(crate) fn parse_ext_arg(arg: &str) -> Result<(i8, ExtDecoder), String> {
+  let (ty, decoder) = arg
+    .split_once('=')
+    .ok_or_else(|| String::from("expected TYPE=DECODER"))?;
+  let ty = ty
+    .trim()
+    .parse::<i8>()
+    .ok()
+    .filter(|ty| *ty >= 0)
+    .ok_or_else(|| format!("ext type must be between 0 and 127, got {ty}"))?;
+  Ok((ty, decoder.trim().parse()?))
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExtRegistry {
+  decoders: BTreeMap<i8, ExtDecoder>,
+}
+
+impl ExtRegistry {
+  pub(crate) fn register(&mut self, ty: i8, decoder: ExtDecoder) {
+    self.decoders.insert(ty, decoder);
+  }
+
+  pub(crate) fn decoder(&self, ty: i8) -> Option<ExtDecoder> {
+    self.decoders.get(&ty).copied()
+  }
+
+  /// The payload as the marker dump shows it.
+  pub(crate) fn describe(&self, ty: i8, data: &[u8]) -> String {
+    if ty == TIMESTAMP_TYPE
+      && let Some(timestamp) = Timestamp::decode(data)
+    {
+      return format!("Timestamp {timestamp}");
+    }
+    match self.decoder(ty) {
+      Some(decoder) => match decoder.decode(data, self) {
+        Some(decoded) => format!("{ty}, {} {decoded}", decoder.name()),
+        None => format!("{ty}, {} not {}", HexDump(data), decoder.name()),
+      },
+      None => format!("{ty}, {}", HexDump(data)),
+    }
+  }
+}
+
+/// Seconds and nanoseconds since the Unix epoch, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Timestamp {
+  pub(crate) seconds: i64,
+  pub(crate) nanos: u32,
+}
+
+impl Timestamp {
+  /// Accepts the 32, 64 and 96 bit forms of the spec.
+  pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+    let timestamp = match data.len() {
+      4 => Self {
+        seconds: u32::from_be_bytes(data.try_into().ok()?).into(),
+        nanos: 0,
+      },
+      8 => {
+        let packed = u64::from_be_bytes(data.try_into().ok()?);
+        Self {
+          seconds: (packed & 0x3_ffff_ffff) as i64,
+          nanos: (packed >> 34) as u32,
+        }
+      }
+      12 => Self {
+        seconds: i64::from_be_bytes(data[4 ..].try_into().ok()?),
+        nanos: u32::from_be_bytes(data[.. 4].try_into().ok()?),
+      },
+      _ => return None,
+    };
+    (timestamp.nanos < 1_000_000_000).then_some(timestamp)
+  }
+
+  /// The smallest form that holds the timestamp.
+  pub(crate) fn encode(self) -> Vec<u8> {
+    if self.seconds >> 34 == 0 {
+      let packed = (u64::from(self.nanos) << 34) | self.seconds as u64;
+      if packed >> 32 == 0 {
+        (packed as u32).to_be_bytes().to_vec()
+      } else {
+        packed.to_be_bytes().to_vec()
+      }
+    } else {
+      let mut data = self.nanos.to_be_bytes().to_vec();
+      data.extend_from_slice(&self.seconds.to_be_bytes());
+      data
+    }
+  }
+}
+
+/// RFC 3339 in UTC, with as many fraction digits as needed.
+impl fmt::Display for Timestamp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let days = self.seconds.div_euclid(86_400);
+    let seconds_of_day = self.seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    write!(
+      f,
+      "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+      seconds_of_day / 3600,
+      seconds_of_day / 60 % 60,
+      seconds_of_day % 60
+    )?;
+    if self.nanos != 0 {
+      let fraction = format!("{:09}", self.nanos);
+      write!(f, ".{}", fraction.trim_end_matches('0'))?;
+    }
+    f.write_str("Z")
+  }
+}
+
+impl FromStr for Timestamp {
+  type Err = String;
+
+  /// Parses what [`Display`](fmt::Display) writes:
+  /// `YYYY-MM-DDTHH:MM:SS[.fraction]Z`.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid timestamp {s:?}, expected YYYY-MM-DDTHH:MM:SS[.fraction]Z");
+
+    let rest = s.strip_suffix('Z').ok_or_else(invalid)?;
+    let (date, time) = rest.split_once('T').ok_or_else(invalid)?;
+    let (negative, date) = match date.strip_prefix('-') {
+      Some(date) => (true, date),
+      None => (false, date),
+    };
+    let mut date = date.splitn(3, '-');
+    let mut date_part = || date.next().and_then(|part| part.parse::<i64>().ok());
+    let (Some(year), Some(month), Some(day)) = (date_part(), date_part(), date_part()) else {
+      return Err(invalid());
+    };
+    let year = if negative { -year } else { year };
+
+    let (time, fraction) = match time.split_once('.') {
+      Some((time, fraction)) => (time, Some(fraction)),
+      None => (time, None),
+    };
+    let mut time = time.splitn(3, ':');
+    let mut time_part = || time.next().and_then(|part| part.parse::<i64>().ok());
+    let (Some(hour), Some(minute), Some(second)) = (time_part(), time_part(), time_part()) else {
+      return Err(invalid());
+    };
+    if !(1 ..= 12).contains(&month)
+      || !(1 ..= 31).contains(&day)
+      || hour > 23
+      || minute > 59
+      || second > 59
+    {
+      return Err(invalid());
+    }
+
+    let nanos = match fraction {
+      Some(fraction)
+        if (1 ..= 9).contains(&fraction.len()) && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+      {
+        format!("{fraction:0<9}")
+          .parse::<u32>()
+          .map_err(|_| invalid())?
+      }
+      Some(_) => return Err(invalid()),
+      None => 0,
+    };
+
+    Ok(Self {
+      seconds: days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second,
+      nanos,
+    })
+  }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, after Howard
+/// Hinnant's `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year.rem_euclid(400);
+  let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn timestamps_use_the_smallest_form() {
+    for (timestamp, len) in [
+      (
+        Timestamp {
+          seconds: 1_700_000_000,
+          nanos: 0,
+        },
+        4,
+      ),
+      (
+        Timestamp {
+          seconds: 1_700_000_000,
+          nanos: 500,
+        },
+        8,
+      ),
+      (
+        Timestamp {
+          seconds: -1,
+          nanos: 999_999_999,
+        },
+        12,
+      ),
+      (
+        Timestamp {
+          seconds: 1 << 40,
+          nanos: 0,
+        },
+        12,
+      ),
+    ] {
+      let data = timestamp.encode();
+      assert_eq!(data.len(), len);
+      assert_eq!(Timestamp::decode(&data), Some(timestamp));
+    }
+  }
+
+  #[test]
+  fn timestamps_print_and_parse_as_rfc3339() {
+    for (timestamp, text) in [
+      (
+        Timestamp {
+          seconds: 0,
+          nanos: 0,
+        },
+        "1970-01-01T00:00:00Z",
+      ),
+      (
+        Timestamp {
+          seconds: 951_782_400,
+          nanos: 120_000_000,
+        },
+        "2000-02-29T00:00:00.12Z",
+      ),
+      (
+        Timestamp {
+          seconds: -1,
+          nanos: 1,
+        },
+        "1969-12-31T23:59:59.000000001Z",
+      ),
+    ] {
+      assert_eq!(timestamp.to_string(), text);
+      assert_eq!(text.parse::<Timestamp>(), Ok(timestamp));
+    }
+    assert!("2024-13-01T00:00:00Z".parse::<Timestamp>().is_err());
+  }
+
+  #[test]
+  fn registered_types_are_decoded() {
+    let mut exts = ExtRegistry::default();
+    exts.register(5, ExtDecoder::Uuid);
+    exts.register(6, ExtDecoder::Int);
+
+    let uuid = (0 .. 16).collect::<Vec<u8>>();
+    assert_eq!(
+      exts.describe(5, &uuid),
+      "5, uuid 00010203-0405-0607-0809-0a0b0c0d0e0f"
+    );
+    assert_eq!(exts.describe(6, &(-2i16).to_be_bytes()), "6, int -2");
+    assert_eq!(exts.describe(5, &[1, 2]), "5, 0102 not uuid");
+    assert_eq!(exts.describe(7, &[1, 2]), "7, 0102");
+    assert_eq!(parse_ext_arg("5=uuid"), Ok((5, ExtDecoder::Uuid)));
+    assert!(parse_ext_arg("-1=hex").is_err());
+  }
+}
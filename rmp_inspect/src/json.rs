@@ -0,0 +1,272 @@
+//! Lossless mapping between MessagePack values and JSON.
+//!
+//! Values JSON has no type for become an object with a single `$` key:
+//!
+//! - `{"$f32": 1.5}` and `{"$f64": "NaN"}` for 32 bit floats and for non-finite 64 bit floats,
+//!   finite ones are plain numbers
+//! - `{"$bin": "hex"}` and `{"$str_hex": "hex"}` for binary and for strings that are not valid
+//!   UTF-8
+//! - `{"$timestamp": "2024-01-02T03:04:05.5Z"}` for timestamp exts
+//! - `{"$ext": {"type": 5, "data": "hex"}}` for other exts, plus the decoded payload under the
+//!   decoder name if the type was registered
+//! - `{"$map": [[key, value], ...]}` for maps that a JSON object cannot hold: keys that are not
+//!   strings, duplicate keys, or a single key that looks like one of these tags
+//!
+//! Converting back gives the same values. Integers are written in their
+//! smallest encoding, so the bytes can differ from a capture that used a
+//! wider one.
+
+use std::error::Error;
+
+use serde_json::{Map, Number, Value as Json, json};
+
+use crate::{
+  ext::{ExtRegistry, TIMESTAMP_TYPE, Timestamp},
+  value::{Value, invalid_data},
+};
+
+const TAGS: [&str; 7] = [
+  "$f32",
+  "$f64",
+  "$bin",
+  "$str_hex",
+  "$timestamp",
+  "$ext",
+  "$map",
+];
+
+pub(crate) fn to_json(value: &Value, exts: &ExtRegistry) -> Json {
+  match value {
+    Value::Nil => Json::Null,
+    Value::Bool(value) => Json::Bool(*value),
+    Value::UInt(value) => Json::from(*value),
+    Value::Int(value) => Json::from(*value),
+    Value::F32(value) => json!({ "$f32": float_to_json(f64::from(*value)) }),
+    Value::F64(value) if value.is_finite() => float_to_json(*value),
+    Value::F64(value) => json!({ "$f64": float_to_json(*value) }),
+    Value::Str(bytes) => match std::str::from_utf8(bytes) {
+      Ok(text) => Json::from(text),
+      Err(_) => json!({ "$str_hex": to_hex(bytes) }),
+    },
+    Value::Bin(bytes) => json!({ "$bin": to_hex(bytes) }),
+    Value::Array(items) => Json::Array(items.iter().map(|item| to_json(item, exts)).collect()),
+    Value::Map(entries) => map_to_json(entries, exts),
+    Value::Ext(TIMESTAMP_TYPE, data) if Timestamp::decode(data).is_some() => {
+      let timestamp = Timestamp::decode(data).expect("checked by the guard");
+      json!({ "$timestamp": timestamp.to_string() })
+    }
+    Value::Ext(ty, data) => {
+      let mut ext = Map::new();
+      ext.insert("type".into(), Json::from(*ty));
+      ext.insert("data".into(), Json::from(to_hex(data)));
+      if let Some(decoder) = exts.decoder(*ty)
+        && let Some(decoded) = decoder.decode(data, exts)
+      {
+        ext.insert(decoder.name().into(), Json::from(decoded));
+      }
+      json!({ "$ext": ext })
+    }
+  }
+}
+
+/// Finite floats are numbers, the rest strings `serde_json` would not write.
+fn float_to_json(value: f64) -> Json {
+  match Number::from_f64(value) {
+    Some(number) => Json::Number(number),
+    None if value.is_nan() => Json::from("NaN"),
+    None if value > 0.0 => Json::from("inf"),
+    None => Json::from("-inf"),
+  }
+}
+
+fn map_to_json(entries: &[(Value, Value)], exts: &ExtRegistry) -> Json {
+  let mut object = Map::new();
+  for (key, value) in entries {
+    let key = match key {
+      Value::Str(bytes) => std::str::from_utf8(bytes).ok(),
+      _ => None,
+    };
+    match key {
+      Some(key) if !object.contains_key(key) => {
+        object.insert(key.to_owned(), to_json(value, exts));
+      }
+      _ => return tagged_map(entries, exts),
+    }
+  }
+  if object.len() == 1 && object.keys().all(|key| TAGS.contains(&key.as_str())) {
+    return tagged_map(entries, exts);
+  }
+  Json::Object(object)
+}
+
+fn tagged_map(entries: &[(Value, Value)], exts: &ExtRegistry) -> Json {
+  let pairs = entries
+    .iter()
+    .map(|(key, value)| json!([to_json(key, exts), to_json(value, exts)]))
+    .collect::<Vec<_>>();
+  json!({ "$map": pairs })
+}
+
+pub(crate) fn from_json(json: &Json) -> Result<Value, Box<dyn Error>> {
+  Ok(match json {
+    Json::Null => Value::Nil,
+    Json::Bool(value) => Value::Bool(*value),
+    Json::Number(number) => {
+      if let Some(value) = number.as_u64() {
+        Value::UInt(value)
+      } else if let Some(value) = number.as_i64() {
+        Value::Int(value)
+      } else {
+        Value::F64(number.as_f64().unwrap_or(f64::NAN))
+      }
+    }
+    Json::String(text) => Value::Str(text.clone().into_bytes()),
+    Json::Array(items) => Value::Array(items.iter().map(from_json).collect::<Result<_, _>>()?),
+    Json::Object(object) => {
+      if object.len() == 1
+        && let Some((tag, content)) = object.iter().next()
+        && TAGS.contains(&tag.as_str())
+      {
+        return from_tagged(tag, content);
+      }
+      Value::Map(
+        object
+          .iter()
+          .map(|(key, value)| Ok((Value::Str(key.clone().into_bytes()), from_json(value)?)))
+          .collect::<Result<_, Box<dyn Error>>>()?,
+      )
+    }
+  })
+}
+
+fn from_tagged(tag: &str, content: &Json) -> Result<Value, Box<dyn Error>> {
+  let invalid = || invalid_data(format!("invalid {tag} value: {content}"));
+  let hex = || content.as_str().and_then(from_hex).ok_or_else(invalid);
+
+  Ok(match tag {
+    "$f32" => Value::F32(float_from_json(content).ok_or_else(invalid)? as f32),
+    "$f64" => Value::F64(float_from_json(content).ok_or_else(invalid)?),
+    "$bin" => Value::Bin(hex()?),
+    "$str_hex" => Value::Str(hex()?),
+    "$timestamp" => {
+      let timestamp = content
+        .as_str()
+        .ok_or_else(invalid)?
+        .parse::<Timestamp>()
+        .map_err(invalid_data)?;
+      Value::Ext(TIMESTAMP_TYPE, timestamp.encode())
+    }
+    "$ext" => {
+      let ty = content
+        .get("type")
+        .and_then(Json::as_i64)
+        .and_then(|ty| i8::try_from(ty).ok())
+        .ok_or_else(invalid)?;
+      let data = content
+        .get("data")
+        .and_then(Json::as_str)
+        .and_then(from_hex)
+        .ok_or_else(invalid)?;
+      Value::Ext(ty, data)
+    }
+    "$map" => {
+      let pairs = content.as_array().ok_or_else(invalid)?;
+      let mut entries = Vec::with_capacity(pairs.len());
+      for pair in pairs {
+        match pair.as_array().map(Vec::as_slice) {
+          Some([key, value]) => entries.push((from_json(key)?, from_json(value)?)),
+          _ => return Err(invalid()),
+        }
+      }
+      Value::Map(entries)
+    }
+    _ => unreachable!("{tag} is not in TAGS"),
+  })
+}
+
+fn float_from_json(json: &Json) -> Option<f64> {
+  match json {
+    Json::Number(number) => number.as_f64(),
+    Json::String(text) => match text.as_str() {
+      "NaN" => Some(f64::NAN),
+      "inf" => Some(f64::INFINITY),
+      "-inf" => Some(f64::NEG_INFINITY),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+  if !text.len().is_multiple_of(2) {
+    return None;
+  }
+  (0 .. text.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(text.get(i .. i + 2)?, 16).ok())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn str(text: &str) -> Value {
+    Value::Str(text.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn round_trips_values_json_has_no_type_for() {
+    let value = Value::Map(vec![
+      (str("f32"), Value::F32(0.1)),
+      (str("nan"), Value::F64(f64::INFINITY)),
+      (str("float"), Value::F64(1.0)),
+      (str("int"), Value::Int(-5)),
+      (str("bin"), Value::Bin(vec![0, 255])),
+      (str("bad utf8"), Value::Str(vec![0xc3, 0x28])),
+      (str("when"), Value::Ext(-1, vec![0x65, 0x53, 0xf1, 0x00])),
+      (str("ext"), Value::Ext(5, vec![1, 2, 3])),
+      (
+        str("int keys"),
+        Value::Map(vec![(Value::UInt(1), str("one")), (Value::Nil, Value::Nil)]),
+      ),
+      (
+        str("looks tagged"),
+        Value::Map(vec![(str("$bin"), str("00"))]),
+      ),
+      (
+        str("duplicate keys"),
+        Value::Map(vec![(str("a"), Value::UInt(1)), (str("a"), Value::UInt(2))]),
+      ),
+    ]);
+
+    let json = to_json(&value, &ExtRegistry::default());
+    assert_eq!(
+      json["when"],
+      json!({ "$timestamp": "2023-11-14T22:13:20Z" })
+    );
+    assert_eq!(json["float"].to_string(), "1.0");
+    let text = serde_json::to_string(&json).unwrap();
+    let parsed = serde_json::from_str::<Json>(&text).unwrap();
+    assert_eq!(from_json(&parsed).unwrap(), value);
+  }
+
+  #[test]
+  fn keeps_the_key_order_of_maps() {
+    let value = Value::Map(vec![(str("z"), Value::Nil), (str("a"), Value::Nil)]);
+    let json = to_json(&value, &ExtRegistry::default());
+    assert_eq!(json.to_string(), r#"{"z":null,"a":null}"#);
+    assert_eq!(from_json(&json).unwrap(), value);
+  }
+
+  #[test]
+  fn rejects_malformed_tags() {
+    assert!(from_json(&json!({ "$bin": "abc" })).is_err());
+    assert!(from_json(&json!({ "$ext": { "type": 300, "data": "" } })).is_err());
+    assert!(from_json(&json!({ "$map": [[1]] })).is_err());
+  }
+}
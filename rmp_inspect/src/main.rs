@@ -1,22 +1,36 @@
+mod ext;
+mod json;
+mod query;
+mod stream;
+mod value;
+
 use std::{
   env,
   error::Error,
   ffi::{OsStr, OsString},
   fmt,
-  io::{self, Read},
-  path::{Path, PathBuf},
+  fs::File,
+  io::{self, BufRead, BufReader, Read, Write},
+  path::PathBuf,
   process,
 };
 
 use rmp::{Marker, decode::*};
+use serde_json::Value as Json;
+
+use crate::{
+  ext::{ExtDecoder, ExtRegistry, parse_ext_arg},
+  stream::{Endian, Framing, MessageReader, write_message},
+  value::{read_bin_data, read_value, write_value},
+};
 
 fn main() {
-  let action = match parse_args(env::args_os().skip(1)) {
+  let options = match parse_args(env::args_os().skip(1)) {
     Ok(Action::Help) => {
       print_usage();
       return;
     }
-    Ok(Action::Dump(path)) => path,
+    Ok(Action::Inspect(options)) => options,
     Err(err) => {
       eprintln!("error: {err}\n");
       print_usage_to_stderr();
@@ -24,7 +38,7 @@ fn main() {
     }
   };
 
-  if let Err(err) = dump_file(&action) {
+  if let Err(err) = run(&options) {
     eprintln!("error: {err}");
     process::exit(1);
   }
@@ -32,13 +46,56 @@ fn main() {
 
 #[derive(Debug, PartialEq, Eq)]
 enum Action {
-  Dump(PathBuf),
+  Inspect(Options),
   Help,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+  input: Input,
+  /// The input is JSON in the format of the `json` module rather than
+  /// MessagePack.
+  from_json: bool,
+  output: Output,
+  /// `None` reads a single message. JSON input in stream mode is a sequence
+  /// of whitespace separated documents, the framing then applies to
+  /// MessagePack output.
+  framing: Option<Framing>,
+  /// `--path` segments, empty for the whole message.
+  path: Vec<String>,
+  exts: ExtRegistry,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+  File(PathBuf),
+  Stdin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+  /// The marker dump, showing the exact encoding.
+  Dump,
+  Json,
+  MessagePack,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum CliError {
-  TooManyArguments { unexpected: OsString },
+  TooManyArguments {
+    unexpected: OsString,
+  },
+  UnknownOption {
+    option: OsString,
+  },
+  MissingValue {
+    option: &'static str,
+  },
+  InvalidValue {
+    option: &'static str,
+    value: String,
+    reason: String,
+  },
 }
 
 impl fmt::Display for CliError {
@@ -51,6 +108,15 @@ impl fmt::Display for CliError {
           unexpected.to_string_lossy()
         )
       }
+      Self::UnknownOption { option } => {
+        write!(f, "unknown option: {}", option.to_string_lossy())
+      }
+      Self::MissingValue { option } => write!(f, "{option} needs a value"),
+      Self::InvalidValue {
+        option,
+        value,
+        reason,
+      } => write!(f, "invalid value {value:?} for {option}: {reason}"),
     }
   }
 }
@@ -82,19 +148,88 @@ impl Error for ReadFileError {
 
 fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<Action, CliError> {
   let mut args = args.into_iter();
-  let Some(path) = args.next() else {
-    return Ok(Action::Dump(default_sample_path()));
-  };
+  let mut input = None;
+  let mut from_json = false;
+  let mut output = None;
+  let mut framing = None;
+  let mut path = Vec::new();
+  let mut exts = ExtRegistry::default();
+
+  while let Some(arg) = args.next() {
+    if is_help_arg(&arg) {
+      return Ok(Action::Help);
+    }
 
-  if is_help_arg(&path) {
-    return Ok(Action::Help);
+    match arg.to_str() {
+      Some("--dump") => output = Some(Output::Dump),
+      Some("--json") => output = Some(Output::Json),
+      Some("--msgpack") => output = Some(Output::MessagePack),
+      Some("--from-json") => from_json = true,
+      Some("--stream") => {
+        framing.get_or_insert(Framing::Concatenated);
+      }
+      Some("--length-prefix") => {
+        let value = option_value(&mut args, "--length-prefix")?;
+        let endian = Endian::parse(&value).map_err(|reason| CliError::InvalidValue {
+          option: "--length-prefix",
+          value,
+          reason,
+        })?;
+        framing = Some(Framing::LengthPrefixed(endian));
+      }
+      Some("--path") => path = query::parse_path(&option_value(&mut args, "--path")?),
+      Some("--ext") => {
+        let value = option_value(&mut args, "--ext")?;
+        let (ty, decoder) = parse_ext_arg(&value).map_err(|reason| CliError::InvalidValue {
+          option: "--ext",
+          value,
+          reason,
+        })?;
+        exts.register(ty, decoder);
+      }
+      Some("-") if input.is_none() => input = Some(Input::Stdin),
+      Some(option) if option.starts_with('-') && option != "-" => {
+        return Err(CliError::UnknownOption { option: arg });
+      }
+      _ if input.is_none() => input = Some(Input::File(PathBuf::from(arg))),
+      _ => return Err(CliError::TooManyArguments { unexpected: arg }),
+    }
   }
 
-  if let Some(unexpected) = args.next() {
-    return Err(CliError::TooManyArguments { unexpected });
-  }
+  // The sample file only makes sense for a single MessagePack message.
+  let input = input.unwrap_or_else(|| {
+    if from_json || framing.is_some() {
+      Input::Stdin
+    } else {
+      Input::File(default_sample_path())
+    }
+  });
+  let output = output.unwrap_or(if from_json {
+    Output::MessagePack
+  } else {
+    Output::Dump
+  });
+
+  Ok(Action::Inspect(Options {
+    input,
+    from_json,
+    output,
+    framing,
+    path,
+    exts,
+  }))
+}
 
-  Ok(Action::Dump(PathBuf::from(path)))
+fn option_value(
+  args: &mut impl Iterator<Item = OsString>,
+  option: &'static str,
+) -> Result<String, CliError> {
+  let value = args.next().ok_or(CliError::MissingValue { option })?;
+  value.into_string().map_err(|value| CliError::InvalidValue {
+    option,
+    value: value.to_string_lossy().into_owned(),
+    reason: String::from("not valid UTF-8"),
+  })
 }
 
 fn is_help_arg(arg: &OsStr) -> bool {
@@ -113,24 +248,123 @@ fn print_usage_to_stderr() {
   eprintln!("{}", usage());
 }
 
-fn usage() -> &'static str {
-  "Usage: rmp_inspect [PATH]\n\nInspect MessagePack bytes from PATH.\nWhen PATH is omitted, \
-   examples/sample.msgpack is used."
+fn usage() -> String {
+  format!(
+    "Usage: rmp_inspect [OPTIONS] [PATH]
+
+Inspect MessagePack bytes from PATH, or from stdin when PATH is -.
+When PATH is omitted, examples/sample.msgpack is used, or stdin with
+--stream, --length-prefix or --from-json.
+
+Options:
+  --dump                  Show every marker with its value (default)
+  --json                  Convert to JSON, types JSON lacks are tagged
+                          objects like {{\"$bin\": \"00ff\"}}
+  --msgpack               Write MessagePack (default with --from-json)
+  --from-json             Read JSON as written by --json
+  --stream                Read concatenated messages until end of input
+  --length-prefix be|le   Read messages each prefixed with a 32 bit length,
+                          MessagePack output keeps the prefix
+  --path FIELD.0.NAME     Only show the value at a path of map keys and
+                          array indexes, messages without it are skipped
+                          in stream mode
+  --ext TYPE=DECODER      Decode ext TYPE (0-127) with one of {}
+  -h, --help              Print this help",
+    ExtDecoder::NAMES
+  )
+}
+
+fn open(input: &Input) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+  match input {
+    Input::File(path) => {
+      let file = File::open(path).map_err(|source| ReadFileError {
+        path: path.clone(),
+        source,
+      })?;
+      Ok(Box::new(BufReader::new(file)))
+    }
+    Input::Stdin => Ok(Box::new(io::stdin().lock())),
+  }
 }
 
-fn dump_file(path: &Path) -> Result<(), Box<dyn Error>> {
-  let data = std::fs::read(path).map_err(|source| -> Box<dyn Error> {
-    Box::new(ReadFileError {
-      path: path.to_path_buf(),
-      source,
-    })
-  })?;
-  dump(&mut Indent { i: 0, start: true }, &mut data.as_slice())?;
-  println!();
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+  let mut rd = open(&options.input)?;
+
+  match (options.framing, options.from_json) {
+    (None, false) => {
+      let mut data = Vec::new();
+      rd.read_to_end(&mut data)?;
+      emit(options, &data, true)?;
+    }
+    (None, true) => {
+      let json = serde_json::from_reader::<_, Json>(rd)?;
+      emit(options, &json_to_message(&json)?, true)?;
+    }
+    (Some(framing), false) => {
+      let mut messages = MessageReader::new(rd, framing);
+      while let Some(message) = messages.next_message()? {
+        emit(options, &message, false)?;
+      }
+    }
+    (Some(_), true) => {
+      for json in serde_json::Deserializer::from_reader(rd).into_iter::<Json>() {
+        emit(options, &json_to_message(&json?)?, false)?;
+      }
+    }
+  }
   Ok(())
 }
 
-fn dump(indent: &mut Indent, rd: &mut &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+fn json_to_message(json: &Json) -> Result<Vec<u8>, Box<dyn Error>> {
+  let mut message = Vec::new();
+  write_value(&mut message, &json::from_json(json)?)?;
+  Ok(message)
+}
+
+/// Writes the selected part of one message. JSON is pretty-printed for a
+/// single message and one line per message in stream mode.
+fn emit(options: &Options, message: &[u8], single: bool) -> Result<(), Box<dyn Error>> {
+  let Some(mut selected) = query::select(message, &options.path)? else {
+    if single {
+      return Err(value::invalid_data(format!(
+        "no value at path {}",
+        options.path.join(".")
+      )));
+    }
+    return Ok(());
+  };
+
+  match options.output {
+    Output::Dump => {
+      dump(
+        &mut Indent { i: 0, start: true },
+        &mut selected,
+        &options.exts,
+      )?;
+      println!();
+    }
+    Output::Json => {
+      let json = json::to_json(&read_value(&mut selected)?, &options.exts);
+      if single {
+        println!("{}", serde_json::to_string_pretty(&json)?);
+      } else {
+        println!("{}", serde_json::to_string(&json)?);
+      }
+    }
+    Output::MessagePack => {
+      let mut stdout = io::stdout().lock();
+      write_message(&mut stdout, options.framing, selected)?;
+      stdout.flush()?;
+    }
+  }
+  Ok(())
+}
+
+fn dump(
+  indent: &mut Indent,
+  rd: &mut &[u8],
+  exts: &ExtRegistry,
+) -> Result<(), Box<dyn std::error::Error>> {
   match read_marker(rd).map_err(ValueReadError::from)? {
     Marker::FixPos(n) => print!("U0({n})"),
     Marker::FixNeg(n) => print!("I0({n})"),
@@ -169,25 +403,37 @@ fn dump(indent: &mut Indent, rd: &mut &[u8]) -> Result<(), Box<dyn std::error::E
       "Bin32({})",
       HexDump(&read_bin_data(rd.read_data_u32()?, rd)?)
     ),
-    Marker::FixArray(len) => dump_array(indent, 0, len.into(), rd)?,
-    Marker::Array16 => dump_array(indent, 16, rd.read_data_u16()?.into(), rd)?,
-    Marker::Array32 => dump_array(indent, 32, rd.read_data_u32()?, rd)?,
-    Marker::FixMap(len) => dump_map(indent, 0, len.into(), rd)?,
-    Marker::Map16 => dump_map(indent, 16, rd.read_data_u16()?.into(), rd)?,
-    Marker::Map32 => dump_map(indent, 32, rd.read_data_u32()?, rd)?,
-    Marker::FixExt1 => return Err(unsupported_marker("FixExt1")),
-    Marker::FixExt2 => return Err(unsupported_marker("FixExt2")),
-    Marker::FixExt4 => return Err(unsupported_marker("FixExt4")),
-    Marker::FixExt8 => return Err(unsupported_marker("FixExt8")),
-    Marker::FixExt16 => return Err(unsupported_marker("FixExt16")),
-    Marker::Ext8 => return Err(unsupported_marker("Ext8")),
-    Marker::Ext16 => return Err(unsupported_marker("Ext16")),
-    Marker::Ext32 => return Err(unsupported_marker("Ext32")),
+    Marker::FixArray(len) => dump_array(indent, 0, len.into(), rd, exts)?,
+    Marker::Array16 => dump_array(indent, 16, rd.read_data_u16()?.into(), rd, exts)?,
+    Marker::Array32 => dump_array(indent, 32, rd.read_data_u32()?, rd, exts)?,
+    Marker::FixMap(len) => dump_map(indent, 0, len.into(), rd, exts)?,
+    Marker::Map16 => dump_map(indent, 16, rd.read_data_u16()?.into(), rd, exts)?,
+    Marker::Map32 => dump_map(indent, 32, rd.read_data_u32()?, rd, exts)?,
+    Marker::FixExt1 => dump_ext("FixExt1", 1, rd, exts)?,
+    Marker::FixExt2 => dump_ext("FixExt2", 2, rd, exts)?,
+    Marker::FixExt4 => dump_ext("FixExt4", 4, rd, exts)?,
+    Marker::FixExt8 => dump_ext("FixExt8", 8, rd, exts)?,
+    Marker::FixExt16 => dump_ext("FixExt16", 16, rd, exts)?,
+    Marker::Ext8 => dump_ext("Ext8", rd.read_data_u8()?.into(), rd, exts)?,
+    Marker::Ext16 => dump_ext("Ext16", rd.read_data_u16()?.into(), rd, exts)?,
+    Marker::Ext32 => dump_ext("Ext32", rd.read_data_u32()?, rd, exts)?,
     Marker::Reserved => return Err(unsupported_marker("Reserved")),
   }
   Ok(())
 }
 
+fn dump_ext(
+  marker: &str,
+  len: u32,
+  rd: &mut &[u8],
+  exts: &ExtRegistry,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let ty = rd.read_data_i8()?;
+  let data = read_bin_data(len, rd)?;
+  print!("{marker}({})", exts.describe(ty, &data));
+  Ok(())
+}
+
 fn unsupported_marker(marker: &'static str) -> Box<dyn Error> {
   io::Error::new(
     io::ErrorKind::InvalidData,
@@ -201,6 +447,7 @@ fn dump_map(
   ty: u8,
   len: u32,
   rd: &mut &[u8],
+  exts: &ExtRegistry,
 ) -> Result<(), Box<dyn std::error::Error>> {
   indent.print(format_args!("Map{ty}{{"));
   let multiline = len > 1;
@@ -212,9 +459,9 @@ fn dump_map(
   indent.ind();
   for i in 0 .. len {
     indent.print("");
-    dump(indent, rd)?;
+    dump(indent, rd, exts)?;
     print!(": ");
-    dump(indent, rd)?;
+    dump(indent, rd, exts)?;
     if multiline {
       print!(",");
       indent.ln();
@@ -232,6 +479,7 @@ fn dump_array(
   ty: u8,
   len: u32,
   rd: &mut &[u8],
+  exts: &ExtRegistry,
 ) -> Result<(), Box<dyn std::error::Error>> {
   indent.print(format_args!("Array{ty}["));
   let multiline = len > 1;
@@ -243,7 +491,7 @@ fn dump_array(
   indent.ind();
   for i in 0 .. len {
     indent.print("");
-    dump(indent, rd)?;
+    dump(indent, rd, exts)?;
     if multiline {
       print!(",");
       indent.ln();
@@ -260,15 +508,6 @@ fn read_str_data<R: Read>(len: u32, rd: &mut R) -> Result<String, io::Error> {
   Ok(String::from_utf8_lossy(&read_bin_data(len, rd)?).into_owned())
 }
 
-fn read_bin_data<R: Read>(len: u32, rd: &mut R) -> Result<Vec<u8>, io::Error> {
-  let mut buf = Vec::with_capacity(len.min(1 << 16) as usize);
-  let bytes_read = rd.take(u64::from(len)).read_to_end(&mut buf)?;
-  if bytes_read != len as usize {
-    return Err(io::ErrorKind::UnexpectedEof.into());
-  }
-  Ok(buf)
-}
-
 struct Indent {
   i: u16,
   start: bool,
@@ -320,9 +559,27 @@ impl fmt::Display for HexDump<'_> {
 mod tests {
   use super::*;
 
+  fn args(args: &[&str]) -> Result<Action, CliError> {
+    parse_args(args.iter().map(OsString::from))
+  }
+
+  fn inspect(input: Input) -> Options {
+    Options {
+      input,
+      from_json: false,
+      output: Output::Dump,
+      framing: None,
+      path: Vec::new(),
+      exts: ExtRegistry::default(),
+    }
+  }
+
   #[test]
   fn no_args_use_sample_file() {
-    assert_eq!(parse_args([]).unwrap(), Action::Dump(default_sample_path()));
+    assert_eq!(
+      parse_args([]).unwrap(),
+      Action::Inspect(inspect(Input::File(default_sample_path())))
+    );
   }
 
   #[test]
@@ -332,13 +589,18 @@ mod tests {
       Action::Help
     );
     assert_eq!(parse_args([OsString::from("-h")]).unwrap(), Action::Help);
+    assert_eq!(args(&["--json", "-h"]).unwrap(), Action::Help);
   }
 
   #[test]
   fn path_arg_is_dumped() {
     assert_eq!(
       parse_args([OsString::from("data.msgpack")]).unwrap(),
-      Action::Dump(PathBuf::from("data.msgpack"))
+      Action::Inspect(inspect(Input::File(PathBuf::from("data.msgpack"))))
+    );
+    assert_eq!(
+      args(&["-"]).unwrap(),
+      Action::Inspect(inspect(Input::Stdin))
     );
   }
 
@@ -351,4 +613,65 @@ mod tests {
       })
     );
   }
+
+  #[test]
+  fn options_are_parsed() {
+    let mut exts = ExtRegistry::default();
+    exts.register(5, ExtDecoder::Uuid);
+    assert_eq!(
+      args(&[
+        "--length-prefix",
+        "le",
+        "--json",
+        "--path",
+        "users.0",
+        "--ext",
+        "5=uuid"
+      ])
+      .unwrap(),
+      Action::Inspect(Options {
+        output: Output::Json,
+        framing: Some(Framing::LengthPrefixed(Endian::Little)),
+        path: vec![String::from("users"), String::from("0")],
+        exts,
+        ..inspect(Input::Stdin)
+      })
+    );
+    assert_eq!(
+      args(&["--from-json", "in.json"]).unwrap(),
+      Action::Inspect(Options {
+        from_json: true,
+        output: Output::MessagePack,
+        ..inspect(Input::File(PathBuf::from("in.json")))
+      })
+    );
+  }
+
+  #[test]
+  fn bad_options_are_rejected() {
+    assert_eq!(
+      args(&["--verbose"]),
+      Err(CliError::UnknownOption {
+        option: OsString::from("--verbose")
+      })
+    );
+    assert_eq!(
+      args(&["--path"]),
+      Err(CliError::MissingValue { option: "--path" })
+    );
+    assert!(matches!(
+      args(&["--length-prefix", "middle"]),
+      Err(CliError::InvalidValue {
+        option: "--length-prefix",
+        ..
+      })
+    ));
+    assert!(matches!(
+      args(&["--ext", "5=base64"]),
+      Err(CliError::InvalidValue {
+        option: "--ext",
+        ..
+      })
+    ));
+  }
 }
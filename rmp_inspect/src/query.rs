@@ -0,0 +1,129 @@
+//! `--path` queries. The field is located in the raw bytes, so the marker
+//! dump of a field shows the same encoding as the dump of the whole message.
+
+use std::error::Error;
+
+use rmp::{Marker, decode::*};
+
+use crate::value::{Value, read_value};
+
+/// Splits `users.0.name` into its segments. An empty path selects the
+/// whole message.
+pub(crate) fn parse_path(path: &str) -> Vec<String> {
+  path
+    .split('.')
+    .filter(|segment| !segment.is_empty())
+    .map(str::to_owned)
+    .collect()
+}
+
+/// The bytes of the value at `path`, `None` if the message has no such
+/// field. Map keys match a segment as a string or as an integer, arrays are
+/// indexed by number.
+pub(crate) fn select<'a>(
+  message: &'a [u8],
+  path: &[String],
+) -> Result<Option<&'a [u8]>, Box<dyn Error>> {
+  let mut rd = message;
+  for segment in path {
+    let found = match read_marker(&mut rd).map_err(ValueReadError::from)? {
+      Marker::FixArray(len) => find_item(&mut rd, len.into(), segment)?,
+      Marker::Array16 => {
+        let len = rd.read_data_u16()?.into();
+        find_item(&mut rd, len, segment)?
+      }
+      Marker::Array32 => {
+        let len = rd.read_data_u32()?;
+        find_item(&mut rd, len, segment)?
+      }
+      Marker::FixMap(len) => find_entry(&mut rd, len.into(), segment)?,
+      Marker::Map16 => {
+        let len = rd.read_data_u16()?.into();
+        find_entry(&mut rd, len, segment)?
+      }
+      Marker::Map32 => {
+        let len = rd.read_data_u32()?;
+        find_entry(&mut rd, len, segment)?
+      }
+      _ => false,
+    };
+    if !found {
+      return Ok(None);
+    }
+  }
+
+  let start = rd;
+  read_value(&mut rd)?;
+  Ok(Some(&start[.. start.len() - rd.len()]))
+}
+
+/// Leaves `rd` at the item `segment` indexes.
+fn find_item(rd: &mut &[u8], len: u32, segment: &str) -> Result<bool, Box<dyn Error>> {
+  let Ok(index) = segment.parse::<u32>() else {
+    return Ok(false);
+  };
+  if index >= len {
+    return Ok(false);
+  }
+  for _ in 0 .. index {
+    read_value(rd)?;
+  }
+  Ok(true)
+}
+
+/// Leaves `rd` at the value of the first key matching `segment`.
+fn find_entry(rd: &mut &[u8], len: u32, segment: &str) -> Result<bool, Box<dyn Error>> {
+  for _ in 0 .. len {
+    let key = read_value(rd)?;
+    if key_matches(&key, segment) {
+      return Ok(true);
+    }
+    read_value(rd)?;
+  }
+  Ok(false)
+}
+
+fn key_matches(key: &Value, segment: &str) -> bool {
+  match key {
+    Value::Str(bytes) => bytes == segment.as_bytes(),
+    Value::UInt(key) => segment.parse::<u64>() == Ok(*key),
+    Value::Int(key) => segment.parse::<i64>() == Ok(*key),
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::value::write_value;
+
+  fn str(text: &str) -> Value {
+    Value::Str(text.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn selects_nested_fields() {
+    let users = Value::Array(vec![
+      Value::Map(vec![(str("name"), str("ann"))]),
+      Value::Map(vec![
+        (str("name"), str("bob")),
+        (Value::UInt(7), Value::Bool(true)),
+      ]),
+    ]);
+    let message = Value::Map(vec![(str("count"), Value::UInt(2)), (str("users"), users)]);
+    let mut bytes = Vec::new();
+    write_value(&mut bytes, &message).unwrap();
+
+    let select_value = |path: &str| {
+      select(&bytes, &parse_path(path))
+        .unwrap()
+        .map(|mut field| read_value(&mut field).unwrap())
+    };
+    assert_eq!(select_value("users.1.name"), Some(str("bob")));
+    assert_eq!(select_value("users.1.7"), Some(Value::Bool(true)));
+    assert_eq!(select_value(""), Some(message.clone()));
+    assert_eq!(select_value("users.2"), None);
+    assert_eq!(select_value("count.x"), None);
+    assert_eq!(select_value("missing"), None);
+  }
+}
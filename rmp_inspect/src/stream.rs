@@ -0,0 +1,145 @@
+//! Splitting a stream of MessagePack messages, either concatenated or each
+//! prefixed with its length as a 32 bit integer.
+
+use std::{
+  error::Error,
+  io::{self, BufRead, Read, Write},
+};
+
+use crate::value::{read_bin_data, read_value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Framing {
+  Concatenated,
+  LengthPrefixed(Endian),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Endian {
+  Big,
+  Little,
+}
+
+impl Endian {
+  pub(crate) fn parse(arg: &str) -> Result<Self, String> {
+    match arg {
+      "be" => Ok(Self::Big),
+      "le" => Ok(Self::Little),
+      _ => Err(String::from("expected be or le")),
+    }
+  }
+}
+
+pub(crate) struct MessageReader<R> {
+  inner: R,
+  framing: Framing,
+}
+
+impl<R: BufRead> MessageReader<R> {
+  pub(crate) fn new(inner: R, framing: Framing) -> Self {
+    Self { inner, framing }
+  }
+
+  /// The raw bytes of the next message, `None` at the end of the stream. A
+  /// message cut off by the end of the stream is an error.
+  pub(crate) fn next_message(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if self.inner.fill_buf()?.is_empty() {
+      return Ok(None);
+    }
+
+    match self.framing {
+      Framing::Concatenated => {
+        let mut recorder = Recorder {
+          inner: &mut self.inner,
+          bytes: Vec::new(),
+        };
+        read_value(&mut recorder)?;
+        Ok(Some(recorder.bytes))
+      }
+      Framing::LengthPrefixed(endian) => {
+        let mut prefix = [0; 4];
+        self.inner.read_exact(&mut prefix)?;
+        let len = match endian {
+          Endian::Big => u32::from_be_bytes(prefix),
+          Endian::Little => u32::from_le_bytes(prefix),
+        };
+        Ok(Some(read_bin_data(len, &mut self.inner)?))
+      }
+    }
+  }
+}
+
+/// Keeps the bytes the decoder consumed, which are exactly one message.
+struct Recorder<'a, R> {
+  inner: &'a mut R,
+  bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<'_, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.inner.read(buf)?;
+    self.bytes.extend_from_slice(&buf[.. read]);
+    Ok(read)
+  }
+}
+
+/// Writes `message` with the framing of the stream, nothing is added
+/// between concatenated messages.
+pub(crate) fn write_message(
+  wr: &mut impl Write,
+  framing: Option<Framing>,
+  message: &[u8],
+) -> Result<(), Box<dyn Error>> {
+  if let Some(Framing::LengthPrefixed(endian)) = framing {
+    let len = u32::try_from(message.len())?;
+    wr.write_all(&match endian {
+      Endian::Big => len.to_be_bytes(),
+      Endian::Little => len.to_le_bytes(),
+    })?;
+  }
+  wr.write_all(message)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_all(bytes: &[u8], framing: Framing) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut reader = MessageReader::new(bytes, framing);
+    let mut messages = Vec::new();
+    while let Some(message) = reader.next_message()? {
+      messages.push(message);
+    }
+    Ok(messages)
+  }
+
+  #[test]
+  fn splits_concatenated_messages() {
+    // 1, "ab", [true, nil]
+    let bytes = [0x01, 0xa2, b'a', b'b', 0x92, 0xc3, 0xc0];
+    assert_eq!(
+      read_all(&bytes, Framing::Concatenated).unwrap(),
+      [vec![0x01], vec![0xa2, b'a', b'b'], vec![0x92, 0xc3, 0xc0]]
+    );
+    assert!(read_all(&bytes[.. 6], Framing::Concatenated).is_err());
+  }
+
+  #[test]
+  fn splits_length_prefixed_messages() {
+    let mut bytes = Vec::new();
+    for message in [&[0x01][..], &[0xa1, b'x']] {
+      write_message(
+        &mut bytes,
+        Some(Framing::LengthPrefixed(Endian::Little)),
+        message,
+      )
+      .unwrap();
+    }
+    assert_eq!(
+      read_all(&bytes, Framing::LengthPrefixed(Endian::Little)).unwrap(),
+      [vec![0x01], vec![0xa1, b'x']]
+    );
+    assert!(read_all(&bytes[.. 7], Framing::LengthPrefixed(Endian::Little)).is_err());
+  }
+}
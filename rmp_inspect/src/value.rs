@@ -0,0 +1,203 @@
+//! Decoded MessagePack values, for the conversions that need a whole tree.
+//! The marker dump in `main.rs` works on the raw bytes instead, so it can
+//! show the exact encoding.
+
+use std::{
+  error::Error,
+  io::{self, Read},
+};
+
+use rmp::{Marker, decode::*, encode};
+
+/// Deeper nesting is rejected rather than risking a stack overflow on a
+/// corrupt capture.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+  Nil,
+  Bool(bool),
+  /// Every unsigned marker and positive fixint.
+  UInt(u64),
+  /// Every signed marker and negative fixint.
+  Int(i64),
+  F32(f32),
+  F64(f64),
+  /// Raw bytes, strings in captures are not always valid UTF-8.
+  Str(Vec<u8>),
+  Bin(Vec<u8>),
+  Array(Vec<Value>),
+  Map(Vec<(Value, Value)>),
+  Ext(i8, Vec<u8>),
+}
+
+pub(crate) fn read_value<R: Read>(rd: &mut R) -> Result<Value, Box<dyn Error>> {
+  read_nested(rd, 0)
+}
+
+fn read_nested<R: Read>(rd: &mut R, depth: usize) -> Result<Value, Box<dyn Error>> {
+  if depth > MAX_DEPTH {
+    return Err(invalid_data(format!(
+      "MessagePack nested deeper than {MAX_DEPTH} levels"
+    )));
+  }
+
+  let value = match read_marker(rd).map_err(ValueReadError::from)? {
+    Marker::FixPos(n) => Value::UInt(n.into()),
+    Marker::FixNeg(n) => Value::Int(n.into()),
+    Marker::Null => Value::Nil,
+    Marker::True => Value::Bool(true),
+    Marker::False => Value::Bool(false),
+    Marker::U8 => Value::UInt(rd.read_data_u8()?.into()),
+    Marker::U16 => Value::UInt(rd.read_data_u16()?.into()),
+    Marker::U32 => Value::UInt(rd.read_data_u32()?.into()),
+    Marker::U64 => Value::UInt(rd.read_data_u64()?),
+    Marker::I8 => Value::Int(rd.read_data_i8()?.into()),
+    Marker::I16 => Value::Int(rd.read_data_i16()?.into()),
+    Marker::I32 => Value::Int(rd.read_data_i32()?.into()),
+    Marker::I64 => Value::Int(rd.read_data_i64()?),
+    Marker::F32 => Value::F32(rd.read_data_f32()?),
+    Marker::F64 => Value::F64(rd.read_data_f64()?),
+    Marker::FixStr(len) => Value::Str(read_bin_data(len.into(), rd)?),
+    Marker::Str8 => Value::Str(read_bin_data(rd.read_data_u8()?.into(), rd)?),
+    Marker::Str16 => Value::Str(read_bin_data(rd.read_data_u16()?.into(), rd)?),
+    Marker::Str32 => Value::Str(read_bin_data(rd.read_data_u32()?, rd)?),
+    Marker::Bin8 => Value::Bin(read_bin_data(rd.read_data_u8()?.into(), rd)?),
+    Marker::Bin16 => Value::Bin(read_bin_data(rd.read_data_u16()?.into(), rd)?),
+    Marker::Bin32 => Value::Bin(read_bin_data(rd.read_data_u32()?, rd)?),
+    Marker::FixArray(len) => read_array(len.into(), rd, depth)?,
+    Marker::Array16 => read_array(rd.read_data_u16()?.into(), rd, depth)?,
+    Marker::Array32 => read_array(rd.read_data_u32()?, rd, depth)?,
+    Marker::FixMap(len) => read_map(len.into(), rd, depth)?,
+    Marker::Map16 => read_map(rd.read_data_u16()?.into(), rd, depth)?,
+    Marker::Map32 => read_map(rd.read_data_u32()?, rd, depth)?,
+    Marker::FixExt1 => read_ext(1, rd)?,
+    Marker::FixExt2 => read_ext(2, rd)?,
+    Marker::FixExt4 => read_ext(4, rd)?,
+    Marker::FixExt8 => read_ext(8, rd)?,
+    Marker::FixExt16 => read_ext(16, rd)?,
+    Marker::Ext8 => read_ext(rd.read_data_u8()?.into(), rd)?,
+    Marker::Ext16 => read_ext(rd.read_data_u16()?.into(), rd)?,
+    Marker::Ext32 => read_ext(rd.read_data_u32()?, rd)?,
+    Marker::Reserved => return Err(invalid_data("reserved MessagePack marker 0xc1".into())),
+  };
+  Ok(value)
+}
+
+fn read_array<R: Read>(len: u32, rd: &mut R, depth: usize) -> Result<Value, Box<dyn Error>> {
+  let mut items = Vec::with_capacity(len.min(1 << 10) as usize);
+  for _ in 0 .. len {
+    items.push(read_nested(rd, depth + 1)?);
+  }
+  Ok(Value::Array(items))
+}
+
+fn read_map<R: Read>(len: u32, rd: &mut R, depth: usize) -> Result<Value, Box<dyn Error>> {
+  let mut entries = Vec::with_capacity(len.min(1 << 10) as usize);
+  for _ in 0 .. len {
+    let key = read_nested(rd, depth + 1)?;
+    let value = read_nested(rd, depth + 1)?;
+    entries.push((key, value));
+  }
+  Ok(Value::Map(entries))
+}
+
+fn read_ext<R: Read>(len: u32, rd: &mut R) -> Result<Value, Box<dyn Error>> {
+  let ty = rd.read_data_i8()?;
+  Ok(Value::Ext(ty, read_bin_data(len, rd)?))
+}
+
+pub(crate) fn read_bin_data<R: Read>(len: u32, rd: &mut R) -> Result<Vec<u8>, io::Error> {
+  let mut buf = Vec::with_capacity(len.min(1 << 16) as usize);
+  let bytes_read = rd.take(u64::from(len)).read_to_end(&mut buf)?;
+  if bytes_read != len as usize {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+  Ok(buf)
+}
+
+/// Integers are written in their smallest encoding, everything else keeps
+/// its type.
+pub(crate) fn write_value(wr: &mut Vec<u8>, value: &Value) -> Result<(), Box<dyn Error>> {
+  match value {
+    Value::Nil => encode::write_nil(wr)?,
+    Value::Bool(value) => encode::write_bool(wr, *value)?,
+    Value::UInt(value) => {
+      encode::write_uint(wr, *value)?;
+    }
+    Value::Int(value) => {
+      encode::write_sint(wr, *value)?;
+    }
+    Value::F32(value) => encode::write_f32(wr, *value)?,
+    Value::F64(value) => encode::write_f64(wr, *value)?,
+    Value::Str(bytes) => {
+      encode::write_str_len(wr, length(bytes.len())?)?;
+      wr.extend_from_slice(bytes);
+    }
+    Value::Bin(bytes) => {
+      encode::write_bin_len(wr, length(bytes.len())?)?;
+      wr.extend_from_slice(bytes);
+    }
+    Value::Array(items) => {
+      encode::write_array_len(wr, length(items.len())?)?;
+      for item in items {
+        write_value(wr, item)?;
+      }
+    }
+    Value::Map(entries) => {
+      encode::write_map_len(wr, length(entries.len())?)?;
+      for (key, value) in entries {
+        write_value(wr, key)?;
+        write_value(wr, value)?;
+      }
+    }
+    Value::Ext(ty, data) => {
+      encode::write_ext_meta(wr, length(data.len())?, *ty)?;
+      wr.extend_from_slice(data);
+    }
+  }
+  Ok(())
+}
+
+fn length(len: usize) -> Result<u32, Box<dyn Error>> {
+  u32::try_from(len).map_err(|_| invalid_data(format!("{len} is too long for MessagePack")))
+}
+
+pub(crate) fn invalid_data(message: String) -> Box<dyn Error> {
+  io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trips_every_type() {
+    let value = Value::Map(vec![
+      (Value::Str(b"nil".to_vec()), Value::Nil),
+      (
+        Value::UInt(300),
+        Value::Array(vec![Value::Int(-70_000), Value::Bool(true)]),
+      ),
+      (Value::Str(vec![0xff, 0xfe]), Value::Bin(vec![1, 2, 3])),
+      (Value::F32(1.5), Value::F64(-0.25)),
+      (Value::Ext(7, vec![9; 3]), Value::Ext(-1, vec![0; 4])),
+    ]);
+
+    let mut bytes = Vec::new();
+    write_value(&mut bytes, &value).unwrap();
+    assert_eq!(read_value(&mut bytes.as_slice()).unwrap(), value);
+  }
+
+  #[test]
+  fn rejects_truncated_input() {
+    // Str8 announcing 5 bytes with only 2 present.
+    assert!(read_value(&mut [0xd9, 5, b'a', b'b'].as_slice()).is_err());
+  }
+
+  #[test]
+  fn rejects_excessive_nesting() {
+    let bytes = vec![0x91; MAX_DEPTH + 2];
+    assert!(read_value(&mut bytes.as_slice()).is_err());
+  }
+}
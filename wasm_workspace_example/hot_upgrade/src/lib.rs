@@ -1,11 +1,15 @@
+pub mod rollout;
 pub mod rule;
 pub mod service;
 pub mod types;
 pub mod wasm_rule;
 pub mod wasm_service;
 
+pub use rollout::RuleHandler;
 pub use service::{HotServiceHandle, StartedService, start_service};
-pub use types::{Decision, Request, Response, ServiceSnapshot, State};
+pub use types::{
+  Decision, Disagreement, Request, Response, RolloutSnapshot, Routing, ServiceSnapshot, State,
+};
 pub use wasm_service::{WasmServiceHandle, WasmStartedService, start_wasm_service};
 
 #[cfg(test)]
@@ -66,4 +70,150 @@ mod tests {
     service.shutdown().await?;
     Ok(())
   }
+
+  #[tokio::test]
+  async fn percent_canary_promotes_with_all_counts() -> Result<()> {
+    let service = start_service(rules_dir().join("current/risk_rule.json"))?;
+    let handle = service.handle();
+
+    let message = handle
+      .start_rollout(
+        rules_dir().join("releases/risk_rule_v2.json"),
+        Routing::Percent(50),
+      )
+      .await?;
+    assert_eq!(
+      message,
+      "rollout risk_rule_v1 -> risk_rule_v2 (50% of requests)"
+    );
+
+    let mut versions = Vec::new();
+    for user_id in 0 .. 4 {
+      let response = handle
+        .call(Request {
+          user_id,
+          amount: 3_000,
+        })
+        .await?;
+      versions.push(response.rule_version);
+    }
+    assert_eq!(
+      versions,
+      [
+        "risk_rule_v1",
+        "risk_rule_v2",
+        "risk_rule_v1",
+        "risk_rule_v2"
+      ]
+    );
+
+    let rollout = handle
+      .snapshot()
+      .await?
+      .rollout
+      .expect("rollout is running");
+    assert_eq!(rollout.stable_served, 2);
+    assert_eq!(rollout.candidate_served, 2);
+
+    assert!(
+      handle
+        .upgrade(rules_dir().join("releases/risk_rule_v2.json"))
+        .await
+        .is_err()
+    );
+
+    let message = handle.promote().await?;
+    assert_eq!(message, "promote risk_rule_v1 -> risk_rule_v2");
+
+    let snapshot = handle.snapshot().await?;
+    assert_eq!(snapshot.processed, 4);
+    assert_eq!(snapshot.schema_version, 2);
+    assert_eq!(snapshot.upgrades, 1);
+    assert_eq!(snapshot.current_rule_version, "risk_rule_v2");
+    assert!(snapshot.rollout.is_none());
+
+    service.shutdown().await?;
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn shadow_records_disagreements_and_rolls_back() -> Result<()> {
+    let service = start_service(rules_dir().join("current/risk_rule.json"))?;
+    let handle = service.handle();
+
+    handle
+      .start_rollout(
+        rules_dir().join("releases/risk_rule_v2.json"),
+        Routing::Shadow,
+      )
+      .await?;
+
+    for (user_id, amount) in [(2, 3_000), (9, 7_000), (3, 1_000)] {
+      let response = handle.call(Request { user_id, amount }).await?;
+      assert_eq!(response.rule_version, "risk_rule_v1");
+      assert_eq!(response.decision, Decision::Allow);
+    }
+
+    let rollout = handle
+      .snapshot()
+      .await?
+      .rollout
+      .expect("rollout is running");
+    assert_eq!(rollout.stable_served, 3);
+    assert_eq!(rollout.shadowed, 3);
+    assert_eq!(rollout.disagreement_count, 2);
+    let candidates = rollout
+      .disagreements
+      .iter()
+      .map(|disagreement| disagreement.candidate.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(candidates, [Decision::AllowFastLane, Decision::Review]);
+
+    let message = handle.rollback().await?;
+    assert_eq!(message, "rollback risk_rule_v2 -> risk_rule_v1");
+
+    let snapshot = handle.snapshot().await?;
+    assert_eq!(snapshot.processed, 3);
+    assert_eq!(snapshot.schema_version, 1);
+    assert_eq!(snapshot.fast_lane_hits, 0);
+    assert_eq!(snapshot.upgrades, 0);
+    assert_eq!(snapshot.current_rule_version, "risk_rule_v1");
+
+    service.shutdown().await?;
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn user_hash_routing_is_sticky_per_user() -> Result<()> {
+    let service = start_service(rules_dir().join("current/risk_rule.json"))?;
+    let handle = service.handle();
+
+    handle
+      .start_rollout(
+        rules_dir().join("releases/risk_rule_v2.json"),
+        Routing::UserHash(50),
+      )
+      .await?;
+
+    let mut seen = std::collections::HashMap::new();
+    for _ in 0 .. 3 {
+      for user_id in 0 .. 20 {
+        let response = handle
+          .call(Request {
+            user_id,
+            amount: 1_000,
+          })
+          .await?;
+        let version = seen.entry(user_id).or_insert(response.rule_version.clone());
+        assert_eq!(*version, response.rule_version);
+      }
+    }
+    assert!(seen.values().any(|version| version == "risk_rule_v1"));
+    assert!(seen.values().any(|version| version == "risk_rule_v2"));
+
+    assert!(handle.set_routing(Routing::Percent(101)).await.is_err());
+
+    service.shutdown().await?;
+    Ok(())
+  }
 }
@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use hot_upgrade::{Request, start_service};
+use hot_upgrade::{Request, Routing, start_service};
 
 fn rules_dir() -> PathBuf {
   PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules")
@@ -27,10 +27,57 @@ async fn main() -> Result<()> {
   }
 
   let message = handle
-    .upgrade(rules_dir().join("releases/risk_rule_v2.json"))
+    .start_rollout(
+      rules_dir().join("releases/risk_rule_v2.json"),
+      Routing::Shadow,
+    )
     .await?;
   println!("{message}");
 
+  for request in [
+    Request {
+      user_id: 4,
+      amount: 2_000,
+    },
+    Request {
+      user_id: 5,
+      amount: 8_000,
+    },
+  ] {
+    let response = handle.call(request).await?;
+    println!("{}:{}", response.rule_version, response.decision);
+  }
+
+  if let Some(rollout) = handle.snapshot().await?.rollout {
+    for disagreement in rollout.disagreements {
+      println!(
+        "shadow disagreement user={} amount={}: {} vs {}",
+        disagreement.request.user_id,
+        disagreement.request.amount,
+        disagreement.stable,
+        disagreement.candidate
+      );
+    }
+  }
+
+  handle.set_routing(Routing::Percent(50)).await?;
+  for request in [
+    Request {
+      user_id: 6,
+      amount: 1_000,
+    },
+    Request {
+      user_id: 7,
+      amount: 1_000,
+    },
+  ] {
+    let response = handle.call(request).await?;
+    println!("{}:{}", response.rule_version, response.decision);
+  }
+
+  let message = handle.promote().await?;
+  println!("{message}");
+
   for request in [
     Request {
       user_id: 2,
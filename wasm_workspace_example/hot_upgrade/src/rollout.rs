@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use anyhow::{Result, anyhow};
+
+use crate::types::{Decision, Disagreement, Request, Response, RolloutSnapshot, Routing, State};
+
+/// Disagreements kept for inspection; older ones are only counted.
+const MAX_DISAGREEMENTS: usize = 32;
+
+/// What a service needs from a rule to run two versions of it side by side.
+pub trait RuleHandler {
+  fn version(&self) -> &str;
+  fn handle(&mut self, state: &mut State, request: Request) -> Result<Response>;
+  fn migrate_state(&self, state: &mut State) -> Result<()>;
+}
+
+/// A candidate rule running next to the stable one until it is promoted or
+/// rolled back.
+///
+/// Two rule versions may need different state schemas, so the candidate serves
+/// from its own copy of the state, migrated when the rollout starts. Whichever
+/// side survives absorbs the counters the other side added in the meantime.
+pub(crate) struct Rollout<H> {
+  candidate: H,
+  routing: Routing,
+  candidate_state: State,
+  stable_base: State,
+  candidate_base: State,
+  requests: u64,
+  stable_served: u64,
+  candidate_served: u64,
+  shadowed: u64,
+  candidate_errors: u64,
+  disagreement_count: u64,
+  disagreements: VecDeque<Disagreement>,
+}
+
+impl<H: RuleHandler> Rollout<H> {
+  pub(crate) fn start(candidate: H, routing: Routing, state: &State) -> Result<Self> {
+    validate_routing(routing)?;
+    let mut candidate_state = state.clone();
+    candidate.migrate_state(&mut candidate_state)?;
+
+    Ok(Self {
+      candidate,
+      routing,
+      candidate_base: candidate_state.clone(),
+      candidate_state,
+      stable_base: state.clone(),
+      requests: 0,
+      stable_served: 0,
+      candidate_served: 0,
+      shadowed: 0,
+      candidate_errors: 0,
+      disagreement_count: 0,
+      disagreements: VecDeque::new(),
+    })
+  }
+
+  pub(crate) fn candidate_version(&self) -> &str {
+    self.candidate.version()
+  }
+
+  pub(crate) fn set_routing(&mut self, routing: Routing) -> Result<()> {
+    validate_routing(routing)?;
+    self.routing = routing;
+    Ok(())
+  }
+
+  /// Serves `request` with whichever rule the routing picks. In shadow mode
+  /// the stable rule answers and the candidate only gets compared.
+  pub(crate) fn call(
+    &mut self,
+    stable: &mut H,
+    state: &mut State,
+    request: Request,
+  ) -> Result<Response> {
+    let sequence = self.requests;
+    self.requests += 1;
+
+    if self.routing == Routing::Shadow {
+      let response = stable.handle(state, request.clone())?;
+      self.stable_served += 1;
+      self.shadow(request, &response.decision);
+      return Ok(response);
+    }
+
+    if picks_candidate(self.routing, sequence, request.user_id) {
+      self.candidate_served += 1;
+      let response = self.candidate.handle(&mut self.candidate_state, request);
+      if response.is_err() {
+        self.candidate_errors += 1;
+      }
+      response
+    } else {
+      self.stable_served += 1;
+      stable.handle(state, request)
+    }
+  }
+
+  fn shadow(&mut self, request: Request, stable: &Decision) {
+    let mut scratch = self.candidate_state.clone();
+    match self.candidate.handle(&mut scratch, request.clone()) {
+      Ok(response) => {
+        self.shadowed += 1;
+        if response.decision != *stable {
+          self.disagreement_count += 1;
+          if self.disagreements.len() == MAX_DISAGREEMENTS {
+            self.disagreements.pop_front();
+          }
+          self.disagreements.push_back(Disagreement {
+            request,
+            stable: stable.clone(),
+            candidate: response.decision,
+          });
+        }
+      }
+      Err(_) => self.candidate_errors += 1,
+    }
+  }
+
+  /// Ends the rollout with the candidate serving everything. Returns it with
+  /// its state, which now also counts what the stable rule served.
+  pub(crate) fn promote(self, state: &State) -> (H, State) {
+    let mut next = self.candidate_state;
+    next.absorb(state, &self.stable_base);
+    next.upgrades = state.upgrades + 1;
+    (self.candidate, next)
+  }
+
+  /// Ends the rollout with the stable rule serving everything again.
+  pub(crate) fn roll_back(self, state: &mut State) {
+    state.absorb(&self.candidate_state, &self.candidate_base);
+  }
+
+  pub(crate) fn snapshot(&self) -> RolloutSnapshot {
+    RolloutSnapshot {
+      candidate_rule_version: self.candidate.version().to_owned(),
+      routing: self.routing,
+      stable_served: self.stable_served,
+      candidate_served: self.candidate_served,
+      shadowed: self.shadowed,
+      candidate_errors: self.candidate_errors,
+      disagreement_count: self.disagreement_count,
+      disagreements: self.disagreements.iter().cloned().collect(),
+    }
+  }
+}
+
+fn validate_routing(routing: Routing) -> Result<()> {
+  match routing {
+    Routing::Percent(percent) | Routing::UserHash(percent) if percent > 100 => {
+      Err(anyhow!("routing percentage must be <= 100, got {percent}"))
+    }
+    _ => Ok(()),
+  }
+}
+
+/// `Percent` spreads candidate requests evenly: exactly `percent` out of every
+/// 100 consecutive requests. `UserHash` is sticky per user.
+fn picks_candidate(routing: Routing, sequence: u64, user_id: i64) -> bool {
+  match routing {
+    Routing::Shadow => false,
+    Routing::Percent(percent) => {
+      let percent = u64::from(percent);
+      (sequence + 1) * percent / 100 > sequence * percent / 100
+    }
+    Routing::UserHash(percent) => user_bucket(user_id) < u64::from(percent),
+  }
+}
+
+/// Maps a user id to a bucket in `0..100` (SplitMix64 finalizer), so
+/// neighbouring ids land in unrelated buckets.
+fn user_bucket(user_id: i64) -> u64 {
+  let mut z = (user_id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  (z ^ (z >> 31)) % 100
+}
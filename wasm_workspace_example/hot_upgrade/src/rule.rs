@@ -3,7 +3,10 @@ use std::{fs, path::Path};
 use anyhow::{Context, Result, anyhow};
 use serde::Deserialize;
 
-use crate::types::{Decision, Request, Response, State};
+use crate::{
+  rollout::RuleHandler,
+  types::{Decision, Request, Response, State},
+};
 
 #[derive(Debug, Clone, Deserialize)]
 struct RuleConfig {
@@ -122,3 +125,17 @@ impl RuleEngine {
     Ok(())
   }
 }
+
+impl RuleHandler for RuleEngine {
+  fn version(&self) -> &str {
+    RuleEngine::version(self)
+  }
+
+  fn handle(&mut self, state: &mut State, request: Request) -> Result<Response> {
+    RuleEngine::handle(self, state, request)
+  }
+
+  fn migrate_state(&self, state: &mut State) -> Result<()> {
+    RuleEngine::migrate_state(self, state)
+  }
+}
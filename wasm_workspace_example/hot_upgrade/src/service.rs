@@ -7,8 +7,9 @@ use tokio::{
 };
 
 use crate::{
+  rollout::Rollout,
   rule::RuleEngine,
-  types::{Request, Response, ServiceSnapshot, State},
+  types::{Request, Response, Routing, ServiceSnapshot, State},
 };
 
 enum Command {
@@ -20,6 +21,21 @@ enum Command {
     rule_path: PathBuf,
     reply: oneshot::Sender<Result<String>>,
   },
+  StartRollout {
+    rule_path: PathBuf,
+    routing: Routing,
+    reply: oneshot::Sender<Result<String>>,
+  },
+  SetRouting {
+    routing: Routing,
+    reply: oneshot::Sender<Result<()>>,
+  },
+  Promote {
+    reply: oneshot::Sender<Result<String>>,
+  },
+  Rollback {
+    reply: oneshot::Sender<Result<String>>,
+  },
   Snapshot {
     reply: oneshot::Sender<ServiceSnapshot>,
   },
@@ -31,6 +47,7 @@ enum Command {
 struct HotService {
   state: State,
   handler: RuleEngine,
+  rollout: Option<Rollout<RuleEngine>>,
   rx: mpsc::Receiver<Command>,
 }
 
@@ -39,13 +56,32 @@ impl HotService {
     while let Some(command) = self.rx.recv().await {
       match command {
         Command::Call { request, reply } => {
-          let response = self.handler.handle(&mut self.state, request);
+          let response = match &mut self.rollout {
+            Some(rollout) => rollout.call(&mut self.handler, &mut self.state, request),
+            None => self.handler.handle(&mut self.state, request),
+          };
           let _ = reply.send(response);
         }
         Command::Upgrade { rule_path, reply } => {
           let response = self.upgrade(rule_path);
           let _ = reply.send(response);
         }
+        Command::StartRollout {
+          rule_path,
+          routing,
+          reply,
+        } => {
+          let _ = reply.send(self.start_rollout(rule_path, routing));
+        }
+        Command::SetRouting { routing, reply } => {
+          let _ = reply.send(self.set_routing(routing));
+        }
+        Command::Promote { reply } => {
+          let _ = reply.send(self.promote());
+        }
+        Command::Rollback { reply } => {
+          let _ = reply.send(self.rollback());
+        }
         Command::Snapshot { reply } => {
           let _ = reply.send(self.snapshot());
         }
@@ -58,6 +94,7 @@ impl HotService {
   }
 
   fn upgrade(&mut self, rule_path: PathBuf) -> Result<String> {
+    self.ensure_no_rollout()?;
     let old_version = self.handler.version().to_owned();
     let next = RuleEngine::load(&rule_path)?;
 
@@ -72,6 +109,67 @@ impl HotService {
     ))
   }
 
+  fn start_rollout(&mut self, rule_path: PathBuf, routing: Routing) -> Result<String> {
+    self.ensure_no_rollout()?;
+    let next = RuleEngine::load(&rule_path)?;
+
+    self.validate(&next)?;
+    let rollout = Rollout::start(next, routing, &self.state)?;
+    let message = format!(
+      "rollout {} -> {} ({routing})",
+      self.handler.version(),
+      rollout.candidate_version()
+    );
+    self.rollout = Some(rollout);
+    Ok(message)
+  }
+
+  fn set_routing(&mut self, routing: Routing) -> Result<()> {
+    self
+      .rollout
+      .as_mut()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?
+      .set_routing(routing)
+  }
+
+  fn promote(&mut self) -> Result<String> {
+    let rollout = self
+      .rollout
+      .take()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?;
+    let old_version = self.handler.version().to_owned();
+    (self.handler, self.state) = rollout.promote(&self.state);
+
+    Ok(format!(
+      "promote {old_version} -> {}",
+      self.handler.version()
+    ))
+  }
+
+  fn rollback(&mut self) -> Result<String> {
+    let rollout = self
+      .rollout
+      .take()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?;
+    let candidate = rollout.candidate_version().to_owned();
+    rollout.roll_back(&mut self.state);
+
+    Ok(format!(
+      "rollback {candidate} -> {}",
+      self.handler.version()
+    ))
+  }
+
+  fn ensure_no_rollout(&self) -> Result<()> {
+    match &self.rollout {
+      Some(rollout) => Err(anyhow!(
+        "rollout of {} in progress; promote or roll it back first",
+        rollout.candidate_version()
+      )),
+      None => Ok(()),
+    }
+  }
+
   fn validate(&self, next: &RuleEngine) -> Result<()> {
     let mut shadow = self.state.clone();
     next.migrate_state(&mut shadow)?;
@@ -98,6 +196,7 @@ impl HotService {
       fast_lane_hits: self.state.fast_lane_hits,
       upgrades: self.state.upgrades,
       current_rule_version: self.handler.version().to_owned(),
+      rollout: self.rollout.as_ref().map(Rollout::snapshot),
     }
   }
 }
@@ -133,6 +232,62 @@ impl HotServiceHandle {
     rx.await.context("hot service dropped upgrade reply")?
   }
 
+  /// Runs the rule at `rule_path` next to the current one; `routing` decides
+  /// which requests it serves.
+  pub async fn start_rollout(
+    &self,
+    rule_path: impl Into<PathBuf>,
+    routing: Routing,
+  ) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(Command::StartRollout {
+        rule_path: rule_path.into(),
+        routing,
+        reply,
+      })
+      .await
+      .context("hot service is not available")?;
+
+    rx.await.context("hot service dropped rollout reply")?
+  }
+
+  pub async fn set_routing(&self, routing: Routing) -> Result<()> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(Command::SetRouting { routing, reply })
+      .await
+      .context("hot service is not available")?;
+
+    rx.await.context("hot service dropped routing reply")?
+  }
+
+  /// Makes the rollout's candidate the only rule.
+  pub async fn promote(&self) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(Command::Promote { reply })
+      .await
+      .context("hot service is not available")?;
+
+    rx.await.context("hot service dropped promote reply")?
+  }
+
+  /// Drops the rollout's candidate; the current rule serves everything again.
+  pub async fn rollback(&self) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(Command::Rollback { reply })
+      .await
+      .context("hot service is not available")?;
+
+    rx.await.context("hot service dropped rollback reply")?
+  }
+
   pub async fn snapshot(&self) -> Result<ServiceSnapshot> {
     let (reply, rx) = oneshot::channel();
     self
@@ -187,6 +342,7 @@ pub fn start_service(initial_rule: impl AsRef<Path>) -> Result<StartedService> {
     HotService {
       state,
       handler: initial,
+      rollout: None,
       rx,
     }
     .run(),
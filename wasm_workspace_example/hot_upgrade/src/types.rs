@@ -43,6 +43,57 @@ pub struct State {
   pub upgrades: u64,
 }
 
+impl State {
+  /// Adds what `other` counted since `base` to this state's counters.
+  pub(crate) fn absorb(&mut self, other: &State, base: &State) {
+    self.processed += other.processed - base.processed;
+    self.fast_lane_hits += other.fast_lane_hits - base.fast_lane_hits;
+  }
+}
+
+/// How requests are split between the stable rule and a candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routing {
+  /// Every request is answered by the stable rule; the candidate evaluates a
+  /// throwaway copy of its state and disagreements are recorded.
+  Shadow,
+  /// This percentage of requests, spread evenly, goes to the candidate.
+  Percent(u8),
+  /// Users whose id hashes into this percentage always go to the candidate.
+  UserHash(u8),
+}
+
+impl fmt::Display for Routing {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Shadow => write!(f, "shadow"),
+      Self::Percent(percent) => write!(f, "{percent}% of requests"),
+      Self::UserHash(percent) => write!(f, "{percent}% of users"),
+    }
+  }
+}
+
+/// A request the stable rule and the candidate decided differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disagreement {
+  pub request: Request,
+  pub stable: Decision,
+  pub candidate: Decision,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolloutSnapshot {
+  pub candidate_rule_version: String,
+  pub routing: Routing,
+  pub stable_served: u64,
+  pub candidate_served: u64,
+  pub shadowed: u64,
+  pub candidate_errors: u64,
+  pub disagreement_count: u64,
+  /// The most recent disagreements; older ones are only counted.
+  pub disagreements: Vec<Disagreement>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ServiceSnapshot {
   pub processed: u64,
@@ -50,4 +101,5 @@ pub struct ServiceSnapshot {
   pub fast_lane_hits: u64,
   pub upgrades: u64,
   pub current_rule_version: String,
+  pub rollout: Option<RolloutSnapshot>,
 }
@@ -3,7 +3,10 @@ use std::path::Path;
 use anyhow::{Result, anyhow};
 use wasmtime::{Engine, Instance, Module, Store, TypedFunc};
 
-use crate::types::{Decision, Request, Response, State};
+use crate::{
+  rollout::RuleHandler,
+  types::{Decision, Request, Response, State},
+};
 
 /// Wraps a loaded `.wasm` rule module and exposes `handle` / `migrate_state`.
 ///
@@ -134,3 +137,17 @@ impl WasmHandler {
     Ok(())
   }
 }
+
+impl RuleHandler for WasmHandler {
+  fn version(&self) -> &str {
+    WasmHandler::version(self)
+  }
+
+  fn handle(&mut self, state: &mut State, request: Request) -> Result<Response> {
+    WasmHandler::handle(self, state, request)
+  }
+
+  fn migrate_state(&self, state: &mut State) -> Result<()> {
+    WasmHandler::migrate_state(self, state)
+  }
+}
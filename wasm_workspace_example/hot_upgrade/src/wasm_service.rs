@@ -8,7 +8,8 @@ use tokio::{
 use wasmtime::Engine;
 
 use crate::{
-  types::{Request, Response, ServiceSnapshot, State},
+  rollout::Rollout,
+  types::{Request, Response, Routing, ServiceSnapshot, State},
   wasm_rule::WasmHandler,
 };
 
@@ -25,6 +26,21 @@ enum WasmCommand {
     wasm_path: PathBuf,
     reply: oneshot::Sender<Result<String>>,
   },
+  StartRollout {
+    wasm_path: PathBuf,
+    routing: Routing,
+    reply: oneshot::Sender<Result<String>>,
+  },
+  SetRouting {
+    routing: Routing,
+    reply: oneshot::Sender<Result<()>>,
+  },
+  Promote {
+    reply: oneshot::Sender<Result<String>>,
+  },
+  Rollback {
+    reply: oneshot::Sender<Result<String>>,
+  },
   Snapshot {
     reply: oneshot::Sender<ServiceSnapshot>,
   },
@@ -42,6 +58,8 @@ struct WasmHotService {
   engine: Engine,
   state: State,
   handler: WasmHandler,
+  /// Candidate module running next to `handler`, if any.
+  rollout: Option<Rollout<WasmHandler>>,
   rx: mpsc::Receiver<WasmCommand>,
 }
 
@@ -50,13 +68,32 @@ impl WasmHotService {
     while let Some(command) = self.rx.recv().await {
       match command {
         WasmCommand::Call { request, reply } => {
-          let response = self.handler.handle(&mut self.state, request);
+          let response = match &mut self.rollout {
+            Some(rollout) => rollout.call(&mut self.handler, &mut self.state, request),
+            None => self.handler.handle(&mut self.state, request),
+          };
           let _ = reply.send(response);
         }
         WasmCommand::Upgrade { wasm_path, reply } => {
           let result = self.upgrade(wasm_path);
           let _ = reply.send(result);
         }
+        WasmCommand::StartRollout {
+          wasm_path,
+          routing,
+          reply,
+        } => {
+          let _ = reply.send(self.start_rollout(wasm_path, routing));
+        }
+        WasmCommand::SetRouting { routing, reply } => {
+          let _ = reply.send(self.set_routing(routing));
+        }
+        WasmCommand::Promote { reply } => {
+          let _ = reply.send(self.promote());
+        }
+        WasmCommand::Rollback { reply } => {
+          let _ = reply.send(self.rollback());
+        }
         WasmCommand::Snapshot { reply } => {
           let _ = reply.send(self.snapshot());
         }
@@ -69,6 +106,7 @@ impl WasmHotService {
   }
 
   fn upgrade(&mut self, wasm_path: PathBuf) -> Result<String> {
+    self.ensure_no_rollout()?;
     let old_version = self.handler.version().to_owned();
     let mut next = WasmHandler::load(&self.engine, &wasm_path)?;
 
@@ -85,6 +123,67 @@ impl WasmHotService {
     ))
   }
 
+  fn start_rollout(&mut self, wasm_path: PathBuf, routing: Routing) -> Result<String> {
+    self.ensure_no_rollout()?;
+    let mut next = WasmHandler::load(&self.engine, &wasm_path)?;
+
+    self.validate(&mut next)?;
+    let rollout = Rollout::start(next, routing, &self.state)?;
+    let message = format!(
+      "rollout {} -> {} ({routing})",
+      self.handler.version(),
+      rollout.candidate_version()
+    );
+    self.rollout = Some(rollout);
+    Ok(message)
+  }
+
+  fn set_routing(&mut self, routing: Routing) -> Result<()> {
+    self
+      .rollout
+      .as_mut()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?
+      .set_routing(routing)
+  }
+
+  fn promote(&mut self) -> Result<String> {
+    let rollout = self
+      .rollout
+      .take()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?;
+    let old_version = self.handler.version().to_owned();
+    (self.handler, self.state) = rollout.promote(&self.state);
+
+    Ok(format!(
+      "promote {old_version} -> {}",
+      self.handler.version()
+    ))
+  }
+
+  fn rollback(&mut self) -> Result<String> {
+    let rollout = self
+      .rollout
+      .take()
+      .ok_or_else(|| anyhow!("no rollout in progress"))?;
+    let candidate = rollout.candidate_version().to_owned();
+    rollout.roll_back(&mut self.state);
+
+    Ok(format!(
+      "rollback {candidate} -> {}",
+      self.handler.version()
+    ))
+  }
+
+  fn ensure_no_rollout(&self) -> Result<()> {
+    match &self.rollout {
+      Some(rollout) => Err(anyhow!(
+        "rollout of {} in progress; promote or roll it back first",
+        rollout.candidate_version()
+      )),
+      None => Ok(()),
+    }
+  }
+
   fn validate(&self, next: &mut WasmHandler) -> Result<()> {
     let mut shadow = self.state.clone();
     next.migrate_state(&mut shadow)?;
@@ -111,6 +210,7 @@ impl WasmHotService {
       fast_lane_hits: self.state.fast_lane_hits,
       upgrades: self.state.upgrades,
       current_rule_version: self.handler.version().to_owned(),
+      rollout: self.rollout.as_ref().map(Rollout::snapshot),
     }
   }
 }
@@ -148,6 +248,59 @@ impl WasmServiceHandle {
     rx.await.context("wasm service dropped upgrade reply")?
   }
 
+  /// Runs the module at `wasm_path` next to the current one; `routing`
+  /// decides which requests it serves.
+  pub async fn start_rollout(
+    &self,
+    wasm_path: impl Into<PathBuf>,
+    routing: Routing,
+  ) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(WasmCommand::StartRollout {
+        wasm_path: wasm_path.into(),
+        routing,
+        reply,
+      })
+      .await
+      .context("wasm service is not available")?;
+    rx.await.context("wasm service dropped rollout reply")?
+  }
+
+  pub async fn set_routing(&self, routing: Routing) -> Result<()> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(WasmCommand::SetRouting { routing, reply })
+      .await
+      .context("wasm service is not available")?;
+    rx.await.context("wasm service dropped routing reply")?
+  }
+
+  /// Makes the rollout's candidate the only module.
+  pub async fn promote(&self) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(WasmCommand::Promote { reply })
+      .await
+      .context("wasm service is not available")?;
+    rx.await.context("wasm service dropped promote reply")?
+  }
+
+  /// Drops the rollout's candidate; the current module serves everything
+  /// again.
+  pub async fn rollback(&self) -> Result<String> {
+    let (reply, rx) = oneshot::channel();
+    self
+      .tx
+      .send(WasmCommand::Rollback { reply })
+      .await
+      .context("wasm service is not available")?;
+    rx.await.context("wasm service dropped rollback reply")?
+  }
+
   pub async fn snapshot(&self) -> Result<ServiceSnapshot> {
     let (reply, rx) = oneshot::channel();
     self
@@ -216,6 +369,7 @@ pub fn start_wasm_service(initial_rule: impl AsRef<Path>) -> Result<WasmStartedS
       engine,
       state,
       handler: initial,
+      rollout: None,
       rx,
     }
     .run(),
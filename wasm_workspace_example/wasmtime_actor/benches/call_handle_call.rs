@@ -19,9 +19,7 @@ wasmtime::component::bindgen!({
   require_store_data_send: true,
 });
 
-use demo::actor::host_actor::{
-  self, ActorMsg, ActorMsgKind, ActorResponse, ActorState, ActorStateV1,
-};
+use demo::actor::host_actor::{self, ActorMsg, ActorMsgKind, ActorResponse, ActorState};
 
 const GUEST_TARGET: &str = "wasm32-wasip2";
 const GUEST_WASM: &str = "wasmtime_actor.wasm";
@@ -150,17 +148,12 @@ fn tick_messages(count: usize) -> Vec<ActorMsg> {
     .collect()
 }
 
+/// The guest's schema 1 encoding of a fresh actor.
 fn initial_actor_state() -> ActorState {
-  ActorState::V1(ActorStateV1 {
-    tick: 0,
-    last_host_reply: 0,
-    elapsed_since_push: 0,
-    last_response: ActorResponse {
-      handled: 0,
-      reply: 0,
-      message: String::new(),
-    },
-  })
+  ActorState {
+    schema: 1,
+    bytes: br#"{"tick":0,"last_host_reply":0,"elapsed_since_push":0,"last_response":{"handled":0,"reply":0,"message":""}}"#.to_vec(),
+  }
 }
 
 fn ensure_guest_component() -> Result<PathBuf> {
@@ -2,6 +2,7 @@ use std::{
   collections::{HashMap, VecDeque},
   env,
   fmt::Write as _,
+  mem,
   path::{Path, PathBuf},
   process::Command,
   thread,
@@ -18,9 +19,7 @@ use wasmtime_wasi::{ResourceTable, WasiCtx, WasiCtxBuilder, WasiCtxView, WasiVie
 
 use crate::bindings::{
   ActorWorld,
-  demo::actor::host_actor::{
-    self, ActorMsg, ActorMsgKind, ActorResponse, ActorState, ActorStateV1,
-  },
+  demo::actor::host_actor::{self, ActorMsg, ActorMsgKind, ActorState},
 };
 
 const GUEST_TARGET: &str = "wasm32-wasip2";
@@ -28,6 +27,11 @@ const GUEST_WASM: &str = "wasmtime_actor.wasm";
 const LOOP_SLEEP_MILLIS: i32 = 500;
 const WASM_PROCESS_NAME: &str = "demo.actor.wasm";
 const DEFAULT_UPGRADE_TICK: i32 = 6;
+/// Calls a freshly upgraded module must survive before the previous module is
+/// dropped.
+const PROBATION_CALLS: usize = 3;
+/// Ticks between the scheduled releases.
+const RELEASE_INTERVAL_TICKS: i32 = 3;
 
 #[derive(Clone, Debug)]
 struct WasmProcessRef {
@@ -157,15 +161,34 @@ impl LoadedActor {
       .instance
       .wasm_actor()
       .call_handle_call(store, msgs, state)
-      .map_err(|err| anyhow!("{} handle-call failed: {err}", self.version))
+      .map_err(|err| anyhow!("{} handle-call failed: {}", self.version, err.root_cause()))
   }
 
-  fn migrate_state(&self, store: &mut Store<StoreState>, state: &ActorState) -> Result<ActorState> {
+  fn migrate(
+    &self,
+    store: &mut Store<StoreState>,
+    from_version: u32,
+    bytes: &[u8],
+  ) -> Result<ActorState> {
+    self
+      .instance
+      .wasm_actor()
+      .call_migrate(store, from_version, bytes)
+      .map_err(|err| anyhow!("{} migrate failed: {}", self.version, err.root_cause()))?
+      .map_err(|err| {
+        anyhow!(
+          "{} refused to migrate schema {from_version}: {err}",
+          self.version
+        )
+      })
+  }
+
+  fn initial_state(&self, store: &mut Store<StoreState>) -> Result<ActorState> {
     self
       .instance
       .wasm_actor()
-      .call_migrate_state(store, state)
-      .map_err(|err| anyhow!("{} migrate-state failed: {err}", self.version))
+      .call_initial_state(store)
+      .map_err(|err| anyhow!("{} initial-state failed: {err}", self.version))
   }
 
   fn render_state(&self, store: &mut Store<StoreState>, state: &ActorState) -> Result<String> {
@@ -224,13 +247,13 @@ pub fn run() -> Result<()> {
 
   if max_ticks == 0 {
     println!(
-      "host: driving wasm process {} ({}) through handle-call forever; soft-upgrade at tick \
+      "host: driving wasm process {} ({}) through handle-call forever; first release at tick \
        {upgrade_tick}; press Ctrl+C to stop",
       process.name, process.id
     );
   } else {
     println!(
-      "host: driving wasm process {} ({}) for {max_ticks} ticks; soft-upgrade at tick \
+      "host: driving wasm process {} ({}) for {max_ticks} ticks; first release at tick \
        {upgrade_tick}",
       process.name, process.id
     );
@@ -242,22 +265,45 @@ pub fn run() -> Result<()> {
     actor.component_path.display()
   );
 
-  let mut state = initial_actor_state();
-  let mut upgraded = false;
+  // guest-v3 goes out first and is rolled back: it migrates and passes the
+  // shadow run but traps on its first real tick. guest-v2 follows.
+  let mut releases = VecDeque::from([
+    (upgrade_tick, "guest-v3", guest_components.v3.clone()),
+    (
+      upgrade_tick + RELEASE_INTERVAL_TICKS,
+      "guest-v2",
+      guest_components.v2.clone(),
+    ),
+  ]);
+  let mut state = actor.initial_state(&mut store)?;
+  let mut probation: Option<Probation> = None;
+  let mut ticks = 0;
   let mut host_messages = initial_host_messages();
   loop {
-    if !upgraded && tick_of(&state) >= upgrade_tick {
-      let next = LoadedActor::load(
-        &engine,
-        &linker,
-        &mut store,
-        "guest-v2",
-        guest_components.v2.clone(),
-      )?;
-      let message = upgrade_actor(&mut store, &actor, next, &mut state)?;
-      actor = message.actor;
-      upgraded = true;
-      println!("{}", message.summary);
+    if probation.is_none() && releases.front().is_some_and(|(at, ..)| ticks >= *at) {
+      let (_, version, component_path) = releases.pop_front().expect("release is due");
+      let next = LoadedActor::load(&engine, &linker, &mut store, version, component_path)?;
+      match upgrade_actor(&mut store, &actor, &next, &state) {
+        Ok(migrated) => {
+          println!(
+            "host: soft upgrade {} schema {} -> {} schema {}; on probation for {PROBATION_CALLS} \
+             calls",
+            actor.version, actor.state_schema, next.version, next.state_schema
+          );
+          probation = Some(Probation {
+            previous: mem::replace(&mut actor, next),
+            previous_state: mem::replace(&mut state, migrated),
+            batches: Vec::new(),
+          });
+        }
+        Err(err) => {
+          println!(
+            "host: rejected upgrade to {version}: {err}; staying on {}",
+            actor.version
+          );
+          (store, actor) = reinstantiate(&engine, &linker, store, &actor)?;
+        }
+      }
     }
 
     let host_message = host_messages.pop_front();
@@ -275,14 +321,50 @@ pub fn run() -> Result<()> {
       });
     }
 
-    state = actor.handle_call(&mut store, &msgs, &state)?;
+    match actor.handle_call(&mut store, &msgs, &state) {
+      Ok(next) => {
+        state = next;
+        if let Some(trial) = &mut probation {
+          trial.batches.push(msgs);
+          if trial.batches.len() >= PROBATION_CALLS {
+            println!(
+              "host: {} passed probation; dropping {}",
+              actor.version, trial.previous.version
+            );
+            probation = None;
+          }
+        }
+      }
+      Err(err) => {
+        let Some(trial) = probation.take() else {
+          return Err(err);
+        };
+        println!(
+          "host: {} trapped on probation call {}: {err}",
+          actor.version,
+          trial.batches.len() + 1
+        );
+        let failed = actor.version;
+        (store, actor) = reinstantiate(&engine, &linker, store, &trial.previous)?;
+        state = trial.previous_state;
+        // The previous module never saw these batches; its host calls run
+        // again during the replay.
+        for batch in trial.batches.iter().chain([&msgs]) {
+          state = actor.handle_call(&mut store, batch, &state)?;
+        }
+        println!(
+          "host: rolled back {failed} -> {} and replayed {} batch(es)",
+          actor.version,
+          trial.batches.len() + 1
+        );
+      }
+    }
+    ticks += 1;
 
-    if max_ticks > 0 && tick_of(&state) >= max_ticks {
+    if max_ticks > 0 && ticks >= max_ticks {
       println!(
-        "host: leaving verification loop after {} ticks with {} state schema {}",
-        tick_of(&state),
-        actor.version,
-        actor.state_schema
+        "host: leaving verification loop after {ticks} ticks with {} state schema {}",
+        actor.version, actor.state_schema
       );
       break;
     }
@@ -303,17 +385,45 @@ pub fn run() -> Result<()> {
   Ok(())
 }
 
-struct UpgradeMessage {
-  actor: LoadedActor,
-  summary: String,
+/// A freshly upgraded module on trial. Until it has handled
+/// [`PROBATION_CALLS`] batches, the host keeps the module it replaced, the
+/// state that module handed over, and every batch since, so a trap can be
+/// undone by replaying those batches on the previous module.
+struct Probation {
+  previous: LoadedActor,
+  previous_state: ActorState,
+  batches: Vec<Vec<ActorMsg>>,
 }
 
+/// A trap poisons the whole store, not just the instance that trapped, so the
+/// surviving module is instantiated again in a fresh store that carries the
+/// same `StoreState`.
+fn reinstantiate(
+  engine: &Engine,
+  linker: &Linker<StoreState>,
+  store: Store<StoreState>,
+  actor: &LoadedActor,
+) -> Result<(Store<StoreState>, LoadedActor)> {
+  let mut store = Store::new(engine, store.into_data());
+  let actor = LoadedActor::load(
+    engine,
+    linker,
+    &mut store,
+    actor.version,
+    actor.component_path.clone(),
+  )?;
+  Ok((store, actor))
+}
+
+/// Migrates a copy of `state` to `next`'s schema by chaining its one-step
+/// `migrate` calls, then runs `next` once against that shadow copy. The live
+/// state is untouched unless every step succeeds.
 fn upgrade_actor(
   store: &mut Store<StoreState>,
   current: &LoadedActor,
-  next: LoadedActor,
-  state: &mut ActorState,
-) -> Result<UpgradeMessage> {
+  next: &LoadedActor,
+  state: &ActorState,
+) -> Result<ActorState> {
   if next.state_schema <= current.state_schema {
     bail!(
       "refusing actor upgrade {} schema {} -> {} schema {}",
@@ -324,64 +434,34 @@ fn upgrade_actor(
     );
   }
 
-  let mut shadow_state = next.migrate_state(store, state)?;
-  match (&*state, &shadow_state) {
-    (ActorState::V1(_), ActorState::V2(_)) => {}
-    (before, after) => {
+  let mut shadow_state = state.clone();
+  while shadow_state.schema < next.state_schema {
+    let from_version = shadow_state.schema;
+    shadow_state = next.migrate(store, from_version, &shadow_state.bytes)?;
+    if shadow_state.schema != from_version + 1 {
       bail!(
-        "upgrade did not change actor-state variant: before={}, after={}",
-        state_variant(before),
-        state_variant(after),
+        "{} migrated schema {from_version} to {} instead of {}",
+        next.version,
+        shadow_state.schema,
+        from_version + 1
       );
     }
+    println!(
+      "host: {} migrated actor-state schema {from_version} -> {}",
+      next.version, shadow_state.schema
+    );
   }
 
   shadow_state = next.handle_call(store, &[], &shadow_state)?;
-  if !matches!(shadow_state, ActorState::V2(_)) {
+  if shadow_state.schema != next.state_schema {
     bail!(
-      "new actor validation returned {} instead of v2 state",
-      state_variant(&shadow_state)
+      "new actor validation returned schema {} instead of {}",
+      shadow_state.schema,
+      next.state_schema
     );
   }
 
-  *state = shadow_state;
-
-  let summary = format!(
-    "host: soft upgrade {} schema {} -> {} schema {}; ActorState V1 migrated to V2",
-    current.version, current.state_schema, next.version, next.state_schema
-  );
-
-  Ok(UpgradeMessage {
-    actor: next,
-    summary,
-  })
-}
-
-fn initial_actor_state() -> ActorState {
-  ActorState::V1(ActorStateV1 {
-    tick: 0,
-    last_host_reply: 0,
-    elapsed_since_push: 0,
-    last_response: ActorResponse {
-      handled: 0,
-      reply: 0,
-      message: String::new(),
-    },
-  })
-}
-
-fn tick_of(state: &ActorState) -> i32 {
-  match state {
-    ActorState::V1(state) => state.tick,
-    ActorState::V2(state) => state.tick,
-  }
-}
-
-fn state_variant(state: &ActorState) -> &'static str {
-  match state {
-    ActorState::V1(_) => "v1",
-    ActorState::V2(_) => "v2",
-  }
+  Ok(shadow_state)
 }
 
 fn initial_host_messages() -> VecDeque<host_actor::HostMessage> {
@@ -437,6 +517,7 @@ fn upgrade_tick_from_env() -> Result<i32> {
 struct GuestComponents {
   v1: PathBuf,
   v2: PathBuf,
+  v3: PathBuf,
 }
 
 fn ensure_guest_components() -> Result<GuestComponents> {
@@ -449,6 +530,7 @@ fn ensure_guest_components() -> Result<GuestComponents> {
   Ok(GuestComponents {
     v1: build_guest_component(package_dir, &guest_target_dir, "guest-v1")?,
     v2: build_guest_component(package_dir, &guest_target_dir, "guest-v2")?,
+    v3: build_guest_component(package_dir, &guest_target_dir, "guest-v3")?,
   })
 }
 
@@ -1,30 +1,39 @@
 #![cfg(target_arch = "wasm32")]
 
-#[cfg(all(feature = "guest-v1", feature = "guest-v2"))]
-compile_error!("enable exactly one of `guest-v1` or `guest-v2` for the wasm guest");
+#[cfg(any(
+  all(feature = "guest-v1", feature = "guest-v2"),
+  all(feature = "guest-v1", feature = "guest-v3"),
+  all(feature = "guest-v2", feature = "guest-v3"),
+))]
+compile_error!("enable exactly one of `guest-v1`, `guest-v2` or `guest-v3` for the wasm guest");
 
-#[cfg(not(any(feature = "guest-v1", feature = "guest-v2")))]
-compile_error!("enable either `guest-v1` or `guest-v2` for the wasm guest");
+#[cfg(not(any(feature = "guest-v1", feature = "guest-v2", feature = "guest-v3")))]
+compile_error!("enable one of `guest-v1`, `guest-v2` or `guest-v3` for the wasm guest");
 
 wit_bindgen::generate!({
   path: "src/wit",
   world: "actor-world",
 });
 
-#[cfg(feature = "guest-v1")]
-use host_actor::ActorStateV1;
-#[cfg(feature = "guest-v2")]
-use host_actor::ActorStateV2;
+mod schema;
+
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+use host_actor::HostMessage;
 use host_actor::{ActorMsg, ActorMsgKind, ActorResponse, ActorState, GuestMessage};
+#[cfg(feature = "guest-v1")]
+use schema::StateV1;
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+use schema::StateV2;
+#[cfg(feature = "guest-v3")]
+use schema::StateV3;
+use schema::{Schema, decode, encode};
 
 use crate::demo::actor::host_actor;
 
 const LOOP_SLEEP_MILLIS: i32 = 500;
 const WASM_TO_HOST_INTERVAL_MILLIS: i32 = 3_000;
-#[cfg(feature = "guest-v1")]
-const V1_SCHEMA: u32 = 1;
-#[cfg(feature = "guest-v2")]
-const V2_SCHEMA: u32 = 2;
+#[cfg(feature = "guest-v3")]
+const REPLY_HISTORY: usize = 4;
 
 struct WasmActor;
 
@@ -33,14 +42,18 @@ impl exports::wasm_actor::Guest for WasmActor {
     active_actor().handle_call(msgs, state)
   }
 
-  fn migrate_state(state: ActorState) -> ActorState {
-    active_actor().migrate_state(state)
+  fn migrate(from_version: u32, bytes: Vec<u8>) -> Result<ActorState, String> {
+    schema::migrate_step(from_version, bytes)
   }
 
   fn state_schema() -> u32 {
     active_actor().state_schema()
   }
 
+  fn initial_state() -> ActorState {
+    active_actor().initial_state()
+  }
+
   fn render_state(state: ActorState) -> String {
     active_actor().render_state(state)
   }
@@ -48,8 +61,8 @@ impl exports::wasm_actor::Guest for WasmActor {
 
 trait GuestActor {
   fn handle_call(&self, msgs: Vec<ActorMsg>, state: ActorState) -> ActorState;
-  fn migrate_state(&self, state: ActorState) -> ActorState;
   fn state_schema(&self) -> u32;
+  fn initial_state(&self) -> ActorState;
   fn render_state(&self, state: ActorState) -> String;
 }
 
@@ -63,15 +76,24 @@ fn active_actor() -> impl GuestActor {
   GuestV2
 }
 
+#[cfg(feature = "guest-v3")]
+fn active_actor() -> impl GuestActor {
+  GuestV3
+}
+
+/// A state that does not decode is a host bug, so it traps like any other
+/// guest panic.
+fn expect_state<S: Schema>(state: &ActorState, label: &str) -> S {
+  decode(state).unwrap_or_else(|err| panic!("{label}: {err}"))
+}
+
 #[cfg(feature = "guest-v1")]
 struct GuestV1;
 
 #[cfg(feature = "guest-v1")]
 impl GuestActor for GuestV1 {
   fn handle_call(&self, msgs: Vec<ActorMsg>, state: ActorState) -> ActorState {
-    let ActorState::V1(mut state) = state else {
-      panic!("guest-v1 handle-call requires actor-state.v1");
-    };
+    let mut state: StateV1 = expect_state(&state, "guest-v1 handle-call");
 
     for msg in msgs {
       state = match msg.kind {
@@ -89,26 +111,19 @@ impl GuestActor for GuestV1 {
     }
 
     println!("wasm actor v1: state={}", render_v1_state(&state));
-    ActorState::V1(state)
+    encode(&state)
   }
 
-  fn migrate_state(&self, state: ActorState) -> ActorState {
-    match state {
-      ActorState::V1(state) => ActorState::V1(state),
-      ActorState::V2(_) => panic!("guest-v1 cannot downgrade actor-state.v2"),
-    }
+  fn state_schema(&self) -> u32 {
+    StateV1::VERSION
   }
 
-  fn state_schema(&self) -> u32 {
-    V1_SCHEMA
+  fn initial_state(&self) -> ActorState {
+    encode(&StateV1::default())
   }
 
   fn render_state(&self, state: ActorState) -> String {
-    let ActorState::V1(state) = state else {
-      panic!("guest-v1 render-state requires actor-state.v1");
-    };
-
-    render_v1_state(&state)
+    render_v1_state(&expect_state(&state, "guest-v1 render-state"))
   }
 }
 
@@ -118,22 +133,14 @@ struct GuestV2;
 #[cfg(feature = "guest-v2")]
 impl GuestActor for GuestV2 {
   fn handle_call(&self, msgs: Vec<ActorMsg>, state: ActorState) -> ActorState {
-    let ActorState::V2(mut state) = state else {
-      panic!("guest-v2 handle-call requires actor-state.v2");
-    };
+    let mut state: StateV2 = expect_state(&state, "guest-v2 handle-call");
 
     for msg in msgs {
       state = match msg.kind {
-        ActorMsgKind::Tick => handle_v2_tick(state),
+        ActorMsgKind::Tick => handle_v2_tick(state, "wasm actor v2"),
         ActorMsgKind::HostMessage => {
           if let Some(msg) = msg.host_message {
-            println!(
-              "wasm actor v2: received host主动消息 #{} payload={}",
-              msg.sequence, msg.payload
-            );
-            state.host_messages_seen += 1;
-            state.last_host_sequence = msg.sequence;
-            state.last_host_payload = msg.payload;
+            handle_v2_host_message(&mut state, msg, "wasm actor v2");
           }
           state
         }
@@ -141,42 +148,63 @@ impl GuestActor for GuestV2 {
     }
 
     println!("wasm actor v2: state={}", render_v2_state(&state));
-    ActorState::V2(state)
+    encode(&state)
   }
 
-  fn migrate_state(&self, state: ActorState) -> ActorState {
-    match state {
-      ActorState::V2(state) => ActorState::V2(state),
-      ActorState::V1(state) => ActorState::V2(ActorStateV2 {
-        tick: state.tick,
-        last_host_reply: state.last_host_reply,
-        elapsed_since_push: state.elapsed_since_push,
-        last_response: state.last_response,
-        upgrade_generation: 1,
-        migrated_from_tick: state.tick,
-        host_messages_seen: 0,
-        proactive_sends: 0,
-        last_host_sequence: 0,
-        last_host_payload: String::new(),
-      }),
+  fn state_schema(&self) -> u32 {
+    StateV2::VERSION
+  }
+
+  fn initial_state(&self) -> ActorState {
+    encode(&StateV2::from(schema::StateV1::default()))
+  }
+
+  fn render_state(&self, state: ActorState) -> String {
+    render_v2_state(&expect_state(&state, "guest-v2 render-state"))
+  }
+}
+
+/// A deliberately bad release: its migrations are sound and it passes the
+/// host's shadow run, but its first real tick traps, so the host has to roll
+/// back to the previous module.
+#[cfg(feature = "guest-v3")]
+struct GuestV3;
+
+#[cfg(feature = "guest-v3")]
+impl GuestActor for GuestV3 {
+  fn handle_call(&self, msgs: Vec<ActorMsg>, state: ActorState) -> ActorState {
+    let mut state: StateV3 = expect_state(&state, "guest-v3 handle-call");
+
+    for msg in msgs {
+      match msg.kind {
+        ActorMsgKind::Tick => state = handle_v3_tick(state),
+        ActorMsgKind::HostMessage => {
+          if let Some(msg) = msg.host_message {
+            handle_v2_host_message(&mut state.v2, msg, "wasm actor v3");
+          }
+        }
+      }
     }
+
+    println!("wasm actor v3: state={}", render_v3_state(&state));
+    encode(&state)
   }
 
   fn state_schema(&self) -> u32 {
-    V2_SCHEMA
+    StateV3::VERSION
   }
 
-  fn render_state(&self, state: ActorState) -> String {
-    let ActorState::V2(state) = state else {
-      panic!("guest-v2 render-state requires actor-state.v2");
-    };
+  fn initial_state(&self) -> ActorState {
+    encode(&StateV3::from(StateV2::from(schema::StateV1::default())))
+  }
 
-    render_v2_state(&state)
+  fn render_state(&self, state: ActorState) -> String {
+    render_v3_state(&expect_state(&state, "guest-v3 render-state"))
   }
 }
 
 #[cfg(feature = "guest-v1")]
-fn handle_v1_tick(mut state: ActorStateV1) -> ActorStateV1 {
+fn handle_v1_tick(mut state: StateV1) -> StateV1 {
   state.tick += 1;
   state.elapsed_since_push += LOOP_SLEEP_MILLIS;
   if state.elapsed_since_push >= WASM_TO_HOST_INTERVAL_MILLIS {
@@ -188,32 +216,52 @@ fn handle_v1_tick(mut state: ActorStateV1) -> ActorStateV1 {
       state.last_host_reply,
     );
     state.last_host_reply = response.reply;
-    state.last_response = response;
+    state.last_response = response.into();
   }
 
   state
 }
 
-#[cfg(feature = "guest-v2")]
-fn handle_v2_tick(mut state: ActorStateV2) -> ActorStateV2 {
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+fn handle_v2_tick(mut state: StateV2, actor_label: &str) -> StateV2 {
   state.tick += 1;
   state.elapsed_since_push += LOOP_SLEEP_MILLIS;
   if state.elapsed_since_push >= WASM_TO_HOST_INTERVAL_MILLIS {
     state.elapsed_since_push = 0;
     let response = send_proactive_message(
-      "wasm actor v2",
+      actor_label,
       state.tick,
       state.last_response.handled,
       state.last_host_reply,
     );
     state.last_host_reply = response.reply;
-    state.last_response = response;
+    state.last_response = response.into();
     state.proactive_sends += 1;
   }
 
   state
 }
 
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+fn handle_v2_host_message(state: &mut StateV2, msg: HostMessage, actor_label: &str) {
+  println!(
+    "{actor_label}: received host主动消息 #{} payload={}",
+    msg.sequence, msg.payload
+  );
+  state.host_messages_seen += 1;
+  state.last_host_sequence = msg.sequence;
+  state.last_host_payload = msg.payload;
+}
+
+#[cfg(feature = "guest-v3")]
+fn handle_v3_tick(mut state: StateV3) -> StateV3 {
+  state.v2 = handle_v2_tick(state.v2, "wasm actor v3");
+  // The bug: the ring is indexed before it has ever been filled.
+  let slot = state.v2.tick as usize % REPLY_HISTORY;
+  state.reply_history[slot] = state.v2.last_host_reply;
+  state
+}
+
 fn send_proactive_message(
   actor_label: &str,
   tick: i32,
@@ -240,7 +288,7 @@ fn send_proactive_message(
 }
 
 #[cfg(feature = "guest-v1")]
-fn render_v1_state(state: &ActorStateV1) -> String {
+fn render_v1_state(state: &StateV1) -> String {
   format!(
     r#"{{"schema":1,"tick":{},"handled":{},"reply":{},"message":{:?}}}"#,
     state.tick, state.last_response.handled, state.last_response.reply, state.last_response.message,
@@ -248,7 +296,7 @@ fn render_v1_state(state: &ActorStateV1) -> String {
 }
 
 #[cfg(feature = "guest-v2")]
-fn render_v2_state(state: &ActorStateV2) -> String {
+fn render_v2_state(state: &StateV2) -> String {
   format!(
     r#"{{"schema":2,"tick":{},"handled":{},"reply":{},"message":{:?},"upgrade_generation":{},"migrated_from_tick":{},"host_messages_seen":{},"proactive_sends":{},"last_host_sequence":{},"last_host_payload":{:?}}}"#,
     state.tick,
@@ -264,4 +312,23 @@ fn render_v2_state(state: &ActorStateV2) -> String {
   )
 }
 
+#[cfg(feature = "guest-v3")]
+fn render_v3_state(state: &StateV3) -> String {
+  let v2 = &state.v2;
+  format!(
+    r#"{{"schema":3,"tick":{},"handled":{},"reply":{},"message":{:?},"upgrade_generation":{},"migrated_from_tick":{},"host_messages_seen":{},"proactive_sends":{},"last_host_sequence":{},"last_host_payload":{:?},"reply_history":{:?}}}"#,
+    v2.tick,
+    v2.last_response.handled,
+    v2.last_response.reply,
+    v2.last_response.message,
+    v2.upgrade_generation,
+    v2.migrated_from_tick,
+    v2.host_messages_seen,
+    v2.proactive_sends,
+    v2.last_host_sequence,
+    v2.last_host_payload,
+    state.reply_history,
+  )
+}
+
 export!(WasmActor);
@@ -0,0 +1,146 @@
+//! Versioned actor state owned by the guest.
+//!
+//! Each release keeps the records of every older schema it can migrate from,
+//! plus one `vN -> vN+1` step per version. The host never decodes these; it
+//! only chains `migrate` calls until the state reaches the release's schema.
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::demo::actor::host_actor::{ActorResponse, ActorState};
+
+pub(crate) trait Schema: Serialize + DeserializeOwned {
+  const VERSION: u32;
+}
+
+pub(crate) fn encode<S: Schema>(state: &S) -> ActorState {
+  ActorState {
+    schema: S::VERSION,
+    bytes: serde_json::to_vec(state).expect("actor state always encodes"),
+  }
+}
+
+pub(crate) fn decode<S: Schema>(state: &ActorState) -> Result<S, String> {
+  if state.schema != S::VERSION {
+    return Err(format!(
+      "expected actor-state schema {}, got {}",
+      S::VERSION,
+      state.schema
+    ));
+  }
+  decode_bytes(&state.bytes)
+}
+
+fn decode_bytes<S: Schema>(bytes: &[u8]) -> Result<S, String> {
+  serde_json::from_slice(bytes)
+    .map_err(|err| format!("actor-state schema {} does not decode: {err}", S::VERSION))
+}
+
+/// Upgrades `bytes`, encoded with schema `from_version`, by exactly one
+/// version.
+pub(crate) fn migrate_step(from_version: u32, bytes: Vec<u8>) -> Result<ActorState, String> {
+  match from_version {
+    #[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+    1 => Ok(encode(&StateV2::from(decode_bytes::<StateV1>(&bytes)?))),
+    #[cfg(feature = "guest-v3")]
+    2 => Ok(encode(&StateV3::from(decode_bytes::<StateV2>(&bytes)?))),
+    version => Err(format!(
+      "this release cannot migrate actor-state schema {version} ({} bytes)",
+      bytes.len()
+    )),
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct LastResponse {
+  pub(crate) handled: u64,
+  pub(crate) reply: i32,
+  pub(crate) message: String,
+}
+
+impl From<ActorResponse> for LastResponse {
+  fn from(response: ActorResponse) -> Self {
+    Self {
+      handled: response.handled,
+      reply: response.reply,
+      message: response.message,
+    }
+  }
+}
+
+/// State used by the v1 guest actor.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct StateV1 {
+  pub(crate) tick: i32,
+  pub(crate) last_host_reply: i32,
+  pub(crate) elapsed_since_push: i32,
+  pub(crate) last_response: LastResponse,
+}
+
+impl Schema for StateV1 {
+  const VERSION: u32 = 1;
+}
+
+/// State used by the v2 guest actor: v1 plus counters for host traffic and
+/// where the upgrade happened.
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StateV2 {
+  pub(crate) tick: i32,
+  pub(crate) last_host_reply: i32,
+  pub(crate) elapsed_since_push: i32,
+  pub(crate) last_response: LastResponse,
+  pub(crate) upgrade_generation: u64,
+  pub(crate) migrated_from_tick: i32,
+  pub(crate) host_messages_seen: u64,
+  pub(crate) proactive_sends: u64,
+  pub(crate) last_host_sequence: u64,
+  pub(crate) last_host_payload: String,
+}
+
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+impl Schema for StateV2 {
+  const VERSION: u32 = 2;
+}
+
+#[cfg(any(feature = "guest-v2", feature = "guest-v3"))]
+impl From<StateV1> for StateV2 {
+  fn from(state: StateV1) -> Self {
+    Self {
+      tick: state.tick,
+      last_host_reply: state.last_host_reply,
+      elapsed_since_push: state.elapsed_since_push,
+      last_response: state.last_response,
+      upgrade_generation: 1,
+      migrated_from_tick: state.tick,
+      host_messages_seen: 0,
+      proactive_sends: 0,
+      last_host_sequence: 0,
+      last_host_payload: String::new(),
+    }
+  }
+}
+
+/// State used by the v3 guest actor: v2 plus the most recent host replies.
+#[cfg(feature = "guest-v3")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StateV3 {
+  #[serde(flatten)]
+  pub(crate) v2: StateV2,
+  pub(crate) reply_history: Vec<i32>,
+}
+
+#[cfg(feature = "guest-v3")]
+impl Schema for StateV3 {
+  const VERSION: u32 = 3;
+}
+
+#[cfg(feature = "guest-v3")]
+impl From<StateV2> for StateV3 {
+  fn from(mut state: StateV2) -> Self {
+    state.upgrade_generation += 1;
+    Self {
+      v2: state,
+      reply_history: Vec::new(),
+    }
+  }
+}
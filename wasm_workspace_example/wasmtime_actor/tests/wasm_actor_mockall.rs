@@ -6,6 +6,7 @@ use std::{
 
 use anyhow::{Context, Result, anyhow, bail};
 use mockall::automock;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use wasmtime::{
   Config, Engine, Store,
   component::{Component, HasSelf, Linker},
@@ -18,13 +19,41 @@ wasmtime::component::bindgen!({
   require_store_data_send: true,
 });
 
-use demo::actor::host_actor::{
-  self, ActorMsg, ActorMsgKind, ActorResponse, ActorState, ActorStateV1,
-};
+use demo::actor::host_actor::{self, ActorMsg, ActorMsgKind, ActorResponse, ActorState};
 
 const GUEST_TARGET: &str = "wasm32-wasip2";
 const GUEST_WASM: &str = "wasmtime_actor.wasm";
 
+// Mirrors of the guest's serde encoding for schemas 1 and 2; the host itself
+// never decodes actor-state.
+#[derive(Debug, Serialize, Deserialize)]
+struct LastResponse {
+  handled: u64,
+  reply: i32,
+  message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateV1 {
+  tick: i32,
+  last_host_reply: i32,
+  elapsed_since_push: i32,
+  last_response: LastResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct StateV2 {
+  tick: i32,
+  last_host_reply: i32,
+  last_response: LastResponse,
+  upgrade_generation: u64,
+  migrated_from_tick: i32,
+  host_messages_seen: u64,
+  proactive_sends: u64,
+  last_host_sequence: u64,
+  last_host_payload: String,
+}
+
 #[automock]
 trait HostGateway {
   fn send_to_host(&mut self, msg: host_actor::GuestMessage) -> ActorResponse;
@@ -96,12 +125,20 @@ impl MockedActor {
       .map_err(|err| anyhow!("call_handle_call failed: {err}"))
   }
 
-  fn migrate_state(&mut self, state: &ActorState) -> Result<ActorState> {
+  fn migrate(&mut self, state: &ActorState) -> Result<Result<ActorState, String>> {
     self
       .instance
       .wasm_actor()
-      .call_migrate_state(&mut self.store, state)
-      .map_err(|err| anyhow!("migrate_state failed: {err}"))
+      .call_migrate(&mut self.store, state.schema, &state.bytes)
+      .map_err(|err| anyhow!("migrate failed: {err}"))
+  }
+
+  fn state_schema(&mut self) -> Result<u32> {
+    self
+      .instance
+      .wasm_actor()
+      .call_state_schema(&mut self.store)
+      .map_err(|err| anyhow!("state_schema failed: {err}"))
   }
 }
 
@@ -112,13 +149,8 @@ fn wasm_does_not_call_host_before_push_interval() -> Result<()> {
 
   let mut actor = MockedActor::new("guest-v1", host)?;
   let initial = initial_actor_state(0, 7, 10, 0);
-  let actual = actor.handle_call(&tick_messages(5), &initial)?;
-  let ActorState::V1(actual) = actual else {
-    panic!("guest-v1 returned non-v1 state");
-  };
-  let ActorState::V1(initial) = initial else {
-    panic!("initial state should be v1");
-  };
+  let actual: StateV1 = decode(&actor.handle_call(&tick_messages(5), &initial)?, 1)?;
+  let initial: StateV1 = decode(&initial, 1)?;
 
   assert_eq!(actual.tick, 5);
   assert_eq!(actual.elapsed_since_push, 2_500);
@@ -150,10 +182,7 @@ fn wasm_calls_mocked_host_when_push_interval_is_reached() -> Result<()> {
 
   let mut actor = MockedActor::new("guest-v1", host)?;
   let initial = initial_actor_state(0, 13, 41, 0);
-  let actual = actor.handle_call(&tick_messages(6), &initial)?;
-  let ActorState::V1(actual) = actual else {
-    panic!("guest-v1 returned non-v1 state");
-  };
+  let actual: StateV1 = decode(&actor.handle_call(&tick_messages(6), &initial)?, 1)?;
 
   assert_eq!(actual.tick, 6);
   assert_eq!(actual.elapsed_since_push, 0);
@@ -175,10 +204,9 @@ fn guest_v2_migrates_v1_state_and_handles_calls_with_v2_state() -> Result<()> {
 
   let mut actor = MockedActor::new("guest-v2", host)?;
   let initial = initial_actor_state(6, 123, 99, 0);
-  let migrated = actor.migrate_state(&initial)?;
-  let ActorState::V2(migrated) = migrated else {
-    panic!("guest-v2 migrate-state should return v2 state");
-  };
+  assert_eq!(actor.state_schema()?, 2);
+  let migrated_state = actor.migrate(&initial)?.map_err(|err| anyhow!(err))?;
+  let migrated: StateV2 = decode(&migrated_state, 2)?;
 
   assert_eq!(migrated.tick, 6);
   assert_eq!(migrated.last_host_reply, 123);
@@ -201,11 +229,9 @@ fn guest_v2_migrates_v1_state_and_handles_calls_with_v2_state() -> Result<()> {
         host_message: None,
       },
     ],
-    &ActorState::V2(migrated),
+    &migrated_state,
   )?;
-  let ActorState::V2(actual) = actual else {
-    panic!("guest-v2 handle-call should keep v2 state");
-  };
+  let actual: StateV2 = decode(&actual, 2)?;
 
   assert_eq!(actual.tick, 7);
   assert_eq!(actual.upgrade_generation, 1);
@@ -218,6 +244,77 @@ fn guest_v2_migrates_v1_state_and_handles_calls_with_v2_state() -> Result<()> {
   Ok(())
 }
 
+#[test]
+fn guest_v3_chains_migrations_from_v1() -> Result<()> {
+  let mut host = MockHostGateway::new();
+  host.expect_send_to_host().times(0);
+
+  let mut actor = MockedActor::new("guest-v3", host)?;
+  assert_eq!(actor.state_schema()?, 3);
+
+  let v2 = actor
+    .migrate(&initial_actor_state(6, 123, 99, 0))?
+    .map_err(|err| anyhow!(err))?;
+  assert_eq!(v2.schema, 2);
+  let v3 = actor.migrate(&v2)?.map_err(|err| anyhow!(err))?;
+  assert_eq!(v3.schema, 3);
+
+  let v3: serde_json::Value = serde_json::from_slice(&v3.bytes)?;
+  assert_eq!(v3["tick"], 6);
+  assert_eq!(v3["last_response"]["handled"], 99);
+  assert_eq!(v3["upgrade_generation"], 2);
+  assert_eq!(v3["migrated_from_tick"], 6);
+  assert_eq!(v3["reply_history"], serde_json::json!([]));
+
+  Ok(())
+}
+
+#[test]
+fn guest_refuses_schemas_it_cannot_migrate() -> Result<()> {
+  let mut actor = MockedActor::new("guest-v2", MockHostGateway::new())?;
+  let v2 = ActorState {
+    schema: 2,
+    bytes: b"{}".to_vec(),
+  };
+  let err = actor
+    .migrate(&v2)?
+    .expect_err("guest-v2 has no 2 -> 3 step");
+  assert!(err.contains("cannot migrate actor-state schema 2"), "{err}");
+
+  let mut actor = MockedActor::new("guest-v1", MockHostGateway::new())?;
+  let err = actor
+    .migrate(&initial_actor_state(0, 0, 0, 0))?
+    .expect_err("guest-v1 is the first schema");
+  assert!(err.contains("cannot migrate actor-state schema 1"), "{err}");
+
+  Ok(())
+}
+
+#[test]
+fn guest_v3_traps_on_its_first_tick() -> Result<()> {
+  let mut host = MockHostGateway::new();
+  host.expect_send_to_host().times(0);
+
+  let mut actor = MockedActor::new("guest-v3", host)?;
+  let v2 = actor
+    .migrate(&initial_actor_state(6, 123, 99, 0))?
+    .map_err(|err| anyhow!(err))?;
+  let v3 = actor.migrate(&v2)?.map_err(|err| anyhow!(err))?;
+
+  // An empty batch is what the host's shadow run sends; it passes.
+  let v3 = actor.handle_call(&[], &v3)?;
+  assert!(actor.handle_call(&tick_messages(1), &v3).is_err());
+
+  Ok(())
+}
+
+fn decode<S: DeserializeOwned>(state: &ActorState, schema: u32) -> Result<S> {
+  if state.schema != schema {
+    bail!("expected actor-state schema {schema}, got {}", state.schema);
+  }
+  Ok(serde_json::from_slice(&state.bytes)?)
+}
+
 fn tick_messages(count: usize) -> Vec<ActorMsg> {
   (0 .. count)
     .map(|_| ActorMsg {
@@ -233,16 +330,20 @@ fn initial_actor_state(
   handled: u64,
   elapsed_since_push: i32,
 ) -> ActorState {
-  ActorState::V1(ActorStateV1 {
+  let state = StateV1 {
     tick,
     last_host_reply,
     elapsed_since_push,
-    last_response: ActorResponse {
+    last_response: LastResponse {
       handled,
       reply: last_host_reply,
       message: format!("initial handled {handled}"),
     },
-  })
+  };
+  ActorState {
+    schema: 1,
+    bytes: serde_json::to_vec(&state).expect("state encodes"),
+  }
 }
 
 fn ensure_guest_component(feature: &'static str) -> Result<PathBuf> {
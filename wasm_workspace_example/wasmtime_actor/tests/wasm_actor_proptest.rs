@@ -7,6 +7,7 @@ use std::{
 
 use anyhow::{Context, Result, anyhow, bail};
 use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use wasmtime::{
   Config, Engine, Store,
@@ -20,15 +21,48 @@ wasmtime::component::bindgen!({
   require_store_data_send: true,
 });
 
-use demo::actor::host_actor::{
-  self, ActorMsg, ActorMsgKind, ActorResponse, ActorState, ActorStateV1,
-};
+use demo::actor::host_actor::{self, ActorMsg, ActorMsgKind, ActorResponse, ActorState};
 
 const GUEST_TARGET: &str = "wasm32-wasip2";
 const GUEST_WASM: &str = "wasmtime_actor.wasm";
 const LOOP_SLEEP_MILLIS: i32 = 500;
 const WASM_TO_HOST_INTERVAL_MILLIS: i32 = 3_000;
 
+/// Mirror of the guest's schema 1 encoding.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StateV1 {
+  tick: i32,
+  last_host_reply: i32,
+  elapsed_since_push: i32,
+  last_response: LastResponse,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LastResponse {
+  handled: u64,
+  reply: i32,
+  message: String,
+}
+
+impl StateV1 {
+  fn encode(&self) -> ActorState {
+    ActorState {
+      schema: 1,
+      bytes: serde_json::to_vec(self).expect("state encodes"),
+    }
+  }
+
+  fn decode(state: &ActorState) -> std::result::Result<Self, TestCaseError> {
+    if state.schema != 1 {
+      return Err(TestCaseError::fail(format!(
+        "guest-v1 returned schema {}",
+        state.schema
+      )));
+    }
+    serde_json::from_slice(&state.bytes).map_err(|err| TestCaseError::fail(err.to_string()))
+  }
+}
+
 struct TestStoreState {
   host_callbacks: u64,
   wasi: WasiCtx,
@@ -166,17 +200,17 @@ proptest! {
     inputs in prop::collection::vec(arb_input_msg(), 0 .. 80),
   ) {
     let mut actor = TestActor::new().map_err(|err| TestCaseError::fail(err.to_string()))?;
-    let initial_state = ActorStateV1 {
+    let initial_state = StateV1 {
       tick: initial_tick,
       last_host_reply: initial_last_host_reply,
       elapsed_since_push: initial_elapsed,
-      last_response: ActorResponse {
+      last_response: LastResponse {
         handled: initial_handled,
         reply: initial_last_host_reply,
         message: format!("initial response {initial_handled}"),
       },
     };
-    let initial_actor_state = ActorState::V1(initial_state.clone());
+    let initial_actor_state = initial_state.encode();
 
     let msgs: Vec<_> = inputs
       .clone()
@@ -187,9 +221,7 @@ proptest! {
     let actual = actor
       .handle_call(&msgs, &initial_actor_state)
       .map_err(|err| TestCaseError::fail(err.to_string()))?;
-    let ActorState::V1(actual) = actual else {
-      return Err(TestCaseError::fail("guest-v1 returned non-v1 state"));
-    };
+    let actual = StateV1::decode(&actual)?;
 
     prop_assert_eq!(actual.tick, expected.state.tick);
     prop_assert_eq!(actual.elapsed_since_push, expected.state.elapsed_since_push);
@@ -208,16 +240,17 @@ proptest! {
     message in "[a-zA-Z0-9 _`-]{0,64}",
   ) {
     let mut actor = TestActor::new().map_err(|err| TestCaseError::fail(err.to_string()))?;
-    let state = ActorState::V1(ActorStateV1 {
+    let state = StateV1 {
       tick,
       last_host_reply: reply,
       elapsed_since_push: 0,
-      last_response: ActorResponse {
+      last_response: LastResponse {
         handled,
         reply,
         message: message.clone(),
       },
-    });
+    }
+    .encode();
 
     let rendered = actor
       .render_state(&state)
@@ -234,13 +267,13 @@ proptest! {
 }
 
 struct ModelOutcome {
-  state: ActorStateV1,
+  state: StateV1,
   host_callbacks: u64,
 }
 
 fn model_handle_call(
   inputs: &[InputMsg],
-  initial: &ActorStateV1,
+  initial: &StateV1,
   initial_host_callbacks: u64,
 ) -> ModelOutcome {
   let mut state = initial.clone();
@@ -261,7 +294,7 @@ fn model_handle_call(
           let message =
             format!("test host processed `{payload}` after {last_handled} handled messages");
           state.last_host_reply = reply;
-          state.last_response = ActorResponse {
+          state.last_response = LastResponse {
             handled: host_callbacks,
             reply,
             message,
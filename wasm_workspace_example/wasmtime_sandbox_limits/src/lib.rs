@@ -0,0 +1,9 @@
+//! A reusable sandbox for hosting untrusted modules from several tenants in
+//! one process. `main.rs` shows each wasmtime limit on its own; this puts them
+//! together per tenant and reports what every tenant used.
+
+mod tenant;
+
+pub use tenant::{
+  EPOCH_TICK, SandboxError, TenantInstance, TenantLimits, TenantSandbox, TenantUsage,
+};
@@ -9,6 +9,7 @@ use std::{
 
 use anyhow::Result;
 use wasmtime::{Config, Engine, Instance, Module, ResourceLimiter, Store, Trap};
+use wasmtime_sandbox_limits::{TenantLimits, TenantSandbox};
 
 const WASM_PAGE_SIZE: usize = 64 * 1024;
 
@@ -22,6 +23,7 @@ fn main() -> Result<()> {
   memory_limiter_blocks_growth()?;
   table_limiter_blocks_growth()?;
   instance_limiter_blocks_extra_instances()?;
+  tenants_share_one_sandbox()?;
 
   Ok(())
 }
@@ -240,6 +242,80 @@ fn instance_limiter_blocks_extra_instances() -> Result<()> {
   Ok(())
 }
 
+fn tenants_share_one_sandbox() -> Result<()> {
+  println!("\n7. tenant sandbox: per-tenant budgets and usage counters");
+
+  let sandbox = TenantSandbox::new()?;
+  sandbox.register_tenant(
+    "search-team",
+    TenantLimits::new()
+      .with_fuel(1 << 32, 1 << 30, Duration::from_secs(1))
+      .with_call_fuel(1 << 30)
+      .with_instances(2)
+      .with_deadline(Duration::from_millis(50)),
+  )?;
+  sandbox.register_tenant(
+    "ads-team",
+    TenantLimits::new()
+      .with_fuel(20_000, 5_000, Duration::from_secs(1))
+      .with_memory_bytes(2 * WASM_PAGE_SIZE)
+      .with_instances(1),
+  )?;
+
+  let plugin = Module::new(
+    sandbox.engine(),
+    r#"
+      (module
+        (memory 1 10)
+        (func (export "grow_memory") (param $pages i32) (result i32)
+          local.get $pages
+          memory.grow)
+        (func (export "spin")
+          (loop $again
+            br $again)))
+    "#,
+  )?;
+
+  let mut search = sandbox.instantiate("search-team", &plugin)?;
+  println!(
+    "   search-team grow_memory(1) -> {}",
+    search.call::<i32, i32>("grow_memory", 1)?
+  );
+  let _second_search = sandbox.instantiate("search-team", &plugin)?;
+  if let Err(err) = search.call::<(), ()>("spin", ()) {
+    println!("   search-team spin failed: {err}");
+  }
+
+  let mut ads = sandbox.instantiate("ads-team", &plugin)?;
+  println!(
+    "   ads-team grow_memory(2) -> {}",
+    ads.call::<i32, i32>("grow_memory", 2)?
+  );
+  for attempt in 1 ..= 2 {
+    match ads.call::<(), ()>("spin", ()) {
+      Ok(()) => println!("   ads-team spin #{attempt} unexpectedly returned"),
+      Err(err) => println!("   ads-team spin #{attempt} failed: {err}"),
+    }
+  }
+  if let Err(err) = sandbox.instantiate("ads-team", &plugin) {
+    println!("   second ads-team instance rejected: {err}");
+  }
+
+  for (tenant, usage) in sandbox.usage_all() {
+    println!("   usage {tenant}: {usage}");
+  }
+  println!("   prometheus sample:");
+  for line in sandbox
+    .render_prometheus()
+    .lines()
+    .filter(|line| line.starts_with("wasm_tenant_fuel_consumed_total"))
+  {
+    println!("     {line}");
+  }
+
+  Ok(())
+}
+
 fn metered_engine(consume_fuel: bool, epoch_interruption: bool) -> Result<Engine> {
   let mut config = Config::new();
   config.consume_fuel(consume_fuel);
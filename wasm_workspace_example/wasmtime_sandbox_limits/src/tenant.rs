@@ -0,0 +1,850 @@
+use std::{
+  collections::BTreeMap,
+  error::Error,
+  fmt::{self, Write as _},
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use wasmtime::{
+  Config, Engine, Instance, Module, ResourceLimiter, Store, Trap, WasmParams, WasmResults,
+};
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// How often the sandbox's ticker advances the engine epoch. Deadlines are
+/// rounded up to whole ticks.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Resource limits for one tenant.
+#[derive(Debug, Clone)]
+pub struct TenantLimits {
+  fuel_capacity: u64,
+  fuel_refill: u64,
+  fuel_refill_interval: Duration,
+  call_fuel: u64,
+  memory_limit_bytes: usize,
+  table_limit_elements: usize,
+  max_instances: usize,
+  deadline: Duration,
+}
+
+impl TenantLimits {
+  pub fn new() -> Self {
+    Self {
+      fuel_capacity: 1_000_000,
+      fuel_refill: 100_000,
+      fuel_refill_interval: Duration::from_millis(100),
+      call_fuel: 1_000_000,
+      memory_limit_bytes: 10 * WASM_PAGE_SIZE,
+      table_limit_elements: 10_000,
+      max_instances: 4,
+      deadline: Duration::from_secs(1),
+    }
+  }
+
+  /// The tenant's fuel bucket holds at most `capacity` and gains `refill`
+  /// every `interval`. It starts full.
+  pub fn with_fuel(mut self, capacity: u64, refill: u64, interval: Duration) -> Self {
+    self.fuel_capacity = capacity;
+    self.fuel_refill = refill;
+    self.fuel_refill_interval = interval;
+    self
+  }
+
+  /// Caps how much of the bucket a single call may reserve, so one call
+  /// cannot starve the tenant's other instances.
+  pub fn with_call_fuel(mut self, fuel: u64) -> Self {
+    self.call_fuel = fuel;
+    self
+  }
+
+  pub fn with_memory_bytes(mut self, bytes: usize) -> Self {
+    self.memory_limit_bytes = bytes;
+    self
+  }
+
+  pub fn with_table_elements(mut self, elements: usize) -> Self {
+    self.table_limit_elements = elements;
+    self
+  }
+
+  /// Caps the number of the tenant's instances that are alive at once.
+  pub fn with_instances(mut self, instances: usize) -> Self {
+    self.max_instances = instances;
+    self
+  }
+
+  /// Wall-clock limit for a single instantiation or call.
+  pub fn with_deadline(mut self, deadline: Duration) -> Self {
+    self.deadline = deadline;
+    self
+  }
+}
+
+impl Default for TenantLimits {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Debug)]
+pub enum SandboxError {
+  UnknownTenant(String),
+  TenantExists(String),
+  TooManyInstances {
+    tenant: String,
+    max: usize,
+  },
+  /// The tenant's bucket was empty, or the call burned its whole reservation.
+  OutOfFuel {
+    tenant: String,
+  },
+  DeadlineExceeded {
+    tenant: String,
+    deadline: Duration,
+  },
+  Wasm(wasmtime::Error),
+}
+
+impl fmt::Display for SandboxError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnknownTenant(tenant) => write!(f, "unknown tenant {tenant}"),
+      Self::TenantExists(tenant) => write!(f, "tenant {tenant} is already registered"),
+      Self::TooManyInstances { tenant, max } => {
+        write!(f, "tenant {tenant} already has {max} live instance(s)")
+      }
+      Self::OutOfFuel { tenant } => write!(f, "tenant {tenant} ran out of fuel"),
+      Self::DeadlineExceeded { tenant, deadline } => {
+        write!(f, "tenant {tenant} exceeded its {deadline:?} deadline")
+      }
+      Self::Wasm(err) => write!(f, "wasm error: {err}"),
+    }
+  }
+}
+
+impl Error for SandboxError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      Self::Wasm(err) => Some(err.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+/// Hosts untrusted modules for several tenants on one engine.
+///
+/// Every tenant gets a refilling fuel budget, memory and table caps, a
+/// wall-clock deadline per call and a cap on live instances. Each instance
+/// lives in its own `Store`, so a trap only takes down that instance.
+pub struct TenantSandbox {
+  engine: Engine,
+  tenants: Mutex<BTreeMap<String, Arc<Tenant>>>,
+  stop_ticker: Arc<AtomicBool>,
+  ticker: Option<thread::JoinHandle<()>>,
+}
+
+impl TenantSandbox {
+  pub fn new() -> wasmtime::Result<Self> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+
+    let stop_ticker = Arc::new(AtomicBool::new(false));
+    let ticker = {
+      let engine = engine.clone();
+      let stop = stop_ticker.clone();
+      thread::Builder::new()
+        .name("tenant-sandbox-epoch-ticker".to_owned())
+        .spawn(move || {
+          while !stop.load(Ordering::Relaxed) {
+            thread::sleep(EPOCH_TICK);
+            engine.increment_epoch();
+          }
+        })?
+    };
+
+    Ok(Self {
+      engine,
+      tenants: Mutex::new(BTreeMap::new()),
+      stop_ticker,
+      ticker: Some(ticker),
+    })
+  }
+
+  /// Modules must be compiled with this engine.
+  pub fn engine(&self) -> &Engine {
+    &self.engine
+  }
+
+  pub fn register_tenant(
+    &self,
+    name: impl Into<String>,
+    limits: TenantLimits,
+  ) -> Result<(), SandboxError> {
+    let name = name.into();
+    let mut tenants = self.tenants.lock().expect("tenant registry poisoned");
+    if tenants.contains_key(&name) {
+      return Err(SandboxError::TenantExists(name));
+    }
+    tenants.insert(name.clone(), Arc::new(Tenant::new(name, limits)));
+    Ok(())
+  }
+
+  /// Instantiates `module` for `tenant`. The instance counts against the
+  /// tenant's cap until it is dropped.
+  pub fn instantiate(&self, tenant: &str, module: &Module) -> Result<TenantInstance, SandboxError> {
+    let tenant = self.tenant(tenant)?;
+    let slot = InstanceSlot::acquire(tenant.clone())?;
+
+    let mut store = Store::new(
+      &self.engine,
+      TenantStoreState {
+        limiter: TenantLimiter {
+          memory_limit_bytes: tenant.limits.memory_limit_bytes,
+          table_limit_elements: tenant.limits.table_limit_elements,
+          tenant: tenant.clone(),
+        },
+      },
+    );
+    store.limiter(|state| &mut state.limiter);
+    store.epoch_deadline_trap();
+
+    let instance = tenant.metered(&mut store, |store| Instance::new(store, module, &[]))?;
+    tenant
+      .usage
+      .instances_started
+      .fetch_add(1, Ordering::Relaxed);
+
+    Ok(TenantInstance {
+      tenant,
+      store,
+      instance,
+      _slot: slot,
+    })
+  }
+
+  pub fn usage(&self, tenant: &str) -> Result<TenantUsage, SandboxError> {
+    Ok(self.tenant(tenant)?.usage())
+  }
+
+  /// Usage of every tenant, ordered by name.
+  pub fn usage_all(&self) -> Vec<(String, TenantUsage)> {
+    self
+      .tenants
+      .lock()
+      .expect("tenant registry poisoned")
+      .values()
+      .map(|tenant| (tenant.name.clone(), tenant.usage()))
+      .collect()
+  }
+
+  /// Renders every tenant's usage in the Prometheus text exposition format.
+  pub fn render_prometheus(&self) -> String {
+    let usage = self.usage_all();
+    let mut out = String::new();
+    for metric in METRICS {
+      let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+      let _ = writeln!(out, "# TYPE {} {}", metric.name, metric.kind);
+      for (tenant, usage) in &usage {
+        let _ = writeln!(
+          out,
+          "{}{{tenant=\"{}\"}} {}",
+          metric.name,
+          escape_label(tenant),
+          (metric.value)(usage)
+        );
+      }
+    }
+    out
+  }
+
+  fn tenant(&self, name: &str) -> Result<Arc<Tenant>, SandboxError> {
+    self
+      .tenants
+      .lock()
+      .expect("tenant registry poisoned")
+      .get(name)
+      .cloned()
+      .ok_or_else(|| SandboxError::UnknownTenant(name.to_owned()))
+  }
+}
+
+impl Drop for TenantSandbox {
+  fn drop(&mut self) {
+    self.stop_ticker.store(true, Ordering::Relaxed);
+    if let Some(ticker) = self.ticker.take() {
+      let _ = ticker.join();
+    }
+  }
+}
+
+/// A live instance owned by one tenant.
+pub struct TenantInstance {
+  tenant: Arc<Tenant>,
+  store: Store<TenantStoreState>,
+  instance: Instance,
+  _slot: InstanceSlot,
+}
+
+impl TenantInstance {
+  pub fn tenant(&self) -> &str {
+    &self.tenant.name
+  }
+
+  /// Calls the exported function `name` under the tenant's fuel budget and
+  /// deadline.
+  pub fn call<Params, Results>(
+    &mut self,
+    name: &str,
+    params: Params,
+  ) -> Result<Results, SandboxError>
+  where
+    Params: WasmParams,
+    Results: WasmResults,
+  {
+    let func = self
+      .instance
+      .get_typed_func::<Params, Results>(&mut self.store, name)
+      .map_err(SandboxError::Wasm)?;
+    self.tenant.usage.calls.fetch_add(1, Ordering::Relaxed);
+    let results = self
+      .tenant
+      .metered(&mut self.store, |store| func.call(store, params))?;
+    self
+      .tenant
+      .usage
+      .completed_calls
+      .fetch_add(1, Ordering::Relaxed);
+    Ok(results)
+  }
+}
+
+struct TenantStoreState {
+  limiter: TenantLimiter,
+}
+
+struct Tenant {
+  name: String,
+  limits: TenantLimits,
+  fuel: Mutex<FuelBucket>,
+  live_instances: AtomicUsize,
+  usage: UsageCounters,
+}
+
+impl Tenant {
+  fn new(name: String, limits: TenantLimits) -> Self {
+    Self {
+      fuel: Mutex::new(FuelBucket::new(&limits, Instant::now())),
+      name,
+      limits,
+      live_instances: AtomicUsize::new(0),
+      usage: UsageCounters::default(),
+    }
+  }
+
+  /// Runs `f` with fuel reserved from the tenant's bucket and the tenant's
+  /// deadline armed, then returns unburnt fuel to the bucket.
+  fn metered<T>(
+    &self,
+    store: &mut Store<TenantStoreState>,
+    f: impl FnOnce(&mut Store<TenantStoreState>) -> wasmtime::Result<T>,
+  ) -> Result<T, SandboxError> {
+    let reserved = self.bucket().reserve(self.limits.call_fuel, Instant::now());
+    if reserved == 0 {
+      self.usage.out_of_fuel.fetch_add(1, Ordering::Relaxed);
+      return Err(SandboxError::OutOfFuel {
+        tenant: self.name.clone(),
+      });
+    }
+    store.set_fuel(reserved).map_err(SandboxError::Wasm)?;
+    store.set_epoch_deadline(deadline_ticks(self.limits.deadline));
+
+    let result = f(store);
+
+    // Wasmtime does not write back the fuel burnt before an epoch interrupt,
+    // so a call stopped by its deadline is charged its whole reservation.
+    let interrupted = matches!(
+      &result,
+      Err(err) if err.downcast_ref::<Trap>() == Some(&Trap::Interrupt)
+    );
+    let remaining = if interrupted {
+      0
+    } else {
+      store.get_fuel().unwrap_or(0)
+    };
+    store.set_fuel(0).map_err(SandboxError::Wasm)?;
+    self.bucket().give_back(remaining);
+    self
+      .usage
+      .fuel_consumed
+      .fetch_add(reserved - remaining, Ordering::Relaxed);
+
+    result.map_err(|err| self.classify(err))
+  }
+
+  fn classify(&self, err: wasmtime::Error) -> SandboxError {
+    match err.downcast_ref::<Trap>() {
+      Some(Trap::OutOfFuel) => {
+        self.usage.out_of_fuel.fetch_add(1, Ordering::Relaxed);
+        SandboxError::OutOfFuel {
+          tenant: self.name.clone(),
+        }
+      }
+      Some(Trap::Interrupt) => {
+        self.usage.deadline_exceeded.fetch_add(1, Ordering::Relaxed);
+        SandboxError::DeadlineExceeded {
+          tenant: self.name.clone(),
+          deadline: self.limits.deadline,
+        }
+      }
+      _ => {
+        self.usage.traps.fetch_add(1, Ordering::Relaxed);
+        SandboxError::Wasm(err)
+      }
+    }
+  }
+
+  fn bucket(&self) -> std::sync::MutexGuard<'_, FuelBucket> {
+    self.fuel.lock().expect("fuel bucket poisoned")
+  }
+
+  fn usage(&self) -> TenantUsage {
+    let counters = &self.usage;
+    TenantUsage {
+      calls: counters.calls.load(Ordering::Relaxed),
+      completed_calls: counters.completed_calls.load(Ordering::Relaxed),
+      fuel_consumed: counters.fuel_consumed.load(Ordering::Relaxed),
+      fuel_available: self.bucket().available(Instant::now()),
+      out_of_fuel: counters.out_of_fuel.load(Ordering::Relaxed),
+      deadline_exceeded: counters.deadline_exceeded.load(Ordering::Relaxed),
+      traps: counters.traps.load(Ordering::Relaxed),
+      memory_denied: counters.memory_denied.load(Ordering::Relaxed),
+      table_denied: counters.table_denied.load(Ordering::Relaxed),
+      instances_started: counters.instances_started.load(Ordering::Relaxed),
+      instances_rejected: counters.instances_rejected.load(Ordering::Relaxed),
+      live_instances: self.live_instances.load(Ordering::Relaxed),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct UsageCounters {
+  calls: AtomicU64,
+  completed_calls: AtomicU64,
+  fuel_consumed: AtomicU64,
+  out_of_fuel: AtomicU64,
+  deadline_exceeded: AtomicU64,
+  traps: AtomicU64,
+  memory_denied: AtomicU64,
+  table_denied: AtomicU64,
+  instances_started: AtomicU64,
+  instances_rejected: AtomicU64,
+}
+
+/// Point-in-time usage of one tenant. Everything but `fuel_available` and
+/// `live_instances` only ever grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TenantUsage {
+  pub calls: u64,
+  pub completed_calls: u64,
+  pub fuel_consumed: u64,
+  pub fuel_available: u64,
+  pub out_of_fuel: u64,
+  pub deadline_exceeded: u64,
+  pub traps: u64,
+  pub memory_denied: u64,
+  pub table_denied: u64,
+  pub instances_started: u64,
+  pub instances_rejected: u64,
+  pub live_instances: usize,
+}
+
+impl fmt::Display for TenantUsage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "calls={} completed={} fuel_consumed={} fuel_available={} out_of_fuel={} \
+       deadline_exceeded={} traps={} memory_denied={} table_denied={} instances={}/{} rejected={}",
+      self.calls,
+      self.completed_calls,
+      self.fuel_consumed,
+      self.fuel_available,
+      self.out_of_fuel,
+      self.deadline_exceeded,
+      self.traps,
+      self.memory_denied,
+      self.table_denied,
+      self.live_instances,
+      self.instances_started,
+      self.instances_rejected
+    )
+  }
+}
+
+struct Metric {
+  name: &'static str,
+  help: &'static str,
+  kind: &'static str,
+  value: fn(&TenantUsage) -> u64,
+}
+
+const METRICS: &[Metric] = &[
+  Metric {
+    name: "wasm_tenant_calls_total",
+    help: "Calls made into tenant instances.",
+    kind: "counter",
+    value: |usage| usage.calls,
+  },
+  Metric {
+    name: "wasm_tenant_completed_calls_total",
+    help: "Calls that returned without trapping.",
+    kind: "counter",
+    value: |usage| usage.completed_calls,
+  },
+  Metric {
+    name: "wasm_tenant_fuel_consumed_total",
+    help: "Fuel burnt by the tenant's instances.",
+    kind: "counter",
+    value: |usage| usage.fuel_consumed,
+  },
+  Metric {
+    name: "wasm_tenant_fuel_available",
+    help: "Fuel left in the tenant's bucket.",
+    kind: "gauge",
+    value: |usage| usage.fuel_available,
+  },
+  Metric {
+    name: "wasm_tenant_out_of_fuel_total",
+    help: "Calls refused or trapped for lack of fuel.",
+    kind: "counter",
+    value: |usage| usage.out_of_fuel,
+  },
+  Metric {
+    name: "wasm_tenant_deadline_exceeded_total",
+    help: "Calls interrupted by the wall-clock deadline.",
+    kind: "counter",
+    value: |usage| usage.deadline_exceeded,
+  },
+  Metric {
+    name: "wasm_tenant_traps_total",
+    help: "Calls that trapped for any other reason.",
+    kind: "counter",
+    value: |usage| usage.traps,
+  },
+  Metric {
+    name: "wasm_tenant_memory_denied_total",
+    help: "Memory growths denied by the tenant's cap.",
+    kind: "counter",
+    value: |usage| usage.memory_denied,
+  },
+  Metric {
+    name: "wasm_tenant_table_denied_total",
+    help: "Table growths denied by the tenant's cap.",
+    kind: "counter",
+    value: |usage| usage.table_denied,
+  },
+  Metric {
+    name: "wasm_tenant_instances_started_total",
+    help: "Instances created for the tenant.",
+    kind: "counter",
+    value: |usage| usage.instances_started,
+  },
+  Metric {
+    name: "wasm_tenant_instances_rejected_total",
+    help: "Instantiations refused by the live instance cap.",
+    kind: "counter",
+    value: |usage| usage.instances_rejected,
+  },
+  Metric {
+    name: "wasm_tenant_live_instances",
+    help: "Instances currently alive.",
+    kind: "gauge",
+    value: |usage| usage.live_instances as u64,
+  },
+];
+
+fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+fn deadline_ticks(deadline: Duration) -> u64 {
+  let ticks = deadline.as_nanos().div_ceil(EPOCH_TICK.as_nanos());
+  u64::try_from(ticks).unwrap_or(u64::MAX).max(1)
+}
+
+/// Holds one of the tenant's live instance slots until dropped.
+struct InstanceSlot {
+  tenant: Arc<Tenant>,
+}
+
+impl InstanceSlot {
+  fn acquire(tenant: Arc<Tenant>) -> Result<Self, SandboxError> {
+    let max = tenant.limits.max_instances;
+    let acquired =
+      tenant
+        .live_instances
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| {
+          (live < max).then_some(live + 1)
+        });
+    if acquired.is_err() {
+      tenant
+        .usage
+        .instances_rejected
+        .fetch_add(1, Ordering::Relaxed);
+      return Err(SandboxError::TooManyInstances {
+        tenant: tenant.name.clone(),
+        max,
+      });
+    }
+    Ok(Self { tenant })
+  }
+}
+
+impl Drop for InstanceSlot {
+  fn drop(&mut self) {
+    self.tenant.live_instances.fetch_sub(1, Ordering::AcqRel);
+  }
+}
+
+/// Token bucket of fuel: starts full, gains `refill` every whole `interval`
+/// and never holds more than `capacity`.
+#[derive(Debug)]
+struct FuelBucket {
+  capacity: u64,
+  refill: u64,
+  interval: Duration,
+  available: u64,
+  last_refill: Instant,
+}
+
+impl FuelBucket {
+  fn new(limits: &TenantLimits, now: Instant) -> Self {
+    Self {
+      capacity: limits.fuel_capacity,
+      refill: limits.fuel_refill,
+      interval: limits.fuel_refill_interval,
+      available: limits.fuel_capacity,
+      last_refill: now,
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    if self.interval.is_zero() || self.available == self.capacity {
+      self.last_refill = now;
+      return;
+    }
+    let elapsed = now.saturating_duration_since(self.last_refill);
+    let intervals = elapsed.as_nanos() / self.interval.as_nanos();
+    if intervals == 0 {
+      return;
+    }
+    let gained = u64::try_from(intervals)
+      .unwrap_or(u64::MAX)
+      .saturating_mul(self.refill);
+    self.available = self.available.saturating_add(gained).min(self.capacity);
+    self.last_refill += self.interval * u32::try_from(intervals).unwrap_or(u32::MAX);
+  }
+
+  fn available(&mut self, now: Instant) -> u64 {
+    self.refill(now);
+    self.available
+  }
+
+  fn reserve(&mut self, limit: u64, now: Instant) -> u64 {
+    self.refill(now);
+    let reserved = self.available.min(limit);
+    self.available -= reserved;
+    reserved
+  }
+
+  fn give_back(&mut self, fuel: u64) {
+    self.available = self.available.saturating_add(fuel).min(self.capacity);
+  }
+}
+
+struct TenantLimiter {
+  memory_limit_bytes: usize,
+  table_limit_elements: usize,
+  tenant: Arc<Tenant>,
+}
+
+impl ResourceLimiter for TenantLimiter {
+  fn memory_growing(
+    &mut self,
+    _current: usize,
+    desired: usize,
+    _maximum: Option<usize>,
+  ) -> wasmtime::Result<bool> {
+    let allowed = desired <= self.memory_limit_bytes;
+    if !allowed {
+      self
+        .tenant
+        .usage
+        .memory_denied
+        .fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(allowed)
+  }
+
+  fn table_growing(
+    &mut self,
+    _current: usize,
+    desired: usize,
+    _maximum: Option<usize>,
+  ) -> wasmtime::Result<bool> {
+    let allowed = desired <= self.table_limit_elements;
+    if !allowed {
+      self
+        .tenant
+        .usage
+        .table_denied
+        .fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(allowed)
+  }
+
+  /// Each `TenantInstance` owns its store, so a store never holds more than
+  /// one instance.
+  fn instances(&self) -> usize {
+    1
+  }
+
+  fn tables(&self) -> usize {
+    10
+  }
+
+  fn memories(&self) -> usize {
+    10
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sandbox_with(limits: TenantLimits) -> TenantSandbox {
+    let sandbox = TenantSandbox::new().expect("sandbox starts");
+    sandbox
+      .register_tenant("team-a", limits)
+      .expect("tenant registers");
+    sandbox
+  }
+
+  fn module(sandbox: &TenantSandbox, wat: &str) -> Module {
+    Module::new(sandbox.engine(), wat).expect("module compiles")
+  }
+
+  const SPIN: &str = r#"(module (func (export "spin") (loop $again br $again)))"#;
+
+  #[test]
+  fn fuel_bucket_refills_by_whole_intervals_up_to_capacity() {
+    let start = Instant::now();
+    let limits = TenantLimits::new().with_fuel(1_000, 100, Duration::from_millis(10));
+    let mut bucket = FuelBucket::new(&limits, start);
+
+    assert_eq!(bucket.reserve(10_000, start), 1_000);
+    assert_eq!(bucket.available(start + Duration::from_millis(25)), 200);
+    bucket.give_back(50);
+    assert_eq!(bucket.available(start + Duration::from_millis(30)), 350);
+    assert_eq!(bucket.available(start + Duration::from_secs(5)), 1_000);
+  }
+
+  #[test]
+  fn exhausted_tenant_is_refused_until_refilled() {
+    let sandbox =
+      sandbox_with(TenantLimits::new().with_fuel(10_000, 10_000, Duration::from_secs(3600)));
+    let module = module(&sandbox, SPIN);
+    let mut instance = sandbox.instantiate("team-a", &module).unwrap();
+
+    let err = instance.call::<(), ()>("spin", ()).unwrap_err();
+    assert!(matches!(err, SandboxError::OutOfFuel { .. }), "{err}");
+    let err = instance.call::<(), ()>("spin", ()).unwrap_err();
+    assert!(matches!(err, SandboxError::OutOfFuel { .. }), "{err}");
+
+    let usage = sandbox.usage("team-a").unwrap();
+    assert_eq!(usage.calls, 2);
+    assert_eq!(usage.completed_calls, 0);
+    assert_eq!(usage.out_of_fuel, 2);
+    assert_eq!(usage.fuel_available, 0);
+    assert!(usage.fuel_consumed >= 9_000, "{usage}");
+  }
+
+  #[test]
+  fn deadline_interrupts_long_calls() {
+    let sandbox = sandbox_with(
+      TenantLimits::new()
+        .with_fuel(1 << 40, 0, Duration::ZERO)
+        .with_call_fuel(1 << 40)
+        .with_deadline(Duration::from_millis(30)),
+    );
+    let module = module(&sandbox, SPIN);
+    let mut instance = sandbox.instantiate("team-a", &module).unwrap();
+
+    let err = instance.call::<(), ()>("spin", ()).unwrap_err();
+    assert!(
+      matches!(err, SandboxError::DeadlineExceeded { .. }),
+      "{err}"
+    );
+    let usage = sandbox.usage("team-a").unwrap();
+    assert_eq!(usage.deadline_exceeded, 1);
+    assert_eq!(usage.fuel_consumed, 1 << 40);
+  }
+
+  #[test]
+  fn live_instances_are_capped_per_tenant() {
+    let sandbox = sandbox_with(TenantLimits::new().with_instances(1));
+    sandbox
+      .register_tenant("team-b", TenantLimits::new().with_instances(1))
+      .unwrap();
+    let module = module(
+      &sandbox,
+      r#"(module (func (export "answer") (result i32) i32.const 42))"#,
+    );
+
+    let first = sandbox.instantiate("team-a", &module).unwrap();
+    let err = sandbox.instantiate("team-a", &module).err().unwrap();
+    assert!(
+      matches!(err, SandboxError::TooManyInstances { max: 1, .. }),
+      "{err}"
+    );
+    let _other_tenant = sandbox.instantiate("team-b", &module).unwrap();
+
+    drop(first);
+    let mut again = sandbox.instantiate("team-a", &module).unwrap();
+    assert_eq!(again.call::<(), i32>("answer", ()).unwrap(), 42);
+
+    let usage = sandbox.usage("team-a").unwrap();
+    assert_eq!(usage.instances_started, 2);
+    assert_eq!(usage.instances_rejected, 1);
+    assert_eq!(usage.live_instances, 1);
+  }
+
+  #[test]
+  fn denied_growth_is_counted_and_exported() {
+    let sandbox = sandbox_with(TenantLimits::new().with_memory_bytes(2 * WASM_PAGE_SIZE));
+    let module = module(
+      &sandbox,
+      r#"(module (memory 1 10) (func (export "grow") (param i32) (result i32) local.get 0 memory.grow))"#,
+    );
+    let mut instance = sandbox.instantiate("team-a", &module).unwrap();
+
+    assert_eq!(instance.call::<i32, i32>("grow", 1).unwrap(), 1);
+    assert_eq!(instance.call::<i32, i32>("grow", 1).unwrap(), -1);
+    assert_eq!(sandbox.usage("team-a").unwrap().memory_denied, 1);
+
+    let metrics = sandbox.render_prometheus();
+    assert!(metrics.contains("# TYPE wasm_tenant_memory_denied_total counter"));
+    assert!(metrics.contains("wasm_tenant_memory_denied_total{tenant=\"team-a\"} 1"));
+    assert!(metrics.contains("wasm_tenant_completed_calls_total{tenant=\"team-a\"} 2"));
+  }
+}
@@ -0,0 +1,407 @@
+//! Persistent record of transcription jobs.
+//!
+//! Every state change is written to a JSON file before it takes effect for
+//! the workers, so a crashed or killed batch picks up where it stopped: jobs
+//! that were running go back to the queue, finished jobs stay finished.
+
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::{BufWriter, ErrorKind, Write},
+  path::{Path, PathBuf},
+  sync::{Mutex, MutexGuard, PoisonError},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::output::OutputFormat;
+
+/// Failed jobs are retried on later runs until they failed this many times.
+pub const MAX_ATTEMPTS: u32 = 3;
+/// Progress is written to disk each time it crosses a multiple of this many
+/// percent, it is always up to date in memory.
+const PROGRESS_PERSIST_STEP: u8 = 10;
+
+pub type JobId = u64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+  pub id: JobId,
+  pub input: PathBuf,
+  /// Outputs are this path with the extension of each format.
+  pub output_base: PathBuf,
+  pub formats: Vec<OutputFormat>,
+  pub language: String,
+  pub status: JobStatus,
+  pub attempts: u32,
+  /// Seconds since the Unix epoch.
+  pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+  Queued,
+  Running {
+    stage: Stage,
+    /// Transcription progress in percent.
+    progress: u8,
+  },
+  Done {
+    outputs: Vec<PathBuf>,
+    language: Option<String>,
+  },
+  Skipped {
+    reason: String,
+  },
+  Failed {
+    error: String,
+  },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+  ExtractingAudio,
+  Transcribing,
+  Writing,
+}
+
+impl JobStatus {
+  fn is_active(&self) -> bool {
+    matches!(self, Self::Queued | Self::Running { .. })
+  }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LedgerState {
+  next_id: JobId,
+  jobs: BTreeMap<JobId, Job>,
+}
+
+pub struct Ledger {
+  path: PathBuf,
+  state: Mutex<LedgerState>,
+}
+
+/// What [`Ledger::enqueue`] did with a scanned file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+  New(JobId),
+  /// An unfinished or retryable job of an earlier run.
+  Resumed(JobId),
+  /// The file already failed [`MAX_ATTEMPTS`] times.
+  GaveUp(JobId),
+}
+
+impl Ledger {
+  /// Loads the ledger at `path`, or starts an empty one. Jobs that were
+  /// running when the previous process stopped are queued again.
+  pub fn open(path: &Path) -> Result<Self> {
+    let mut state = match fs::read(path) {
+      Ok(bytes) => serde_json::from_slice::<LedgerState>(&bytes)
+        .with_context(|| format!("failed to parse job ledger {}", path.display()))?,
+      Err(err) if err.kind() == ErrorKind::NotFound => LedgerState::default(),
+      Err(err) => {
+        return Err(err).with_context(|| format!("failed to read job ledger {}", path.display()));
+      }
+    };
+
+    for job in state.jobs.values_mut() {
+      if matches!(job.status, JobStatus::Running { .. }) {
+        job.status = JobStatus::Queued;
+      }
+    }
+
+    let ledger = Self {
+      path: path.to_path_buf(),
+      state: Mutex::new(state),
+    };
+    ledger.persist(&ledger.lock())?;
+    Ok(ledger)
+  }
+
+  fn lock(&self) -> MutexGuard<'_, LedgerState> {
+    self.state.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn persist(&self, state: &LedgerState) -> Result<()> {
+    if let Some(parent) = self.path.parent() {
+      fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create ledger directory {}", parent.display()))?;
+    }
+    let temp_path = self.path.with_extension("json.tmp");
+    let file = fs::File::create(&temp_path)
+      .with_context(|| format!("failed to create {}", temp_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, state)?;
+    writer
+      .flush()
+      .with_context(|| format!("failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, &self.path)
+      .with_context(|| format!("failed to replace job ledger {}", self.path.display()))
+  }
+
+  /// Records a file found by a directory scan. A file gets at most one job;
+  /// its unfinished or failed job is reused rather than duplicated.
+  pub fn enqueue(
+    &self,
+    input: &Path,
+    output_base: &Path,
+    formats: &[OutputFormat],
+    language: &str,
+  ) -> Result<Enqueued> {
+    let mut state = self.lock();
+    let existing = state.jobs.values_mut().rev().find(|job| job.input == input);
+
+    let enqueued = match existing {
+      Some(job) if job.status.is_active() => return Ok(Enqueued::Resumed(job.id)),
+      Some(job)
+        if matches!(job.status, JobStatus::Failed { .. }) && job.attempts >= MAX_ATTEMPTS =>
+      {
+        return Ok(Enqueued::GaveUp(job.id));
+      }
+      Some(job) => {
+        // Finished or skipped before, but the scan found outputs missing.
+        job.output_base = output_base.to_path_buf();
+        job.formats = formats.to_vec();
+        job.language = language.to_owned();
+        job.status = JobStatus::Queued;
+        job.updated_at = now();
+        Enqueued::Resumed(job.id)
+      }
+      None => Enqueued::New(insert_job(
+        &mut state,
+        input,
+        output_base,
+        formats,
+        language,
+      )),
+    };
+    self.persist(&state)?;
+    Ok(enqueued)
+  }
+
+  /// Queues an explicitly submitted file, even if it was done before.
+  /// Returns the active job instead if the file is already queued or running.
+  pub fn submit(
+    &self,
+    input: &Path,
+    output_base: &Path,
+    formats: &[OutputFormat],
+    language: &str,
+  ) -> Result<std::result::Result<Job, Job>> {
+    let mut state = self.lock();
+    if let Some(job) = state
+      .jobs
+      .values()
+      .find(|job| job.input == input && job.status.is_active())
+    {
+      return Ok(Err(job.clone()));
+    }
+
+    let id = insert_job(&mut state, input, output_base, formats, language);
+    self.persist(&state)?;
+    Ok(Ok(state.jobs[&id].clone()))
+  }
+
+  /// Queued jobs in submission order.
+  pub fn queued(&self) -> Vec<JobId> {
+    self
+      .lock()
+      .jobs
+      .values()
+      .filter(|job| job.status == JobStatus::Queued)
+      .map(|job| job.id)
+      .collect()
+  }
+
+  pub fn get(&self, id: JobId) -> Option<Job> {
+    self.lock().jobs.get(&id).cloned()
+  }
+
+  pub fn jobs(&self) -> Vec<Job> {
+    self.lock().jobs.values().cloned().collect()
+  }
+
+  /// Marks a queued job as running and returns it, or `None` if it is gone
+  /// or no longer queued.
+  pub fn start(&self, id: JobId) -> Result<Option<Job>> {
+    let mut state = self.lock();
+    let Some(job) = state.jobs.get_mut(&id) else {
+      return Ok(None);
+    };
+    if job.status != JobStatus::Queued {
+      return Ok(None);
+    }
+    job.status = JobStatus::Running {
+      stage: Stage::ExtractingAudio,
+      progress: 0,
+    };
+    job.attempts += 1;
+    job.updated_at = now();
+    let job = job.clone();
+    self.persist(&state)?;
+    Ok(Some(job))
+  }
+
+  pub fn set_progress(&self, id: JobId, stage: Stage, progress: u8) -> Result<()> {
+    let mut state = self.lock();
+    let Some(job) = state.jobs.get_mut(&id) else {
+      return Ok(());
+    };
+    let JobStatus::Running {
+      stage: old_stage,
+      progress: old_progress,
+    } = job.status
+    else {
+      return Ok(());
+    };
+    if (old_stage, old_progress) == (stage, progress) {
+      return Ok(());
+    }
+    job.status = JobStatus::Running { stage, progress };
+    job.updated_at = now();
+    if old_stage != stage
+      || progress / PROGRESS_PERSIST_STEP != old_progress / PROGRESS_PERSIST_STEP
+    {
+      self.persist(&state)?;
+    }
+    Ok(())
+  }
+
+  pub fn finish(&self, id: JobId, status: JobStatus) -> Result<()> {
+    let mut state = self.lock();
+    if let Some(job) = state.jobs.get_mut(&id) {
+      job.status = status;
+      job.updated_at = now();
+    }
+    self.persist(&state)
+  }
+}
+
+fn insert_job(
+  state: &mut LedgerState,
+  input: &Path,
+  output_base: &Path,
+  formats: &[OutputFormat],
+  language: &str,
+) -> JobId {
+  let id = state.next_id;
+  state.next_id += 1;
+  state.jobs.insert(
+    id,
+    Job {
+      id,
+      input: input.to_path_buf(),
+      output_base: output_base.to_path_buf(),
+      formats: formats.to_vec(),
+      language: language.to_owned(),
+      status: JobStatus::Queued,
+      attempts: 0,
+      updated_at: now(),
+    },
+  );
+  id
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn enqueue(ledger: &Ledger, input: &str) -> Result<Enqueued> {
+    ledger.enqueue(
+      Path::new(input),
+      Path::new(input),
+      &[OutputFormat::Srt],
+      "en",
+    )
+  }
+
+  #[test]
+  fn requeues_running_jobs_after_restart() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("jobs.json");
+
+    let ledger = Ledger::open(&path)?;
+    let Enqueued::New(first) = enqueue(&ledger, "/media/a.mp4")? else {
+      panic!("expected a new job");
+    };
+    let Enqueued::New(second) = enqueue(&ledger, "/media/b.mp4")? else {
+      panic!("expected a new job");
+    };
+    ledger.start(first)?;
+    ledger.set_progress(first, Stage::Transcribing, 40)?;
+    drop(ledger);
+
+    let ledger = Ledger::open(&path)?;
+    assert_eq!(ledger.queued(), [first, second]);
+    assert_eq!(ledger.get(first).map(|job| job.attempts), Some(1));
+    assert_eq!(enqueue(&ledger, "/media/a.mp4")?, Enqueued::Resumed(first));
+    Ok(())
+  }
+
+  #[test]
+  fn gives_up_after_max_attempts() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let ledger = Ledger::open(&dir.path().join("jobs.json"))?;
+    let Enqueued::New(id) = enqueue(&ledger, "/media/a.mp4")? else {
+      panic!("expected a new job");
+    };
+
+    for attempt in 1 ..= MAX_ATTEMPTS {
+      assert!(ledger.start(id)?.is_some());
+      ledger.finish(
+        id,
+        JobStatus::Failed {
+          error: String::from("boom"),
+        },
+      )?;
+      let expected = if attempt < MAX_ATTEMPTS {
+        Enqueued::Resumed(id)
+      } else {
+        Enqueued::GaveUp(id)
+      };
+      assert_eq!(enqueue(&ledger, "/media/a.mp4")?, expected);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn submit_refuses_duplicate_active_jobs() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let ledger = Ledger::open(&dir.path().join("jobs.json"))?;
+    let input = Path::new("/media/a.mp4");
+
+    let job = ledger
+      .submit(input, input, &[OutputFormat::Json], "auto")?
+      .expect("first submission is accepted");
+    let active = ledger
+      .submit(input, input, &[OutputFormat::Json], "auto")?
+      .expect_err("second submission finds the queued job");
+    assert_eq!(active.id, job.id);
+
+    ledger.start(job.id)?;
+    ledger.finish(
+      job.id,
+      JobStatus::Done {
+        outputs: vec![input.with_extension("json")],
+        language: Some(String::from("de")),
+      },
+    )?;
+    assert!(
+      ledger
+        .submit(input, input, &[OutputFormat::Json], "auto")?
+        .is_ok()
+    );
+    Ok(())
+  }
+}
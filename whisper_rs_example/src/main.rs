@@ -2,9 +2,11 @@ use std::{
   env,
   ffi::{CStr, CString},
   fs,
-  io::{ErrorKind, Write},
+  io::ErrorKind,
+  net::SocketAddr,
+  num::NonZeroUsize,
   os::{
-    raw::{c_char, c_int},
+    raw::{c_char, c_int, c_void},
     unix::ffi::OsStrExt,
   },
   path::{Path, PathBuf},
@@ -14,18 +16,33 @@ use std::{
 };
 
 use anyhow::{Context, Result, bail, ensure};
+use clap::{Parser, builder::FalseyValueParser};
 use many_cpus::SystemHardware;
 use tempfile::Builder;
 use walkdir::WalkDir;
 use whisper_rs::whisper_rs_sys;
 
+mod ledger;
+mod output;
+mod server;
+
+use ledger::{Enqueued, Job, JobId, JobStatus, Ledger, Stage};
+use output::{OutputFormat, TokenPiece, TranscribedSegment, Transcript};
+
 const DEFAULT_BACKUP_DIR: &str = "backup-20251212";
+const DEFAULT_LEDGER_FILE: &str = ".whisper-jobs.json";
 const WHISPER_MODEL_ENV: &str = "WHISPER_MODEL";
+const WHISPER_LANG_ENV: &str = "WHISPER_LANG";
 const JOBS_ENV: &str = "MP4S_TO_SRT_JOBS";
 const WHISPER_THREADS_ENV: &str = "WHISPER_THREADS";
 const WHISPER_PROMPT_ENV: &str = "WHISPER_PROMPT";
 const WHISPER_NO_GPU_ENV: &str = "WHISPER_NO_GPU";
 const VALIDATE_AUDIO_ENV: &str = "MP4S_TO_SRT_VALIDATE_AUDIO";
+const BACKUP_DIR_ENV: &str = "MP4S_TO_SRT_BACKUP_DIR";
+const FORMATS_ENV: &str = "MP4S_TO_SRT_FORMATS";
+const LEDGER_ENV: &str = "MP4S_TO_SRT_LEDGER";
+const SERVE_ENV: &str = "MP4S_TO_SRT_SERVE";
+const SERVE_TOKEN_ENV: &str = "MP4S_TO_SRT_SERVE_TOKEN";
 const WHISPER_SAMPLE_RATE: u32 = 16_000;
 const DEFAULT_MAX_WHISPER_THREADS_PER_TASK: usize = 4;
 const COMMAND_ERROR_TAIL_LINES: usize = 8;
@@ -43,41 +60,117 @@ const SUPPORTED_MEDIA_EXTENSIONS: &[&str] = &[
   "wma", "wmv",
 ];
 
+/// Transcribes the media files under a directory with whisper.cpp. Every
+/// option can also be set through its environment variable.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+  /// Directory to scan for media files [default: current directory]
+  scan_dir: Option<PathBuf>,
+
+  /// ggml whisper model file
+  #[arg(long, env = WHISPER_MODEL_ENV)]
+  model: PathBuf,
+
+  /// Spoken language, or `auto` to detect it for each file
+  #[arg(long, env = WHISPER_LANG_ENV, default_value = "en")]
+  language: String,
+
+  /// Output formats written next to each media file
+  #[arg(
+    long,
+    env = FORMATS_ENV,
+    value_enum,
+    value_delimiter = ',',
+    default_value = "srt"
+  )]
+  formats: Vec<OutputFormat>,
+
+  /// Files transcribed in parallel [default: number of processors]
+  #[arg(long, env = JOBS_ENV)]
+  jobs: Option<NonZeroUsize>,
+
+  /// whisper threads per file [default: number of processors, at most 4]
+  #[arg(long, env = WHISPER_THREADS_ENV)]
+  whisper_threads: Option<NonZeroUsize>,
+
+  /// Initial prompt to steer spelling and style
+  #[arg(long, env = WHISPER_PROMPT_ENV, default_value = "")]
+  prompt: String,
+
+  #[arg(long, env = WHISPER_NO_GPU_ENV, value_parser = FalseyValueParser::new())]
+  no_gpu: bool,
+
+  /// Check audio streams with ffprobe and skip files whose audio does not
+  /// extract completely
+  #[arg(long, env = VALIDATE_AUDIO_ENV, value_parser = FalseyValueParser::new())]
+  validate_audio: bool,
+
+  /// Where outputs go for media in read-only directories
+  /// [default: ~/backup-20251212]
+  #[arg(long, env = BACKUP_DIR_ENV)]
+  backup_dir: Option<PathBuf>,
+
+  /// Job ledger that lets an interrupted batch resume
+  /// [default: ~/.whisper-jobs.json]
+  #[arg(long, env = LEDGER_ENV)]
+  ledger: Option<PathBuf>,
+
+  /// Keep running after the scan and accept jobs over HTTP on this address.
+  /// Addresses other than loopback need `--serve-token`
+  #[arg(long, env = SERVE_ENV)]
+  serve: Option<SocketAddr>,
+
+  /// Bearer token every HTTP API request has to send
+  #[arg(long, env = SERVE_TOKEN_ENV, hide_env_values = true)]
+  serve_token: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 struct Config {
   scan_dir: PathBuf,
   backup_dir: PathBuf,
+  ledger: PathBuf,
   model: PathBuf,
   language: String,
+  formats: Vec<OutputFormat>,
+  prompt: String,
   jobs: Option<usize>,
   whisper_threads: Option<usize>,
   use_gpu: bool,
   validate_audio: bool,
+  serve: Option<SocketAddr>,
+  serve_token: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 struct Task {
   input: PathBuf,
-  output: PathBuf,
+  /// Outputs are this path with the extension of each format.
+  output_base: PathBuf,
+  /// Requested formats that do not exist yet.
+  formats: Vec<OutputFormat>,
 }
 
 #[derive(Debug)]
 pub struct TaskResult {
   pub input: PathBuf,
-  output: PathBuf,
   result: Result<TaskOutcome>,
 }
 
 #[derive(Debug)]
 enum TaskOutcome {
-  Generated,
+  Generated {
+    outputs: Vec<PathBuf>,
+    language: Option<String>,
+  },
   Skipped(String),
 }
 
 fn main() -> Result<()> {
   whisper_rs::install_logging_hooks();
 
-  let config = Config::from_env()?;
+  let config = Config::parse()?;
 
   ensure_command_available("ffmpeg")?;
   if config.validate_audio {
@@ -89,23 +182,46 @@ fn main() -> Result<()> {
     config.model.display()
   );
 
-  let tasks = collect_tasks(&config)?;
-  if tasks.is_empty() {
+  let ledger = Arc::new(Ledger::open(&config.ledger)?);
+  for task in collect_tasks(&config)? {
+    match ledger.enqueue(
+      &task.input,
+      &task.output_base,
+      &task.formats,
+      &config.language,
+    )? {
+      Enqueued::New(_) => {}
+      Enqueued::Resumed(id) => eprintln!("Resuming job {id}: {}", task.input.display()),
+      Enqueued::GaveUp(id) => eprintln!(
+        "Not retrying job {id} after {} failed attempts: {}",
+        ledger::MAX_ATTEMPTS,
+        task.input.display()
+      ),
+    }
+  }
+
+  // Also picks up queued jobs of files outside this scan, e.g. submitted
+  // over HTTP before a restart.
+  let queued = ledger.queued();
+  if queued.is_empty() && config.serve.is_none() {
     eprintln!(
-      "No supported media files need SRT generation under: {}",
+      "No supported media files need transcription under: {}",
       config.scan_dir.display()
     );
     return Ok(());
   }
 
   let default_jobs = default_jobs();
-  let jobs = config.jobs.unwrap_or(default_jobs).min(tasks.len());
+  let mut jobs = config.jobs.unwrap_or(default_jobs);
+  if config.serve.is_none() {
+    jobs = jobs.min(queued.len());
+  }
   let default_whisper_threads = default_whisper_threads();
   let whisper_threads = config.whisper_threads.unwrap_or(default_whisper_threads);
   let validation_policy = if config.validate_audio {
     "strict audio validation enabled"
   } else {
-    "script-compatible fast path; set MP4S_TO_SRT_VALIDATE_AUDIO=1 for ffprobe checks"
+    "script-compatible fast path; pass --validate-audio for ffprobe checks"
   };
   let gpu_policy = if config.use_gpu {
     "GPU enabled"
@@ -113,57 +229,67 @@ fn main() -> Result<()> {
     "GPU disabled by WHISPER_NO_GPU"
   };
   eprintln!(
-    "Processing {} media file(s) with {jobs} worker thread(s), {whisper_threads} whisper thread(s)
-      per worker ({validation_policy}; {gpu_policy})",
-    tasks.len()
+    "Processing {} queued media file(s) with {jobs} worker thread(s), {whisper_threads} whisper \
+     thread(s)
+      per worker ({validation_policy}; {gpu_policy}; ledger {})",
+    queued.len(),
+    config.ledger.display()
   );
 
-  run_tasks(tasks, config, jobs, whisper_threads)
+  run_jobs(queued, config, ledger, jobs.max(1), whisper_threads)
 }
 
 impl Config {
-  fn from_env() -> Result<Self> {
-    let args = env::args_os().skip(1).collect::<Vec<_>>();
-    ensure!(args.len() <= 1, "usage: whisper_rs_example [SCAN_DIR]");
+  fn parse() -> Result<Self> {
+    let args = Args::parse();
 
-    let scan_dir = match args.first() {
-      Some(path) => PathBuf::from(path),
+    let scan_dir = match args.scan_dir {
+      Some(path) => path,
       None => env::current_dir().context("failed to read current directory")?,
     }
     .canonicalize()
     .context("failed to canonicalize scan directory")?;
 
-    let backup_dir = backup_dir()?;
-    let model_path = env::var_os(WHISPER_MODEL_ENV)
-      .map(PathBuf::from)
-      .with_context(|| format!("{WHISPER_MODEL_ENV} is not set"))?;
-    let model = expand_home_path(model_path)?;
-    let language = env::var("WHISPER_LANG").unwrap_or_else(|_| String::from("en"));
-    let jobs = optional_positive_usize(JOBS_ENV)?;
-    let whisper_threads = optional_positive_usize(WHISPER_THREADS_ENV)?;
-    let use_gpu = !env_flag_enabled(WHISPER_NO_GPU_ENV);
-    let validate_audio = env_flag_enabled(VALIDATE_AUDIO_ENV);
+    let backup_dir = path_or_home_default(args.backup_dir, DEFAULT_BACKUP_DIR)?;
+    let ledger = path_or_home_default(args.ledger, DEFAULT_LEDGER_FILE)?;
+    let model = expand_home_path(args.model)?;
+    let mut formats = args.formats;
+    formats.dedup();
+    ensure!(!formats.is_empty(), "at least one output format is needed");
+    let serve_token = args.serve_token.filter(|token| !token.is_empty());
+    if let Some(addr) = args.serve {
+      ensure!(
+        addr.ip().is_loopback() || serve_token.is_some(),
+        "--serve {addr} is reachable from other hosts; set --serve-token or bind to a loopback \
+         address"
+      );
+    }
 
     Ok(Self {
       scan_dir,
       backup_dir,
+      ledger,
       model,
-      language,
-      jobs,
-      whisper_threads,
-      use_gpu,
-      validate_audio,
+      language: args.language,
+      formats,
+      prompt: args.prompt,
+      jobs: args.jobs.map(NonZeroUsize::get),
+      whisper_threads: args.whisper_threads.map(NonZeroUsize::get),
+      use_gpu: !args.no_gpu,
+      validate_audio: args.validate_audio,
+      serve: args.serve,
+      serve_token,
     })
   }
 }
 
-fn backup_dir() -> Result<PathBuf> {
-  if let Some(path) = env::var_os("MP4S_TO_SRT_BACKUP_DIR") {
-    return expand_home_path(PathBuf::from(path));
+fn path_or_home_default(path: Option<PathBuf>, default: &str) -> Result<PathBuf> {
+  if let Some(path) = path {
+    return expand_home_path(path);
   }
 
   let home = env::var_os("HOME").context("HOME is not set")?;
-  Ok(PathBuf::from(home).join(DEFAULT_BACKUP_DIR))
+  Ok(PathBuf::from(home).join(default))
 }
 
 fn expand_home_path(path: PathBuf) -> Result<PathBuf> {
@@ -184,31 +310,6 @@ fn expand_home_path(path: PathBuf) -> Result<PathBuf> {
   Ok(path)
 }
 
-fn optional_positive_usize(env_name: &str) -> Result<Option<usize>> {
-  let Some(value) = env::var_os(env_name) else {
-    return Ok(None);
-  };
-
-  let value = value
-    .to_string_lossy()
-    .parse::<usize>()
-    .with_context(|| format!("{env_name} must be a positive integer"))?;
-  ensure!(value > 0, "{env_name} must be a positive integer");
-  Ok(Some(value))
-}
-
-fn env_flag_enabled(env_name: &str) -> bool {
-  let Ok(value) = env::var(env_name) else {
-    return false;
-  };
-  let value = value.trim();
-  !(value.is_empty()
-    || value == "0"
-    || value.eq_ignore_ascii_case("false")
-    || value.eq_ignore_ascii_case("no")
-    || value.eq_ignore_ascii_case("off"))
-}
-
 fn default_jobs() -> usize {
   processor_count()
 }
@@ -248,11 +349,7 @@ fn collect_tasks(config: &Config) -> Result<Vec<Task>> {
 }
 
 fn task_for_input(input: &Path, config: &Config) -> Result<Option<Task>> {
-  if let Some(existing_subtitle) = existing_subtitle_for(input) {
-    eprintln!(
-      "Skipping existing subtitle: {}",
-      existing_subtitle.display()
-    );
+  if missing_formats(input, &config.formats).is_empty() {
     return Ok(None);
   }
 
@@ -277,36 +374,53 @@ fn task_for_input(input: &Path, config: &Config) -> Result<Option<Task>> {
     }
   }
 
+  let output_base = output_base_for(input, config)?;
+  let formats = missing_formats(&output_base, &config.formats);
+  if formats.is_empty() {
+    return Ok(None);
+  }
+
+  Ok(Some(Task {
+    input: input.to_path_buf(),
+    output_base,
+    formats,
+  }))
+}
+
+/// Outputs go next to the input, or under the backup directory when the
+/// input directory is read-only.
+fn output_base_for(input: &Path, config: &Config) -> Result<PathBuf> {
   let input_dir = input
     .parent()
     .with_context(|| format!("failed to read parent directory for {}", input.display()))?;
 
-  let output = if can_create_file_in(input_dir) {
-    input.with_extension("srt")
+  if can_create_file_in(input_dir) {
+    Ok(input.to_path_buf())
   } else {
     let relative_input = input.strip_prefix(&config.scan_dir).unwrap_or(input);
-    let backup_base = config.backup_dir.join(relative_input);
-    if let Some(existing_subtitle) = existing_subtitle_for(&backup_base) {
-      eprintln!(
-        "Skipping existing subtitle: {}",
-        existing_subtitle.display()
-      );
-      return Ok(None);
-    }
-    backup_base.with_extension("srt")
-  };
-
-  Ok(Some(Task {
-    input: input.to_path_buf(),
-    output,
-  }))
+    Ok(config.backup_dir.join(relative_input))
+  }
 }
 
-fn existing_subtitle_for(input: &Path) -> Option<PathBuf> {
-  ["srt", "vtt"]
-    .into_iter()
-    .map(|extension| input.with_extension(extension))
-    .find(|subtitle| subtitle.is_file())
+/// Requested formats without an output yet. Logs the existing outputs when
+/// there is nothing left to generate.
+fn missing_formats(output_base: &Path, formats: &[OutputFormat]) -> Vec<OutputFormat> {
+  let mut missing = Vec::new();
+  let mut existing = Vec::new();
+  for &format in formats {
+    match format.existing_output(output_base) {
+      Some(output) if !existing.contains(&output) => existing.push(output),
+      Some(_) => {}
+      None => missing.push(format),
+    }
+  }
+
+  if missing.is_empty() {
+    for output in existing {
+      eprintln!("Skipping existing output: {}", output.display());
+    }
+  }
+  missing
 }
 
 fn is_supported_media(path: &Path) -> bool {
@@ -337,13 +451,32 @@ fn can_create_file_in(dir: &Path) -> bool {
   }
 }
 
-fn run_tasks(tasks: Vec<Task>, config: Config, jobs: usize, whisper_threads: usize) -> Result<()> {
+fn run_jobs(
+  queued: Vec<JobId>,
+  config: Config,
+  ledger: Arc<Ledger>,
+  jobs: usize,
+  whisper_threads: usize,
+) -> Result<()> {
   eprintln!("Loading whisper model: {}", config.model.display());
   let whisper_context = Arc::new(RawWhisperContext::new(&config.model, config.use_gpu)?);
+  let config = Arc::new(config);
 
-  let (task_tx, task_rx) = mpsc::channel::<Task>();
+  let (task_tx, task_rx) = mpsc::channel::<JobId>();
   let (result_tx, result_rx) = mpsc::channel::<TaskResult>();
 
+  // The server keeps a sender alive, so workers wait for submissions instead
+  // of stopping once the scanned jobs are done.
+  let _server = match config.serve {
+    Some(addr) => Some(server::spawn(
+      addr,
+      Arc::clone(&config),
+      Arc::clone(&ledger),
+      task_tx.clone(),
+    )?),
+    None => None,
+  };
+
   let worker_count = jobs.max(1);
   let mut workers = Vec::with_capacity(worker_count);
   let task_rx = Arc::new(std::sync::Mutex::new(task_rx));
@@ -351,7 +484,8 @@ fn run_tasks(tasks: Vec<Task>, config: Config, jobs: usize, whisper_threads: usi
   for _ in 0 .. worker_count {
     let task_rx = Arc::clone(&task_rx);
     let result_tx = result_tx.clone();
-    let config = config.clone();
+    let config = Arc::clone(&config);
+    let ledger = Arc::clone(&ledger);
     let whisper_context = Arc::clone(&whisper_context);
 
     workers.push(thread::spawn(move || {
@@ -364,15 +498,37 @@ fn run_tasks(tasks: Vec<Task>, config: Config, jobs: usize, whisper_threads: usi
           task_rx.recv()
         };
 
-        let task = match task {
-          Ok(task) => task,
+        let id = match task {
+          Ok(id) => id,
           Err(_) => break,
         };
 
-        let result = process_task(&config, &whisper_context, &task, whisper_threads);
+        let job = match ledger.start(id) {
+          Ok(Some(job)) => job,
+          Ok(None) => continue,
+          Err(err) => {
+            eprintln!("failed to start job {id}: {err:#}");
+            continue;
+          }
+        };
+        let result = process_job(&config, &ledger, &whisper_context, &job, whisper_threads);
+        let status = match &result {
+          Ok(TaskOutcome::Generated { outputs, language }) => JobStatus::Done {
+            outputs: outputs.clone(),
+            language: language.clone(),
+          },
+          Ok(TaskOutcome::Skipped(reason)) => JobStatus::Skipped {
+            reason: reason.clone(),
+          },
+          Err(err) => JobStatus::Failed {
+            error: format!("{err:#}"),
+          },
+        };
+        if let Err(err) = ledger.finish(id, status) {
+          eprintln!("failed to record the result of job {id}: {err:#}");
+        }
         let _ = result_tx.send(TaskResult {
-          input: task.input,
-          output: task.output,
+          input: job.input,
           result,
         });
       }
@@ -381,17 +537,24 @@ fn run_tasks(tasks: Vec<Task>, config: Config, jobs: usize, whisper_threads: usi
 
   drop(result_tx);
 
-  for task in tasks {
+  for id in queued {
     task_tx
-      .send(task)
-      .context("failed to send task to worker thread")?;
+      .send(id)
+      .context("failed to send job to worker thread")?;
   }
   drop(task_tx);
 
   let mut failed = 0usize;
   for task_result in result_rx {
     match task_result.result {
-      Ok(TaskOutcome::Generated) => eprintln!("Generated: {}", task_result.output.display()),
+      Ok(TaskOutcome::Generated { outputs, language }) => {
+        for output in outputs {
+          eprintln!("Generated: {}", output.display());
+        }
+        if let Some(language) = language {
+          eprintln!("  language: {language}");
+        }
+      }
       Ok(TaskOutcome::Skipped(reason)) => {
         eprintln!("Skipped: {},   {reason}", task_result.input.display());
       }
@@ -413,13 +576,14 @@ fn run_tasks(tasks: Vec<Task>, config: Config, jobs: usize, whisper_threads: usi
   Ok(())
 }
 
-fn process_task(
+fn process_job(
   config: &Config,
+  ledger: &Ledger,
   whisper_context: &RawWhisperContext,
-  task: &Task,
+  job: &Job,
   whisper_threads: usize,
 ) -> Result<TaskOutcome> {
-  if let Some(parent) = task.output.parent() {
+  if let Some(parent) = job.output_base.parent() {
     fs::create_dir_all(parent)
       .with_context(|| format!("failed to create output directory {}", parent.display()))?;
   }
@@ -434,48 +598,91 @@ fn process_task(
     .tempfile_in(temp_dir.path())
     .context("failed to create temp wav file")?;
   let wav_path = wav_file.path().to_path_buf();
-  let generated_srt = temp_dir.path().join("output.srt");
 
-  eprintln!("Extracting audio: {}", task.input.display());
-  if let Err(err) = extract_audio(&task.input, &wav_path) {
+  eprintln!("Extracting audio: {}", job.input.display());
+  if let Err(err) = extract_audio(&job.input, &wav_path) {
     return Ok(TaskOutcome::Skipped(format!(
       "failed to extract audio with whisper.cpp script ffmpeg arguments: {err:#}"
     )));
   }
-  if config.validate_audio {
-    if let Err(err) = ensure_complete_audio(&task.input, &wav_path) {
-      return Ok(TaskOutcome::Skipped(format!(
-        "extracted audio is incomplete, not generating a short SRT: {err:#}"
-      )));
-    }
+  if config.validate_audio
+    && let Err(err) = ensure_complete_audio(&job.input, &wav_path)
+  {
+    return Ok(TaskOutcome::Skipped(format!(
+      "extracted audio is incomplete, not generating short output: {err:#}"
+    )));
   }
 
-  eprintln!("Transcribing to SRT: {}", task.output.display());
-  transcribe_to_srt(
+  eprintln!("Transcribing: {}", job.input.display());
+  let progress = ProgressReporter {
+    ledger,
+    job_id: job.id,
+  };
+  progress.set(Stage::Transcribing, 0);
+  let transcript = transcribe(
     config,
     whisper_context,
+    job,
     &wav_path,
-    &generated_srt,
     whisper_threads,
+    &progress,
   )?;
 
-  ensure!(
-    generated_srt.is_file(),
-    "whisper-rs did not produce an SRT file: {}",
-    generated_srt.display()
-  );
-  move_generated_srt(&generated_srt, &task.output)?;
+  progress.set(Stage::Writing, 100);
+  let mut outputs = Vec::with_capacity(job.formats.len());
+  for &format in &job.formats {
+    let generated = temp_dir
+      .path()
+      .join("output")
+      .with_extension(format.extension());
+    let target = job.output_base.with_extension(format.extension());
+    output::write_transcript(&transcript, format, &generated)?;
+    move_generated_output(&generated, &target)?;
+    outputs.push(target);
+  }
 
-  Ok(TaskOutcome::Generated)
+  Ok(TaskOutcome::Generated {
+    outputs,
+    language: transcript.language,
+  })
 }
 
-fn move_generated_srt(source: &Path, target: &Path) -> Result<()> {
+/// Records the stage and progress of a running job in the ledger. whisper.cpp
+/// calls [`report_progress`] with a pointer to it while transcribing.
+struct ProgressReporter<'a> {
+  ledger: &'a Ledger,
+  job_id: JobId,
+}
+
+impl ProgressReporter<'_> {
+  fn set(&self, stage: Stage, progress: u8) {
+    if let Err(err) = self.ledger.set_progress(self.job_id, stage, progress) {
+      eprintln!("failed to record progress of job {}: {err:#}", self.job_id);
+    }
+  }
+}
+
+unsafe extern "C" fn report_progress(
+  _ctx: *mut whisper_rs_sys::whisper_context,
+  _state: *mut whisper_rs_sys::whisper_state,
+  progress: c_int,
+  user_data: *mut c_void,
+) {
+  // SAFETY: user_data is the ProgressReporter set in whisper_cli_full_params,
+  // which outlives the synchronous whisper_full_with_state call invoking this.
+  let reporter = unsafe { &*(user_data as *const ProgressReporter<'_>) };
+  reporter.set(Stage::Transcribing, progress.clamp(0, 100) as u8);
+}
+
+fn move_generated_output(source: &Path, target: &Path) -> Result<()> {
   match fs::rename(source, target) {
     Ok(()) => Ok(()),
-    Err(err) if is_cross_device_link(&err) => copy_generated_srt_across_filesystems(source, target),
+    Err(err) if is_cross_device_link(&err) => {
+      copy_generated_output_across_filesystems(source, target)
+    }
     Err(err) => Err(err).with_context(|| {
       format!(
-        "failed to move generated SRT from {} to {}",
+        "failed to move generated output from {} to {}",
         source.display(),
         target.display()
       )
@@ -483,24 +690,28 @@ fn move_generated_srt(source: &Path, target: &Path) -> Result<()> {
   }
 }
 
-fn copy_generated_srt_across_filesystems(source: &Path, target: &Path) -> Result<()> {
+fn copy_generated_output_across_filesystems(source: &Path, target: &Path) -> Result<()> {
   let target_dir = target
     .parent()
     .with_context(|| format!("failed to read target directory for {}", target.display()))?;
+  let suffix = target
+    .extension()
+    .map(|extension| format!(".{}", extension.to_string_lossy()))
+    .unwrap_or_default();
   let temp_file = Builder::new()
     .prefix(".whisper-mp4-to-srt.")
-    .suffix(".srt")
+    .suffix(&suffix)
     .tempfile_in(target_dir)
     .with_context(|| {
       format!(
-        "failed to create temporary SRT file next to {}",
+        "failed to create temporary output file next to {}",
         target.display()
       )
     })?;
 
   fs::copy(source, temp_file.path()).with_context(|| {
     format!(
-      "failed to copy generated SRT from {} to {}",
+      "failed to copy generated output from {} to {}",
       source.display(),
       temp_file.path().display()
     )
@@ -512,7 +723,7 @@ fn copy_generated_srt_across_filesystems(source: &Path, target: &Path) -> Result
     .map_err(|err| err.error)
     .with_context(|| {
       format!(
-        "failed to move copied SRT from temporary file to {}",
+        "failed to move copied output from temporary file to {}",
         target.display()
       )
     })
@@ -664,29 +875,41 @@ fn ffprobe_duration(path: &Path, args: &[&str]) -> Result<f64> {
     .with_context(|| format!("failed to parse ffprobe duration for {}", path.display()))
 }
 
-fn transcribe_to_srt(
+fn transcribe(
   config: &Config,
   whisper_context: &RawWhisperContext,
+  job: &Job,
   wav_file: &Path,
-  output: &Path,
   whisper_threads: usize,
-) -> Result<()> {
+  progress: &ProgressReporter<'_>,
+) -> Result<Transcript> {
   let audio = read_wav_samples(wav_file)?;
   let mut state = whisper_context.create_state()?;
-  let language = whisper_language(&config.language)?;
-  let initial_prompt = whisper_initial_prompt()?;
+  let language = whisper_language(&job.language)?;
+  let initial_prompt = CString::new(config.prompt.as_str())
+    .with_context(|| format!("{WHISPER_PROMPT_ENV} contains a null byte"))?;
   let whisper_threads =
     c_int::try_from(whisper_threads).context("WHISPER_THREADS is too large for whisper-rs")?;
+  let word_timestamps = job
+    .formats
+    .iter()
+    .any(|format| format.needs_word_timestamps());
 
   let params = whisper_cli_full_params(
     whisper_threads,
     language.as_deref(),
     initial_prompt.as_c_str(),
+    word_timestamps,
+    progress,
   );
   state
     .full(params, &audio)
     .with_context(|| format!("failed to transcribe {}", wav_file.display()))?;
-  write_srt(&state, output)
+
+  Ok(Transcript {
+    language: state.language(),
+    segments: state.segments(word_timestamps)?,
+  })
 }
 
 struct RawWhisperContext {
@@ -766,7 +989,27 @@ impl RawWhisperState<'_> {
     Ok(())
   }
 
-  fn segments(&self) -> Result<Vec<TranscribedSegment>> {
+  /// The language of the last transcription, detected if none was given.
+  fn language(&self) -> Option<String> {
+    // SAFETY: state is live and was successfully transcribed before this call.
+    let id = unsafe { whisper_rs_sys::whisper_full_lang_id_from_state(self.state) };
+    if id < 0 {
+      return None;
+    }
+    // SAFETY: returns a pointer to a static string, or null for unknown ids.
+    let language = unsafe { whisper_rs_sys::whisper_lang_str(id) };
+    if language.is_null() {
+      return None;
+    }
+    // SAFETY: language is a non-null NUL-terminated static string.
+    Some(
+      unsafe { CStr::from_ptr(language) }
+        .to_string_lossy()
+        .into_owned(),
+    )
+  }
+
+  fn segments(&self, word_timestamps: bool) -> Result<Vec<TranscribedSegment>> {
     // SAFETY: state is live and was successfully transcribed before this call.
     let segment_count = unsafe { whisper_rs_sys::whisper_full_n_segments_from_state(self.state) };
     ensure!(
@@ -795,15 +1038,64 @@ impl RawWhisperState<'_> {
       // SAFETY: index is within 0..segment_count for this live state.
       let end_timestamp =
         unsafe { whisper_rs_sys::whisper_full_get_segment_t1_from_state(self.state, index) };
+      let words = if word_timestamps {
+        self.words(index)
+      } else {
+        Vec::new()
+      };
       segments.push(TranscribedSegment {
         start_timestamp,
         end_timestamp,
         text,
+        words,
       });
     }
 
     Ok(segments)
   }
+
+  fn words(&self, segment: c_int) -> Vec<output::Word> {
+    // SAFETY: context is live and the call takes no other arguments.
+    let end_of_text = unsafe { whisper_rs_sys::whisper_token_eot(self.context.ctx) };
+    // SAFETY: segment is within 0..segment_count for this live state.
+    let token_count =
+      unsafe { whisper_rs_sys::whisper_full_n_tokens_from_state(self.state, segment) };
+
+    let mut tokens = Vec::with_capacity(token_count.max(0) as usize);
+    for token in 0 .. token_count {
+      // SAFETY: segment and token are within bounds for this live state.
+      let data = unsafe {
+        whisper_rs_sys::whisper_full_get_token_data_from_state(self.state, segment, token)
+      };
+      // Ids from end-of-text up are special and timestamp tokens.
+      if data.id >= end_of_text {
+        continue;
+      }
+      // SAFETY: segment and token are within bounds. The text stays valid
+      // until the next transcription on this state and is copied below.
+      let text = unsafe {
+        whisper_rs_sys::whisper_full_get_token_text_from_state(
+          self.context.ctx,
+          self.state,
+          segment,
+          token,
+        )
+      };
+      if text.is_null() {
+        continue;
+      }
+      // SAFETY: text is a non-null NUL-terminated string, see above.
+      let bytes = unsafe { CStr::from_ptr(text) }.to_bytes().to_vec();
+      tokens.push((bytes, data));
+    }
+
+    output::group_words(tokens.iter().map(|(bytes, data)| TokenPiece {
+      bytes,
+      start_timestamp: data.t0,
+      end_timestamp: data.t1,
+      probability: data.p,
+    }))
+  }
 }
 
 impl Drop for RawWhisperState<'_> {
@@ -816,31 +1108,22 @@ impl Drop for RawWhisperState<'_> {
   }
 }
 
-struct TranscribedSegment {
-  start_timestamp: i64,
-  end_timestamp: i64,
-  text: String,
-}
-
 fn whisper_language(language: &str) -> Result<Option<CString>> {
   if language.eq_ignore_ascii_case("auto") {
     return Ok(None);
   }
 
   CString::new(language)
-    .with_context(|| format!("WHISPER_LANG contains a null byte: {language}"))
+    .with_context(|| format!("{WHISPER_LANG_ENV} contains a null byte: {language}"))
     .map(Some)
 }
 
-fn whisper_initial_prompt() -> Result<CString> {
-  let prompt = env::var(WHISPER_PROMPT_ENV).unwrap_or_default();
-  CString::new(prompt).with_context(|| format!("{WHISPER_PROMPT_ENV} contains a null byte"))
-}
-
 fn whisper_cli_full_params(
   whisper_threads: c_int,
   language: Option<&CStr>,
   initial_prompt: &CStr,
+  word_timestamps: bool,
+  progress: &ProgressReporter<'_>,
 ) -> whisper_rs_sys::whisper_full_params {
   // SAFETY: returns a plain parameter value initialized by whisper.cpp.
   let mut params = unsafe {
@@ -858,9 +1141,11 @@ fn whisper_cli_full_params(
   params.language = language.map_or(std::ptr::null(), |language| {
     language.as_ptr() as *const c_char
   });
-  params.detect_language = language.is_none();
+  // A null language already makes whisper.cpp detect it. detect_language
+  // would stop right after the detection without transcribing.
+  params.detect_language = false;
   params.n_threads = whisper_threads;
-  params.token_timestamps = false;
+  params.token_timestamps = word_timestamps;
   params.thold_pt = 0.01;
   params.max_len = 0;
   params.split_on_word = false;
@@ -875,6 +1160,8 @@ fn whisper_cli_full_params(
   params.no_speech_thold = WHISPER_NO_SPEECH_THOLD;
   params.no_timestamps = false;
   params.suppress_nst = false;
+  params.progress_callback = Some(report_progress);
+  params.progress_callback_user_data = progress as *const ProgressReporter<'_> as *mut c_void;
 
   params
 }
@@ -913,49 +1200,6 @@ fn read_wav_samples(wav_file: &Path) -> Result<Vec<f32>> {
   Ok(audio)
 }
 
-fn write_srt(state: &RawWhisperState<'_>, output: &Path) -> Result<()> {
-  let mut file = fs::File::create(output)
-    .with_context(|| format!("failed to create SRT file {}", output.display()))?;
-  let segments = state.segments()?;
-
-  for (index, segment) in segments.iter().enumerate() {
-    writeln!(file, "{}", index + 1)
-      .with_context(|| format!("failed to write SRT file {}", output.display()))?;
-    writeln!(
-      file,
-      "{} --> {}",
-      format_srt_timestamp(segment.start_timestamp),
-      format_srt_timestamp(segment.end_timestamp)
-    )
-    .with_context(|| format!("failed to write SRT file {}", output.display()))?;
-    writeln!(file, "{}", segment.text)
-      .with_context(|| format!("failed to write SRT file {}", output.display()))?;
-    writeln!(file).with_context(|| format!("failed to write SRT file {}", output.display()))?;
-  }
-
-  ensure!(
-    !segments.is_empty(),
-    "whisper-rs did not produce any segments"
-  );
-  Ok(())
-}
-
-fn format_srt_timestamp(timestamp_cs: i64) -> String {
-  let mut millis = timestamp_cs.saturating_mul(10);
-  if millis < 0 {
-    millis = 0;
-  }
-
-  let hours = millis / 3_600_000;
-  millis -= hours * 3_600_000;
-  let minutes = millis / 60_000;
-  millis -= minutes * 60_000;
-  let seconds = millis / 1_000;
-  millis -= seconds * 1_000;
-
-  format!("{hours:02}:{minutes:02}:{seconds:02},{millis:03}")
-}
-
 fn ensure_command_available(name: &str) -> Result<()> {
   let status = Command::new(name)
     .arg("-version")
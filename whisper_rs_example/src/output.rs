@@ -0,0 +1,282 @@
+use std::{
+  fs,
+  io::{BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  Srt,
+  Vtt,
+  /// Segments with word timestamps.
+  Json,
+}
+
+impl OutputFormat {
+  pub fn extension(self) -> &'static str {
+    match self {
+      Self::Srt => "srt",
+      Self::Vtt => "vtt",
+      Self::Json => "json",
+    }
+  }
+
+  pub fn needs_word_timestamps(self) -> bool {
+    self == Self::Json
+  }
+
+  /// SRT and VTT hold the same subtitles, so an existing file of either one
+  /// satisfies both.
+  pub fn existing_output(self, base: &Path) -> Option<PathBuf> {
+    let extensions: &[&str] = match self {
+      Self::Srt | Self::Vtt => &["srt", "vtt"],
+      Self::Json => &["json"],
+    };
+    extensions
+      .iter()
+      .map(|extension| base.with_extension(extension))
+      .find(|output| output.is_file())
+  }
+}
+
+/// Timestamps are in centiseconds, as whisper.cpp reports them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
+  /// Language whisper transcribed in, detected when `auto` was requested.
+  pub language: Option<String>,
+  pub segments: Vec<TranscribedSegment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscribedSegment {
+  pub start_timestamp: i64,
+  pub end_timestamp: i64,
+  pub text: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Word {
+  pub start_timestamp: i64,
+  pub end_timestamp: i64,
+  pub text: String,
+  /// Mean probability of the tokens making up the word.
+  pub probability: f32,
+}
+
+/// A text token with its timing, as whisper.cpp reports it.
+pub struct TokenPiece<'a> {
+  pub bytes: &'a [u8],
+  pub start_timestamp: i64,
+  pub end_timestamp: i64,
+  pub probability: f32,
+}
+
+/// Joins tokens into words. A token starting with a space starts a new word.
+/// Bytes are joined before decoding because whisper.cpp may split a multibyte
+/// character across tokens.
+pub fn group_words<'a>(tokens: impl IntoIterator<Item = TokenPiece<'a>>) -> Vec<Word> {
+  struct Pending {
+    start_timestamp: i64,
+    end_timestamp: i64,
+    bytes: Vec<u8>,
+    probabilities: Vec<f32>,
+  }
+
+  fn finish(pending: Pending) -> Option<Word> {
+    let text = String::from_utf8_lossy(&pending.bytes).trim().to_owned();
+    if text.is_empty() {
+      return None;
+    }
+    Some(Word {
+      start_timestamp: pending.start_timestamp,
+      end_timestamp: pending.end_timestamp,
+      text,
+      probability: pending.probabilities.iter().sum::<f32>() / pending.probabilities.len() as f32,
+    })
+  }
+
+  let mut words = Vec::new();
+  let mut current: Option<Pending> = None;
+  for token in tokens {
+    match &mut current {
+      Some(pending) if !token.bytes.starts_with(b" ") => {
+        pending.end_timestamp = token.end_timestamp;
+        pending.bytes.extend_from_slice(token.bytes);
+        pending.probabilities.push(token.probability);
+      }
+      _ => {
+        words.extend(current.take().and_then(finish));
+        current = Some(Pending {
+          start_timestamp: token.start_timestamp,
+          end_timestamp: token.end_timestamp,
+          bytes: token.bytes.to_vec(),
+          probabilities: vec![token.probability],
+        });
+      }
+    }
+  }
+  words.extend(current.and_then(finish));
+  words
+}
+
+pub fn write_transcript(
+  transcript: &Transcript,
+  format: OutputFormat,
+  output: &Path,
+) -> Result<()> {
+  ensure!(
+    !transcript.segments.is_empty(),
+    "whisper-rs did not produce any segments"
+  );
+
+  let file = fs::File::create(output)
+    .with_context(|| format!("failed to create output file {}", output.display()))?;
+  let mut writer = BufWriter::new(file);
+  match format {
+    OutputFormat::Srt => write_srt(transcript, &mut writer),
+    OutputFormat::Vtt => write_vtt(transcript, &mut writer),
+    OutputFormat::Json => serde_json::to_writer_pretty(&mut writer, transcript).map_err(Into::into),
+  }
+  .and_then(|()| writer.flush())
+  .with_context(|| format!("failed to write output file {}", output.display()))
+}
+
+fn write_srt(transcript: &Transcript, writer: &mut impl Write) -> std::io::Result<()> {
+  for (index, segment) in transcript.segments.iter().enumerate() {
+    writeln!(writer, "{}", index + 1)?;
+    writeln!(
+      writer,
+      "{} --> {}",
+      format_timestamp(segment.start_timestamp, ','),
+      format_timestamp(segment.end_timestamp, ',')
+    )?;
+    writeln!(writer, "{}", segment.text)?;
+    writeln!(writer)?;
+  }
+  Ok(())
+}
+
+fn write_vtt(transcript: &Transcript, writer: &mut impl Write) -> std::io::Result<()> {
+  writeln!(writer, "WEBVTT")?;
+  writeln!(writer)?;
+  for segment in &transcript.segments {
+    writeln!(
+      writer,
+      "{} --> {}",
+      format_timestamp(segment.start_timestamp, '.'),
+      format_timestamp(segment.end_timestamp, '.')
+    )?;
+    writeln!(writer, "{}", segment.text.trim())?;
+    writeln!(writer)?;
+  }
+  Ok(())
+}
+
+/// SRT separates milliseconds with a comma, VTT with a dot.
+pub fn format_timestamp(timestamp_cs: i64, millis_separator: char) -> String {
+  let mut millis = timestamp_cs.saturating_mul(10);
+  if millis < 0 {
+    millis = 0;
+  }
+
+  let hours = millis / 3_600_000;
+  millis -= hours * 3_600_000;
+  let minutes = millis / 60_000;
+  millis -= minutes * 60_000;
+  let seconds = millis / 1_000;
+  millis -= seconds * 1_000;
+
+  format!("{hours:02}:{minutes:02}:{seconds:02}{millis_separator}{millis:03}")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn token(bytes: &[u8], start_timestamp: i64, end_timestamp: i64) -> TokenPiece<'_> {
+    TokenPiece {
+      bytes,
+      start_timestamp,
+      end_timestamp,
+      probability: 0.5,
+    }
+  }
+
+  fn transcript() -> Transcript {
+    Transcript {
+      language: Some(String::from("en")),
+      segments: vec![TranscribedSegment {
+        start_timestamp: 0,
+        end_timestamp: 361_234,
+        text: String::from(" Hello world"),
+        words: Vec::new(),
+      }],
+    }
+  }
+
+  #[test]
+  fn formats_srt_and_vtt_timestamps() {
+    assert_eq!(format_timestamp(361_234, ','), "01:00:12,340");
+    assert_eq!(format_timestamp(361_234, '.'), "01:00:12.340");
+    assert_eq!(format_timestamp(-5, ','), "00:00:00,000");
+  }
+
+  #[test]
+  fn groups_tokens_into_words() {
+    let words = group_words([
+      token(b" Hel", 0, 10),
+      token(b"lo", 10, 20),
+      token(b" w", 25, 30),
+      token(b"orld", 30, 40),
+      token(b".", 40, 41),
+    ]);
+
+    let texts = words
+      .iter()
+      .map(|word| word.text.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(texts, ["Hello", "world."]);
+    assert_eq!((words[1].start_timestamp, words[1].end_timestamp), (25, 41));
+    assert_eq!(words[0].probability, 0.5);
+  }
+
+  #[test]
+  fn joins_multibyte_characters_split_across_tokens() {
+    let bytes = " 你".as_bytes();
+    let words = group_words([token(&bytes[.. 2], 0, 5), token(&bytes[2 ..], 5, 10)]);
+
+    assert_eq!(words.len(), 1);
+    assert_eq!(words[0].text, "你");
+  }
+
+  #[test]
+  fn writes_vtt_header_and_cues() -> Result<()> {
+    let mut output = Vec::new();
+    write_vtt(&transcript(), &mut output)?;
+
+    assert_eq!(
+      String::from_utf8(output)?,
+      "WEBVTT\n\n00:00:00.000 --> 01:00:12.340\nHello world\n\n"
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn writes_srt_cues() -> Result<()> {
+    let mut output = Vec::new();
+    write_srt(&transcript(), &mut output)?;
+
+    assert_eq!(
+      String::from_utf8(output)?,
+      "1\n00:00:00,000 --> 01:00:12,340\n Hello world\n\n"
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,190 @@
+//! Small HTTP API to submit files and poll their jobs.
+//!
+//! - `POST /jobs` with `{"path": "...", "formats": ["srt", "json"], "language": "auto"}`, where
+//!   `formats` and `language` default to the command line settings
+//! - `GET /jobs` lists all jobs of the ledger
+//! - `GET /jobs/{id}` returns one job with its status and progress
+//!
+//! Only media under the scan directory can be submitted, and only jobs for
+//! those files are listed. With `--serve-token` set every request needs an
+//! `Authorization: Bearer <token>` header.
+
+use std::{
+  net::SocketAddr,
+  path::PathBuf,
+  sync::{Arc, mpsc},
+  thread,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+  Json, Router,
+  extract::{Path, Request, State},
+  http::{StatusCode, header},
+  middleware::{self, Next},
+  response::{IntoResponse, Response},
+  routing::get,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+  Config, is_supported_media,
+  ledger::{Job, JobId, Ledger},
+  output::OutputFormat,
+  output_base_for,
+};
+
+#[derive(Clone)]
+struct AppState {
+  config: Arc<Config>,
+  ledger: Arc<Ledger>,
+  queue: mpsc::Sender<JobId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitRequest {
+  path: PathBuf,
+  formats: Option<Vec<OutputFormat>>,
+  language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+  error: String,
+}
+
+fn error(status: StatusCode, error: impl Into<String>) -> Response {
+  (
+    status,
+    Json(ErrorBody {
+      error: error.into(),
+    }),
+  )
+    .into_response()
+}
+
+/// Binds `addr` right away so a taken port fails the start, then serves on a
+/// thread of its own. Accepted jobs are sent to the worker queue.
+pub fn spawn(
+  addr: SocketAddr,
+  config: Arc<Config>,
+  ledger: Arc<Ledger>,
+  queue: mpsc::Sender<JobId>,
+) -> Result<thread::JoinHandle<Result<()>>> {
+  let listener = std::net::TcpListener::bind(addr)
+    .with_context(|| format!("failed to bind HTTP API to {addr}"))?;
+  listener.set_nonblocking(true)?;
+  eprintln!("HTTP API listening on http://{addr}");
+
+  let state = AppState {
+    config,
+    ledger,
+    queue,
+  };
+  Ok(thread::spawn(move || {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(1)
+      .enable_all()
+      .build()
+      .context("failed to start HTTP runtime")?;
+    runtime.block_on(async move {
+      let listener = tokio::net::TcpListener::from_std(listener)?;
+      let app = Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/{id}", get(get_job))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+      axum::serve(listener, app).await.context("HTTP API stopped")
+    })
+  }))
+}
+
+async fn authorize(State(state): State<AppState>, request: Request, next: Next) -> Response {
+  let Some(token) = &state.config.serve_token else {
+    return next.run(request).await;
+  };
+  let authorized = request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()));
+  if !authorized {
+    return error(StatusCode::UNAUTHORIZED, "missing or invalid bearer token");
+  }
+  next.run(request).await
+}
+
+/// Takes as long for a near miss as for a wrong first byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The ledger is shared with scans of other directories; only this one is
+/// visible over HTTP.
+fn in_scan_dir(state: &AppState, job: &Job) -> bool {
+  job.input.starts_with(&state.config.scan_dir)
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Json<Vec<Job>> {
+  let mut jobs = state.ledger.jobs();
+  jobs.retain(|job| in_scan_dir(&state, job));
+  Json(jobs)
+}
+
+async fn get_job(State(state): State<AppState>, Path(id): Path<JobId>) -> Response {
+  match state.ledger.get(id).filter(|job| in_scan_dir(&state, job)) {
+    Some(job) => Json(job).into_response(),
+    None => error(StatusCode::NOT_FOUND, format!("no job {id}")),
+  }
+}
+
+async fn submit_job(State(state): State<AppState>, Json(request): Json<SubmitRequest>) -> Response {
+  let Ok(input) = request.path.canonicalize() else {
+    return error(
+      StatusCode::UNPROCESSABLE_ENTITY,
+      format!("file not found: {}", request.path.display()),
+    );
+  };
+  // Canonical on both sides, so neither `..` nor a symlink can leave it
+  if !input.starts_with(&state.config.scan_dir) {
+    return error(
+      StatusCode::FORBIDDEN,
+      format!(
+        "{} is outside the scan directory {}",
+        request.path.display(),
+        state.config.scan_dir.display()
+      ),
+    );
+  }
+  if !input.is_file() || !is_supported_media(&input) {
+    return error(
+      StatusCode::UNPROCESSABLE_ENTITY,
+      format!("not a supported media file: {}", input.display()),
+    );
+  }
+  let formats = request
+    .formats
+    .filter(|formats| !formats.is_empty())
+    .unwrap_or_else(|| state.config.formats.clone());
+  let language = request
+    .language
+    .unwrap_or_else(|| state.config.language.clone());
+
+  let submitted = output_base_for(&input, &state.config).and_then(|output_base| {
+    state
+      .ledger
+      .submit(&input, &output_base, &formats, &language)
+  });
+  match submitted {
+    Ok(Ok(job)) => {
+      if state.queue.send(job.id).is_err() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "workers have stopped");
+      }
+      eprintln!("Submitted job {}: {}", job.id, job.input.display());
+      (StatusCode::ACCEPTED, Json(job)).into_response()
+    }
+    Ok(Err(active)) => (StatusCode::CONFLICT, Json(active)).into_response(),
+    Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")),
+  }
+}